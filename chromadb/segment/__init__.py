@@ -23,6 +23,8 @@ class SegmentType(Enum):
     HNSW_LOCAL_MEMORY = "urn:chroma:segment/vector/hnsw-local-memory"
     HNSW_LOCAL_PERSISTED = "urn:chroma:segment/vector/hnsw-local-persisted"
     HNSW_DISTRIBUTED = "urn:chroma:segment/vector/hnsw-distributed"
+    PQ_DISTRIBUTED = "urn:chroma:segment/vector/pq-distributed"
+    IVF_DISTRIBUTED = "urn:chroma:segment/vector/ivf-distributed"
     RECORD = "urn:chroma:segment/record"
     BLOCKFILE_METADATA = "urn:chroma:segment/metadata/blockfile"
 
@@ -1,25 +1,20 @@
 // Assumes that chroma-hnswlib is checked out at the same level as chroma
 #include "../../../hnswlib/hnswlib/hnswlib.h"
 
-class AllowAndDisallowListFilterFunctor : public hnswlib::BaseFilterFunctor
+// Whether the element with the label passes the filter, which is owned by the caller
+typedef bool (*filter_callback_t)(const void *context, hnswlib::labeltype id);
+
+class CallbackFilterFunctor : public hnswlib::BaseFilterFunctor
 {
 public:
-    std::unordered_set<hnswlib::labeltype> allow_list;
-    std::unordered_set<hnswlib::labeltype> disallow_list;
+    filter_callback_t callback;
+    const void *context;
 
-    AllowAndDisallowListFilterFunctor(std::unordered_set<hnswlib::labeltype> allow_list, std::unordered_set<hnswlib::labeltype> disallow_list) : allow_list(allow_list), disallow_list(disallow_list) {}
+    CallbackFilterFunctor(filter_callback_t callback, const void *context) : callback(callback), context(context) {}
 
     bool operator()(hnswlib::labeltype id)
     {
-        if (allow_list.size() > 0 && allow_list.find(id) == allow_list.end())
-        {
-            return false;
-        }
-        if (disallow_list.size() > 0 && disallow_list.find(id) != disallow_list.end())
-        {
-            return false;
-        }
-        return true;
+        return callback(context, id);
     }
 };
 
@@ -130,37 +125,88 @@ public:
         return 0;
     }
 
-    size_t knn_query(const data_t *query_vector, const size_t k, hnswlib::labeltype *ids, data_t *distance, const hnswlib::labeltype *allowed_ids, const size_t allowed_id_length, const hnswlib::labeltype *disallowed_ids, const size_t disallowed_id_length)
+    // The search keeps max(ef_, k) candidates, so searching for ef results searches with
+    // at least ef without changing ef_, which is shared by concurrent queries. An ef of 0
+    // searches with ef_. The ef of a query can therefore only raise ef_, an ef below ef_
+    // searches with ef_.
+    size_t knn_query(const data_t *query_vector, const size_t k, const size_t ef, hnswlib::labeltype *ids, data_t *distance, filter_callback_t filter_callback, const void *filter_context)
     {
         if (!index_inited)
         {
-            std::runtime_error("Index not inited");
+            throw std::runtime_error("Index not inited");
+        }
+
+        CallbackFilterFunctor filter = CallbackFilterFunctor(filter_callback, filter_context);
+        std::priority_queue<std::pair<dist_t, hnswlib::labeltype>> res = appr_alg->searchKnn(query_vector, std::max(k, ef), filter_callback != NULL ? &filter : nullptr);
+        while (res.size() > k)
+        {
+            res.pop();
+        }
+        // Fewer than k elements may pass the filter, the caller reads the number of results.
+        int total_results = std::min(res.size(), k);
+        for (int i = total_results - 1; i >= 0; i--)
+        {
+            std::pair<dist_t, hnswlib::labeltype> res_i = res.top();
+            ids[i] = res_i.second;
+            distance[i] = res_i.first;
+            res.pop();
         }
+        return total_results;
+    }
 
-        std::unordered_set<hnswlib::labeltype> allow_list;
-        std::unordered_set<hnswlib::labeltype> disallow_list;
-        if (allowed_ids != NULL)
+    // Exact search over the given labels, for filters that allow few elements, or over
+    // every element that passes the filter callback if labels is null. Labels that are
+    // not in the index or are deleted are skipped. The query vector must be normalized
+    // for the cosine space.
+    size_t brute_force_query(const data_t *query_vector, const size_t k, hnswlib::labeltype *ids, data_t *distance, const hnswlib::labeltype *labels, const size_t label_length, filter_callback_t filter_callback, const void *filter_context)
+    {
+        if (!index_inited)
+        {
+            throw std::runtime_error("Index not inited");
+        }
+
+        std::priority_queue<std::pair<dist_t, hnswlib::labeltype>> res;
+        auto consider = [&](hnswlib::tableint internal_id, hnswlib::labeltype label)
         {
-            for (int i = 0; i < allowed_id_length; i++)
+            if (appr_alg->isMarkedDeleted(internal_id))
             {
-                allow_list.insert(allowed_ids[i]);
+                return;
             }
-        }
-        if (disallowed_ids != NULL)
+            dist_t dist = appr_alg->fstdistfunc_(query_vector, appr_alg->getDataByInternalId(internal_id), appr_alg->dist_func_param_);
+            res.emplace(dist, label);
+            if (res.size() > k)
+            {
+                res.pop();
+            }
+        };
+        if (labels == nullptr)
         {
-            for (int i = 0; i < disallowed_id_length; i++)
+            size_t element_count = appr_alg->cur_element_count;
+            for (hnswlib::tableint internal_id = 0; internal_id < element_count; internal_id++)
             {
-                disallow_list.insert(disallowed_ids[i]);
+                hnswlib::labeltype label = appr_alg->getExternalLabel(internal_id);
+                if (filter_callback != NULL && !filter_callback(filter_context, label))
+                {
+                    continue;
+                }
+                consider(internal_id, label);
             }
         }
-        AllowAndDisallowListFilterFunctor filter = AllowAndDisallowListFilterFunctor(allow_list, disallow_list);
-        std::priority_queue<std::pair<dist_t, hnswlib::labeltype>> res = appr_alg->searchKnn(query_vector, k, &filter);
-        if (res.size() < k)
+        for (size_t i = 0; labels != nullptr && i < label_length; i++)
         {
-            // TODO: This is ok and we should return < K results, but for maintining compatibility with the old API we throw an error for now
-            std::runtime_error("Not enough results");
+            hnswlib::tableint internal_id;
+            {
+                std::unique_lock<std::mutex> lock(appr_alg->label_lookup_lock);
+                auto search = appr_alg->label_lookup_.find(labels[i]);
+                if (search == appr_alg->label_lookup_.end())
+                {
+                    continue;
+                }
+                internal_id = search->second;
+            }
+            consider(internal_id, labels[i]);
         }
-        int total_results = std::min(res.size(), k);
+        int total_results = res.size();
         for (int i = total_results - 1; i >= 0; i--)
         {
             std::pair<dist_t, hnswlib::labeltype> res_i = res.top();
@@ -188,6 +234,15 @@ public:
         }
         appr_alg->ef_ = ef;
     }
+
+    void resize_index(const size_t new_size)
+    {
+        if (!index_inited)
+        {
+            throw std::runtime_error("Index not inited");
+        }
+        appr_alg->resizeIndex(new_size);
+    }
 };
 
 extern "C"
@@ -197,6 +252,11 @@ extern "C"
         return new Index<float>(space_name, dim);
     }
 
+    void free_index(Index<float> *index)
+    {
+        delete index;
+    }
+
     void init_index(Index<float> *index, const size_t max_elements, const size_t M, const size_t ef_construction, const size_t random_seed, const bool allow_replace_deleted, const bool is_persistent_index, const char *persistence_location)
     {
         index->init_index(max_elements, M, ef_construction, random_seed, allow_replace_deleted, is_persistent_index, persistence_location);
@@ -227,9 +287,14 @@ extern "C"
         return index->mark_deleted(id);
     }
 
-    size_t knn_query(Index<float> *index, const float *query_vector, const size_t k, hnswlib::labeltype *ids, float *distance, const hnswlib::labeltype *allowed_ids, const size_t allowed_id_length, const hnswlib::labeltype *disallowed_ids, const size_t disallowed_id_length)
+    size_t knn_query(Index<float> *index, const float *query_vector, const size_t k, const size_t ef, hnswlib::labeltype *ids, float *distance, filter_callback_t filter_callback, const void *filter_context)
+    {
+        return index->knn_query(query_vector, k, ef, ids, distance, filter_callback, filter_context);
+    }
+
+    size_t brute_force_query(Index<float> *index, const float *query_vector, const size_t k, hnswlib::labeltype *ids, float *distance, const hnswlib::labeltype *labels, const size_t label_length, filter_callback_t filter_callback, const void *filter_context)
     {
-        return index->knn_query(query_vector, k, ids, distance, allowed_ids, allowed_id_length, disallowed_ids, disallowed_id_length);
+        return index->brute_force_query(query_vector, k, ids, distance, labels, label_length, filter_callback, filter_context);
     }
 
     int get_ef(Index<float> *index)
@@ -246,4 +311,19 @@ extern "C"
     {
         return index->appr_alg->getCurrentElementCount() - index->appr_alg->getDeletedCount();
     }
+
+    size_t get_max_elements(Index<float> *index)
+    {
+        return index->appr_alg->getMaxElements();
+    }
+
+    size_t get_current_count(Index<float> *index)
+    {
+        return index->appr_alg->getCurrentElementCount();
+    }
+
+    void resize_index(Index<float> *index, const size_t new_size)
+    {
+        index->resize_index(new_size);
+    }
 }
@@ -18,5 +18,29 @@ fn distance_metrics(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, distance_metrics,);
+fn int8_distance_metrics(c: &mut Criterion) {
+    // Quantized values are in [-127, 127]
+    let mut x: Vec<i8> = Vec::with_capacity(786);
+    for _ in 0..x.capacity() {
+        x.push(rand::random::<i8>().max(-127));
+    }
+    let mut y: Vec<i8> = Vec::with_capacity(786);
+    for _ in 0..y.capacity() {
+        y.push(rand::random::<i8>().max(-127));
+    }
+    c.bench_function("int8_dot_product", |b| {
+        b.iter(|| {
+            let d = DistanceFunction::InnerProduct;
+            std::hint::black_box(DistanceFunction::distance_int8(&d, &x, &y));
+        });
+    });
+    c.bench_function("int8_euclidean_distance", |b| {
+        b.iter(|| {
+            let d = DistanceFunction::Euclidean;
+            std::hint::black_box(DistanceFunction::distance_int8(&d, &x, &y));
+        });
+    });
+}
+
+criterion_group!(benches, distance_metrics, int8_distance_metrics);
 criterion_main!(benches);
@@ -0,0 +1,6 @@
+use worker::recall_eval_entrypoint;
+
+#[tokio::main]
+async fn main() {
+    recall_eval_entrypoint().await;
+}
@@ -7,7 +7,7 @@ use crate::{
         arrow::types::{ArrowReadableValue, ArrowWriteableKey, ArrowWriteableValue},
         key::{CompositeKey, KeyWrapper},
     },
-    chroma_proto::UpdateMetadata,
+    chroma_proto::{MultiVector, SparseVector, UpdateMetadata},
     segment::DataRecord,
 };
 use arrow::array::BinaryArray;
@@ -25,8 +25,14 @@ impl ArrowWriteableValue for &DataRecord<'_> {
         let id_offset = bit_util::round_upto_multiple_of_64((item_count + 1) * 4);
         let metdata_offset = bit_util::round_upto_multiple_of_64((item_count + 1) * 4);
         let document_offset = bit_util::round_upto_multiple_of_64((item_count + 1) * 4);
-
-        id_offset + metdata_offset + document_offset
+        let sparse_embedding_offset = bit_util::round_upto_multiple_of_64((item_count + 1) * 4);
+        let multi_embedding_offset = bit_util::round_upto_multiple_of_64((item_count + 1) * 4);
+
+        id_offset
+            + metdata_offset
+            + document_offset
+            + sparse_embedding_offset
+            + multi_embedding_offset
     }
 
     fn add(prefix: &str, key: KeyWrapper, value: Self, delta: &BlockDelta) {
@@ -57,9 +63,25 @@ impl ArrowWriteableValue for &DataRecord<'_> {
 
                 let mut document_storage = builder.document_storage.write();
                 document_storage.insert(
-                    composite_key,
+                    composite_key.clone(),
                     value.document.map_or(None, |doc| Some(doc.to_string())),
                 );
+
+                let mut sparse_embedding_storage = builder.sparse_embedding_storage.write();
+                sparse_embedding_storage.insert(
+                    composite_key.clone(),
+                    value.sparse_embedding.as_ref().map(|sparse_embedding| {
+                        Into::<SparseVector>::into(sparse_embedding.clone()).encode_to_vec()
+                    }),
+                );
+
+                let mut multi_embedding_storage = builder.multi_embedding_storage.write();
+                multi_embedding_storage.insert(
+                    composite_key,
+                    value.multi_embedding.as_ref().map(|multi_embedding| {
+                        Into::<MultiVector>::into(multi_embedding.clone()).encode_to_vec()
+                    }),
+                );
             }
             _ => panic!("Invalid builder type"),
         }
@@ -68,16 +90,24 @@ impl ArrowWriteableValue for &DataRecord<'_> {
     fn delete(prefix: &str, key: KeyWrapper, delta: &BlockDelta) {
         match &delta.builder {
             BlockStorage::DataRecord(builder) => {
-                let mut id_storage = builder.id_storage.write();
-                let mut embedding_storage = builder.embedding_storage.write();
-                id_storage.remove(&CompositeKey {
-                    prefix: prefix.to_string(),
-                    key: key.clone(),
-                });
-                embedding_storage.remove(&CompositeKey {
+                let composite_key = CompositeKey {
                     prefix: prefix.to_string(),
                     key,
-                });
+                };
+                // The fields are zipped together when the block is built, so they
+                // are all removed
+                builder.id_storage.write().remove(&composite_key);
+                builder.embedding_storage.write().remove(&composite_key);
+                builder.metadata_storage.write().remove(&composite_key);
+                builder.document_storage.write().remove(&composite_key);
+                builder
+                    .sparse_embedding_storage
+                    .write()
+                    .remove(&composite_key);
+                builder
+                    .multi_embedding_storage
+                    .write()
+                    .remove(&composite_key);
             }
             _ => panic!("Invalid builder type"),
         }
@@ -143,9 +173,52 @@ impl<'referred_data> ArrowReadableValue<'referred_data> for DataRecord<'referred
             false => Some(document_arr.value(index)),
         };
 
+        // Read out sparse embedding, blocks written before sparse embeddings were
+        // stored do not have the column
+        let sparse_embedding = match as_struct_array.column_by_name("sparse_embedding") {
+            Some(sparse_embedding_arr) => {
+                let sparse_embedding_arr = sparse_embedding_arr
+                    .as_any()
+                    .downcast_ref::<BinaryArray>()
+                    .unwrap();
+                match sparse_embedding_arr.is_null(index) {
+                    true => None,
+                    false => {
+                        let sparse_embedding_proto =
+                            SparseVector::decode(sparse_embedding_arr.value(index)).unwrap();
+                        // TODO: unwrap error handling
+                        Some(sparse_embedding_proto.try_into().unwrap())
+                    }
+                }
+            }
+            None => None,
+        };
+
+        // Read out multi-vector embedding, which older blocks do not have either
+        let multi_embedding = match as_struct_array.column_by_name("multi_embedding") {
+            Some(multi_embedding_arr) => {
+                let multi_embedding_arr = multi_embedding_arr
+                    .as_any()
+                    .downcast_ref::<BinaryArray>()
+                    .unwrap();
+                match multi_embedding_arr.is_null(index) {
+                    true => None,
+                    false => {
+                        let multi_embedding_proto =
+                            MultiVector::decode(multi_embedding_arr.value(index)).unwrap();
+                        // TODO: unwrap error handling
+                        Some(multi_embedding_proto.try_into().unwrap())
+                    }
+                }
+            }
+            None => None,
+        };
+
         DataRecord {
             id: &id_arr.value(index),
             embedding,
+            sparse_embedding,
+            multi_embedding,
             metadata,
             document,
         }
@@ -386,18 +386,24 @@ mod test {
             DataRecord {
                 id: ids[0],
                 embedding: &embeddings[0],
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: metadatas[0].clone(),
                 document: documents[0],
             },
             DataRecord {
                 id: ids[1],
                 embedding: &embeddings[1],
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: metadatas[1].clone(),
                 document: documents[1],
             },
             DataRecord {
                 id: ids[2],
                 embedding: &embeddings[2],
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: metadatas[2].clone(),
                 document: documents[2],
             },
@@ -25,6 +25,7 @@ pub enum BlockStorage {
     String(StringValueStorage),
     Int32Array(Int32ArrayStorage),
     UInt32(UInt32Storage),
+    Float32(Float32Storage),
     RoaringBitmap(RoaringBitmapStorage),
     DataRecord(DataRecordStorage),
 }
@@ -35,6 +36,7 @@ impl Debug for BlockStorage {
             BlockStorage::String(_) => write!(f, "String"),
             BlockStorage::Int32Array(_) => write!(f, "Int32Array"),
             BlockStorage::UInt32(_) => write!(f, "UInt32"),
+            BlockStorage::Float32(_) => write!(f, "Float32"),
             BlockStorage::RoaringBitmap(_) => write!(f, "RoaringBitmap"),
             BlockStorage::DataRecord(_) => write!(f, "DataRecord"),
         }
@@ -355,6 +357,94 @@ impl UInt32Storage {
     }
 }
 
+#[derive(Clone)]
+pub(super) struct Float32Storage {
+    pub(super) storage: Arc<RwLock<BTreeMap<CompositeKey, f32>>>,
+}
+
+impl Float32Storage {
+    pub(super) fn new() -> Self {
+        Self {
+            storage: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    fn get_prefix_size(&self, start: usize, end: usize) -> usize {
+        let storage = self.storage.read();
+        let key_stream = storage
+            .iter()
+            .skip(start)
+            .take(end - start)
+            .map(|(key, _)| key);
+        calculate_prefix_size(key_stream)
+    }
+
+    fn get_key_size(&self, start: usize, end: usize) -> usize {
+        let storage = self.storage.read();
+        let key_stream = storage
+            .iter()
+            .skip(start)
+            .take(end - start)
+            .map(|(key, _)| key);
+        calculate_key_size(key_stream)
+    }
+
+    fn get_value_size(&self, start: usize, end: usize) -> usize {
+        let storage = self.storage.read();
+        let value_stream = storage
+            .iter()
+            .skip(start)
+            .take(end - start)
+            .map(|(_, value)| value);
+        value_stream.fold(0, |acc, _| acc + std::mem::size_of::<f32>())
+    }
+
+    fn split(&self, prefix: &str, key: KeyWrapper) -> Float32Storage {
+        let mut storage_guard = self.storage.write();
+        let split = storage_guard.split_off(&CompositeKey {
+            prefix: prefix.to_string(),
+            key,
+        });
+        Float32Storage {
+            storage: Arc::new(RwLock::new(split)),
+        }
+    }
+
+    fn get_key(&self, index: usize) -> CompositeKey {
+        let storage = self.storage.read();
+        let (key, _) = storage.iter().nth(index).unwrap();
+        key.clone()
+    }
+
+    fn build_keys(&self, builder: BlockKeyArrowBuilder) -> BlockKeyArrowBuilder {
+        let storage = self.storage.read();
+        let mut builder = builder;
+        for (key, _) in storage.iter() {
+            builder.add_key(key.clone());
+        }
+        builder
+    }
+
+    fn len(&self) -> usize {
+        let storage = self.storage.read();
+        storage.len()
+    }
+
+    fn to_arrow(&self) -> (Field, ArrayRef) {
+        let item_capacity = self.storage.read().len();
+        let mut value_builder = Float32Builder::with_capacity(item_capacity);
+        for (_, value) in self.storage.read().iter() {
+            value_builder.append_value(*value);
+        }
+        let value_field = Field::new("value", arrow::datatypes::DataType::Float32, false);
+        let value_arr = value_builder.finish();
+        (
+            value_field,
+            (&value_arr as &dyn Array).slice(0, value_arr.len()),
+        )
+    }
+}
+
 #[derive(Clone)]
 pub(super) struct Int32ArrayStorage {
     pub(super) storage: Arc<RwLock<BTreeMap<CompositeKey, Int32Array>>>,
@@ -567,6 +657,8 @@ pub(super) struct DataRecordStorage {
     pub(super) embedding_storage: Arc<RwLock<BTreeMap<CompositeKey, Vec<f32>>>>,
     pub(super) metadata_storage: Arc<RwLock<BTreeMap<CompositeKey, Option<Vec<u8>>>>>,
     pub(super) document_storage: Arc<RwLock<BTreeMap<CompositeKey, Option<String>>>>,
+    pub(super) sparse_embedding_storage: Arc<RwLock<BTreeMap<CompositeKey, Option<Vec<u8>>>>>,
+    pub(super) multi_embedding_storage: Arc<RwLock<BTreeMap<CompositeKey, Option<Vec<u8>>>>>,
 }
 
 impl DataRecordStorage {
@@ -576,6 +668,8 @@ impl DataRecordStorage {
             embedding_storage: Arc::new(RwLock::new(BTreeMap::new())),
             metadata_storage: Arc::new(RwLock::new(BTreeMap::new())),
             document_storage: Arc::new(RwLock::new(BTreeMap::new())),
+            sparse_embedding_storage: Arc::new(RwLock::new(BTreeMap::new())),
+            multi_embedding_storage: Arc::new(RwLock::new(BTreeMap::new())),
         }
     }
 
@@ -639,6 +733,26 @@ impl DataRecordStorage {
         document_stream.fold(0, |acc, value| acc + value.as_ref().map_or(0, |v| v.len()))
     }
 
+    fn get_sparse_embedding_size(&self, start: usize, end: usize) -> usize {
+        let sparse_embedding_storage = self.sparse_embedding_storage.read();
+        let sparse_embedding_stream = sparse_embedding_storage
+            .iter()
+            .skip(start)
+            .take(end - start)
+            .map(|(_, value)| value);
+        sparse_embedding_stream.fold(0, |acc, value| acc + value.as_ref().map_or(0, |v| v.len()))
+    }
+
+    fn get_multi_embedding_size(&self, start: usize, end: usize) -> usize {
+        let multi_embedding_storage = self.multi_embedding_storage.read();
+        let multi_embedding_stream = multi_embedding_storage
+            .iter()
+            .skip(start)
+            .take(end - start)
+            .map(|(_, value)| value);
+        multi_embedding_stream.fold(0, |acc, value| acc + value.as_ref().map_or(0, |v| v.len()))
+    }
+
     fn get_total_embedding_count(&self) -> usize {
         let embedding_storage = self.embedding_storage.read();
         embedding_storage
@@ -652,11 +766,21 @@ impl DataRecordStorage {
             bit_util::round_upto_multiple_of_64(self.get_embedding_size(start, end));
         let metadata_size = bit_util::round_upto_multiple_of_64(self.get_metadata_size(start, end));
         let document_size = bit_util::round_upto_multiple_of_64(self.get_document_size(start, end));
+        let sparse_embedding_size =
+            bit_util::round_upto_multiple_of_64(self.get_sparse_embedding_size(start, end));
+        let multi_embedding_size =
+            bit_util::round_upto_multiple_of_64(self.get_multi_embedding_size(start, end));
         // TODO: I think this will break can_add logic
         let validity_bytes = bit_util::round_upto_multiple_of_64(bit_util::ceil(end - start, 8));
-        // Validity bytes are used for metadata and document fields since they are optional
-        let total_size =
-            id_size + embedding_size + metadata_size + document_size + validity_bytes * 2;
+        // Validity bytes are used for the metadata, document, sparse embedding and
+        // multi-embedding fields since they are optional
+        let total_size = id_size
+            + embedding_size
+            + metadata_size
+            + document_size
+            + sparse_embedding_size
+            + multi_embedding_size
+            + validity_bytes * 4;
 
         total_size
     }
@@ -678,13 +802,29 @@ impl DataRecordStorage {
         });
         let split_document = self.document_storage.write().split_off(&CompositeKey {
             prefix: prefix.to_string(),
-            key,
+            key: key.clone(),
         });
+        let split_sparse_embedding =
+            self.sparse_embedding_storage
+                .write()
+                .split_off(&CompositeKey {
+                    prefix: prefix.to_string(),
+                    key: key.clone(),
+                });
+        let split_multi_embedding = self
+            .multi_embedding_storage
+            .write()
+            .split_off(&CompositeKey {
+                prefix: prefix.to_string(),
+                key,
+            });
         DataRecordStorage {
             id_storage: Arc::new(RwLock::new(split_id)),
             embedding_storage: Arc::new(RwLock::new(split_embedding)),
             metadata_storage: Arc::new(RwLock::new(split_metadata)),
             document_storage: Arc::new(RwLock::new(split_document)),
+            sparse_embedding_storage: Arc::new(RwLock::new(split_sparse_embedding)),
+            multi_embedding_storage: Arc::new(RwLock::new(split_multi_embedding)),
         }
     }
 
@@ -722,17 +862,33 @@ impl DataRecordStorage {
             BinaryBuilder::with_capacity(item_capacity, self.get_metadata_size(0, self.len()));
         let mut document_builder =
             StringBuilder::with_capacity(item_capacity, self.get_document_size(0, self.len()));
+        let mut sparse_embedding_builder = BinaryBuilder::with_capacity(
+            item_capacity,
+            self.get_sparse_embedding_size(0, self.len()),
+        );
+        let mut multi_embedding_builder = BinaryBuilder::with_capacity(
+            item_capacity,
+            self.get_multi_embedding_size(0, self.len()),
+        );
 
         let id_storage = self.id_storage.read();
         let embedding_storage = self.embedding_storage.read();
         let metadata_storage = self.metadata_storage.read();
         let document_storage = self.document_storage.read();
+        let sparse_embedding_storage = self.sparse_embedding_storage.read();
+        let multi_embedding_storage = self.multi_embedding_storage.read();
         let iter = id_storage
             .iter()
             .zip(embedding_storage.iter())
             .zip(metadata_storage.iter())
-            .zip(document_storage.iter());
-        for ((((_, id), (_, embedding)), (_, metadata)), (_, document)) in iter {
+            .zip(document_storage.iter())
+            .zip(sparse_embedding_storage.iter())
+            .zip(multi_embedding_storage.iter());
+        for (
+            (((((_, id), (_, embedding)), (_, metadata)), (_, document)), (_, sparse_embedding)),
+            (_, multi_embedding),
+        ) in iter
+        {
             id_builder.append_value(id);
             let embedding_arr = embedding_builder.values();
             for entry in embedding.iter() {
@@ -741,6 +897,8 @@ impl DataRecordStorage {
             embedding_builder.append(true);
             metadata_builder.append_option(metadata.as_deref());
             document_builder.append_option(document.as_deref());
+            sparse_embedding_builder.append_option(sparse_embedding.as_deref());
+            multi_embedding_builder.append_option(multi_embedding.as_deref());
         }
 
         let id_field = Field::new("id", arrow::datatypes::DataType::Utf8, true);
@@ -758,11 +916,17 @@ impl DataRecordStorage {
         );
         let metadata_field = Field::new("metadata", arrow::datatypes::DataType::Binary, true);
         let document_field = Field::new("document", arrow::datatypes::DataType::Utf8, true);
+        let sparse_embedding_field =
+            Field::new("sparse_embedding", arrow::datatypes::DataType::Binary, true);
+        let multi_embedding_field =
+            Field::new("multi_embedding", arrow::datatypes::DataType::Binary, true);
 
         let id_arr = id_builder.finish();
         let embedding_arr = embedding_builder.finish();
         let metadata_arr = metadata_builder.finish();
         let document_arr = document_builder.finish();
+        let sparse_embedding_arr = sparse_embedding_builder.finish();
+        let multi_embedding_arr = multi_embedding_builder.finish();
 
         let struct_arr = StructArray::from(vec![
             (Arc::new(id_field.clone()), Arc::new(id_arr) as ArrayRef),
@@ -778,12 +942,22 @@ impl DataRecordStorage {
                 Arc::new(document_field.clone()),
                 Arc::new(document_arr) as ArrayRef,
             ),
+            (
+                Arc::new(sparse_embedding_field.clone()),
+                Arc::new(sparse_embedding_arr) as ArrayRef,
+            ),
+            (
+                Arc::new(multi_embedding_field.clone()),
+                Arc::new(multi_embedding_arr) as ArrayRef,
+            ),
         ]);
         let struct_fields = Fields::from(vec![
             id_field,
             embedding_field,
             metadata_field,
             document_field,
+            sparse_embedding_field,
+            multi_embedding_field,
         ]);
         let struct_field = Field::new(
             "value",
@@ -802,6 +976,7 @@ impl BlockStorage {
         match self {
             BlockStorage::String(builder) => builder.get_prefix_size(start, end),
             BlockStorage::UInt32(builder) => builder.get_prefix_size(start, end),
+            BlockStorage::Float32(builder) => builder.get_prefix_size(start, end),
             BlockStorage::DataRecord(builder) => builder.get_prefix_size(start, end),
             BlockStorage::Int32Array(builder) => builder.get_prefix_size(start, end),
             BlockStorage::RoaringBitmap(builder) => builder.get_prefix_size(start, end),
@@ -812,6 +987,7 @@ impl BlockStorage {
         match self {
             BlockStorage::String(builder) => builder.get_key_size(start, end),
             BlockStorage::UInt32(builder) => builder.get_key_size(start, end),
+            BlockStorage::Float32(builder) => builder.get_key_size(start, end),
             BlockStorage::DataRecord(builder) => builder.get_key_size(start, end),
             BlockStorage::Int32Array(builder) => builder.get_key_size(start, end),
             BlockStorage::RoaringBitmap(builder) => builder.get_key_size(start, end),
@@ -822,6 +998,7 @@ impl BlockStorage {
         match self {
             BlockStorage::String(builder) => builder.get_value_size(start, end),
             BlockStorage::UInt32(builder) => builder.get_value_size(start, end),
+            BlockStorage::Float32(builder) => builder.get_value_size(start, end),
             BlockStorage::DataRecord(builder) => builder.get_value_size(start, end),
             BlockStorage::Int32Array(builder) => builder.get_value_size(start, end),
             BlockStorage::RoaringBitmap(builder) => builder.get_value_size(start, end),
@@ -832,6 +1009,7 @@ impl BlockStorage {
         match self {
             BlockStorage::String(builder) => BlockStorage::String(builder.split(prefix, key)),
             BlockStorage::UInt32(builder) => BlockStorage::UInt32(builder.split(prefix, key)),
+            BlockStorage::Float32(builder) => BlockStorage::Float32(builder.split(prefix, key)),
             BlockStorage::DataRecord(builder) => {
                 BlockStorage::DataRecord(builder.split(prefix, key))
             }
@@ -859,6 +1037,7 @@ impl BlockStorage {
                 }
             }
             BlockStorage::UInt32(builder) => builder.get_key(index),
+            BlockStorage::Float32(builder) => builder.get_key(index),
             BlockStorage::DataRecord(builder) => builder.get_key(index),
             BlockStorage::Int32Array(builder) => builder.get_key(index),
             BlockStorage::RoaringBitmap(builder) => builder.get_key(index),
@@ -869,6 +1048,7 @@ impl BlockStorage {
         match self {
             BlockStorage::String(builder) => builder.len(),
             BlockStorage::UInt32(builder) => builder.len(),
+            BlockStorage::Float32(builder) => builder.len(),
             BlockStorage::DataRecord(builder) => builder.len(),
             BlockStorage::Int32Array(builder) => builder.len(),
             BlockStorage::RoaringBitmap(builder) => builder.len(),
@@ -888,6 +1068,9 @@ impl BlockStorage {
             BlockStorage::UInt32(builder) => {
                 key_builder = builder.build_keys(key_builder);
             }
+            BlockStorage::Float32(builder) => {
+                key_builder = builder.build_keys(key_builder);
+            }
             BlockStorage::DataRecord(builder) => {
                 key_builder = builder.build_keys(key_builder);
             }
@@ -903,6 +1086,7 @@ impl BlockStorage {
         let (value_field, value_arr) = match self {
             BlockStorage::String(builder) => builder.to_arrow(),
             BlockStorage::UInt32(builder) => builder.to_arrow(),
+            BlockStorage::Float32(builder) => builder.to_arrow(),
             BlockStorage::DataRecord(builder) => builder.to_arrow(),
             BlockStorage::Int32Array(builder) => builder.to_arrow(),
             BlockStorage::RoaringBitmap(builder) => builder.to_arrow(),
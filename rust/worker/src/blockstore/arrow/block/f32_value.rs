@@ -0,0 +1,66 @@
+use super::{
+    delta::BlockDelta,
+    delta_storage::{BlockStorage, Float32Storage},
+};
+use crate::blockstore::{
+    arrow::types::{ArrowReadableValue, ArrowWriteableKey, ArrowWriteableValue},
+    key::{CompositeKey, KeyWrapper},
+};
+use arrow::array::{Array, Float32Array};
+use std::sync::Arc;
+
+impl ArrowWriteableValue for f32 {
+    type ReadableValue<'referred_data> = f32;
+
+    fn offset_size(_item_count: usize) -> usize {
+        0
+    }
+
+    fn add(prefix: &str, key: KeyWrapper, value: Self, delta: &BlockDelta) {
+        match &delta.builder {
+            BlockStorage::Float32(builder) => {
+                let mut storage = builder.storage.write();
+                storage.insert(
+                    CompositeKey {
+                        prefix: prefix.to_string(),
+                        key,
+                    },
+                    value,
+                );
+            }
+            _ => panic!("Invalid builder type: {:?}", &delta.builder),
+        }
+    }
+
+    fn delete(prefix: &str, key: KeyWrapper, delta: &BlockDelta) {
+        match &delta.builder {
+            BlockStorage::Float32(builder) => {
+                let mut storage = builder.storage.write();
+                storage.remove(&CompositeKey {
+                    prefix: prefix.to_string(),
+                    key,
+                });
+            }
+            _ => panic!("Invalid builder type: {:?}", &delta.builder),
+        }
+    }
+
+    fn get_delta_builder() -> BlockStorage {
+        BlockStorage::Float32(Float32Storage::new())
+    }
+}
+
+impl ArrowReadableValue<'_> for f32 {
+    fn get(array: &Arc<dyn Array>, index: usize) -> f32 {
+        let array = array.as_any().downcast_ref::<Float32Array>().unwrap();
+        array.value(index)
+    }
+    fn add_to_delta<K: ArrowWriteableKey>(
+        prefix: &str,
+        key: K,
+        value: Self,
+        delta: &mut BlockDelta,
+    ) {
+        delta.add(prefix, key, value);
+    }
+}
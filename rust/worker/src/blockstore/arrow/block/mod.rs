@@ -3,6 +3,7 @@ mod data_record_value;
 pub(in crate::blockstore::arrow) mod delta;
 pub(in crate::blockstore::arrow) mod delta_storage;
 mod f32_key;
+mod f32_value;
 mod int32array_value;
 mod roaring_bitmap_value;
 mod str_key;
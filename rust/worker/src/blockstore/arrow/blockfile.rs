@@ -1063,6 +1063,8 @@ mod tests {
                 id: &key,
                 embedding: &[i as f32],
                 document: None,
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: Some(metdata),
             };
             writer.set("key", key.as_str(), &value).await.unwrap();
@@ -76,6 +76,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![1.0, 2.0, 3.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -87,6 +89,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: Some(vec![4.0, 5.0, 6.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -98,6 +102,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![7.0, 8.0, 9.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -111,6 +117,8 @@ mod tests {
                 id: &record.0.record.id,
                 embedding: record.0.record.embedding.as_ref().unwrap(),
                 document: None,
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: None,
             })
             .collect::<Vec<_>>();
@@ -222,6 +222,21 @@ mod tests {
         assert_eq!(value, "value1");
     }
 
+    #[test]
+    fn test_absent_prefix_is_not_found() {
+        let storage_manager = StorageManager::new();
+        let writer = MemoryBlockfileWriter::new(storage_manager.clone());
+        let _ = writer.set("prefix", "key1", "value1");
+        let _ = writer.commit();
+
+        let reader: MemoryBlockfileReader<&str, &str> =
+            MemoryBlockfileReader::open(writer.id, storage_manager);
+        let error = reader.get_by_prefix("absent").unwrap_err();
+        assert!(BlockfileError::is_not_found(&*error));
+        let error: Box<dyn ChromaError> = Box::new(BlockfileError::BlockNotFound);
+        assert!(!BlockfileError::is_not_found(&*error));
+    }
+
     #[test]
     fn test_string_key_rbm_value() {
         let storage_manager = StorageManager::new();
@@ -251,6 +266,8 @@ mod tests {
         let record = DataRecord {
             id: &id,
             embedding: &embedding,
+            sparse_embedding: None,
+            multi_embedding: None,
             metadata: None,
             document: None,
         };
@@ -262,6 +279,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![1.0, 2.0, 3.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -273,6 +292,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: Some(vec![4.0, 5.0, 6.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -284,6 +305,8 @@ mod tests {
                     id: "embedding_id_3".to_string(),
                     embedding: Some(vec![7.0, 8.0, 9.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -297,6 +320,8 @@ mod tests {
                 id: &record.0.record.id,
                 embedding: record.0.record.embedding.as_ref().unwrap(),
                 document: None,
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: None,
             })
             .collect::<Vec<_>>();
@@ -892,6 +892,8 @@ impl<'referred_data> Readable<'referred_data> for DataRecord<'referred_data> {
         Some(DataRecord {
             id: &id.unwrap(),
             embedding: &embedding.unwrap(),
+            sparse_embedding: None,
+            multi_embedding: None,
             metadata: None,
             document: None,
         })
@@ -913,6 +915,8 @@ impl<'referred_data> Readable<'referred_data> for DataRecord<'referred_data> {
                     DataRecord {
                         id,
                         embedding,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                     },
@@ -938,6 +942,8 @@ impl<'referred_data> Readable<'referred_data> for DataRecord<'referred_data> {
                     DataRecord {
                         id,
                         embedding,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                     },
@@ -963,6 +969,8 @@ impl<'referred_data> Readable<'referred_data> for DataRecord<'referred_data> {
                     DataRecord {
                         id,
                         embedding,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                     },
@@ -988,6 +996,8 @@ impl<'referred_data> Readable<'referred_data> for DataRecord<'referred_data> {
                     DataRecord {
                         id,
                         embedding,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                     },
@@ -1013,6 +1023,8 @@ impl<'referred_data> Readable<'referred_data> for DataRecord<'referred_data> {
                     DataRecord {
                         id,
                         embedding,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                     },
@@ -1033,6 +1045,8 @@ impl<'referred_data> Readable<'referred_data> for DataRecord<'referred_data> {
             DataRecord {
                 id,
                 embedding,
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: None,
                 document: None,
             },
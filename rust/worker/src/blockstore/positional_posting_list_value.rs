@@ -108,6 +108,17 @@ impl PositionalPostingListBuilder {
         Ok(())
     }
 
+    pub(crate) fn remove_doc_id(&mut self, doc_id: i32) -> Result<(), Box<dyn ChromaError>> {
+        if !self.doc_ids.remove(&doc_id) {
+            return Err(Box::new(
+                PositionalPostingListBuilderError::DocIdDoesNotExist,
+            ));
+        }
+
+        self.positions.remove(&doc_id);
+        Ok(())
+    }
+
     pub(crate) fn build(&mut self) -> PositionalPostingList {
         let mut doc_ids_builder = Int32Builder::new();
         let mut positions_builder = ListBuilder::new(Int32Builder::new());
@@ -218,6 +229,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_positional_posting_list_remove_document() {
+        let mut builder = PositionalPostingListBuilder::new();
+        let _res = builder.add_doc_id_and_positions(1, vec![1, 2, 3]);
+        let _res = builder.add_doc_id_and_positions(2, vec![4, 5, 6]);
+        assert!(builder.remove_doc_id(1).is_ok());
+        assert!(!builder.contains_doc_id(1));
+        assert!(builder.remove_doc_id(1).is_err());
+
+        let list = builder.build();
+        assert_eq!(list.get_doc_ids().len(), 1);
+        assert_eq!(list.get_doc_ids().values()[0], 2);
+        assert_eq!(list.get_positions_for_doc_id(1), None);
+    }
+
     #[test]
     fn test_all_positional_posting_list_behaviors_together() {
         let mut builder = PositionalPostingListBuilder::new();
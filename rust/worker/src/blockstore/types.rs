@@ -48,6 +48,19 @@ impl ChromaError for BlockfileError {
     }
 }
 
+impl BlockfileError {
+    /// Whether the error reports that no entry has the key or prefix that was read.
+    /// Readers that treat an absent key as empty should only swallow these, other
+    /// errors mean the blockfile could not be read.
+    pub(crate) fn is_not_found(error: &(dyn ChromaError + 'static)) -> bool {
+        let error: &(dyn std::error::Error + 'static) = error;
+        matches!(
+            error.downcast_ref::<BlockfileError>(),
+            Some(BlockfileError::NotFoundError)
+        )
+    }
+}
+
 // ===== Key Types =====
 pub(crate) trait Key: PartialEq + Debug + Display + Into<KeyWrapper> + Clone {
     fn get_size(&self) -> usize;
@@ -107,6 +120,12 @@ impl Value for u32 {
     }
 }
 
+impl Value for f32 {
+    fn get_size(&self) -> usize {
+        4
+    }
+}
+
 impl Value for RoaringBitmap {
     fn get_size(&self) -> usize {
         self.serialized_size()
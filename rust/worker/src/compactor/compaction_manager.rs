@@ -11,6 +11,7 @@ use crate::execution::operator::TaskMessage;
 use crate::execution::orchestration::CompactOrchestrator;
 use crate::execution::orchestration::CompactionResponse;
 use crate::index::hnsw_provider::HnswIndexProvider;
+use crate::index::pq_provider::PqIndexProvider;
 use crate::log::log::Log;
 use crate::memberlist::Memberlist;
 use crate::storage::Storage;
@@ -39,6 +40,7 @@ pub(crate) struct CompactionManager {
     storage: Storage,
     blockfile_provider: BlockfileProvider,
     hnsw_index_provider: HnswIndexProvider,
+    pq_index_provider: PqIndexProvider,
     // Dispatcher
     dispatcher: Option<Box<dyn Receiver<TaskMessage>>>,
     // Config
@@ -68,6 +70,7 @@ impl CompactionManager {
         storage: Storage,
         blockfile_provider: BlockfileProvider,
         hnsw_index_provider: HnswIndexProvider,
+        pq_index_provider: PqIndexProvider,
         compaction_manager_queue_size: usize,
         compaction_interval: Duration,
     ) -> Self {
@@ -79,6 +82,7 @@ impl CompactionManager {
             storage,
             blockfile_provider,
             hnsw_index_provider,
+            pq_index_provider,
             dispatcher: None,
             compaction_manager_queue_size,
             compaction_interval,
@@ -107,6 +111,7 @@ impl CompactionManager {
                     self.sysdb.clone(),
                     self.blockfile_provider.clone(),
                     self.hnsw_index_provider.clone(),
+                    self.pq_index_provider.clone(),
                     dispatcher.clone(),
                     None,
                 );
@@ -216,14 +221,19 @@ impl Configurable<CompactionServiceConfig> for CompactionManager {
         // TODO: real path
         let path = PathBuf::from("~/tmp");
         // TODO: blockfile proivder should be injected somehow
-        // TODO: hnsw index provider should be injected somehow
+        // TODO: hnsw and pq index providers should be injected somehow
         Ok(CompactionManager::new(
             scheduler,
             log,
             sysdb,
             storage.clone(),
             BlockfileProvider::new_arrow(storage.clone()),
-            HnswIndexProvider::new(storage.clone(), path),
+            HnswIndexProvider::new(
+                storage.clone(),
+                path.clone(),
+                config.hnsw_cache.capacity_bytes,
+            ),
+            PqIndexProvider::new(storage.clone(), path),
             compaction_manager_queue_size,
             Duration::from_secs(compaction_interval_sec),
         ))
@@ -324,6 +334,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: Some(vec![1.0, 2.0, 3.0]),
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -345,6 +357,8 @@ mod tests {
                         id: "embedding_id_2".to_string(),
                         embedding: Some(vec![4.0, 5.0, 6.0]),
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -453,7 +467,12 @@ mod tests {
             sysdb,
             storage.clone(),
             BlockfileProvider::new_arrow(storage.clone()),
-            HnswIndexProvider::new(storage, PathBuf::from(tmpdir.path().to_str().unwrap())),
+            HnswIndexProvider::new(
+                storage.clone(),
+                PathBuf::from(tmpdir.path().to_str().unwrap()),
+                usize::MAX,
+            ),
+            PqIndexProvider::new(storage, PathBuf::from(tmpdir.path().to_str().unwrap())),
             compaction_manager_queue_size,
             compaction_interval,
         );
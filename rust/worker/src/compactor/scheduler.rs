@@ -217,6 +217,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -238,6 +240,8 @@ mod tests {
                         id: "embedding_id_2".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -354,6 +358,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -373,6 +379,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -392,6 +400,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -411,6 +421,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
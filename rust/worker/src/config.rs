@@ -103,6 +103,8 @@ pub(crate) struct QueryServiceConfig {
     pub(crate) storage: crate::storage::config::StorageConfig,
     pub(crate) log: crate::log::config::LogConfig,
     pub(crate) dispatcher: crate::execution::config::DispatcherConfig,
+    #[serde(default)]
+    pub(crate) hnsw_cache: crate::index::config::HnswCacheConfig,
 }
 
 #[derive(Deserialize)]
@@ -128,6 +130,8 @@ pub(crate) struct CompactionServiceConfig {
     pub(crate) log: crate::log::config::LogConfig,
     pub(crate) dispatcher: crate::execution::config::DispatcherConfig,
     pub(crate) compactor: crate::compactor::config::CompactorConfig,
+    #[serde(default)]
+    pub(crate) hnsw_cache: crate::index::config::HnswCacheConfig,
 }
 
 /// # Description
@@ -0,0 +1,60 @@
+/// Binary quantization of a vector: a bit per dimension, set if the value is
+/// negative, packed 64 dimensions to a word, with the first dimension in the lowest bit.
+/// # Notes
+/// Uses the sign bit of the float, so -0.0 is negative.
+pub fn binary_quantize(vector: &[f32]) -> Vec<u64> {
+    vector.chunks(64).map(sign_bits).collect()
+}
+
+// The sign bits of at most 64 values
+fn sign_bits(values: &[f32]) -> u64 {
+    let mut word = 0;
+    for (i, value) in values.iter().enumerate() {
+        word |= ((value.to_bits() >> 31) as u64) << i;
+    }
+    word
+}
+
+/// The number of bits that differ between two binary quantized vectors.
+pub fn hamming_distance(a: &[u64], b: &[u64]) -> u32 {
+    a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum()
+}
+
+/// The hamming distance between the binary quantizations of two vectors, without
+/// allocating them, see `binary_quantize`.
+pub fn sign_hamming_distance(a: &[f32], b: &[f32]) -> u32 {
+    a.chunks(64)
+        .zip(b.chunks(64))
+        .map(|(a, b)| (sign_bits(a) ^ sign_bits(b)).count_ones())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_quantize() {
+        let vector: Vec<f32> = (0..70)
+            .map(|i| if i % 3 == 0 { -1.0 } else { 1.0 })
+            .collect();
+        let code = binary_quantize(&vector);
+        assert_eq!(code.len(), 2);
+        assert_eq!(code[0] & 0b1111, 0b1001);
+        assert_eq!(code[1], 0b10_0100);
+        assert_eq!(binary_quantize(&[0.0, -0.0, 2.0]), vec![0b010]);
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        let a: Vec<f32> = (0..100).map(|i| i as f32 - 50.0).collect();
+        let b: Vec<f32> = (0..100).map(|i| 50.0 - i as f32).collect();
+        // The values at 50 are both 0.0, every other sign differs
+        assert_eq!(
+            hamming_distance(&binary_quantize(&a), &binary_quantize(&b)),
+            99
+        );
+        assert_eq!(sign_hamming_distance(&a, &b), 99);
+        assert_eq!(sign_hamming_distance(&a, &a), 0);
+    }
+}
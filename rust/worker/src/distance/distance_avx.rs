@@ -203,7 +203,7 @@ copyright Qdrant, licensed under the Apache 2.0 license.
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+#[target_feature(enable = "avx,fma")]
 pub unsafe fn hsum256_ps_avx(x: __m256) -> f32 {
     let x128: __m128 = _mm_add_ps(_mm256_extractf128_ps(x, 1), _mm256_castps256_ps128(x));
     let x64: __m128 = _mm_add_ps(x128, _mm_movehl_ps(x128, x128));
@@ -211,7 +211,7 @@ pub unsafe fn hsum256_ps_avx(x: __m256) -> f32 {
     _mm_cvtss_f32(x32)
 }
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+#[target_feature(enable = "avx,fma")]
 pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 32);
@@ -256,7 +256,7 @@ pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+#[target_feature(enable = "avx,fma")]
 pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 32);
@@ -301,7 +301,7 @@ pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+#[target_feature(enable = "avx,fma")]
 pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 32);
@@ -343,3 +343,241 @@ pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     }
     result
 }
+
+#[target_feature(enable = "avx,fma")]
+pub unsafe fn manhattan_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 32);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    // The absolute value clears the sign bit
+    let sign_mask: __m256 = _mm256_set1_ps(-0.0);
+    let mut sum256_1: __m256 = _mm256_setzero_ps();
+    let mut sum256_2: __m256 = _mm256_setzero_ps();
+    let mut sum256_3: __m256 = _mm256_setzero_ps();
+    let mut sum256_4: __m256 = _mm256_setzero_ps();
+    let mut i: usize = 0;
+    while i < m {
+        let sub256_1: __m256 =
+            _mm256_sub_ps(_mm256_loadu_ps(ptr1.add(0)), _mm256_loadu_ps(ptr2.add(0)));
+        sum256_1 = _mm256_add_ps(_mm256_andnot_ps(sign_mask, sub256_1), sum256_1);
+
+        let sub256_2: __m256 =
+            _mm256_sub_ps(_mm256_loadu_ps(ptr1.add(8)), _mm256_loadu_ps(ptr2.add(8)));
+        sum256_2 = _mm256_add_ps(_mm256_andnot_ps(sign_mask, sub256_2), sum256_2);
+
+        let sub256_3: __m256 =
+            _mm256_sub_ps(_mm256_loadu_ps(ptr1.add(16)), _mm256_loadu_ps(ptr2.add(16)));
+        sum256_3 = _mm256_add_ps(_mm256_andnot_ps(sign_mask, sub256_3), sum256_3);
+
+        let sub256_4: __m256 =
+            _mm256_sub_ps(_mm256_loadu_ps(ptr1.add(24)), _mm256_loadu_ps(ptr2.add(24)));
+        sum256_4 = _mm256_add_ps(_mm256_andnot_ps(sign_mask, sub256_4), sum256_4);
+
+        ptr1 = ptr1.add(32);
+        ptr2 = ptr2.add(32);
+        i += 32;
+    }
+
+    let mut result = hsum256_ps_avx(sum256_1)
+        + hsum256_ps_avx(sum256_2)
+        + hsum256_ps_avx(sum256_3)
+        + hsum256_ps_avx(sum256_4);
+    for i in 0..n - m {
+        result += (*ptr1.add(i) - *ptr2.add(i)).abs();
+    }
+    result
+}
+
+#[target_feature(enable = "avx,fma")]
+pub unsafe fn jaccard_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut min256_1: __m256 = _mm256_setzero_ps();
+    let mut min256_2: __m256 = _mm256_setzero_ps();
+    let mut max256_1: __m256 = _mm256_setzero_ps();
+    let mut max256_2: __m256 = _mm256_setzero_ps();
+    let mut i: usize = 0;
+    while i < m {
+        let a256_1 = _mm256_loadu_ps(ptr1);
+        let b256_1 = _mm256_loadu_ps(ptr2);
+        min256_1 = _mm256_add_ps(_mm256_min_ps(a256_1, b256_1), min256_1);
+        max256_1 = _mm256_add_ps(_mm256_max_ps(a256_1, b256_1), max256_1);
+
+        let a256_2 = _mm256_loadu_ps(ptr1.add(8));
+        let b256_2 = _mm256_loadu_ps(ptr2.add(8));
+        min256_2 = _mm256_add_ps(_mm256_min_ps(a256_2, b256_2), min256_2);
+        max256_2 = _mm256_add_ps(_mm256_max_ps(a256_2, b256_2), max256_2);
+
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+
+    let mut min_sum = hsum256_ps_avx(min256_1) + hsum256_ps_avx(min256_2);
+    let mut max_sum = hsum256_ps_avx(max256_1) + hsum256_ps_avx(max256_2);
+    for i in 0..n - m {
+        min_sum += (*ptr1.add(i)).min(*ptr2.add(i));
+        max_sum += (*ptr1.add(i)).max(*ptr2.add(i));
+    }
+    if max_sum == 0.0 {
+        return 0.0;
+    }
+    1.0_f32 - min_sum / max_sum
+}
+
+// The sign bits of 8 values at a time are gathered by _mm256_movemask_ps
+#[target_feature(enable = "avx,fma")]
+pub unsafe fn sign_bit_distance(a: &[f32], b: &[f32]) -> u32 {
+    let n = a.len();
+    let m = n - (n % 32);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut result: u32 = 0;
+    let mut i: usize = 0;
+    while i < m {
+        let mask_1 =
+            _mm256_movemask_ps(_mm256_xor_ps(_mm256_loadu_ps(ptr1), _mm256_loadu_ps(ptr2))) as u32;
+        let mask_2 = _mm256_movemask_ps(_mm256_xor_ps(
+            _mm256_loadu_ps(ptr1.add(8)),
+            _mm256_loadu_ps(ptr2.add(8)),
+        )) as u32;
+        let mask_3 = _mm256_movemask_ps(_mm256_xor_ps(
+            _mm256_loadu_ps(ptr1.add(16)),
+            _mm256_loadu_ps(ptr2.add(16)),
+        )) as u32;
+        let mask_4 = _mm256_movemask_ps(_mm256_xor_ps(
+            _mm256_loadu_ps(ptr1.add(24)),
+            _mm256_loadu_ps(ptr2.add(24)),
+        )) as u32;
+        result += (mask_1 | mask_2 << 8 | mask_3 << 16 | mask_4 << 24).count_ones();
+
+        ptr1 = ptr1.add(32);
+        ptr2 = ptr2.add(32);
+        i += 32;
+    }
+
+    for i in 0..n - m {
+        result += ((*ptr1.add(i)).to_bits() ^ (*ptr2.add(i)).to_bits()) >> 31;
+    }
+    result
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn hsum256_epi32_avx2(x: __m256i) -> i32 {
+    let x128: __m128i = _mm_add_epi32(_mm256_extracti128_si256(x, 1), _mm256_castsi256_si128(x));
+    let x64: __m128i = _mm_add_epi32(x128, _mm_unpackhi_epi64(x128, x128));
+    let x32: __m128i = _mm_add_epi32(x64, _mm_shuffle_epi32(x64, 0x55));
+    _mm_cvtsi128_si32(x32)
+}
+
+// Products of int8 values are summed in 16 bit pairs by _mm256_madd_epi16, which
+// can not overflow for values in [-127, 127].
+#[target_feature(enable = "avx2")]
+pub unsafe fn int8_dot_product(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 32);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let mut sum256_1: __m256i = _mm256_setzero_si256();
+    let mut sum256_2: __m256i = _mm256_setzero_si256();
+    let mut i: usize = 0;
+    while i < m {
+        let a256_1 = _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr1 as *const __m128i));
+        let b256_1 = _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr2 as *const __m128i));
+        sum256_1 = _mm256_add_epi32(sum256_1, _mm256_madd_epi16(a256_1, b256_1));
+
+        let a256_2 = _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr1.add(16) as *const __m128i));
+        let b256_2 = _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr2.add(16) as *const __m128i));
+        sum256_2 = _mm256_add_epi32(sum256_2, _mm256_madd_epi16(a256_2, b256_2));
+
+        ptr1 = ptr1.add(32);
+        ptr2 = ptr2.add(32);
+        i += 32;
+    }
+
+    let mut result = hsum256_epi32_avx2(_mm256_add_epi32(sum256_1, sum256_2));
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32) * (*ptr2.add(i) as i32);
+    }
+    result
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn int8_euclidean_distance(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 32);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let mut sum256_1: __m256i = _mm256_setzero_si256();
+    let mut sum256_2: __m256i = _mm256_setzero_si256();
+    let mut i: usize = 0;
+    while i < m {
+        let sub256_1 = _mm256_sub_epi16(
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr1 as *const __m128i)),
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr2 as *const __m128i)),
+        );
+        sum256_1 = _mm256_add_epi32(sum256_1, _mm256_madd_epi16(sub256_1, sub256_1));
+
+        let sub256_2 = _mm256_sub_epi16(
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr1.add(16) as *const __m128i)),
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr2.add(16) as *const __m128i)),
+        );
+        sum256_2 = _mm256_add_epi32(sum256_2, _mm256_madd_epi16(sub256_2, sub256_2));
+
+        ptr1 = ptr1.add(32);
+        ptr2 = ptr2.add(32);
+        i += 32;
+    }
+
+    let mut result = hsum256_epi32_avx2(_mm256_add_epi32(sum256_1, sum256_2));
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32 - *ptr2.add(i) as i32).pow(2);
+    }
+    result
+}
+
+// The absolute differences fit in int16, and are summed in pairs into int32 by
+// _mm256_madd_epi16 with ones.
+#[target_feature(enable = "avx2")]
+pub unsafe fn int8_manhattan_distance(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 32);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let ones: __m256i = _mm256_set1_epi16(1);
+    let mut sum256_1: __m256i = _mm256_setzero_si256();
+    let mut sum256_2: __m256i = _mm256_setzero_si256();
+    let mut i: usize = 0;
+    while i < m {
+        let sub256_1 = _mm256_sub_epi16(
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr1 as *const __m128i)),
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr2 as *const __m128i)),
+        );
+        sum256_1 = _mm256_add_epi32(
+            sum256_1,
+            _mm256_madd_epi16(_mm256_abs_epi16(sub256_1), ones),
+        );
+
+        let sub256_2 = _mm256_sub_epi16(
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr1.add(16) as *const __m128i)),
+            _mm256_cvtepi8_epi16(_mm_loadu_si128(ptr2.add(16) as *const __m128i)),
+        );
+        sum256_2 = _mm256_add_epi32(
+            sum256_2,
+            _mm256_madd_epi16(_mm256_abs_epi16(sub256_2), ones),
+        );
+
+        ptr1 = ptr1.add(32);
+        ptr2 = ptr2.add(32);
+        i += 32;
+    }
+
+    let mut result = hsum256_epi32_avx2(_mm256_add_epi32(sum256_1, sum256_2));
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32 - *ptr2.add(i) as i32).abs();
+    }
+    result
+}
@@ -0,0 +1,121 @@
+use std::arch::x86_64::*;
+
+// The last n < 16 values of a vector, with zeros past the end
+#[target_feature(enable = "avx512f")]
+unsafe fn loadu_tail_ps(ptr: *const f32, n: usize) -> __m512 {
+    _mm512_maskz_loadu_ps(((1_u32 << n) - 1) as __mmask16, ptr)
+}
+
+#[target_feature(enable = "avx512f")]
+pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0_f32 - dot_product(a, b)
+}
+
+#[target_feature(enable = "avx512f")]
+pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
+    1.0_f32 - dot_product(a, b)
+}
+
+#[target_feature(enable = "avx512f")]
+unsafe fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 64);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut sum512_1: __m512 = _mm512_setzero_ps();
+    let mut sum512_2: __m512 = _mm512_setzero_ps();
+    let mut sum512_3: __m512 = _mm512_setzero_ps();
+    let mut sum512_4: __m512 = _mm512_setzero_ps();
+    let mut i: usize = 0;
+    while i < m {
+        sum512_1 = _mm512_fmadd_ps(_mm512_loadu_ps(ptr1), _mm512_loadu_ps(ptr2), sum512_1);
+        sum512_2 = _mm512_fmadd_ps(
+            _mm512_loadu_ps(ptr1.add(16)),
+            _mm512_loadu_ps(ptr2.add(16)),
+            sum512_2,
+        );
+        sum512_3 = _mm512_fmadd_ps(
+            _mm512_loadu_ps(ptr1.add(32)),
+            _mm512_loadu_ps(ptr2.add(32)),
+            sum512_3,
+        );
+        sum512_4 = _mm512_fmadd_ps(
+            _mm512_loadu_ps(ptr1.add(48)),
+            _mm512_loadu_ps(ptr2.add(48)),
+            sum512_4,
+        );
+
+        ptr1 = ptr1.add(64);
+        ptr2 = ptr2.add(64);
+        i += 64;
+    }
+    while i + 16 <= n {
+        sum512_1 = _mm512_fmadd_ps(_mm512_loadu_ps(ptr1), _mm512_loadu_ps(ptr2), sum512_1);
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+    // The last values are loaded with a mask instead of a scalar loop
+    if i < n {
+        sum512_2 = _mm512_fmadd_ps(
+            loadu_tail_ps(ptr1, n - i),
+            loadu_tail_ps(ptr2, n - i),
+            sum512_2,
+        );
+    }
+
+    _mm512_reduce_add_ps(_mm512_add_ps(
+        _mm512_add_ps(sum512_1, sum512_2),
+        _mm512_add_ps(sum512_3, sum512_4),
+    ))
+}
+
+#[target_feature(enable = "avx512f")]
+pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 64);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut sum512_1: __m512 = _mm512_setzero_ps();
+    let mut sum512_2: __m512 = _mm512_setzero_ps();
+    let mut sum512_3: __m512 = _mm512_setzero_ps();
+    let mut sum512_4: __m512 = _mm512_setzero_ps();
+    let mut i: usize = 0;
+    while i < m {
+        let sub512_1: __m512 = _mm512_sub_ps(_mm512_loadu_ps(ptr1), _mm512_loadu_ps(ptr2));
+        sum512_1 = _mm512_fmadd_ps(sub512_1, sub512_1, sum512_1);
+
+        let sub512_2: __m512 =
+            _mm512_sub_ps(_mm512_loadu_ps(ptr1.add(16)), _mm512_loadu_ps(ptr2.add(16)));
+        sum512_2 = _mm512_fmadd_ps(sub512_2, sub512_2, sum512_2);
+
+        let sub512_3: __m512 =
+            _mm512_sub_ps(_mm512_loadu_ps(ptr1.add(32)), _mm512_loadu_ps(ptr2.add(32)));
+        sum512_3 = _mm512_fmadd_ps(sub512_3, sub512_3, sum512_3);
+
+        let sub512_4: __m512 =
+            _mm512_sub_ps(_mm512_loadu_ps(ptr1.add(48)), _mm512_loadu_ps(ptr2.add(48)));
+        sum512_4 = _mm512_fmadd_ps(sub512_4, sub512_4, sum512_4);
+
+        ptr1 = ptr1.add(64);
+        ptr2 = ptr2.add(64);
+        i += 64;
+    }
+    while i + 16 <= n {
+        let sub512: __m512 = _mm512_sub_ps(_mm512_loadu_ps(ptr1), _mm512_loadu_ps(ptr2));
+        sum512_1 = _mm512_fmadd_ps(sub512, sub512, sum512_1);
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+    // The last values are loaded with a mask instead of a scalar loop
+    if i < n {
+        let sub512: __m512 = _mm512_sub_ps(loadu_tail_ps(ptr1, n - i), loadu_tail_ps(ptr2, n - i));
+        sum512_2 = _mm512_fmadd_ps(sub512, sub512, sum512_2);
+    }
+
+    _mm512_reduce_add_ps(_mm512_add_ps(
+        _mm512_add_ps(sum512_1, sum512_2),
+        _mm512_add_ps(sum512_3, sum512_4),
+    ))
+}
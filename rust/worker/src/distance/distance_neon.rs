@@ -200,10 +200,9 @@ copyright Qdrant, licensed under the Apache 2.0 license.
    limitations under the License.
 */
 
-#[cfg(target_feature = "neon")]
 use std::arch::aarch64::*;
 
-#[cfg(target_feature = "neon")]
+#[target_feature(enable = "neon")]
 pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -231,7 +230,7 @@ pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(target_feature = "neon")]
+#[target_feature(enable = "neon")]
 pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -259,7 +258,7 @@ pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(target_feature = "neon")]
+#[target_feature(enable = "neon")]
 pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -294,3 +293,203 @@ pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     }
     result
 }
+
+#[target_feature(enable = "neon")]
+pub unsafe fn manhattan_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut sum1 = vdupq_n_f32(0.);
+    let mut sum2 = vdupq_n_f32(0.);
+    let mut sum3 = vdupq_n_f32(0.);
+    let mut sum4 = vdupq_n_f32(0.);
+
+    let mut i: usize = 0;
+    while i < m {
+        sum1 = vaddq_f32(sum1, vabdq_f32(vld1q_f32(ptr1), vld1q_f32(ptr2)));
+        sum2 = vaddq_f32(
+            sum2,
+            vabdq_f32(vld1q_f32(ptr1.add(4)), vld1q_f32(ptr2.add(4))),
+        );
+        sum3 = vaddq_f32(
+            sum3,
+            vabdq_f32(vld1q_f32(ptr1.add(8)), vld1q_f32(ptr2.add(8))),
+        );
+        sum4 = vaddq_f32(
+            sum4,
+            vabdq_f32(vld1q_f32(ptr1.add(12)), vld1q_f32(ptr2.add(12))),
+        );
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+    let mut result = vaddvq_f32(sum1) + vaddvq_f32(sum2) + vaddvq_f32(sum3) + vaddvq_f32(sum4);
+    for i in 0..n - m {
+        result += (*ptr1.add(i) - *ptr2.add(i)).abs();
+    }
+    result
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn jaccard_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 8);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut min1 = vdupq_n_f32(0.);
+    let mut min2 = vdupq_n_f32(0.);
+    let mut max1 = vdupq_n_f32(0.);
+    let mut max2 = vdupq_n_f32(0.);
+
+    let mut i: usize = 0;
+    while i < m {
+        let a1 = vld1q_f32(ptr1);
+        let b1 = vld1q_f32(ptr2);
+        min1 = vaddq_f32(min1, vminq_f32(a1, b1));
+        max1 = vaddq_f32(max1, vmaxq_f32(a1, b1));
+
+        let a2 = vld1q_f32(ptr1.add(4));
+        let b2 = vld1q_f32(ptr2.add(4));
+        min2 = vaddq_f32(min2, vminq_f32(a2, b2));
+        max2 = vaddq_f32(max2, vmaxq_f32(a2, b2));
+
+        ptr1 = ptr1.add(8);
+        ptr2 = ptr2.add(8);
+        i += 8;
+    }
+    let mut min_sum = vaddvq_f32(min1) + vaddvq_f32(min2);
+    let mut max_sum = vaddvq_f32(max1) + vaddvq_f32(max2);
+    for i in 0..n - m {
+        min_sum += (*ptr1.add(i)).min(*ptr2.add(i));
+        max_sum += (*ptr1.add(i)).max(*ptr2.add(i));
+    }
+    if max_sum == 0.0 {
+        return 0.0;
+    }
+    1.0_f32 - min_sum / max_sum
+}
+
+// The sign bits of the xor of the values are shifted down and counted per lane
+#[target_feature(enable = "neon")]
+pub unsafe fn sign_bit_distance(a: &[f32], b: &[f32]) -> u32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut sum1 = vdupq_n_u32(0);
+    let mut sum2 = vdupq_n_u32(0);
+
+    let mut i: usize = 0;
+    while i < m {
+        let xor1 = veorq_u32(vld1q_u32(ptr1 as *const u32), vld1q_u32(ptr2 as *const u32));
+        let xor2 = veorq_u32(
+            vld1q_u32(ptr1.add(4) as *const u32),
+            vld1q_u32(ptr2.add(4) as *const u32),
+        );
+        let xor3 = veorq_u32(
+            vld1q_u32(ptr1.add(8) as *const u32),
+            vld1q_u32(ptr2.add(8) as *const u32),
+        );
+        let xor4 = veorq_u32(
+            vld1q_u32(ptr1.add(12) as *const u32),
+            vld1q_u32(ptr2.add(12) as *const u32),
+        );
+        sum1 = vsraq_n_u32::<31>(sum1, xor1);
+        sum2 = vsraq_n_u32::<31>(sum2, xor2);
+        sum1 = vsraq_n_u32::<31>(sum1, xor3);
+        sum2 = vsraq_n_u32::<31>(sum2, xor4);
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+    let mut result = vaddvq_u32(sum1) + vaddvq_u32(sum2);
+    for i in 0..n - m {
+        result += ((*ptr1.add(i)).to_bits() ^ (*ptr2.add(i)).to_bits()) >> 31;
+    }
+    result
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn int8_dot_product(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let mut sum1 = vdupq_n_s32(0);
+    let mut sum2 = vdupq_n_s32(0);
+
+    let mut i: usize = 0;
+    while i < m {
+        let a1 = vld1q_s8(ptr1);
+        let b1 = vld1q_s8(ptr2);
+        sum1 = vpadalq_s16(sum1, vmull_s8(vget_low_s8(a1), vget_low_s8(b1)));
+        sum2 = vpadalq_s16(sum2, vmull_high_s8(a1, b1));
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+    let mut result = vaddvq_s32(sum1) + vaddvq_s32(sum2);
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32) * (*ptr2.add(i) as i32);
+    }
+    result
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn int8_euclidean_distance(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let mut sum1 = vdupq_n_s32(0);
+    let mut sum2 = vdupq_n_s32(0);
+
+    let mut i: usize = 0;
+    while i < m {
+        let a1 = vld1q_s8(ptr1);
+        let b1 = vld1q_s8(ptr2);
+        // Squares of differences can exceed int16, so they are accumulated in int32
+        let sub_lo = vsubl_s8(vget_low_s8(a1), vget_low_s8(b1));
+        let sub_hi = vsubl_high_s8(a1, b1);
+        sum1 = vmlal_s16(sum1, vget_low_s16(sub_lo), vget_low_s16(sub_lo));
+        sum1 = vmlal_high_s16(sum1, sub_lo, sub_lo);
+        sum2 = vmlal_s16(sum2, vget_low_s16(sub_hi), vget_low_s16(sub_hi));
+        sum2 = vmlal_high_s16(sum2, sub_hi, sub_hi);
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+    let mut result = vaddvq_s32(sum1) + vaddvq_s32(sum2);
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32 - *ptr2.add(i) as i32).pow(2);
+    }
+    result
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn int8_manhattan_distance(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let mut sum1 = vdupq_n_s32(0);
+    let mut sum2 = vdupq_n_s32(0);
+
+    let mut i: usize = 0;
+    while i < m {
+        let a1 = vld1q_s8(ptr1);
+        let b1 = vld1q_s8(ptr2);
+        // The absolute differences are widened to int16 and summed in pairs into int32
+        sum1 = vpadalq_s16(sum1, vabdl_s8(vget_low_s8(a1), vget_low_s8(b1)));
+        sum2 = vpadalq_s16(sum2, vabdl_high_s8(a1, b1));
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+    let mut result = vaddvq_s32(sum1) + vaddvq_s32(sum2);
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32 - *ptr2.add(i) as i32).abs();
+    }
+    result
+}
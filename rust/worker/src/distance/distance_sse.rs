@@ -205,14 +205,14 @@ use std::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
-#[cfg(target_feature = "sse")]
+#[target_feature(enable = "sse")]
 pub unsafe fn hsum128_ps_sse(x: __m128) -> f32 {
     let x64: __m128 = _mm_add_ps(x, _mm_movehl_ps(x, x));
     let x32: __m128 = _mm_add_ss(x64, _mm_shuffle_ps(x64, x64, 0x55));
     _mm_cvtss_f32(x32)
 }
 
-#[cfg(target_feature = "sse")]
+#[target_feature(enable = "sse")]
 pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -257,7 +257,7 @@ pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(target_feature = "sse")]
+#[target_feature(enable = "sse")]
 pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -302,7 +302,7 @@ pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(target_feature = "sse")]
+#[target_feature(enable = "sse")]
 pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -340,3 +340,230 @@ pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     }
     result
 }
+
+#[target_feature(enable = "sse")]
+pub unsafe fn manhattan_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    // The absolute value clears the sign bit
+    let sign_mask: __m128 = _mm_set1_ps(-0.0);
+    let mut sum128_1: __m128 = _mm_setzero_ps();
+    let mut sum128_2: __m128 = _mm_setzero_ps();
+    let mut sum128_3: __m128 = _mm_setzero_ps();
+    let mut sum128_4: __m128 = _mm_setzero_ps();
+    let mut i: usize = 0;
+    while i < m {
+        let sub128_1 = _mm_sub_ps(_mm_loadu_ps(ptr1), _mm_loadu_ps(ptr2));
+        sum128_1 = _mm_add_ps(_mm_andnot_ps(sign_mask, sub128_1), sum128_1);
+
+        let sub128_2 = _mm_sub_ps(_mm_loadu_ps(ptr1.add(4)), _mm_loadu_ps(ptr2.add(4)));
+        sum128_2 = _mm_add_ps(_mm_andnot_ps(sign_mask, sub128_2), sum128_2);
+
+        let sub128_3 = _mm_sub_ps(_mm_loadu_ps(ptr1.add(8)), _mm_loadu_ps(ptr2.add(8)));
+        sum128_3 = _mm_add_ps(_mm_andnot_ps(sign_mask, sub128_3), sum128_3);
+
+        let sub128_4 = _mm_sub_ps(_mm_loadu_ps(ptr1.add(12)), _mm_loadu_ps(ptr2.add(12)));
+        sum128_4 = _mm_add_ps(_mm_andnot_ps(sign_mask, sub128_4), sum128_4);
+
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+
+    let mut result = hsum128_ps_sse(sum128_1)
+        + hsum128_ps_sse(sum128_2)
+        + hsum128_ps_sse(sum128_3)
+        + hsum128_ps_sse(sum128_4);
+    for i in 0..n - m {
+        result += (*ptr1.add(i) - *ptr2.add(i)).abs();
+    }
+    result
+}
+
+#[target_feature(enable = "sse")]
+pub unsafe fn jaccard_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let m = n - (n % 8);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut min128_1: __m128 = _mm_setzero_ps();
+    let mut min128_2: __m128 = _mm_setzero_ps();
+    let mut max128_1: __m128 = _mm_setzero_ps();
+    let mut max128_2: __m128 = _mm_setzero_ps();
+    let mut i: usize = 0;
+    while i < m {
+        let a128_1 = _mm_loadu_ps(ptr1);
+        let b128_1 = _mm_loadu_ps(ptr2);
+        min128_1 = _mm_add_ps(_mm_min_ps(a128_1, b128_1), min128_1);
+        max128_1 = _mm_add_ps(_mm_max_ps(a128_1, b128_1), max128_1);
+
+        let a128_2 = _mm_loadu_ps(ptr1.add(4));
+        let b128_2 = _mm_loadu_ps(ptr2.add(4));
+        min128_2 = _mm_add_ps(_mm_min_ps(a128_2, b128_2), min128_2);
+        max128_2 = _mm_add_ps(_mm_max_ps(a128_2, b128_2), max128_2);
+
+        ptr1 = ptr1.add(8);
+        ptr2 = ptr2.add(8);
+        i += 8;
+    }
+
+    let mut min_sum = hsum128_ps_sse(min128_1) + hsum128_ps_sse(min128_2);
+    let mut max_sum = hsum128_ps_sse(max128_1) + hsum128_ps_sse(max128_2);
+    for i in 0..n - m {
+        min_sum += (*ptr1.add(i)).min(*ptr2.add(i));
+        max_sum += (*ptr1.add(i)).max(*ptr2.add(i));
+    }
+    if max_sum == 0.0 {
+        return 0.0;
+    }
+    1.0_f32 - min_sum / max_sum
+}
+
+// The sign bits of 4 values at a time are gathered by _mm_movemask_ps
+#[target_feature(enable = "sse")]
+pub unsafe fn sign_bit_distance(a: &[f32], b: &[f32]) -> u32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut result: u32 = 0;
+    let mut i: usize = 0;
+    while i < m {
+        let mask_1 = _mm_movemask_ps(_mm_xor_ps(_mm_loadu_ps(ptr1), _mm_loadu_ps(ptr2))) as u32;
+        let mask_2 = _mm_movemask_ps(_mm_xor_ps(
+            _mm_loadu_ps(ptr1.add(4)),
+            _mm_loadu_ps(ptr2.add(4)),
+        )) as u32;
+        let mask_3 = _mm_movemask_ps(_mm_xor_ps(
+            _mm_loadu_ps(ptr1.add(8)),
+            _mm_loadu_ps(ptr2.add(8)),
+        )) as u32;
+        let mask_4 = _mm_movemask_ps(_mm_xor_ps(
+            _mm_loadu_ps(ptr1.add(12)),
+            _mm_loadu_ps(ptr2.add(12)),
+        )) as u32;
+        result += (mask_1 | mask_2 << 4 | mask_3 << 8 | mask_4 << 12).count_ones();
+
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+
+    for i in 0..n - m {
+        result += ((*ptr1.add(i)).to_bits() ^ (*ptr2.add(i)).to_bits()) >> 31;
+    }
+    result
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn hsum128_epi32_sse2(x: __m128i) -> i32 {
+    let x64: __m128i = _mm_add_epi32(x, _mm_unpackhi_epi64(x, x));
+    let x32: __m128i = _mm_add_epi32(x64, _mm_shuffle_epi32(x64, 0x55));
+    _mm_cvtsi128_si32(x32)
+}
+
+// Sign extends the low and high 8 lanes of int8 values to int16, SSE2 has no
+// _mm_cvtepi8_epi16.
+#[target_feature(enable = "sse2")]
+unsafe fn cvtepi8_epi16_sse2(x: __m128i) -> (__m128i, __m128i) {
+    (
+        _mm_srai_epi16(_mm_unpacklo_epi8(x, x), 8),
+        _mm_srai_epi16(_mm_unpackhi_epi8(x, x), 8),
+    )
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn int8_dot_product(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let mut sum128_1: __m128i = _mm_setzero_si128();
+    let mut sum128_2: __m128i = _mm_setzero_si128();
+
+    let mut i: usize = 0;
+    while i < m {
+        let (a128_lo, a128_hi) = cvtepi8_epi16_sse2(_mm_loadu_si128(ptr1 as *const __m128i));
+        let (b128_lo, b128_hi) = cvtepi8_epi16_sse2(_mm_loadu_si128(ptr2 as *const __m128i));
+        sum128_1 = _mm_add_epi32(sum128_1, _mm_madd_epi16(a128_lo, b128_lo));
+        sum128_2 = _mm_add_epi32(sum128_2, _mm_madd_epi16(a128_hi, b128_hi));
+
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+
+    let mut result = hsum128_epi32_sse2(_mm_add_epi32(sum128_1, sum128_2));
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32) * (*ptr2.add(i) as i32);
+    }
+    result
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn int8_euclidean_distance(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let mut sum128_1: __m128i = _mm_setzero_si128();
+    let mut sum128_2: __m128i = _mm_setzero_si128();
+
+    let mut i: usize = 0;
+    while i < m {
+        let (a128_lo, a128_hi) = cvtepi8_epi16_sse2(_mm_loadu_si128(ptr1 as *const __m128i));
+        let (b128_lo, b128_hi) = cvtepi8_epi16_sse2(_mm_loadu_si128(ptr2 as *const __m128i));
+        let sub128_lo = _mm_sub_epi16(a128_lo, b128_lo);
+        let sub128_hi = _mm_sub_epi16(a128_hi, b128_hi);
+        sum128_1 = _mm_add_epi32(sum128_1, _mm_madd_epi16(sub128_lo, sub128_lo));
+        sum128_2 = _mm_add_epi32(sum128_2, _mm_madd_epi16(sub128_hi, sub128_hi));
+
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+
+    let mut result = hsum128_epi32_sse2(_mm_add_epi32(sum128_1, sum128_2));
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32 - *ptr2.add(i) as i32).pow(2);
+    }
+    result
+}
+
+// SSE2 has no _mm_abs_epi16, the absolute differences are the maximum of the
+// differences and their negations.
+#[target_feature(enable = "sse2")]
+pub unsafe fn int8_manhattan_distance(a: &[i8], b: &[i8]) -> i32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const i8 = a.as_ptr();
+    let mut ptr2: *const i8 = b.as_ptr();
+    let zero: __m128i = _mm_setzero_si128();
+    let ones: __m128i = _mm_set1_epi16(1);
+    let mut sum128_1: __m128i = _mm_setzero_si128();
+    let mut sum128_2: __m128i = _mm_setzero_si128();
+
+    let mut i: usize = 0;
+    while i < m {
+        let (a128_lo, a128_hi) = cvtepi8_epi16_sse2(_mm_loadu_si128(ptr1 as *const __m128i));
+        let (b128_lo, b128_hi) = cvtepi8_epi16_sse2(_mm_loadu_si128(ptr2 as *const __m128i));
+        let sub128_lo = _mm_sub_epi16(a128_lo, b128_lo);
+        let sub128_hi = _mm_sub_epi16(a128_hi, b128_hi);
+        let abs128_lo = _mm_max_epi16(sub128_lo, _mm_sub_epi16(zero, sub128_lo));
+        let abs128_hi = _mm_max_epi16(sub128_hi, _mm_sub_epi16(zero, sub128_hi));
+        sum128_1 = _mm_add_epi32(sum128_1, _mm_madd_epi16(abs128_lo, ones));
+        sum128_2 = _mm_add_epi32(sum128_2, _mm_madd_epi16(abs128_hi, ones));
+
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+
+    let mut result = hsum128_epi32_sse2(_mm_add_epi32(sum128_1, sum128_2));
+    for i in 0..n - m {
+        result += (*ptr1.add(i) as i32 - *ptr2.add(i) as i32).abs();
+    }
+    result
+}
@@ -0,0 +1,367 @@
+use std::sync::OnceLock;
+
+/// A kernel over two float vectors of the same length.
+pub type FloatKernel = unsafe fn(&[f32], &[f32]) -> f32;
+/// A kernel counting the values of two float vectors whose signs differ.
+pub type SignBitKernel = unsafe fn(&[f32], &[f32]) -> u32;
+/// A kernel over two int8 vectors of the same length, with values in [-127, 127].
+pub type Int8Kernel = unsafe fn(&[i8], &[i8]) -> i32;
+
+/// The instruction sets with distance kernels.
+/// # Variants
+/// - `Avx512` - AVX-512F, with the AVX kernels for the distances it has no kernel for.
+/// - `Avx2` - AVX2, for the int8 kernels.
+/// - `Avx` - AVX and FMA, for the float kernels.
+/// - `Sse2` - SSE2, for the int8 kernels.
+/// - `Sse` - SSE, for the float kernels.
+/// - `Neon` - NEON, for both.
+/// - `Scalar` - No SIMD, supported everywhere.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionSet {
+    Avx512,
+    Avx2,
+    Avx,
+    Sse2,
+    Sse,
+    Neon,
+    Scalar,
+}
+
+impl InstructionSet {
+    /// The instruction sets the CPU supports, from the fastest.
+    pub fn supported() -> Vec<InstructionSet> {
+        [
+            InstructionSet::Avx512,
+            InstructionSet::Avx2,
+            InstructionSet::Avx,
+            InstructionSet::Sse2,
+            InstructionSet::Sse,
+            InstructionSet::Neon,
+            InstructionSet::Scalar,
+        ]
+        .into_iter()
+        .filter(|instruction_set| instruction_set.is_supported())
+        .collect()
+    }
+
+    fn is_supported(&self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            InstructionSet::Avx512 => {
+                std::arch::is_x86_feature_detected!("avx512f") && InstructionSet::Avx.is_supported()
+            }
+            #[cfg(target_arch = "x86_64")]
+            InstructionSet::Avx2 => std::arch::is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            InstructionSet::Avx => {
+                std::arch::is_x86_feature_detected!("avx")
+                    && std::arch::is_x86_feature_detected!("fma")
+            }
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            InstructionSet::Sse2 => std::arch::is_x86_feature_detected!("sse2"),
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            InstructionSet::Sse => std::arch::is_x86_feature_detected!("sse"),
+            #[cfg(target_arch = "aarch64")]
+            InstructionSet::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            InstructionSet::Scalar => true,
+            // Not built for this architecture
+            _ => false,
+        }
+    }
+}
+
+/// The float distance kernels of an instruction set.
+/// # Notes
+/// The kernels may only be called on CPUs that support `instruction_set`, which
+/// `for_instruction_set` checks.
+#[derive(Clone, Copy, Debug)]
+pub struct FloatKernels {
+    pub instruction_set: InstructionSet,
+    pub euclidean_distance: FloatKernel,
+    pub cosine_distance: FloatKernel,
+    pub inner_product: FloatKernel,
+    pub manhattan_distance: FloatKernel,
+    pub jaccard_distance: FloatKernel,
+    pub sign_bit_distance: SignBitKernel,
+}
+
+impl FloatKernels {
+    /// The kernels of the instruction set, None if the CPU does not support it or it
+    /// has no float kernels.
+    pub fn for_instruction_set(instruction_set: InstructionSet) -> Option<FloatKernels> {
+        if !instruction_set.is_supported() {
+            return None;
+        }
+        match instruction_set {
+            #[cfg(target_arch = "x86_64")]
+            InstructionSet::Avx512 => Some(FloatKernels {
+                instruction_set,
+                euclidean_distance: crate::distance::distance_avx512::euclidean_distance,
+                cosine_distance: crate::distance::distance_avx512::cosine_distance,
+                inner_product: crate::distance::distance_avx512::inner_product,
+                ..FloatKernels::for_instruction_set(InstructionSet::Avx)?
+            }),
+            #[cfg(target_arch = "x86_64")]
+            InstructionSet::Avx => Some(FloatKernels {
+                instruction_set,
+                euclidean_distance: crate::distance::distance_avx::euclidean_distance,
+                cosine_distance: crate::distance::distance_avx::cosine_distance,
+                inner_product: crate::distance::distance_avx::inner_product,
+                manhattan_distance: crate::distance::distance_avx::manhattan_distance,
+                jaccard_distance: crate::distance::distance_avx::jaccard_distance,
+                sign_bit_distance: crate::distance::distance_avx::sign_bit_distance,
+            }),
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            InstructionSet::Sse => Some(FloatKernels {
+                instruction_set,
+                euclidean_distance: crate::distance::distance_sse::euclidean_distance,
+                cosine_distance: crate::distance::distance_sse::cosine_distance,
+                inner_product: crate::distance::distance_sse::inner_product,
+                manhattan_distance: crate::distance::distance_sse::manhattan_distance,
+                jaccard_distance: crate::distance::distance_sse::jaccard_distance,
+                sign_bit_distance: crate::distance::distance_sse::sign_bit_distance,
+            }),
+            #[cfg(target_arch = "aarch64")]
+            InstructionSet::Neon => Some(FloatKernels {
+                instruction_set,
+                euclidean_distance: crate::distance::distance_neon::euclidean_distance,
+                cosine_distance: crate::distance::distance_neon::cosine_distance,
+                inner_product: crate::distance::distance_neon::inner_product,
+                manhattan_distance: crate::distance::distance_neon::manhattan_distance,
+                jaccard_distance: crate::distance::distance_neon::jaccard_distance,
+                sign_bit_distance: crate::distance::distance_neon::sign_bit_distance,
+            }),
+            InstructionSet::Scalar => Some(FloatKernels {
+                instruction_set,
+                euclidean_distance: crate::distance::scalar::euclidean_distance,
+                cosine_distance: crate::distance::scalar::cosine_distance,
+                inner_product: crate::distance::scalar::inner_product,
+                manhattan_distance: crate::distance::scalar::manhattan_distance,
+                jaccard_distance: crate::distance::scalar::jaccard_distance,
+                sign_bit_distance: crate::distance::scalar::sign_bit_distance,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The int8 distance kernels of an instruction set, see `FloatKernels`.
+#[derive(Clone, Copy, Debug)]
+pub struct Int8Kernels {
+    pub instruction_set: InstructionSet,
+    pub dot_product: Int8Kernel,
+    pub euclidean_distance: Int8Kernel,
+    pub manhattan_distance: Int8Kernel,
+}
+
+impl Int8Kernels {
+    /// The kernels of the instruction set, None if the CPU does not support it or it
+    /// has no int8 kernels.
+    pub fn for_instruction_set(instruction_set: InstructionSet) -> Option<Int8Kernels> {
+        if !instruction_set.is_supported() {
+            return None;
+        }
+        match instruction_set {
+            #[cfg(target_arch = "x86_64")]
+            InstructionSet::Avx2 => Some(Int8Kernels {
+                instruction_set,
+                dot_product: crate::distance::distance_avx::int8_dot_product,
+                euclidean_distance: crate::distance::distance_avx::int8_euclidean_distance,
+                manhattan_distance: crate::distance::distance_avx::int8_manhattan_distance,
+            }),
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            InstructionSet::Sse2 => Some(Int8Kernels {
+                instruction_set,
+                dot_product: crate::distance::distance_sse::int8_dot_product,
+                euclidean_distance: crate::distance::distance_sse::int8_euclidean_distance,
+                manhattan_distance: crate::distance::distance_sse::int8_manhattan_distance,
+            }),
+            #[cfg(target_arch = "aarch64")]
+            InstructionSet::Neon => Some(Int8Kernels {
+                instruction_set,
+                dot_product: crate::distance::distance_neon::int8_dot_product,
+                euclidean_distance: crate::distance::distance_neon::int8_euclidean_distance,
+                manhattan_distance: crate::distance::distance_neon::int8_manhattan_distance,
+            }),
+            InstructionSet::Scalar => Some(Int8Kernels {
+                instruction_set,
+                dot_product: crate::distance::scalar::int8_dot_product,
+                euclidean_distance: crate::distance::scalar::int8_euclidean_distance,
+                manhattan_distance: crate::distance::scalar::int8_manhattan_distance,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The float kernels of the fastest instruction set the CPU supports, detected on the
+/// first call.
+pub fn float_kernels() -> &'static FloatKernels {
+    static KERNELS: OnceLock<FloatKernels> = OnceLock::new();
+    KERNELS.get_or_init(|| {
+        let kernels = InstructionSet::supported()
+            .into_iter()
+            .find_map(FloatKernels::for_instruction_set)
+            .expect("Scalar kernels are supported everywhere");
+        tracing::info!(
+            "Using the {:?} float distance kernels",
+            kernels.instruction_set
+        );
+        kernels
+    })
+}
+
+/// The int8 kernels of the fastest instruction set the CPU supports, detected on the
+/// first call.
+pub fn int8_kernels() -> &'static Int8Kernels {
+    static KERNELS: OnceLock<Int8Kernels> = OnceLock::new();
+    KERNELS.get_or_init(|| {
+        let kernels = InstructionSet::supported()
+            .into_iter()
+            .find_map(Int8Kernels::for_instruction_set)
+            .expect("Scalar kernels are supported everywhere");
+        tracing::info!(
+            "Using the {:?} int8 distance kernels",
+            kernels.instruction_set
+        );
+        kernels
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::scalar;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::test_runner::Config;
+
+    // Pairs of vectors of the same length, long enough for the SIMD loops of every
+    // instruction set and their remainders
+    fn float_vectors(values: std::ops::Range<f32>) -> impl Strategy<Value = (Vec<f32>, Vec<f32>)> {
+        (0..300_usize).prop_flat_map(move |n| (vec(values.clone(), n), vec(values.clone(), n)))
+    }
+
+    fn int8_vectors() -> impl Strategy<Value = (Vec<i8>, Vec<i8>)> {
+        (0..300_usize).prop_flat_map(|n| (vec(-127..=127_i8, n), vec(-127..=127_i8, n)))
+    }
+
+    // The float kernels sum in a different order than the scalar kernels, so their
+    // results differ by rounding, relative to the magnitude of the summed terms
+    fn assert_close(actual: f32, expected: f32, magnitude: f32) {
+        let tolerance = 1e-4 * (1.0 + magnitude);
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    fn all_float_kernels() -> Vec<FloatKernels> {
+        InstructionSet::supported()
+            .into_iter()
+            .filter_map(FloatKernels::for_instruction_set)
+            .collect()
+    }
+
+    fn all_int8_kernels() -> Vec<Int8Kernels> {
+        InstructionSet::supported()
+            .into_iter()
+            .filter_map(Int8Kernels::for_instruction_set)
+            .collect()
+    }
+
+    #[test]
+    fn test_selects_the_fastest_supported_kernels() {
+        let supported = InstructionSet::supported();
+        assert_eq!(supported.last(), Some(&InstructionSet::Scalar));
+        assert_eq!(
+            Some(float_kernels().instruction_set),
+            all_float_kernels()
+                .first()
+                .map(|kernels| kernels.instruction_set)
+        );
+        assert_eq!(
+            Some(int8_kernels().instruction_set),
+            all_int8_kernels()
+                .first()
+                .map(|kernels| kernels.instruction_set)
+        );
+    }
+
+    proptest! {
+        #![proptest_config(Config::with_cases(200))]
+        #[test]
+        fn test_float_kernels_match_scalar((a, b) in float_vectors(-100.0..100.0)) {
+            let dot_magnitude: f32 = a.iter().zip(&b).map(|(a, b)| (a * b).abs()).sum();
+            for kernels in all_float_kernels() {
+                // Safety: for_instruction_set only returns kernels the CPU supports
+                unsafe {
+                    let expected = scalar::euclidean_distance(&a, &b);
+                    assert_close((kernels.euclidean_distance)(&a, &b), expected, expected);
+                    assert_close(
+                        (kernels.cosine_distance)(&a, &b),
+                        scalar::cosine_distance(&a, &b),
+                        dot_magnitude,
+                    );
+                    assert_close(
+                        (kernels.inner_product)(&a, &b),
+                        scalar::inner_product(&a, &b),
+                        dot_magnitude,
+                    );
+                    let expected = scalar::manhattan_distance(&a, &b);
+                    assert_close((kernels.manhattan_distance)(&a, &b), expected, expected);
+                    assert_eq!(
+                        (kernels.sign_bit_distance)(&a, &b),
+                        scalar::sign_bit_distance(&a, &b),
+                        "{:?}",
+                        kernels.instruction_set
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_jaccard_kernels_match_scalar((a, b) in float_vectors(0.0..100.0)) {
+            for kernels in all_float_kernels() {
+                // Safety: for_instruction_set only returns kernels the CPU supports
+                unsafe {
+                    assert_close(
+                        (kernels.jaccard_distance)(&a, &b),
+                        scalar::jaccard_distance(&a, &b),
+                        0.0,
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_int8_kernels_match_scalar((a, b) in int8_vectors()) {
+            for kernels in all_int8_kernels() {
+                // Safety: for_instruction_set only returns kernels the CPU supports
+                unsafe {
+                    assert_eq!(
+                        (kernels.dot_product)(&a, &b),
+                        scalar::int8_dot_product(&a, &b),
+                        "{:?}",
+                        kernels.instruction_set
+                    );
+                    assert_eq!(
+                        (kernels.euclidean_distance)(&a, &b),
+                        scalar::int8_euclidean_distance(&a, &b),
+                        "{:?}",
+                        kernels.instruction_set
+                    );
+                    assert_eq!(
+                        (kernels.manhattan_distance)(&a, &b),
+                        scalar::int8_manhattan_distance(&a, &b),
+                        "{:?}",
+                        kernels.instruction_set
+                    );
+                }
+            }
+        }
+    }
+}
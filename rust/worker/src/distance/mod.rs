@@ -1,9 +1,16 @@
+pub mod binary;
+#[cfg(target_arch = "x86_64")]
 pub mod distance_avx;
+#[cfg(target_arch = "x86_64")]
+pub mod distance_avx512;
+#[cfg(target_arch = "aarch64")]
 pub mod distance_neon;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 pub mod distance_sse;
+pub mod kernels;
+pub mod scalar;
 pub mod types;
 
-pub use distance_avx::*;
-pub use distance_neon::*;
-pub use distance_sse::*;
+pub use binary::*;
+pub use kernels::*;
 pub use types::*;
@@ -0,0 +1,77 @@
+// The distance kernels without SIMD, for CPUs without any of the instruction sets of
+// the other kernels, and as the reference the other kernels are tested against.
+
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    // For cosine we just assume the vectors have been normalized, since that
+    // is what our indices expect.
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        sum += a[i] * b[i];
+    }
+    1.0_f32 - sum
+}
+
+pub fn inner_product(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        sum += a[i] * b[i];
+    }
+    1.0_f32 - sum
+}
+
+pub fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        sum += (a[i] - b[i]).powi(2);
+    }
+    sum
+}
+
+pub fn manhattan_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        sum += (a[i] - b[i]).abs();
+    }
+    sum
+}
+
+pub fn jaccard_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut min_sum = 0.0;
+    let mut max_sum = 0.0;
+    for i in 0..a.len() {
+        min_sum += a[i].min(b[i]);
+        max_sum += a[i].max(b[i]);
+    }
+    if max_sum == 0.0 {
+        return 0.0;
+    }
+    1.0_f32 - min_sum / max_sum
+}
+
+pub fn sign_bit_distance(a: &[f32], b: &[f32]) -> u32 {
+    crate::distance::binary::sign_hamming_distance(a, b)
+}
+
+pub fn int8_dot_product(a: &[i8], b: &[i8]) -> i32 {
+    let mut sum = 0;
+    for i in 0..a.len() {
+        sum += a[i] as i32 * b[i] as i32;
+    }
+    sum
+}
+
+pub fn int8_euclidean_distance(a: &[i8], b: &[i8]) -> i32 {
+    let mut sum = 0;
+    for i in 0..a.len() {
+        sum += (a[i] as i32 - b[i] as i32).pow(2);
+    }
+    sum
+}
+
+pub fn int8_manhattan_distance(a: &[i8], b: &[i8]) -> i32 {
+    let mut sum = 0;
+    for i in 0..a.len() {
+        sum += (a[i] as i32 - b[i] as i32).abs();
+    }
+    sum
+}
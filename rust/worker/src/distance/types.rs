@@ -200,6 +200,7 @@ copyright Qdrant, licensed under the Apache 2.0 license.
    limitations under the License.
 */
 
+use crate::distance::kernels::{float_kernels, int8_kernels};
 use crate::errors::{ChromaError, ErrorCodes};
 use thiserror::Error;
 
@@ -210,6 +211,13 @@ use thiserror::Error;
 /// - `Euclidean` - The Euclidean or l2 norm.
 /// - `Cosine` - The cosine distance. Specifically, 1 - cosine.
 /// - `InnerProduct` - The inner product. Specifically, 1 - inner product.
+/// - `Hamming` - The number of dimensions whose signs differ, the hamming distance of
+///   the binary quantized vectors, see `binary_quantize`.
+/// - `Manhattan` - The Manhattan or l1 norm.
+/// - `Jaccard` - The weighted Jaccard distance, 1 - sum of minimums / sum of maximums.
+///   For vectors of 0s and 1s, such as fingerprints, this is the Jaccard distance of
+///   the sets of their 1s. Values are expected to be non-negative, and two vectors of
+///   0s are at distance 0.
 /// # Notes
 /// See https://docs.trychroma.com/guides#changing-the-distance-function
 #[derive(Clone, Debug, PartialEq)]
@@ -217,114 +225,64 @@ pub enum DistanceFunction {
     Euclidean,
     Cosine,
     InnerProduct,
+    Hamming,
+    Manhattan,
+    Jaccard,
 }
 
 impl DistanceFunction {
     // TOOD: Should we error if mismatched dimensions?
     pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
-        // TODO: Figure out why the compiler is not auto vectorizing these
-        // by default.
-        match self {
-            DistanceFunction::Euclidean => {
-                #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-                {
-                    if std::arch::is_aarch64_feature_detected!("neon") {
-                        return unsafe { crate::distance::distance_neon::euclidean_distance(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    any(target_arch = "x86_64", target_arch = "x86"),
-                    target_feature = "sse"
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("sse") {
-                        return unsafe { crate::distance::distance_sse::euclidean_distance(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    target_arch = "x86_64",
-                    all(target_feature = "avx", target_feature = "fma")
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("avx")
-                        && std::arch::is_x86_feature_detected!("fma")
-                    {
-                        return unsafe { crate::distance::distance_avx::euclidean_distance(a, b) };
-                    }
-                }
-                let mut sum = 0.0;
-                for i in 0..a.len() {
-                    sum += (a[i] - b[i]).powi(2);
-                }
-                sum
+        let kernels = float_kernels();
+        // Safety: the kernels are of an instruction set the CPU supports
+        unsafe {
+            match self {
+                DistanceFunction::Euclidean => (kernels.euclidean_distance)(a, b),
+                DistanceFunction::Cosine => (kernels.cosine_distance)(a, b),
+                DistanceFunction::InnerProduct => (kernels.inner_product)(a, b),
+                DistanceFunction::Hamming => (kernels.sign_bit_distance)(a, b) as f32,
+                DistanceFunction::Manhattan => (kernels.manhattan_distance)(a, b),
+                DistanceFunction::Jaccard => (kernels.jaccard_distance)(a, b),
             }
-            DistanceFunction::Cosine => {
-                #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-                {
-                    if std::arch::is_aarch64_feature_detected!("neon") {
-                        return unsafe { crate::distance::distance_neon::cosine_distance(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    any(target_arch = "x86_64", target_arch = "x86"),
-                    target_feature = "sse"
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("sse") {
-                        return unsafe { crate::distance::distance_sse::cosine_distance(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    target_arch = "x86_64",
-                    all(target_feature = "avx", target_feature = "fma")
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("avx")
-                        && std::arch::is_x86_feature_detected!("fma")
-                    {
-                        return unsafe { crate::distance::distance_avx::cosine_distance(a, b) };
-                    }
-                }
-                // For cosine we just assume the vectors have been normalized, since that
-                // is what our indices expect.
-                let mut sum = 0.0;
+        }
+    }
+
+    /// The distance between two int8 quantized vectors, see `ScalarQuantizer`.
+    /// # Description
+    /// Euclidean is the squared l2 distance between the quantized values, cosine and
+    /// inner product are the negated inner product of the quantized values, since
+    /// quantized vectors are not normalized. These are only comparable to distances
+    /// between vectors quantized by the same quantizer, not to the distances of
+    /// `distance`. Hamming is the number of values whose signs differ, Manhattan is the
+    /// l1 distance between the quantized values, and Jaccard is the weighted Jaccard
+    /// distance of the quantized values shifted to [0, 254].
+    /// # Notes
+    /// Values are expected in [-127, 127], and sums are accumulated in i32.
+    pub fn distance_int8(&self, a: &[i8], b: &[i8]) -> f32 {
+        let kernels = int8_kernels();
+        match self {
+            // Safety: the kernels are of an instruction set the CPU supports
+            DistanceFunction::Euclidean => unsafe { (kernels.euclidean_distance)(a, b) as f32 },
+            DistanceFunction::Cosine | DistanceFunction::InnerProduct => unsafe {
+                -((kernels.dot_product)(a, b) as f32)
+            },
+            DistanceFunction::Manhattan => unsafe { (kernels.manhattan_distance)(a, b) as f32 },
+            DistanceFunction::Hamming => a
+                .iter()
+                .zip(b)
+                .filter(|(a, b)| a.is_negative() != b.is_negative())
+                .count() as f32,
+            DistanceFunction::Jaccard => {
+                let mut min_sum = 0;
+                let mut max_sum = 0;
                 for i in 0..a.len() {
-                    sum += a[i] * b[i];
-                }
-                1.0_f32 - sum
-            }
-            DistanceFunction::InnerProduct => {
-                #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-                {
-                    if std::arch::is_aarch64_feature_detected!("neon") {
-                        return unsafe { crate::distance::distance_neon::inner_product(a, b) };
-                    }
+                    min_sum += a[i].min(b[i]) as i32 + 127;
+                    max_sum += a[i].max(b[i]) as i32 + 127;
                 }
-                #[cfg(all(
-                    any(target_arch = "x86_64", target_arch = "x86"),
-                    target_feature = "sse"
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("sse") {
-                        return unsafe { crate::distance::distance_sse::inner_product(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    target_arch = "x86_64",
-                    all(target_feature = "avx", target_feature = "fma")
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("avx")
-                        && std::arch::is_x86_feature_detected!("fma")
-                    {
-                        return unsafe { crate::distance::distance_avx::inner_product(a, b) };
-                    }
-                }
-                let mut sum = 0.0;
-                for i in 0..a.len() {
-                    sum += a[i] * b[i];
+                if max_sum == 0 {
+                    return 0.0;
                 }
-                1.0_f32 - sum
+                1.0_f32 - min_sum as f32 / max_sum as f32
             }
         }
     }
@@ -352,6 +310,9 @@ impl TryFrom<&str> for DistanceFunction {
             "l2" => Ok(DistanceFunction::Euclidean),
             "cosine" => Ok(DistanceFunction::Cosine),
             "ip" => Ok(DistanceFunction::InnerProduct),
+            "hamming" => Ok(DistanceFunction::Hamming),
+            "l1" => Ok(DistanceFunction::Manhattan),
+            "jaccard" => Ok(DistanceFunction::Jaccard),
             _ => Err(DistanceFunctionError::InvalidDistanceFunction(
                 value.to_string(),
             )),
@@ -365,6 +326,9 @@ impl Into<String> for DistanceFunction {
             DistanceFunction::Euclidean => "l2".to_string(),
             DistanceFunction::Cosine => "cosine".to_string(),
             DistanceFunction::InnerProduct => "ip".to_string(),
+            DistanceFunction::Hamming => "hamming".to_string(),
+            DistanceFunction::Manhattan => "l1".to_string(),
+            DistanceFunction::Jaccard => "jaccard".to_string(),
         }
     }
 }
@@ -381,6 +345,13 @@ mod tests {
         assert_eq!(distance_function, DistanceFunction::Cosine);
         let distance_function: DistanceFunction = "ip".try_into().unwrap();
         assert_eq!(distance_function, DistanceFunction::InnerProduct);
+        let distance_function: DistanceFunction = "hamming".try_into().unwrap();
+        assert_eq!(distance_function, DistanceFunction::Hamming);
+        let distance_function: DistanceFunction = "l1".try_into().unwrap();
+        assert_eq!(distance_function, DistanceFunction::Manhattan);
+        let distance_function: DistanceFunction = "jaccard".try_into().unwrap();
+        assert_eq!(distance_function, DistanceFunction::Jaccard);
+        assert!(DistanceFunction::try_from("l3").is_err());
     }
 
     #[test]
@@ -391,6 +362,12 @@ mod tests {
         assert_eq!(distance_function, "cosine");
         let distance_function: String = DistanceFunction::InnerProduct.into();
         assert_eq!(distance_function, "ip");
+        let distance_function: String = DistanceFunction::Hamming.into();
+        assert_eq!(distance_function, "hamming");
+        let distance_function: String = DistanceFunction::Manhattan.into();
+        assert_eq!(distance_function, "l1");
+        let distance_function: String = DistanceFunction::Jaccard.into();
+        assert_eq!(distance_function, "jaccard");
     }
 
     #[test]
@@ -418,4 +395,105 @@ mod tests {
             inner_product_sim
         );
     }
+
+    #[test]
+    fn test_distance_function_hamming() {
+        let a = vec![1.0, -2.0, 3.0, -4.0];
+        let b = vec![-1.0, -2.0, 0.5, 4.0];
+        assert_eq!(DistanceFunction::Hamming.distance(&a, &b), 2.0);
+        assert_eq!(
+            DistanceFunction::Hamming.distance_int8(&[1, -2, 3, -4], &[-1, -2, 0, 4]),
+            2.0
+        );
+    }
+
+    #[test]
+    fn test_distance_function_hamming_simd() {
+        // Long enough to use the SIMD loops and their remainders
+        let a: Vec<f32> = (0..77).map(|i| ((i * 37) % 11) as f32 - 5.0).collect();
+        let b: Vec<f32> = (0..77).map(|i| ((i * 91 + 13) % 7) as f32 - 3.0).collect();
+        let expected = a
+            .iter()
+            .zip(b.iter())
+            .filter(|(a, b)| a.is_sign_negative() != b.is_sign_negative())
+            .count() as f32;
+        assert_eq!(DistanceFunction::Hamming.distance(&a, &b), expected);
+    }
+
+    #[test]
+    fn test_distance_function_manhattan() {
+        let a = vec![1.0, -2.0, 3.0];
+        let b = vec![4.0, 5.0, -6.0];
+        assert_eq!(DistanceFunction::Manhattan.distance(&a, &b), 19.0);
+
+        // Long enough to use the SIMD loops and their remainders
+        let a: Vec<f32> = (0..77).map(|i| ((i * 37) % 11) as f32 - 5.0).collect();
+        let b: Vec<f32> = (0..77).map(|i| ((i * 91 + 13) % 7) as f32 - 3.0).collect();
+        let l1: f32 = a.iter().zip(b.iter()).map(|(a, b)| (a - b).abs()).sum();
+        assert_eq!(DistanceFunction::Manhattan.distance(&a, &b), l1);
+        assert_eq!(DistanceFunction::Manhattan.distance(&a, &a), 0.0);
+    }
+
+    #[test]
+    fn test_distance_function_jaccard() {
+        // Fingerprints sharing 2 of 4 set bits
+        let a = vec![1.0, 1.0, 0.0, 1.0, 0.0];
+        let b = vec![1.0, 0.0, 1.0, 1.0, 0.0];
+        assert_eq!(DistanceFunction::Jaccard.distance(&a, &b), 0.5);
+        assert_eq!(DistanceFunction::Jaccard.distance(&a, &a), 0.0);
+        assert_eq!(
+            DistanceFunction::Jaccard.distance(&[0.0; 5], &[0.0; 5]),
+            0.0
+        );
+
+        // Long enough to use the SIMD loops and their remainders
+        let a: Vec<f32> = (0..77).map(|i| ((i * 37) % 11) as f32).collect();
+        let b: Vec<f32> = (0..77).map(|i| ((i * 91 + 13) % 7) as f32).collect();
+        let min_sum: f32 = a.iter().zip(b.iter()).map(|(a, b)| a.min(*b)).sum();
+        let max_sum: f32 = a.iter().zip(b.iter()).map(|(a, b)| a.max(*b)).sum();
+        let distance = DistanceFunction::Jaccard.distance(&a, &b);
+        assert!((distance - (1.0 - min_sum / max_sum)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_distance_function_int8() {
+        // Long enough to use the SIMD loops and their remainders, with the extreme values
+        let a: Vec<i8> = (0..77).map(|i| ((i * 37) % 255 - 127) as i8).collect();
+        let b: Vec<i8> = (0..77).map(|i| ((i * 91 + 13) % 255 - 127) as i8).collect();
+        let l2_sqr: i32 = a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+            .sum();
+        let inner_product: i32 = a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| *a as i32 * *b as i32)
+            .sum();
+
+        assert_eq!(
+            DistanceFunction::Euclidean.distance_int8(&a, &b),
+            l2_sqr as f32
+        );
+        assert_eq!(
+            DistanceFunction::InnerProduct.distance_int8(&a, &b),
+            -inner_product as f32
+        );
+        assert_eq!(
+            DistanceFunction::Cosine.distance_int8(&a, &b),
+            -inner_product as f32
+        );
+
+        let l1: i32 = a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .sum();
+        assert_eq!(DistanceFunction::Manhattan.distance_int8(&a, &b), l1 as f32);
+        assert_eq!(DistanceFunction::Jaccard.distance_int8(&a, &a), 0.0);
+        assert_eq!(
+            DistanceFunction::Jaccard.distance_int8(&[127, 127], &[127, -127]),
+            0.5
+        );
+    }
 }
@@ -120,6 +120,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -131,6 +133,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
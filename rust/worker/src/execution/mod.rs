@@ -4,4 +4,5 @@ pub(crate) mod dispatcher;
 pub(crate) mod operator;
 mod operators;
 pub(crate) mod orchestration;
+pub(crate) mod recall_eval;
 mod worker_thread;
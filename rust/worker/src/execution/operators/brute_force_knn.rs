@@ -175,6 +175,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![0.0, 0.0, 0.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -186,6 +188,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: Some(vec![0.0, 1.0, 1.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -197,6 +201,8 @@ mod tests {
                     id: "embedding_id_3".to_string(),
                     embedding: Some(vec![7.0, 8.0, 9.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -236,6 +242,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![0.0, 1.0, 0.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -247,6 +255,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: Some(data_1.clone()),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -258,6 +268,8 @@ mod tests {
                     id: "embedding_id_3".to_string(),
                     embedding: Some(data_2.clone()),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -283,6 +295,55 @@ mod tests {
         assert_eq!(output.data.get_visibility(2), Some(false));
     }
 
+    #[tokio::test]
+    async fn test_brute_force_knn_manhattan_and_jaccard() {
+        let operator = BruteForceKnnOperator {};
+        let embeddings = vec![
+            vec![1.0, 1.0, 0.0, 0.0],
+            vec![1.0, 0.0, 1.0, 1.0],
+            vec![0.0, 0.0, 1.0, 0.0],
+        ];
+        let data: Vec<LogRecord> = embeddings
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i + 1),
+                    embedding: Some(embedding),
+                    encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect();
+        let data_chunk = Chunk::new(data.into());
+
+        // The query shares 1 of 3 set bits with the first, and 2 of 3 with the second
+        let input = BruteForceKnnOperatorInput {
+            data: data_chunk.clone(),
+            query: vec![1.0, 0.0, 1.0, 0.0],
+            k: 2,
+            distance_metric: DistanceFunction::Jaccard,
+        };
+        let output = operator.run(&input).await.unwrap();
+        assert_eq!(output.indices, vec![1, 2]);
+        assert_eq!(output.distances, vec![1.0 - 2.0 / 3.0, 0.5]);
+
+        let input = BruteForceKnnOperatorInput {
+            data: data_chunk,
+            query: vec![1.0, 0.0, 1.0, 1.0],
+            k: 3,
+            distance_metric: DistanceFunction::Manhattan,
+        };
+        let output = operator.run(&input).await.unwrap();
+        assert_eq!(output.indices, vec![1, 2, 0]);
+        assert_eq!(output.distances, vec![0.0, 2.0, 3.0]);
+    }
+
     #[tokio::test]
     async fn test_data_less_than_k() {
         // If we have less data than k, we should return all the data, sorted by distance.
@@ -293,6 +354,8 @@ mod tests {
                 id: "embedding_id_1".to_string(),
                 embedding: Some(vec![0.0, 0.0, 0.0]),
                 encoding: None,
+                sparse_embedding: None,
+                multi_embedding: None,
                 metadata: None,
                 document: None,
                 operation: Operation::Add,
@@ -323,6 +386,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![0.0, 0.0, 0.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -334,6 +399,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -345,6 +412,8 @@ mod tests {
                     id: "embedding_id_3".to_string(),
                     embedding: Some(vec![7.0, 8.0, 9.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
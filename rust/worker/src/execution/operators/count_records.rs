@@ -100,7 +100,8 @@ impl Operator<CountRecordsInput, CountRecordsOutput> for CountRecordsOperator {
                     RecordSegmentReaderCreationError::BlockfileOpenError(_) => {
                         return Err(CountRecordsError::RecordSegmentCreateError(*e));
                     }
-                    RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
+                    RecordSegmentReaderCreationError::InvalidNumberOfFiles
+                    | RecordSegmentReaderCreationError::InvalidConfig(_) => {
                         return Err(CountRecordsError::RecordSegmentCreateError(*e));
                     }
                 }
@@ -226,6 +227,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: Some(vec![1.0, 2.0, 3.0]),
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -237,6 +240,8 @@ mod tests {
                         id: "embedding_id_2".to_string(),
                         embedding: Some(vec![4.0, 5.0, 6.0]),
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -248,6 +253,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Delete,
@@ -255,7 +262,10 @@ mod tests {
                 },
             ];
             let data: Chunk<LogRecord> = Chunk::new(data.into());
-            segment_writer.materialize(&data).await;
+            segment_writer
+                .materialize(&data)
+                .await
+                .expect("Materialize failed");
             let flusher = segment_writer
                 .commit()
                 .expect("Commit for segment writer failed");
@@ -268,6 +278,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![1.0, 2.0, 3.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -279,6 +291,8 @@ mod tests {
                     id: "embedding_id_4".to_string(),
                     embedding: Some(vec![4.0, 5.0, 6.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -290,6 +304,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Update,
@@ -332,6 +348,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: Some(vec![1.0, 2.0, 3.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -343,6 +361,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: Some(vec![4.0, 5.0, 6.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -354,6 +374,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Delete,
@@ -365,6 +387,8 @@ mod tests {
                     id: "embedding_id_3".to_string(),
                     embedding: Some(vec![1.0, 2.0, 3.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -376,6 +400,8 @@ mod tests {
                     id: "embedding_id_3".to_string(),
                     embedding: Some(vec![4.0, 5.0, 6.0]),
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Upsert,
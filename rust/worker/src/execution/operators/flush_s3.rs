@@ -6,8 +6,9 @@ use crate::types::SegmentFlushInfo;
 use crate::{
     execution::operator::Operator,
     segment::{
-        distributed_hnsw_segment::DistributedHNSWSegmentWriter,
-        record_segment::RecordSegmentWriter, SegmentWriter,
+        metadata_segment::MetadataSegmentWriter, record_segment::RecordSegmentWriter,
+        sparse_vector_segment::SparseVectorSegmentWriter, vector_segment::VectorSegmentWriter,
+        SegmentWriter,
     },
 };
 use async_trait::async_trait;
@@ -24,17 +25,23 @@ impl FlushS3Operator {
 #[derive(Debug)]
 pub struct FlushS3Input {
     record_segment_writer: RecordSegmentWriter,
-    hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+    vector_segment_writer: VectorSegmentWriter,
+    metadata_segment_writer: MetadataSegmentWriter,
+    sparse_vector_segment_writer: Option<SparseVectorSegmentWriter>,
 }
 
 impl FlushS3Input {
     pub fn new(
         record_segment_writer: RecordSegmentWriter,
-        hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+        vector_segment_writer: VectorSegmentWriter,
+        metadata_segment_writer: MetadataSegmentWriter,
+        sparse_vector_segment_writer: Option<SparseVectorSegmentWriter>,
     ) -> Self {
         Self {
             record_segment_writer,
-            hnsw_segment_writer,
+            vector_segment_writer,
+            metadata_segment_writer,
+            sparse_vector_segment_writer,
         }
     }
 }
@@ -72,14 +79,23 @@ impl Operator<FlushS3Input, FlushS3Output> for FlushS3Operator {
             }
         };
 
-        let hnsw_segment_flusher = input.hnsw_segment_writer.clone().commit();
-        let hnsw_segment_flush_info = match hnsw_segment_flusher {
+        let mut vector_segment_writer = input.vector_segment_writer.clone();
+        match vector_segment_writer.write_to_blockfiles().await {
+            Ok(_) => {}
+            Err(e) => {
+                // TODO: use logging
+                println!("Error Writing Vector Segment: {:?}", e);
+                return Err(e);
+            }
+        }
+        let vector_segment_flusher = vector_segment_writer.commit();
+        let vector_segment_flush_info = match vector_segment_flusher {
             Ok(flusher) => {
-                let segment_id = input.hnsw_segment_writer.id;
+                let segment_id = input.vector_segment_writer.id();
                 let res = flusher.flush().await;
                 match res {
                     Ok(res) => {
-                        println!("HNSW Segment Flushed");
+                        println!("Vector Segment Flushed");
                         SegmentFlushInfo {
                             segment_id,
                             file_paths: res,
@@ -87,22 +103,101 @@ impl Operator<FlushS3Input, FlushS3Output> for FlushS3Operator {
                     }
                     Err(e) => {
                         // TODO: use logging
-                        println!("Error Flushing HNSW Segment: {:?}", e);
+                        println!("Error Flushing Vector Segment: {:?}", e);
                         return Err(e);
                     }
                 }
             }
             Err(e) => {
                 // TODO: use logging;
-                println!("Error Commiting HNSW Segment: {:?}", e);
+                println!("Error Commiting Vector Segment: {:?}", e);
+                return Err(e);
+            }
+        };
+
+        // The metadata segment buffers its writes until they are written to
+        // the blockfiles, deletes included.
+        let mut metadata_segment_writer = input.metadata_segment_writer.clone();
+        let segment_id = metadata_segment_writer.id;
+        match metadata_segment_writer.write_to_blockfiles().await {
+            Ok(_) => {}
+            Err(e) => {
+                // TODO: use logging
+                println!("Error Writing Metadata Segment: {:?}", e);
+                return Err(Box::new(e));
+            }
+        }
+        let metadata_segment_flusher = metadata_segment_writer.commit();
+        let metadata_segment_flush_info = match metadata_segment_flusher {
+            Ok(flusher) => {
+                let res = flusher.flush().await;
+                match res {
+                    Ok(res) => {
+                        println!("Metadata Segment Flushed");
+                        SegmentFlushInfo {
+                            segment_id,
+                            file_paths: res,
+                        }
+                    }
+                    Err(e) => {
+                        // TODO: use logging
+                        println!("Error Flushing Metadata Segment: {:?}", e);
+                        return Err(e);
+                    }
+                }
+            }
+            Err(e) => {
+                // TODO: use logging
+                println!("Error Commiting Metadata Segment: {:?}", e);
                 return Err(e);
             }
         };
 
+        let mut segment_flush_info = vec![
+            record_segment_flush_info,
+            vector_segment_flush_info,
+            metadata_segment_flush_info,
+        ];
+
+        if let Some(sparse_vector_segment_writer) = &input.sparse_vector_segment_writer {
+            let mut sparse_vector_segment_writer = sparse_vector_segment_writer.clone();
+            let segment_id = sparse_vector_segment_writer.id;
+            match sparse_vector_segment_writer.write_to_blockfiles().await {
+                Ok(_) => {}
+                Err(e) => {
+                    // TODO: use logging
+                    println!("Error Writing Sparse Vector Segment: {:?}", e);
+                    return Err(e);
+                }
+            }
+            let sparse_vector_segment_flusher = match sparse_vector_segment_writer.commit() {
+                Ok(flusher) => flusher,
+                Err(e) => {
+                    // TODO: use logging
+                    println!("Error Commiting Sparse Vector Segment: {:?}", e);
+                    return Err(e);
+                }
+            };
+            match sparse_vector_segment_flusher.flush().await {
+                Ok(res) => {
+                    println!("Sparse Vector Segment Flushed");
+                    segment_flush_info.push(SegmentFlushInfo {
+                        segment_id,
+                        file_paths: res,
+                    });
+                }
+                Err(e) => {
+                    // TODO: use logging
+                    println!("Error Flushing Sparse Vector Segment: {:?}", e);
+                    return Err(e);
+                }
+            }
+        }
+
         // TODO: use logging
         println!("Flush to S3 complete");
         Ok(FlushS3Output {
-            segment_flush_info: Arc::new([record_segment_flush_info, hnsw_segment_flush_info]),
+            segment_flush_info: segment_flush_info.into(),
         })
     }
 }
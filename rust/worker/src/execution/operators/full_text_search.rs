@@ -0,0 +1,421 @@
+use crate::{
+    blockstore::{provider::BlockfileProvider, BlockfileError},
+    errors::{ChromaError, ErrorCodes},
+    execution::{data::data_chunk::Chunk, operator::Operator},
+    index::fulltext::query::{DocumentQuery, DocumentQueryParseError},
+    segment::{
+        metadata_segment::{full_text_normalization, MetadataSegmentError, MetadataSegmentReader},
+        record_segment::{RecordSegmentReader, RecordSegmentReaderCreationError},
+    },
+    types::{LogRecord, Operation, Segment},
+};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+// BM25 term frequency saturation.
+const K1: f32 = 1.2;
+
+/// The full text search operator ranks the documents matching a document
+/// query, in the syntax of `$matches`, by relevance.
+/// # Inputs
+/// - The log records that have not been compacted into the segments yet.
+/// - The metadata segment, the record segment and a blockfile provider to read them.
+/// - The document query and the number of results to return.
+/// # Outputs
+/// - The user ids of the best matches and their scores, best first.
+/// # Scoring
+/// Documents are scored with BM25 over the phrases of the query. Document
+/// lengths are not indexed, so there is no length normalization. A record in
+/// the log shadows the version of it in the segment.
+#[derive(Debug)]
+pub(crate) struct FullTextSearchOperator {}
+
+impl FullTextSearchOperator {
+    pub(crate) fn new() -> Box<Self> {
+        Box::new(FullTextSearchOperator {})
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FullTextSearchInput {
+    log_records: Chunk<LogRecord>,
+    metadata_segment_definition: Segment,
+    record_segment_definition: Segment,
+    blockfile_provider: BlockfileProvider,
+    query: String,
+    k: usize,
+}
+
+impl FullTextSearchInput {
+    pub(crate) fn new(
+        log_records: Chunk<LogRecord>,
+        metadata_segment_definition: Segment,
+        record_segment_definition: Segment,
+        blockfile_provider: BlockfileProvider,
+        query: String,
+        k: usize,
+    ) -> Self {
+        Self {
+            log_records,
+            metadata_segment_definition,
+            record_segment_definition,
+            blockfile_provider,
+            query,
+            k,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FullTextSearchOutput {
+    pub(crate) user_ids: Vec<String>,
+    pub(crate) scores: Vec<f32>,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum FullTextSearchError {
+    #[error("Invalid document query")]
+    DocumentQueryParseError(#[from] DocumentQueryParseError),
+    #[error("Error reading metadata segment")]
+    MetadataSegmentError(#[from] MetadataSegmentError),
+    #[error("Error creating record segment reader")]
+    RecordSegmentCreationError(#[from] RecordSegmentReaderCreationError),
+    #[error("Error searching full text index")]
+    FullTextIndexError(#[from] Box<dyn ChromaError>),
+}
+
+impl ChromaError for FullTextSearchError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            FullTextSearchError::DocumentQueryParseError(e) => e.code(),
+            FullTextSearchError::MetadataSegmentError(e) => e.code(),
+            FullTextSearchError::RecordSegmentCreationError(e) => e.code(),
+            FullTextSearchError::FullTextIndexError(e) => e.code(),
+        }
+    }
+}
+
+// The BM25 score of a document, given how often each phrase occurs in it
+// and in how many of the documents each phrase occurs.
+fn bm25(term_frequencies: &[usize], document_frequencies: &[usize], num_documents: usize) -> f32 {
+    let mut score = 0.0;
+    for (tf, df) in term_frequencies.iter().zip(document_frequencies.iter()) {
+        if *tf == 0 {
+            continue;
+        }
+        let tf = *tf as f32;
+        let df = *df as f32;
+        let idf = (1.0 + (num_documents as f32 - df + 0.5) / (df + 0.5)).ln();
+        score += idf * tf * (K1 + 1.0) / (tf + K1);
+    }
+    score
+}
+
+#[async_trait]
+impl Operator<FullTextSearchInput, FullTextSearchOutput> for FullTextSearchOperator {
+    type Error = FullTextSearchError;
+
+    async fn run(
+        &self,
+        input: &FullTextSearchInput,
+    ) -> Result<FullTextSearchOutput, FullTextSearchError> {
+        let query = DocumentQuery::parse(&input.query)?;
+        let normalization = full_text_normalization(&input.metadata_segment_definition)?;
+        let phrases: Vec<String> = query
+            .positive_phrases()
+            .iter()
+            .map(|phrase| normalization.normalize(phrase).to_string())
+            .filter(|phrase| !phrase.is_empty())
+            .collect();
+
+        // user id -> occurrences of each phrase
+        let mut term_frequencies: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut document_frequencies = vec![0; phrases.len()];
+        let mut num_documents = 0;
+
+        // The latest document of each record in the log, None if the record is
+        // deleted. A deletion or a new document replaces the indexed document, an
+        // update that leaves the document alone does not.
+        let mut log_documents: HashMap<&str, Option<&str>> = HashMap::new();
+        for (log_record, _) in input.log_records.iter() {
+            match (&log_record.record.operation, &log_record.record.document) {
+                (Operation::Delete, _) => {
+                    log_documents.insert(log_record.record.id.as_str(), None);
+                }
+                (_, Some(document)) => {
+                    log_documents.insert(log_record.record.id.as_str(), Some(document.as_str()));
+                }
+                (_, None) => {}
+            }
+        }
+
+        // Records in the log are not indexed yet, so match them directly.
+        for (user_id, document) in log_documents.iter() {
+            let document = match document {
+                Some(document) => document,
+                None => continue,
+            };
+            num_documents += 1;
+            if !query.matches_document(document, &normalization) {
+                continue;
+            }
+            let normalized_document = normalization.normalize(document);
+            let mut frequencies = Vec::with_capacity(phrases.len());
+            for (i, phrase) in phrases.iter().enumerate() {
+                let frequency = normalized_document.matches(phrase.as_str()).count();
+                if frequency > 0 {
+                    document_frequencies[i] += 1;
+                }
+                frequencies.push(frequency);
+            }
+            term_frequencies.insert(user_id.to_string(), frequencies);
+        }
+
+        // An uninitialized segment has no files and nothing to search.
+        if !input.metadata_segment_definition.file_path.is_empty() {
+            let metadata_segment_reader = MetadataSegmentReader::from_segment(
+                &input.metadata_segment_definition,
+                &input.blockfile_provider,
+            )
+            .await?;
+            let record_segment_reader = RecordSegmentReader::from_segment(
+                &input.record_segment_definition,
+                &input.blockfile_provider,
+            )
+            .await
+            .map_err(|e| *e)?;
+            // The segment versions of the records in the log are not counted, nor matched
+            let mut shadowed_offset_ids = HashSet::new();
+            for user_id in log_documents.keys() {
+                if record_segment_reader
+                    .data_exists_for_user_id(user_id)
+                    .await?
+                {
+                    shadowed_offset_ids.insert(
+                        record_segment_reader
+                            .get_offset_id_for_user_id(user_id)
+                            .await? as i32,
+                    );
+                }
+            }
+            num_documents += record_segment_reader.count().await? - shadowed_offset_ids.len();
+
+            let offset_ids = metadata_segment_reader
+                .full_text_index_reader
+                .search_query(&query)
+                .await?;
+            let mut phrase_occurrences = Vec::with_capacity(phrases.len());
+            for (i, phrase) in phrases.iter().enumerate() {
+                // Phrases with tokens absent from the index occur nowhere.
+                let occurrences = match metadata_segment_reader
+                    .full_text_index_reader
+                    .search_with_offsets(phrase)
+                    .await
+                {
+                    Ok(occurrences) => occurrences,
+                    Err(e) if BlockfileError::is_not_found(&*e) => HashMap::new(),
+                    Err(e) => return Err(FullTextSearchError::FullTextIndexError(e)),
+                };
+                document_frequencies[i] += occurrences
+                    .keys()
+                    .filter(|offset_id| !shadowed_offset_ids.contains(*offset_id))
+                    .count();
+                phrase_occurrences.push(occurrences);
+            }
+            for offset_id in offset_ids {
+                if shadowed_offset_ids.contains(&offset_id) {
+                    continue;
+                }
+                let user_id = record_segment_reader
+                    .get_user_id_for_offset_id(offset_id as u32)
+                    .await?;
+                let frequencies = phrase_occurrences
+                    .iter()
+                    .map(|occurrences| match occurrences.get(&offset_id) {
+                        Some(ranges) => ranges.len(),
+                        None => 0,
+                    })
+                    .collect();
+                term_frequencies.insert(user_id.to_string(), frequencies);
+            }
+        }
+
+        let mut results: Vec<(String, f32)> = term_frequencies
+            .into_iter()
+            .map(|(user_id, frequencies)| {
+                let score = bm25(&frequencies, &document_frequencies, num_documents);
+                (user_id, score)
+            })
+            .collect();
+        // Best first, ties broken by user id so that results are stable.
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        results.truncate(input.k);
+
+        let (user_ids, scores) = results.into_iter().unzip();
+        Ok(FullTextSearchOutput { user_ids, scores })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::metadata_segment::MetadataSegmentWriter;
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::{LogMaterializer, SegmentFlusher, SegmentWriter};
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{OperationRecord, SegmentScope, SegmentType};
+
+    fn log_record(id: &str, document: &str) -> LogRecord {
+        LogRecord {
+            log_offset: 0,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: None,
+                encoding: None,
+                sparse_embedding: None,
+                multi_embedding: None,
+                metadata: None,
+                document: Some(document.to_string()),
+                operation: Operation::Add,
+            },
+        }
+    }
+
+    #[test]
+    fn test_bm25() {
+        // More occurrences score higher, with diminishing returns.
+        let one = bm25(&[1], &[2], 10);
+        let two = bm25(&[2], &[2], 10);
+        let four = bm25(&[4], &[2], 10);
+        assert!(one < two && two < four);
+        assert!(two - one > four - two);
+        // Rarer phrases weigh more.
+        assert!(bm25(&[1], &[1], 10) > bm25(&[1], &[5], 10));
+        assert_eq!(bm25(&[0, 0], &[1, 1], 10), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_search_log() {
+        let log_records = vec![
+            log_record("a", "apple pie"),
+            log_record("b", "apple apple apple"),
+            log_record("c", "banana split"),
+            log_record("d", "apple banana"),
+        ];
+        let input = FullTextSearchInput::new(
+            Chunk::new(log_records.into()),
+            Segment {
+                id: uuid::Uuid::new_v4(),
+                r#type: crate::types::SegmentType::BlockfileMetadata,
+                scope: crate::types::SegmentScope::METADATA,
+                collection: None,
+                metadata: None,
+                file_path: HashMap::new(),
+            },
+            Segment {
+                id: uuid::Uuid::new_v4(),
+                r#type: crate::types::SegmentType::Record,
+                scope: crate::types::SegmentScope::RECORD,
+                collection: None,
+                metadata: None,
+                file_path: HashMap::new(),
+            },
+            BlockfileProvider::new_memory(),
+            "apple OR banana".to_string(),
+            3,
+        );
+        let output = FullTextSearchOperator::new().run(&input).await.unwrap();
+        // "banana" is rarer than "apple", so it outweighs repeated apples.
+        assert_eq!(output.user_ids, vec!["d", "c", "b"]);
+        assert!(output.scores[0] > output.scores[1]);
+        assert!(output.scores[1] > output.scores[2]);
+    }
+
+    #[tokio::test]
+    async fn test_search_log_and_segment() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let mut record_segment = Segment {
+            id: uuid::Uuid::new_v4(),
+            r#type: SegmentType::Record,
+            scope: SegmentScope::RECORD,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut metadata_segment = Segment {
+            id: uuid::Uuid::new_v4(),
+            r#type: SegmentType::BlockfileMetadata,
+            scope: SegmentScope::METADATA,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let segment_records: Vec<LogRecord> = [("a", "apple pie"), ("b", "banana split")]
+            .into_iter()
+            .map(|(id, document)| {
+                let log_record = log_record(id, document);
+                LogRecord {
+                    record: OperationRecord {
+                        embedding: Some(vec![1.0]),
+                        ..log_record.record
+                    },
+                    ..log_record
+                }
+            })
+            .collect();
+        let segment_records = Chunk::new(segment_records.into());
+        let record_segment_writer =
+            RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let mut metadata_segment_writer =
+            MetadataSegmentWriter::from_segment(&metadata_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let materialized = record_segment_writer
+            .materialize(&segment_records)
+            .await
+            .unwrap();
+        metadata_segment_writer
+            .apply_materialized_log_chunk(materialized)
+            .unwrap();
+        metadata_segment_writer.write_to_blockfiles().await.unwrap();
+        record_segment.file_path = record_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+        metadata_segment.file_path = metadata_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+
+        // The log replaces the document of a record in the segment
+        let update = log_record("a", "apple crumble");
+        let update = LogRecord {
+            record: OperationRecord {
+                operation: Operation::Update,
+                ..update.record
+            },
+            ..update
+        };
+        let input = FullTextSearchInput::new(
+            Chunk::new(vec![update].into()),
+            metadata_segment,
+            record_segment,
+            blockfile_provider,
+            "apple".to_string(),
+            10,
+        );
+        let output = FullTextSearchOperator::new().run(&input).await.unwrap();
+        // Each record is counted once, in the log if it is there
+        assert_eq!(output.user_ids, vec!["a"]);
+        assert_eq!(output.scores, vec![bm25(&[1], &[1], 2)]);
+    }
+}
@@ -1,15 +1,17 @@
+use super::normalize_vectors::normalize;
+use crate::distance::DistanceFunction;
 use crate::execution::data::data_chunk::Chunk;
+use crate::index::{HnswSearchParams, IndexFilter};
 use crate::types::{LogRecord, Operation};
 use crate::{
     blockstore::provider::BlockfileProvider,
     errors::{ChromaError, ErrorCodes},
     execution::operator::Operator,
-    segment::{
-        distributed_hnsw_segment::DistributedHNSWSegmentReader, record_segment::RecordSegmentReader,
-    },
+    segment::{record_segment::RecordSegmentReader, vector_segment::VectorSegmentReader},
     types::Segment,
 };
 use async_trait::async_trait;
+use roaring::RoaringBitmap;
 use std::sync::Arc;
 use thiserror::Error;
 
@@ -18,13 +20,14 @@ pub struct HnswKnnOperator {}
 
 #[derive(Debug)]
 pub struct HnswKnnOperatorInput {
-    pub segment: Box<DistributedHNSWSegmentReader>,
+    pub segment: VectorSegmentReader,
     pub query: Vec<f32>,
     pub k: usize,
     pub record_segment: Segment,
     pub blockfile_provider: BlockfileProvider,
     pub allowed_ids: Arc<[String]>,
     pub logs: Chunk<LogRecord>,
+    pub search_params: HnswSearchParams,
 }
 
 #[derive(Debug)]
@@ -80,6 +83,42 @@ impl HnswKnnOperator {
         Ok(disallowed_ids)
     }
 
+    // Rerank the approximate candidates by their exact distance to the query, computed from
+    // the full vectors in the record segment, and keep the k nearest
+    async fn rerank(
+        &self,
+        query: &[f32],
+        k: usize,
+        offset_ids: Vec<usize>,
+        distance_function: &DistanceFunction,
+        record_segment_reader: &RecordSegmentReader<'_>,
+    ) -> Result<(Vec<usize>, Vec<f32>), Box<dyn ChromaError>> {
+        let query = match distance_function {
+            DistanceFunction::Cosine => normalize(query),
+            _ => query.to_vec(),
+        };
+        let mut candidates = Vec::with_capacity(offset_ids.len());
+        for offset_id in offset_ids {
+            let record = match record_segment_reader
+                .get_data_for_offset_id(offset_id as u32)
+                .await
+            {
+                Ok(record) => record,
+                Err(e) => return Err(e),
+            };
+            let distance = match distance_function {
+                DistanceFunction::Cosine => {
+                    distance_function.distance(&query, &normalize(record.embedding))
+                }
+                _ => distance_function.distance(&query, record.embedding),
+            };
+            candidates.push((offset_id, distance));
+        }
+        candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+        candidates.truncate(k);
+        Ok(candidates.into_iter().unzip())
+    }
+
     // Validate that the allowed ids are not in the disallowed ids
     fn validate_allowed_and_disallowed_ids(
         &self,
@@ -146,18 +185,40 @@ impl Operator<HnswKnnOperatorInput, HnswKnnOperatorOutput> for HnswKnnOperator {
             }
         };
 
-        // Convert to usize
-        let allowed_offset_ids: Vec<usize> =
-            allowed_offset_ids.iter().map(|&x| x as usize).collect();
-        let disallowed_offset_ids: Vec<usize> =
-            disallowed_offset_ids.iter().map(|&x| x as usize).collect();
+        // No allowed ids means that every id is allowed
+        let allowed_offset_ids: Option<RoaringBitmap> = match allowed_offset_ids.is_empty() {
+            true => None,
+            false => Some(allowed_offset_ids.into_iter().collect()),
+        };
+        let disallowed_offset_ids: RoaringBitmap = disallowed_offset_ids.into_iter().collect();
+        let filter = IndexFilter::new(allowed_offset_ids.as_ref(), Some(&disallowed_offset_ids));
 
-        let (offset_ids, distances) = input.segment.query(
-            &input.query,
-            input.k,
-            &allowed_offset_ids,
-            &disallowed_offset_ids,
-        );
+        let (offset_ids, distances) = match input
+            .segment
+            .query(&input.query, input.k, &filter, &input.search_params)
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => return Err(e),
+        };
+        let (offset_ids, distances) = match input.segment.rerank() {
+            Some((_, distance_function)) => match self
+                .rerank(
+                    &input.query,
+                    input.k,
+                    offset_ids,
+                    &distance_function,
+                    &record_segment_reader,
+                )
+                .await
+            {
+                Ok(reranked) => reranked,
+                Err(_) => {
+                    return Err(Box::new(HnswKnnOperatorError::RecordSegmentReadError));
+                }
+            },
+            None => (offset_ids, distances),
+        };
         Ok(HnswKnnOperatorOutput {
             offset_ids,
             distances,
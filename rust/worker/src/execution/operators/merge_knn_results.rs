@@ -1,3 +1,5 @@
+use super::normalize_vectors::normalize;
+use crate::distance::DistanceFunction;
 use crate::{
     blockstore::provider::BlockfileProvider,
     errors::ChromaError,
@@ -22,6 +24,17 @@ pub struct MergeKnnResultsOperatorInput {
     k: usize,
     record_segment_definition: Segment,
     blockfile_provider: BlockfileProvider,
+    rerank: Option<MergeKnnRerank>,
+}
+
+/// Reranks the candidates by their distance to the query in another space before
+/// merging them, for segments whose distances are a cheap first stage, such as
+/// hamming distances, see `HnswRerankConfig`. The brute force results must then
+/// include their vectors.
+#[derive(Debug)]
+pub struct MergeKnnRerank {
+    pub query: Vec<f32>,
+    pub distance_function: DistanceFunction,
 }
 
 impl MergeKnnResultsOperatorInput {
@@ -35,6 +48,7 @@ impl MergeKnnResultsOperatorInput {
         k: usize,
         record_segment_definition: Segment,
         blockfile_provider: BlockfileProvider,
+        rerank: Option<MergeKnnRerank>,
     ) -> Self {
         Self {
             hnsw_result_offset_ids,
@@ -46,6 +60,7 @@ impl MergeKnnResultsOperatorInput {
             k,
             record_segment_definition,
             blockfile_provider: blockfile_provider,
+            rerank,
         }
     }
 }
@@ -76,6 +91,26 @@ impl Operator<MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput>
         &self,
         input: &MergeKnnResultsOperatorInput,
     ) -> Result<MergeKnnResultsOperatorOutput, Self::Error> {
+        let (brute_force_result_user_ids, brute_force_result_distances, brute_force_result_vectors) =
+            match &input.rerank {
+                Some(rerank) => {
+                    let vectors = input.brute_force_result_vectors.clone().expect(
+                        "Invariant violation. Reranked brute force results should have vectors",
+                    );
+                    let (user_ids, distances, vectors) =
+                        rerank_results(rerank, input.brute_force_result_user_ids.clone(), vectors);
+                    (
+                        user_ids,
+                        distances,
+                        input.include_vectors.then_some(vectors),
+                    )
+                }
+                None => (
+                    input.brute_force_result_user_ids.clone(),
+                    input.brute_force_result_distances.clone(),
+                    input.brute_force_result_vectors.clone(),
+                ),
+            };
         let (result_user_ids, result_distances, result_vectors) =
             match RecordSegmentReader::from_segment(
                 &input.record_segment_definition,
@@ -88,7 +123,7 @@ impl Operator<MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput>
                     // Convert the HNSW result offset IDs to user IDs
                     let mut hnsw_result_user_ids = Vec::new();
                     let mut hnsw_result_vectors = None;
-                    if input.include_vectors {
+                    if input.include_vectors || input.rerank.is_some() {
                         hnsw_result_vectors = Some(Vec::new());
                     }
 
@@ -106,13 +141,30 @@ impl Operator<MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput>
                             }
                         }
                     }
+                    let (hnsw_result_user_ids, hnsw_result_distances, hnsw_result_vectors) =
+                        match (&input.rerank, hnsw_result_vectors) {
+                            (Some(rerank), Some(vectors)) => {
+                                let (user_ids, distances, vectors) =
+                                    rerank_results(rerank, hnsw_result_user_ids, vectors);
+                                (
+                                    user_ids,
+                                    distances,
+                                    input.include_vectors.then_some(vectors),
+                                )
+                            }
+                            (_, vectors) => (
+                                hnsw_result_user_ids,
+                                input.hnsw_result_distances.clone(),
+                                vectors,
+                            ),
+                        };
                     merge_results(
                         &hnsw_result_user_ids,
-                        &input.hnsw_result_distances,
+                        &hnsw_result_distances,
                         &hnsw_result_vectors,
-                        &input.brute_force_result_user_ids,
-                        &input.brute_force_result_distances,
-                        &input.brute_force_result_vectors,
+                        &brute_force_result_user_ids,
+                        &brute_force_result_distances,
+                        &brute_force_result_vectors,
                         input.include_vectors,
                         input.k,
                     )
@@ -121,7 +173,8 @@ impl Operator<MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput>
                     RecordSegmentReaderCreationError::BlockfileOpenError(e) => {
                         return Err(e);
                     }
-                    RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
+                    RecordSegmentReaderCreationError::InvalidNumberOfFiles
+                    | RecordSegmentReaderCreationError::InvalidConfig(_) => {
                         return Err(e);
                     }
                     RecordSegmentReaderCreationError::UninitializedSegment => {
@@ -133,9 +186,9 @@ impl Operator<MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput>
                             &hnsw_result_user_ids,
                             &hnsw_result_distances,
                             &hnsw_result_vectors,
-                            &input.brute_force_result_user_ids,
-                            &input.brute_force_result_distances,
-                            &input.brute_force_result_vectors,
+                            &brute_force_result_user_ids,
+                            &brute_force_result_distances,
+                            &brute_force_result_vectors,
                             input.include_vectors,
                             input.k,
                         )
@@ -151,6 +204,41 @@ impl Operator<MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput>
     }
 }
 
+// Sort the candidates by their distance to the query in the rerank space
+fn rerank_results<T>(
+    rerank: &MergeKnnRerank,
+    user_ids: Vec<T>,
+    vectors: Vec<Vec<f32>>,
+) -> (Vec<T>, Vec<f32>, Vec<Vec<f32>>) {
+    let query = match rerank.distance_function {
+        DistanceFunction::Cosine => normalize(&rerank.query),
+        _ => rerank.query.clone(),
+    };
+    let mut candidates: Vec<(T, f32, Vec<f32>)> = user_ids
+        .into_iter()
+        .zip(vectors)
+        .map(|(user_id, vector)| {
+            let distance = match rerank.distance_function {
+                DistanceFunction::Cosine => rerank
+                    .distance_function
+                    .distance(&query, &normalize(&vector)),
+                _ => rerank.distance_function.distance(&query, &vector),
+            };
+            (user_id, distance, vector)
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let mut user_ids = Vec::with_capacity(candidates.len());
+    let mut distances = Vec::with_capacity(candidates.len());
+    let mut vectors = Vec::with_capacity(candidates.len());
+    for (user_id, distance, vector) in candidates {
+        user_ids.push(user_id);
+        distances.push(distance);
+        vectors.push(vector);
+    }
+    (user_ids, distances, vectors)
+}
+
 fn merge_results(
     hnsw_result_user_ids: &Vec<&str>,
     hnsw_result_distances: &Vec<f32>,
@@ -247,3 +335,23 @@ fn merge_results(
 
     (result_user_ids, result_distances, result_vectors)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rerank_results() {
+        let rerank = MergeKnnRerank {
+            query: vec![1.0, 1.0],
+            distance_function: DistanceFunction::Euclidean,
+        };
+        // Ordered by hamming distance, all of them are 0 from the query
+        let user_ids = vec!["far", "near", "mid"];
+        let vectors = vec![vec![9.0, 9.0], vec![1.0, 2.0], vec![3.0, 3.0]];
+        let (user_ids, distances, vectors) = rerank_results(&rerank, user_ids, vectors);
+        assert_eq!(user_ids, vec!["near", "mid", "far"]);
+        assert_eq!(distances, vec![1.0, 8.0, 128.0]);
+        assert_eq!(vectors[0], vec![1.0, 2.0]);
+    }
+}
@@ -0,0 +1,441 @@
+use super::normalize_vectors::normalize;
+use crate::distance::DistanceFunction;
+use crate::execution::data::data_chunk::Chunk;
+use crate::types::{LogRecord, MultiVector};
+use crate::{
+    blockstore::{provider::BlockfileProvider, BlockfileError},
+    errors::ChromaError,
+    execution::operator::Operator,
+    segment::record_segment::{
+        resolve_log, RecordSegmentReader, RecordSegmentReaderCreationError, ResolvedOperation,
+    },
+    types::Segment,
+};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Scores the candidates of a multi-vector query by late interaction (MaxSim). Each
+/// vector of the query is matched to its nearest vector of a record's multi-vector
+/// embedding, and the record's distance is the sum of these distances.
+/// # Notes
+/// The candidates are the records the hnsw segment found for any query vector, and
+/// the records written in the log. Their multi-vectors are read from the record segment
+/// and the log, so the distances are exact.
+#[derive(Debug)]
+pub struct MergeMaxSimResultsOperator {}
+
+#[derive(Debug)]
+pub struct MergeMaxSimResultsOperatorInput {
+    query_vectors: Vec<Vec<f32>>,
+    hnsw_result_offset_ids: Vec<Vec<usize>>,
+    logs: Chunk<LogRecord>,
+    allowed_ids: Arc<[String]>,
+    distance_function: DistanceFunction,
+    include_vectors: bool,
+    k: usize,
+    record_segment_definition: Segment,
+    blockfile_provider: BlockfileProvider,
+}
+
+impl MergeMaxSimResultsOperatorInput {
+    pub fn new(
+        query_vectors: Vec<Vec<f32>>,
+        hnsw_result_offset_ids: Vec<Vec<usize>>,
+        logs: Chunk<LogRecord>,
+        allowed_ids: Arc<[String]>,
+        distance_function: DistanceFunction,
+        include_vectors: bool,
+        k: usize,
+        record_segment_definition: Segment,
+        blockfile_provider: BlockfileProvider,
+    ) -> Self {
+        Self {
+            query_vectors,
+            hnsw_result_offset_ids,
+            logs,
+            allowed_ids,
+            distance_function,
+            include_vectors,
+            k,
+            record_segment_definition,
+            blockfile_provider,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MergeMaxSimResultsOperatorOutput {
+    pub user_ids: Vec<String>,
+    pub distances: Vec<f32>,
+    pub vectors: Option<Vec<Vec<f32>>>,
+}
+
+// A record as written by the log. Fields that are not set are read from the record
+// segment if the record is an update of it.
+struct LogCandidate {
+    embedding: Option<Vec<f32>>,
+    multi_embedding: Option<MultiVector>,
+    in_segment: bool,
+}
+
+// The records written by the log, resolved against the record segment as the log
+// materializer applies them. None if the record was deleted.
+async fn log_candidates(
+    logs: &Chunk<LogRecord>,
+    reader: Option<&RecordSegmentReader<'_>>,
+) -> Result<HashMap<String, Option<LogCandidate>>, Box<dyn ChromaError>> {
+    let mut candidates: HashMap<String, Option<LogCandidate>> = HashMap::new();
+    for (log, _, operation) in resolve_log(logs, reader).await? {
+        let record = &log.record;
+        match operation {
+            ResolvedOperation::Add => {
+                candidates.insert(
+                    record.id.clone(),
+                    Some(LogCandidate {
+                        embedding: record.embedding.clone(),
+                        multi_embedding: record.multi_embedding.clone(),
+                        in_segment: false,
+                    }),
+                );
+            }
+            ResolvedOperation::Update => match candidates.get_mut(&record.id) {
+                Some(Some(candidate)) => {
+                    if record.embedding.is_some() {
+                        candidate.embedding = record.embedding.clone();
+                    }
+                    if record.multi_embedding.is_some() {
+                        candidate.multi_embedding = record.multi_embedding.clone();
+                    }
+                }
+                // Only records that exist are updated, so this is a record of the segment
+                Some(None) | None => {
+                    candidates.insert(
+                        record.id.clone(),
+                        Some(LogCandidate {
+                            embedding: record.embedding.clone(),
+                            multi_embedding: record.multi_embedding.clone(),
+                            in_segment: true,
+                        }),
+                    );
+                }
+            },
+            ResolvedOperation::Delete => {
+                candidates.insert(record.id.clone(), None);
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+// The sum over the query vectors of the distance to their nearest vector of the
+// multi-vector. The query vectors are expected to be normalized for cosine distances.
+fn max_sim_distance(
+    query_vectors: &[Vec<f32>],
+    multi_vector: &MultiVector,
+    distance_function: &DistanceFunction,
+) -> f32 {
+    let vectors: Vec<Vec<f32>> = match distance_function {
+        DistanceFunction::Cosine => multi_vector.iter().map(normalize).collect(),
+        _ => multi_vector.iter().map(|vector| vector.to_vec()).collect(),
+    };
+    query_vectors
+        .iter()
+        .map(|query| {
+            vectors
+                .iter()
+                .map(|vector| distance_function.distance(query, vector))
+                .fold(f32::INFINITY, f32::min)
+        })
+        .sum()
+}
+
+#[async_trait]
+impl Operator<MergeMaxSimResultsOperatorInput, MergeMaxSimResultsOperatorOutput>
+    for MergeMaxSimResultsOperator
+{
+    type Error = Box<dyn ChromaError>;
+
+    async fn run(
+        &self,
+        input: &MergeMaxSimResultsOperatorInput,
+    ) -> Result<MergeMaxSimResultsOperatorOutput, Self::Error> {
+        let reader = match RecordSegmentReader::from_segment(
+            &input.record_segment_definition,
+            &input.blockfile_provider,
+        )
+        .await
+        {
+            Ok(reader) => Some(reader),
+            Err(e) => match *e {
+                // The record segment doesn't exist - which implies no HNSW results
+                RecordSegmentReaderCreationError::UninitializedSegment => None,
+                RecordSegmentReaderCreationError::BlockfileOpenError(e) => return Err(e),
+                RecordSegmentReaderCreationError::InvalidNumberOfFiles
+                | RecordSegmentReaderCreationError::InvalidConfig(_) => return Err(e),
+            },
+        };
+        let log_candidates = log_candidates(&input.logs, reader.as_ref()).await?;
+
+        // The user id, embedding and multi-vector of every candidate
+        let mut candidates: Vec<(String, Vec<f32>, MultiVector)> = Vec::new();
+        if let Some(reader) = reader.as_ref() {
+            let mut seen = HashSet::new();
+            for offset_id in input.hnsw_result_offset_ids.iter().flatten() {
+                if !seen.insert(*offset_id) {
+                    continue;
+                }
+                let record = match reader.get_data_for_offset_id(*offset_id as u32).await {
+                    Ok(record) => record,
+                    Err(e) => return Err(e),
+                };
+                // Records written by the log are scored with their log state below
+                if log_candidates.contains_key(record.id) {
+                    continue;
+                }
+                if let Some(multi_embedding) = record.multi_embedding {
+                    candidates.push((
+                        record.id.to_string(),
+                        record.embedding.to_vec(),
+                        multi_embedding,
+                    ));
+                }
+            }
+        }
+        for (user_id, candidate) in log_candidates {
+            let candidate = match candidate {
+                Some(candidate) => candidate,
+                None => continue,
+            };
+            let (embedding, multi_embedding) =
+                match (candidate.embedding, candidate.multi_embedding) {
+                    (Some(embedding), Some(multi_embedding)) => (embedding, multi_embedding),
+                    (embedding, multi_embedding) => {
+                        // An update of a record in the record segment
+                        let offset_id = match (reader.as_ref(), candidate.in_segment) {
+                            (Some(reader), true) => {
+                                match reader.get_offset_id_for_user_id(&user_id).await {
+                                    Ok(offset_id) => offset_id,
+                                    Err(e) if BlockfileError::is_not_found(&*e) => continue,
+                                    Err(e) => return Err(e),
+                                }
+                            }
+                            _ => continue,
+                        };
+                        let record = match reader
+                            .as_ref()
+                            .expect("The reader is set if the offset id was found")
+                            .get_data_for_offset_id(offset_id)
+                            .await
+                        {
+                            Ok(record) => record,
+                            Err(e) => return Err(e),
+                        };
+                        match multi_embedding.or(record.multi_embedding) {
+                            Some(multi_embedding) => (
+                                embedding.unwrap_or_else(|| record.embedding.to_vec()),
+                                multi_embedding,
+                            ),
+                            None => continue,
+                        }
+                    }
+                };
+            candidates.push((user_id, embedding, multi_embedding));
+        }
+
+        let query_vectors: Vec<Vec<f32>> = match input.distance_function {
+            DistanceFunction::Cosine => input
+                .query_vectors
+                .iter()
+                .map(|query| normalize(query))
+                .collect(),
+            _ => input.query_vectors.clone(),
+        };
+        let allowed_ids: HashSet<&str> = input.allowed_ids.iter().map(String::as_str).collect();
+        let mut scored: Vec<(String, f32, Vec<f32>)> = candidates
+            .into_iter()
+            .filter(|(user_id, _, _)| {
+                allowed_ids.is_empty() || allowed_ids.contains(user_id.as_str())
+            })
+            .map(|(user_id, embedding, multi_embedding)| {
+                let distance =
+                    max_sim_distance(&query_vectors, &multi_embedding, &input.distance_function);
+                (user_id, distance, embedding)
+            })
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(input.k);
+
+        let mut user_ids = Vec::with_capacity(scored.len());
+        let mut distances = Vec::with_capacity(scored.len());
+        let mut vectors = input
+            .include_vectors
+            .then(|| Vec::with_capacity(scored.len()));
+        for (user_id, distance, embedding) in scored {
+            user_ids.push(user_id);
+            distances.push(distance);
+            if let Some(vectors) = vectors.as_mut() {
+                vectors.push(embedding);
+            }
+        }
+        Ok(MergeMaxSimResultsOperatorOutput {
+            user_ids,
+            distances,
+            vectors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::{LogMaterializer, SegmentFlusher, SegmentWriter};
+    use crate::types::{Operation, OperationRecord, SegmentScope, SegmentType};
+
+    fn log_record(
+        log_offset: i64,
+        id: &str,
+        multi_embedding: Option<Vec<f32>>,
+        operation: Operation,
+    ) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: match operation {
+                    Operation::Add => Some(vec![0.0, 0.0]),
+                    _ => None,
+                },
+                encoding: None,
+                sparse_embedding: None,
+                multi_embedding: multi_embedding.map(|values| MultiVector::new(2, values).unwrap()),
+                metadata: None,
+                document: None,
+                operation,
+            },
+        }
+    }
+
+    fn input(
+        hnsw_result_offset_ids: Vec<usize>,
+        logs: Vec<LogRecord>,
+        allowed_ids: &[&str],
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
+    ) -> MergeMaxSimResultsOperatorInput {
+        MergeMaxSimResultsOperatorInput::new(
+            vec![vec![1.0, 0.0]],
+            vec![hnsw_result_offset_ids],
+            Chunk::new(logs.into()),
+            allowed_ids.iter().map(|id| id.to_string()).collect(),
+            DistanceFunction::Euclidean,
+            false,
+            10,
+            record_segment.clone(),
+            blockfile_provider.clone(),
+        )
+    }
+
+    #[test]
+    fn test_max_sim_distance() {
+        let multi_vector = MultiVector::new(2, vec![1.0, 0.0, 0.0, 1.0, 3.0, 3.0]).unwrap();
+        // Each query vector is matched to its nearest vector of the multi-vector
+        let query_vectors = vec![vec![1.0, 1.0], vec![3.0, 2.0]];
+        let distance =
+            max_sim_distance(&query_vectors, &multi_vector, &DistanceFunction::Euclidean);
+        assert_eq!(distance, 1.0 + 1.0);
+
+        // Matching the same vector of the multi-vector more than once is allowed
+        let query_vectors = vec![vec![3.0, 3.0], vec![3.0, 3.0]];
+        let distance =
+            max_sim_distance(&query_vectors, &multi_vector, &DistanceFunction::Euclidean);
+        assert_eq!(distance, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_merge_log_and_segment() {
+        let blockfile_provider = BlockfileProvider::new_memory();
+        let mut record_segment = Segment {
+            id: uuid::Uuid::new_v4(),
+            r#type: SegmentType::Record,
+            scope: SegmentScope::RECORD,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        // The squared l2 distances of "a", "b" and "c" to the query are 1, 4 and 9
+        let compacted = Chunk::new(
+            vec![
+                log_record(1, "a", Some(vec![2.0, 0.0, 9.0, 9.0]), Operation::Add),
+                log_record(2, "b", Some(vec![3.0, 0.0]), Operation::Add),
+                log_record(3, "c", Some(vec![4.0, 0.0]), Operation::Add),
+            ]
+            .into(),
+        );
+        {
+            let record_segment_writer =
+                RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                    .await
+                    .unwrap();
+            let materialized = record_segment_writer.materialize(&compacted).await.unwrap();
+            record_segment_writer
+                .apply_materialized_log_chunk(materialized)
+                .unwrap();
+            record_segment.file_path = record_segment_writer
+                .commit()
+                .unwrap()
+                .flush()
+                .await
+                .unwrap();
+        }
+        let record_segment_reader =
+            RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let mut hnsw_result_offset_ids = vec![];
+        for user_id in ["a", "b", "c"] {
+            hnsw_result_offset_ids.push(
+                record_segment_reader
+                    .get_offset_id_for_user_id(user_id)
+                    .await
+                    .unwrap() as usize,
+            );
+        }
+
+        // "a" is deleted and "b" moves away. Adding "c" again and updating the
+        // missing "e" are no-ops, and "d" is added.
+        let logs = vec![
+            log_record(4, "a", None, Operation::Delete),
+            log_record(5, "b", Some(vec![6.0, 0.0]), Operation::Update),
+            log_record(6, "c", Some(vec![1.0, 0.0]), Operation::Add),
+            log_record(7, "d", Some(vec![1.5, 0.0]), Operation::Add),
+            log_record(8, "e", Some(vec![1.0, 0.0]), Operation::Update),
+        ];
+        let output = MergeMaxSimResultsOperator {}
+            .run(&input(
+                hnsw_result_offset_ids.clone(),
+                logs.clone(),
+                &[],
+                &record_segment,
+                &blockfile_provider,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output.user_ids, vec!["d", "c", "b"]);
+        assert_eq!(output.distances, vec![0.25, 9.0, 25.0]);
+        assert!(output.vectors.is_none());
+
+        let output = MergeMaxSimResultsOperator {}
+            .run(&input(
+                hnsw_result_offset_ids,
+                logs,
+                &["a", "b", "e"],
+                &record_segment,
+                &blockfile_provider,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output.user_ids, vec!["b"]);
+    }
+}
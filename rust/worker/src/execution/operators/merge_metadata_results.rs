@@ -1,7 +1,9 @@
 use crate::{
     blockstore::provider::BlockfileProvider,
     errors::{ChromaError, ErrorCodes},
-    execution::{data::data_chunk::Chunk, operator::Operator},
+    execution::{
+        data::data_chunk::Chunk, operator::Operator, operators::metadata_filtering::MatchOffsets,
+    },
     segment::record_segment::{RecordSegmentReader, RecordSegmentReaderCreationError},
     types::{
         update_metdata_to_metdata, LogRecord, Metadata, MetadataValueConversionError, Segment,
@@ -32,6 +34,8 @@ pub struct MergeMetadataResultsOperatorInput {
     // The offset ids that were found in the log, from where/where_document filters
     // if they were specified in the query
     filtered_index_offset_ids: Option<Vec<u32>>,
+    // Where the where_document filter matched in the filtered records, if requested
+    match_offsets: Option<MatchOffsets>,
     record_segment_definition: Segment,
     blockfile_provider: BlockfileProvider,
 }
@@ -41,6 +45,7 @@ impl MergeMetadataResultsOperatorInput {
         filtered_log: Chunk<LogRecord>,
         remaining_query_ids: Option<Vec<String>>,
         filtered_index_offset_ids: Option<Vec<u32>>,
+        match_offsets: Option<MatchOffsets>,
         record_segment_definition: Segment,
         blockfile_provider: BlockfileProvider,
     ) -> Self {
@@ -48,6 +53,7 @@ impl MergeMetadataResultsOperatorInput {
             filtered_log: filtered_log,
             remaining_query_ids: remaining_query_ids,
             filtered_index_offset_ids: filtered_index_offset_ids,
+            match_offsets: match_offsets,
             record_segment_definition,
             blockfile_provider: blockfile_provider,
         }
@@ -59,6 +65,9 @@ pub struct MergeMetadataResultsOperatorOutput {
     pub ids: Vec<String>,
     pub metadata: Vec<Option<Metadata>>,
     pub documents: Vec<Option<String>>,
+    // The (start, end) byte ranges of where_document matches in each document,
+    // empty unless match offsets were requested.
+    pub match_offsets: Vec<Vec<(usize, usize)>>,
 }
 
 #[derive(Error, Debug)]
@@ -99,6 +108,7 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
         let mut ids: Vec<String> = Vec::new();
         let mut metadata = Vec::new();
         let mut documents = Vec::new();
+        let mut match_offsets = Vec::new();
         // Add the data from the brute force results
         for (log_entry, index) in input.filtered_log.iter() {
             ids.push(log_entry.record.id.to_string());
@@ -119,6 +129,13 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
             };
             metadata.push(output_metadata);
             documents.push(log_entry.record.document.clone());
+            match &input.match_offsets {
+                Some(offsets) => match offsets.log.get(&index) {
+                    Some(ranges) => match_offsets.push(ranges.clone()),
+                    None => match_offsets.push(vec![]),
+                },
+                None => match_offsets.push(vec![]),
+            }
         }
 
         let record_segment_reader = match RecordSegmentReader::from_segment(
@@ -136,6 +153,7 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
                             ids,
                             metadata,
                             documents,
+                            match_offsets,
                         });
                     }
                     RecordSegmentReaderCreationError::BlockfileOpenError(_) => {
@@ -144,7 +162,8 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
                             MergeMetadataResultsOperatorError::RecordSegmentCreationError(*e),
                         );
                     }
-                    RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
+                    RecordSegmentReaderCreationError::InvalidNumberOfFiles
+                    | RecordSegmentReaderCreationError::InvalidConfig(_) => {
                         error!("Error creating Record Segment: {:?}", e);
                         return Err(
                             MergeMetadataResultsOperatorError::RecordSegmentCreationError(*e),
@@ -182,6 +201,15 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
 
                 ids.push(user_id.to_string());
                 metadata.push(record.metadata.clone());
+                // Offsets from the index refer to the normalized document.
+                match (&input.match_offsets, record.document) {
+                    (Some(offsets), Some(document)) => match offsets.segment.get(index_offset_id) {
+                        Some(ranges) => match_offsets
+                            .push(offsets.normalization.original_ranges(document, ranges)),
+                        None => match_offsets.push(vec![]),
+                    },
+                    _ => match_offsets.push(vec![]),
+                }
                 match record.document {
                     Some(document) => documents.push(Some(document.to_string())),
                     None => documents.push(None),
@@ -217,6 +245,7 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
 
                 ids.push(record.id.to_string());
                 metadata.push(record.metadata.clone());
+                match_offsets.push(vec![]);
                 match record.document {
                     Some(document) => documents.push(Some(document.to_string())),
                     None => documents.push(None),
@@ -237,6 +266,7 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
             for record in data.iter() {
                 ids.push(record.id.to_string());
                 metadata.push(record.metadata.clone());
+                match_offsets.push(vec![]);
                 match record.document {
                     Some(document) => documents.push(Some(document.to_string())),
                     None => documents.push(None),
@@ -248,6 +278,7 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
             ids,
             metadata,
             documents,
+            match_offsets,
         })
     }
 }
@@ -0,0 +1,692 @@
+use crate::{
+    blockstore::provider::BlockfileProvider,
+    errors::{ChromaError, ErrorCodes},
+    execution::{data::data_chunk::Chunk, operator::Operator},
+    index::fulltext::{
+        normalization::TextNormalization,
+        query::{DocumentQuery, DocumentQueryParseError},
+        regex_prefilter::{regex_prefilter, RegexPrefilterError},
+    },
+    segment::{
+        metadata_segment::{full_text_normalization, MetadataSegmentError, MetadataSegmentReader},
+        record_segment::{
+            resolve_log, RecordSegmentReader, RecordSegmentReaderCreationError, ResolvedOperation,
+        },
+    },
+    types::{BooleanOperator, LogRecord, Segment, WhereDocument, WhereDocumentOperator},
+};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+use tonic::async_trait;
+
+/// The metadata filtering operator evaluates a where document clause against
+/// the log and the metadata segment.
+/// # Inputs
+/// - The log records that have not been compacted into the segments yet.
+/// - The metadata segment, the record segment and a blockfile provider to read them.
+/// - The where document clause.
+/// - Whether to report where in each document the clause matched.
+/// # Outputs
+/// - The log records that match the clause.
+/// - The offset ids of the records in the segment that match the clause.
+/// - The byte ranges of the matches, if requested.
+/// # Log
+/// Only the latest document of a record in the log is matched. A record whose
+/// document the log deletes or replaces is not matched in the segment. The log is
+/// resolved against the record segment as the materializer applies it, so adding
+/// a record that exists, or updating one that does not, changes nothing.
+/// # Regex
+/// The metadata segment can only narrow down the documents a `$regex` may
+/// match, so these candidates are verified against the document text stored
+/// in the record segment. If a regex requires no literal text at all, every
+/// document in the record segment is checked.
+#[derive(Debug)]
+pub(crate) struct MetadataFilteringOperator {}
+
+impl MetadataFilteringOperator {
+    pub(crate) fn new() -> Box<Self> {
+        Box::new(MetadataFilteringOperator {})
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MetadataFilteringInput {
+    log_records: Chunk<LogRecord>,
+    metadata_segment_definition: Segment,
+    record_segment_definition: Segment,
+    blockfile_provider: BlockfileProvider,
+    where_document_clause: WhereDocument,
+    include_match_offsets: bool,
+}
+
+impl MetadataFilteringInput {
+    pub(crate) fn new(
+        log_records: Chunk<LogRecord>,
+        metadata_segment_definition: Segment,
+        record_segment_definition: Segment,
+        blockfile_provider: BlockfileProvider,
+        where_document_clause: WhereDocument,
+        include_match_offsets: bool,
+    ) -> Self {
+        Self {
+            log_records,
+            metadata_segment_definition,
+            record_segment_definition,
+            blockfile_provider,
+            where_document_clause,
+            include_match_offsets,
+        }
+    }
+}
+
+/// Where the phrases of a where document clause occur in each matching document.
+#[derive(Debug, Default)]
+pub(crate) struct MatchOffsets {
+    // Index of the record in the log chunk -> byte ranges in its document.
+    pub(crate) log: HashMap<usize, Vec<(usize, usize)>>,
+    // Offset id -> byte ranges in the normalized document, these are mapped
+    // onto the original document once it is read from the record segment.
+    pub(crate) segment: HashMap<u32, Vec<(usize, usize)>>,
+    pub(crate) normalization: TextNormalization,
+}
+
+#[derive(Debug)]
+pub(crate) struct MetadataFilteringOutput {
+    pub(crate) log_records: Chunk<LogRecord>,
+    pub(crate) offset_ids: Vec<u32>,
+    pub(crate) match_offsets: Option<MatchOffsets>,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum MetadataFilteringError {
+    #[error("Error reading metadata segment")]
+    MetadataSegmentError(#[from] MetadataSegmentError),
+    #[error("Invalid document query")]
+    DocumentQueryParseError(#[from] DocumentQueryParseError),
+    #[error("Invalid regex")]
+    RegexError(#[from] regex::Error),
+    #[error("Invalid regex")]
+    RegexPrefilterError(#[from] RegexPrefilterError),
+    #[error("Error creating record segment reader")]
+    RecordSegmentCreationError(#[from] RecordSegmentReaderCreationError),
+    #[error("Error reading record segment")]
+    RecordSegmentReadError(#[from] Box<dyn ChromaError>),
+}
+
+impl ChromaError for MetadataFilteringError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            MetadataFilteringError::MetadataSegmentError(e) => e.code(),
+            MetadataFilteringError::DocumentQueryParseError(e) => e.code(),
+            MetadataFilteringError::RegexError(_) => ErrorCodes::InvalidArgument,
+            MetadataFilteringError::RegexPrefilterError(e) => e.code(),
+            MetadataFilteringError::RecordSegmentCreationError(e) => e.code(),
+            MetadataFilteringError::RecordSegmentReadError(e) => e.code(),
+        }
+    }
+}
+
+// Compiles every regex in the clause, keyed by its pattern.
+fn compile_regexes(
+    where_document_clause: &WhereDocument,
+    regexes: &mut HashMap<String, Regex>,
+) -> Result<(), MetadataFilteringError> {
+    match where_document_clause {
+        WhereDocument::DirectWhereDocumentComparison(direct_document_comparison) => {
+            match direct_document_comparison.operator {
+                WhereDocumentOperator::Regex => {
+                    if !regexes.contains_key(&direct_document_comparison.document) {
+                        let regex = Regex::new(&direct_document_comparison.document)?;
+                        regexes.insert(direct_document_comparison.document.clone(), regex);
+                    }
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+        WhereDocument::WhereDocumentChildren(where_document_children) => {
+            for child in where_document_children.children.iter() {
+                compile_regexes(child, regexes)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Evaluates a where document clause directly against a document's text.
+fn matches_where_document(
+    where_document_clause: &WhereDocument,
+    document: &str,
+    normalization: &TextNormalization,
+    regexes: &HashMap<String, Regex>,
+) -> Result<bool, MetadataFilteringError> {
+    match where_document_clause {
+        WhereDocument::DirectWhereDocumentComparison(direct_document_comparison) => {
+            let phrase = normalization.normalize(&direct_document_comparison.document);
+            match direct_document_comparison.operator {
+                WhereDocumentOperator::Contains => {
+                    Ok(normalization.normalize(document).contains(phrase.as_ref()))
+                }
+                WhereDocumentOperator::NotContains => {
+                    Ok(!normalization.normalize(document).contains(phrase.as_ref()))
+                }
+                WhereDocumentOperator::Matches => {
+                    let query = DocumentQuery::parse(&direct_document_comparison.document)?;
+                    Ok(query.matches_document(document, normalization))
+                }
+                // Regexes match the original text, normalization does not apply.
+                WhereDocumentOperator::Regex => {
+                    match regexes.get(&direct_document_comparison.document) {
+                        Some(regex) => Ok(regex.is_match(document)),
+                        None => Ok(
+                            Regex::new(&direct_document_comparison.document)?.is_match(document)
+                        ),
+                    }
+                }
+            }
+        }
+        WhereDocument::WhereDocumentChildren(where_document_children) => {
+            for child in where_document_children.children.iter() {
+                let matches = matches_where_document(child, document, normalization, regexes)?;
+                match (&where_document_children.operator, matches) {
+                    (BooleanOperator::And, false) => return Ok(false),
+                    (BooleanOperator::Or, true) => return Ok(true),
+                    _ => {}
+                }
+            }
+            match where_document_children.operator {
+                BooleanOperator::And => Ok(true),
+                BooleanOperator::Or => Ok(false),
+            }
+        }
+    }
+}
+
+// The phrases whose occurrences are reported as matches of the clause.
+fn positive_phrases(
+    where_document_clause: &WhereDocument,
+) -> Result<Vec<String>, MetadataFilteringError> {
+    match where_document_clause {
+        WhereDocument::DirectWhereDocumentComparison(direct_document_comparison) => {
+            match direct_document_comparison.operator {
+                WhereDocumentOperator::Contains => {
+                    Ok(vec![direct_document_comparison.document.clone()])
+                }
+                WhereDocumentOperator::NotContains | WhereDocumentOperator::Regex => Ok(vec![]),
+                WhereDocumentOperator::Matches => {
+                    let query = DocumentQuery::parse(&direct_document_comparison.document)?;
+                    Ok(query
+                        .positive_phrases()
+                        .into_iter()
+                        .map(|phrase| phrase.to_string())
+                        .collect())
+                }
+            }
+        }
+        WhereDocument::WhereDocumentChildren(where_document_children) => {
+            let mut phrases = vec![];
+            for child in where_document_children.children.iter() {
+                phrases.extend(positive_phrases(child)?);
+            }
+            Ok(phrases)
+        }
+    }
+}
+
+// Whether the clause has a regex that the metadata segment can not narrow
+// down, so that every document has to be checked.
+fn has_unbounded_regex(
+    where_document_clause: &WhereDocument,
+) -> Result<bool, MetadataFilteringError> {
+    match where_document_clause {
+        WhereDocument::DirectWhereDocumentComparison(direct_document_comparison) => {
+            match direct_document_comparison.operator {
+                WhereDocumentOperator::Regex => {
+                    Ok(regex_prefilter(&direct_document_comparison.document)?.is_none())
+                }
+                _ => Ok(false),
+            }
+        }
+        WhereDocument::WhereDocumentChildren(where_document_children) => {
+            for child in where_document_children.children.iter() {
+                if has_unbounded_regex(child)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+    }
+}
+
+#[async_trait]
+impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilteringOperator {
+    type Error = MetadataFilteringError;
+
+    async fn run(
+        &self,
+        input: &MetadataFilteringInput,
+    ) -> Result<MetadataFilteringOutput, MetadataFilteringError> {
+        let normalization = full_text_normalization(&input.metadata_segment_definition)?;
+        let phrases = match input.include_match_offsets {
+            true => positive_phrases(&input.where_document_clause)?,
+            false => vec![],
+        };
+        let mut match_offsets = MatchOffsets {
+            normalization,
+            ..Default::default()
+        };
+        let mut regexes = HashMap::new();
+        compile_regexes(&input.where_document_clause, &mut regexes)?;
+
+        let record_segment_reader = match regexes.is_empty() && input.log_records.len() == 0 {
+            true => None,
+            false => match RecordSegmentReader::from_segment(
+                &input.record_segment_definition,
+                &input.blockfile_provider,
+            )
+            .await
+            {
+                Ok(reader) => Some(reader),
+                Err(e) => match *e {
+                    RecordSegmentReaderCreationError::UninitializedSegment => None,
+                    _ => return Err(MetadataFilteringError::RecordSegmentCreationError(*e)),
+                },
+            },
+        };
+
+        // The latest document of each record in the log. A deletion or a new
+        // document replaces the document in the segment, an update that leaves
+        // the document alone does not.
+        let mut log_documents: HashMap<&str, Option<(usize, &str)>> = HashMap::new();
+        for (log_record, index, operation) in
+            resolve_log(&input.log_records, record_segment_reader.as_ref()).await?
+        {
+            let user_id = log_record.record.id.as_str();
+            match (operation, &log_record.record.document) {
+                (ResolvedOperation::Delete, _) | (ResolvedOperation::Add, None) => {
+                    log_documents.insert(user_id, None);
+                }
+                (_, Some(document)) => {
+                    log_documents.insert(user_id, Some((index, document.as_str())));
+                }
+                (ResolvedOperation::Update, None) => {}
+            }
+        }
+
+        // Records in the log are not indexed yet, so match them directly.
+        let mut log_records = input.log_records.clone();
+        let mut visibility = vec![false; log_records.total_len()];
+        for (index, document) in log_documents.values().flatten() {
+            if !matches_where_document(
+                &input.where_document_clause,
+                document,
+                &normalization,
+                &regexes,
+            )? {
+                continue;
+            }
+            visibility[*index] = true;
+            if input.include_match_offsets {
+                let normalized_document = normalization.normalize(document);
+                let mut ranges = vec![];
+                for phrase in phrases.iter() {
+                    let phrase = normalization.normalize(phrase);
+                    if phrase.is_empty() {
+                        continue;
+                    }
+                    for (start, _) in normalized_document.match_indices(phrase.as_ref()) {
+                        ranges.push((start, start + phrase.len()));
+                    }
+                }
+                let mut ranges = normalization.original_ranges(document, &ranges);
+                ranges.sort();
+                ranges.dedup();
+                match_offsets.log.insert(*index, ranges);
+            }
+        }
+        log_records.set_visibility(visibility);
+
+        // An uninitialized segment has no files and nothing to search.
+        if input.metadata_segment_definition.file_path.is_empty() {
+            return Ok(MetadataFilteringOutput {
+                log_records,
+                offset_ids: vec![],
+                match_offsets: match input.include_match_offsets {
+                    true => Some(match_offsets),
+                    false => None,
+                },
+            });
+        }
+
+        let metadata_segment_reader = MetadataSegmentReader::from_segment(
+            &input.metadata_segment_definition,
+            &input.blockfile_provider,
+        )
+        .await?;
+        let unbounded = has_unbounded_regex(&input.where_document_clause)?;
+        let mut offset_ids: Vec<u32> = match unbounded {
+            true => vec![],
+            false => metadata_segment_reader
+                .query(None, Some(&input.where_document_clause), None, 0, 0)
+                .await?
+                .into_iter()
+                .map(|offset_id| offset_id as u32)
+                .collect(),
+        };
+
+        match &record_segment_reader {
+            Some(record_segment_reader) => {
+                if unbounded {
+                    offset_ids = record_segment_reader.get_all_offset_ids().await?;
+                    offset_ids.sort();
+                }
+                // Records whose document the log replaced are matched against the log.
+                if !log_documents.is_empty() {
+                    let mut shadowed_offset_ids = HashSet::new();
+                    for user_id in log_documents.keys() {
+                        if record_segment_reader
+                            .data_exists_for_user_id(user_id)
+                            .await?
+                        {
+                            shadowed_offset_ids.insert(
+                                record_segment_reader
+                                    .get_offset_id_for_user_id(user_id)
+                                    .await?,
+                            );
+                        }
+                    }
+                    offset_ids.retain(|offset_id| !shadowed_offset_ids.contains(offset_id));
+                }
+                // Verify the candidates for regexes against the documents.
+                if !regexes.is_empty() {
+                    let mut verified_offset_ids = vec![];
+                    for offset_id in offset_ids {
+                        let record = record_segment_reader
+                            .get_data_for_offset_id(offset_id)
+                            .await?;
+                        match record.document {
+                            Some(document) => {
+                                if matches_where_document(
+                                    &input.where_document_clause,
+                                    document,
+                                    &normalization,
+                                    &regexes,
+                                )? {
+                                    verified_offset_ids.push(offset_id);
+                                }
+                            }
+                            None => {}
+                        }
+                    }
+                    offset_ids = verified_offset_ids;
+                }
+            }
+            None => {
+                if !regexes.is_empty() {
+                    offset_ids = vec![];
+                }
+            }
+        }
+        if input.include_match_offsets {
+            let phrases: Vec<&str> = phrases.iter().map(|phrase| phrase.as_str()).collect();
+            match_offsets.segment = metadata_segment_reader
+                .phrase_offsets(&phrases, &offset_ids)
+                .await;
+        }
+
+        Ok(MetadataFilteringOutput {
+            log_records,
+            offset_ids,
+            match_offsets: match input.include_match_offsets {
+                true => Some(match_offsets),
+                false => None,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::{
+        metadata_segment::MetadataSegmentWriter, record_segment::RecordSegmentWriter,
+        LogMaterializer, SegmentFlusher, SegmentWriter,
+    };
+    use crate::types::{
+        DirectDocumentComparison, Operation, OperationRecord, SegmentScope, SegmentType,
+    };
+
+    fn contains(document: &str) -> WhereDocument {
+        WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+            document: document.to_string(),
+            operator: WhereDocumentOperator::Contains,
+        })
+    }
+
+    fn log_record(
+        log_offset: i64,
+        id: &str,
+        document: Option<&str>,
+        operation: Operation,
+    ) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: match operation {
+                    Operation::Add => Some(vec![1.0, 2.0, 3.0]),
+                    _ => None,
+                },
+                encoding: None,
+                sparse_embedding: None,
+                multi_embedding: None,
+                metadata: None,
+                document: document.map(|document| document.to_string()),
+                operation,
+            },
+        }
+    }
+
+    #[test]
+    fn test_matches_where_document() {
+        let normalization = TextNormalization::default();
+        let clause = WhereDocument::WhereDocumentChildren(crate::types::WhereDocumentChildren {
+            children: vec![
+                contains("hello"),
+                WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+                    document: "goodbye".to_string(),
+                    operator: WhereDocumentOperator::NotContains,
+                }),
+            ],
+            operator: BooleanOperator::And,
+        });
+        let regexes = HashMap::new();
+        assert!(matches_where_document(&clause, "hello world", &normalization, &regexes).unwrap());
+        assert!(
+            !matches_where_document(&clause, "hello, goodbye", &normalization, &regexes).unwrap()
+        );
+        assert!(!matches_where_document(&clause, "world", &normalization, &regexes).unwrap());
+        assert_eq!(positive_phrases(&clause).unwrap(), vec!["hello"]);
+
+        let normalization = TextNormalization {
+            case_fold: true,
+            strip_accents: false,
+        };
+        assert!(
+            matches_where_document(&contains("HELLO"), "Hello", &normalization, &regexes).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_matches_regex() {
+        let normalization = TextNormalization::default();
+        let clause = WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+            document: r"fn \w+\(".to_string(),
+            operator: WhereDocumentOperator::Regex,
+        });
+        let mut regexes = HashMap::new();
+        compile_regexes(&clause, &mut regexes).unwrap();
+        assert_eq!(regexes.len(), 1);
+        assert!(
+            matches_where_document(&clause, "pub fn search(&self)", &normalization, &regexes)
+                .unwrap()
+        );
+        assert!(!matches_where_document(&clause, "fn (", &normalization, &regexes).unwrap());
+        assert!(!has_unbounded_regex(&clause).unwrap());
+
+        let unbounded = WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+            document: r"\d{3}".to_string(),
+            operator: WhereDocumentOperator::Regex,
+        });
+        assert!(has_unbounded_regex(&unbounded).unwrap());
+
+        let invalid = WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+            document: "(".to_string(),
+            operator: WhereDocumentOperator::Regex,
+        });
+        assert!(compile_regexes(&invalid, &mut HashMap::new()).is_err());
+    }
+
+    // Record and metadata segments holding "a", "b" and "c", each with a
+    // document that contains "hello".
+    async fn compacted_segments(blockfile_provider: &BlockfileProvider) -> (Segment, Segment) {
+        let mut record_segment = Segment {
+            id: uuid::Uuid::new_v4(),
+            r#type: SegmentType::Record,
+            scope: SegmentScope::RECORD,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut metadata_segment = Segment {
+            id: uuid::Uuid::new_v4(),
+            r#type: SegmentType::BlockfileMetadata,
+            scope: SegmentScope::METADATA,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let compacted: Chunk<LogRecord> = Chunk::new(
+            vec![
+                log_record(1, "a", Some("hello apple"), Operation::Add),
+                log_record(2, "b", Some("hello banana"), Operation::Add),
+                log_record(3, "c", Some("hello cherry"), Operation::Add),
+            ]
+            .into(),
+        );
+        {
+            let record_segment_writer =
+                RecordSegmentWriter::from_segment(&record_segment, blockfile_provider)
+                    .await
+                    .unwrap();
+            let mut metadata_segment_writer =
+                MetadataSegmentWriter::from_segment(&metadata_segment, blockfile_provider)
+                    .await
+                    .unwrap();
+            let materialized = record_segment_writer
+                .materialize(&compacted)
+                .await
+                .expect("Materialize failed");
+            metadata_segment_writer
+                .apply_materialized_log_chunk(materialized)
+                .expect("Apply materialized log chunk failed");
+            metadata_segment_writer.write_to_blockfiles().await.unwrap();
+            record_segment.file_path = record_segment_writer
+                .commit()
+                .unwrap()
+                .flush()
+                .await
+                .unwrap();
+            metadata_segment.file_path = metadata_segment_writer
+                .commit()
+                .unwrap()
+                .flush()
+                .await
+                .unwrap();
+        }
+        (record_segment, metadata_segment)
+    }
+
+    async fn segment_user_ids(
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
+        offset_ids: Vec<u32>,
+    ) -> Vec<String> {
+        let record_segment_reader =
+            RecordSegmentReader::from_segment(record_segment, blockfile_provider)
+                .await
+                .unwrap();
+        let mut user_ids = vec![];
+        for offset_id in offset_ids {
+            user_ids.push(
+                record_segment_reader
+                    .get_user_id_for_offset_id(offset_id)
+                    .await
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+        user_ids
+    }
+
+    #[tokio::test]
+    async fn test_log_shadows_segment() {
+        let blockfile_provider = BlockfileProvider::new_memory();
+        let (record_segment, metadata_segment) = compacted_segments(&blockfile_provider).await;
+
+        // "a" is deleted, "b" gets a new document and "c" only new metadata.
+        let log_records = vec![
+            log_record(4, "a", None, Operation::Delete),
+            log_record(5, "b", Some("hello blueberry"), Operation::Update),
+            log_record(6, "c", None, Operation::Update),
+            log_record(7, "d", Some("goodbye"), Operation::Add),
+            log_record(8, "d", Some("hello date"), Operation::Update),
+        ];
+        let input = MetadataFilteringInput::new(
+            Chunk::new(log_records.into()),
+            metadata_segment,
+            record_segment.clone(),
+            blockfile_provider.clone(),
+            contains("hello"),
+            false,
+        );
+        let output = MetadataFilteringOperator::new().run(&input).await.unwrap();
+        let log_ids: Vec<&str> = output
+            .log_records
+            .iter()
+            .map(|(log_record, _)| log_record.record.id.as_str())
+            .collect();
+        assert_eq!(log_ids, vec!["b", "d"]);
+        assert_eq!(
+            segment_user_ids(&record_segment, &blockfile_provider, output.offset_ids).await,
+            vec!["c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_log_ignores_operations_the_materializer_skips() {
+        let blockfile_provider = BlockfileProvider::new_memory();
+        let (record_segment, metadata_segment) = compacted_segments(&blockfile_provider).await;
+
+        // Adding "a" again and updating the missing "e" are both no-ops.
+        let log_records = vec![
+            log_record(4, "a", Some("goodbye"), Operation::Add),
+            log_record(5, "e", Some("hello elderberry"), Operation::Update),
+        ];
+        let input = MetadataFilteringInput::new(
+            Chunk::new(log_records.into()),
+            metadata_segment,
+            record_segment.clone(),
+            blockfile_provider.clone(),
+            contains("hello"),
+            false,
+        );
+        let output = MetadataFilteringOperator::new().run(&input).await.unwrap();
+        assert_eq!(output.log_records.iter().count(), 0);
+        let mut segment_ids =
+            segment_user_ids(&record_segment, &blockfile_provider, output.offset_ids).await;
+        segment_ids.sort();
+        assert_eq!(segment_ids, vec!["a", "b", "c"]);
+    }
+}
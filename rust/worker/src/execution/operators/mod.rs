@@ -1,11 +1,16 @@
 pub(super) mod brute_force_knn;
 pub(super) mod count_records;
 pub(super) mod flush_s3;
+pub(super) mod full_text_search;
 pub(super) mod hnsw_knn;
 pub(super) mod merge_knn_results;
+pub(super) mod merge_max_sim_results;
 pub(super) mod merge_metadata_results;
+pub(super) mod metadata_filtering;
 pub(super) mod normalize_vectors;
 pub(super) mod partition;
 pub(super) mod pull_log;
+pub(super) mod rank_fusion;
+pub(super) mod sparse_knn;
 pub(super) mod register;
 pub(super) mod write_segments;
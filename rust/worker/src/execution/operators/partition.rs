@@ -149,6 +149,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -160,6 +162,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -171,6 +175,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
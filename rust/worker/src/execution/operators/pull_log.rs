@@ -169,6 +169,8 @@ mod tests {
                         id: "embedding_id_1".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
@@ -188,6 +190,8 @@ mod tests {
                         id: "embedding_id_2".to_string(),
                         embedding: None,
                         encoding: None,
+                        sparse_embedding: None,
+                        multi_embedding: None,
                         metadata: None,
                         document: None,
                         operation: Operation::Add,
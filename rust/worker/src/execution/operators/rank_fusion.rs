@@ -0,0 +1,268 @@
+use crate::{execution::operator::Operator, types::HybridQueryResult};
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+pub(crate) const DEFAULT_RANK_CONSTANT: f32 = 60.0;
+
+/// How the ranked lists of a hybrid query are combined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FusionMethod {
+    /// Reciprocal rank fusion, each list contributes 1 / (rank_constant + rank)
+    /// for every record it contains, with ranks starting at 1.
+    ReciprocalRank { rank_constant: f32 },
+    /// Each list's scores are min-max normalized to [0, 1], distances are
+    /// turned into similarities, and the weighted sum is the fused score.
+    WeightedScore {
+        vector_weight: f32,
+        text_weight: f32,
+    },
+}
+
+/// The rank fusion operator combines the nearest neighbors of a query vector
+/// with the full text matches of a document query, or the sparse matches of a
+/// sparse query, into a single ranking.
+/// # Inputs
+/// - The nearest neighbors and their distances, closest first, as output by
+///   the MergeKnnResultsOperator.
+/// - The text matches and their scores, best first, as output by the
+///   FullTextSearchOperator or the SparseKnnOperator.
+/// - The fusion method and the number of results to return.
+/// # Outputs
+/// - The k best records by fused score, best first.
+#[derive(Debug)]
+pub(crate) struct RankFusionOperator {}
+
+impl RankFusionOperator {
+    pub(crate) fn new() -> Box<Self> {
+        Box::new(RankFusionOperator {})
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RankFusionInput {
+    vector_user_ids: Vec<String>,
+    vector_distances: Vec<f32>,
+    vectors: Option<Vec<Vec<f32>>>,
+    text_user_ids: Vec<String>,
+    text_scores: Vec<f32>,
+    method: FusionMethod,
+    k: usize,
+}
+
+impl RankFusionInput {
+    pub(crate) fn new(
+        vector_user_ids: Vec<String>,
+        vector_distances: Vec<f32>,
+        vectors: Option<Vec<Vec<f32>>>,
+        text_user_ids: Vec<String>,
+        text_scores: Vec<f32>,
+        method: FusionMethod,
+        k: usize,
+    ) -> Self {
+        Self {
+            vector_user_ids,
+            vector_distances,
+            vectors,
+            text_user_ids,
+            text_scores,
+            method,
+            k,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RankFusionOutput {
+    pub(crate) results: Vec<HybridQueryResult>,
+}
+
+// Scales the values to [0, 1]. If all values are equal they all map to 1.
+fn min_max_normalize(values: &[f32]) -> Vec<f32> {
+    let min = values.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let range = max - min;
+    values
+        .iter()
+        .map(|value| match range > 0.0 {
+            true => (value - min) / range,
+            false => 1.0,
+        })
+        .collect()
+}
+
+/// The fused score of every record in either list, best first. Ties are
+/// broken by user id so that the ranking is deterministic.
+pub(crate) fn fuse(
+    vector_user_ids: &[String],
+    vector_distances: &[f32],
+    text_user_ids: &[String],
+    text_scores: &[f32],
+    method: FusionMethod,
+) -> Vec<(String, f32)> {
+    let mut fused: HashMap<&str, f32> = HashMap::new();
+    match method {
+        FusionMethod::ReciprocalRank { rank_constant } => {
+            for (rank, user_id) in vector_user_ids.iter().enumerate() {
+                *fused.entry(user_id).or_insert(0.0) += 1.0 / (rank_constant + rank as f32 + 1.0);
+            }
+            for (rank, user_id) in text_user_ids.iter().enumerate() {
+                *fused.entry(user_id).or_insert(0.0) += 1.0 / (rank_constant + rank as f32 + 1.0);
+            }
+        }
+        FusionMethod::WeightedScore {
+            vector_weight,
+            text_weight,
+        } => {
+            // Negated distances normalize to similarities, the closest is 1.
+            let negated_distances: Vec<f32> =
+                vector_distances.iter().map(|distance| -distance).collect();
+            let similarities = min_max_normalize(&negated_distances);
+            for (user_id, similarity) in vector_user_ids.iter().zip(similarities) {
+                *fused.entry(user_id).or_insert(0.0) += vector_weight * similarity;
+            }
+            let text_scores = min_max_normalize(text_scores);
+            for (user_id, score) in text_user_ids.iter().zip(text_scores) {
+                *fused.entry(user_id).or_insert(0.0) += text_weight * score;
+            }
+        }
+    }
+    let mut fused: Vec<(String, f32)> = fused
+        .into_iter()
+        .map(|(user_id, score)| (user_id.to_string(), score))
+        .collect();
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    fused
+}
+
+#[async_trait]
+impl Operator<RankFusionInput, RankFusionOutput> for RankFusionOperator {
+    type Error = ();
+
+    async fn run(&self, input: &RankFusionInput) -> Result<RankFusionOutput, Self::Error> {
+        let fused = fuse(
+            &input.vector_user_ids,
+            &input.vector_distances,
+            &input.text_user_ids,
+            &input.text_scores,
+            input.method,
+        );
+
+        let mut vector_hits = HashMap::new();
+        for (i, user_id) in input.vector_user_ids.iter().enumerate() {
+            vector_hits.insert(user_id.as_str(), i);
+        }
+        let mut text_hits = HashMap::new();
+        for (i, user_id) in input.text_user_ids.iter().enumerate() {
+            text_hits.insert(user_id.as_str(), i);
+        }
+
+        let mut results = Vec::with_capacity(input.k.min(fused.len()));
+        for (user_id, score) in fused.into_iter().take(input.k) {
+            let vector_hit = vector_hits.get(user_id.as_str()).cloned();
+            let text_hit = text_hits.get(user_id.as_str()).cloned();
+            let vector = match (&input.vectors, vector_hit) {
+                (Some(vectors), Some(i)) => Some(vectors[i].clone()),
+                _ => None,
+            };
+            results.push(HybridQueryResult {
+                id: user_id,
+                score,
+                distance: vector_hit.map(|i| input.vector_distances[i]),
+                text_score: text_hit.map(|i| input.text_scores[i]),
+                vector,
+            });
+        }
+        Ok(RankFusionOutput { results })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let fused = fuse(
+            &ids(&["a", "b", "c"]),
+            &[0.1, 0.2, 0.3],
+            &ids(&["c", "b", "d"]),
+            &[3.0, 2.0, 1.0],
+            FusionMethod::ReciprocalRank {
+                rank_constant: 60.0,
+            },
+        );
+        let fused_ids: Vec<&str> = fused.iter().map(|(id, _)| id.as_str()).collect();
+        // c is first and third, just ahead of b which is second in both lists.
+        assert_eq!(fused_ids, vec!["c", "b", "a", "d"]);
+        assert!((fused[0].1 - (1.0 / 61.0 + 1.0 / 63.0)).abs() < 1e-6);
+        assert!((fused[1].1 - 2.0 / 62.0).abs() < 1e-6);
+        assert!((fused[2].1 - 1.0 / 61.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_weighted_score_fusion() {
+        let vector_user_ids = ids(&["a", "b", "c"]);
+        let text_user_ids = ids(&["c", "b"]);
+        let fused = fuse(
+            &vector_user_ids,
+            &[0.0, 0.5, 1.0],
+            &text_user_ids,
+            &[4.0, 2.0],
+            FusionMethod::WeightedScore {
+                vector_weight: 0.5,
+                text_weight: 0.5,
+            },
+        );
+        // a: 0.5 * 1.0, b: 0.5 * 0.5 + 0.5 * 0.0, c: 0.5 * 0.0 + 0.5 * 1.0
+        assert_eq!(
+            fused,
+            vec![
+                ("a".to_string(), 0.5),
+                ("c".to_string(), 0.5),
+                ("b".to_string(), 0.25)
+            ]
+        );
+
+        // Favoring the text scores puts c first.
+        let fused = fuse(
+            &vector_user_ids,
+            &[0.0, 0.5, 1.0],
+            &text_user_ids,
+            &[4.0, 2.0],
+            FusionMethod::WeightedScore {
+                vector_weight: 0.2,
+                text_weight: 0.8,
+            },
+        );
+        assert_eq!(fused[0].0, "c");
+    }
+
+    #[tokio::test]
+    async fn test_rank_fusion_operator() {
+        let input = RankFusionInput::new(
+            ids(&["a", "b"]),
+            vec![0.1, 0.2],
+            Some(vec![vec![1.0, 0.0], vec![0.0, 1.0]]),
+            ids(&["b", "c"]),
+            vec![2.0, 1.0],
+            FusionMethod::ReciprocalRank {
+                rank_constant: DEFAULT_RANK_CONSTANT,
+            },
+            2,
+        );
+        let output = RankFusionOperator::new().run(&input).await.unwrap();
+        assert_eq!(output.results.len(), 2);
+        let b = &output.results[0];
+        assert_eq!(b.id, "b");
+        assert_eq!(b.distance, Some(0.2));
+        assert_eq!(b.text_score, Some(2.0));
+        assert_eq!(b.vector, Some(vec![0.0, 1.0]));
+        let a = &output.results[1];
+        assert_eq!(a.id, "a");
+        assert_eq!(a.text_score, None);
+    }
+}
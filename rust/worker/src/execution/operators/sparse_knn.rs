@@ -0,0 +1,206 @@
+use crate::{
+    blockstore::provider::BlockfileProvider,
+    errors::{ChromaError, ErrorCodes},
+    execution::{data::data_chunk::Chunk, operator::Operator},
+    index::IndexFilter,
+    segment::{
+        record_segment::{RecordSegmentReader, RecordSegmentReaderCreationError},
+        sparse_vector_segment::{SparseVectorSegmentFromSegmentError, SparseVectorSegmentReader},
+    },
+    types::{LogRecord, Operation, Segment, SparseVector},
+};
+use async_trait::async_trait;
+use roaring::RoaringBitmap;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// The sparse knn operator finds the records whose sparse embeddings have the
+/// largest dot product with a sparse query vector.
+/// # Inputs
+/// - The log records that have not been compacted into the segments yet.
+/// - The sparse vector segment, the record segment and a blockfile provider to read them.
+/// - The sparse query vector, the number of results to return and the user ids
+///   the results are restricted to, all ids are allowed if empty.
+/// # Outputs
+/// - The user ids of the best matches and their dot products, largest first.
+/// # Notes
+/// Records without a positive dot product with the query are not matches. A
+/// record in the log shadows the version of it in the segment.
+#[derive(Debug)]
+pub(crate) struct SparseKnnOperator {}
+
+impl SparseKnnOperator {
+    pub(crate) fn new() -> Box<Self> {
+        Box::new(SparseKnnOperator {})
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SparseKnnInput {
+    log_records: Chunk<LogRecord>,
+    sparse_vector_segment_definition: Segment,
+    record_segment_definition: Segment,
+    blockfile_provider: BlockfileProvider,
+    query: SparseVector,
+    k: usize,
+    allowed_ids: Arc<[String]>,
+}
+
+impl SparseKnnInput {
+    pub(crate) fn new(
+        log_records: Chunk<LogRecord>,
+        sparse_vector_segment_definition: Segment,
+        record_segment_definition: Segment,
+        blockfile_provider: BlockfileProvider,
+        query: SparseVector,
+        k: usize,
+        allowed_ids: Arc<[String]>,
+    ) -> Self {
+        Self {
+            log_records,
+            sparse_vector_segment_definition,
+            record_segment_definition,
+            blockfile_provider,
+            query,
+            k,
+            allowed_ids,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SparseKnnOutput {
+    pub(crate) user_ids: Vec<String>,
+    pub(crate) scores: Vec<f32>,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum SparseKnnError {
+    #[error("Error creating sparse vector segment reader")]
+    SparseVectorSegmentError(#[from] SparseVectorSegmentFromSegmentError),
+    #[error("Error creating record segment reader")]
+    RecordSegmentCreationError(#[from] RecordSegmentReaderCreationError),
+    #[error("Error searching sparse vector index")]
+    SparseIndexError(#[from] Box<dyn ChromaError>),
+}
+
+impl ChromaError for SparseKnnError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            SparseKnnError::SparseVectorSegmentError(e) => e.code(),
+            SparseKnnError::RecordSegmentCreationError(e) => e.code(),
+            SparseKnnError::SparseIndexError(e) => e.code(),
+        }
+    }
+}
+
+#[async_trait]
+impl Operator<SparseKnnInput, SparseKnnOutput> for SparseKnnOperator {
+    type Error = SparseKnnError;
+
+    async fn run(&self, input: &SparseKnnInput) -> Result<SparseKnnOutput, SparseKnnError> {
+        let allowed = |user_id: &str| {
+            input.allowed_ids.is_empty() || input.allowed_ids.iter().any(|id| id == user_id)
+        };
+
+        // The latest sparse embedding of each record in the log, None if the record
+        // is deleted. An update that leaves the sparse embedding alone keeps the
+        // indexed one.
+        let mut log_vectors: HashMap<&str, Option<&SparseVector>> = HashMap::new();
+        for (log_record, _) in input.log_records.iter() {
+            match (
+                &log_record.record.operation,
+                &log_record.record.sparse_embedding,
+            ) {
+                (Operation::Delete, _) => {
+                    log_vectors.insert(log_record.record.id.as_str(), None);
+                }
+                (_, Some(sparse_embedding)) => {
+                    log_vectors.insert(log_record.record.id.as_str(), Some(sparse_embedding));
+                }
+                (_, None) => {}
+            }
+        }
+
+        let mut results: Vec<(String, f32)> = Vec::new();
+        for (user_id, vector) in log_vectors.iter() {
+            let vector = match vector {
+                Some(vector) if allowed(user_id) => vector,
+                _ => continue,
+            };
+            let score = input.query.dot(vector);
+            if score > 0.0 {
+                results.push((user_id.to_string(), score));
+            }
+        }
+
+        // An uninitialized segment has no files and nothing to search.
+        if !input.sparse_vector_segment_definition.file_path.is_empty() {
+            let sparse_vector_segment_reader = SparseVectorSegmentReader::from_segment(
+                &input.sparse_vector_segment_definition,
+                &input.blockfile_provider,
+            )
+            .map_err(|e| *e)?;
+            let record_segment_reader = RecordSegmentReader::from_segment(
+                &input.record_segment_definition,
+                &input.blockfile_provider,
+            )
+            .await
+            .map_err(|e| *e)?;
+
+            let mut disallowed_offset_ids = RoaringBitmap::new();
+            for user_id in log_vectors.keys() {
+                if record_segment_reader
+                    .data_exists_for_user_id(user_id)
+                    .await?
+                {
+                    disallowed_offset_ids.insert(
+                        record_segment_reader
+                            .get_offset_id_for_user_id(user_id)
+                            .await?,
+                    );
+                }
+            }
+            // No allowed ids means that every id is allowed
+            let allowed_offset_ids = match input.allowed_ids.is_empty() {
+                true => None,
+                false => {
+                    let mut allowed_offset_ids = RoaringBitmap::new();
+                    for user_id in input.allowed_ids.iter() {
+                        if record_segment_reader
+                            .data_exists_for_user_id(user_id)
+                            .await?
+                        {
+                            allowed_offset_ids.insert(
+                                record_segment_reader
+                                    .get_offset_id_for_user_id(user_id)
+                                    .await?,
+                            );
+                        }
+                    }
+                    Some(allowed_offset_ids)
+                }
+            };
+            let filter =
+                IndexFilter::new(allowed_offset_ids.as_ref(), Some(&disallowed_offset_ids));
+
+            let (offset_ids, scores) = sparse_vector_segment_reader
+                .query(&input.query, input.k, &filter)
+                .await?;
+            for (offset_id, score) in offset_ids.into_iter().zip(scores) {
+                let user_id = record_segment_reader
+                    .get_user_id_for_offset_id(offset_id)
+                    .await?;
+                results.push((user_id.to_string(), score));
+            }
+        }
+
+        // Largest first, ties broken by user id so that results are stable.
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        results.truncate(input.k);
+
+        let (user_ids, scores) = results.into_iter().unzip();
+        Ok(SparseKnnOutput { user_ids, scores })
+    }
+}
@@ -1,9 +1,11 @@
+use crate::errors::ChromaError;
 use crate::segment::LogMaterializer;
 use crate::segment::SegmentWriter;
 use crate::{
     execution::{data::data_chunk::Chunk, operator::Operator},
     segment::{
-        distributed_hnsw_segment::DistributedHNSWSegmentWriter, record_segment::RecordSegmentWriter,
+        metadata_segment::MetadataSegmentWriter, record_segment::RecordSegmentWriter,
+        sparse_vector_segment::SparseVectorSegmentWriter, vector_segment::VectorSegmentWriter,
     },
     types::LogRecord,
 };
@@ -21,19 +23,26 @@ impl WriteSegmentsOperator {
 #[derive(Debug)]
 pub struct WriteSegmentsInput {
     record_segment_writer: RecordSegmentWriter,
-    hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+    vector_segment_writer: VectorSegmentWriter,
+    metadata_segment_writer: MetadataSegmentWriter,
+    // Only collections with a sparse vector segment have a writer for it
+    sparse_vector_segment_writer: Option<SparseVectorSegmentWriter>,
     chunk: Chunk<LogRecord>,
 }
 
 impl<'me> WriteSegmentsInput {
     pub fn new(
         record_segment_writer: RecordSegmentWriter,
-        hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+        vector_segment_writer: VectorSegmentWriter,
+        metadata_segment_writer: MetadataSegmentWriter,
+        sparse_vector_segment_writer: Option<SparseVectorSegmentWriter>,
         chunk: Chunk<LogRecord>,
     ) -> Self {
         WriteSegmentsInput {
             record_segment_writer,
-            hnsw_segment_writer,
+            vector_segment_writer,
+            metadata_segment_writer,
+            sparse_vector_segment_writer,
             chunk,
         }
     }
@@ -42,22 +51,39 @@ impl<'me> WriteSegmentsInput {
 #[derive(Debug)]
 pub struct WriteSegmentsOutput {
     pub(crate) record_segment_writer: RecordSegmentWriter,
-    pub(crate) hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+    pub(crate) vector_segment_writer: VectorSegmentWriter,
+    pub(crate) metadata_segment_writer: MetadataSegmentWriter,
+    pub(crate) sparse_vector_segment_writer: Option<SparseVectorSegmentWriter>,
 }
 
 #[async_trait]
 impl Operator<WriteSegmentsInput, WriteSegmentsOutput> for WriteSegmentsOperator {
-    type Error = ();
+    type Error = Box<dyn ChromaError>;
 
     async fn run(&self, input: &WriteSegmentsInput) -> Result<WriteSegmentsOutput, Self::Error> {
         println!("Materializing N Records: {:?}", input.chunk.len());
-        let res = input.record_segment_writer.materialize(&input.chunk).await;
+        let res = input
+            .record_segment_writer
+            .materialize(&input.chunk)
+            .await?;
         println!("Materialized N Records: {:?}", res.len());
-        input.hnsw_segment_writer.apply_materialized_log_chunk(res);
-        println!("Applied Materialized Records to HNSW Segment");
+        input
+            .metadata_segment_writer
+            .apply_materialized_log_chunk(res.clone())?;
+        println!("Applied Materialized Records to Metadata Segment");
+        if let Some(sparse_vector_segment_writer) = &input.sparse_vector_segment_writer {
+            sparse_vector_segment_writer.apply_materialized_log_chunk(res.clone())?;
+            println!("Applied Materialized Records to Sparse Vector Segment");
+        }
+        input
+            .vector_segment_writer
+            .apply_materialized_log_chunk(res)?;
+        println!("Applied Materialized Records to Vector Segment");
         Ok(WriteSegmentsOutput {
             record_segment_writer: input.record_segment_writer.clone(),
-            hnsw_segment_writer: input.hnsw_segment_writer.clone(),
+            vector_segment_writer: input.vector_segment_writer.clone(),
+            metadata_segment_writer: input.metadata_segment_writer.clone(),
+            sparse_vector_segment_writer: input.sparse_vector_segment_writer.clone(),
         })
     }
 }
@@ -22,10 +22,13 @@ use crate::execution::operators::write_segments::WriteSegmentsInput;
 use crate::execution::operators::write_segments::WriteSegmentsOperator;
 use crate::execution::operators::write_segments::WriteSegmentsOutput;
 use crate::index::hnsw_provider::HnswIndexProvider;
+use crate::index::pq_provider::PqIndexProvider;
 use crate::log::log::Log;
 use crate::log::log::PullLogsError;
-use crate::segment::distributed_hnsw_segment::DistributedHNSWSegmentWriter;
+use crate::segment::metadata_segment::MetadataSegmentWriter;
 use crate::segment::record_segment::RecordSegmentWriter;
+use crate::segment::sparse_vector_segment::SparseVectorSegmentWriter;
+use crate::segment::vector_segment::{is_vector_segment_type, VectorSegmentWriter};
 use crate::sysdb::sysdb::GetCollectionsError;
 use crate::sysdb::sysdb::GetSegmentsError;
 use crate::sysdb::sysdb::SysDb;
@@ -83,6 +86,7 @@ pub struct CompactOrchestrator {
     sysdb: Box<dyn SysDb>,
     blockfile_provider: BlockfileProvider,
     hnsw_index_provider: HnswIndexProvider,
+    pq_index_provider: PqIndexProvider,
     // State we hold across the execution
     pulled_log_offset: Option<i64>,
     // Dispatcher
@@ -102,16 +106,22 @@ enum GetSegmentWritersError {
     SysDbGetSegmentsError(#[from] GetSegmentsError),
     #[error("Error creating Record Segment Writer")]
     RecordSegmentWriterError,
-    #[error("Error creating HNSW Segment Writer")]
-    HnswSegmentWriterError,
+    #[error("Error creating Vector Segment Writer")]
+    VectorSegmentWriterError,
     #[error("No record segment found for collection")]
     NoRecordSegmentFound,
     #[error("Collection not found")]
     CollectionNotFound,
     #[error("Error getting collection")]
     GetCollectionError(#[from] GetCollectionsError),
-    #[error("No hnsw segment found for collection")]
-    NoHnswSegmentFound,
+    #[error("No vector segment found for collection")]
+    NoVectorSegmentFound,
+    #[error("Error creating Metadata Segment Writer")]
+    MetadataSegmentWriterError,
+    #[error("No metadata segment found for collection")]
+    NoMetadataSegmentFound,
+    #[error("Error creating Sparse Vector Segment Writer")]
+    SparseVectorSegmentWriterError,
 }
 
 impl ChromaError for GetSegmentWritersError {
@@ -137,6 +147,7 @@ impl CompactOrchestrator {
         sysdb: Box<dyn SysDb>,
         blockfile_provider: BlockfileProvider,
         hnsw_index_provider: HnswIndexProvider,
+        pq_index_provider: PqIndexProvider,
         dispatcher: Box<dyn Receiver<TaskMessage>>,
         result_channel: Option<
             tokio::sync::oneshot::Sender<Result<CompactionResponse, Box<dyn ChromaError>>>,
@@ -152,6 +163,7 @@ impl CompactOrchestrator {
             sysdb,
             blockfile_provider,
             hnsw_index_provider,
+            pq_index_provider,
             pulled_log_offset: None,
             dispatcher,
             num_write_tasks: 0,
@@ -219,12 +231,17 @@ impl CompactOrchestrator {
     async fn write(
         &mut self,
         partitions: Vec<Chunk<LogRecord>>,
-        self_address: Box<dyn Receiver<TaskResult<WriteSegmentsOutput, ()>>>,
+        self_address: Box<dyn Receiver<TaskResult<WriteSegmentsOutput, Box<dyn ChromaError>>>>,
     ) {
         self.state = ExecutionState::Write;
 
         let writer_res = self.get_segment_writers().await;
-        let (record_segment_writer, hnsw_segment_writer) = match writer_res {
+        let (
+            record_segment_writer,
+            vector_segment_writer,
+            metadata_segment_writer,
+            sparse_vector_segment_writer,
+        ) = match writer_res {
             Ok(writers) => writers,
             Err(e) => {
                 // Log an error and return
@@ -237,7 +254,9 @@ impl CompactOrchestrator {
             let operator = WriteSegmentsOperator::new();
             let input = WriteSegmentsInput::new(
                 record_segment_writer.clone(),
-                hnsw_segment_writer.clone(),
+                vector_segment_writer.clone(),
+                metadata_segment_writer.clone(),
+                sparse_vector_segment_writer.clone(),
                 parition.clone(),
             );
             let task = wrap(operator, input, self_address.clone());
@@ -253,13 +272,20 @@ impl CompactOrchestrator {
     async fn flush_s3(
         &mut self,
         record_segment_writer: RecordSegmentWriter,
-        hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+        vector_segment_writer: VectorSegmentWriter,
+        metadata_segment_writer: MetadataSegmentWriter,
+        sparse_vector_segment_writer: Option<SparseVectorSegmentWriter>,
         self_address: Box<dyn Receiver<TaskResult<FlushS3Output, Box<dyn ChromaError>>>>,
     ) {
         self.state = ExecutionState::Flush;
 
         let operator = FlushS3Operator::new();
-        let input = FlushS3Input::new(record_segment_writer, hnsw_segment_writer);
+        let input = FlushS3Input::new(
+            record_segment_writer,
+            vector_segment_writer,
+            metadata_segment_writer,
+            sparse_vector_segment_writer,
+        );
 
         let task = wrap(operator, input, self_address);
         match self.dispatcher.send(task, Some(Span::current())).await {
@@ -299,8 +325,15 @@ impl CompactOrchestrator {
 
     async fn get_segment_writers(
         &mut self,
-    ) -> Result<(RecordSegmentWriter, Box<DistributedHNSWSegmentWriter>), Box<dyn ChromaError>>
-    {
+    ) -> Result<
+        (
+            RecordSegmentWriter,
+            VectorSegmentWriter,
+            MetadataSegmentWriter,
+            Option<SparseVectorSegmentWriter>,
+        ),
+        Box<dyn ChromaError>,
+    > {
         // Care should be taken to use the same writers across the compaction process
         // Since the segment writers are stateful, we should not create new writers for each partition
         // Nor should we create new writers across different tasks
@@ -350,7 +383,7 @@ impl CompactOrchestrator {
 
         println!("Record Segment Writer created");
 
-        // Create a hnsw segment writer
+        // Create a vector segment writer
         let collection_res = self
             .sysdb
             .get_collections(Some(self.collection_id), None, None, None)
@@ -369,32 +402,82 @@ impl CompactOrchestrator {
         };
         let collection = &collection_res[0];
 
-        let hnsw_segment = segments
+        let vector_segment = segments
             .iter()
-            .find(|segment| segment.r#type == SegmentType::HnswDistributed);
-        if hnsw_segment.is_none() {
-            return Err(Box::new(GetSegmentWritersError::NoHnswSegmentFound));
+            .find(|segment| is_vector_segment_type(&segment.r#type));
+        if vector_segment.is_none() {
+            return Err(Box::new(GetSegmentWritersError::NoVectorSegmentFound));
         }
-        let hnsw_segment = hnsw_segment.unwrap();
+        let vector_segment = vector_segment.unwrap();
         let dimension = collection
             .dimension
             .expect("Dimension is required in the compactor");
 
-        let hnsw_segment_writer = match DistributedHNSWSegmentWriter::from_segment(
-            hnsw_segment,
+        let vector_segment_writer = match VectorSegmentWriter::from_segment(
+            vector_segment,
             dimension as usize,
             self.hnsw_index_provider.clone(),
+            self.pq_index_provider.clone(),
+            &self.blockfile_provider,
+            record_segment,
         )
         .await
         {
             Ok(writer) => writer,
             Err(e) => {
-                println!("Error creating HNSW Segment Writer: {:?}", e);
-                return Err(Box::new(GetSegmentWritersError::HnswSegmentWriterError));
+                println!("Error creating Vector Segment Writer: {:?}", e);
+                return Err(Box::new(GetSegmentWritersError::VectorSegmentWriterError));
             }
         };
 
-        Ok((record_segment_writer, hnsw_segment_writer))
+        // Create a metadata segment writer, forking the segment's blockfiles
+        // so that updates and deletes rewrite the existing indices.
+        let metadata_segment = segments
+            .iter()
+            .find(|segment| segment.r#type == SegmentType::BlockfileMetadata);
+        if metadata_segment.is_none() {
+            return Err(Box::new(GetSegmentWritersError::NoMetadataSegmentFound));
+        }
+        let metadata_segment = metadata_segment.unwrap();
+        let metadata_segment_writer =
+            match MetadataSegmentWriter::from_segment(metadata_segment, &self.blockfile_provider)
+                .await
+            {
+                Ok(writer) => writer,
+                Err(e) => {
+                    println!("Error creating Metadata Segment Writer: {:?}", e);
+                    return Err(Box::new(GetSegmentWritersError::MetadataSegmentWriterError));
+                }
+            };
+
+        // Create a sparse vector segment writer if the collection has the segment
+        let sparse_vector_segment_writer = match segments
+            .iter()
+            .find(|segment| segment.r#type == SegmentType::SparseVectorDistributed)
+        {
+            Some(sparse_vector_segment) => match SparseVectorSegmentWriter::from_segment(
+                sparse_vector_segment,
+                &self.blockfile_provider,
+            )
+            .await
+            {
+                Ok(writer) => Some(writer),
+                Err(e) => {
+                    println!("Error creating Sparse Vector Segment Writer: {:?}", e);
+                    return Err(Box::new(
+                        GetSegmentWritersError::SparseVectorSegmentWriterError,
+                    ));
+                }
+            },
+            None => None,
+        };
+
+        Ok((
+            record_segment_writer,
+            vector_segment_writer,
+            metadata_segment_writer,
+            sparse_vector_segment_writer,
+        ))
     }
 
     pub(crate) async fn run(mut self) -> Result<CompactionResponse, Box<dyn ChromaError>> {
@@ -493,10 +576,10 @@ impl Handler<TaskResult<PartitionOutput, PartitionError>> for CompactOrchestrato
 }
 
 #[async_trait]
-impl Handler<TaskResult<WriteSegmentsOutput, ()>> for CompactOrchestrator {
+impl Handler<TaskResult<WriteSegmentsOutput, Box<dyn ChromaError>>> for CompactOrchestrator {
     async fn handle(
         &mut self,
-        message: TaskResult<WriteSegmentsOutput, ()>,
+        message: TaskResult<WriteSegmentsOutput, Box<dyn ChromaError>>,
         _ctx: &crate::system::ComponentContext<CompactOrchestrator>,
     ) {
         let message = message.into_inner();
@@ -507,14 +590,24 @@ impl Handler<TaskResult<WriteSegmentsOutput, ()>> for CompactOrchestrator {
                 output
             }
             Err(e) => {
-                // Log an error
+                // The first failed write fails the compaction
+                let result_channel = match self.result_channel.take() {
+                    Some(tx) => tx,
+                    None => {
+                        // Log an error
+                        return;
+                    }
+                };
+                let _ = result_channel.send(Err(e));
                 return;
             }
         };
         if self.num_write_tasks == 0 {
             self.flush_s3(
                 output.record_segment_writer,
-                output.hnsw_segment_writer,
+                output.vector_segment_writer,
+                output.metadata_segment_writer,
+                output.sparse_vector_segment_writer,
                 _ctx.sender.as_receiver(),
             )
             .await;
@@ -8,22 +8,38 @@ use crate::execution::operator::TaskResult;
 use crate::execution::operators::brute_force_knn::{
     BruteForceKnnOperator, BruteForceKnnOperatorInput, BruteForceKnnOperatorOutput,
 };
+use crate::execution::operators::full_text_search::{
+    FullTextSearchError, FullTextSearchInput, FullTextSearchOperator, FullTextSearchOutput,
+};
 use crate::execution::operators::hnsw_knn::{
     HnswKnnOperator, HnswKnnOperatorInput, HnswKnnOperatorOutput,
 };
 use crate::execution::operators::merge_knn_results::{
-    MergeKnnResultsOperator, MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput,
+    MergeKnnRerank, MergeKnnResultsOperator, MergeKnnResultsOperatorInput,
+    MergeKnnResultsOperatorOutput,
+};
+use crate::execution::operators::merge_max_sim_results::{
+    MergeMaxSimResultsOperator, MergeMaxSimResultsOperatorInput, MergeMaxSimResultsOperatorOutput,
 };
 use crate::execution::operators::pull_log::PullLogsOutput;
+pub(crate) use crate::execution::operators::rank_fusion::{FusionMethod, DEFAULT_RANK_CONSTANT};
+use crate::execution::operators::rank_fusion::{
+    RankFusionInput, RankFusionOperator, RankFusionOutput,
+};
+use crate::execution::operators::sparse_knn::{
+    SparseKnnError, SparseKnnInput, SparseKnnOperator, SparseKnnOutput,
+};
 use crate::index::hnsw_provider::HnswIndexProvider;
-use crate::index::IndexConfig;
+use crate::index::pq_provider::PqIndexProvider;
+use crate::index::{HnswRerankConfig, HnswSearchParams, IndexConfig};
 use crate::log::log::PullLogsError;
-use crate::segment::distributed_hnsw_segment::{
-    DistributedHNSWSegmentFromSegmentError, DistributedHNSWSegmentReader,
-};
+use crate::segment::schema::HnswSegmentConfig;
+use crate::segment::vector_segment::{is_vector_segment_type, VectorSegmentReader};
 use crate::sysdb::sysdb::{GetCollectionsError, GetSegmentsError, SysDb};
 use crate::system::{ComponentContext, System};
-use crate::types::{Collection, LogRecord, Segment, SegmentType, VectorQueryResult};
+use crate::types::{
+    Collection, HybridQueryResult, LogRecord, Segment, SegmentType, SparseVector, VectorQueryResult,
+};
 use crate::{
     log::log::Log,
     system::{Component, Handler, Receiver},
@@ -50,6 +66,24 @@ understand. We can always add more abstraction later if we need it.
                                │                      │
                                └───► HNSW ────────────┘
 
+```
+A hybrid query additionally runs a full text search, or a sparse vector search, next to
+the KNN queries, and fuses each merged KNN result with its result before finishing.
+```plaintext
+
+                               ┌───► Brute Force ─────┐
+                               │                      │
+  Pending ─► PullLogs ─► Group ├───► HNSW ────────────┴─► MergeResults ─┐
+                               │                                        ├─► Fusion ─► Finished
+                               └───► Full Text / Sparse Search ─────────┘
+
+```
+A MaxSim query searches a multi-vector hnsw segment with each query vector, and scores
+the hits of all query vectors together with the log in a single merge.
+```plaintext
+
+  Pending ─► PullLogs ─► HNSW ─► MergeResults (MaxSim) ─► Finished
+
 ```
 */
 #[derive(Debug)]
@@ -59,6 +93,7 @@ enum ExecutionState {
     Partition,
     QueryKnn, // This is both the Brute force and HNSW query state
     MergeResults,
+    Fusion,
     Finished,
 }
 
@@ -74,10 +109,16 @@ enum HnswSegmentQueryError {
     GetCollectionError(#[from] GetCollectionsError),
     #[error("Record segment not found for collection: {0}")]
     RecordSegmentNotFound(Uuid),
+    #[error("Metadata segment not found for collection: {0}")]
+    MetadataSegmentNotFound(Uuid),
+    #[error("Sparse vector segment not found for collection: {0}")]
+    SparseVectorSegmentNotFound(Uuid),
     #[error("HNSW segment has no collection")]
     HnswSegmentHasNoCollection,
     #[error("Collection has no dimension set")]
     CollectionHasNoDimension,
+    #[error("MaxSim queries need a segment indexing multi-vectors, segment {0} does not")]
+    NotMultiVectorSegment(Uuid),
 }
 
 impl ChromaError for HnswSegmentQueryError {
@@ -88,8 +129,11 @@ impl ChromaError for HnswSegmentQueryError {
             HnswSegmentQueryError::CollectionNotFound(_) => ErrorCodes::NotFound,
             HnswSegmentQueryError::GetCollectionError(_) => ErrorCodes::Internal,
             HnswSegmentQueryError::RecordSegmentNotFound(_) => ErrorCodes::NotFound,
+            HnswSegmentQueryError::MetadataSegmentNotFound(_) => ErrorCodes::NotFound,
+            HnswSegmentQueryError::SparseVectorSegmentNotFound(_) => ErrorCodes::NotFound,
             HnswSegmentQueryError::HnswSegmentHasNoCollection => ErrorCodes::InvalidArgument,
             HnswSegmentQueryError::CollectionHasNoDimension => ErrorCodes::InvalidArgument,
+            HnswSegmentQueryError::NotMultiVectorSegment(_) => ErrorCodes::InvalidArgument,
         }
     }
 }
@@ -105,23 +149,43 @@ pub(crate) struct HnswQueryOrchestrator {
     allowed_ids: Arc<[String]>,
     include_embeddings: bool,
     hnsw_segment_id: Uuid,
+    // Hybrid query state, either the full text query or the sparse query is set for
+    // hybrid queries. k is then the number of candidates retrieved by each search,
+    // and fusion_k the number of fused results.
+    full_text_query: Option<String>,
+    sparse_query: Option<SparseVector>,
+    fusion_method: FusionMethod,
+    fusion_k: usize,
+    search_params: HnswSearchParams,
+    // MaxSim query state, the logs are kept to be scored with the hnsw results
+    max_sim: bool,
+    logs: Option<Chunk<LogRecord>>,
     // State fetched or created for query execution
     hnsw_segment: Option<Segment>,
     record_segment: Option<Segment>,
+    metadata_segment: Option<Segment>,
+    sparse_vector_segment: Option<Segment>,
     collection: Option<Collection>,
     index_config: Option<IndexConfig>,
+    rerank: Option<HnswRerankConfig>,
     // query_vectors index to the result
     hnsw_result_offset_ids: HashMap<usize, Vec<usize>>,
     hnsw_result_distances: HashMap<usize, Vec<f32>>,
     brute_force_result_user_ids: HashMap<usize, Vec<String>>,
     brute_force_result_distances: HashMap<usize, Vec<f32>>,
     brute_force_result_embeddings: HashMap<usize, Vec<Vec<f32>>>,
+    merge_results: HashMap<usize, MergeKnnResultsOperatorOutput>,
+    // The full text or sparse result is shared by all query vectors
+    text_result_user_ids: Option<Vec<String>>,
+    text_result_scores: Option<Vec<f32>>,
     // Task id to query_vectors index
     hnsw_task_id_to_query_index: HashMap<Uuid, usize>,
     brute_force_task_id_to_query_index: HashMap<Uuid, usize>,
     merge_task_id_to_query_index: HashMap<Uuid, usize>,
+    fusion_task_id_to_query_index: HashMap<Uuid, usize>,
     // Result state
     results: Option<Vec<Vec<VectorQueryResult>>>,
+    hybrid_results: HashMap<usize, Vec<HybridQueryResult>>,
     // State machine management
     merge_dependency_count: u32,
     finish_dependency_count: u32,
@@ -130,11 +194,15 @@ pub(crate) struct HnswQueryOrchestrator {
     sysdb: Box<dyn SysDb>,
     dispatcher: Box<dyn Receiver<TaskMessage>>,
     hnsw_index_provider: HnswIndexProvider,
+    pq_index_provider: PqIndexProvider,
     blockfile_provider: BlockfileProvider,
     // Result channel
     result_channel: Option<
         tokio::sync::oneshot::Sender<Result<Vec<Vec<VectorQueryResult>>, Box<dyn ChromaError>>>,
     >,
+    hybrid_result_channel: Option<
+        tokio::sync::oneshot::Sender<Result<Vec<Vec<HybridQueryResult>>, Box<dyn ChromaError>>>,
+    >,
 }
 
 impl HnswQueryOrchestrator {
@@ -148,6 +216,7 @@ impl HnswQueryOrchestrator {
         log: Box<dyn Log>,
         sysdb: Box<dyn SysDb>,
         hnsw_index_provider: HnswIndexProvider,
+        pq_index_provider: PqIndexProvider,
         blockfile_provider: BlockfileProvider,
         dispatcher: Box<dyn Receiver<TaskMessage>>,
     ) -> Self {
@@ -170,28 +239,96 @@ impl HnswQueryOrchestrator {
             allowed_ids: allowed_ids.into(),
             include_embeddings,
             hnsw_segment_id: segment_id,
+            full_text_query: None,
+            sparse_query: None,
+            fusion_method: FusionMethod::ReciprocalRank {
+                rank_constant: DEFAULT_RANK_CONSTANT,
+            },
+            fusion_k: 0,
+            search_params: HnswSearchParams::default(),
+            max_sim: false,
+            logs: None,
             hnsw_segment: None,
             record_segment: None,
+            metadata_segment: None,
+            sparse_vector_segment: None,
             collection: None,
             index_config: None,
+            rerank: None,
             hnsw_result_offset_ids: HashMap::new(),
             hnsw_result_distances: HashMap::new(),
             brute_force_result_user_ids: HashMap::new(),
             brute_force_result_distances: HashMap::new(),
             brute_force_result_embeddings: HashMap::new(),
+            merge_results: HashMap::new(),
+            text_result_user_ids: None,
+            text_result_scores: None,
             hnsw_task_id_to_query_index: HashMap::new(),
             brute_force_task_id_to_query_index: HashMap::new(),
             merge_task_id_to_query_index: HashMap::new(),
+            fusion_task_id_to_query_index: HashMap::new(),
             results,
+            hybrid_results: HashMap::new(),
             log,
             sysdb,
             dispatcher,
             hnsw_index_provider,
+            pq_index_provider,
             blockfile_provider,
             result_channel: None,
+            hybrid_result_channel: None,
         }
     }
 
+    /// Make this a hybrid query, which fuses the nearest neighbors of each query vector
+    /// with the full text matches of the document query. The k passed to the constructor
+    /// is the number of candidates each search retrieves, k is the number of fused results.
+    pub(crate) fn set_full_text_query(
+        &mut self,
+        full_text_query: String,
+        fusion_method: FusionMethod,
+        k: usize,
+    ) {
+        self.full_text_query = Some(full_text_query);
+        self.fusion_method = fusion_method;
+        self.fusion_k = k;
+    }
+
+    /// Make this a hybrid query, which fuses the nearest neighbors of each query vector
+    /// with the records whose sparse embeddings best match the sparse query. The k passed
+    /// to the constructor is the number of candidates each search retrieves, k is the
+    /// number of fused results.
+    pub(crate) fn set_sparse_query(
+        &mut self,
+        sparse_query: SparseVector,
+        fusion_method: FusionMethod,
+        k: usize,
+    ) {
+        self.sparse_query = Some(sparse_query);
+        self.fusion_method = fusion_method;
+        self.fusion_k = k;
+    }
+
+    fn is_hybrid(&self) -> bool {
+        self.full_text_query.is_some() || self.sparse_query.is_some()
+    }
+
+    /// Search hnsw segments with the given parameters instead of those of the segment.
+    pub(crate) fn set_search_params(&mut self, search_params: HnswSearchParams) {
+        self.search_params = search_params;
+    }
+
+    /// Make this a MaxSim query, which scores records by the late interaction of all the
+    /// query vectors with their multi-vector embeddings. The query has a single result.
+    /// The segment must index multi-vectors.
+    pub(crate) fn set_max_sim(&mut self) {
+        self.max_sim = true;
+        // Only the hnsw segment is queried per query vector, and its results are
+        // merged together
+        self.merge_dependency_count = self.query_vectors.len() as u32;
+        self.finish_dependency_count = 1;
+    }
+
     async fn pull_logs(
         &mut self,
         self_address: Box<dyn Receiver<TaskResult<PullLogsOutput, PullLogsError>>>,
@@ -249,7 +386,7 @@ impl HnswQueryOrchestrator {
             let bf_input = BruteForceKnnOperatorInput {
                 data: logs.clone(),
                 query: query_vector.clone(),
-                k: self.k as usize,
+                k: self.num_candidates(),
                 distance_metric: distance_function.clone(),
             };
             let operator = Box::new(BruteForceKnnOperator {});
@@ -280,33 +417,46 @@ impl HnswQueryOrchestrator {
             .expect("Invariant violation. Collection dimension is not set");
 
         // Fetch the data needed for the duration of the query - The HNSW Segment, The record Segment and the Collection
-        let hnsw_segment_reader = match DistributedHNSWSegmentReader::from_segment(
+        let hnsw_segment_reader = match VectorSegmentReader::from_segment(
             // These unwraps are safe because we have already checked that the segments are set in the orchestrator on_start
             hnsw_segment,
             dimensionality as usize,
             self.hnsw_index_provider.clone(),
+            self.pq_index_provider.clone(),
+            &self.blockfile_provider,
         )
         .await
         {
             Ok(reader) => reader,
-            Err(e) => match *e {
-                DistributedHNSWSegmentFromSegmentError::Uninitialized => {
-                    // no task, decrement the merge dependency count and return
-                    // with an empty result
-                    for (i, _) in self.query_vectors.iter().enumerate() {
-                        self.merge_dependency_count -= 1;
-                        self.hnsw_result_distances.insert(i, Vec::new());
-                        self.hnsw_result_offset_ids.insert(i, Vec::new());
-                    }
-                    return;
+            Err(e) if e.is_uninitialized() => {
+                // no task, decrement the merge dependency count and return
+                // with an empty result
+                for (i, _) in self.query_vectors.iter().enumerate() {
+                    self.merge_dependency_count -= 1;
+                    self.hnsw_result_distances.insert(i, Vec::new());
+                    self.hnsw_result_offset_ids.insert(i, Vec::new());
                 }
-                _ => {
-                    self.terminate_with_error(e, ctx);
-                    return;
+                // A MaxSim query has no brute force results to wait for
+                if self.merge_dependency_count == 0 {
+                    self.merge_results(ctx).await;
                 }
-            },
+                return;
+            }
+            Err(e) => {
+                self.terminate_with_error(e, ctx);
+                return;
+            }
         };
         println!("Created HNSW Segment Reader: {:?}", hnsw_segment_reader);
+        if self.max_sim && !hnsw_segment_reader.multi_vector() {
+            self.terminate_with_error(
+                Box::new(HnswSegmentQueryError::NotMultiVectorSegment(
+                    self.hnsw_segment_id,
+                )),
+                ctx,
+            );
+            return;
+        }
 
         let record_segment = self
             .record_segment
@@ -319,11 +469,12 @@ impl HnswQueryOrchestrator {
             let input = HnswKnnOperatorInput {
                 segment: hnsw_segment_reader.clone(),
                 query: query_vector.clone(),
-                k: self.k as usize,
+                k: self.num_candidates(),
                 record_segment: record_segment.clone(),
                 blockfile_provider: self.blockfile_provider.clone(),
                 allowed_ids: self.allowed_ids.clone(),
                 logs: logs.clone(),
+                search_params: self.search_params,
             };
             let task = wrap(operator, input, ctx.sender.as_receiver());
             self.hnsw_task_id_to_query_index.insert(task.id(), i);
@@ -337,13 +488,71 @@ impl HnswQueryOrchestrator {
         }
     }
 
+    // The number of candidates to take from the segment and the log, rerank_factor * k
+    // if the candidates are reranked when merged
+    fn num_candidates(&self) -> usize {
+        match &self.rerank {
+            Some(rerank) => self.k as usize * rerank.rerank_factor,
+            None => self.k as usize,
+        }
+    }
+
     async fn merge_results(&mut self, ctx: &ComponentContext<Self>) {
         self.state = ExecutionState::MergeResults;
+        if self.max_sim {
+            self.merge_max_sim_results(ctx).await;
+            return;
+        }
         for i in 0..self.query_vectors.len() {
             self.merge_results_for_index(ctx, i).await;
         }
     }
 
+    async fn merge_max_sim_results(&mut self, ctx: &ComponentContext<Self>) {
+        let record_segment = self
+            .record_segment
+            .as_ref()
+            .expect("Invariant violation. Record Segment is not set");
+        let distance_function = self
+            .index_config
+            .as_ref()
+            .expect("Invariant violation. Index config is not set")
+            .distance_function
+            .clone();
+        let logs = self
+            .logs
+            .take()
+            .expect("Invariant violation. Logs are not set for a MaxSim query");
+
+        let mut hnsw_result_offset_ids = Vec::with_capacity(self.query_vectors.len());
+        for i in 0..self.query_vectors.len() {
+            hnsw_result_offset_ids.push(self.hnsw_result_offset_ids.remove(&i).expect(
+                "Invariant violation. HNSW result offset ids are not set for query vector index",
+            ));
+        }
+
+        let operator = Box::new(MergeMaxSimResultsOperator {});
+        let input = MergeMaxSimResultsOperatorInput::new(
+            self.query_vectors.clone(),
+            hnsw_result_offset_ids,
+            logs,
+            self.allowed_ids.clone(),
+            distance_function,
+            self.include_embeddings,
+            self.k as usize,
+            record_segment.clone(),
+            self.blockfile_provider.clone(),
+        );
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        match self.dispatcher.send(task, Some(Span::current())).await {
+            Ok(_) => (),
+            Err(e) => {
+                // Log an error
+                println!("Error sending Merge MaxSim task: {:?}", e);
+            }
+        }
+    }
+
     async fn merge_results_for_index(
         &mut self,
         ctx: &ComponentContext<Self>,
@@ -374,6 +583,11 @@ impl HnswQueryOrchestrator {
             .brute_force_result_embeddings
             .remove(&query_vector_index);
 
+        let rerank = self.rerank.as_ref().map(|rerank| MergeKnnRerank {
+            query: self.query_vectors[query_vector_index].clone(),
+            distance_function: rerank.distance_function.clone(),
+        });
+
         let operator = Box::new(MergeKnnResultsOperator {});
         let input = MergeKnnResultsOperatorInput::new(
             hnsw_result_offset_ids,
@@ -385,6 +599,7 @@ impl HnswQueryOrchestrator {
             self.k as usize,
             record_segment.clone(),
             self.blockfile_provider.clone(),
+            rerank,
         );
 
         let task = wrap(operator, input, ctx.sender.as_receiver());
@@ -399,6 +614,130 @@ impl HnswQueryOrchestrator {
         }
     }
 
+    async fn full_text_search(&mut self, logs: Chunk<LogRecord>, ctx: &ComponentContext<Self>) {
+        let full_text_query = match &self.full_text_query {
+            Some(full_text_query) => full_text_query.clone(),
+            None => return,
+        };
+        let metadata_segment = self
+            .metadata_segment
+            .as_ref()
+            .expect("Invariant violation. Metadata Segment is not set");
+        let record_segment = self
+            .record_segment
+            .as_ref()
+            .expect("Invariant violation. Record Segment is not set");
+
+        let operator = FullTextSearchOperator::new();
+        let input = FullTextSearchInput::new(
+            logs,
+            metadata_segment.clone(),
+            record_segment.clone(),
+            self.blockfile_provider.clone(),
+            full_text_query,
+            self.k as usize,
+        );
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        match self.dispatcher.send(task, Some(Span::current())).await {
+            Ok(_) => (),
+            Err(e) => {
+                // Log an error
+                println!("Error sending Full Text Search task: {:?}", e);
+            }
+        }
+    }
+
+    async fn sparse_search(&mut self, logs: Chunk<LogRecord>, ctx: &ComponentContext<Self>) {
+        let sparse_query = match &self.sparse_query {
+            Some(sparse_query) => sparse_query.clone(),
+            None => return,
+        };
+        let sparse_vector_segment = self
+            .sparse_vector_segment
+            .as_ref()
+            .expect("Invariant violation. Sparse Vector Segment is not set");
+        let record_segment = self
+            .record_segment
+            .as_ref()
+            .expect("Invariant violation. Record Segment is not set");
+
+        let operator = SparseKnnOperator::new();
+        let input = SparseKnnInput::new(
+            logs,
+            sparse_vector_segment.clone(),
+            record_segment.clone(),
+            self.blockfile_provider.clone(),
+            sparse_query,
+            self.k as usize,
+            self.allowed_ids.clone(),
+        );
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        match self.dispatcher.send(task, Some(Span::current())).await {
+            Ok(_) => (),
+            Err(e) => {
+                // Log an error
+                println!("Error sending Sparse KNN task: {:?}", e);
+            }
+        }
+    }
+
+    // Store the result of the full text or sparse search and fuse the KNN results that
+    // were merged before it came in
+    async fn set_text_result(
+        &mut self,
+        user_ids: Vec<String>,
+        scores: Vec<f32>,
+        ctx: &ComponentContext<Self>,
+    ) {
+        self.text_result_user_ids = Some(user_ids);
+        self.text_result_scores = Some(scores);
+        let merged_query_indices: Vec<usize> = self.merge_results.keys().cloned().collect();
+        for query_index in merged_query_indices {
+            self.fuse_results_for_index(ctx, query_index).await;
+        }
+    }
+
+    async fn fuse_results_for_index(
+        &mut self,
+        ctx: &ComponentContext<Self>,
+        query_vector_index: usize,
+    ) {
+        self.state = ExecutionState::Fusion;
+        let merge_results = self
+            .merge_results
+            .remove(&query_vector_index)
+            .expect("Invariant violation. Merge results are not set for query vector index");
+        let text_result_user_ids = self
+            .text_result_user_ids
+            .as_ref()
+            .expect("Invariant violation. Text result user ids are not set");
+        let text_result_scores = self
+            .text_result_scores
+            .as_ref()
+            .expect("Invariant violation. Text result scores are not set");
+
+        let operator = RankFusionOperator::new();
+        let input = RankFusionInput::new(
+            merge_results.user_ids,
+            merge_results.distances,
+            merge_results.vectors,
+            text_result_user_ids.clone(),
+            text_result_scores.clone(),
+            self.fusion_method,
+            self.fusion_k,
+        );
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        self.fusion_task_id_to_query_index
+            .insert(task.id(), query_vector_index);
+        match self.dispatcher.send(task, Some(Span::current())).await {
+            Ok(_) => (),
+            Err(e) => {
+                // Log an error
+                println!("Error sending Rank Fusion task: {:?}", e);
+            }
+        }
+    }
+
     async fn get_hnsw_segment_from_id(
         &self,
         mut sysdb: Box<dyn SysDb>,
@@ -421,7 +760,7 @@ impl HnswQueryOrchestrator {
             }
         };
 
-        if segment.r#type != SegmentType::HnswDistributed {
+        if !is_vector_segment_type(&segment.r#type) {
             return Err(Box::new(HnswSegmentQueryError::HnswSegmentNotFound(
                 *hnsw_segment_id,
             )));
@@ -491,17 +830,92 @@ impl HnswQueryOrchestrator {
         Ok(segment)
     }
 
-    fn terminate_with_error(&mut self, error: Box<dyn ChromaError>, ctx: &ComponentContext<Self>) {
-        let result_channel = self
-            .result_channel
-            .take()
-            .expect("Invariant violation. Result channel is not set.");
-        match result_channel.send(Err(error)) {
-            Ok(_) => (),
+    async fn get_sparse_vector_segment_for_collection(
+        &self,
+        mut sysdb: Box<dyn SysDb>,
+        collection_id: &Uuid,
+    ) -> Result<Segment, Box<dyn ChromaError>> {
+        let segments = sysdb
+            .get_segments(
+                None,
+                Some(SegmentType::SparseVectorDistributed.into()),
+                None,
+                Some(*collection_id),
+            )
+            .await;
+
+        let segment = match segments {
+            Ok(mut segments) => {
+                if segments.is_empty() {
+                    return Err(Box::new(
+                        HnswSegmentQueryError::SparseVectorSegmentNotFound(*collection_id),
+                    ));
+                }
+                segments.drain(..).next().unwrap()
+            }
             Err(e) => {
-                // Log an error - this implied the listener was dropped
-                println!("[HnswQueryOrchestrator] Result channel dropped before sending error");
+                return Err(Box::new(HnswSegmentQueryError::GetSegmentsError(e)));
             }
+        };
+
+        if segment.r#type != SegmentType::SparseVectorDistributed {
+            return Err(Box::new(
+                HnswSegmentQueryError::SparseVectorSegmentNotFound(*collection_id),
+            ));
+        }
+        Ok(segment)
+    }
+
+    async fn get_metadata_segment_for_collection(
+        &self,
+        mut sysdb: Box<dyn SysDb>,
+        collection_id: &Uuid,
+    ) -> Result<Segment, Box<dyn ChromaError>> {
+        let segments = sysdb
+            .get_segments(
+                None,
+                Some(SegmentType::BlockfileMetadata.into()),
+                None,
+                Some(*collection_id),
+            )
+            .await;
+
+        let segment = match segments {
+            Ok(mut segments) => {
+                if segments.is_empty() {
+                    return Err(Box::new(HnswSegmentQueryError::MetadataSegmentNotFound(
+                        *collection_id,
+                    )));
+                }
+                segments.drain(..).next().unwrap()
+            }
+            Err(e) => {
+                return Err(Box::new(HnswSegmentQueryError::GetSegmentsError(e)));
+            }
+        };
+
+        if segment.r#type != SegmentType::BlockfileMetadata {
+            return Err(Box::new(HnswSegmentQueryError::MetadataSegmentNotFound(
+                *collection_id,
+            )));
+        }
+        Ok(segment)
+    }
+
+    fn terminate_with_error(&mut self, error: Box<dyn ChromaError>, ctx: &ComponentContext<Self>) {
+        let sent = match self.hybrid_result_channel.take() {
+            Some(hybrid_result_channel) => hybrid_result_channel.send(Err(error)).is_ok(),
+            None => {
+                let result_channel = self
+                    .result_channel
+                    .take()
+                    .expect("Invariant violation. Result channel is not set.");
+                result_channel.send(Err(error)).is_ok()
+            }
+        };
+        if !sent {
+            // Log an error - this implied the listener was dropped
+            println!("[HnswQueryOrchestrator] Result channel dropped before sending error");
         }
         // Cancel the orchestrator so it stops processing
         ctx.cancellation_token.cancel();
@@ -519,6 +933,21 @@ impl HnswQueryOrchestrator {
         handle.stop();
         result.unwrap()
     }
+
+    ///  Run the orchestrator as a hybrid query and return the fused result.
+    ///  # Note
+    ///  The full text query must be set with `set_full_text_query`, or the sparse query
+    ///  with `set_sparse_query`, first.
+    pub(crate) async fn run_hybrid(
+        mut self,
+    ) -> Result<Vec<Vec<HybridQueryResult>>, Box<dyn ChromaError>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.hybrid_result_channel = Some(tx);
+        let mut handle = self.system.clone().start_component(self);
+        let result = rx.await;
+        handle.stop();
+        result.unwrap()
+    }
 }
 
 // ============== Component Implementation ==============
@@ -596,6 +1025,55 @@ impl Component for HnswQueryOrchestrator {
             }
         }
 
+        // Hamming distances may be reranked with the full vectors when merging
+        if hnsw_segment.r#type == SegmentType::HnswDistributed
+            && self
+                .index_config
+                .as_ref()
+                .map(|config| &config.distance_function)
+                == Some(&DistanceFunction::Hamming)
+        {
+            match HnswSegmentConfig::from_segment(&hnsw_segment) {
+                Ok(config) => {
+                    self.rerank = config.rerank;
+                }
+                Err(e) => {
+                    self.terminate_with_error(Box::new(e), ctx);
+                    return;
+                }
+            }
+        }
+
+        if self.full_text_query.is_some() {
+            match self
+                .get_metadata_segment_for_collection(self.sysdb.clone(), collection_id)
+                .await
+            {
+                Ok(segment) => {
+                    self.metadata_segment = Some(segment);
+                }
+                Err(e) => {
+                    self.terminate_with_error(e, ctx);
+                    return;
+                }
+            }
+        }
+
+        if self.sparse_query.is_some() {
+            match self
+                .get_sparse_vector_segment_for_collection(self.sysdb.clone(), collection_id)
+                .await
+            {
+                Ok(segment) => {
+                    self.sparse_vector_segment = Some(segment);
+                }
+                Err(e) => {
+                    self.terminate_with_error(e, ctx);
+                    return;
+                }
+            }
+        }
+
         self.record_segment = Some(record_segment);
         self.hnsw_segment = Some(hnsw_segment);
         self.collection = Some(collection);
@@ -619,8 +1097,15 @@ impl Handler<TaskResult<PullLogsOutput, PullLogsError>> for HnswQueryOrchestrato
         match message {
             Ok(pull_logs_output) => {
                 let logs = pull_logs_output.logs();
-                self.brute_force_query(logs.clone(), ctx.sender.as_receiver())
-                    .await;
+                if self.max_sim {
+                    // The log is scored with the hnsw results when they are merged
+                    self.logs = Some(logs.clone());
+                } else {
+                    self.brute_force_query(logs.clone(), ctx.sender.as_receiver())
+                        .await;
+                    self.full_text_search(logs.clone(), ctx).await;
+                    self.sparse_search(logs.clone(), ctx).await;
+                }
                 self.hnsw_segment_query(logs, ctx).await;
             }
             Err(e) => {
@@ -648,7 +1133,8 @@ impl Handler<TaskResult<BruteForceKnnOperatorOutput, ()>> for HnswQueryOrchestra
             Ok(output) => {
                 let mut user_ids = Vec::new();
                 let mut embeddings = None;
-                if self.include_embeddings {
+                // Reranking needs the embeddings of the candidates
+                if self.include_embeddings || self.rerank.is_some() {
                     embeddings = Some(Vec::new());
                 }
                 for index in output.indices {
@@ -745,14 +1231,27 @@ impl Handler<TaskResult<MergeKnnResultsOperatorOutput, Box<dyn ChromaError>>>
 
         self.state = ExecutionState::Finished;
 
-        let (mut output_ids, mut output_distances, output_vectors) = match message {
-            Ok(output) => (output.user_ids, output.distances, output.vectors),
+        let output = match message {
+            Ok(output) => output,
             Err(e) => {
                 self.terminate_with_error(e, ctx);
                 return;
             }
         };
 
+        // A hybrid query fuses the merged results with the full text or sparse result,
+        // once it is in
+        if self.is_hybrid() {
+            self.merge_results.insert(query_index, output);
+            if self.text_result_user_ids.is_some() {
+                self.fuse_results_for_index(ctx, query_index).await;
+            }
+            return;
+        }
+
+        let (mut output_ids, mut output_distances, output_vectors) =
+            (output.user_ids, output.distances, output.vectors);
+
         let mut query_results = Vec::new();
         if self.include_embeddings {
             for ((index, distance), vector) in
@@ -820,3 +1319,348 @@ impl Handler<TaskResult<MergeKnnResultsOperatorOutput, Box<dyn ChromaError>>>
         }
     }
 }
+
+#[async_trait]
+impl Handler<TaskResult<FullTextSearchOutput, FullTextSearchError>> for HnswQueryOrchestrator {
+    async fn handle(
+        &mut self,
+        message: TaskResult<FullTextSearchOutput, FullTextSearchError>,
+        ctx: &ComponentContext<Self>,
+    ) {
+        let message = message.into_inner();
+        let output = match message {
+            Ok(output) => output,
+            Err(e) => {
+                self.terminate_with_error(Box::new(e), ctx);
+                return;
+            }
+        };
+
+        let mut user_ids = Vec::with_capacity(output.user_ids.len());
+        let mut scores = Vec::with_capacity(output.scores.len());
+        for (user_id, score) in output.user_ids.into_iter().zip(output.scores) {
+            if !self.allowed_ids.is_empty() && !self.allowed_ids.contains(&user_id) {
+                continue;
+            }
+            user_ids.push(user_id);
+            scores.push(score);
+        }
+        self.set_text_result(user_ids, scores, ctx).await;
+    }
+}
+
+#[async_trait]
+impl Handler<TaskResult<SparseKnnOutput, SparseKnnError>> for HnswQueryOrchestrator {
+    async fn handle(
+        &mut self,
+        message: TaskResult<SparseKnnOutput, SparseKnnError>,
+        ctx: &ComponentContext<Self>,
+    ) {
+        let message = message.into_inner();
+        match message {
+            // The sparse search is restricted to the allowed ids already
+            Ok(output) => {
+                self.set_text_result(output.user_ids, output.scores, ctx)
+                    .await;
+            }
+            Err(e) => {
+                self.terminate_with_error(Box::new(e), ctx);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<TaskResult<RankFusionOutput, ()>> for HnswQueryOrchestrator {
+    async fn handle(
+        &mut self,
+        message: TaskResult<RankFusionOutput, ()>,
+        _ctx: &ComponentContext<Self>,
+    ) {
+        let task_id = message.id();
+        let message = message.into_inner();
+        let query_index = self
+            .fusion_task_id_to_query_index
+            .remove(&task_id)
+            .expect("Invariant violation. Fusion task id is not set for query vector index");
+
+        let output = match message {
+            Ok(output) => output,
+            Err(_) => {
+                // Fusion does not fail
+                return;
+            }
+        };
+        trace!("Fused results: {:?}", output.results);
+        self.hybrid_results.insert(query_index, output.results);
+        self.finish_dependency_count -= 1;
+
+        if self.finish_dependency_count == 0 {
+            self.state = ExecutionState::Finished;
+            let hybrid_result_channel = match self.hybrid_result_channel.take() {
+                Some(tx) => tx,
+                None => {
+                    // Log an error - this is an invariant violation, the result channel should always be set
+                    return;
+                }
+            };
+            let mut results = Vec::with_capacity(self.query_vectors.len());
+            for i in 0..self.query_vectors.len() {
+                results.push(
+                    self.hybrid_results
+                        .remove(&i)
+                        .expect("Invariant violation. Fused results are not set"),
+                );
+            }
+            match hybrid_result_channel.send(Ok(results)) {
+                Ok(_) => (),
+                Err(_) => {
+                    // Log an error
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<TaskResult<MergeMaxSimResultsOperatorOutput, Box<dyn ChromaError>>>
+    for HnswQueryOrchestrator
+{
+    async fn handle(
+        &mut self,
+        message: TaskResult<MergeMaxSimResultsOperatorOutput, Box<dyn ChromaError>>,
+        ctx: &ComponentContext<Self>,
+    ) {
+        let message = message.into_inner();
+        self.state = ExecutionState::Finished;
+
+        let output = match message {
+            Ok(output) => output,
+            Err(e) => {
+                self.terminate_with_error(e, ctx);
+                return;
+            }
+        };
+
+        let vectors: Box<dyn Iterator<Item = Option<Vec<f32>>>> = match output.vectors {
+            Some(vectors) => Box::new(vectors.into_iter().map(Some)),
+            None => Box::new(std::iter::repeat(None)),
+        };
+        let query_results: Vec<VectorQueryResult> = output
+            .user_ids
+            .into_iter()
+            .zip(output.distances)
+            .zip(vectors)
+            .map(|((id, distance), vector)| VectorQueryResult {
+                id,
+                distance,
+                vector,
+            })
+            .collect();
+        trace!("MaxSim results: {:?}", query_results);
+        self.finish_dependency_count -= 1;
+
+        let result_channel = match self.result_channel.take() {
+            Some(tx) => tx,
+            None => {
+                // Log an error - this is an invariant violation, the result channel should always be set
+                return;
+            }
+        };
+        match result_channel.send(Ok(vec![query_results])) {
+            Ok(_) => (),
+            Err(_) => {
+                // Log an error
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::dispatcher::Dispatcher;
+    use crate::log::log::{InMemoryLog, InternalLogRecord};
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::sparse_vector_segment::SparseVectorSegmentWriter;
+    use crate::segment::{LogMaterializer, SegmentFlusher, SegmentWriter};
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::sysdb::test_sysdb::TestSysDb;
+    use crate::types::{Operation, OperationRecord, SegmentScope};
+    use std::path::PathBuf;
+
+    fn log_record(
+        log_offset: i64,
+        id: &str,
+        embedding: Option<Vec<f32>>,
+        sparse_embedding: Option<SparseVector>,
+        operation: Operation,
+    ) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding,
+                encoding: None,
+                sparse_embedding,
+                multi_embedding: None,
+                metadata: None,
+                document: None,
+                operation,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_query_with_sparse_query() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let collection_id = Uuid::new_v4();
+        let segment = |r#type: SegmentType, scope: SegmentScope| Segment {
+            id: Uuid::new_v4(),
+            r#type,
+            scope,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut record_segment = segment(SegmentType::Record, SegmentScope::RECORD);
+        let mut sparse_vector_segment =
+            segment(SegmentType::SparseVectorDistributed, SegmentScope::VECTOR);
+        // The dense vectors are only in the log, the hnsw segment is uninitialized
+        let hnsw_segment = segment(SegmentType::HnswDistributed, SegmentScope::VECTOR);
+        let sparse =
+            |indices: Vec<u32>, values: Vec<f32>| SparseVector::new(indices, values).unwrap();
+
+        // Compact a and b into the record and sparse vector segments
+        let compacted = vec![
+            log_record(
+                0,
+                "a",
+                Some(vec![9.0, 9.0, 9.0]),
+                Some(sparse(vec![1], vec![2.0])),
+                Operation::Add,
+            ),
+            log_record(
+                1,
+                "b",
+                Some(vec![9.0, 9.0, 9.0]),
+                Some(sparse(vec![1, 2], vec![1.0, 5.0])),
+                Operation::Add,
+            ),
+        ];
+        let record_segment_writer =
+            RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let mut sparse_vector_segment_writer =
+            SparseVectorSegmentWriter::from_segment(&sparse_vector_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let compacted_chunk = Chunk::new(compacted.clone().into());
+        let materialized = record_segment_writer
+            .materialize(&compacted_chunk)
+            .await
+            .unwrap();
+        sparse_vector_segment_writer
+            .apply_materialized_log_chunk(materialized)
+            .unwrap();
+        sparse_vector_segment_writer
+            .write_to_blockfiles()
+            .await
+            .unwrap();
+        record_segment.file_path = record_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+        sparse_vector_segment.file_path = sparse_vector_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+
+        // The log adds c and deletes a, which shadows it in the segment
+        let uncompacted = vec![
+            log_record(
+                2,
+                "c",
+                Some(vec![1.0, 2.0, 3.0]),
+                Some(sparse(vec![1], vec![3.0])),
+                Operation::Add,
+            ),
+            log_record(3, "a", None, None, Operation::Delete),
+        ];
+        let mut log = Box::new(InMemoryLog::new());
+        for log_record in compacted.into_iter().chain(uncompacted) {
+            log.add_log(
+                collection_id,
+                Box::new(InternalLogRecord {
+                    collection_id,
+                    log_offset: log_record.log_offset,
+                    log_ts: log_record.log_offset,
+                    record: log_record,
+                }),
+            );
+        }
+
+        let mut sysdb = Box::new(TestSysDb::new());
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(3),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: 1,
+            version: 0,
+        });
+        let hnsw_segment_id = hnsw_segment.id;
+        sysdb.add_segment(record_segment);
+        sysdb.add_segment(sparse_vector_segment);
+        sysdb.add_segment(hnsw_segment);
+
+        let system = System::new();
+        let dispatcher = Dispatcher::new(4, 100, 100);
+        let dispatcher_handle = system.start_component(dispatcher);
+        let mut orchestrator = HnswQueryOrchestrator::new(
+            system,
+            vec![vec![1.0, 2.0, 3.0]],
+            10,
+            vec![],
+            false,
+            hnsw_segment_id,
+            log,
+            sysdb,
+            HnswIndexProvider::new(
+                storage.clone(),
+                PathBuf::from(tmp_dir.path().to_str().unwrap()),
+                usize::MAX,
+            ),
+            PqIndexProvider::new(storage, PathBuf::from(tmp_dir.path().to_str().unwrap())),
+            blockfile_provider,
+            dispatcher_handle.receiver(),
+        );
+        orchestrator.set_sparse_query(
+            sparse(vec![1], vec![1.0]),
+            FusionMethod::ReciprocalRank {
+                rank_constant: DEFAULT_RANK_CONSTANT,
+            },
+            10,
+        );
+        let results = orchestrator.run_hybrid().await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        let ids: Vec<&str> = results[0].iter().map(|result| result.id.as_str()).collect();
+        assert_eq!(ids, vec!["c", "b"]);
+        // c is the nearest neighbor and the best sparse match
+        assert_eq!(results[0][0].distance, Some(0.0));
+        assert_eq!(results[0][0].text_score, Some(3.0));
+        // b only matches the sparse query
+        assert_eq!(results[0][1].distance, None);
+        assert_eq!(results[0][1].text_score, Some(1.0));
+    }
+}
@@ -8,11 +8,15 @@ use crate::execution::operators::merge_metadata_results::{
     MergeMetadataResultsOperator, MergeMetadataResultsOperatorError,
     MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOutput,
 };
+use crate::execution::operators::metadata_filtering::{
+    MatchOffsets, MetadataFilteringError, MetadataFilteringInput, MetadataFilteringOperator,
+    MetadataFilteringOutput,
+};
 use crate::execution::operators::pull_log::{PullLogsInput, PullLogsOperator, PullLogsOutput};
 use crate::log::log::PullLogsError;
 use crate::sysdb::sysdb::{GetCollectionsError, GetSegmentsError};
 use crate::system::{Component, ComponentContext, Handler};
-use crate::types::{Collection, LogRecord, Metadata, SegmentType};
+use crate::types::{Collection, LogRecord, Metadata, SegmentType, WhereDocument};
 use crate::{
     blockstore::provider::BlockfileProvider,
     execution::operator::TaskMessage,
@@ -36,9 +40,17 @@ enum ExecutionState {
     MergeResults,
 }
 
-// Returns the ids, metadata, and documents
-type MetadataQueryOrchestratorResult =
-    Result<(Vec<String>, Vec<Option<Metadata>>, Vec<Option<String>>), Box<dyn ChromaError>>;
+// Returns the ids, metadata, documents and the byte ranges of where document
+// matches in each document
+type MetadataQueryOrchestratorResult = Result<
+    (
+        Vec<String>,
+        Vec<Option<Metadata>>,
+        Vec<Option<String>>,
+        Vec<Vec<(usize, usize)>>,
+    ),
+    Box<dyn ChromaError>,
+>;
 
 #[derive(Debug)]
 pub(crate) struct MetadataQueryOrchestrator {
@@ -48,6 +60,8 @@ pub(crate) struct MetadataQueryOrchestrator {
     // Query state
     metadata_segment_id: Uuid,
     query_ids: Option<Vec<String>>,
+    where_document_clause: Option<WhereDocument>,
+    include_match_offsets: bool,
     // State fetched or created for query execution
     metadata_segment: Option<Segment>,
     record_segment: Option<Segment>,
@@ -435,6 +449,8 @@ impl MetadataQueryOrchestrator {
         system: System,
         metadata_segment_id: &Uuid,
         query_ids: Option<Vec<String>>,
+        where_document_clause: Option<WhereDocument>,
+        include_match_offsets: bool,
         log: Box<dyn Log>,
         sysdb: Box<dyn SysDb>,
         dispatcher: Box<dyn Receiver<TaskMessage>>,
@@ -445,6 +461,8 @@ impl MetadataQueryOrchestrator {
             system,
             metadata_segment_id: *metadata_segment_id,
             query_ids,
+            where_document_clause,
+            include_match_offsets,
             metadata_segment: None,
             record_segment: None,
             collection: None,
@@ -556,12 +574,43 @@ impl MetadataQueryOrchestrator {
         println!("Filtering logs and searching metadata segment");
         self.state = ExecutionState::Filter;
 
-        // TODO: Implement filtering and searching metadata segment
-        // for now we just proxy the items through on the request thread
-        // since in the server we disallow where/where document.
-        // When we implement this we can move it to an operator
+        // The where document clause is evaluated against the log and the
+        // metadata segment by an operator, the merge happens once it returns.
+        // The server does not allow it to be combined with query ids.
+        match self.where_document_clause.take() {
+            Some(where_document_clause) => {
+                let operator = MetadataFilteringOperator::new();
+                let input = MetadataFilteringInput::new(
+                    logs,
+                    self.metadata_segment
+                        .as_ref()
+                        .expect("Invariant violation. Metadata segment is not set.")
+                        .clone(),
+                    self.record_segment
+                        .as_ref()
+                        .expect("Invariant violation. Record segment is not set.")
+                        .clone(),
+                    self.blockfile_provider.clone(),
+                    where_document_clause,
+                    self.include_match_offsets,
+                );
+                let task = wrap(operator, input, ctx.sender.as_receiver());
+                match self.dispatcher.send(task, Some(Span::current())).await {
+                    Ok(_) => (),
+                    Err(e) => {
+                        // Log an error - this implies the dispatcher was dropped somehow
+                        // and is likely fatal
+                        println!("Error sending Metadata Query task: {:?}", e);
+                    }
+                }
+                return;
+            }
+            None => {}
+        }
 
-        // TODO: If we were to search the metadata segment we would do it here
+        // TODO: Implement filtering on where clauses
+        // for now we just proxy the items through on the request thread
+        // since in the server we disallow where.
         let filtered_index_offset_ids = None;
 
         if self.query_ids.is_some() {
@@ -588,12 +637,13 @@ impl MetadataQueryOrchestrator {
                 logs,
                 Some(remaining_query_ids),
                 filtered_index_offset_ids,
+                None,
                 ctx,
             )
             .await;
         } else {
             // No query ids to filter on
-            self.merge_results(logs, None, filtered_index_offset_ids, ctx)
+            self.merge_results(logs, None, filtered_index_offset_ids, None, ctx)
                 .await;
         }
     }
@@ -603,6 +653,7 @@ impl MetadataQueryOrchestrator {
         logs: Chunk<LogRecord>,
         remaining_query_ids: Option<Vec<String>>,
         filtered_index_offset_ids: Option<Vec<u32>>,
+        match_offsets: Option<MatchOffsets>,
         ctx: &ComponentContext<Self>,
     ) {
         println!("Merging metadata results");
@@ -613,6 +664,7 @@ impl MetadataQueryOrchestrator {
             logs,
             remaining_query_ids,
             filtered_index_offset_ids,
+            match_offsets,
             self.record_segment
                 .as_ref()
                 .expect("Invariant violation. Record segment is not set.")
@@ -787,6 +839,34 @@ impl Handler<TaskResult<PullLogsOutput, PullLogsError>> for MetadataQueryOrchest
     }
 }
 
+#[async_trait]
+impl Handler<TaskResult<MetadataFilteringOutput, MetadataFilteringError>>
+    for MetadataQueryOrchestrator
+{
+    async fn handle(
+        &mut self,
+        message: TaskResult<MetadataFilteringOutput, MetadataFilteringError>,
+        ctx: &ComponentContext<Self>,
+    ) {
+        let message = message.into_inner();
+        match message {
+            Ok(output) => {
+                self.merge_results(
+                    output.log_records,
+                    None,
+                    Some(output.offset_ids),
+                    output.match_offsets,
+                    ctx,
+                )
+                .await;
+            }
+            Err(e) => {
+                self.terminate_with_error(Box::new(e), ctx);
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl Handler<TaskResult<MergeMetadataResultsOperatorOutput, MergeMetadataResultsOperatorError>>
     for MetadataQueryOrchestrator
@@ -809,7 +889,12 @@ impl Handler<TaskResult<MergeMetadataResultsOperatorOutput, MergeMetadataResults
             .take()
             .expect("Invariant violation. Result channel is not set.");
 
-        let output = (output.ids, output.metadata, output.documents);
+        let output = (
+            output.ids,
+            output.metadata,
+            output.documents,
+            output.match_offsets,
+        );
         println!("Merged metadata results: {:?}", output);
 
         match result_channel.send(Ok(output)) {
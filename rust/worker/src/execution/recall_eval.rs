@@ -0,0 +1,432 @@
+use super::data::data_chunk::Chunk;
+use super::operator::Operator;
+use super::operators::brute_force_knn::{BruteForceKnnOperator, BruteForceKnnOperatorInput};
+use super::operators::normalize_vectors::normalize;
+use crate::blockstore::provider::BlockfileProvider;
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::hnsw_provider::HnswIndexProvider;
+use crate::index::{HnswSearchParams, IndexConfig, IndexConfigFromSegmentError, IndexFilter};
+use crate::segment::distributed_hnsw_segment::{
+    DistributedHNSWSegmentFromSegmentError, DistributedHNSWSegmentReader,
+};
+use crate::segment::record_segment::{RecordSegmentReader, RecordSegmentReaderCreationError};
+use crate::sysdb::sysdb::{GetSegmentsError, SysDb};
+use crate::types::{LogRecord, Operation, OperationRecord, SegmentType};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use uuid::Uuid;
+
+const DEFAULT_K: usize = 10;
+const DEFAULT_NUM_QUERIES: usize = 100;
+const DEFAULT_EF_VALUES: [usize; 6] = [10, 20, 40, 80, 160, 320];
+
+const USAGE: &str = "usage: recall_eval <collection_id> [--k <k>] [--queries <n>] \
+[--ef <ef>,<ef>,...] [--seed <seed>]";
+
+/// The arguments of the recall evaluation.
+/// # Parameters
+/// * `collection_id` - The collection whose hnsw segment is evaluated.
+/// * `k` - The number of nearest neighbors recall is measured at.
+/// * `num_queries` - The number of vectors sampled from the collection to query with.
+/// * `ef_values` - The ef values the index is searched with.
+/// * `seed` - The seed of the query sample.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RecallEvalArgs {
+    pub(crate) collection_id: Uuid,
+    pub(crate) k: usize,
+    pub(crate) num_queries: usize,
+    pub(crate) ef_values: Vec<usize>,
+    pub(crate) seed: u64,
+}
+
+impl RecallEvalArgs {
+    pub(crate) fn parse(
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<RecallEvalArgs, RecallEvalError> {
+        let collection_id = match args.next().map(|arg| Uuid::parse_str(&arg)) {
+            Some(Ok(collection_id)) => collection_id,
+            _ => return Err(RecallEvalError::InvalidArgument(USAGE.to_string())),
+        };
+        let mut eval_args = RecallEvalArgs {
+            collection_id,
+            k: DEFAULT_K,
+            num_queries: DEFAULT_NUM_QUERIES,
+            ef_values: DEFAULT_EF_VALUES.to_vec(),
+            seed: 0,
+        };
+        while let Some(flag) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(RecallEvalError::InvalidArgument(USAGE.to_string())),
+            };
+            match flag.as_str() {
+                "--k" => eval_args.k = parse_positive(&flag, &value)?,
+                "--queries" => eval_args.num_queries = parse_positive(&flag, &value)?,
+                "--ef" => {
+                    eval_args.ef_values = value
+                        .split(',')
+                        .map(|ef| parse_positive(&flag, ef))
+                        .collect::<Result<_, _>>()?
+                }
+                "--seed" => {
+                    eval_args.seed = value.parse().map_err(|_| {
+                        RecallEvalError::InvalidArgument(format!("Invalid {}: {}", flag, value))
+                    })?
+                }
+                _ => return Err(RecallEvalError::InvalidArgument(USAGE.to_string())),
+            }
+        }
+        Ok(eval_args)
+    }
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<usize, RecallEvalError> {
+    match value.parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(RecallEvalError::InvalidArgument(format!(
+            "Invalid {}: {}",
+            flag, value
+        ))),
+    }
+}
+
+/// The search quality of the index at one ef value.
+/// # Parameters
+/// * `ef` - The ef the index was searched with.
+/// * `recall` - The mean fraction of the exact top k found by the index.
+/// * `p50`, `p95`, `p99` - The latency percentiles of the index queries.
+#[derive(Clone, Debug)]
+pub(crate) struct EfReport {
+    pub(crate) ef: usize,
+    pub(crate) recall: f64,
+    pub(crate) p50: Duration,
+    pub(crate) p95: Duration,
+    pub(crate) p99: Duration,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum RecallEvalError {
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("Error getting segments: {0}")]
+    GetSegmentsError(#[from] GetSegmentsError),
+    #[error("No hnsw segment found for collection {0}")]
+    HnswSegmentNotFound(Uuid),
+    #[error("No record segment found for collection {0}")]
+    RecordSegmentNotFound(Uuid),
+    #[error("Error creating record segment reader: {0}")]
+    RecordSegmentReaderError(#[from] RecordSegmentReaderCreationError),
+    #[error("Error reading records: {0}")]
+    ReadRecordsError(Box<dyn ChromaError>),
+    #[error("Collection {0} has no vectors")]
+    EmptyCollection(Uuid),
+    #[error("Error reading the index config: {0}")]
+    IndexConfigError(#[from] IndexConfigFromSegmentError),
+    #[error("Error creating hnsw segment reader: {0}")]
+    HnswSegmentReaderError(#[from] DistributedHNSWSegmentFromSegmentError),
+    #[error("Brute force query failed")]
+    BruteForceError,
+}
+
+impl ChromaError for RecallEvalError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            RecallEvalError::InvalidArgument(_) => ErrorCodes::InvalidArgument,
+            RecallEvalError::GetSegmentsError(e) => e.code(),
+            RecallEvalError::HnswSegmentNotFound(_) => ErrorCodes::NotFound,
+            RecallEvalError::RecordSegmentNotFound(_) => ErrorCodes::NotFound,
+            RecallEvalError::RecordSegmentReaderError(e) => e.code(),
+            RecallEvalError::ReadRecordsError(e) => e.code(),
+            RecallEvalError::EmptyCollection(_) => ErrorCodes::InvalidArgument,
+            RecallEvalError::IndexConfigError(e) => e.code(),
+            RecallEvalError::HnswSegmentReaderError(e) => e.code(),
+            RecallEvalError::BruteForceError => ErrorCodes::Internal,
+        }
+    }
+}
+
+/// Loads the hnsw and record segments of the collection and measures the recall and
+/// latency of the index at each ef against the exact nearest neighbors.
+pub(crate) async fn run(
+    args: &RecallEvalArgs,
+    mut sysdb: Box<dyn SysDb>,
+    hnsw_index_provider: HnswIndexProvider,
+    blockfile_provider: &BlockfileProvider,
+) -> Result<Vec<EfReport>, RecallEvalError> {
+    let segments = sysdb
+        .get_segments(None, None, None, Some(args.collection_id))
+        .await?;
+    let hnsw_segment = match segments
+        .iter()
+        .find(|segment| segment.r#type == SegmentType::HnswDistributed)
+    {
+        Some(segment) => segment,
+        None => return Err(RecallEvalError::HnswSegmentNotFound(args.collection_id)),
+    };
+    let record_segment = match segments
+        .iter()
+        .find(|segment| segment.r#type == SegmentType::Record)
+    {
+        Some(segment) => segment,
+        None => return Err(RecallEvalError::RecordSegmentNotFound(args.collection_id)),
+    };
+
+    let record_segment_reader =
+        RecordSegmentReader::from_segment(record_segment, blockfile_provider)
+            .await
+            .map_err(|e| *e)?;
+    let offset_ids = record_segment_reader
+        .get_all_offset_ids()
+        .await
+        .map_err(RecallEvalError::ReadRecordsError)?;
+    let mut vectors = Vec::with_capacity(offset_ids.len());
+    for offset_id in offset_ids {
+        let data = record_segment_reader
+            .get_data_for_offset_id(offset_id)
+            .await
+            .map_err(RecallEvalError::ReadRecordsError)?;
+        vectors.push((offset_id, data.embedding.to_vec()));
+    }
+    let dimensionality = match vectors.first() {
+        Some((_, vector)) => vector.len(),
+        None => return Err(RecallEvalError::EmptyCollection(args.collection_id)),
+    };
+
+    let index_config =
+        IndexConfig::from_segment(hnsw_segment, dimensionality as i32).map_err(|e| *e)?;
+    let hnsw_segment_reader = DistributedHNSWSegmentReader::from_segment(
+        hnsw_segment,
+        dimensionality,
+        hnsw_index_provider,
+    )
+    .await
+    .map_err(|e| *e)?;
+    let filter = IndexFilter::default();
+    evaluate(
+        &vectors,
+        &index_config.distance_function,
+        args,
+        |query, k, params| hnsw_segment_reader.query(query, k, &filter, params).0,
+    )
+    .await
+}
+
+/// Samples queries from the vectors, finds their exact top k with the brute force
+/// operator and compares the results of the search function against them at each ef.
+pub(crate) async fn evaluate<F>(
+    vectors: &[(u32, Vec<f32>)],
+    distance_function: &DistanceFunction,
+    args: &RecallEvalArgs,
+    search: F,
+) -> Result<Vec<EfReport>, RecallEvalError>
+where
+    F: Fn(&[f32], usize, &HnswSearchParams) -> Vec<usize>,
+{
+    let k = args.k.min(vectors.len());
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let queries: Vec<&[f32]> =
+        rand::seq::index::sample(&mut rng, vectors.len(), args.num_queries.min(vectors.len()))
+            .iter()
+            .map(|i| &vectors[i].1[..])
+            .collect();
+
+    // The brute force operator expects the data to be normalized for cosine
+    let log_records: Vec<LogRecord> = vectors
+        .iter()
+        .enumerate()
+        .map(|(i, (offset_id, vector))| LogRecord {
+            log_offset: i as i64,
+            record: OperationRecord {
+                id: offset_id.to_string(),
+                embedding: Some(match distance_function {
+                    DistanceFunction::Cosine => normalize(vector),
+                    _ => vector.clone(),
+                }),
+                encoding: None,
+                sparse_embedding: None,
+                multi_embedding: None,
+                metadata: None,
+                document: None,
+                operation: Operation::Add,
+            },
+        })
+        .collect();
+    let data = Chunk::new(log_records.into());
+    let operator = BruteForceKnnOperator {};
+    let mut exact_results = Vec::with_capacity(queries.len());
+    for query in queries.iter() {
+        let input = BruteForceKnnOperatorInput {
+            data: data.clone(),
+            query: query.to_vec(),
+            k,
+            distance_metric: distance_function.clone(),
+        };
+        let output = match operator.run(&input).await {
+            Ok(output) => output,
+            Err(_) => return Err(RecallEvalError::BruteForceError),
+        };
+        let exact: Vec<usize> = output
+            .indices
+            .iter()
+            .map(|index| vectors[*index].0 as usize)
+            .collect();
+        exact_results.push(exact);
+    }
+
+    let mut reports = Vec::with_capacity(args.ef_values.len());
+    for ef in args.ef_values.iter() {
+        let params = HnswSearchParams {
+            ef: Some(*ef),
+            ..Default::default()
+        };
+        let mut total_recall = 0.0;
+        let mut latencies = Vec::with_capacity(queries.len());
+        for (query, exact) in queries.iter().zip(exact_results.iter()) {
+            let start = Instant::now();
+            let result = search(query, k, &params);
+            latencies.push(start.elapsed());
+            total_recall += recall_at_k(exact, &result);
+        }
+        latencies.sort();
+        reports.push(EfReport {
+            ef: *ef,
+            recall: total_recall / queries.len() as f64,
+            p50: percentile(&latencies, 50.0),
+            p95: percentile(&latencies, 95.0),
+            p99: percentile(&latencies, 99.0),
+        });
+    }
+    Ok(reports)
+}
+
+// The fraction of the exact neighbors found in the result
+fn recall_at_k(exact: &[usize], result: &[usize]) -> f64 {
+    if exact.is_empty() {
+        return 1.0;
+    }
+    let found = exact.iter().filter(|id| result.contains(id)).count();
+    found as f64 / exact.len() as f64
+}
+
+// The nearest rank percentile of latencies sorted in ascending order
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{
+        HnswImplementation, HnswIndex, HnswIndexConfig, Index, DEFAULT_RESIZE_FACTOR,
+    };
+    use rand::Rng;
+    use tempfile::tempdir;
+
+    #[test]
+    fn it_parses_the_arguments() {
+        let collection_id = Uuid::new_v4();
+        let args = RecallEvalArgs::parse(
+            [
+                collection_id.to_string(),
+                "--k".to_string(),
+                "5".to_string(),
+                "--ef".to_string(),
+                "16,64".to_string(),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        assert_eq!(args.collection_id, collection_id);
+        assert_eq!(args.k, 5);
+        assert_eq!(args.num_queries, DEFAULT_NUM_QUERIES);
+        assert_eq!(args.ef_values, vec![16, 64]);
+
+        assert!(RecallEvalArgs::parse(std::iter::empty()).is_err());
+        assert!(RecallEvalArgs::parse(
+            [
+                collection_id.to_string(),
+                "--k".to_string(),
+                "0".to_string()
+            ]
+            .into_iter()
+        )
+        .is_err());
+        assert!(RecallEvalArgs::parse(
+            [collection_id.to_string(), "--queries".to_string()].into_iter()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_computes_recall_and_percentiles() {
+        assert_eq!(recall_at_k(&[1, 2, 3, 4], &[4, 2, 7, 8]), 0.5);
+        assert_eq!(recall_at_k(&[], &[]), 1.0);
+
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies[..1], 95.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn it_evaluates_the_index_against_brute_force() {
+        let n = 500;
+        let d = 16;
+        let distance_function = DistanceFunction::Cosine;
+        let tmp_dir = tempdir().unwrap();
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function: distance_function.clone(),
+            },
+            Some(&HnswIndexConfig {
+                max_elements: n,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 10,
+                random_seed: 0,
+                persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                implementation: HnswImplementation::Native,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
+            }),
+            Uuid::new_v4(),
+        )
+        .unwrap();
+        let mut rng = rand::thread_rng();
+        let vectors: Vec<(u32, Vec<f32>)> = (0..n as u32)
+            .map(|offset_id| (offset_id + 1, (0..d).map(|_| rng.gen::<f32>()).collect()))
+            .collect();
+        for (offset_id, vector) in vectors.iter() {
+            index.add(*offset_id as usize, vector);
+        }
+
+        let args = RecallEvalArgs {
+            collection_id: Uuid::new_v4(),
+            k: 10,
+            num_queries: 20,
+            ef_values: vec![10, n],
+            seed: 0,
+        };
+        let filter = IndexFilter::default();
+        let reports = evaluate(&vectors, &distance_function, &args, |query, k, params| {
+            index.query_with_params(query, k, &filter, params).0
+        })
+        .await
+        .unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].ef, 10);
+        assert!(reports[0].recall > 0.0 && reports[0].recall <= 1.0);
+        // Searching with an ef as large as the index finds the exact neighbors
+        assert_eq!(reports[1].ef, n);
+        assert!((reports[1].recall - 1.0).abs() < 1e-9);
+        assert!(reports[1].p50 <= reports[1].p99);
+    }
+}
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+// 4 GiB
+const DEFAULT_HNSW_CACHE_CAPACITY_BYTES: usize = 4 << 30;
+
+#[derive(Deserialize, Clone, Debug)]
+/// The configuration of the cache of loaded hnsw indices.
+/// # Fields
+/// - capacity_bytes: The estimated memory the cached indices may take before the least
+///   recently used ones that are not in use are evicted.
+pub(crate) struct HnswCacheConfig {
+    pub(crate) capacity_bytes: usize,
+}
+
+impl Default for HnswCacheConfig {
+    fn default() -> Self {
+        HnswCacheConfig {
+            capacity_bytes: DEFAULT_HNSW_CACHE_CAPACITY_BYTES,
+        }
+    }
+}
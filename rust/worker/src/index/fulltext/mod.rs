@@ -1,2 +1,5 @@
+pub mod normalization;
+pub mod query;
+pub mod regex_prefilter;
 pub mod tokenizer;
 pub mod types;
@@ -0,0 +1,176 @@
+use crate::errors::{ChromaError, ErrorCodes};
+use std::borrow::Cow;
+use thiserror::Error;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+const CASE_FOLD: &str = "case_fold";
+const STRIP_ACCENTS: &str = "strip_accents";
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum TextNormalizationError {
+    #[error("Unknown full text normalization option {0}")]
+    UnknownOption(String),
+}
+
+impl ChromaError for TextNormalizationError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+/// How documents and queries are normalized before they are tokenized.
+/// # Description
+/// The same normalization must be applied when indexing and when querying,
+/// so it is fixed when a full text index is created and recorded alongside
+/// its files. Positions stored in the index are byte offsets into the
+/// normalized text.
+/// # Options
+/// - `case_fold`: lowercase the text, so "Paris" matches "paris".
+/// - `strip_accents`: decompose the text with Unicode NFKD and drop combining
+///   marks, so "París" matches "Paris".
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TextNormalization {
+    pub(crate) case_fold: bool,
+    pub(crate) strip_accents: bool,
+}
+
+impl TextNormalization {
+    pub(crate) fn normalize<'text>(&self, text: &'text str) -> Cow<'text, str> {
+        let text = match self.case_fold {
+            true => Cow::Owned(text.to_lowercase()),
+            false => Cow::Borrowed(text),
+        };
+        match self.strip_accents {
+            true => Cow::Owned(text.nfkd().filter(|c| !is_combining_mark(*c)).collect()),
+            false => text,
+        }
+    }
+
+    /// Map byte ranges in the normalized text back to byte ranges in the original text.
+    /// A range boundary that falls inside the expansion of a single original
+    /// character is widened to cover that whole character.
+    pub(crate) fn original_ranges(
+        &self,
+        text: &str,
+        ranges: &[(usize, usize)],
+    ) -> Vec<(usize, usize)> {
+        if *self == TextNormalization::default() {
+            return ranges.to_vec();
+        }
+        // The range of the original character each normalized byte came from.
+        // Characters normalize independently, so the lengths add up to the
+        // length of the normalized text.
+        let mut sources: Vec<(usize, usize)> = Vec::with_capacity(text.len());
+        for (start, c) in text.char_indices() {
+            let end = start + c.len_utf8();
+            let normalized_len = self.normalize(&text[start..end]).len();
+            sources.extend(std::iter::repeat((start, end)).take(normalized_len));
+        }
+        ranges
+            .iter()
+            .filter(|(start, end)| start < end && *end <= sources.len())
+            .map(|(start, end)| (sources[*start].0, sources[*end - 1].1))
+            .collect()
+    }
+
+    /// Parse the normalization from the options recorded in a segment's file manifest.
+    pub(crate) fn from_options(options: &[String]) -> Result<Self, TextNormalizationError> {
+        let mut normalization = TextNormalization::default();
+        for option in options {
+            match option.trim() {
+                CASE_FOLD => normalization.case_fold = true,
+                STRIP_ACCENTS => normalization.strip_accents = true,
+                "" => {}
+                option => return Err(TextNormalizationError::UnknownOption(option.to_string())),
+            }
+        }
+        Ok(normalization)
+    }
+
+    /// The options to record in a segment's file manifest.
+    pub(crate) fn to_options(&self) -> Vec<String> {
+        let mut options = vec![];
+        if self.case_fold {
+            options.push(CASE_FOLD.to_string());
+        }
+        if self.strip_accents {
+            options.push(STRIP_ACCENTS.to_string());
+        }
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let text = "Café in PARÍS";
+        assert_eq!(TextNormalization::default().normalize(text), text);
+        let case_fold = TextNormalization {
+            case_fold: true,
+            strip_accents: false,
+        };
+        assert_eq!(case_fold.normalize(text), "café in parís");
+        let strip_accents = TextNormalization {
+            case_fold: false,
+            strip_accents: true,
+        };
+        assert_eq!(strip_accents.normalize(text), "Cafe in PARIS");
+        let both = TextNormalization {
+            case_fold: true,
+            strip_accents: true,
+        };
+        assert_eq!(both.normalize(text), "cafe in paris");
+        // NFKD also folds compatibility characters.
+        assert_eq!(both.normalize("ﬁle"), "file");
+    }
+
+    #[test]
+    fn test_original_ranges() {
+        let text = "Café PARÍS ﬁn";
+        let both = TextNormalization {
+            case_fold: true,
+            strip_accents: true,
+        };
+        let normalized = both.normalize(text);
+        assert_eq!(normalized, "cafe paris fin");
+        let paris = normalized.find("paris").unwrap();
+        let ranges = both.original_ranges(text, &[(paris, paris + 5), (0, 4)]);
+        assert_eq!(ranges, vec![(6, 12), (0, 5)]);
+        assert_eq!(&text[6..12], "PARÍS");
+        // "f" is half of the "ﬁ" ligature, the range covers the whole ligature.
+        let fin = normalized.find("fin").unwrap();
+        assert_eq!(
+            both.original_ranges(text, &[(fin, fin + 1)]),
+            vec![(13, 16)]
+        );
+        // Without normalization ranges are passed through.
+        assert_eq!(
+            TextNormalization::default().original_ranges(text, &[(6, 12)]),
+            vec![(6, 12)]
+        );
+    }
+
+    #[test]
+    fn test_options_round_trip() {
+        let both = TextNormalization {
+            case_fold: true,
+            strip_accents: true,
+        };
+        assert_eq!(
+            TextNormalization::from_options(&both.to_options()).unwrap(),
+            both
+        );
+        assert_eq!(
+            TextNormalization::from_options(&[]).unwrap(),
+            TextNormalization::default()
+        );
+        assert_eq!(
+            TextNormalization::from_options(&["upper".to_string()]),
+            Err(TextNormalizationError::UnknownOption("upper".to_string()))
+        );
+    }
+}
@@ -0,0 +1,636 @@
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::fulltext::normalization::TextNormalization;
+use thiserror::Error;
+
+// Characters that separate words when counting proximity between terms.
+pub(crate) const WORD_SEPARATORS: [&str; 4] = [" ", "\t", "\n", "\r"];
+
+/// A parsed document query.
+/// # Description
+/// A `DocumentQuery` is a tree of clauses, where each node is exactly one of:
+/// - A phrase that must appear verbatim in the document.
+/// - A proximity clause whose terms must appear in order, with at most `slop`
+///   other words between each pair of consecutive terms.
+/// - A branch node combining its children with AND or OR.
+/// - A negation of a child clause. Negations are only valid as children of an
+///   AND that also has at least one positive clause.
+/// # Syntax
+/// - `"hello world"` or `hello` - a phrase
+/// - `"hello world"~3` - a proximity clause
+/// - `a AND b`, `a b` - both clauses must match (AND is implicit)
+/// - `a OR b` - either clause must match
+/// - `a NOT b`, `a AND NOT b` - a must match and b must not match
+/// - `(a OR b) AND c` - parentheses group clauses
+/// AND binds tighter than OR. Operators must be written in upper case, lower
+/// case `and`, `or` and `not` are treated as regular words.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum DocumentQuery {
+    Phrase(String),
+    Proximity { terms: Vec<String>, slop: u32 },
+    And(Vec<DocumentQuery>),
+    Or(Vec<DocumentQuery>),
+    Not(Box<DocumentQuery>),
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum DocumentQueryParseError {
+    #[error("Document query is empty")]
+    EmptyQuery,
+    #[error("Unterminated phrase starting at position {0}")]
+    UnterminatedPhrase(usize),
+    #[error("Empty phrase at position {0}")]
+    EmptyPhrase(usize),
+    #[error("Invalid proximity `{0}`, expected a phrase followed by ~ and a number")]
+    InvalidProximity(String),
+    #[error("Unexpected `{0}` in document query")]
+    UnexpectedToken(String),
+    #[error("Unexpected end of document query")]
+    UnexpectedEnd,
+    #[error("NOT must be combined with AND and at least one positive clause")]
+    UnboundedNegation,
+}
+
+impl ChromaError for DocumentQueryParseError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Phrase { text: String, slop: Option<u32> },
+}
+
+impl DocumentQuery {
+    pub(crate) fn parse(query: &str) -> Result<DocumentQuery, DocumentQueryParseError> {
+        let lexemes = lex(query)?;
+        if lexemes.is_empty() {
+            return Err(DocumentQueryParseError::EmptyQuery);
+        }
+        let mut parser = Parser {
+            lexemes,
+            position: 0,
+        };
+        let parsed = parser.parse_or()?;
+        if let Some(lexeme) = parser.peek() {
+            return Err(DocumentQueryParseError::UnexpectedToken(lexeme_to_string(
+                lexeme,
+            )));
+        }
+        if !parsed.is_bounded() {
+            return Err(DocumentQueryParseError::UnboundedNegation);
+        }
+        Ok(parsed)
+    }
+
+    // A clause is bounded if it can be evaluated without enumerating every
+    // document in the index. That is the case unless a NOT appears outside of
+    // an AND that also contains a positive clause.
+    fn is_bounded(&self) -> bool {
+        match self {
+            DocumentQuery::Phrase(_) | DocumentQuery::Proximity { .. } => true,
+            DocumentQuery::Not(_) => false,
+            DocumentQuery::Or(children) => children.iter().all(|child| child.is_bounded()),
+            DocumentQuery::And(children) => {
+                let mut has_positive = false;
+                for child in children {
+                    match child {
+                        DocumentQuery::Not(negated) => {
+                            if !negated.is_bounded() {
+                                return false;
+                            }
+                        }
+                        _ => {
+                            if !child.is_bounded() {
+                                return false;
+                            }
+                            has_positive = true;
+                        }
+                    }
+                }
+                has_positive
+            }
+        }
+    }
+
+    /// The phrases a document must contain for this query to match it, i.e.
+    /// every phrase and proximity term that is not negated. Used to report
+    /// where a query matched.
+    pub(crate) fn positive_phrases(&self) -> Vec<&str> {
+        match self {
+            DocumentQuery::Phrase(phrase) => vec![phrase.as_str()],
+            DocumentQuery::Proximity { terms, .. } => {
+                terms.iter().map(|term| term.as_str()).collect()
+            }
+            DocumentQuery::And(children) | DocumentQuery::Or(children) => children
+                .iter()
+                .flat_map(|child| child.positive_phrases())
+                .collect(),
+            DocumentQuery::Not(_) => vec![],
+        }
+    }
+
+    /// Evaluate the query directly against a document's text, for documents
+    /// that have not been indexed yet. Must agree with
+    /// `FullTextIndexReader::search_query` on indexed documents.
+    pub(crate) fn matches_document(
+        &self,
+        document: &str,
+        normalization: &TextNormalization,
+    ) -> bool {
+        let document = normalization.normalize(document);
+        self.matches_normalized_document(&document, normalization)
+    }
+
+    fn matches_normalized_document(
+        &self,
+        document: &str,
+        normalization: &TextNormalization,
+    ) -> bool {
+        match self {
+            DocumentQuery::Phrase(phrase) => {
+                document.contains(normalization.normalize(phrase).as_ref())
+            }
+            DocumentQuery::Proximity { terms, slop } => {
+                let terms: Vec<String> = terms
+                    .iter()
+                    .map(|term| normalization.normalize(term).into_owned())
+                    .collect();
+                let term_starts: Vec<Vec<i32>> = terms
+                    .iter()
+                    .map(|term| {
+                        document
+                            .match_indices(term.as_str())
+                            .map(|(start, _)| start as i32)
+                            .collect()
+                    })
+                    .collect();
+                let term_lengths: Vec<i32> = terms.iter().map(|term| term.len() as i32).collect();
+                let whitespace: Vec<i32> = document
+                    .char_indices()
+                    .filter(|(_, c)| {
+                        WORD_SEPARATORS
+                            .iter()
+                            .any(|separator| separator.starts_with(*c))
+                    })
+                    .map(|(position, _)| position as i32)
+                    .collect();
+                within_proximity(
+                    &term_starts,
+                    &term_lengths,
+                    &word_starts(&whitespace),
+                    *slop,
+                )
+            }
+            DocumentQuery::Or(children) => children
+                .iter()
+                .any(|child| child.matches_normalized_document(document, normalization)),
+            DocumentQuery::And(children) => children
+                .iter()
+                .all(|child| child.matches_normalized_document(document, normalization)),
+            DocumentQuery::Not(negated) => {
+                !negated.matches_normalized_document(document, normalization)
+            }
+        }
+    }
+}
+
+/// Returns the byte offsets at which a run of whitespace ends and a new word
+/// starts, given the sorted byte offsets of the whitespace in a document.
+pub(crate) fn word_starts(whitespace: &[i32]) -> Vec<i32> {
+    // Whitespace characters are single bytes, so a run ends wherever the
+    // next byte is not whitespace.
+    let mut boundaries = vec![];
+    for (index, position) in whitespace.iter().enumerate() {
+        if index + 1 == whitespace.len() || whitespace[index + 1] != position + 1 {
+            boundaries.push(position + 1);
+        }
+    }
+    boundaries
+}
+
+/// Whether the terms of a proximity clause occur in order in a document, with
+/// at most `slop` other words between each pair of consecutive terms.
+/// # Parameters
+/// - term_starts: the start byte offsets of every occurrence of each term.
+/// - term_lengths: the byte length of each term.
+/// - word_starts: the sorted byte offsets at which words start, see `word_starts`.
+pub(crate) fn within_proximity(
+    term_starts: &[Vec<i32>],
+    term_lengths: &[i32],
+    word_starts: &[i32],
+    slop: u32,
+) -> bool {
+    if term_starts.is_empty() {
+        return false;
+    }
+    let word_index = |position: i32| -> i64 {
+        word_starts.partition_point(|boundary| *boundary <= position) as i64
+    };
+
+    // The end (byte offset, word index) of every chain of terms matched so far.
+    let mut chain_ends: Vec<(i32, i64)> = term_starts[0]
+        .iter()
+        .map(|start| {
+            let end = start + term_lengths[0];
+            (end, word_index(end - 1))
+        })
+        .collect();
+    for (term_length, starts) in term_lengths[1..].iter().zip(term_starts[1..].iter()) {
+        let mut next_chain_ends = vec![];
+        for start in starts {
+            let start_word = word_index(*start);
+            let extends_chain = chain_ends
+                .iter()
+                .any(|(end, end_word)| *start >= *end && start_word - end_word - 1 <= slop as i64);
+            if extends_chain {
+                let end = start + term_length;
+                next_chain_ends.push((end, word_index(end - 1)));
+            }
+        }
+        chain_ends = next_chain_ends;
+        if chain_ends.is_empty() {
+            return false;
+        }
+    }
+    !chain_ends.is_empty()
+}
+
+fn lexeme_to_string(lexeme: &Lexeme) -> String {
+    match lexeme {
+        Lexeme::LeftParen => "(".to_string(),
+        Lexeme::RightParen => ")".to_string(),
+        Lexeme::And => "AND".to_string(),
+        Lexeme::Or => "OR".to_string(),
+        Lexeme::Not => "NOT".to_string(),
+        Lexeme::Phrase { text, .. } => text.clone(),
+    }
+}
+
+fn is_word_boundary(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '"'
+}
+
+fn lex(query: &str) -> Result<Vec<Lexeme>, DocumentQueryParseError> {
+    let mut lexemes = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        match c {
+            '(' => lexemes.push(Lexeme::LeftParen),
+            ')' => lexemes.push(Lexeme::RightParen),
+            '"' => {
+                let mut text = String::new();
+                let mut terminated = false;
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => match chars.next() {
+                            Some((_, escaped)) => text.push(escaped),
+                            None => break,
+                        },
+                        '"' => {
+                            terminated = true;
+                            break;
+                        }
+                        _ => text.push(c),
+                    }
+                }
+                if !terminated {
+                    return Err(DocumentQueryParseError::UnterminatedPhrase(start));
+                }
+                if text.is_empty() {
+                    return Err(DocumentQueryParseError::EmptyPhrase(start));
+                }
+                let slop = match chars.peek() {
+                    Some((_, '~')) => {
+                        chars.next();
+                        let mut digits = String::new();
+                        while let Some((_, c)) = chars.peek() {
+                            if is_word_boundary(*c) {
+                                break;
+                            }
+                            digits.push(*c);
+                            chars.next();
+                        }
+                        match digits.parse::<u32>() {
+                            Ok(slop) => Some(slop),
+                            Err(_) => {
+                                return Err(DocumentQueryParseError::InvalidProximity(format!(
+                                    "\"{}\"~{}",
+                                    text, digits
+                                )))
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                lexemes.push(Lexeme::Phrase { text, slop });
+            }
+            _ => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.peek() {
+                    if is_word_boundary(*c) {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+                if word.contains('~') {
+                    return Err(DocumentQueryParseError::InvalidProximity(word));
+                }
+                match word.as_str() {
+                    "AND" => lexemes.push(Lexeme::And),
+                    "OR" => lexemes.push(Lexeme::Or),
+                    "NOT" => lexemes.push(Lexeme::Not),
+                    _ => lexemes.push(Lexeme::Phrase {
+                        text: word,
+                        slop: None,
+                    }),
+                }
+            }
+        }
+    }
+    Ok(lexemes)
+}
+
+struct Parser {
+    lexemes: Vec<Lexeme>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.position).cloned();
+        self.position += 1;
+        lexeme
+    }
+
+    // or := and (OR and)*
+    fn parse_or(&mut self) -> Result<DocumentQuery, DocumentQueryParseError> {
+        let mut children = vec![self.parse_and()?];
+        while let Some(Lexeme::Or) = self.peek() {
+            self.next();
+            children.push(self.parse_and()?);
+        }
+        if children.len() == 1 {
+            return Ok(children.pop().unwrap());
+        }
+        Ok(DocumentQuery::Or(children))
+    }
+
+    // and := unary ([AND] unary)*
+    fn parse_and(&mut self) -> Result<DocumentQuery, DocumentQueryParseError> {
+        let mut children = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                Some(Lexeme::And) => {
+                    self.next();
+                    children.push(self.parse_unary()?);
+                }
+                Some(Lexeme::Not) | Some(Lexeme::LeftParen) | Some(Lexeme::Phrase { .. }) => {
+                    children.push(self.parse_unary()?);
+                }
+                _ => break,
+            }
+        }
+        if children.len() == 1 {
+            return Ok(children.pop().unwrap());
+        }
+        Ok(DocumentQuery::And(children))
+    }
+
+    // unary := NOT unary | primary
+    fn parse_unary(&mut self) -> Result<DocumentQuery, DocumentQueryParseError> {
+        match self.peek() {
+            Some(Lexeme::Not) => {
+                self.next();
+                Ok(DocumentQuery::Not(Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    // primary := '(' or ')' | phrase
+    fn parse_primary(&mut self) -> Result<DocumentQuery, DocumentQueryParseError> {
+        match self.next() {
+            Some(Lexeme::LeftParen) => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Lexeme::RightParen) => Ok(inner),
+                    Some(lexeme) => Err(DocumentQueryParseError::UnexpectedToken(
+                        lexeme_to_string(&lexeme),
+                    )),
+                    None => Err(DocumentQueryParseError::UnexpectedEnd),
+                }
+            }
+            Some(Lexeme::Phrase { text, slop }) => match slop {
+                Some(slop) => {
+                    let terms: Vec<String> = text.split_whitespace().map(String::from).collect();
+                    match terms.len() {
+                        0 => Err(DocumentQueryParseError::InvalidProximity(text)),
+                        1 => Ok(DocumentQuery::Phrase(terms[0].clone())),
+                        _ => Ok(DocumentQuery::Proximity { terms, slop }),
+                    }
+                }
+                None => Ok(DocumentQuery::Phrase(text)),
+            },
+            Some(lexeme) => Err(DocumentQueryParseError::UnexpectedToken(lexeme_to_string(
+                &lexeme,
+            ))),
+            None => Err(DocumentQueryParseError::UnexpectedEnd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phrase(text: &str) -> DocumentQuery {
+        DocumentQuery::Phrase(text.to_string())
+    }
+
+    #[test]
+    fn test_parse_single_word() {
+        assert_eq!(DocumentQuery::parse("hello").unwrap(), phrase("hello"));
+    }
+
+    #[test]
+    fn test_parse_quoted_phrase() {
+        assert_eq!(
+            DocumentQuery::parse("\"hello world\"").unwrap(),
+            phrase("hello world")
+        );
+        assert_eq!(
+            DocumentQuery::parse("\"say \\\"hi\\\"\"").unwrap(),
+            phrase("say \"hi\"")
+        );
+    }
+
+    #[test]
+    fn test_parse_implicit_and_explicit_and() {
+        let expected = DocumentQuery::And(vec![phrase("hello"), phrase("world")]);
+        assert_eq!(DocumentQuery::parse("hello world").unwrap(), expected);
+        assert_eq!(DocumentQuery::parse("hello AND world").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_and_binds_tighter_than_or() {
+        assert_eq!(
+            DocumentQuery::parse("a b OR c").unwrap(),
+            DocumentQuery::Or(vec![
+                DocumentQuery::And(vec![phrase("a"), phrase("b")]),
+                phrase("c"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_parentheses() {
+        assert_eq!(
+            DocumentQuery::parse("(a OR b) AND \"c d\"").unwrap(),
+            DocumentQuery::And(vec![
+                DocumentQuery::Or(vec![phrase("a"), phrase("b")]),
+                phrase("c d"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_not() {
+        assert_eq!(
+            DocumentQuery::parse("a AND NOT b").unwrap(),
+            DocumentQuery::And(vec![phrase("a"), DocumentQuery::Not(Box::new(phrase("b"))),])
+        );
+        assert_eq!(
+            DocumentQuery::parse("a NOT (b OR c)").unwrap(),
+            DocumentQuery::And(vec![
+                phrase("a"),
+                DocumentQuery::Not(Box::new(DocumentQuery::Or(vec![phrase("b"), phrase("c")]))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_lowercase_operators_are_words() {
+        assert_eq!(
+            DocumentQuery::parse("cats and dogs").unwrap(),
+            DocumentQuery::And(vec![phrase("cats"), phrase("and"), phrase("dogs")])
+        );
+    }
+
+    #[test]
+    fn test_parse_proximity() {
+        assert_eq!(
+            DocumentQuery::parse("\"quick fox\"~3").unwrap(),
+            DocumentQuery::Proximity {
+                terms: vec!["quick".to_string(), "fox".to_string()],
+                slop: 3,
+            }
+        );
+        // A single term proximity is just a phrase
+        assert_eq!(DocumentQuery::parse("\"fox\"~3").unwrap(), phrase("fox"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            DocumentQuery::parse("   "),
+            Err(DocumentQueryParseError::EmptyQuery)
+        );
+        assert_eq!(
+            DocumentQuery::parse("\"hello"),
+            Err(DocumentQueryParseError::UnterminatedPhrase(0))
+        );
+        assert_eq!(
+            DocumentQuery::parse("a \"\""),
+            Err(DocumentQueryParseError::EmptyPhrase(2))
+        );
+        assert_eq!(
+            DocumentQuery::parse("\"a b\"~x"),
+            Err(DocumentQueryParseError::InvalidProximity(
+                "\"a b\"~x".to_string()
+            ))
+        );
+        assert_eq!(
+            DocumentQuery::parse("a~2"),
+            Err(DocumentQueryParseError::InvalidProximity("a~2".to_string()))
+        );
+        assert_eq!(
+            DocumentQuery::parse("(a OR b"),
+            Err(DocumentQueryParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            DocumentQuery::parse("a)"),
+            Err(DocumentQueryParseError::UnexpectedToken(")".to_string()))
+        );
+        assert_eq!(
+            DocumentQuery::parse("a AND"),
+            Err(DocumentQueryParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            DocumentQuery::parse("OR a"),
+            Err(DocumentQueryParseError::UnexpectedToken("OR".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_unbounded_negation() {
+        assert_eq!(
+            DocumentQuery::parse("NOT a"),
+            Err(DocumentQueryParseError::UnboundedNegation)
+        );
+        assert_eq!(
+            DocumentQuery::parse("a OR NOT b"),
+            Err(DocumentQueryParseError::UnboundedNegation)
+        );
+        assert_eq!(
+            DocumentQuery::parse("NOT a AND NOT b"),
+            Err(DocumentQueryParseError::UnboundedNegation)
+        );
+    }
+
+    #[test]
+    fn test_positive_phrases() {
+        let query = DocumentQuery::parse("(\"a b\" OR c) NOT d \"e f\"~2").unwrap();
+        assert_eq!(query.positive_phrases(), vec!["a b", "c", "e", "f"]);
+    }
+
+    #[test]
+    fn test_matches_document() {
+        let normalization = TextNormalization::default();
+        let document = "the quick red and brown fox";
+        let matches = |query: &str| {
+            DocumentQuery::parse(query)
+                .unwrap()
+                .matches_document(document, &normalization)
+        };
+        assert!(matches("\"quick red\""));
+        assert!(!matches("\"red quick\""));
+        assert!(matches("quick AND fox NOT blue"));
+        assert!(!matches("quick AND fox NOT red"));
+        assert!(matches("blue OR brown"));
+        assert!(matches("\"quick brown\"~2"));
+        assert!(!matches("\"quick brown\"~1"));
+        assert!(!matches("\"brown quick\"~5"));
+
+        let normalization = TextNormalization {
+            case_fold: true,
+            strip_accents: true,
+        };
+        assert!(DocumentQuery::parse("\"PARIS\"")
+            .unwrap()
+            .matches_document("Visiting París", &normalization));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::fulltext::query::DocumentQuery;
+use regex_syntax::hir::{Hir, HirKind};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum RegexPrefilterError {
+    #[error("Invalid regex: {0}")]
+    InvalidPattern(String),
+}
+
+impl ChromaError for RegexPrefilterError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+// The literals a document must contain to possibly match a regex.
+#[derive(Debug, PartialEq)]
+enum Requirement {
+    // No literal is required, any document may match.
+    Anything,
+    Literal(String),
+    All(Vec<Requirement>),
+    Any(Vec<Requirement>),
+}
+
+/// Build a query over the full text index that matches a superset of the
+/// documents a regex can match.
+/// # Description
+/// The regex is reduced to the literals any match must contain, e.g.
+/// `hello (world|there)\d+` requires "hello " and either "world" or "there".
+/// Looking the literals up in the posting lists narrows down the candidates,
+/// which must then be verified against the document text with the regex itself.
+/// # Returns
+/// None if the regex requires no literal at all, such as `\d+` or `(?i)abc`,
+/// in which case every document is a candidate.
+pub(crate) fn regex_prefilter(pattern: &str) -> Result<Option<DocumentQuery>, RegexPrefilterError> {
+    let hir = match regex_syntax::Parser::new().parse(pattern) {
+        Ok(hir) => hir,
+        Err(e) => return Err(RegexPrefilterError::InvalidPattern(e.to_string())),
+    };
+    Ok(to_document_query(requirement(&hir)))
+}
+
+// The text matched by the expression, if it only ever matches that one text.
+fn exact_literal(hir: &Hir) -> Option<String> {
+    match hir.kind() {
+        HirKind::Empty => Some(String::new()),
+        HirKind::Literal(literal) => String::from_utf8(literal.0.to_vec()).ok(),
+        HirKind::Capture(capture) => exact_literal(&capture.sub),
+        HirKind::Concat(children) => {
+            let mut literal = String::new();
+            for child in children {
+                literal.push_str(&exact_literal(child)?);
+            }
+            Some(literal)
+        }
+        _ => None,
+    }
+}
+
+fn requirement(hir: &Hir) -> Requirement {
+    if let Some(literal) = exact_literal(hir) {
+        return match literal.is_empty() {
+            true => Requirement::Anything,
+            false => Requirement::Literal(literal),
+        };
+    }
+    match hir.kind() {
+        HirKind::Concat(children) => {
+            // Adjacent exact literals are joined into one longer, more selective literal.
+            let mut requirements = vec![];
+            let mut literal = String::new();
+            for child in children {
+                match child.kind() {
+                    // Assertions match no text, so they do not separate literals.
+                    HirKind::Look(_) => continue,
+                    _ => {}
+                }
+                match exact_literal(child) {
+                    Some(child_literal) => literal.push_str(&child_literal),
+                    None => {
+                        if !literal.is_empty() {
+                            requirements.push(Requirement::Literal(std::mem::take(&mut literal)));
+                        }
+                        requirements.push(requirement(child));
+                    }
+                }
+            }
+            if !literal.is_empty() {
+                requirements.push(Requirement::Literal(literal));
+            }
+            all(requirements)
+        }
+        HirKind::Alternation(children) => {
+            let mut requirements = vec![];
+            for child in children {
+                match requirement(child) {
+                    Requirement::Anything => return Requirement::Anything,
+                    child_requirement => requirements.push(child_requirement),
+                }
+            }
+            Requirement::Any(requirements)
+        }
+        HirKind::Repetition(repetition) if repetition.min > 0 => requirement(&repetition.sub),
+        HirKind::Capture(capture) => requirement(&capture.sub),
+        _ => Requirement::Anything,
+    }
+}
+
+fn all(requirements: Vec<Requirement>) -> Requirement {
+    let mut requirements: Vec<Requirement> = requirements
+        .into_iter()
+        .filter(|requirement| *requirement != Requirement::Anything)
+        .collect();
+    match requirements.len() {
+        0 => Requirement::Anything,
+        1 => requirements.remove(0),
+        _ => Requirement::All(requirements),
+    }
+}
+
+fn to_document_query(requirement: Requirement) -> Option<DocumentQuery> {
+    match requirement {
+        Requirement::Anything => None,
+        Requirement::Literal(literal) => Some(DocumentQuery::Phrase(literal)),
+        Requirement::All(requirements) => Some(DocumentQuery::And(
+            requirements
+                .into_iter()
+                .filter_map(to_document_query)
+                .collect(),
+        )),
+        Requirement::Any(requirements) => {
+            let mut queries = vec![];
+            for requirement in requirements {
+                queries.push(to_document_query(requirement)?);
+            }
+            Some(DocumentQuery::Or(queries))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phrase(text: &str) -> DocumentQuery {
+        DocumentQuery::Phrase(text.to_string())
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(regex_prefilter("hello").unwrap(), Some(phrase("hello")));
+        assert_eq!(
+            regex_prefilter("^hello world$").unwrap(),
+            Some(phrase("hello world"))
+        );
+        assert_eq!(regex_prefilter("(hel)lo").unwrap(), Some(phrase("hello")));
+    }
+
+    #[test]
+    fn test_concat() {
+        assert_eq!(
+            regex_prefilter(r"fn \w+\(self").unwrap(),
+            Some(DocumentQuery::And(vec![phrase("fn "), phrase("(self")]))
+        );
+        assert_eq!(
+            regex_prefilter("ab+c").unwrap(),
+            Some(DocumentQuery::And(vec![
+                phrase("a"),
+                phrase("b"),
+                phrase("c")
+            ]))
+        );
+        assert_eq!(
+            regex_prefilter("ab*c").unwrap(),
+            Some(DocumentQuery::And(vec![phrase("a"), phrase("c")]))
+        );
+    }
+
+    #[test]
+    fn test_alternation() {
+        assert_eq!(
+            regex_prefilter(r"hello (world|there)\d+").unwrap(),
+            Some(DocumentQuery::And(vec![
+                phrase("hello "),
+                DocumentQuery::Or(vec![phrase("world"), phrase("there")])
+            ]))
+        );
+        assert_eq!(regex_prefilter("hello|.*").unwrap(), None);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        assert_eq!(regex_prefilter(r"\d+").unwrap(), None);
+        assert_eq!(regex_prefilter("(?i)hello").unwrap(), None);
+        assert_eq!(regex_prefilter("(abc)?").unwrap(), None);
+        assert_eq!(regex_prefilter("").unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(
+            regex_prefilter("(unclosed"),
+            Err(RegexPrefilterError::InvalidPattern(_))
+        ));
+    }
+}
@@ -27,7 +27,7 @@ impl ChromaTokenStream for TantivyChromaTokenStream {
     }
 }
 
-pub(crate) trait ChromaTokenizer {
+pub(crate) trait ChromaTokenizer: Send + Sync {
     fn encode(&mut self, text: &str) -> Box<dyn ChromaTokenStream>;
 }
 
@@ -1,11 +1,17 @@
 use crate::blockstore::positional_posting_list_value::PositionalPostingListBuilder;
-use crate::blockstore::{BlockfileFlusher, BlockfileReader, BlockfileWriter};
+use crate::blockstore::{BlockfileError, BlockfileFlusher, BlockfileReader, BlockfileWriter};
 use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::fulltext::normalization::TextNormalization;
+use crate::index::fulltext::query::{
+    within_proximity, word_starts, DocumentQuery, DocumentQueryParseError, WORD_SEPARATORS,
+};
 use crate::index::fulltext::tokenizer::ChromaTokenizer;
+use crate::utils::{merge_sorted_vecs_conjunction, merge_sorted_vecs_disjunction};
 
 use arrow::array::Int32Array;
+use futures::future::BoxFuture;
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use thiserror::Error;
 use uuid::Uuid;
@@ -24,35 +30,50 @@ impl ChromaError for FullTextIndexError {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct FullTextIndexWriter {
     posting_lists_blockfile_writer: BlockfileWriter,
     frequencies_blockfile_writer: BlockfileWriter,
+    // The frequencies of the index the writers were forked from, if any.
+    // Frequencies are stored in the keys, so updating one means deleting the
+    // old key, which we need to read first.
+    frequencies_blockfile_reader: Option<Arc<BlockfileReader<'static, u32, u32>>>,
     // This is a crime.
     tokenizer: Arc<Mutex<Box<dyn ChromaTokenizer>>>,
+    normalization: TextNormalization,
 
     // term -> positional posting list builder for that term
     uncommitted: Arc<Mutex<HashMap<String, PositionalPostingListBuilder>>>,
+    // term -> change in frequency since the index was forked
     uncommitted_frequencies: Arc<Mutex<HashMap<String, i32>>>,
+    // term -> doc IDs to remove from the posting list of the forked index
+    uncommitted_deletes: Arc<Mutex<HashMap<String, HashSet<u32>>>>,
 }
 
 impl FullTextIndexWriter {
     pub fn new(
         posting_lists_blockfile_writer: BlockfileWriter,
         frequencies_blockfile_writer: BlockfileWriter,
+        frequencies_blockfile_reader: Option<BlockfileReader<'static, u32, u32>>,
         tokenizer: Box<dyn ChromaTokenizer>,
+        normalization: TextNormalization,
     ) -> Self {
         FullTextIndexWriter {
             posting_lists_blockfile_writer,
             frequencies_blockfile_writer,
+            frequencies_blockfile_reader: frequencies_blockfile_reader.map(Arc::new),
             tokenizer: Arc::new(Mutex::new(tokenizer)),
+            normalization,
             uncommitted: Arc::new(Mutex::new(HashMap::new())),
             uncommitted_frequencies: Arc::new(Mutex::new(HashMap::new())),
+            uncommitted_deletes: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     pub fn add_document(&self, document: &str, offset_id: i32) -> Result<(), Box<dyn ChromaError>> {
+        let document = self.normalization.normalize(document);
         let mut tokenizer = self.tokenizer.lock();
-        let tokens = tokenizer.encode(document);
+        let tokens = tokenizer.encode(&document);
         for token in tokens.get_tokens() {
             let mut uncommitted_frequencies = self.uncommitted_frequencies.lock();
             uncommitted_frequencies
@@ -87,9 +108,90 @@ impl FullTextIndexWriter {
         Ok(())
     }
 
+    /// Remove a document from the index.
+    /// # Description
+    /// The document must be the text that was indexed for the doc ID, its
+    /// tokens determine which posting lists and frequencies to update.
+    pub fn delete_document(
+        &self,
+        document: &str,
+        offset_id: i32,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        let document = self.normalization.normalize(document);
+        let mut tokenizer = self.tokenizer.lock();
+        let tokens = tokenizer.encode(&document);
+        for token in tokens.get_tokens() {
+            let mut uncommitted_frequencies = self.uncommitted_frequencies.lock();
+            uncommitted_frequencies
+                .entry(token.text.to_string())
+                .and_modify(|e| *e -= 1)
+                .or_insert(-1);
+
+            // The document may have been added by this writer, in the index
+            // it was forked from, or both if it is being updated.
+            let mut uncommitted = self.uncommitted.lock();
+            match uncommitted.get_mut(&token.text) {
+                Some(builder) => {
+                    if builder.contains_doc_id(offset_id) {
+                        builder.remove_doc_id(offset_id)?;
+                    }
+                }
+                None => {}
+            }
+            let mut uncommitted_deletes = self.uncommitted_deletes.lock();
+            uncommitted_deletes
+                .entry(token.text.to_string())
+                .or_insert(HashSet::new())
+                .insert(offset_id as u32);
+        }
+        Ok(())
+    }
+
+    /// Replace the indexed text of a document.
+    pub fn update_document(
+        &self,
+        old_document: &str,
+        new_document: &str,
+        offset_id: i32,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        self.delete_document(old_document, offset_id)?;
+        self.add_document(new_document, offset_id)
+    }
+
+    // The frequency entries of a token in the forked index. There should be
+    // at most one, but all of them are replaced. A token the forked index does
+    // not have has none, any other read error is returned so that the persisted
+    // frequency is not overwritten.
+    async fn get_forked_frequencies(&self, token: &str) -> Result<Vec<u32>, Box<dyn ChromaError>> {
+        match &self.frequencies_blockfile_reader {
+            Some(reader) => match reader.get_by_prefix(token).await {
+                Ok(frequencies) => Ok(frequencies
+                    .iter()
+                    .map(|(_, frequency, _)| *frequency)
+                    .collect()),
+                Err(e) if BlockfileError::is_not_found(&*e) => Ok(vec![]),
+                Err(e) => Err(e),
+            },
+            None => Ok(vec![]),
+        }
+    }
+
     pub async fn write_to_blockfiles(&mut self) -> Result<(), Box<dyn ChromaError>> {
-        let mut uncommitted = self.uncommitted.lock();
-        for (key, mut value) in uncommitted.drain() {
+        // Deletes go first, an updated document is deleted and then added
+        // under the same doc ID.
+        let uncommitted_deletes: Vec<(String, HashSet<u32>)> =
+            self.uncommitted_deletes.lock().drain().collect();
+        for (key, doc_ids) in uncommitted_deletes {
+            for doc_id in doc_ids {
+                self.posting_lists_blockfile_writer
+                    .delete::<u32, &Int32Array>(key.as_str(), doc_id)
+                    .await?;
+            }
+        }
+
+        let uncommitted: Vec<(String, PositionalPostingListBuilder)> =
+            self.uncommitted.lock().drain().collect();
+        for (key, mut value) in uncommitted {
             let built_list = value.build();
             for doc_id in built_list.doc_ids.iter() {
                 match doc_id {
@@ -110,12 +212,30 @@ impl FullTextIndexWriter {
                 }
             }
         }
-        let mut uncommitted_frequencies = self.uncommitted_frequencies.lock();
-        for (key, value) in uncommitted_frequencies.drain() {
+        let uncommitted_frequencies: Vec<(String, i32)> =
+            self.uncommitted_frequencies.lock().drain().collect();
+        for (key, value) in uncommitted_frequencies {
+            if value == 0 {
+                continue;
+            }
+            let forked_frequencies = self.get_forked_frequencies(key.as_str()).await?;
+            let frequency = match forked_frequencies.first() {
+                Some(forked_frequency) => *forked_frequency as i32 + value,
+                None => value,
+            };
+            for forked_frequency in forked_frequencies {
+                self.frequencies_blockfile_writer
+                    .delete::<u32, u32>(key.as_str(), forked_frequency)
+                    .await?;
+            }
+            // A token that no longer occurs in any document has no entry.
+            if frequency <= 0 {
+                continue;
+            }
             // TODO we just have token -> frequency here. Should frequency be the key or should we use an empty key and make it the value?
             let res = self
                 .frequencies_blockfile_writer
-                .set(key.as_str(), value as u32, 0)
+                .set(key.as_str(), frequency as u32, 0)
                 .await;
             if res.is_err() {
                 return res;
@@ -175,6 +295,8 @@ pub(crate) struct FullTextIndexReader<'me> {
     posting_lists_blockfile_reader: BlockfileReader<'me, u32, Int32Array>,
     frequencies_blockfile_reader: BlockfileReader<'me, u32, u32>,
     tokenizer: Arc<Mutex<Box<dyn ChromaTokenizer>>>,
+    // Must match the normalization the index was written with.
+    normalization: TextNormalization,
 }
 
 impl<'me> FullTextIndexReader<'me> {
@@ -182,29 +304,75 @@ impl<'me> FullTextIndexReader<'me> {
         posting_lists_blockfile_reader: BlockfileReader<'me, u32, Int32Array>,
         frequencies_blockfile_reader: BlockfileReader<'me, u32, u32>,
         tokenizer: Box<dyn ChromaTokenizer>,
+        normalization: TextNormalization,
     ) -> Self {
         FullTextIndexReader {
             posting_lists_blockfile_reader,
             frequencies_blockfile_reader,
             tokenizer: Arc::new(Mutex::new(tokenizer)),
+            normalization,
         }
     }
 
     pub async fn search(&self, query: &str) -> Result<Vec<i32>, Box<dyn ChromaError>> {
-        let mut tokenizer = self.tokenizer.lock();
-        let binding = tokenizer.encode(query);
-        let tokens = binding.get_tokens();
+        let mut candidates = self.match_phrase(query).await?;
+        let mut results = vec![];
+        for (doc_id, _) in candidates.drain() {
+            results.push(doc_id as i32);
+        }
+        return Ok(results);
+    }
+
+    /// Search for a phrase and report where it occurs in each matching document.
+    /// # Returns
+    /// Doc ID -> sorted (start, end) byte ranges of every occurrence of the phrase.
+    /// Ranges are offsets into the normalized document, use
+    /// `TextNormalization::original_ranges` to map them onto the original text.
+    pub async fn search_with_offsets(
+        &self,
+        query: &str,
+    ) -> Result<HashMap<i32, Vec<(usize, usize)>>, Box<dyn ChromaError>> {
+        let query_length = self.normalization.normalize(query).len();
+        let candidates = self.match_phrase(query).await?;
+        let mut results = HashMap::new();
+        for (doc_id, mut starts) in candidates.into_iter() {
+            starts.sort();
+            starts.dedup();
+            let ranges = starts
+                .into_iter()
+                .map(|start| (start as usize, start as usize + query_length))
+                .collect();
+            results.insert(doc_id as i32, ranges);
+        }
+        Ok(results)
+    }
+
+    // Returns doc ID -> starting byte offsets of every occurrence of the query
+    // in that document.
+    async fn match_phrase(
+        &self,
+        query: &str,
+    ) -> Result<HashMap<u32, Vec<i32>>, Box<dyn ChromaError>> {
+        let query = self.normalization.normalize(query);
+        let tokens = {
+            let mut tokenizer = self.tokenizer.lock();
+            let binding = tokenizer.encode(&query);
+            binding.get_tokens().clone()
+        };
+        if tokens.is_empty() {
+            return Ok(HashMap::new());
+        }
 
         // Get query tokens sorted by frequency.
         let mut token_frequencies: Vec<(String, u32)> = vec![];
-        for token in tokens {
+        for token in tokens.iter() {
             // TODO better error matching (NotFoundError should return Ok(vec![])) but some others should error.
             let res = self
                 .frequencies_blockfile_reader
                 .get_by_prefix(token.text.as_str())
                 .await?;
             if res.len() == 0 {
-                return Ok(vec![]);
+                return Ok(HashMap::new());
             }
             if res.len() > 1 {
                 return Err(Box::new(FullTextIndexError::MultipleTokenFrequencies));
@@ -239,22 +407,15 @@ impl<'me> FullTextIndexReader<'me> {
         }
 
         // Iterate through the rest of the tokens, intersecting the posting lists with the candidates.
-        let mut token_offset = 0;
-        for (token, _) in token_frequencies[1..].iter() {
-            token_offset += 1;
+        for (token_index, (token, _)) in token_frequencies.iter().enumerate().skip(1) {
+            // Tokens are ordered per the query, so the offset of this token
+            // relative to the start of the query is known from the tokenizer.
+            let token_offset = tokens[token_index].offset_from as i32 - first_token_offset;
             let positional_posting_list = self
                 .posting_lists_blockfile_reader
                 .get_by_prefix(token.as_str())
                 .await
                 .unwrap();
-            // TODO once we sort by frequency, we need to find the token position
-            // here, taking into account which positions for repeats of the same
-            // token have already been used up.
-            // let token_offset = tokens
-            //     .iter()
-            //     .find(|t| t.text == *token)
-            //     .unwrap()
-            //     .offset_from as i32;
             let mut new_candidates: HashMap<u32, Vec<i32>> = HashMap::new();
             for (doc_id, positions) in candidates.iter() {
                 let mut new_positions = vec![];
@@ -289,16 +450,174 @@ impl<'me> FullTextIndexReader<'me> {
                 }
             }
             if new_candidates.is_empty() {
-                return Ok(vec![]);
+                return Ok(HashMap::new());
             }
             candidates = new_candidates;
         }
 
+        Ok(candidates)
+    }
+
+    /// Evaluate a parsed document query against the index.
+    /// # Returns
+    /// The sorted doc IDs of the documents matching the query.
+    pub fn search_query<'query>(
+        &'query self,
+        query: &'query DocumentQuery,
+    ) -> BoxFuture<'query, Result<Vec<i32>, Box<dyn ChromaError>>> {
+        Box::pin(async move {
+            match query {
+                DocumentQuery::Phrase(phrase) => {
+                    // Tokens absent from the index surface as not found errors from the
+                    // blockfile, within a boolean query they simply match nothing.
+                    let mut results: Vec<i32> = match self.match_phrase(phrase).await {
+                        Ok(candidates) => candidates.keys().map(|doc_id| *doc_id as i32).collect(),
+                        Err(e) if BlockfileError::is_not_found(&*e) => vec![],
+                        Err(e) => return Err(e),
+                    };
+                    results.sort();
+                    Ok(results)
+                }
+                DocumentQuery::Proximity { terms, slop } => {
+                    self.search_proximity(terms, *slop).await
+                }
+                DocumentQuery::Or(children) => {
+                    let mut results = vec![];
+                    for child in children {
+                        let child_results = self.search_query(child).await?;
+                        results = merge_sorted_vecs_disjunction(results, child_results);
+                    }
+                    Ok(results)
+                }
+                DocumentQuery::And(children) => {
+                    let mut results: Option<Vec<i32>> = None;
+                    let mut excluded = vec![];
+                    for child in children {
+                        match child {
+                            DocumentQuery::Not(negated) => {
+                                let child_results = self.search_query(negated).await?;
+                                excluded = merge_sorted_vecs_disjunction(excluded, child_results);
+                            }
+                            _ => {
+                                let child_results = self.search_query(child).await?;
+                                results = match results {
+                                    Some(results) => {
+                                        Some(merge_sorted_vecs_conjunction(results, child_results))
+                                    }
+                                    None => Some(child_results),
+                                };
+                            }
+                        }
+                    }
+                    match results {
+                        Some(results) => Ok(results
+                            .into_iter()
+                            .filter(|doc_id| excluded.binary_search(doc_id).is_err())
+                            .collect()),
+                        None => Err(Box::new(DocumentQueryParseError::UnboundedNegation)
+                            as Box<dyn ChromaError>),
+                    }
+                }
+                DocumentQuery::Not(_) => {
+                    Err(Box::new(DocumentQueryParseError::UnboundedNegation)
+                        as Box<dyn ChromaError>)
+                }
+            }
+        })
+    }
+
+    // Terms must occur in order, with at most `slop` other words between each
+    // pair of consecutive terms. Words are counted from the whitespace positions
+    // stored in the index, so no access to the document text is needed.
+    async fn search_proximity(
+        &self,
+        terms: &[String],
+        slop: u32,
+    ) -> Result<Vec<i32>, Box<dyn ChromaError>> {
+        let mut term_positions = Vec::with_capacity(terms.len());
+        for term in terms {
+            match self.match_phrase(term).await {
+                Ok(positions) if !positions.is_empty() => term_positions.push(positions),
+                Ok(_) => return Ok(vec![]),
+                Err(e) if BlockfileError::is_not_found(&*e) => return Ok(vec![]),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Lengths are measured in the normalized text, like the stored positions.
+        let term_lengths: Vec<i32> = terms
+            .iter()
+            .map(|term| self.normalization.normalize(term).len() as i32)
+            .collect();
+
+        // Only documents that contain every term can match.
+        let candidates: HashSet<u32> = term_positions[0]
+            .keys()
+            .filter(|doc_id| {
+                term_positions
+                    .iter()
+                    .all(|positions| positions.contains_key(*doc_id))
+            })
+            .copied()
+            .collect();
+        let whitespace = self.whitespace_positions(&candidates).await?;
+
         let mut results = vec![];
-        for (doc_id, _) in candidates.drain() {
-            results.push(doc_id as i32);
+        for doc_id in candidates {
+            let term_starts: Vec<Vec<i32>> = term_positions
+                .iter()
+                .map(|positions| positions[&doc_id].clone())
+                .collect();
+            let boundaries = word_starts(whitespace.get(&doc_id).map(Vec::as_slice).unwrap_or(&[]));
+            if within_proximity(&term_starts, &term_lengths, &boundaries, slop) {
+                results.push(doc_id as i32);
+            }
         }
-        return Ok(results);
+        results.sort();
+        Ok(results)
+    }
+
+    // Returns the sorted byte offsets of the whitespace in each of the documents,
+    // reading the posting list of each separator once. Documents without whitespace
+    // are left out.
+    async fn whitespace_positions(
+        &self,
+        doc_ids: &HashSet<u32>,
+    ) -> Result<HashMap<u32, Vec<i32>>, Box<dyn ChromaError>> {
+        let mut whitespace: HashMap<u32, Vec<i32>> = HashMap::new();
+        for separator in WORD_SEPARATORS {
+            // Absent separators are reported as not found errors.
+            let positional_posting_list = match self
+                .posting_lists_blockfile_reader
+                .get_by_prefix(separator)
+                .await
+            {
+                Ok(positional_posting_list) => positional_posting_list,
+                Err(e) if BlockfileError::is_not_found(&*e) => continue,
+                Err(e) => return Err(e),
+            };
+            for (_, doc_id, positions) in positional_posting_list.iter() {
+                if !doc_ids.contains(doc_id) {
+                    continue;
+                }
+                let doc_whitespace = whitespace.entry(*doc_id).or_default();
+                for position in positions.iter() {
+                    match position {
+                        Some(position) => doc_whitespace.push(position),
+                        None => {
+                            return Err(Box::new(
+                                FullTextIndexError::EmptyValueInPositionalPostingList,
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        for doc_whitespace in whitespace.values_mut() {
+            doc_whitespace.sort();
+            doc_whitespace.dedup();
+        }
+        Ok(whitespace)
     }
 }
 
@@ -307,6 +626,8 @@ mod tests {
     use super::*;
     use crate::blockstore::provider::BlockfileProvider;
     use crate::index::fulltext::tokenizer::TantivyChromaTokenizer;
+    use crate::storage::local::LocalStorage;
+    use crate::storage::Storage;
     use tantivy::tokenizer::NgramTokenizer;
 
     #[test]
@@ -317,8 +638,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let _index =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let _index = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
     }
 
     #[tokio::test]
@@ -332,8 +658,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.write_to_blockfiles().await.unwrap();
         let flusher = index_writer.commit().unwrap();
         flusher.flush().await.unwrap();
@@ -346,7 +677,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let _ = FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let _ = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
     }
 
     #[tokio::test]
@@ -360,8 +696,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("hello world", 1).unwrap();
         index_writer.write_to_blockfiles().await.unwrap();
         let flusher = index_writer.commit().unwrap();
@@ -375,8 +716,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let res = index_reader.search("hello").await.unwrap();
         assert_eq!(res, vec![1]);
@@ -399,8 +744,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("helo", 1).unwrap();
         index_writer.write_to_blockfiles().await.unwrap();
         let flusher = index_writer.commit().unwrap();
@@ -414,8 +764,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let res = index_reader.search("hello").await.unwrap();
         assert!(res.is_empty());
@@ -432,8 +786,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("aaa", 1).unwrap();
         index_writer.add_document("aaaaa", 2).unwrap();
         index_writer.write_to_blockfiles().await.unwrap();
@@ -448,8 +807,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let res = index_reader.search("aaaa").await.unwrap();
         assert_eq!(res, vec![2]);
@@ -466,8 +829,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("hello", 1).unwrap();
         index_writer.write_to_blockfiles().await.unwrap();
         let flusher = index_writer.commit().unwrap();
@@ -481,8 +849,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let res = index_reader.search("helo").await.unwrap();
         assert!(res.is_empty());
@@ -499,8 +871,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("hello world", 1).unwrap();
         index_writer.write_to_blockfiles().await.unwrap();
         let flusher = index_writer.commit().unwrap();
@@ -514,8 +891,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let res = index_reader.search("chroma").await;
         assert!(res.is_err());
@@ -532,8 +913,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("hello world hello", 1).unwrap();
         index_writer.add_document("    hello ", 2).unwrap();
         index_writer.write_to_blockfiles().await.unwrap();
@@ -548,8 +934,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let mut res = index_reader.search("hello").await.unwrap();
         res.sort();
@@ -570,8 +960,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("hello world", 1).unwrap();
         index_writer.add_document("hello", 2).unwrap();
         index_writer.write_to_blockfiles().await.unwrap();
@@ -586,8 +981,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let mut res = index_reader.search("hello").await.unwrap();
         res.sort();
@@ -608,8 +1007,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("hello world", 1).unwrap();
         index_writer.add_document("hello", 2).unwrap();
         index_writer.add_document("world", 3).unwrap();
@@ -626,8 +1030,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let mut res = index_reader.search("hello").await.unwrap();
         res.sort();
@@ -657,8 +1065,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("aaa", 1).unwrap();
         index_writer.add_document("aaaa", 2).unwrap();
         index_writer.add_document("bbb", 3).unwrap();
@@ -676,8 +1089,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let mut res = index_reader.search("aaa").await.unwrap();
         res.sort();
@@ -703,8 +1120,13 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_writer =
-            FullTextIndexWriter::new(pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
         index_writer.add_document("!!!!!", 1).unwrap();
         index_writer.add_document("hello world!!!", 2).unwrap();
         index_writer.add_document(".!.!.!", 3).unwrap();
@@ -720,8 +1142,12 @@ mod tests {
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 1, false).unwrap(),
         )));
-        let mut index_reader =
-            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+        let mut index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
 
         let res = index_reader.search("!!!!!").await.unwrap();
         assert_eq!(res, vec![1]);
@@ -733,4 +1159,341 @@ mod tests {
         let res = index_reader.search(".!.").await.unwrap();
         assert_eq!(res, vec![3]);
     }
+
+    #[tokio::test]
+    async fn test_search_boolean_query() {
+        let provider = BlockfileProvider::new_memory();
+        let pl_blockfile_writer = provider.create::<u32, &Int32Array>().unwrap();
+        let freq_blockfile_writer = provider.create::<u32, &str>().unwrap();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
+        index_writer.add_document("hello world", 1).unwrap();
+        index_writer.add_document("hello", 2).unwrap();
+        index_writer.add_document("world", 3).unwrap();
+        index_writer.add_document("goodbye world", 4).unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let pl_blockfile_reader = provider
+            .open::<u32, Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
+
+        let query = DocumentQuery::parse("\"hello\" AND \"world\"").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1]);
+
+        let query = DocumentQuery::parse("\"hello\" OR \"goodbye\"").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1, 2, 4]);
+
+        let query = DocumentQuery::parse("\"world\" NOT \"hello\"").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![3, 4]);
+
+        let query = DocumentQuery::parse("(\"hello\" OR \"goodbye\") AND \"world\"").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1, 4]);
+
+        let query = DocumentQuery::parse("\"hello\" OR \"chroma\"").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1, 2]);
+
+        let query = DocumentQuery::parse("\"chroma\"").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_proximity_query() {
+        let provider = BlockfileProvider::new_memory();
+        let pl_blockfile_writer = provider.create::<u32, &Int32Array>().unwrap();
+        let freq_blockfile_writer = provider.create::<u32, &str>().unwrap();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
+        index_writer.add_document("the quick brown fox", 1).unwrap();
+        index_writer
+            .add_document("the quick red and brown fox", 2)
+            .unwrap();
+        index_writer.add_document("brown quick", 3).unwrap();
+        index_writer.add_document("quick\n\nbrown", 4).unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let pl_blockfile_reader = provider
+            .open::<u32, Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
+
+        let query = DocumentQuery::parse("\"quick brown\"~0").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1, 4]);
+
+        let query = DocumentQuery::parse("\"quick brown\"~2").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1, 2, 4]);
+
+        let query = DocumentQuery::parse("\"quick fox\"~1").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1]);
+
+        let query = DocumentQuery::parse("\"quick brown\"~2 NOT \"red\"").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![1, 4]);
+
+        let query = DocumentQuery::parse("\"quick chroma\"~5").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_with_normalization() {
+        let provider = BlockfileProvider::new_memory();
+        let pl_blockfile_writer = provider.create::<u32, &Int32Array>().unwrap();
+        let freq_blockfile_writer = provider.create::<u32, &str>().unwrap();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+        let normalization = TextNormalization {
+            case_fold: true,
+            strip_accents: true,
+        };
+
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            normalization,
+        );
+        index_writer.add_document("Paris", 1).unwrap();
+        index_writer.add_document("París in spring", 2).unwrap();
+        index_writer.add_document("paris", 3).unwrap();
+        index_writer.add_document("Berlin", 4).unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let pl_blockfile_reader = provider
+            .open::<u32, Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            normalization,
+        );
+
+        for query in ["Paris", "paris", "PARÍS", "parís"] {
+            let mut res = index_reader.search(query).await.unwrap();
+            res.sort();
+            assert_eq!(res, vec![1, 2, 3]);
+        }
+
+        let query = DocumentQuery::parse("\"PARIS SPRING\"~1").unwrap();
+        let res = index_reader.search_query(&query).await.unwrap();
+        assert_eq!(res, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_search_with_offsets() {
+        let provider = BlockfileProvider::new_memory();
+        let pl_blockfile_writer = provider.create::<u32, &Int32Array>().unwrap();
+        let freq_blockfile_writer = provider.create::<u32, &str>().unwrap();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
+        index_writer.add_document("hello world", 1).unwrap();
+        index_writer.add_document("world, hello world", 2).unwrap();
+        index_writer.add_document("hello", 3).unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let pl_blockfile_reader = provider
+            .open::<u32, Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
+
+        let res = index_reader.search_with_offsets("world").await.unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res.get(&1).unwrap(), &vec![(6, 11)]);
+        assert_eq!(res.get(&2).unwrap(), &vec![(0, 5), (13, 18)]);
+
+        let res = index_reader.search_with_offsets("o w").await.unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res.get(&1).unwrap(), &vec![(4, 7)]);
+        assert_eq!(res.get(&2).unwrap(), &vec![(11, 14)]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_update_documents_in_fork() {
+        // The memory provider does not support forking.
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let provider = BlockfileProvider::new_arrow(storage);
+        let pl_blockfile_writer = provider.create::<u32, &Int32Array>().unwrap();
+        let freq_blockfile_writer = provider.create::<u32, u32>().unwrap();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            None,
+            tokenizer,
+            TextNormalization::default(),
+        );
+        index_writer.add_document("hello world", 1).unwrap();
+        index_writer.add_document("hello there", 2).unwrap();
+        index_writer.add_document("world peace", 3).unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        // Fork the index and rewrite it, as compaction does.
+        let pl_blockfile_writer = provider
+            .fork::<u32, &Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let freq_blockfile_writer = provider.fork::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.ok();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer = FullTextIndexWriter::new(
+            pl_blockfile_writer,
+            freq_blockfile_writer,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
+        index_writer.delete_document("hello world", 1).unwrap();
+        index_writer
+            .update_document("hello there", "goodbye", 2)
+            .unwrap();
+        // Added and deleted within the same fork.
+        index_writer.add_document("hello again", 4).unwrap();
+        index_writer.delete_document("hello again", 4).unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let pl_blockfile_reader = provider
+            .open::<u32, Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let index_reader = FullTextIndexReader::new(
+            pl_blockfile_reader,
+            freq_blockfile_reader,
+            tokenizer,
+            TextNormalization::default(),
+        );
+
+        // Stale text no longer matches.
+        let res = index_reader.search("hello").await.unwrap();
+        assert!(res.is_empty());
+        let res = index_reader.search("there").await.unwrap();
+        assert!(res.is_empty());
+        let res = index_reader.search("world").await.unwrap();
+        assert_eq!(res, vec![3]);
+        let res = index_reader.search("goodbye").await.unwrap();
+        assert_eq!(res, vec![2]);
+
+        // Each token keeps a single frequency entry.
+        let res = index_reader
+            .frequencies_blockfile_reader
+            .get_by_prefix("o")
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        // "world peace" and "goodbye"
+        assert_eq!(res[0].1, 3);
+        let res = index_reader
+            .frequencies_blockfile_reader
+            .get_by_prefix("h")
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+    }
 }
@@ -1,13 +1,30 @@
 use std::ffi::CString;
-use std::ffi::{c_char, c_int};
+use std::ffi::{c_char, c_int, c_void};
 
 use crate::errors::{ChromaError, ErrorCodes};
 
-use super::{Index, IndexConfig, PersistentIndex};
-use crate::types::{Metadata, MetadataValue, MetadataValueConversionError, Segment};
+use super::utils::normalize;
+use super::{
+    Index, IndexConfig, IndexFilter, NativeHnswIndex, PersistentIndex, ScalarQuantizer,
+    ScalarQuantizerError, DATA_LEVEL0_FILE, HEADER_FILE, LENGTH_FILE, LINK_LISTS_FILE,
+};
+use crate::distance::DistanceFunction;
+use crate::segment::schema::{HnswSegmentConfig, SegmentConfigError};
+use crate::types::Segment;
+use parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use thiserror::Error;
 use uuid::Uuid;
 
+// The manifest is written next to the index files. Bump the version when the
+// manifest or the index files change in a way older readers cannot handle.
+pub(crate) const HNSW_MANIFEST_FILE: &str = "manifest.json";
+const HNSW_MANIFEST_VERSION: u32 = 1;
+
 // https://doc.rust-lang.org/nomicon/ffi.html#representing-opaque-structs
 #[repr(C)]
 struct IndexPtrFFI {
@@ -18,7 +35,6 @@ struct IndexPtrFFI {
 // TODO: Make this config:
 // - Watchable - for dynamic updates
 // - Have a notion of static vs dynamic config
-// - Have a notion of default config
 // - HNSWIndex should store a ref to the config so it can look up the config values.
 //   deferring this for a config pass
 #[derive(Clone, Debug)]
@@ -29,19 +45,87 @@ pub(crate) struct HnswIndexConfig {
     pub(crate) ef_search: usize,
     pub(crate) random_seed: usize,
     pub(crate) persist_path: String,
+    pub(crate) implementation: HnswImplementation,
+    // The factor the capacity grows by when adds would exceed it
+    pub(crate) resize_factor: f64,
+}
+
+pub(crate) const DEFAULT_RESIZE_FACTOR: f64 = 1.2;
+
+// The fraction of deleted elements at which compaction rebuilds an index
+pub(crate) const DEFAULT_VACUUM_THRESHOLD: f64 = 0.2;
+
+// Queries whose filter allows at most this fraction of the elements scan the allowed
+// elements instead of searching the graph, which visits many filtered out elements
+// before it finds k allowed ones.
+const BRUTE_FORCE_SELECTIVITY: f64 = 0.01;
+
+/// The search parameters of a single query. Unlike set_ef() they do not change the index,
+/// which is shared by concurrent queries.
+/// # Fields
+/// - `ef` - The number of candidates the search keeps, the ef_search of the index if
+///   None. hnswlib always keeps at least the ef_search of the index, so with hnswlib
+///   the ef of a query can only raise it, an ef below it has no effect. The native
+///   index searches with the ef of the query either way.
+/// - `exact` - Whether to compare the query to every element instead of searching the
+///   graph.
+/// - `nprobe` - The number of lists an ivf segment searches, the nprobe of the segment
+///   if None. Other segments ignore it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct HnswSearchParams {
+    pub(crate) ef: Option<usize>,
+    pub(crate) exact: bool,
+    pub(crate) nprobe: Option<usize>,
+}
+
+/// Whether a query with the filter should scan the allowed elements of an index with
+/// len elements rather than search the graph.
+pub(super) fn should_brute_force(filter: &IndexFilter, len: usize) -> bool {
+    match filter.allowed {
+        Some(allowed) => allowed.len() as f64 <= BRUTE_FORCE_SELECTIVITY * len as f64,
+        None => false,
+    }
+}
+
+// Called by hnswlib for every element the search visits, with the filter as the context
+extern "C" fn filter_allows(context: *const c_void, id: usize) -> bool {
+    let filter = unsafe { &*(context as *const IndexFilter) };
+    filter.allows(id)
+}
+
+/// The implementation backing an HnswIndex, selected per segment with the
+/// `hnsw:implementation` metadata key. Both read and write the same files.
+/// # Variants
+/// - `Hnswlib` - The C++ hnswlib through its bindings. Its calls run one at a time, so
+///   queries wait for the adds of a compaction.
+/// - `Native` - The native Rust implementation, see `NativeHnswIndex`, the default.
+///   Queries proceed while it is written.
+/// # Notes
+/// Only the native implementation loads an index from memory or a mapped file, see
+/// `HnswIndex::load_from_buffers`. hnswlib loads from disk, so an hnswlib index in
+/// remote storage is copied to the temporary storage path first.
+/// hnswlib only has the l2, cosine and ip spaces, so indices with any other distance
+/// function, such as hamming, always use the native implementation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum HnswImplementation {
+    Hnswlib,
+    Native,
 }
 
 #[derive(Error, Debug)]
 pub(crate) enum HnswIndexFromSegmentError {
     #[error("Missing config `{0}`")]
     MissingConfig(String),
-    #[error("Invalid metadata value")]
-    MetadataValueError(#[from] MetadataValueConversionError),
+    #[error(transparent)]
+    InvalidConfig(#[from] SegmentConfigError),
 }
 
 impl ChromaError for HnswIndexFromSegmentError {
     fn code(&self) -> ErrorCodes {
-        crate::errors::ErrorCodes::InvalidArgument
+        match self {
+            HnswIndexFromSegmentError::MissingConfig(_) => ErrorCodes::InvalidArgument,
+            HnswIndexFromSegmentError::InvalidConfig(e) => e.code(),
+        }
     }
 }
 
@@ -58,77 +142,470 @@ impl HnswIndexConfig {
                 )))
             }
         };
-        let metadata = match &segment.metadata {
-            Some(metadata) => metadata,
-            None => {
-                // TODO: This should error, but the configuration is not stored correctly
-                // after the configuration is refactored to be always stored and doesn't rely on defaults we can fix this
-                return Ok(HnswIndexConfig {
-                    max_elements: 1000,
-                    m: 16,
-                    ef_construction: 100,
-                    ef_search: 10,
-                    random_seed: 0,
-                    persist_path: persist_path.to_string(),
-                });
-                // return Err(Box::new(HnswIndexFromSegmentError::MissingConfig(
-                //     "metadata".to_string(),
-                // )))
-            }
+        let config = match HnswSegmentConfig::from_segment(segment) {
+            Ok(config) => config,
+            Err(e) => return Err(Box::new(HnswIndexFromSegmentError::InvalidConfig(e))),
         };
+        Ok(HnswIndexConfig {
+            max_elements: config.max_elements,
+            m: config.m,
+            ef_construction: config.ef_construction,
+            ef_search: config.ef_search,
+            random_seed: 0,
+            persist_path: persist_path.to_string(),
+            implementation: config.implementation,
+            resize_factor: config.resize_factor,
+        })
+    }
+}
 
-        fn get_metadata_value_as<'a, T>(
-            metadata: &'a Metadata,
-            key: &str,
-        ) -> Result<T, Box<HnswIndexFromSegmentError>>
-        where
-            T: TryFrom<&'a MetadataValue, Error = MetadataValueConversionError>,
-        {
-            let res = match metadata.get(key) {
-                Some(value) => T::try_from(value),
-                None => {
-                    return Err(Box::new(HnswIndexFromSegmentError::MissingConfig(
-                        key.to_string(),
-                    )))
-                }
-            };
-            match res {
-                Ok(value) => Ok(value),
-                Err(e) => Err(Box::new(HnswIndexFromSegmentError::MetadataValueError(e))),
+/// The float rerank stage of a hamming index, selected per segment with the
+/// `hnsw:rerank_space` metadata key, and `hnsw:rerank_factor`.
+/// # Description
+/// Hamming distances over binary codes are a cheap first stage. With reranking,
+/// queries take rerank_factor * k candidates by hamming distance and rerank them by
+/// their distance in the rerank space, computed from the full vectors.
+/// # Fields
+/// - `rerank_factor` - The number of candidates per result.
+/// - `distance_function` - The distance function to rerank with, any but hamming.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HnswRerankConfig {
+    pub(crate) rerank_factor: usize,
+    pub(crate) distance_function: DistanceFunction,
+}
+
+/// The manifest of a persisted index.
+/// # Description
+/// Records the configuration an index was built with, so that an index is not
+/// loaded with a configuration it was not built with.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HnswIndexManifest {
+    pub(crate) version: u32,
+    pub(crate) dimensionality: i32,
+    pub(crate) space: String,
+    pub(crate) m: usize,
+    pub(crate) ef_construction: usize,
+    pub(crate) ef_search: usize,
+    pub(crate) random_seed: usize,
+    pub(crate) element_count: usize,
+    // The bounds of the scalar quantizer of a quantized index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) quantizer: Option<HnswQuantizerManifest>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HnswQuantizerManifest {
+    pub(crate) min: Vec<f32>,
+    pub(crate) max: Vec<f32>,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum HnswIndexManifestError {
+    #[error("IO error")]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid manifest")]
+    ParseError(#[from] serde_json::Error),
+    #[error("Unsupported manifest version {0}")]
+    UnsupportedVersion(u32),
+    #[error("No manifest found at `{0}`")]
+    MissingManifest(String),
+    #[error("Index was built with {0} {1}, but is configured with {2}")]
+    ConfigMismatch(String, String, String),
+    #[error("Manifest records {0} elements, but the index has {1}")]
+    ElementCountMismatch(usize, usize),
+}
+
+impl ChromaError for HnswIndexManifestError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            HnswIndexManifestError::IOError(_) => ErrorCodes::Internal,
+            HnswIndexManifestError::ParseError(_) => ErrorCodes::Internal,
+            HnswIndexManifestError::UnsupportedVersion(_) => ErrorCodes::Internal,
+            HnswIndexManifestError::MissingManifest(_) => ErrorCodes::NotFound,
+            HnswIndexManifestError::ConfigMismatch(_, _, _) => ErrorCodes::FailedPrecondition,
+            HnswIndexManifestError::ElementCountMismatch(_, _) => ErrorCodes::DataLoss,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum HnswIndexQuantizationError {
+    #[error("Only the native implementation can be quantized")]
+    UnsupportedImplementation,
+    #[error("Hamming indices can not be quantized")]
+    UnsupportedDistanceFunction,
+    #[error(transparent)]
+    InvalidQuantizer(#[from] ScalarQuantizerError),
+}
+
+impl ChromaError for HnswIndexQuantizationError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            HnswIndexQuantizationError::UnsupportedImplementation => ErrorCodes::InvalidArgument,
+            HnswIndexQuantizationError::UnsupportedDistanceFunction => ErrorCodes::InvalidArgument,
+            HnswIndexQuantizationError::InvalidQuantizer(e) => e.code(),
+        }
+    }
+}
+
+impl HnswIndexManifest {
+    /// Read the manifest in the index directory. Indices persisted before manifests
+    /// were introduced have none.
+    pub(crate) fn read(directory: &Path) -> Result<Option<Self>, HnswIndexManifestError> {
+        let bytes = match std::fs::read(directory.join(HNSW_MANIFEST_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(HnswIndexManifestError::IOError(e)),
+        };
+        HnswIndexManifest::from_bytes(&bytes).map(Some)
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, HnswIndexManifestError> {
+        let manifest: HnswIndexManifest = serde_json::from_slice(bytes)?;
+        if manifest.version > HNSW_MANIFEST_VERSION {
+            return Err(HnswIndexManifestError::UnsupportedVersion(manifest.version));
+        }
+        Ok(manifest)
+    }
+
+    fn write(&self, directory: &Path) -> Result<(), HnswIndexManifestError> {
+        let bytes = serde_json::to_vec_pretty(self)?;
+        std::fs::write(directory.join(HNSW_MANIFEST_FILE), bytes)?;
+        Ok(())
+    }
+
+    /// Check that the index is configured the way it was built. The search ef and the
+    /// seed may change between builds and queries, the rest of the configuration may not.
+    pub(crate) fn validate(
+        &self,
+        index_config: &IndexConfig,
+        hnsw_config: Option<&HnswIndexConfig>,
+    ) -> Result<(), HnswIndexManifestError> {
+        fn check<T: PartialEq + ToString>(
+            name: &str,
+            built: T,
+            configured: T,
+        ) -> Result<(), HnswIndexManifestError> {
+            match built == configured {
+                true => Ok(()),
+                false => Err(HnswIndexManifestError::ConfigMismatch(
+                    name.to_string(),
+                    built.to_string(),
+                    configured.to_string(),
+                )),
             }
         }
+        check(
+            "dimensionality",
+            self.dimensionality,
+            index_config.dimensionality,
+        )?;
+        let space: String = index_config.distance_function.clone().into();
+        check("space", self.space.as_str(), space.as_str())?;
+        if let Some(hnsw_config) = hnsw_config {
+            check("M", self.m, hnsw_config.m)?;
+            check(
+                "ef_construction",
+                self.ef_construction,
+                hnsw_config.ef_construction,
+            )?;
+        }
+        Ok(())
+    }
+}
 
-        let max_elements = get_metadata_value_as::<i32>(metadata, "hsnw:max_elements")?;
-        let m = get_metadata_value_as::<i32>(metadata, "hnsw:m")?;
-        let ef_construction = get_metadata_value_as::<i32>(metadata, "hnsw:ef_construction")?;
-        let ef_search = get_metadata_value_as::<i32>(metadata, "hnsw:ef_search")?;
-        return Ok(HnswIndexConfig {
-            max_elements: max_elements as usize,
-            m: m as usize,
-            ef_construction: ef_construction as usize,
-            ef_search: ef_search as usize,
-            random_seed: 0,
-            persist_path: persist_path.to_string(),
-        });
+/// A file of a persisted index, read into memory or mapped from disk.
+pub(crate) enum HnswIndexBuffer {
+    Bytes(Vec<u8>),
+    Mmap(memmap2::Mmap),
+}
+
+impl Deref for HnswIndexBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            HnswIndexBuffer::Bytes(bytes) => bytes.as_slice(),
+            HnswIndexBuffer::Mmap(mmap) => &mmap[..],
+        }
+    }
+}
+
+/// The files of a persisted index, which an index can be loaded from without a
+/// directory of its own.
+pub(crate) struct HnswIndexBuffers {
+    pub(crate) header: HnswIndexBuffer,
+    pub(crate) data_level0: HnswIndexBuffer,
+    pub(crate) length: HnswIndexBuffer,
+    pub(crate) link_lists: HnswIndexBuffer,
+    // Indices persisted before manifests were introduced have none
+    pub(crate) manifest: Option<HnswIndexBuffer>,
+}
+
+impl HnswIndexBuffers {
+    /// Read the files in the index directory into memory.
+    pub(crate) fn read(directory: &Path) -> Result<Self, std::io::Error> {
+        HnswIndexBuffers::open(directory, |path| {
+            Ok(HnswIndexBuffer::Bytes(std::fs::read(path)?))
+        })
+    }
+
+    /// Map the files in the index directory into memory.
+    /// # Notes
+    /// The files must not be modified or truncated while they are mapped.
+    pub(crate) fn mmap(directory: &Path) -> Result<Self, std::io::Error> {
+        HnswIndexBuffers::open(directory, |path| {
+            let file = std::fs::File::open(path)?;
+            let mmap = unsafe { memmap2::Mmap::map(&file)? };
+            Ok(HnswIndexBuffer::Mmap(mmap))
+        })
+    }
+
+    fn open(
+        directory: &Path,
+        open_file: impl Fn(&Path) -> Result<HnswIndexBuffer, std::io::Error>,
+    ) -> Result<Self, std::io::Error> {
+        let manifest = match open_file(&directory.join(HNSW_MANIFEST_FILE)) {
+            Ok(manifest) => Some(manifest),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(HnswIndexBuffers {
+            header: open_file(&directory.join(HEADER_FILE))?,
+            data_level0: open_file(&directory.join(DATA_LEVEL0_FILE))?,
+            length: open_file(&directory.join(LENGTH_FILE))?,
+            link_lists: open_file(&directory.join(LINK_LISTS_FILE))?,
+            manifest,
+        })
+    }
+}
+
+// An index of the C++ HnswIndex class, freed when dropped. hnswlib does not support
+// searching while elements are added, so the index is not Sync and is kept behind a
+// mutex, which runs its calls one at a time.
+struct HnswlibIndex(*const IndexPtrFFI);
+
+// SAFETY: The index is owned by the handle and not tied to the thread that created it.
+unsafe impl Send for HnswlibIndex {}
+
+impl Drop for HnswlibIndex {
+    fn drop(&mut self) {
+        unsafe { free_index(self.0) }
+    }
+}
+
+impl HnswlibIndex {
+    fn add(&mut self, id: usize, vector: &[f32]) {
+        unsafe { add_item(self.0, vector.as_ptr(), id, false) }
+    }
+
+    fn delete(&mut self, id: usize) {
+        unsafe { mark_deleted(self.0, id) }
+    }
+
+    fn get(&self, id: usize, dimensionality: usize) -> Vec<f32> {
+        let mut data: Vec<f32> = vec![0.0f32; dimensionality];
+        unsafe { get_item(self.0, id, data.as_mut_ptr()) };
+        data
+    }
+
+    // Search the graph for the k nearest neighbors that pass the filter, returns the
+    // number of results written to ids and distances
+    fn knn_query(
+        &self,
+        vector: &[f32],
+        ef: usize,
+        ids: &mut [usize],
+        distances: &mut [f32],
+        filter: &IndexFilter,
+    ) -> usize {
+        let (callback, context) = filter_callback(filter);
+        unsafe {
+            knn_query(
+                self.0,
+                vector.as_ptr(),
+                ids.len(),
+                ef,
+                ids.as_mut_ptr(),
+                distances.as_mut_ptr(),
+                callback,
+                context,
+            ) as usize
+        }
+    }
+
+    // Compare the query to the given labels, or every element if there are none
+    fn brute_force_query(
+        &self,
+        vector: &[f32],
+        ids: &mut [usize],
+        distances: &mut [f32],
+        labels: Option<&[usize]>,
+        filter: &IndexFilter,
+    ) -> usize {
+        let (callback, context) = filter_callback(filter);
+        let (labels_ptr, labels_len) = match labels {
+            Some(labels) => (labels.as_ptr(), labels.len()),
+            None => (std::ptr::null(), 0),
+        };
+        unsafe {
+            brute_force_query(
+                self.0,
+                vector.as_ptr(),
+                ids.len(),
+                ids.as_mut_ptr(),
+                distances.as_mut_ptr(),
+                labels_ptr,
+                labels_len,
+                callback,
+                context,
+            ) as usize
+        }
+    }
+
+    fn get_ef(&self) -> usize {
+        unsafe { get_ef(self.0) as usize }
+    }
+
+    fn len(&self) -> usize {
+        unsafe { len(self.0) as usize }
+    }
+
+    fn capacity(&self) -> usize {
+        unsafe { get_max_elements(self.0) }
+    }
+
+    fn len_with_deleted(&self) -> usize {
+        unsafe { get_current_count(self.0) }
+    }
+
+    fn set_ef(&mut self, ef: usize) {
+        unsafe { set_ef(self.0, ef as c_int) }
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        unsafe { resize_index(self.0, new_size) }
+    }
+
+    fn persist_dirty(&mut self) {
+        unsafe { persist_dirty(self.0) }
+    }
+}
+
+// The filter callback and its context for the filter, none if it lets every id through
+fn filter_callback(filter: &IndexFilter) -> (Option<FilterCallback>, *const c_void) {
+    match filter.is_unfiltered() {
+        true => (None, std::ptr::null()),
+        false => (
+            Some(filter_allows),
+            filter as *const IndexFilter as *const c_void,
+        ),
+    }
+}
+
+enum HnswIndexBackend {
+    Hnswlib(Mutex<HnswlibIndex>),
+    Native(NativeHnswIndex),
+}
+
+impl HnswIndexBackend {
+    fn get_ef(&self) -> usize {
+        match self {
+            HnswIndexBackend::Hnswlib(index) => index.lock().get_ef(),
+            HnswIndexBackend::Native(index) => index.get_ef(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            HnswIndexBackend::Hnswlib(index) => index.lock().len(),
+            HnswIndexBackend::Native(index) => index.len(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            HnswIndexBackend::Hnswlib(index) => index.lock().capacity(),
+            HnswIndexBackend::Native(index) => index.capacity(),
+        }
+    }
+
+    fn len_with_deleted(&self) -> usize {
+        match self {
+            HnswIndexBackend::Hnswlib(index) => index.lock().len_with_deleted(),
+            HnswIndexBackend::Native(index) => index.len_with_deleted(),
+        }
+    }
+
+    fn quantizer(&self) -> Option<ScalarQuantizer> {
+        match self {
+            HnswIndexBackend::Hnswlib(_) => None,
+            HnswIndexBackend::Native(index) => index.quantizer(),
+        }
+    }
+
+    fn resize(&mut self, new_size: usize) -> Result<(), Box<dyn ChromaError>> {
+        let current_count = self.len_with_deleted();
+        if new_size < current_count {
+            return Err(Box::new(HnswIndexResizeError::TooSmall(
+                new_size,
+                current_count,
+            )));
+        }
+        match self {
+            HnswIndexBackend::Hnswlib(index) => index.get_mut().resize(new_size),
+            HnswIndexBackend::Native(index) => index.resize(new_size),
+        }
+        Ok(())
     }
 }
 
-#[repr(C)]
 /// The HnswIndex struct.
 /// # Description
-/// This struct wraps a pointer to the C++ HnswIndex class and presents a safe Rust interface.
+/// This struct wraps either an index of the C++ HnswIndex class or a NativeHnswIndex
+/// and presents a safe Rust interface.
 /// # Notes
-/// This struct is not thread safe for concurrent reads and writes. Callers should
-/// synchronize access to the index between reads and writes.
+/// The index can be shared between threads without an outer lock. The native index,
+/// the default, applies adds and deletes one at a time, and queries run concurrently
+/// with each other and only wait for the writes of an add, not for its search for
+/// neighbors, see `NativeHnswIndex`. hnswlib can not search while it adds, so the calls
+/// to an hnswlib index run one at a time. Resizing, saving and setting the search ef
+/// wait for the calls in progress and block new ones until they are done.
 pub(crate) struct HnswIndex {
-    ffi_ptr: *const IndexPtrFFI,
+    // Held for reading by adds, deletes and queries, and for writing by the calls that
+    // may not run concurrently with them. An hnswlib index is further locked per call.
+    backend: RwLock<HnswIndexBackend>,
     dimensionality: i32,
+    distance_function: DistanceFunction,
+    config: HnswIndexConfig,
+    cold_start: ColdStart,
     pub(crate) id: Uuid,
 }
 
-// Make index sync, we should wrap index so that it is sync in the way we expect but for now this implements the trait
-unsafe impl Sync for HnswIndex {}
-unsafe impl Send for HnswIndex {}
+// Times the first query against an index opened to be queried, from when it started
+// loading, which is the latency of a query that misses the cache.
+#[derive(Default)]
+struct ColdStart {
+    opened_at: Option<Instant>,
+    reported: AtomicBool,
+}
+
+impl ColdStart {
+    fn report(&self, id: Uuid) {
+        let opened_at = match self.opened_at {
+            Some(opened_at) => opened_at,
+            None => return,
+        };
+        if self.reported.load(Ordering::Relaxed) || self.reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        tracing::info!(
+            index_id = %id,
+            first_query_ms = opened_at.elapsed().as_millis() as u64,
+            "First query against hnsw index"
+        );
+    }
+}
 
 #[derive(Error, Debug)]
 
@@ -155,6 +632,17 @@ impl Index<HnswIndexConfig> for HnswIndex {
     ) -> Result<Self, Box<dyn ChromaError>> {
         match hnsw_config {
             None => return Err(Box::new(HnswIndexInitError::NoConfigProvided)),
+            Some(config) if HnswIndex::uses_native(index_config, config) => {
+                let index = NativeHnswIndex::init(index_config, Some(config), id)?;
+                Ok(HnswIndex {
+                    backend: RwLock::new(HnswIndexBackend::Native(index)),
+                    dimensionality: index_config.dimensionality,
+                    distance_function: index_config.distance_function.clone(),
+                    config: config.clone(),
+                    cold_start: ColdStart::default(),
+                    id,
+                })
+            }
             Some(config) => {
                 let distance_function_string: String =
                     index_config.distance_function.clone().into();
@@ -168,8 +656,9 @@ impl Index<HnswIndexConfig> for HnswIndex {
                     }
                 };
 
-                let ffi_ptr =
-                    unsafe { create_index(space_name.as_ptr(), index_config.dimensionality) };
+                let index = HnswlibIndex(unsafe {
+                    create_index(space_name.as_ptr(), index_config.dimensionality)
+                });
 
                 let path = match CString::new(config.persist_path.clone()) {
                     Ok(path) => path,
@@ -178,7 +667,7 @@ impl Index<HnswIndexConfig> for HnswIndex {
 
                 unsafe {
                     init_index(
-                        ffi_ptr,
+                        index.0,
                         config.max_elements,
                         config.m,
                         config.ef_construction,
@@ -190,8 +679,11 @@ impl Index<HnswIndexConfig> for HnswIndex {
                 }
 
                 let hnsw_index = HnswIndex {
-                    ffi_ptr: ffi_ptr,
+                    backend: RwLock::new(HnswIndexBackend::Hnswlib(Mutex::new(index))),
                     dimensionality: index_config.dimensionality,
+                    distance_function: index_config.distance_function.clone(),
+                    config: config.clone(),
+                    cold_start: ColdStart::default(),
                     id,
                 };
                 hnsw_index.set_ef(config.ef_search);
@@ -201,37 +693,319 @@ impl Index<HnswIndexConfig> for HnswIndex {
     }
 
     fn add(&self, id: usize, vector: &[f32]) {
-        unsafe { add_item(self.ffi_ptr, vector.as_ptr(), id, false) }
+        match &*self.backend.read() {
+            HnswIndexBackend::Hnswlib(index) => index.lock().add(id, vector),
+            HnswIndexBackend::Native(index) => index.add(id, vector),
+        }
     }
 
     fn delete(&self, id: usize) {
-        unsafe { mark_deleted(self.ffi_ptr, id) }
+        match &*self.backend.read() {
+            HnswIndexBackend::Hnswlib(index) => index.lock().delete(id),
+            HnswIndexBackend::Native(index) => index.delete(id),
+        }
+    }
+
+    fn query(&self, vector: &[f32], k: usize, filter: &IndexFilter) -> (Vec<usize>, Vec<f32>) {
+        self.query_with_params(vector, k, filter, &HnswSearchParams::default())
+    }
+
+    fn get(&self, id: usize) -> Option<Vec<f32>> {
+        match &*self.backend.read() {
+            HnswIndexBackend::Hnswlib(index) => {
+                Some(index.lock().get(id, self.dimensionality as usize))
+            }
+            HnswIndexBackend::Native(index) => index.get(id),
+        }
+    }
+}
+
+impl PersistentIndex<HnswIndexConfig> for HnswIndex {
+    fn save(&self) -> Result<(), Box<dyn ChromaError>> {
+        let mut backend = self.backend.write();
+        match &mut *backend {
+            HnswIndexBackend::Hnswlib(index) => index.get_mut().persist_dirty(),
+            HnswIndexBackend::Native(index) => index.save()?,
+        }
+        let manifest = HnswIndexManifest {
+            version: HNSW_MANIFEST_VERSION,
+            dimensionality: self.dimensionality,
+            space: self.distance_function.clone().into(),
+            m: self.config.m,
+            ef_construction: self.config.ef_construction,
+            ef_search: backend.get_ef(),
+            random_seed: self.config.random_seed,
+            element_count: backend.len(),
+            quantizer: backend.quantizer().map(|quantizer| HnswQuantizerManifest {
+                min: quantizer.min().to_vec(),
+                max: quantizer.max().to_vec(),
+            }),
+        };
+        match manifest.write(Path::new(&self.config.persist_path)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Load the index saved at path. The manifest saved with the index is required
+    /// and validated against the index config.
+    fn load(
+        path: &str,
+        index_config: &IndexConfig,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        HnswIndex::load_with_config(path, index_config, None, id)
+    }
+}
+
+impl HnswIndex {
+    /// Load the index saved at path with the given configuration, which must match the
+    /// configuration recorded in the manifest. Indices saved before manifests existed
+    /// are loaded with the given configuration as is. Either implementation can load
+    /// files saved by the other.
+    pub(crate) fn load_with_config(
+        path: &str,
+        index_config: &IndexConfig,
+        hnsw_config: Option<&HnswIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        let manifest = match HnswIndexManifest::read(Path::new(path)) {
+            Ok(manifest) => manifest,
+            Err(e) => return Err(Box::new(e)),
+        };
+        let config =
+            HnswIndex::config_from_manifest(manifest.as_ref(), path, index_config, hnsw_config)?;
+        let index = HnswIndex::load_backend(path, index_config, config, id)?;
+        HnswIndex::validate_loaded(index, manifest)
+    }
+
+    /// Load the index from the files of a persisted index, with the given configuration
+    /// as in load_with_config. The index is saved to path.
+    /// # Notes
+    /// hnswlib can only load an index from disk, so an index configured to use hnswlib
+    /// is loaded from the files at path instead, which must hold the persisted index.
+    pub(crate) fn load_from_buffers(
+        buffers: HnswIndexBuffers,
+        path: &str,
+        index_config: &IndexConfig,
+        hnsw_config: Option<&HnswIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        let manifest = match &buffers.manifest {
+            Some(bytes) => match HnswIndexManifest::from_bytes(bytes) {
+                Ok(manifest) => Some(manifest),
+                Err(e) => return Err(Box::new(e)),
+            },
+            None => None,
+        };
+        let config =
+            HnswIndex::config_from_manifest(manifest.as_ref(), path, index_config, hnsw_config)?;
+        if !HnswIndex::uses_native(index_config, &config) {
+            let index = HnswIndex::load_backend(path, index_config, config, id)?;
+            return HnswIndex::validate_loaded(index, manifest);
+        }
+        let index = match NativeHnswIndex::load_from_buffers(buffers, path, index_config, id) {
+            Ok(index) => index,
+            Err(e) => return Err(Box::new(e)),
+        };
+        let index = HnswIndex {
+            backend: RwLock::new(HnswIndexBackend::Native(index)),
+            dimensionality: index_config.dimensionality,
+            distance_function: index_config.distance_function.clone(),
+            config,
+            cold_start: ColdStart::default(),
+            id,
+        };
+        HnswIndex::validate_loaded(index, manifest)
     }
 
-    fn query(
+    /// Time the first query against the index from the given instant, see ColdStart.
+    pub(crate) fn set_opened_at(&mut self, opened_at: Instant) {
+        self.cold_start.opened_at = Some(opened_at);
+    }
+
+    fn config_from_manifest(
+        manifest: Option<&HnswIndexManifest>,
+        path: &str,
+        index_config: &IndexConfig,
+        hnsw_config: Option<&HnswIndexConfig>,
+    ) -> Result<HnswIndexConfig, Box<dyn ChromaError>> {
+        match (manifest, hnsw_config) {
+            (Some(manifest), hnsw_config) => {
+                if let Err(e) = manifest.validate(index_config, hnsw_config) {
+                    return Err(Box::new(e));
+                }
+                Ok(HnswIndexConfig {
+                    max_elements: manifest.element_count,
+                    m: manifest.m,
+                    ef_construction: manifest.ef_construction,
+                    ef_search: manifest.ef_search,
+                    random_seed: manifest.random_seed,
+                    persist_path: path.to_string(),
+                    implementation: match hnsw_config {
+                        Some(hnsw_config) => hnsw_config.implementation,
+                        None => HnswImplementation::Hnswlib,
+                    },
+                    resize_factor: match hnsw_config {
+                        Some(hnsw_config) => hnsw_config.resize_factor,
+                        None => DEFAULT_RESIZE_FACTOR,
+                    },
+                })
+            }
+            (None, Some(hnsw_config)) => Ok(HnswIndexConfig {
+                persist_path: path.to_string(),
+                ..hnsw_config.clone()
+            }),
+            (None, None) => Err(Box::new(HnswIndexManifestError::MissingManifest(
+                path.to_string(),
+            ))),
+        }
+    }
+
+    // Restore the search ef and the quantizer, and check the loaded index against its
+    // manifest
+    fn validate_loaded(
+        index: HnswIndex,
+        manifest: Option<HnswIndexManifest>,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        index.set_ef(index.config.ef_search);
+        if let Some(manifest) = manifest {
+            if manifest.element_count != index.len() {
+                return Err(Box::new(HnswIndexManifestError::ElementCountMismatch(
+                    manifest.element_count,
+                    index.len(),
+                )));
+            }
+            if let Some(quantizer) = manifest.quantizer {
+                let quantizer = match ScalarQuantizer::new(quantizer.min, quantizer.max) {
+                    Ok(quantizer) => quantizer,
+                    Err(e) => {
+                        return Err(Box::new(HnswIndexQuantizationError::InvalidQuantizer(e)))
+                    }
+                };
+                if let Err(e) = index.set_quantizer(quantizer) {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// Whether the index is implemented natively rather than with hnswlib, which only
+    /// supports some distance functions.
+    pub(crate) fn uses_native(index_config: &IndexConfig, config: &HnswIndexConfig) -> bool {
+        config.implementation == HnswImplementation::Native
+            || !matches!(
+                index_config.distance_function,
+                DistanceFunction::Euclidean
+                    | DistanceFunction::Cosine
+                    | DistanceFunction::InnerProduct
+            )
+    }
+
+    fn load_backend(
+        path: &str,
+        index_config: &IndexConfig,
+        config: HnswIndexConfig,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        if HnswIndex::uses_native(index_config, &config) {
+            let index = NativeHnswIndex::load(path, index_config, id)?;
+            return Ok(HnswIndex {
+                backend: RwLock::new(HnswIndexBackend::Native(index)),
+                dimensionality: index_config.dimensionality,
+                distance_function: index_config.distance_function.clone(),
+                config,
+                cold_start: ColdStart::default(),
+                id,
+            });
+        }
+        let distance_function_string: String = index_config.distance_function.clone().into();
+        let space_name = match CString::new(distance_function_string) {
+            Ok(space_name) => space_name,
+            Err(e) => {
+                return Err(Box::new(HnswIndexInitError::InvalidDistanceFunction(
+                    e.to_string(),
+                )))
+            }
+        };
+        let index =
+            HnswlibIndex(unsafe { create_index(space_name.as_ptr(), index_config.dimensionality) });
+        let path = match CString::new(path.to_string()) {
+            Ok(path) => path,
+            Err(e) => return Err(Box::new(HnswIndexInitError::InvalidPath(e.to_string()))),
+        };
+        unsafe {
+            load_index(index.0, path.as_ptr(), true, true);
+        }
+        let hnsw_index = HnswIndex {
+            backend: RwLock::new(HnswIndexBackend::Hnswlib(Mutex::new(index))),
+            dimensionality: index_config.dimensionality,
+            distance_function: index_config.distance_function.clone(),
+            config,
+            cold_start: ColdStart::default(),
+            id,
+        };
+        Ok(hnsw_index)
+    }
+
+    /// The nearest neighbors of the vector, searched with the given parameters instead
+    /// of those of the index.
+    pub(crate) fn query_with_params(
         &self,
         vector: &[f32],
         k: usize,
-        allowed_ids: &[usize],
-        disallowed_ids: &[usize],
+        filter: &IndexFilter,
+        params: &HnswSearchParams,
     ) -> (Vec<usize>, Vec<f32>) {
-        let actual_k = std::cmp::min(k, self.len());
+        let result = self.query_backend(vector, k, filter, params);
+        self.cold_start.report(self.id);
+        result
+    }
+
+    fn query_backend(
+        &self,
+        vector: &[f32],
+        k: usize,
+        filter: &IndexFilter,
+        params: &HnswSearchParams,
+    ) -> (Vec<usize>, Vec<f32>) {
+        let backend = self.backend.read();
+        let index = match &*backend {
+            HnswIndexBackend::Hnswlib(index) => index.lock(),
+            HnswIndexBackend::Native(index) => {
+                return index.query_with_params(vector, k, filter, params)
+            }
+        };
+        let len = index.len();
+        let actual_k = std::cmp::min(k, len);
         let mut ids = vec![0usize; actual_k];
         let mut distance = vec![0.0f32; actual_k];
-        let mut total_result = actual_k;
-        unsafe {
-            total_result = knn_query(
-                self.ffi_ptr,
-                vector.as_ptr(),
-                k,
-                ids.as_mut_ptr(),
-                distance.as_mut_ptr(),
-                allowed_ids.as_ptr(),
-                allowed_ids.len(),
-                disallowed_ids.as_ptr(),
-                disallowed_ids.len(),
-            ) as usize;
-        }
+        let total_result = if params.exact || should_brute_force(filter, len) {
+            // hnswlib only normalizes the queries it searches the graph with
+            let query = match self.distance_function {
+                DistanceFunction::Cosine => normalize(vector),
+                _ => vector.to_vec(),
+            };
+            // The allowed set is small enough to materialize, without one every element
+            // that passes the filter is compared
+            let labels: Option<Vec<usize>> = filter.allowed.map(|allowed| {
+                allowed
+                    .iter()
+                    .map(|id| id as usize)
+                    .filter(|id| filter.allows(*id))
+                    .collect()
+            });
+            index.brute_force_query(&query, &mut ids, &mut distance, labels.as_deref(), filter)
+        } else {
+            index.knn_query(
+                vector,
+                params.ef.unwrap_or(0),
+                &mut ids,
+                &mut distance,
+                filter,
+            )
+        };
         if total_result < actual_k {
             ids.truncate(total_result);
             distance.truncate(total_result);
@@ -239,70 +1013,178 @@ impl Index<HnswIndexConfig> for HnswIndex {
         return (ids, distance);
     }
 
-    fn get(&self, id: usize) -> Option<Vec<f32>> {
-        unsafe {
-            let mut data: Vec<f32> = vec![0.0f32; self.dimensionality as usize];
-            get_item(self.ffi_ptr, id, data.as_mut_ptr());
-            return Some(data);
+    /// Set the search ef, waits for the adds and queries in progress.
+    pub fn set_ef(&self, ef: usize) {
+        match &mut *self.backend.write() {
+            HnswIndexBackend::Hnswlib(index) => index.get_mut().set_ef(ef),
+            HnswIndexBackend::Native(index) => index.set_ef(ef),
         }
     }
-}
 
-impl PersistentIndex<HnswIndexConfig> for HnswIndex {
-    fn save(&self) -> Result<(), Box<dyn ChromaError>> {
-        unsafe { persist_dirty(self.ffi_ptr) };
-        Ok(())
+    pub fn get_ef(&self) -> usize {
+        self.backend.read().get_ef()
+    }
+
+    /// The dimension of the vectors the index holds.
+    pub fn dimensionality(&self) -> usize {
+        self.dimensionality as usize
+    }
+
+    pub(crate) fn distance_function(&self) -> &DistanceFunction {
+        &self.distance_function
+    }
+
+    /// The scalar quantizer of a quantized index, see `NativeHnswIndex`.
+    pub(crate) fn quantizer(&self) -> Option<ScalarQuantizer> {
+        self.backend.read().quantizer()
+    }
+
+    /// Quantize the index with a quantizer trained on the vectors, which are normalized
+    /// for the cosine distance as the vectors of the index are. See set_quantizer().
+    pub(crate) fn train_quantizer(
+        &self,
+        vectors: &[&[f32]],
+    ) -> Result<(), Box<HnswIndexQuantizationError>> {
+        let points: Vec<f32> = vectors
+            .iter()
+            .flat_map(|vector| match self.distance_function {
+                DistanceFunction::Cosine => normalize(vector),
+                _ => vector.to_vec(),
+            })
+            .collect();
+        let quantizer = ScalarQuantizer::train(
+            &points,
+            self.dimensionality as usize,
+            &self.distance_function,
+        );
+        self.set_quantizer(quantizer)
+    }
+
+    /// Quantize the index, so that it is searched by the distances between int8
+    /// quantized vectors. The quantizer is saved with the index. Only native indices
+    /// with a float distance function can be quantized.
+    pub(crate) fn set_quantizer(
+        &self,
+        quantizer: ScalarQuantizer,
+    ) -> Result<(), Box<HnswIndexQuantizationError>> {
+        if self.distance_function == DistanceFunction::Hamming {
+            return Err(Box::new(
+                HnswIndexQuantizationError::UnsupportedDistanceFunction,
+            ));
+        }
+        if quantizer.dimensionality() != self.dimensionality as usize {
+            return Err(Box::new(HnswIndexQuantizationError::InvalidQuantizer(
+                ScalarQuantizerError::InvalidDimensionality(
+                    quantizer.dimensionality(),
+                    self.dimensionality as usize,
+                ),
+            )));
+        }
+        match &*self.backend.read() {
+            HnswIndexBackend::Hnswlib(_) => Err(Box::new(
+                HnswIndexQuantizationError::UnsupportedImplementation,
+            )),
+            HnswIndexBackend::Native(index) => {
+                index.set_quantizer(quantizer);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.backend.read().len()
+    }
+
+    /// The number of elements the index has room for.
+    pub fn capacity(&self) -> usize {
+        self.backend.read().capacity()
+    }
+
+    /// An estimate of the memory the index takes, from the vectors and the level 0 links
+    /// of the elements it has room for.
+    pub fn estimated_size_bytes(&self) -> usize {
+        self.capacity() * self.element_size_bytes()
+    }
+
+    /// The estimated size as in estimated_size_bytes(), or None instead of waiting if
+    /// the index is being resized or saved.
+    pub fn try_estimated_size_bytes(&self) -> Option<usize> {
+        let backend = self.backend.try_read()?;
+        Some(backend.capacity() * self.element_size_bytes())
+    }
+
+    fn element_size_bytes(&self) -> usize {
+        self.dimensionality as usize * std::mem::size_of::<f32>()
+            + 2 * self.config.m * std::mem::size_of::<u32>()
+            + std::mem::size_of::<usize>()
+    }
+
+    /// The number of elements taking up capacity. Unlike len() this includes deleted
+    /// elements, as their slots are not reused.
+    pub fn len_with_deleted(&self) -> usize {
+        self.backend.read().len_with_deleted()
     }
 
-    fn load(
-        path: &str,
-        index_config: &IndexConfig,
-        id: Uuid,
-    ) -> Result<Self, Box<dyn ChromaError>> {
-        let distance_function_string: String = index_config.distance_function.clone().into();
-        let space_name = match CString::new(distance_function_string) {
-            Ok(space_name) => space_name,
-            Err(e) => {
-                return Err(Box::new(HnswIndexInitError::InvalidDistanceFunction(
-                    e.to_string(),
-                )))
-            }
-        };
-        let ffi_ptr = unsafe { create_index(space_name.as_ptr(), index_config.dimensionality) };
-        let path = match CString::new(path.to_string()) {
-            Ok(path) => path,
-            Err(e) => return Err(Box::new(HnswIndexInitError::InvalidPath(e.to_string()))),
-        };
-        unsafe {
-            load_index(ffi_ptr, path.as_ptr(), true, true);
+    /// The fraction of the elements taking up capacity that are deleted.
+    pub fn deleted_ratio(&self) -> f64 {
+        let backend = self.backend.read();
+        let len_with_deleted = backend.len_with_deleted();
+        if len_with_deleted == 0 {
+            return 0.0;
         }
-        let hnsw_index = HnswIndex {
-            ffi_ptr: ffi_ptr,
-            dimensionality: index_config.dimensionality,
-            id,
-        };
-        Ok(hnsw_index)
+        (len_with_deleted - backend.len()) as f64 / len_with_deleted as f64
     }
-}
 
-impl HnswIndex {
-    pub fn set_ef(&self, ef: usize) {
-        unsafe { set_ef(self.ffi_ptr, ef as c_int) }
+    /// Resize the index to have room for new_size elements. The index can not be
+    /// shrunk below the number of elements it holds. Waits for the adds and queries
+    /// in progress.
+    pub fn resize(&self, new_size: usize) -> Result<(), Box<dyn ChromaError>> {
+        self.backend.write().resize(new_size)
     }
 
-    pub fn get_ef(&self) -> usize {
-        unsafe { get_ef(self.ffi_ptr) as usize }
+    /// Grow the index so that additional elements fit, by the configured resize
+    /// factor or further if that does not suffice. Returns the new capacity.
+    /// # Notes
+    /// Adds and queries only wait for the index if it is resized.
+    pub fn reserve(&self, additional: usize) -> Result<usize, Box<dyn ChromaError>> {
+        // Other reads proceed while the capacity is checked, but no other reservation
+        // can resize the index in between
+        let backend = self.backend.upgradable_read();
+        let capacity = backend.capacity();
+        let required = backend.len_with_deleted() + additional;
+        if required <= capacity {
+            return Ok(capacity);
+        }
+        let grown = (capacity as f64 * self.config.resize_factor).ceil() as usize;
+        let new_size = grown.max(required);
+        RwLockUpgradableReadGuard::upgrade(backend).resize(new_size)?;
+        Ok(new_size)
     }
+}
 
-    pub fn len(&self) -> usize {
-        unsafe { len(self.ffi_ptr) as usize }
+#[derive(Error, Debug)]
+pub(crate) enum HnswIndexResizeError {
+    #[error("Can not resize the index to {0} elements, it holds {1}")]
+    TooSmall(usize, usize),
+}
+
+impl ChromaError for HnswIndexResizeError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            HnswIndexResizeError::TooSmall(_, _) => ErrorCodes::InvalidArgument,
+        }
     }
 }
 
+// Whether the element with the label passes the filter given as the context
+type FilterCallback = extern "C" fn(*const c_void, usize) -> bool;
+
 #[link(name = "bindings", kind = "static")]
 extern "C" {
     fn create_index(space_name: *const c_char, dim: c_int) -> *const IndexPtrFFI;
 
+    fn free_index(index: *const IndexPtrFFI);
+
     fn init_index(
         index: *const IndexPtrFFI,
         max_elements: usize,
@@ -327,20 +1209,34 @@ extern "C" {
     fn mark_deleted(index: *const IndexPtrFFI, id: usize);
     fn get_item(index: *const IndexPtrFFI, id: usize, data: *mut f32);
     fn knn_query(
+        index: *const IndexPtrFFI,
+        query_vector: *const f32,
+        k: usize,
+        ef: usize,
+        ids: *mut usize,
+        distance: *mut f32,
+        filter: Option<FilterCallback>,
+        filter_context: *const c_void,
+    ) -> c_int;
+
+    fn brute_force_query(
         index: *const IndexPtrFFI,
         query_vector: *const f32,
         k: usize,
         ids: *mut usize,
         distance: *mut f32,
-        allowed_ids: *const usize,
-        allowed_ids_length: usize,
-        disallowed_ids: *const usize,
-        disallowed_ids_length: usize,
+        labels: *const usize,
+        labels_length: usize,
+        filter: Option<FilterCallback>,
+        filter_context: *const c_void,
     ) -> c_int;
 
     fn get_ef(index: *const IndexPtrFFI) -> c_int;
     fn set_ef(index: *const IndexPtrFFI, ef: c_int);
     fn len(index: *const IndexPtrFFI) -> c_int;
+    fn get_max_elements(index: *const IndexPtrFFI) -> usize;
+    fn get_current_count(index: *const IndexPtrFFI) -> usize;
+    fn resize_index(index: *const IndexPtrFFI, new_size: usize);
 
 }
 
@@ -350,10 +1246,13 @@ pub mod test {
 
     use crate::distance::DistanceFunction;
     use crate::index::utils;
+    use crate::index::DEFAULT_RERANK_FACTOR;
+    use crate::types::{Metadata, MetadataValue};
     use rand::seq::IteratorRandom;
     use rand::Rng;
     use rayon::prelude::*;
     use rayon::ThreadPoolBuilder;
+    use roaring::RoaringBitmap;
     use tempfile::tempdir;
 
     #[test]
@@ -375,6 +1274,8 @@ pub mod test {
                 ef_search: 10,
                 random_seed: 0,
                 persist_path: persist_path,
+                implementation: HnswImplementation::Hnswlib,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
             }),
             Uuid::new_v4(),
         );
@@ -407,6 +1308,8 @@ pub mod test {
                 ef_search: 100,
                 random_seed: 0,
                 persist_path: persist_path,
+                implementation: HnswImplementation::Hnswlib,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
             }),
             Uuid::new_v4(),
         );
@@ -478,6 +1381,8 @@ pub mod test {
                 ef_search: 100,
                 random_seed: 0,
                 persist_path: persist_path,
+                implementation: HnswImplementation::Hnswlib,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
             }),
             Uuid::new_v4(),
         );
@@ -518,9 +1423,8 @@ pub mod test {
 
         // Query the data
         let query = &data[0..d];
-        let allow_ids = &[];
-        let disallow_ids = &[];
-        let (ids, distances) = index.query(query, 1, allow_ids, disallow_ids);
+        let filter = IndexFilter::default();
+        let (ids, distances) = index.query(query, 1, &filter);
         assert_eq!(ids.len(), 1);
         assert_eq!(distances.len(), 1);
         assert_eq!(ids[0], 0);
@@ -547,6 +1451,8 @@ pub mod test {
                 ef_search: 100,
                 random_seed: 0,
                 persist_path: persist_path,
+                implementation: HnswImplementation::Hnswlib,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
             }),
             Uuid::new_v4(),
         );
@@ -572,12 +1478,11 @@ pub mod test {
             index.delete(*id);
         }
 
-        let allow_ids = &[];
-        let disallow_ids = &[];
+        let filter = IndexFilter::default();
         // Query for the deleted ids and ensure they are not found
         for deleted_id in &delete_ids {
             let target_vector = &data[*deleted_id * d..(*deleted_id + 1) * d];
-            let (ids, _) = index.query(target_vector, 10, allow_ids, disallow_ids);
+            let (ids, _) = index.query(target_vector, 10, &filter);
             for check_deleted_id in &delete_ids {
                 assert!(!ids.contains(check_deleted_id));
             }
@@ -604,6 +1509,8 @@ pub mod test {
                 ef_search: 100,
                 random_seed: 0,
                 persist_path: persist_path.clone(),
+                implementation: HnswImplementation::Hnswlib,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
             }),
             id,
         );
@@ -642,15 +1549,14 @@ pub mod test {
             Err(e) => panic!("Error loading index: {}", e),
             Ok(index) => index,
         };
-        // TODO: This should be set by the load
-        index.set_ef(100);
+        // The search ef is restored from the manifest
+        assert_eq!(index.get_ef(), 100);
         assert_eq!(index.id, id);
 
         // Query the data
         let query = &data[0..d];
-        let allow_ids = &[];
-        let disallow_ids = &[];
-        let (ids, distances) = index.query(query, 1, allow_ids, disallow_ids);
+        let filter = IndexFilter::default();
+        let (ids, distances) = index.query(query, 1, &filter);
         assert_eq!(ids.len(), 1);
         assert_eq!(distances.len(), 1);
         assert_eq!(ids[0], 0);
@@ -692,6 +1598,8 @@ pub mod test {
                 ef_search: 100,
                 random_seed: 0,
                 persist_path: persist_path,
+                implementation: HnswImplementation::Hnswlib,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
             }),
             Uuid::new_v4(),
         );
@@ -711,10 +1619,892 @@ pub mod test {
 
         // Query the data
         let query = &data[0..d];
-        let allow_ids = &[0, 2];
-        let disallow_ids = &[3];
-        let (ids, distances) = index.query(query, 10, allow_ids, disallow_ids);
+        let allowed = RoaringBitmap::from_iter([0, 2]);
+        let disallowed = RoaringBitmap::from_iter([3]);
+        let filter = IndexFilter::new(Some(&allowed), Some(&disallowed));
+        let (ids, distances) = index.query(query, 10, &filter);
         assert_eq!(ids.len(), 2);
         assert_eq!(distances.len(), 2);
     }
+
+    #[test]
+    fn it_filters_with_bitmaps_and_brute_forces_selective_filters() {
+        let n = 1000;
+        let d = 16;
+        let distance_function = DistanceFunction::Euclidean;
+        for implementation in [HnswImplementation::Hnswlib, HnswImplementation::Native] {
+            let tmp_dir = tempdir().unwrap();
+            let index = HnswIndex::init(
+                &IndexConfig {
+                    dimensionality: d as i32,
+                    distance_function: distance_function.clone(),
+                },
+                Some(&HnswIndexConfig {
+                    max_elements: n,
+                    m: 16,
+                    ef_construction: 100,
+                    ef_search: 100,
+                    random_seed: 0,
+                    persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                    implementation,
+                    resize_factor: DEFAULT_RESIZE_FACTOR,
+                }),
+                Uuid::new_v4(),
+            )
+            .unwrap();
+            let data: Vec<f32> = utils::generate_random_data(n, d);
+            for i in 0..n {
+                index.add(i, &data[i * d..(i + 1) * d]);
+            }
+            index.delete(4);
+            let query = &data[0..d];
+
+            // Half of the elements pass, the graph is searched with the filter
+            let allowed: RoaringBitmap = (0..n as u32).filter(|id| id % 2 == 0).collect();
+            let disallowed = RoaringBitmap::from_iter([0]);
+            let filter = IndexFilter::new(Some(&allowed), Some(&disallowed));
+            assert!(!should_brute_force(&filter, index.len()));
+            let (ids, _) = index.query(query, 10, &filter);
+            assert_eq!(ids.len(), 10);
+            assert!(ids.iter().all(|id| id % 2 == 0 && *id != 0 && *id != 4));
+
+            // Few elements pass, they are scanned for the exact neighbors
+            let allowed = RoaringBitmap::from_iter([3, 4, 7, 500, 999, 5000]);
+            let filter = IndexFilter::new(Some(&allowed), None);
+            assert!(should_brute_force(&filter, index.len()));
+            let (ids, distances) = index.query(query, 3, &filter);
+            let mut expected: Vec<(f32, usize)> = [3, 7, 500, 999]
+                .iter()
+                .map(|id| {
+                    (
+                        distance_function.distance(query, &data[id * d..(id + 1) * d]),
+                        *id,
+                    )
+                })
+                .collect();
+            expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let expected_ids: Vec<usize> = expected.iter().take(3).map(|(_, id)| *id).collect();
+            assert_eq!(ids, expected_ids);
+            for (distance, (expected_distance, _)) in distances.iter().zip(&expected) {
+                assert!((distance - expected_distance).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn it_searches_exactly_or_with_a_query_ef() {
+        let n = 1000;
+        let d = 16;
+        let distance_function = DistanceFunction::Euclidean;
+        for implementation in [HnswImplementation::Hnswlib, HnswImplementation::Native] {
+            let tmp_dir = tempdir().unwrap();
+            let index = HnswIndex::init(
+                &IndexConfig {
+                    dimensionality: d as i32,
+                    distance_function: distance_function.clone(),
+                },
+                Some(&HnswIndexConfig {
+                    max_elements: n,
+                    m: 16,
+                    ef_construction: 100,
+                    ef_search: 10,
+                    random_seed: 0,
+                    persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                    implementation,
+                    resize_factor: DEFAULT_RESIZE_FACTOR,
+                }),
+                Uuid::new_v4(),
+            )
+            .unwrap();
+            let data: Vec<f32> = utils::generate_random_data(n, d);
+            for i in 0..n {
+                index.add(i, &data[i * d..(i + 1) * d]);
+            }
+            index.delete(4);
+            let query = &data[0..d];
+            let disallowed = RoaringBitmap::from_iter([0]);
+            let filter = IndexFilter::new(None, Some(&disallowed));
+
+            let mut expected: Vec<(f32, usize)> = (1..n)
+                .filter(|id| *id != 4)
+                .map(|id| {
+                    (
+                        distance_function.distance(query, &data[id * d..(id + 1) * d]),
+                        id,
+                    )
+                })
+                .collect();
+            expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let expected_ids: Vec<usize> = expected.iter().take(10).map(|(_, id)| *id).collect();
+
+            let exact = HnswSearchParams {
+                exact: true,
+                ..Default::default()
+            };
+            let (ids, distances) = index.query_with_params(query, 10, &filter, &exact);
+            assert_eq!(ids, expected_ids);
+            for (distance, (expected_distance, _)) in distances.iter().zip(&expected) {
+                assert!((distance - expected_distance).abs() < 1e-3);
+            }
+
+            // An ef as large as the index searches the whole graph
+            let wide = HnswSearchParams {
+                ef: Some(n),
+                ..Default::default()
+            };
+            let (ids, _) = index.query_with_params(query, 10, &filter, &wide);
+            assert_eq!(ids, expected_ids);
+            assert_eq!(index.get_ef(), 10);
+        }
+    }
+
+    #[test]
+    fn it_lowers_the_query_ef_only_with_the_native_implementation() {
+        let n = 2000;
+        let d = 32;
+        let k = 10;
+        let distance_function = DistanceFunction::Euclidean;
+        let data: Vec<f32> = utils::generate_random_data(n, d);
+        let queries: Vec<f32> = utils::generate_random_data(50, d);
+        let expected: Vec<Vec<usize>> = queries
+            .chunks(d)
+            .map(|query| {
+                let mut distances: Vec<(f32, usize)> = (0..n)
+                    .map(|id| {
+                        (
+                            distance_function.distance(query, &data[id * d..(id + 1) * d]),
+                            id,
+                        )
+                    })
+                    .collect();
+                distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+                distances.iter().take(k).map(|(_, id)| *id).collect()
+            })
+            .collect();
+
+        for implementation in [HnswImplementation::Hnswlib, HnswImplementation::Native] {
+            let tmp_dir = tempdir().unwrap();
+            // A sparse graph, so that the ef makes a difference to the recall
+            let index = HnswIndex::init(
+                &IndexConfig {
+                    dimensionality: d as i32,
+                    distance_function: distance_function.clone(),
+                },
+                Some(&HnswIndexConfig {
+                    max_elements: n,
+                    m: 4,
+                    ef_construction: 20,
+                    ef_search: 50,
+                    random_seed: 0,
+                    persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                    implementation,
+                    resize_factor: DEFAULT_RESIZE_FACTOR,
+                }),
+                Uuid::new_v4(),
+            )
+            .unwrap();
+            for i in 0..n {
+                index.add(i, &data[i * d..(i + 1) * d]);
+            }
+
+            let search = |ef: Option<usize>| -> (Vec<Vec<usize>>, usize) {
+                let params = HnswSearchParams {
+                    ef,
+                    ..Default::default()
+                };
+                let results: Vec<Vec<usize>> = queries
+                    .chunks(d)
+                    .map(|query| {
+                        index
+                            .query_with_params(query, k, &IndexFilter::default(), &params)
+                            .0
+                    })
+                    .collect();
+                let hits = results
+                    .iter()
+                    .zip(&expected)
+                    .map(|(ids, expected)| ids.iter().filter(|id| expected.contains(*id)).count())
+                    .sum::<usize>();
+                (results, hits)
+            };
+            let (low_ids, low_hits) = search(Some(k));
+            let (default_ids, default_hits) = search(None);
+            let (_, high_hits) = search(Some(500));
+
+            // A larger ef finds more of the nearest neighbors with both
+            assert!(high_hits > default_hits);
+            match implementation {
+                // hnswlib searches with at least the ef_search of the index
+                HnswImplementation::Hnswlib => assert_eq!(low_ids, default_ids),
+                HnswImplementation::Native => assert!(low_hits < default_hits),
+            }
+            assert_eq!(index.get_ef(), 50);
+        }
+    }
+
+    #[test]
+    fn it_selects_the_implementation_from_segment_metadata() {
+        let tmp_dir = tempdir().unwrap();
+        let mut metadata = Metadata::new();
+        metadata.insert("hnsw:max_elements".to_string(), MetadataValue::Int(10));
+        metadata.insert("hnsw:m".to_string(), MetadataValue::Int(16));
+        metadata.insert("hnsw:ef_construction".to_string(), MetadataValue::Int(100));
+        metadata.insert("hnsw:ef_search".to_string(), MetadataValue::Int(10));
+        metadata.insert(
+            "hnsw:implementation".to_string(),
+            MetadataValue::Str("native".to_string()),
+        );
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(metadata),
+            file_path: std::collections::HashMap::new(),
+        };
+        let config = HnswIndexConfig::from_segment(&segment, tmp_dir.path()).unwrap();
+        assert_eq!(config.implementation, HnswImplementation::Native);
+
+        // The native index persists files the index can be loaded back from
+        let index_config = IndexConfig {
+            dimensionality: 4,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let index = HnswIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        index.add(7, &[1.0, 2.0, 3.0, 4.0]);
+        index.save().unwrap();
+        let index = HnswIndex::load_with_config(
+            tmp_dir.path().to_str().unwrap(),
+            &index_config,
+            Some(&config),
+            index.id,
+        )
+        .unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get(7), Some(vec![1.0, 2.0, 3.0, 4.0]));
+
+        segment.metadata.as_mut().unwrap().insert(
+            "hnsw:implementation".to_string(),
+            MetadataValue::Str("faiss".to_string()),
+        );
+        assert!(HnswIndexConfig::from_segment(&segment, tmp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn it_uses_the_native_implementation_for_spaces_hnswlib_lacks() {
+        let tmp_dir = tempdir().unwrap();
+        let config = HnswIndexConfig {
+            max_elements: 10,
+            m: 16,
+            ef_construction: 100,
+            ef_search: 10,
+            random_seed: 0,
+            persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+            implementation: HnswImplementation::Hnswlib,
+            resize_factor: DEFAULT_RESIZE_FACTOR,
+        };
+        for (distance_function, native) in [
+            (DistanceFunction::Euclidean, false),
+            (DistanceFunction::Cosine, false),
+            (DistanceFunction::InnerProduct, false),
+            (DistanceFunction::Hamming, true),
+            (DistanceFunction::Manhattan, true),
+            (DistanceFunction::Jaccard, true),
+        ] {
+            let index_config = IndexConfig {
+                dimensionality: 2,
+                distance_function,
+            };
+            assert_eq!(HnswIndex::uses_native(&index_config, &config), native);
+        }
+
+        let index_config = IndexConfig {
+            dimensionality: 2,
+            distance_function: DistanceFunction::Manhattan,
+        };
+        let index = HnswIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        index.add(1, &[2.0, 2.0]);
+        index.add(2, &[3.0, 0.0]);
+        index.add(3, &[5.0, 5.0]);
+        // The closest vector by l2 distance is 1, by l1 distance it is 2
+        let (ids, distances) = index.query(&[0.0, 0.0], 1, &IndexFilter::default());
+        assert_eq!(ids, vec![2]);
+        assert_eq!(distances, vec![3.0]);
+    }
+
+    #[test]
+    fn it_can_load_from_buffers() {
+        let n = 1000;
+        let d: usize = 16;
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap().to_string();
+        let id = Uuid::new_v4();
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let hnsw_config = HnswIndexConfig {
+            max_elements: n,
+            m: 16,
+            ef_construction: 100,
+            ef_search: 100,
+            random_seed: 0,
+            persist_path: persist_path.clone(),
+            implementation: HnswImplementation::Hnswlib,
+            resize_factor: DEFAULT_RESIZE_FACTOR,
+        };
+        let index = HnswIndex::init(&index_config, Some(&hnsw_config), id).unwrap();
+        let data: Vec<f32> = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        index.save().unwrap();
+
+        // An hnswlib index is loaded from the files at the path, a native index from
+        // the buffers
+        let directory = Path::new(&persist_path);
+        for (implementation, buffers) in [
+            (
+                HnswImplementation::Hnswlib,
+                HnswIndexBuffers::read(directory).unwrap(),
+            ),
+            (
+                HnswImplementation::Native,
+                HnswIndexBuffers::read(directory).unwrap(),
+            ),
+            (
+                HnswImplementation::Native,
+                HnswIndexBuffers::mmap(directory).unwrap(),
+            ),
+        ] {
+            let loaded = HnswIndex::load_from_buffers(
+                buffers,
+                &persist_path,
+                &index_config,
+                Some(&HnswIndexConfig {
+                    implementation,
+                    ..hnsw_config.clone()
+                }),
+                id,
+            )
+            .unwrap();
+            assert_eq!(loaded.len(), n);
+            assert_eq!(loaded.get_ef(), 100);
+            let filter = IndexFilter::default();
+            for i in [0, n / 2, n - 1] {
+                let (ids, distances) = loaded.query(&data[i * d..(i + 1) * d], 1, &filter);
+                assert_eq!(ids, vec![i]);
+                assert_eq!(distances, vec![0.0]);
+            }
+        }
+
+        // The manifest is validated as when loading from disk
+        let buffers = HnswIndexBuffers::read(directory).unwrap();
+        let res = HnswIndex::load_from_buffers(
+            buffers,
+            &persist_path,
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function: DistanceFunction::Cosine,
+            },
+            None,
+            id,
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn it_validates_the_manifest_on_load() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap().to_string();
+        let config = HnswIndexConfig {
+            max_elements: 10,
+            m: 16,
+            ef_construction: 100,
+            ef_search: 20,
+            random_seed: 0,
+            persist_path: persist_path.clone(),
+            implementation: HnswImplementation::Native,
+            resize_factor: DEFAULT_RESIZE_FACTOR,
+        };
+        let index_config = IndexConfig {
+            dimensionality: 2,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let index = HnswIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        index.add(1, &[1.0, 0.0]);
+        index.add(2, &[0.0, 1.0]);
+        index.set_ef(30);
+        index.save().unwrap();
+
+        let manifest = HnswIndexManifest::read(tmp_dir.path()).unwrap().unwrap();
+        assert_eq!(
+            manifest,
+            HnswIndexManifest {
+                version: HNSW_MANIFEST_VERSION,
+                dimensionality: 2,
+                space: "l2".to_string(),
+                m: 16,
+                ef_construction: 100,
+                ef_search: 30,
+                random_seed: 0,
+                element_count: 2,
+                quantizer: None,
+            }
+        );
+
+        let index =
+            HnswIndex::load_with_config(&persist_path, &index_config, Some(&config), index.id)
+                .unwrap();
+        assert_eq!(index.get_ef(), 30);
+        assert_eq!(index.len(), 2);
+
+        // A different dimensionality, space or M is detected instead of corrupting queries
+        let mismatched_index_configs = [
+            IndexConfig {
+                dimensionality: 3,
+                distance_function: DistanceFunction::Euclidean,
+            },
+            IndexConfig {
+                dimensionality: 2,
+                distance_function: DistanceFunction::Cosine,
+            },
+        ];
+        for mismatched_index_config in mismatched_index_configs.iter() {
+            let result = HnswIndex::load_with_config(
+                &persist_path,
+                mismatched_index_config,
+                Some(&config),
+                index.id,
+            );
+            assert_eq!(result.err().unwrap().code(), ErrorCodes::FailedPrecondition);
+        }
+        let mismatched_config = HnswIndexConfig {
+            m: 32,
+            ..config.clone()
+        };
+        let result = HnswIndex::load_with_config(
+            &persist_path,
+            &index_config,
+            Some(&mismatched_config),
+            index.id,
+        );
+        assert_eq!(result.err().unwrap().code(), ErrorCodes::FailedPrecondition);
+
+        // Without a manifest the index config can not be trusted
+        std::fs::remove_file(tmp_dir.path().join(HNSW_MANIFEST_FILE)).unwrap();
+        let result = HnswIndex::load(&persist_path, &index_config, index.id);
+        assert_eq!(result.err().unwrap().code(), ErrorCodes::NotFound);
+    }
+
+    #[test]
+    fn it_saves_and_restores_the_quantizer() {
+        let n = 1000;
+        let d = 16;
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap().to_string();
+        let config = HnswIndexConfig {
+            max_elements: n,
+            m: 16,
+            ef_construction: 100,
+            ef_search: 100,
+            random_seed: 0,
+            persist_path: persist_path.clone(),
+            implementation: HnswImplementation::Native,
+            resize_factor: DEFAULT_RESIZE_FACTOR,
+        };
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::InnerProduct,
+        };
+        let index = HnswIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let data = utils::generate_random_data(n, d);
+        let quantizer = ScalarQuantizer::train(&data, d, &DistanceFunction::InnerProduct);
+        index.set_quantizer(quantizer.clone()).unwrap();
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        index.save().unwrap();
+
+        let loaded =
+            HnswIndex::load_with_config(&persist_path, &index_config, Some(&config), index.id)
+                .unwrap();
+        assert_eq!(loaded.quantizer(), Some(quantizer));
+        // The loaded index searches the same quantized distances
+        let filter = IndexFilter::default();
+        for i in [0, n / 2, n - 1] {
+            let query = &data[i * d..(i + 1) * d];
+            assert_eq!(
+                loaded.query(query, 10, &filter),
+                index.query(query, 10, &filter)
+            );
+        }
+
+        // hnswlib can not be quantized
+        let hnswlib = HnswIndex::init(
+            &index_config,
+            Some(&HnswIndexConfig {
+                implementation: HnswImplementation::Hnswlib,
+                persist_path: tempdir().unwrap().path().to_str().unwrap().to_string(),
+                ..config.clone()
+            }),
+            Uuid::new_v4(),
+        )
+        .unwrap();
+        let quantizer = ScalarQuantizer::train(&data, d, &DistanceFunction::InnerProduct);
+        assert!(matches!(
+            hnswlib.set_quantizer(quantizer).map_err(|e| *e),
+            Err(HnswIndexQuantizationError::UnsupportedImplementation)
+        ));
+    }
+
+    #[test]
+    fn it_grows_capacity_by_the_resize_factor() {
+        for implementation in [HnswImplementation::Hnswlib, HnswImplementation::Native] {
+            let tmp_dir = tempdir().unwrap();
+            let config = HnswIndexConfig {
+                max_elements: 10,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 100,
+                random_seed: 0,
+                persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                implementation,
+                resize_factor: 2.0,
+            };
+            let index_config = IndexConfig {
+                dimensionality: 2,
+                distance_function: DistanceFunction::Euclidean,
+            };
+            let index = HnswIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+            assert_eq!(index.capacity(), 10);
+
+            // Room is left, nothing changes
+            assert_eq!(index.reserve(10).unwrap(), 10);
+
+            // Grows by the factor
+            for i in 0..8 {
+                index.add(i, &[i as f32, 0.0]);
+            }
+            index.delete(0);
+            assert_eq!(index.len(), 7);
+            assert_eq!(index.len_with_deleted(), 8);
+            assert_eq!(index.reserve(3).unwrap(), 20);
+            assert_eq!(index.capacity(), 20);
+
+            // Grows past the factor when it does not suffice
+            assert_eq!(index.reserve(50).unwrap(), 58);
+            for i in 8..58 {
+                index.add(i, &[i as f32, 0.0]);
+            }
+            assert_eq!(index.len(), 57);
+            let (labels, _) = index.query(&[57.0, 0.0], 1, &IndexFilter::default());
+            assert_eq!(labels, vec![57]);
+
+            // Can not shrink below the number of elements
+            assert!(index.resize(10).is_err());
+        }
+    }
+
+    #[test]
+    fn it_queries_while_adding_and_resizing() {
+        let n = 1000;
+        let d = 16;
+        let mut rng = rand::thread_rng();
+        let data: Vec<Vec<f32>> = (0..n)
+            .map(|_| (0..d).map(|_| rng.gen()).collect())
+            .collect();
+        for implementation in [HnswImplementation::Hnswlib, HnswImplementation::Native] {
+            let tmp_dir = tempdir().unwrap();
+            let config = HnswIndexConfig {
+                max_elements: 10,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 100,
+                random_seed: 0,
+                persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                implementation,
+                resize_factor: 2.0,
+            };
+            let index_config = IndexConfig {
+                dimensionality: d as i32,
+                distance_function: DistanceFunction::Euclidean,
+            };
+            let index = HnswIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+            index.add(0, &data[0]);
+
+            // The index is shared without a lock, queries run while it is written
+            let done = std::sync::atomic::AtomicBool::new(false);
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                            let (ids, _) = index.query(&data[0], 5, &IndexFilter::default());
+                            assert!(!ids.is_empty());
+                            assert!(ids.iter().all(|id| *id < n));
+                        }
+                    });
+                }
+                for (id, vector) in data.iter().enumerate().skip(1) {
+                    index.reserve(1).unwrap();
+                    index.add(id, vector);
+                }
+                done.store(true, std::sync::atomic::Ordering::Relaxed);
+            });
+
+            assert_eq!(index.len(), n);
+            assert!(index.capacity() >= n);
+            for id in [0, n / 2, n - 1] {
+                let (ids, distances) = index.query(&data[id], 1, &IndexFilter::default());
+                assert_eq!(ids, vec![id]);
+                assert_eq!(distances, vec![0.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn it_queries_the_default_index_while_an_add_is_blocked() {
+        let n = 100;
+        let d = 8;
+        let tmp_dir = tempdir().unwrap();
+        let segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: None,
+            file_path: std::collections::HashMap::new(),
+        };
+        let config = HnswIndexConfig::from_segment(&segment, tmp_dir.path()).unwrap();
+        assert_eq!(config.implementation, HnswImplementation::Native);
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let index = HnswIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let data = utils::generate_random_data(n, d);
+        for i in 0..n - 1 {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+
+        let (index, data) = (&index, &data);
+        std::thread::scope(|scope| {
+            // Hold the graph as an add in progress does, the next add waits for it
+            let backend = index.backend.read();
+            let adding = match &*backend {
+                HnswIndexBackend::Native(index) => index.hold_for_add(),
+                HnswIndexBackend::Hnswlib(_) => unreachable!(),
+            };
+            let (add_sender, add_receiver) = std::sync::mpsc::channel();
+            scope.spawn(move || {
+                index.add(n - 1, &data[(n - 1) * d..]);
+                add_sender.send(()).unwrap();
+            });
+            assert!(add_receiver
+                .recv_timeout(std::time::Duration::from_millis(100))
+                .is_err());
+
+            // Queries do not wait for the blocked add
+            let (sender, receiver) = std::sync::mpsc::channel();
+            scope.spawn(move || {
+                let (ids, _) = index.query(&data[..d], 1, &IndexFilter::default());
+                sender.send(ids).unwrap();
+            });
+            let ids = receiver
+                .recv_timeout(std::time::Duration::from_secs(10))
+                .expect("query waited for the add");
+            assert_eq!(ids, vec![0]);
+
+            drop(adding);
+            drop(backend);
+            add_receiver.recv().unwrap();
+        });
+        assert_eq!(index.len(), n);
+    }
+
+    #[test]
+    fn it_reads_the_resize_factor_from_segment_metadata() {
+        let tmp_dir = tempdir().unwrap();
+        let mut metadata = Metadata::new();
+        metadata.insert("hnsw:max_elements".to_string(), MetadataValue::Int(10));
+        metadata.insert("hnsw:m".to_string(), MetadataValue::Int(16));
+        metadata.insert("hnsw:ef_construction".to_string(), MetadataValue::Int(100));
+        metadata.insert("hnsw:ef_search".to_string(), MetadataValue::Int(10));
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(metadata),
+            file_path: std::collections::HashMap::new(),
+        };
+        let config = HnswIndexConfig::from_segment(&segment, tmp_dir.path()).unwrap();
+        assert_eq!(config.resize_factor, DEFAULT_RESIZE_FACTOR);
+
+        segment
+            .metadata
+            .as_mut()
+            .unwrap()
+            .insert("hnsw:resize_factor".to_string(), MetadataValue::Float(1.5));
+        let config = HnswIndexConfig::from_segment(&segment, tmp_dir.path()).unwrap();
+        assert_eq!(config.resize_factor, 1.5);
+
+        segment
+            .metadata
+            .as_mut()
+            .unwrap()
+            .insert("hnsw:resize_factor".to_string(), MetadataValue::Float(0.5));
+        assert!(HnswIndexConfig::from_segment(&segment, tmp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn it_reports_the_deleted_ratio_and_reads_the_vacuum_threshold() {
+        let tmp_dir = tempdir().unwrap();
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: 2,
+                distance_function: DistanceFunction::Euclidean,
+            },
+            Some(&HnswIndexConfig {
+                max_elements: 10,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 10,
+                random_seed: 0,
+                persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                implementation: HnswImplementation::Hnswlib,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
+            }),
+            Uuid::new_v4(),
+        )
+        .unwrap();
+        assert_eq!(index.deleted_ratio(), 0.0);
+        for i in 0..4 {
+            index.add(i, &[i as f32, 0.0]);
+        }
+        index.delete(0);
+        assert_eq!(index.deleted_ratio(), 0.25);
+
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: None,
+            file_path: std::collections::HashMap::new(),
+        };
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&segment)
+                .unwrap()
+                .vacuum_threshold,
+            DEFAULT_VACUUM_THRESHOLD
+        );
+        let mut metadata = Metadata::new();
+        metadata.insert(
+            "hnsw:vacuum_threshold".to_string(),
+            MetadataValue::Float(0.5),
+        );
+        segment.metadata = Some(metadata);
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&segment)
+                .unwrap()
+                .vacuum_threshold,
+            0.5
+        );
+        segment.metadata.as_mut().unwrap().insert(
+            "hnsw:vacuum_threshold".to_string(),
+            MetadataValue::Float(1.5),
+        );
+        assert!(HnswSegmentConfig::from_segment(&segment).is_err());
+    }
+
+    #[test]
+    fn it_reads_the_indexed_vectors_from_segment_metadata() {
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: None,
+            file_path: std::collections::HashMap::new(),
+        };
+        assert!(
+            !HnswSegmentConfig::from_segment(&segment)
+                .unwrap()
+                .multi_vector
+        );
+        let mut metadata = Metadata::new();
+        metadata.insert(
+            "hnsw:vectors".to_string(),
+            MetadataValue::Str("multi_vector".to_string()),
+        );
+        segment.metadata = Some(metadata);
+        assert!(
+            HnswSegmentConfig::from_segment(&segment)
+                .unwrap()
+                .multi_vector
+        );
+        segment.metadata.as_mut().unwrap().insert(
+            "hnsw:vectors".to_string(),
+            MetadataValue::Str("tokens".to_string()),
+        );
+        assert!(matches!(
+            HnswSegmentConfig::from_segment(&segment),
+            Err(SegmentConfigError::InvalidChoice(_, _, _))
+        ));
+    }
+
+    #[test]
+    fn it_reads_the_rerank_config_from_segment_metadata() {
+        let mut metadata = Metadata::new();
+        metadata.insert(
+            "hnsw:space".to_string(),
+            MetadataValue::Str("hamming".to_string()),
+        );
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(metadata),
+            file_path: std::collections::HashMap::new(),
+        };
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&segment).unwrap().rerank,
+            None
+        );
+
+        let metadata = segment.metadata.as_mut().unwrap();
+        metadata.insert(
+            "hnsw:rerank_space".to_string(),
+            MetadataValue::Str("cosine".to_string()),
+        );
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&segment).unwrap().rerank,
+            Some(HnswRerankConfig {
+                rerank_factor: DEFAULT_RERANK_FACTOR,
+                distance_function: DistanceFunction::Cosine,
+            })
+        );
+
+        let metadata = segment.metadata.as_mut().unwrap();
+        metadata.insert("hnsw:rerank_factor".to_string(), MetadataValue::Int(8));
+        let config = HnswSegmentConfig::from_segment(&segment)
+            .unwrap()
+            .rerank
+            .unwrap();
+        assert_eq!(config.rerank_factor, 8);
+
+        let metadata = segment.metadata.as_mut().unwrap();
+        metadata.insert("hnsw:rerank_factor".to_string(), MetadataValue::Int(0));
+        assert!(HnswSegmentConfig::from_segment(&segment).is_err());
+
+        let metadata = segment.metadata.as_mut().unwrap();
+        metadata.insert("hnsw:rerank_factor".to_string(), MetadataValue::Int(8));
+        metadata.insert(
+            "hnsw:rerank_space".to_string(),
+            MetadataValue::Str("hamming".to_string()),
+        );
+        assert!(HnswSegmentConfig::from_segment(&segment).is_err());
+    }
 }
@@ -0,0 +1,267 @@
+use super::HnswIndex;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A cache of loaded hnsw indices, bounded by their estimated size in bytes.
+/// # Description
+/// When the cached indices exceed the capacity, the least recently used ones are evicted
+/// and their directories under the temporary storage path are removed. A directory is
+/// moved aside before the state is unlocked and removed after, so an index loaded again
+/// under the same id has a directory of its own.
+/// # Notes
+/// An index that is referenced outside the cache is in use and pinned, it is never
+/// evicted. The cache can exceed its capacity while the indices in use do.
+pub(super) struct HnswIndexCache {
+    capacity_bytes: usize,
+    temporary_storage_path: PathBuf,
+    state: Mutex<CacheState>,
+}
+
+struct CacheEntry {
+    index: Arc<HnswIndex>,
+    // The size when the index was last measured, the index can not be measured while
+    // it is resized or saved
+    size_bytes: usize,
+    last_used: u64,
+}
+
+struct CacheState {
+    entries: HashMap<Uuid, CacheEntry>,
+    // Incremented on every access, to order the entries by recency
+    clock: u64,
+}
+
+impl HnswIndexCache {
+    pub(super) fn new(capacity_bytes: usize, temporary_storage_path: PathBuf) -> Self {
+        HnswIndexCache {
+            capacity_bytes,
+            temporary_storage_path,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub(super) fn get(&self, id: &Uuid) -> Option<Arc<HnswIndex>> {
+        let mut state = self.state.lock();
+        state.clock += 1;
+        let clock = state.clock;
+        match state.entries.get_mut(id) {
+            Some(entry) => {
+                entry.last_used = clock;
+                Some(entry.index.clone())
+            }
+            None => None,
+        }
+    }
+
+    /// Cache the index, evicting the least recently used indices not in use if the
+    /// cache exceeds its capacity.
+    pub(super) fn insert(&self, id: Uuid, index: Arc<HnswIndex>) {
+        let evicted = {
+            let mut state = self.state.lock();
+            state.clock += 1;
+            // Measured when evicting
+            let entry = CacheEntry {
+                index,
+                size_bytes: 0,
+                last_used: state.clock,
+            };
+            state.entries.insert(id, entry);
+            self.evict(&mut state)
+                .into_iter()
+                .map(|(id, entry)| (entry, self.move_directory_aside(&id)))
+                .collect::<Vec<_>>()
+        };
+        // The evicted indices are dropped with their entries before their files are removed
+        for (entry, path) in evicted {
+            drop(entry);
+            if let Some(path) = path {
+                Self::remove_directory(&path);
+            }
+        }
+    }
+
+    /// Remove the index from the cache along with its directory under the temporary
+    /// storage path.
+    /// # Notes
+    /// The index must not be in use, its files are removed regardless.
+    pub(super) fn remove(&self, id: &Uuid) {
+        let (entry, path) = {
+            let mut state = self.state.lock();
+            (state.entries.remove(id), self.move_directory_aside(id))
+        };
+        drop(entry);
+        if let Some(path) = path {
+            Self::remove_directory(&path);
+        }
+    }
+
+    // Rename the directory of the index to a path no index is loaded into, returns
+    // None if there is no directory to remove
+    fn move_directory_aside(&self, id: &Uuid) -> Option<PathBuf> {
+        let path = self.temporary_storage_path.join(id.to_string());
+        let removed_path =
+            self.temporary_storage_path
+                .join(format!("{}.removed.{}", id, Uuid::new_v4()));
+        match std::fs::rename(&path, &removed_path) {
+            Ok(_) => Some(removed_path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                tracing::error!("Failed to move hnsw index directory {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn remove_directory(path: &PathBuf) {
+        match std::fs::remove_dir_all(path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                tracing::error!("Failed to remove hnsw index directory {:?}: {}", path, e);
+            }
+        }
+    }
+
+    fn evict(&self, state: &mut CacheState) -> Vec<(Uuid, CacheEntry)> {
+        let mut total_bytes = 0;
+        for entry in state.entries.values_mut() {
+            // Indices grow as they are written to
+            if let Some(size_bytes) = entry.index.try_estimated_size_bytes() {
+                entry.size_bytes = size_bytes;
+            }
+            total_bytes += entry.size_bytes;
+        }
+
+        let mut evicted = Vec::new();
+        while total_bytes > self.capacity_bytes {
+            let victim = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| Arc::strong_count(&entry.index) == 1)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(id, _)| *id);
+            let id = match victim {
+                Some(id) => id,
+                // Every index left is in use
+                None => break,
+            };
+            if let Some(entry) = state.entries.remove(&id) {
+                total_bytes -= entry.size_bytes;
+                evicted.push((id, entry));
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::DistanceFunction;
+    use crate::index::{HnswImplementation, HnswIndexConfig, Index, IndexConfig};
+    use crate::index::{PersistentIndex, DEFAULT_RESIZE_FACTOR};
+
+    fn create_index(path: &PathBuf, id: Uuid) -> Arc<HnswIndex> {
+        let persist_path = path.join(id.to_string());
+        std::fs::create_dir_all(&persist_path).unwrap();
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: 4,
+                distance_function: DistanceFunction::Euclidean,
+            },
+            Some(&HnswIndexConfig {
+                max_elements: 100,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 10,
+                random_seed: 0,
+                persist_path: persist_path.to_str().unwrap().to_string(),
+                implementation: HnswImplementation::Native,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
+            }),
+            id,
+        )
+        .unwrap();
+        index.add(0, &[1.0, 2.0, 3.0, 4.0]);
+        index.save().unwrap();
+        Arc::new(index)
+    }
+
+    #[test]
+    fn it_evicts_the_least_recently_used_indices_not_in_use() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().to_path_buf();
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let index_size = create_index(&path, Uuid::new_v4()).estimated_size_bytes();
+        // Room for two indices
+        let cache = HnswIndexCache::new(2 * index_size, path.clone());
+
+        // Callers hold the indices they insert, as the provider does
+        for id in &ids[0..2] {
+            let index = create_index(&path, *id);
+            cache.insert(*id, index.clone());
+        }
+        assert_eq!(cache.len(), 2);
+        // The first index is now more recently used than the second
+        assert!(cache.get(&ids[0]).is_some());
+        let index = create_index(&path, ids[2]);
+        cache.insert(ids[2], index.clone());
+        drop(index);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&ids[1]).is_none());
+        assert!(!path.join(ids[1].to_string()).exists());
+        assert!(path.join(ids[0].to_string()).exists());
+
+        // Indices in use are pinned
+        let pinned = vec![cache.get(&ids[0]).unwrap(), cache.get(&ids[2]).unwrap()];
+        let index = create_index(&path, ids[3]);
+        cache.insert(ids[3], index.clone());
+        assert_eq!(cache.len(), 3);
+        drop(pinned);
+        drop(index);
+
+        let index = create_index(&path, ids[1]);
+        cache.insert(ids[1], index.clone());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&ids[3]).is_some());
+        assert!(cache.get(&ids[1]).is_some());
+        assert!(!path.join(ids[0].to_string()).exists());
+        // Only the directories of the cached indices and of the measured one are left
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn it_removes_an_index_without_touching_a_reload_of_it() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().to_path_buf();
+        let id = Uuid::new_v4();
+        let cache = HnswIndexCache::new(usize::MAX, path.clone());
+        cache.insert(id, create_index(&path, id));
+
+        // The directory is moved aside under the lock, a reload takes the path over
+        let removed_path = {
+            let _state = cache.state.lock();
+            cache.move_directory_aside(&id).unwrap()
+        };
+        assert!(!path.join(id.to_string()).exists());
+        let reloaded = create_index(&path, id);
+        HnswIndexCache::remove_directory(&removed_path);
+        assert!(!removed_path.exists());
+        assert!(path.join(id.to_string()).exists());
+        drop(reloaded);
+
+        cache.remove(&id);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+    }
+}
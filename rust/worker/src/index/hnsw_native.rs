@@ -0,0 +1,1619 @@
+use super::{
+    should_brute_force, HnswIndexBuffer, HnswIndexBuffers, HnswIndexConfig, HnswIndexInitError,
+    HnswSearchParams, Index, IndexConfig, IndexFilter, PersistentIndex, ScalarQuantizer,
+};
+use crate::distance::{binary_quantize, hamming_distance, DistanceFunction};
+use crate::errors::{ChromaError, ErrorCodes};
+use parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use thiserror::Error;
+use uuid::Uuid;
+
+// The files of an hnswlib persistent index. The native index reads and writes the same
+// format, so an index can be written by one implementation and loaded by the other.
+pub(crate) const HEADER_FILE: &str = "header.bin";
+pub(crate) const DATA_LEVEL0_FILE: &str = "data_level0.bin";
+pub(crate) const LENGTH_FILE: &str = "length.bin";
+pub(crate) const LINK_LISTS_FILE: &str = "link_lists.bin";
+
+// hnswlib stores ids as u32 (tableint), list sizes as u32 (linklistsizeint) and
+// labels as u64 (labeltype).
+const ID_SIZE: usize = 4;
+const LIST_SIZE_SIZE: usize = 4;
+const LABEL_SIZE: usize = 8;
+// The deleted mark lives in the third byte of the level 0 list size.
+const DELETE_MARK: u8 = 0x01;
+// hnswlib sets ef to 10 when an index is created or loaded.
+const DEFAULT_EF: usize = 10;
+
+#[derive(Error, Debug)]
+pub(crate) enum NativeHnswIndexError {
+    #[error("IO error")]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid hnsw index file `{0}`: {1}")]
+    InvalidFormat(String, String),
+}
+
+impl ChromaError for NativeHnswIndexError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            NativeHnswIndexError::IOError(_) => ErrorCodes::Internal,
+            NativeHnswIndexError::InvalidFormat(_, _) => ErrorCodes::Internal,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    id: u32,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+// The elements visited by a search, as hnswlib's VisitedList. An element is visited if
+// its tag is the current one, so the list is cleared by moving to the next tag and only
+// zeroed when the tags wrap around.
+struct VisitedList {
+    tags: Vec<u16>,
+    tag: u16,
+}
+
+impl VisitedList {
+    // Clear the list and make room for len elements
+    fn reset(&mut self, len: usize) {
+        if self.tags.len() < len {
+            self.tags.resize(len, 0);
+        }
+        self.tag = self.tag.wrapping_add(1);
+        if self.tag == 0 {
+            self.tags.fill(0);
+            self.tag = 1;
+        }
+    }
+
+    // Mark the element visited, returns whether it already was
+    fn visit(&mut self, id: u32) -> bool {
+        let tag = &mut self.tags[id as usize];
+        let visited = *tag == self.tag;
+        *tag = self.tag;
+        visited
+    }
+}
+
+// What distances are computed from, the binary code of a vector for the hamming
+// distance, its int8 code for a quantized index and the vector itself otherwise.
+enum Prepared<'a> {
+    Vector(Cow<'a, [f32]>),
+    Code(Cow<'a, [u64]>),
+    Int8(Cow<'a, [i8]>),
+}
+
+// The vectors of all elements. An index loaded from a persisted index reads them in
+// place from its data_level0 file, so that a mapped file is not copied, until the
+// index is written to.
+enum Vectors {
+    Owned(Vec<f32>),
+    // The vector of element i starts at float i * stride + offset of the buffer, which
+    // is aligned for f32 and little endian
+    Persisted {
+        buffer: HnswIndexBuffer,
+        stride: usize,
+        offset: usize,
+    },
+}
+
+impl Vectors {
+    fn get(&self, id: usize, dimensionality: usize) -> &[f32] {
+        match self {
+            Vectors::Owned(vectors) => &vectors[id * dimensionality..(id + 1) * dimensionality],
+            Vectors::Persisted {
+                buffer,
+                stride,
+                offset,
+            } => {
+                // Safety: the buffer is aligned for f32 and every bit pattern is a valid
+                // f32, which is checked when the index is loaded
+                let floats = unsafe {
+                    std::slice::from_raw_parts(
+                        buffer.as_ptr() as *const f32,
+                        buffer.len() / std::mem::size_of::<f32>(),
+                    )
+                };
+                let start = id * stride + offset;
+                &floats[start..start + dimensionality]
+            }
+        }
+    }
+
+    // The vectors to write to, copied out of the persisted index the first time
+    fn to_mut(&mut self, count: usize, dimensionality: usize) -> &mut Vec<f32> {
+        if let Vectors::Persisted { .. } = self {
+            let vectors = (0..count)
+                .flat_map(|id| self.get(id, dimensionality).iter().copied())
+                .collect();
+            *self = Vectors::Owned(vectors);
+        }
+        match self {
+            Vectors::Owned(vectors) => vectors,
+            Vectors::Persisted { .. } => unreachable!(),
+        }
+    }
+}
+
+// The graph, guarded by a lock in the index. Internal ids index every vector.
+struct Graph {
+    // Configured capacity, kept for the header. The native index grows past it, but
+    // resizing reserves room up front.
+    max_elements: usize,
+    labels: Vec<usize>,
+    label_to_id: HashMap<usize, u32>,
+    // Vectors of all elements, flattened
+    vectors: Vectors,
+    // Binary codes of all elements, flattened, only kept for the hamming distance
+    codes: Vec<u64>,
+    // Set once the index is quantized, see `NativeHnswIndex::set_quantizer`
+    quantizer: Option<ScalarQuantizer>,
+    // Int8 codes of all elements, flattened, only kept once the index is quantized
+    int8_codes: Vec<i8>,
+    // links[id][level] are the neighbors of id at level
+    links: Vec<Vec<Vec<u32>>>,
+    deleted: Vec<bool>,
+    deleted_count: usize,
+    entry_point: Option<u32>,
+    max_level: usize,
+    rng: StdRng,
+}
+
+/// A native Rust HNSW index.
+/// # Description
+/// An implementation of Hierarchical Navigable Small World graphs that follows hnswlib,
+/// and reads and writes its persistent index format. Unlike the hnswlib bindings it is
+/// safe to share between threads, queries only take a read lock on the graph.
+/// # Concurrency
+/// Adds are applied one at a time. An add searches for the neighbors of the element
+/// under an upgradable read lock, which queries share, and only takes the write lock
+/// to write the element and then its links. Queries therefore proceed while an add
+/// searches the graph, the bulk of its work, and only wait for the short writes.
+/// Deletes take the write lock, so they wait for the add in progress.
+/// # Notes
+/// Vectors are normalized for the cosine distance, as hnswlib does, so `get` returns
+/// normalized vectors for cosine indices.
+/// For the hamming distance the graph is built and searched over the binary codes of
+/// the vectors, see `binary_quantize`. The vectors are still kept, so that callers can
+/// rerank the results with a float distance.
+/// Once the index has a scalar quantizer, elements are linked and searched by the
+/// distance between their int8 codes, and queries return the quantized distances.
+/// The vectors are kept for the persisted files and for callers to rerank with.
+pub(crate) struct NativeHnswIndex {
+    graph: RwLock<Graph>,
+    dimensionality: usize,
+    distance_function: DistanceFunction,
+    m: usize,
+    max_m0: usize,
+    ef_construction: usize,
+    ef_search: AtomicUsize,
+    // Visited lists reused across searches, so a search does not allocate one the size
+    // of the graph
+    visited_lists: Mutex<Vec<VisitedList>>,
+    level_multiplier: f64,
+    persist_path: String,
+    pub(crate) id: Uuid,
+}
+
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    // hnswlib adds a small epsilon to avoid dividing by zero
+    let norm = 1.0 / (norm + 1e-30);
+    vector.iter().map(|x| x * norm).collect()
+}
+
+impl NativeHnswIndex {
+    fn new(
+        index_config: &IndexConfig,
+        max_elements: usize,
+        m: usize,
+        ef_construction: usize,
+        random_seed: usize,
+        persist_path: String,
+        id: Uuid,
+    ) -> Self {
+        NativeHnswIndex {
+            graph: RwLock::new(Graph {
+                max_elements,
+                labels: Vec::new(),
+                label_to_id: HashMap::new(),
+                vectors: Vectors::Owned(Vec::new()),
+                codes: Vec::new(),
+                quantizer: None,
+                int8_codes: Vec::new(),
+                links: Vec::new(),
+                deleted: Vec::new(),
+                deleted_count: 0,
+                entry_point: None,
+                max_level: 0,
+                rng: StdRng::seed_from_u64(random_seed as u64),
+            }),
+            dimensionality: index_config.dimensionality as usize,
+            distance_function: index_config.distance_function.clone(),
+            m,
+            max_m0: 2 * m,
+            ef_construction: ef_construction.max(m),
+            ef_search: AtomicUsize::new(DEFAULT_EF),
+            visited_lists: Mutex::new(Vec::new()),
+            level_multiplier: 1.0 / (m as f64).ln(),
+            persist_path,
+            id,
+        }
+    }
+
+    // Hold the graph as an add does while it searches for neighbors, so that other adds
+    // wait until the guard is dropped
+    #[cfg(test)]
+    pub(super) fn hold_for_add(&self) -> impl Sized + '_ {
+        self.graph.upgradable_read()
+    }
+
+    pub(crate) fn set_ef(&self, ef: usize) {
+        self.ef_search.store(ef, AtomicOrdering::Relaxed);
+    }
+
+    pub(crate) fn get_ef(&self) -> usize {
+        self.ef_search.load(AtomicOrdering::Relaxed)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let graph = self.graph.read();
+        graph.labels.len() - graph.deleted_count
+    }
+
+    pub(crate) fn len_with_deleted(&self) -> usize {
+        self.graph.read().labels.len()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        let graph = self.graph.read();
+        graph.max_elements.max(graph.labels.len())
+    }
+
+    pub(crate) fn resize(&self, new_size: usize) {
+        let mut graph = self.graph.write();
+        let additional = new_size.saturating_sub(graph.labels.len());
+        graph.labels.reserve(additional);
+        graph.links.reserve(additional);
+        graph.deleted.reserve(additional);
+        let count = graph.labels.len();
+        graph
+            .vectors
+            .to_mut(count, self.dimensionality)
+            .reserve(additional * self.dimensionality);
+        if self.distance_function == DistanceFunction::Hamming {
+            graph.codes.reserve(additional * self.code_words());
+        }
+        if graph.quantizer.is_some() {
+            graph.int8_codes.reserve(additional * self.dimensionality);
+        }
+        graph.max_elements = new_size;
+    }
+
+    pub(crate) fn quantizer(&self) -> Option<ScalarQuantizer> {
+        self.graph.read().quantizer.clone()
+    }
+
+    /// Quantize the index with the quantizer, replacing the quantizer it had. The
+    /// elements are quantized, but not relinked, so the graph built so far is kept.
+    /// Hamming indices are searched over their binary codes and are not quantized.
+    pub(crate) fn set_quantizer(&self, quantizer: ScalarQuantizer) {
+        if self.distance_function == DistanceFunction::Hamming {
+            return;
+        }
+        let mut graph = self.graph.write();
+        let graph = &mut *graph;
+        graph.int8_codes = (0..graph.labels.len())
+            .flat_map(|id| quantizer.quantize(graph.vectors.get(id, self.dimensionality)))
+            .collect();
+        graph.quantizer = Some(quantizer);
+    }
+
+    fn max_m(&self, level: usize) -> usize {
+        match level {
+            0 => self.max_m0,
+            _ => self.m,
+        }
+    }
+
+    fn vector<'graph>(&self, graph: &'graph Graph, id: u32) -> &'graph [f32] {
+        graph.vectors.get(id as usize, self.dimensionality)
+    }
+
+    fn code_words(&self) -> usize {
+        (self.dimensionality + 63) / 64
+    }
+
+    fn code<'graph>(&self, graph: &'graph Graph, id: u32) -> &'graph [u64] {
+        let start = id as usize * self.code_words();
+        &graph.codes[start..start + self.code_words()]
+    }
+
+    fn int8_code<'graph>(&self, graph: &'graph Graph, id: u32) -> &'graph [i8] {
+        let start = id as usize * self.dimensionality;
+        &graph.int8_codes[start..start + self.dimensionality]
+    }
+
+    // Prepare a vector, already normalized for the cosine distance, for searching
+    fn prepare<'a>(&self, graph: &Graph, vector: &'a [f32]) -> Prepared<'a> {
+        match (&self.distance_function, &graph.quantizer) {
+            (DistanceFunction::Hamming, _) => Prepared::Code(Cow::Owned(binary_quantize(vector))),
+            (_, Some(quantizer)) => Prepared::Int8(Cow::Owned(quantizer.quantize(vector))),
+            (_, None) => Prepared::Vector(Cow::Borrowed(vector)),
+        }
+    }
+
+    fn prepared<'graph>(&self, graph: &'graph Graph, id: u32) -> Prepared<'graph> {
+        match (&self.distance_function, &graph.quantizer) {
+            (DistanceFunction::Hamming, _) => Prepared::Code(Cow::Borrowed(self.code(graph, id))),
+            (_, Some(_)) => Prepared::Int8(Cow::Borrowed(self.int8_code(graph, id))),
+            (_, None) => Prepared::Vector(Cow::Borrowed(self.vector(graph, id))),
+        }
+    }
+
+    fn distance(&self, graph: &Graph, query: &Prepared, id: u32) -> f32 {
+        match query {
+            Prepared::Code(code) => hamming_distance(code, self.code(graph, id)) as f32,
+            Prepared::Int8(code) => self
+                .distance_function
+                .distance_int8(code, self.int8_code(graph, id)),
+            Prepared::Vector(vector) => self
+                .distance_function
+                .distance(vector, self.vector(graph, id)),
+        }
+    }
+
+    // Exact search over the elements the filter allows, for filters that allow few
+    // elements or exact queries
+    fn brute_force_query(
+        &self,
+        graph: &Graph,
+        query: &Prepared,
+        k: usize,
+        filter: &IndexFilter,
+    ) -> (Vec<usize>, Vec<f32>) {
+        let internal_ids: Vec<u32> = match filter.allowed {
+            Some(allowed) => allowed
+                .iter()
+                .filter_map(|label| graph.label_to_id.get(&(label as usize)).copied())
+                .collect(),
+            None => (0..graph.labels.len() as u32).collect(),
+        };
+        let mut results: Vec<Candidate> = internal_ids
+            .into_iter()
+            .filter(|internal_id| {
+                !graph.deleted[*internal_id as usize]
+                    && filter.allows(graph.labels[*internal_id as usize])
+            })
+            .map(|internal_id| Candidate {
+                distance: self.distance(graph, query, internal_id),
+                id: internal_id,
+            })
+            .collect();
+        results.sort();
+        results
+            .into_iter()
+            .take(k)
+            .map(|candidate| (graph.labels[candidate.id as usize], candidate.distance))
+            .unzip()
+    }
+
+    fn random_level(&self, graph: &mut Graph) -> usize {
+        // Sample from (0, 1], as -ln(0) is infinite
+        let uniform: f64 = 1.0 - graph.rng.gen::<f64>();
+        (-uniform.ln() * self.level_multiplier) as usize
+    }
+
+    // Greedily walk towards the query on a single level
+    fn greedy_search(
+        &self,
+        graph: &Graph,
+        query: &Prepared,
+        entry: Candidate,
+        level: usize,
+    ) -> Candidate {
+        let mut current = entry;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for neighbor in &graph.links[current.id as usize][level] {
+                let distance = self.distance(graph, query, *neighbor);
+                if distance < current.distance {
+                    current = Candidate {
+                        distance,
+                        id: *neighbor,
+                    };
+                    changed = true;
+                }
+            }
+        }
+        current
+    }
+
+    // Best first search of a single level. Every element is traversed, only those for
+    // which `accept` returns true are returned, closest first.
+    fn search_layer(
+        &self,
+        graph: &Graph,
+        query: &Prepared,
+        entry: Candidate,
+        ef: usize,
+        level: usize,
+        accept: &dyn Fn(u32) -> bool,
+    ) -> Vec<Candidate> {
+        let mut visited = self.visited_lists.lock().pop().unwrap_or(VisitedList {
+            tags: Vec::new(),
+            tag: 0,
+        });
+        visited.reset(graph.labels.len());
+        visited.visit(entry.id);
+        let mut candidates = BinaryHeap::new();
+        candidates.push(Reverse(entry));
+        let mut results: BinaryHeap<Candidate> = BinaryHeap::new();
+        if accept(entry.id) {
+            results.push(entry);
+        }
+        // The bound starts at the entry even if it is not accepted
+        let mut lower_bound = entry.distance;
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            if candidate.distance > lower_bound && results.len() >= ef {
+                break;
+            }
+            for neighbor in &graph.links[candidate.id as usize][level] {
+                if visited.visit(*neighbor) {
+                    continue;
+                }
+                let distance = self.distance(graph, query, *neighbor);
+                if results.len() < ef || distance < lower_bound {
+                    let neighbor = Candidate {
+                        distance,
+                        id: *neighbor,
+                    };
+                    candidates.push(Reverse(neighbor));
+                    if accept(neighbor.id) {
+                        results.push(neighbor);
+                        if results.len() > ef {
+                            results.pop();
+                        }
+                    }
+                    if let Some(furthest) = results.peek() {
+                        lower_bound = furthest.distance;
+                    }
+                }
+            }
+        }
+        self.visited_lists.lock().push(visited);
+        results.into_sorted_vec()
+    }
+
+    // The neighbor selection heuristic of hnswlib. A candidate is kept only if it is
+    // closer to the base than to every neighbor kept so far.
+    fn select_neighbors(&self, graph: &Graph, candidates: &[Candidate], m: usize) -> Vec<u32> {
+        if candidates.len() <= m {
+            return candidates.iter().map(|candidate| candidate.id).collect();
+        }
+        let mut selected: Vec<Candidate> = Vec::with_capacity(m);
+        for candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            let candidate_vector = self.prepared(graph, candidate.id);
+            let good = selected
+                .iter()
+                .all(|kept| self.distance(graph, &candidate_vector, kept.id) >= candidate.distance);
+            if good {
+                selected.push(*candidate);
+            }
+        }
+        selected.iter().map(|candidate| candidate.id).collect()
+    }
+
+    fn connect(&self, graph: &mut Graph, id: u32, neighbors: &[u32], level: usize) {
+        graph.links[id as usize][level] = neighbors.to_vec();
+        let max_m = self.max_m(level);
+        for neighbor in neighbors {
+            let neighbor = *neighbor;
+            if graph.links[neighbor as usize][level].contains(&id) {
+                continue;
+            }
+            if graph.links[neighbor as usize][level].len() < max_m {
+                graph.links[neighbor as usize][level].push(id);
+                continue;
+            }
+            // The neighbor is full, pick its best neighbors from the old ones and the new one
+            let neighbor_vector = self.prepared(graph, neighbor);
+            let mut candidates: Vec<Candidate> = graph.links[neighbor as usize][level]
+                .iter()
+                .chain(std::iter::once(&id))
+                .map(|candidate| Candidate {
+                    distance: self.distance(graph, &neighbor_vector, *candidate),
+                    id: *candidate,
+                })
+                .collect();
+            candidates.sort();
+            let selected = self.select_neighbors(graph, &candidates, max_m);
+            graph.links[neighbor as usize][level] = selected;
+        }
+    }
+
+    // The neighbors of an element on each of its levels that are in the graph, closest
+    // first. Searching only reads the graph, so it runs alongside queries.
+    fn search_neighbors(&self, graph: &Graph, id: u32, vector: &Prepared) -> Vec<Vec<u32>> {
+        let level = graph.links[id as usize].len() - 1;
+        let entry_point = match graph.entry_point {
+            Some(entry_point) if graph.labels.len() > 1 => entry_point,
+            _ => return Vec::new(),
+        };
+
+        let mut entry = Candidate {
+            distance: self.distance(graph, vector, entry_point),
+            id: entry_point,
+        };
+        let mut current_level = graph.max_level;
+        while current_level > level {
+            entry = self.greedy_search(graph, vector, entry, current_level);
+            current_level -= 1;
+        }
+        let mut neighbors = vec![Vec::new(); current_level + 1];
+        for level in (0..=current_level).rev() {
+            let candidates = self.search_layer(
+                graph,
+                vector,
+                entry,
+                self.ef_construction,
+                level,
+                &|candidate| candidate != id,
+            );
+            neighbors[level] = self.select_neighbors(graph, &candidates, self.m);
+            if let Some(closest) = candidates.first() {
+                entry = *closest;
+            }
+        }
+        neighbors
+    }
+
+    // Link an element into the graph, on every level up to its own, to the neighbors
+    // found by search_neighbors
+    fn link(&self, graph: &mut Graph, id: u32, neighbors: &[Vec<u32>]) {
+        let level = graph.links[id as usize].len() - 1;
+        if graph.entry_point.is_none() {
+            graph.entry_point = Some(id);
+            graph.max_level = level;
+            return;
+        }
+        for (level, neighbors) in neighbors.iter().enumerate() {
+            self.connect(graph, id, neighbors, level);
+        }
+        if level > graph.max_level {
+            graph.entry_point = Some(id);
+            graph.max_level = level;
+        }
+    }
+
+    // Write the vector of an element, a new one or one that replaces the vector of the
+    // label, without linking it. Nothing links to a new element until it is linked.
+    fn write_element<'a>(
+        &self,
+        graph: &mut Graph,
+        id: usize,
+        vector: &'a [f32],
+    ) -> (u32, Prepared<'a>) {
+        match graph.label_to_id.get(&id).cloned() {
+            Some(internal_id) => {
+                // Replace the vector, the element is relinked on its levels
+                let start = internal_id as usize * self.dimensionality;
+                let count = graph.labels.len();
+                graph.vectors.to_mut(count, self.dimensionality)
+                    [start..start + self.dimensionality]
+                    .copy_from_slice(vector);
+                let prepared = self.prepare(graph, vector);
+                match &prepared {
+                    Prepared::Code(code) => {
+                        let start = internal_id as usize * self.code_words();
+                        graph.codes[start..start + self.code_words()].copy_from_slice(code);
+                    }
+                    Prepared::Int8(code) => {
+                        let start = internal_id as usize * self.dimensionality;
+                        graph.int8_codes[start..start + self.dimensionality].copy_from_slice(code);
+                    }
+                    Prepared::Vector(_) => {}
+                }
+                if graph.deleted[internal_id as usize] {
+                    graph.deleted[internal_id as usize] = false;
+                    graph.deleted_count -= 1;
+                }
+                (internal_id, prepared)
+            }
+            None => {
+                let internal_id = graph.labels.len() as u32;
+                let level = self.random_level(graph);
+                graph
+                    .vectors
+                    .to_mut(internal_id as usize, self.dimensionality)
+                    .extend_from_slice(vector);
+                graph.labels.push(id);
+                graph.label_to_id.insert(id, internal_id);
+                let prepared = self.prepare(graph, vector);
+                match &prepared {
+                    Prepared::Code(code) => graph.codes.extend_from_slice(code),
+                    Prepared::Int8(code) => graph.int8_codes.extend_from_slice(code),
+                    Prepared::Vector(_) => {}
+                }
+                graph.links.push(vec![Vec::new(); level + 1]);
+                graph.deleted.push(false);
+                (internal_id, prepared)
+            }
+        }
+    }
+
+    fn file_path(&self, file: &str) -> std::path::PathBuf {
+        Path::new(&self.persist_path).join(file)
+    }
+
+    fn size_links_level0(&self) -> usize {
+        self.max_m0 * ID_SIZE + LIST_SIZE_SIZE
+    }
+
+    fn size_links_per_element(&self) -> usize {
+        self.m * ID_SIZE + LIST_SIZE_SIZE
+    }
+
+    fn size_data_per_element(&self) -> usize {
+        self.size_links_level0() + self.dimensionality * 4 + LABEL_SIZE
+    }
+
+    fn write_files(&self) -> Result<(), NativeHnswIndexError> {
+        let graph = self.graph.read();
+        let count = graph.labels.len();
+        let size_links_level0 = self.size_links_level0();
+        let size_links_per_element = self.size_links_per_element();
+        let size_data_per_element = self.size_data_per_element();
+
+        let mut header = Vec::with_capacity(96);
+        // offsetLevel0_
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&(graph.max_elements.max(count) as u64).to_le_bytes());
+        header.extend_from_slice(&(count as u64).to_le_bytes());
+        header.extend_from_slice(&(size_data_per_element as u64).to_le_bytes());
+        // label_offset_
+        header.extend_from_slice(
+            &((size_links_level0 + self.dimensionality * 4) as u64).to_le_bytes(),
+        );
+        // offsetData_
+        header.extend_from_slice(&(size_links_level0 as u64).to_le_bytes());
+        let (max_level, entry_point) = match graph.entry_point {
+            Some(entry_point) => (graph.max_level as i32, entry_point),
+            None => (-1, u32::MAX),
+        };
+        header.extend_from_slice(&max_level.to_le_bytes());
+        header.extend_from_slice(&entry_point.to_le_bytes());
+        header.extend_from_slice(&(self.m as u64).to_le_bytes());
+        header.extend_from_slice(&(self.max_m0 as u64).to_le_bytes());
+        header.extend_from_slice(&(self.m as u64).to_le_bytes());
+        header.extend_from_slice(&self.level_multiplier.to_le_bytes());
+        header.extend_from_slice(&(self.ef_construction as u64).to_le_bytes());
+
+        let mut data_level0 = Vec::with_capacity(count * size_data_per_element);
+        let mut lengths = Vec::with_capacity(count * 4);
+        let mut link_lists = Vec::new();
+        for id in 0..count {
+            let level0 = &graph.links[id][0];
+            let mut list_size = (level0.len() as u32).to_le_bytes();
+            if graph.deleted[id] {
+                list_size[2] |= DELETE_MARK;
+            }
+            data_level0.extend_from_slice(&list_size);
+            for slot in 0..self.max_m0 {
+                let neighbor = level0.get(slot).cloned().unwrap_or(0);
+                data_level0.extend_from_slice(&neighbor.to_le_bytes());
+            }
+            for value in self.vector(&graph, id as u32) {
+                data_level0.extend_from_slice(&value.to_le_bytes());
+            }
+            data_level0.extend_from_slice(&(graph.labels[id] as u64).to_le_bytes());
+
+            let upper_levels = graph.links[id].len() - 1;
+            lengths
+                .extend_from_slice(&((upper_levels * size_links_per_element) as u32).to_le_bytes());
+            for level in 1..=upper_levels {
+                let neighbors = &graph.links[id][level];
+                link_lists.extend_from_slice(&(neighbors.len() as u32).to_le_bytes());
+                for slot in 0..self.m {
+                    let neighbor = neighbors.get(slot).cloned().unwrap_or(0);
+                    link_lists.extend_from_slice(&neighbor.to_le_bytes());
+                }
+            }
+        }
+
+        for (file, bytes) in [
+            (HEADER_FILE, header),
+            (DATA_LEVEL0_FILE, data_level0),
+            (LENGTH_FILE, lengths),
+            (LINK_LISTS_FILE, link_lists),
+        ] {
+            let mut file = std::fs::File::create(self.file_path(file))?;
+            file.write_all(&bytes)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Load the index from the files of a persisted index, read into memory or mapped
+    /// from disk. The index is saved to path.
+    /// # Notes
+    /// The vectors are read in place from the data_level0 buffer, which the index keeps,
+    /// rather than copied. The links are parsed, they are small next to the vectors.
+    pub(crate) fn load_from_buffers(
+        buffers: HnswIndexBuffers,
+        path: &str,
+        index_config: &IndexConfig,
+        id: Uuid,
+    ) -> Result<Self, NativeHnswIndexError> {
+        fn invalid(file: &str, reason: &str) -> NativeHnswIndexError {
+            NativeHnswIndexError::InvalidFormat(file.to_string(), reason.to_string())
+        }
+        fn u64_at(bytes: &[u8], offset: usize) -> usize {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+        }
+        fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        }
+
+        let header = &buffers.header[..];
+        if header.len() < 96 {
+            return Err(invalid(HEADER_FILE, "header is too short"));
+        }
+        let max_elements = u64_at(header, 8);
+        let count = u64_at(header, 16);
+        let size_data_per_element = u64_at(header, 24);
+        let label_offset = u64_at(header, 32);
+        let offset_data = u64_at(header, 40);
+        let max_level = i32::from_le_bytes(header[48..52].try_into().unwrap());
+        let entry_point = u32_at(header, 52);
+        let max_m = u64_at(header, 56);
+        let max_m0 = u64_at(header, 64);
+        let m = u64_at(header, 72);
+        let level_multiplier = f64::from_le_bytes(header[80..88].try_into().unwrap());
+        let ef_construction = u64_at(header, 88);
+        // hnswlib always uses M neighbors on the upper levels
+        if max_m != m || m < 2 {
+            return Err(invalid(HEADER_FILE, "unsupported M"));
+        }
+
+        let mut index = NativeHnswIndex::new(
+            index_config,
+            max_elements,
+            m,
+            ef_construction,
+            0,
+            path.to_string(),
+            id,
+        );
+        index.max_m0 = max_m0;
+        index.level_multiplier = level_multiplier;
+        let dimensionality = index.dimensionality;
+        if offset_data != index.size_links_level0()
+            || label_offset != offset_data + dimensionality * 4
+            || size_data_per_element != index.size_data_per_element()
+        {
+            return Err(invalid(
+                HEADER_FILE,
+                &format!(
+                    "element layout does not match an index of dimensionality {}",
+                    dimensionality
+                ),
+            ));
+        }
+
+        let data_level0 = &buffers.data_level0[..];
+        // The vectors of a misaligned or big endian buffer are copied out instead
+        let vectors_in_place = cfg!(target_endian = "little")
+            && data_level0.as_ptr() as usize % std::mem::align_of::<f32>() == 0;
+        // The element count is read from the header, so the sizes it implies are checked
+        // against the files before anything is allocated for the elements.
+        if count > max_elements {
+            return Err(invalid(HEADER_FILE, "element count exceeds max elements"));
+        }
+        match count.checked_mul(size_data_per_element) {
+            Some(size) if size <= data_level0.len() => {}
+            _ => return Err(invalid(DATA_LEVEL0_FILE, "file is too short")),
+        }
+        let lengths = &buffers.length[..];
+        match count.checked_mul(4) {
+            Some(size) if size <= lengths.len() => {}
+            _ => return Err(invalid(LENGTH_FILE, "file is too short")),
+        }
+        let link_lists = &buffers.link_lists[..];
+
+        let size_links_per_element = index.size_links_per_element();
+        {
+            let mut graph = index.graph.write();
+            let graph = &mut *graph;
+            graph.labels.reserve(count);
+            let mut vectors = Vec::new();
+            if !vectors_in_place {
+                vectors.reserve(count * dimensionality);
+            }
+            let mut link_lists_offset = 0;
+            for element in 0..count {
+                let base = element * size_data_per_element;
+                let list_size = &data_level0[base..base + LIST_SIZE_SIZE];
+                let neighbor_count = u16::from_le_bytes([list_size[0], list_size[1]]) as usize;
+                let deleted = list_size[2] & DELETE_MARK != 0;
+                if neighbor_count > max_m0 {
+                    return Err(invalid(DATA_LEVEL0_FILE, "too many level 0 neighbors"));
+                }
+                let mut levels = vec![(0..neighbor_count)
+                    .map(|i| u32_at(data_level0, base + LIST_SIZE_SIZE + i * ID_SIZE))
+                    .collect::<Vec<u32>>()];
+                if !vectors_in_place {
+                    for i in 0..dimensionality {
+                        let offset = base + offset_data + i * 4;
+                        vectors.push(f32::from_le_bytes(
+                            data_level0[offset..offset + 4].try_into().unwrap(),
+                        ));
+                    }
+                }
+                let label = u64_at(data_level0, base + label_offset);
+
+                let links_size = u32_at(lengths, element * 4) as usize;
+                if links_size % size_links_per_element != 0
+                    || links_size > link_lists.len() - link_lists_offset
+                {
+                    return Err(invalid(LINK_LISTS_FILE, "link lists do not match lengths"));
+                }
+                for level in 0..links_size / size_links_per_element {
+                    let level_base = link_lists_offset + level * size_links_per_element;
+                    let neighbor_count = u32_at(link_lists, level_base) as usize & 0xFFFF;
+                    if neighbor_count > m {
+                        return Err(invalid(LINK_LISTS_FILE, "too many neighbors"));
+                    }
+                    levels.push(
+                        (0..neighbor_count)
+                            .map(|i| u32_at(link_lists, level_base + LIST_SIZE_SIZE + i * ID_SIZE))
+                            .collect(),
+                    );
+                }
+                link_lists_offset += links_size;
+
+                graph.labels.push(label);
+                graph.label_to_id.insert(label, element as u32);
+                graph.links.push(levels);
+                graph.deleted.push(deleted);
+                if deleted {
+                    graph.deleted_count += 1;
+                }
+            }
+            let out_of_range = graph
+                .links
+                .iter()
+                .flatten()
+                .flatten()
+                .any(|neighbor| *neighbor as usize >= count);
+            if out_of_range {
+                return Err(invalid(DATA_LEVEL0_FILE, "neighbor id out of range"));
+            }
+            graph.vectors = match vectors_in_place {
+                // The element size and the offset of the vectors are multiples of 4
+                true => Vectors::Persisted {
+                    buffer: buffers.data_level0,
+                    stride: size_data_per_element / 4,
+                    offset: offset_data / 4,
+                },
+                false => Vectors::Owned(vectors),
+            };
+            if index.distance_function == DistanceFunction::Hamming {
+                graph.codes = (0..count)
+                    .flat_map(|id| binary_quantize(graph.vectors.get(id, dimensionality)))
+                    .collect();
+            }
+            if count > 0 {
+                if entry_point as usize >= count || max_level < 0 {
+                    return Err(invalid(HEADER_FILE, "invalid entry point"));
+                }
+                graph.entry_point = Some(entry_point);
+                graph.max_level = max_level as usize;
+            }
+        }
+        Ok(index)
+    }
+}
+
+impl Index<HnswIndexConfig> for NativeHnswIndex {
+    fn init(
+        index_config: &IndexConfig,
+        hnsw_config: Option<&HnswIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        match hnsw_config {
+            None => Err(Box::new(HnswIndexInitError::NoConfigProvided)),
+            Some(config) => {
+                let index = NativeHnswIndex::new(
+                    index_config,
+                    config.max_elements,
+                    config.m,
+                    config.ef_construction,
+                    config.random_seed,
+                    config.persist_path.clone(),
+                    id,
+                );
+                index.set_ef(config.ef_search);
+                Ok(index)
+            }
+        }
+    }
+
+    fn add(&self, id: usize, vector: &[f32]) {
+        let vector = match self.distance_function {
+            DistanceFunction::Cosine => normalize(vector),
+            _ => vector.to_vec(),
+        };
+        // The upgradable lock keeps other adds and deletes out for the whole add, so the
+        // graph does not change between the search and the writes
+        let graph = self.graph.upgradable_read();
+        let mut graph = RwLockUpgradableReadGuard::upgrade(graph);
+        let (internal_id, prepared) = self.write_element(&mut graph, id, &vector);
+        let graph = RwLockWriteGuard::downgrade_to_upgradable(graph);
+        let neighbors = self.search_neighbors(&graph, internal_id, &prepared);
+        let mut graph = RwLockUpgradableReadGuard::upgrade(graph);
+        self.link(&mut graph, internal_id, &neighbors);
+    }
+
+    fn delete(&self, id: usize) {
+        let mut graph = self.graph.write();
+        if let Some(internal_id) = graph.label_to_id.get(&id).cloned() {
+            if !graph.deleted[internal_id as usize] {
+                graph.deleted[internal_id as usize] = true;
+                graph.deleted_count += 1;
+            }
+        }
+    }
+
+    fn query(&self, vector: &[f32], k: usize, filter: &IndexFilter) -> (Vec<usize>, Vec<f32>) {
+        self.query_with_params(vector, k, filter, &HnswSearchParams::default())
+    }
+
+    fn get(&self, id: usize) -> Option<Vec<f32>> {
+        let graph = self.graph.read();
+        match graph.label_to_id.get(&id) {
+            Some(internal_id) if !graph.deleted[*internal_id as usize] => {
+                Some(self.vector(&graph, *internal_id).to_vec())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl NativeHnswIndex {
+    /// The nearest neighbors of the vector, searched with the given parameters instead
+    /// of those of the index.
+    pub(crate) fn query_with_params(
+        &self,
+        vector: &[f32],
+        k: usize,
+        filter: &IndexFilter,
+        params: &HnswSearchParams,
+    ) -> (Vec<usize>, Vec<f32>) {
+        let query = match self.distance_function {
+            DistanceFunction::Cosine => normalize(vector),
+            _ => vector.to_vec(),
+        };
+        let graph = self.graph.read();
+        let query = self.prepare(&graph, &query);
+        let entry_point = match graph.entry_point {
+            Some(entry_point) => entry_point,
+            None => return (Vec::new(), Vec::new()),
+        };
+
+        if params.exact || should_brute_force(filter, graph.labels.len() - graph.deleted_count) {
+            return self.brute_force_query(&graph, &query, k, filter);
+        }
+
+        let accept = |internal_id: u32| {
+            !graph.deleted[internal_id as usize]
+                && filter.allows(graph.labels[internal_id as usize])
+        };
+
+        let mut entry = Candidate {
+            distance: self.distance(&graph, &query, entry_point),
+            id: entry_point,
+        };
+        for level in (1..=graph.max_level).rev() {
+            entry = self.greedy_search(&graph, &query, entry, level);
+        }
+        let ef = params.ef.unwrap_or_else(|| self.get_ef()).max(k);
+        let results = self.search_layer(&graph, &query, entry, ef, 0, &accept);
+
+        results
+            .into_iter()
+            .take(k)
+            .map(|candidate| (graph.labels[candidate.id as usize], candidate.distance))
+            .unzip()
+    }
+}
+
+impl PersistentIndex<HnswIndexConfig> for NativeHnswIndex {
+    fn save(&self) -> Result<(), Box<dyn ChromaError>> {
+        match self.write_files() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn load(
+        path: &str,
+        index_config: &IndexConfig,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        let buffers = match HnswIndexBuffers::read(Path::new(path)) {
+            Ok(buffers) => buffers,
+            Err(e) => return Err(Box::new(NativeHnswIndexError::IOError(e))),
+        };
+        match NativeHnswIndex::load_from_buffers(buffers, path, index_config, id) {
+            Ok(index) => Ok(index),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::utils;
+    use roaring::RoaringBitmap;
+    use tempfile::tempdir;
+
+    fn create_index(
+        n: usize,
+        d: usize,
+        distance_function: DistanceFunction,
+        persist_path: &str,
+    ) -> NativeHnswIndex {
+        let index = NativeHnswIndex::init(
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function,
+            },
+            Some(&HnswIndexConfig {
+                max_elements: n,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 100,
+                random_seed: 0,
+                persist_path: persist_path.to_string(),
+                implementation: super::super::HnswImplementation::Native,
+                resize_factor: super::super::DEFAULT_RESIZE_FACTOR,
+            }),
+            Uuid::new_v4(),
+        );
+        match index {
+            Err(e) => panic!("Error initializing index: {}", e),
+            Ok(index) => index,
+        }
+    }
+
+    fn brute_force(
+        data: &[f32],
+        d: usize,
+        query: &[f32],
+        k: usize,
+        distance_function: &DistanceFunction,
+    ) -> Vec<usize> {
+        let mut distances: Vec<(f32, usize)> = (0..data.len() / d)
+            .map(|i| {
+                (
+                    distance_function.distance(query, &data[i * d..(i + 1) * d]),
+                    i,
+                )
+            })
+            .collect();
+        distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+        distances.into_iter().take(k).map(|(_, i)| i).collect()
+    }
+
+    #[test]
+    fn it_can_add_and_query_with_high_recall() {
+        let n = 2000;
+        let d = 32;
+        let k = 10;
+        let tmp_dir = tempdir().unwrap();
+        let distance_function = DistanceFunction::Euclidean;
+        let index = create_index(
+            n,
+            d,
+            distance_function.clone(),
+            tmp_dir.path().to_str().unwrap(),
+        );
+        let data = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        assert_eq!(index.len(), n);
+
+        let queries = utils::generate_random_data(50, d);
+        let mut hits = 0;
+        for q in 0..50 {
+            let query = &queries[q * d..(q + 1) * d];
+            let (ids, distances) = index.query(query, k, &IndexFilter::default());
+            assert_eq!(ids.len(), k);
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+            let expected = brute_force(&data, d, query, k, &distance_function);
+            hits += ids.iter().filter(|id| expected.contains(id)).count();
+        }
+        let recall = hits as f32 / (50 * k) as f32;
+        assert!(recall > 0.9, "recall {} is too low", recall);
+    }
+
+    #[test]
+    fn it_can_add_in_parallel_and_query_concurrently() {
+        let n = 500;
+        let d = 16;
+        let tmp_dir = tempdir().unwrap();
+        let index = create_index(
+            n,
+            d,
+            DistanceFunction::InnerProduct,
+            tmp_dir.path().to_str().unwrap(),
+        );
+        let data = utils::generate_random_data(n, d);
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let index = &index;
+                let data = &data;
+                scope.spawn(move || {
+                    for i in (thread..n).step_by(4) {
+                        index.add(i, &data[i * d..(i + 1) * d]);
+                        let (ids, _) =
+                            index.query(&data[i * d..(i + 1) * d], 1, &IndexFilter::default());
+                        assert_eq!(ids.len(), 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(index.len(), n);
+        for i in 0..n {
+            assert_eq!(index.get(i).unwrap(), data[i * d..(i + 1) * d].to_vec());
+        }
+    }
+
+    #[test]
+    fn it_can_delete_update_and_filter() {
+        let n = 300;
+        let d = 8;
+        let tmp_dir = tempdir().unwrap();
+        let index = create_index(
+            n,
+            d,
+            DistanceFunction::Euclidean,
+            tmp_dir.path().to_str().unwrap(),
+        );
+        let data = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+
+        index.delete(0);
+        assert_eq!(index.len(), n - 1);
+        assert_eq!(index.get(0), None);
+        let (ids, _) = index.query(&data[0..d], 10, &IndexFilter::default());
+        assert!(!ids.contains(&0));
+
+        // Re-adding a deleted id restores it with the new vector
+        let new_vector = vec![10.0; d];
+        index.add(0, &new_vector);
+        assert_eq!(index.len(), n);
+        let (ids, distances) = index.query(&new_vector, 1, &IndexFilter::default());
+        assert_eq!(ids, vec![0]);
+        assert_eq!(distances, vec![0.0]);
+
+        let allowed = RoaringBitmap::from_iter([1, 2, 3]);
+        let disallowed = RoaringBitmap::from_iter([3]);
+        let filter = IndexFilter::new(Some(&allowed), Some(&disallowed));
+        let (ids, _) = index.query(&data[d..2 * d], 10, &filter);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[0], 1);
+        assert!(ids.contains(&2));
+    }
+
+    #[test]
+    fn it_normalizes_cosine_vectors() {
+        let tmp_dir = tempdir().unwrap();
+        let index = create_index(
+            10,
+            2,
+            DistanceFunction::Cosine,
+            tmp_dir.path().to_str().unwrap(),
+        );
+        index.add(1, &[3.0, 4.0]);
+        index.add(2, &[-1.0, 0.0]);
+        let vector = index.get(1).unwrap();
+        assert!((vector[0] - 0.6).abs() < 1e-6);
+        assert!((vector[1] - 0.8).abs() < 1e-6);
+        let (ids, distances) = index.query(&[6.0, 8.0], 2, &IndexFilter::default());
+        assert_eq!(ids, vec![1, 2]);
+        assert!(distances[0].abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_queries_with_manhattan_and_jaccard_distances() {
+        let n = 1000;
+        let d = 16;
+        let k = 10;
+        let data = utils::generate_random_data(n, d);
+        for distance_function in [DistanceFunction::Manhattan, DistanceFunction::Jaccard] {
+            let tmp_dir = tempdir().unwrap();
+            let index = create_index(
+                n,
+                d,
+                distance_function.clone(),
+                tmp_dir.path().to_str().unwrap(),
+            );
+            for i in 0..n {
+                index.add(i, &data[i * d..(i + 1) * d]);
+            }
+
+            let mut hits = 0;
+            for q in 0..20 {
+                let query = &data[q * d..(q + 1) * d];
+                let (ids, distances) = index.query(query, k, &IndexFilter::default());
+                assert_eq!(ids[0], q);
+                assert_eq!(
+                    distances[1],
+                    distance_function.distance(query, &data[ids[1] * d..(ids[1] + 1) * d])
+                );
+                let expected = brute_force(&data, d, query, k, &distance_function);
+                hits += ids.iter().filter(|id| expected.contains(id)).count();
+            }
+            let recall = hits as f32 / (20 * k) as f32;
+            assert!(recall > 0.9, "recall {} is too low", recall);
+        }
+    }
+
+    #[test]
+    fn it_searches_binary_codes_for_hamming() {
+        let n = 1000;
+        let d = 96;
+        let k = 10;
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().to_str().unwrap();
+        let distance_function = DistanceFunction::Hamming;
+        let index = create_index(n, d, distance_function.clone(), path);
+        let data: Vec<f32> = utils::generate_random_data(n, d)
+            .iter()
+            .map(|x| x - 0.5)
+            .collect();
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+
+        let mut hits = 0;
+        for q in 0..20 {
+            let query = &data[q * d..(q + 1) * d];
+            let (ids, distances) = index.query(query, k, &IndexFilter::default());
+            assert_eq!(ids[0], q);
+            assert_eq!(distances[0], 0.0);
+            // Distances are the number of differing signs
+            for (id, distance) in ids.iter().zip(&distances) {
+                assert_eq!(
+                    *distance,
+                    distance_function.distance(query, &data[id * d..(id + 1) * d])
+                );
+            }
+            // Ties make the exact neighbors ambiguous, compare the distances instead
+            let expected = brute_force(&data, d, query, k, &distance_function);
+            let kth = distance_function.distance(query, &data[expected[k - 1] * d..][..d]);
+            hits += distances
+                .iter()
+                .filter(|distance| **distance <= kth)
+                .count();
+        }
+        let recall = hits as f32 / (20 * k) as f32;
+        assert!(recall > 0.9, "recall {} is too low", recall);
+        // The float vectors are kept for reranking
+        assert_eq!(index.get(3).unwrap(), data[3 * d..4 * d].to_vec());
+
+        index.save().unwrap();
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function,
+        };
+        let loaded = NativeHnswIndex::load(path, &index_config, index.id).unwrap();
+        loaded.set_ef(100);
+        let query = &data[7 * d..8 * d];
+        assert_eq!(
+            loaded.query(query, k, &IndexFilter::default()),
+            index.query(query, k, &IndexFilter::default())
+        );
+    }
+
+    #[test]
+    fn it_searches_int8_codes_once_quantized() {
+        let n = 1000;
+        let d = 32;
+        let k = 10;
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().to_str().unwrap();
+        let distance_function = DistanceFunction::Cosine;
+        let index = create_index(n, d, distance_function.clone(), path);
+        let data: Vec<f32> = utils::generate_random_data(n, d)
+            .iter()
+            .map(|x| x - 0.5)
+            .collect();
+        let normalized: Vec<f32> = data.chunks(d).flat_map(normalize).collect();
+        // The elements added before the quantizer is set are quantized with it
+        for i in 0..n / 2 {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        let quantizer = ScalarQuantizer::train(&normalized, d, &distance_function);
+        index.set_quantizer(quantizer.clone());
+        for i in n / 2..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        assert_eq!(index.quantizer(), Some(quantizer.clone()));
+
+        let mut hits = 0;
+        for q in 0..20 {
+            let query = &normalized[q * d..(q + 1) * d];
+            let (ids, distances) = index.query(query, 4 * k, &IndexFilter::default());
+            // Distances are between the int8 codes
+            for (id, distance) in ids.iter().zip(&distances) {
+                let code = quantizer.quantize(&normalized[id * d..(id + 1) * d]);
+                assert_eq!(
+                    *distance,
+                    distance_function.distance_int8(&quantizer.quantize(query), &code)
+                );
+            }
+            // Rerank the candidates with the full vectors, which the index keeps
+            let mut reranked: Vec<(f32, usize)> = ids
+                .iter()
+                .map(|id| {
+                    (
+                        distance_function.distance(query, &index.get(*id).unwrap()),
+                        *id,
+                    )
+                })
+                .collect();
+            reranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let expected = brute_force(&normalized, d, query, k, &distance_function);
+            hits += reranked
+                .iter()
+                .take(k)
+                .filter(|(_, id)| expected.contains(id))
+                .count();
+        }
+        let recall = hits as f32 / (20 * k) as f32;
+        assert!(recall > 0.9, "recall {} is too low", recall);
+    }
+
+    #[test]
+    fn it_clears_visited_lists_between_searches() {
+        let mut visited = VisitedList {
+            tags: Vec::new(),
+            tag: 0,
+        };
+        visited.reset(4);
+        assert!(!visited.visit(1));
+        assert!(visited.visit(1));
+
+        // Each reset clears the list, also when the tags wrap around
+        for _ in 0..u16::MAX as usize + 1 {
+            visited.reset(4);
+            assert!(!visited.visit(1));
+        }
+        visited.reset(8);
+        assert!(!visited.visit(1));
+        assert!(!visited.visit(7));
+        assert!(visited.visit(7));
+    }
+
+    #[test]
+    fn it_queries_while_an_add_searches_the_graph() {
+        let n = 100;
+        let d = 8;
+        let tmp_dir = tempdir().unwrap();
+        let index = create_index(
+            n,
+            d,
+            DistanceFunction::Euclidean,
+            tmp_dir.path().to_str().unwrap(),
+        );
+        let data = utils::generate_random_data(n, d);
+        for i in 0..n - 1 {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+
+        let (index, data) = (&index, &data);
+        std::thread::scope(|scope| {
+            // Hold the graph as an add does while it searches for neighbors
+            let searching = index.graph.upgradable_read();
+            let (sender, receiver) = std::sync::mpsc::channel();
+            for id in [0, 1] {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let query = &data[id * d..(id + 1) * d];
+                    let (ids, _) = index.query(query, 1, &IndexFilter::default());
+                    sender.send((id, ids)).unwrap();
+                });
+            }
+            for _ in 0..2 {
+                let (id, ids) = receiver
+                    .recv_timeout(std::time::Duration::from_secs(10))
+                    .expect("query waited for the add");
+                assert_eq!(ids, vec![id]);
+            }
+
+            // Other adds wait for it, so the graph does not change under its search
+            let (sender, receiver) = std::sync::mpsc::channel();
+            scope.spawn(move || {
+                index.add(n - 1, &data[(n - 1) * d..]);
+                sender.send(()).unwrap();
+            });
+            assert!(receiver
+                .recv_timeout(std::time::Duration::from_millis(100))
+                .is_err());
+            drop(searching);
+            receiver.recv().unwrap();
+        });
+        assert_eq!(index.len(), n);
+    }
+
+    #[test]
+    fn it_writes_the_hnswlib_layout() {
+        let n = 100;
+        let d = 4;
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().to_str().unwrap();
+        let index = create_index(n, d, DistanceFunction::Euclidean, path);
+        let data = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.add(i + 1000, &data[i * d..(i + 1) * d]);
+        }
+        index.delete(1000);
+        index.save().unwrap();
+
+        let header = std::fs::read(tmp_dir.path().join(HEADER_FILE)).unwrap();
+        assert_eq!(header.len(), 96);
+        let field =
+            |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+        // offsetLevel0_, max_elements_, cur_element_count
+        assert_eq!(field(0), 0);
+        assert_eq!(field(8), n as u64);
+        assert_eq!(field(16), n as u64);
+        // size_data_per_element_: level 0 links, data and label
+        let size_links_level0 = 32 * 4 + 4;
+        let size_data_per_element = size_links_level0 + d * 4 + 8;
+        assert_eq!(field(24), size_data_per_element as u64);
+        assert_eq!(field(32), (size_links_level0 + d * 4) as u64);
+        assert_eq!(field(40), size_links_level0 as u64);
+        // maxM_, maxM0_, M_
+        assert_eq!(field(56), 16);
+        assert_eq!(field(64), 32);
+        assert_eq!(field(72), 16);
+
+        let data_level0 = std::fs::read(tmp_dir.path().join(DATA_LEVEL0_FILE)).unwrap();
+        assert_eq!(data_level0.len(), n * size_data_per_element);
+        // The first element is marked deleted and labeled 1000
+        assert_eq!(data_level0[2] & DELETE_MARK, DELETE_MARK);
+        let label_offset = size_links_level0 + d * 4;
+        assert_eq!(
+            u64::from_le_bytes(
+                data_level0[label_offset..label_offset + 8]
+                    .try_into()
+                    .unwrap()
+            ),
+            1000
+        );
+        let lengths = std::fs::read(tmp_dir.path().join(LENGTH_FILE)).unwrap();
+        assert_eq!(lengths.len(), n * 4);
+    }
+
+    #[test]
+    fn it_can_persist_and_load() {
+        let n = 1000;
+        let d = 16;
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().to_str().unwrap();
+        let index = create_index(n, d, DistanceFunction::Euclidean, path);
+        let data = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        index.delete(5);
+        index.save().unwrap();
+
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let loaded = NativeHnswIndex::load(path, &index_config, index.id).unwrap();
+        loaded.set_ef(100);
+        assert_eq!(loaded.len(), n - 1);
+        assert_eq!(loaded.get(5), None);
+        for i in [0, 17, 999] {
+            assert_eq!(loaded.get(i), index.get(i));
+            let query = &data[i * d..(i + 1) * d];
+            assert_eq!(
+                loaded.query(query, 10, &IndexFilter::default()),
+                index.query(query, 10, &IndexFilter::default())
+            );
+        }
+
+        // The loaded index can keep growing
+        loaded.add(n, &[0.5; 16]);
+        let (ids, _) = loaded.query(&[0.5; 16], 1, &IndexFilter::default());
+        assert_eq!(ids, vec![n]);
+
+        // Loading with the wrong dimensionality fails instead of reading garbage
+        let wrong_config = IndexConfig {
+            dimensionality: 8,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        assert!(NativeHnswIndex::load(path, &wrong_config, index.id).is_err());
+    }
+
+    #[test]
+    fn it_rejects_an_element_count_the_files_do_not_hold() {
+        let n = 10;
+        let d = 4;
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().to_str().unwrap();
+        let index = create_index(n, d, DistanceFunction::Euclidean, path);
+        let data = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        index.save().unwrap();
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+
+        let header_path = tmp_dir.path().join(HEADER_FILE);
+        let header = std::fs::read(&header_path).unwrap();
+        for (max_elements, count) in [
+            // The element count times the element size overflows
+            (u64::MAX, u64::MAX / 2),
+            // The data level 0 file holds fewer elements
+            (2 * n as u64, n as u64 + 1),
+            // More elements than the index can hold
+            (n as u64, n as u64 + 1),
+        ] {
+            let mut corrupted = header.clone();
+            corrupted[8..16].copy_from_slice(&max_elements.to_le_bytes());
+            corrupted[16..24].copy_from_slice(&count.to_le_bytes());
+            std::fs::write(&header_path, &corrupted).unwrap();
+            match NativeHnswIndex::load(path, &index_config, index.id) {
+                Ok(_) => panic!("loaded {} elements from {} saved", count, n),
+                Err(e) => assert_eq!(e.code(), ErrorCodes::Internal),
+            }
+        }
+    }
+}
@@ -1,30 +1,33 @@
+use super::hnsw_cache::HnswIndexCache;
 use super::{
-    HnswIndex, HnswIndexConfig, HnswIndexFromSegmentError, Index, IndexConfig,
-    IndexConfigFromSegmentError,
+    HnswIndex, HnswIndexBuffer, HnswIndexBuffers, HnswIndexConfig, HnswIndexFromSegmentError,
+    Index, IndexConfig, IndexConfigFromSegmentError, DATA_LEVEL0_FILE, HEADER_FILE,
+    HNSW_MANIFEST_FILE, LENGTH_FILE, LINK_LISTS_FILE,
 };
 use crate::errors::ErrorCodes;
 use crate::index::types::PersistentIndex;
 use crate::{errors::ChromaError, storage::Storage, types::Segment};
-use parking_lot::RwLock;
 use std::fmt::Debug;
 use std::path::Path;
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::time::Instant;
+use std::{path::PathBuf, sync::Arc};
 use thiserror::Error;
+use tokio::io::AsyncReadExt;
 use uuid::Uuid;
 
 // These are the files hnswlib writes to disk. This is strong coupling, but we need to know
 // what files to read from disk. We could in the future have the C++ code return the files
 // but ideally we have a rust implementation of hnswlib
-const FILES: [&'static str; 4] = [
-    "header.bin",
-    "data_level0.bin",
-    "length.bin",
-    "link_lists.bin",
-];
+const FILES: [&'static str; 4] = [HEADER_FILE, DATA_LEVEL0_FILE, LENGTH_FILE, LINK_LISTS_FILE];
+// Indices flushed before the manifest was introduced do not have one.
+const OPTIONAL_FILES: [&'static str; 1] = [HNSW_MANIFEST_FILE];
 
+/// Loads hnsw indices from storage and caches them. Indices forked to be written are
+/// copied into the temporary storage path, indices opened to be queried are not.
+/// The cache is bounded by the estimated size of the indices, see HnswIndexCache.
 #[derive(Clone)]
 pub(crate) struct HnswIndexProvider {
-    cache: Arc<RwLock<HashMap<Uuid, Arc<RwLock<HnswIndex>>>>>,
+    cache: Arc<HnswIndexCache>,
     pub(crate) temporary_storage_path: PathBuf,
     storage: Storage,
 }
@@ -35,27 +38,43 @@ impl Debug for HnswIndexProvider {
             f,
             "HnswIndexProvider {{ temporary_storage_path: {:?}, cache: {} }}",
             self.temporary_storage_path,
-            self.cache.read().len(),
+            self.cache.len(),
         )
     }
 }
 
 impl HnswIndexProvider {
-    pub(crate) fn new(storage: Storage, storage_path: PathBuf) -> Self {
+    pub(crate) fn new(
+        storage: Storage,
+        storage_path: PathBuf,
+        cache_capacity_bytes: usize,
+    ) -> Self {
         Self {
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            cache: Arc::new(HnswIndexCache::new(
+                cache_capacity_bytes,
+                storage_path.clone(),
+            )),
             storage,
             temporary_storage_path: storage_path,
         }
     }
 
-    pub(crate) fn get(&self, id: &Uuid) -> Option<Arc<RwLock<HnswIndex>>> {
-        let cache = self.cache.read();
-        cache.get(id).cloned()
+    pub(crate) fn get(&self, id: &Uuid) -> Option<Arc<HnswIndex>> {
+        self.cache.get(id)
+    }
+
+    /// Drop an index that will not be committed, such as a fork that is discarded,
+    /// and remove its temporary files.
+    pub(crate) fn remove(&self, id: &Uuid) {
+        self.cache.remove(id);
+    }
+
+    fn format_directory_key(&self, id: &Uuid) -> String {
+        format!("hnsw/{}", id)
     }
 
     fn format_key(&self, id: &Uuid, file: &str) -> String {
-        format!("hnsw/{}/{}", id, file)
+        format!("{}/{}", self.format_directory_key(id), file)
     }
 
     pub(crate) async fn fork(
@@ -63,7 +82,7 @@ impl HnswIndexProvider {
         source_id: &Uuid,
         segment: &Segment,
         dimensionality: i32,
-    ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderForkError>> {
+    ) -> Result<Arc<HnswIndex>, Box<HnswIndexProviderForkError>> {
         let new_id = Uuid::new_v4();
         let new_storage_path = self.temporary_storage_path.join(new_id.to_string());
         match self.create_dir_all(&new_storage_path) {
@@ -101,7 +120,7 @@ impl HnswIndexProvider {
         };
 
         let storage_path_str = match new_storage_path.to_str() {
-            Some(storage_path_str) => storage_path_str,
+            Some(storage_path_str) => storage_path_str.to_string(),
             None => {
                 return Err(Box::new(HnswIndexProviderForkError::PathToStringError(
                     new_storage_path,
@@ -109,14 +128,24 @@ impl HnswIndexProvider {
             }
         };
 
-        match HnswIndex::load(storage_path_str, &index_config, new_id) {
-            Ok(index) => {
-                let index = Arc::new(RwLock::new(index));
-                let mut cache = self.cache.write();
-                cache.insert(new_id, index.clone());
+        // Loading validates the config against the manifest of the source index
+        let load_res = tokio::task::spawn_blocking(move || {
+            HnswIndex::load_with_config(
+                &storage_path_str,
+                &index_config,
+                Some(&hnsw_config),
+                new_id,
+            )
+        })
+        .await;
+        match load_res {
+            Ok(Ok(index)) => {
+                let index = Arc::new(index);
+                self.cache.insert(new_id, index.clone());
                 Ok(index)
             }
-            Err(e) => Err(Box::new(HnswIndexProviderForkError::IndexLoadError(e))),
+            Ok(Err(e)) => Err(Box::new(HnswIndexProviderForkError::IndexLoadError(e))),
+            Err(e) => Err(Box::new(HnswIndexProviderForkError::LoadTaskError(e))),
         }
     }
 
@@ -127,71 +156,91 @@ impl HnswIndexProvider {
     ) -> Result<(), Box<HnswIndexProviderFileError>> {
         // Fetch the files from storage and put them in the index storage path
         for file in FILES.iter() {
-            let key = self.format_key(source_id, file);
-            println!("Loading hnsw index file: {}", key);
-            let res = self.storage.get(&key).await;
-            let mut reader = match res {
-                Ok(reader) => reader,
-                Err(e) => {
-                    println!("Failed to load hnsw index file from storage: {}", e);
-                    return Err(Box::new(HnswIndexProviderFileError::StorageGetError(e)));
-                }
-            };
-
-            let file_path = index_storage_path.join(file);
-            // For now, we never evict from the cache, so if the index is being loaded, the file does not exist
-            let file_handle = tokio::fs::File::create(&file_path).await;
-            let mut file_handle = match file_handle {
-                Ok(file) => file,
-                Err(e) => {
-                    println!("Failed to create file: {}", e);
-                    return Err(Box::new(HnswIndexProviderFileError::IOError(e)));
-                }
-            };
-            let copy_res = tokio::io::copy(&mut reader, &mut file_handle).await;
-            match copy_res {
-                Ok(_) => {
-                    println!(
-                        "Copied storage key: {} to file: {}",
-                        key,
-                        file_path.to_str().unwrap()
-                    );
-                }
-                Err(e) => {
-                    println!("Failed to copy file: {}", e);
-                    return Err(Box::new(HnswIndexProviderFileError::IOError(e)));
-                }
+            self.load_hnsw_file_into_directory(source_id, file, index_storage_path)
+                .await?;
+        }
+        for file in OPTIONAL_FILES.iter() {
+            match self
+                .load_hnsw_file_into_directory(source_id, file, index_storage_path)
+                .await
+            {
+                Ok(_) => {}
+                Err(e) => match *e {
+                    HnswIndexProviderFileError::StorageGetError(
+                        crate::storage::GetError::NoSuchKey(_),
+                    ) => {
+                        tracing::debug!("No optional hnsw index file: {}", file);
+                    }
+                    _ => return Err(e),
+                },
             }
-            // bytes is an AsyncBufRead, so we fil and consume it to a file
-            println!("Loaded hnsw index file: {}", file);
         }
         Ok(())
     }
 
-    pub(crate) async fn open(
+    async fn load_hnsw_file_into_directory(
         &self,
-        id: &Uuid,
-        segment: &Segment,
-        dimensionality: i32,
-    ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderOpenError>> {
-        let index_storage_path = self.temporary_storage_path.join(id.to_string());
-
-        match self.create_dir_all(&index_storage_path) {
-            Ok(_) => {}
+        source_id: &Uuid,
+        file: &str,
+        index_storage_path: &Path,
+    ) -> Result<(), Box<HnswIndexProviderFileError>> {
+        let key = self.format_key(source_id, file);
+        tracing::debug!("Loading hnsw index file: {}", key);
+        let res = self.storage.get(&key).await;
+        let mut reader = match res {
+            Ok(reader) => reader,
             Err(e) => {
-                return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+                tracing::error!("Failed to load hnsw index file from storage: {}", e);
+                return Err(Box::new(HnswIndexProviderFileError::StorageGetError(e)));
             }
-        }
+        };
 
-        match self
-            .load_hnsw_segment_into_directory(id, &index_storage_path)
-            .await
-        {
-            Ok(_) => {}
+        let file_path = index_storage_path.join(file);
+        // Evicted indices have their directory removed, so if the index is being loaded, the file does not exist
+        let file_handle = tokio::fs::File::create(&file_path).await;
+        let mut file_handle = match file_handle {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::error!("Failed to create file: {}", e);
+                return Err(Box::new(HnswIndexProviderFileError::IOError(e)));
+            }
+        };
+        let copy_res = tokio::io::copy(&mut reader, &mut file_handle).await;
+        match copy_res {
+            Ok(_) => {
+                tracing::debug!(
+                    "Copied storage key: {} to file: {}",
+                    key,
+                    file_path.to_str().unwrap()
+                );
+            }
             Err(e) => {
-                return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+                tracing::error!("Failed to copy file: {}", e);
+                return Err(Box::new(HnswIndexProviderFileError::IOError(e)));
             }
         }
+        // bytes is an AsyncBufRead, so we fil and consume it to a file
+        tracing::debug!("Loaded hnsw index file: {}", file);
+        Ok(())
+    }
+
+    /// Load the index from storage for querying.
+    /// # Notes
+    /// Only a native index is loaded without temporary files: it is parsed from the
+    /// files of the index in place, files in local storage are mapped and files in
+    /// remote storage are read into memory. hnswlib can only load an index from disk, so
+    /// an hnswlib index, which a segment must ask for, is loaded from local storage
+    /// directly, and from remote storage through a copy of its files in the temporary
+    /// storage path.
+    /// The index is loaded off the async runtime.
+    pub(crate) async fn open(
+        &self,
+        id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<Arc<HnswIndex>, Box<HnswIndexProviderOpenError>> {
+        let start = Instant::now();
+        let index_storage_path = self.temporary_storage_path.join(id.to_string());
 
         let index_config = IndexConfig::from_segment(&segment, dimensionality);
         let index_config = match index_config {
@@ -209,15 +258,151 @@ impl HnswIndexProvider {
             }
         };
 
-        // TODO: don't unwrap path conv here
-        match HnswIndex::load(index_storage_path.to_str().unwrap(), &index_config, *id) {
-            Ok(index) => {
-                let index = Arc::new(RwLock::new(index));
-                let mut cache = self.cache.write();
-                cache.insert(*id, index.clone());
+        let local_directory = self.storage.local_path(&self.format_directory_key(id));
+        let index_directory = match (
+            HnswIndex::uses_native(&index_config, &hnsw_config),
+            local_directory,
+        ) {
+            (true, _) => None,
+            (false, Some(local_directory)) => Some(local_directory),
+            (false, None) => {
+                match self.create_dir_all(&index_storage_path) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+                    }
+                }
+                match self
+                    .load_hnsw_segment_into_directory(id, &index_storage_path)
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+                    }
+                }
+                Some(index_storage_path.clone())
+            }
+        };
+
+        // The index is not written to by queries, the path is only recorded in its config
+        let storage_path = index_directory.as_ref().unwrap_or(&index_storage_path);
+        let storage_path_str = match storage_path.to_str() {
+            Some(storage_path_str) => storage_path_str.to_string(),
+            None => {
+                return Err(Box::new(HnswIndexProviderOpenError::PathToStringError(
+                    storage_path.clone(),
+                )));
+            }
+        };
+
+        let buffers = match index_directory {
+            Some(_) => None,
+            None => match self.storage.local_path(&self.format_directory_key(id)) {
+                Some(directory) => {
+                    match tokio::task::spawn_blocking(move || HnswIndexBuffers::mmap(&directory))
+                        .await
+                    {
+                        Ok(Ok(buffers)) => Some(buffers),
+                        Ok(Err(e)) => {
+                            return Err(Box::new(HnswIndexProviderOpenError::FileError(
+                                HnswIndexProviderFileError::IOError(e),
+                            )));
+                        }
+                        Err(e) => {
+                            return Err(Box::new(HnswIndexProviderOpenError::LoadTaskError(e)));
+                        }
+                    }
+                }
+                None => match self.load_hnsw_segment_into_buffers(id).await {
+                    Ok(buffers) => Some(buffers),
+                    Err(e) => {
+                        return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+                    }
+                },
+            },
+        };
+
+        // Loading validates the config against the manifest, so that an index is not
+        // queried with a config it was not built with
+        let index_id = *id;
+        let load_res = tokio::task::spawn_blocking(move || match buffers {
+            Some(buffers) => HnswIndex::load_from_buffers(
+                buffers,
+                &storage_path_str,
+                &index_config,
+                Some(&hnsw_config),
+                index_id,
+            ),
+            None => HnswIndex::load_with_config(
+                &storage_path_str,
+                &index_config,
+                Some(&hnsw_config),
+                index_id,
+            ),
+        })
+        .await;
+        match load_res {
+            Ok(Ok(mut index)) => {
+                index.set_opened_at(start);
+                let index = Arc::new(index);
+                self.cache.insert(*id, index.clone());
                 Ok(index)
             }
-            Err(e) => Err(Box::new(HnswIndexProviderOpenError::IndexLoadError(e))),
+            Ok(Err(e)) => Err(Box::new(HnswIndexProviderOpenError::IndexLoadError(e))),
+            Err(e) => Err(Box::new(HnswIndexProviderOpenError::LoadTaskError(e))),
+        }
+    }
+
+    async fn load_hnsw_segment_into_buffers(
+        &self,
+        id: &Uuid,
+    ) -> Result<HnswIndexBuffers, Box<HnswIndexProviderFileError>> {
+        let manifest = match self
+            .load_hnsw_file_into_buffer(id, HNSW_MANIFEST_FILE)
+            .await
+        {
+            Ok(manifest) => Some(manifest),
+            Err(e) => match *e {
+                HnswIndexProviderFileError::StorageGetError(
+                    crate::storage::GetError::NoSuchKey(_),
+                ) => {
+                    tracing::debug!("No optional hnsw index file: {}", HNSW_MANIFEST_FILE);
+                    None
+                }
+                _ => return Err(e),
+            },
+        };
+        Ok(HnswIndexBuffers {
+            header: self.load_hnsw_file_into_buffer(id, HEADER_FILE).await?,
+            data_level0: self
+                .load_hnsw_file_into_buffer(id, DATA_LEVEL0_FILE)
+                .await?,
+            length: self.load_hnsw_file_into_buffer(id, LENGTH_FILE).await?,
+            link_lists: self.load_hnsw_file_into_buffer(id, LINK_LISTS_FILE).await?,
+            manifest,
+        })
+    }
+
+    async fn load_hnsw_file_into_buffer(
+        &self,
+        id: &Uuid,
+        file: &str,
+    ) -> Result<HnswIndexBuffer, Box<HnswIndexProviderFileError>> {
+        let key = self.format_key(id, file);
+        let mut reader = match self.storage.get(&key).await {
+            Ok(reader) => reader,
+            Err(e) => {
+                return Err(Box::new(HnswIndexProviderFileError::StorageGetError(e)));
+            }
+        };
+        let mut bytes = Vec::new();
+        match reader.read_to_end(&mut bytes).await {
+            Ok(_) => Ok(HnswIndexBuffer::Bytes(bytes)),
+            Err(e) => {
+                tracing::error!("Failed to read hnsw index file: {}", e);
+                Err(Box::new(HnswIndexProviderFileError::IOError(e)))
+            }
         }
     }
 
@@ -237,7 +422,7 @@ impl HnswIndexProvider {
         // TODO: This should not take Segment. The index layer should not know about the segment concept
         segment: &Segment,
         dimensionality: i32,
-    ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderCreateError>> {
+    ) -> Result<Arc<HnswIndex>, Box<HnswIndexProviderCreateError>> {
         let id = Uuid::new_v4();
         let index_storage_path = self.temporary_storage_path.join(id.to_string());
 
@@ -262,28 +447,26 @@ impl HnswIndexProvider {
             }
         };
 
-        let mut cache = self.cache.write();
         let index = match HnswIndex::init(&index_config, Some(&hnsw_config), id) {
             Ok(index) => index,
             Err(e) => {
                 return Err(Box::new(HnswIndexProviderCreateError::IndexInitError(e)));
             }
         };
-        let index = Arc::new(RwLock::new(index));
-        cache.insert(id, index.clone());
+        let index = Arc::new(index);
+        self.cache.insert(id, index.clone());
         Ok(index)
     }
 
     pub(crate) fn commit(&self, id: &Uuid) -> Result<(), Box<HnswIndexProviderCommitError>> {
-        let cache = self.cache.read();
-        let index = match cache.get(id) {
+        let index = match self.cache.get(id) {
             Some(index) => index,
             None => {
                 return Err(Box::new(HnswIndexProviderCommitError::NoIndexFound(*id)));
             }
         };
 
-        match index.write().save() {
+        match index.save() {
             Ok(_) => {}
             Err(e) => {
                 return Err(Box::new(HnswIndexProviderCommitError::HnswSaveError(e)));
@@ -294,17 +477,16 @@ impl HnswIndexProvider {
     }
 
     pub(crate) async fn flush(&self, id: &Uuid) -> Result<(), Box<HnswIndexProviderFlushError>> {
-        // Scope to drop the cache lock before we await to write to s3
+        // Scope to drop the index before we await to write to s3
         // TODO: since we commit(), we don't need to save the index here
         {
-            let cache = self.cache.read();
-            let index = match cache.get(id) {
+            let index = match self.cache.get(id) {
                 Some(index) => index,
                 None => {
                     return Err(Box::new(HnswIndexProviderFlushError::NoIndexFound(*id)));
                 }
             };
-            match index.write().save() {
+            match index.save() {
                 Ok(_) => {}
                 Err(e) => {
                     return Err(Box::new(HnswIndexProviderFlushError::HnswSaveError(e)));
@@ -313,7 +495,7 @@ impl HnswIndexProvider {
         }
 
         let index_storage_path = self.temporary_storage_path.join(id.to_string());
-        for file in FILES.iter() {
+        for file in FILES.iter().chain(OPTIONAL_FILES.iter()) {
             let file_path = index_storage_path.join(file);
             let key = self.format_key(id, file);
             let res = self
@@ -322,7 +504,7 @@ impl HnswIndexProvider {
                 .await;
             match res {
                 Ok(_) => {
-                    println!("Flushed hnsw index file: {}", file);
+                    tracing::debug!("Flushed hnsw index file: {}", file);
                 }
                 Err(e) => {
                     return Err(Box::new(HnswIndexProviderFlushError::StoragePutError(e)));
@@ -350,6 +532,10 @@ pub(crate) enum HnswIndexProviderOpenError {
     HnswConfigError(#[from] HnswIndexFromSegmentError),
     #[error("Index load error")]
     IndexLoadError(#[from] Box<dyn ChromaError>),
+    #[error("Index load task failed")]
+    LoadTaskError(#[from] tokio::task::JoinError),
+    #[error("Path: {0} could not be converted to string")]
+    PathToStringError(PathBuf),
 }
 
 impl ChromaError for HnswIndexProviderOpenError {
@@ -359,6 +545,8 @@ impl ChromaError for HnswIndexProviderOpenError {
             HnswIndexProviderOpenError::FileError(_) => ErrorCodes::Internal,
             HnswIndexProviderOpenError::HnswConfigError(e) => e.code(),
             HnswIndexProviderOpenError::IndexLoadError(e) => e.code(),
+            HnswIndexProviderOpenError::LoadTaskError(_) => ErrorCodes::Internal,
+            HnswIndexProviderOpenError::PathToStringError(_) => ErrorCodes::InvalidArgument,
         }
     }
 }
@@ -375,6 +563,8 @@ pub(crate) enum HnswIndexProviderForkError {
     IndexLoadError(#[from] Box<dyn ChromaError>),
     #[error("Path: {0} could not be converted to string")]
     PathToStringError(PathBuf),
+    #[error("Index load task failed")]
+    LoadTaskError(#[from] tokio::task::JoinError),
 }
 
 impl ChromaError for HnswIndexProviderForkError {
@@ -385,6 +575,7 @@ impl ChromaError for HnswIndexProviderForkError {
             HnswIndexProviderForkError::HnswConfigError(e) => e.code(),
             HnswIndexProviderForkError::IndexLoadError(e) => e.code(),
             HnswIndexProviderForkError::PathToStringError(_) => ErrorCodes::InvalidArgument,
+            HnswIndexProviderForkError::LoadTaskError(_) => ErrorCodes::Internal,
         }
     }
 }
@@ -463,9 +654,11 @@ pub(crate) enum HnswIndexProviderFileError {
 mod tests {
     use super::*;
     use crate::{
+        index::HnswImplementation,
         storage::{local::LocalStorage, Storage},
         types::SegmentType,
     };
+    use std::collections::HashMap;
 
     #[tokio::test]
     async fn test_fork() {
@@ -477,7 +670,7 @@ mod tests {
 
         let storage = Storage::Local(LocalStorage::new(storage_dir.to_str().unwrap()));
 
-        let provider = HnswIndexProvider::new(storage, hnsw_tmp_path);
+        let provider = HnswIndexProvider::new(storage, hnsw_tmp_path, usize::MAX);
         let segment = Segment {
             id: Uuid::new_v4(),
             r#type: SegmentType::HnswDistributed,
@@ -489,14 +682,69 @@ mod tests {
 
         let dimensionality = 128;
         let created_index = provider.create(&segment, dimensionality).unwrap();
-        let created_index_id = created_index.read().id;
+        let created_index_id = created_index.id;
 
         let forked_index = provider
             .fork(&created_index_id, &segment, dimensionality)
             .await
             .unwrap();
-        let forked_index_id = forked_index.read().id;
+        let forked_index_id = forked_index.id;
 
         assert_ne!(created_index_id, forked_index_id);
     }
+
+    #[tokio::test]
+    async fn test_open_without_temporary_files() {
+        let storage_dir = tempfile::tempdir().unwrap().path().to_path_buf();
+        let hnsw_tmp_path = storage_dir.join("tmp");
+        std::fs::create_dir_all(&hnsw_tmp_path).unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.to_str().unwrap()));
+        let segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+
+        let dimensionality = 4;
+        let writer = HnswIndexProvider::new(storage.clone(), hnsw_tmp_path.clone(), usize::MAX);
+        let created_index = writer.create(&segment, dimensionality).unwrap();
+        let created_index_id = created_index.id;
+        created_index.add(0, &[1.0, 2.0, 3.0, 4.0]);
+        created_index.add(1, &[4.0, 3.0, 2.0, 1.0]);
+        writer.flush(&created_index_id).await.unwrap();
+
+        let reader_tmp_path = storage_dir.join("reader");
+        let reader = HnswIndexProvider::new(storage, reader_tmp_path.clone(), usize::MAX);
+        let opened_index = reader
+            .open(&created_index_id, &segment, dimensionality)
+            .await
+            .unwrap();
+        assert_eq!(opened_index.len(), 2);
+        assert!(reader.get(&created_index_id).is_some());
+        assert!(!reader_tmp_path.join(created_index_id.to_string()).exists());
+
+        // Remote files are read into memory and parsed by the native implementation
+        let buffers = reader
+            .load_hnsw_segment_into_buffers(&created_index_id)
+            .await
+            .unwrap();
+        assert!(buffers.manifest.is_some());
+        let index_config = IndexConfig::from_segment(&segment, dimensionality).unwrap();
+        let hnsw_config = HnswIndexConfig {
+            implementation: HnswImplementation::Native,
+            ..HnswIndexConfig::from_segment(&segment, &reader_tmp_path).unwrap()
+        };
+        let index = HnswIndex::load_from_buffers(
+            buffers,
+            reader_tmp_path.to_str().unwrap(),
+            &index_config,
+            Some(&hnsw_config),
+            created_index_id,
+        )
+        .unwrap();
+        assert_eq!(index.len(), 2);
+    }
 }
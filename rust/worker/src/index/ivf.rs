@@ -0,0 +1,1133 @@
+use super::utils::{closest_centroid, kmeans, normalize, squared_l2};
+use super::{
+    Index, IndexConfig, IndexFilter, ScalarQuantizationConfig, ScalarQuantizer,
+    ScalarQuantizerError, DEFAULT_TRAINING_ITERATIONS,
+};
+use crate::blockstore::{BlockfileError, BlockfileFlusher, BlockfileReader, BlockfileWriter};
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::segment::schema::{IvfSegmentConfig, SegmentConfigError};
+use crate::segment::DataRecord;
+use crate::types::Segment;
+use parking_lot::{Mutex, RwLock};
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub(crate) const DEFAULT_NPROBE: usize = 8;
+// Centroids are not trained until there are this many vectors per list, and are
+// trained on a sample of at most MAX_TRAINING_VECTORS_PER_LIST vectors per list.
+const MIN_TRAINING_VECTORS_PER_LIST: usize = 32;
+const MAX_TRAINING_VECTORS_PER_LIST: usize = 256;
+
+/// The configuration of an inverted file index.
+/// # Fields
+/// - `num_lists` - The number of lists, and centroids, vectors are partitioned into.
+///   Defaults to the square root of the number of vectors the index is trained with.
+/// - `nprobe` - The number of lists a query searches, unless the query sets it.
+/// - `training_iterations` - The number of k-means iterations to train centroids with.
+/// - `quantization` - Whether vectors are scanned by their int8 quantized distance,
+///   with the quantizer trained along with the centroids. Set with `ivf:quantization`
+///   "int8" and `ivf:rerank_factor`.
+#[derive(Clone, Debug)]
+pub(crate) struct IvfIndexConfig {
+    pub(crate) num_lists: Option<usize>,
+    pub(crate) nprobe: usize,
+    pub(crate) training_iterations: usize,
+    pub(crate) quantization: Option<ScalarQuantizationConfig>,
+    pub(crate) random_seed: usize,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum IvfIndexFromSegmentError {
+    #[error(transparent)]
+    InvalidConfig(#[from] SegmentConfigError),
+}
+
+impl ChromaError for IvfIndexFromSegmentError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            IvfIndexFromSegmentError::InvalidConfig(e) => e.code(),
+        }
+    }
+}
+
+impl IvfIndexConfig {
+    pub(crate) fn from_segment(
+        segment: &Segment,
+    ) -> Result<IvfIndexConfig, Box<IvfIndexFromSegmentError>> {
+        let config = match IvfSegmentConfig::from_segment(segment) {
+            Ok(config) => config,
+            Err(e) => return Err(Box::new(IvfIndexFromSegmentError::InvalidConfig(e))),
+        };
+        Ok(IvfIndexConfig {
+            num_lists: config.num_lists,
+            nprobe: config.nprobe,
+            training_iterations: config.training_iterations,
+            quantization: config.quantization,
+            random_seed: 0,
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum IvfIndexError {
+    #[error("No config provided")]
+    NoConfigProvided,
+    #[error("Index has {0} floats of centroids, which is not a multiple of dimensionality {1}")]
+    InvalidCentroids(usize, usize),
+    #[error("Invalid quantizer")]
+    QuantizerError(#[from] ScalarQuantizerError),
+}
+
+impl ChromaError for IvfIndexError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            IvfIndexError::NoConfigProvided => ErrorCodes::InvalidArgument,
+            IvfIndexError::InvalidCentroids(_, _) => ErrorCodes::FailedPrecondition,
+            IvfIndexError::QuantizerError(e) => e.code(),
+        }
+    }
+}
+
+// A vector in a loaded list, only its int8 code once the index has a quantizer
+enum ListVector {
+    Full(Vec<f32>),
+    Quantized(Vec<i8>),
+}
+
+struct IvfState {
+    // Flattened centroids, a centroid per list, empty until trained
+    centroids: Vec<f32>,
+    // list -> label -> vector, of the lists that are loaded
+    lists: HashMap<usize, HashMap<usize, ListVector>>,
+    // label -> list, of the vectors in loaded lists
+    assignments: HashMap<usize, usize>,
+    // Trained with the centroids if the index is quantized
+    quantizer: Option<ScalarQuantizer>,
+}
+
+impl IvfState {
+    fn is_trained(&self) -> bool {
+        !self.centroids.is_empty()
+    }
+
+    fn insert(&mut self, list: usize, label: usize, vector: Vec<f32>) {
+        self.remove(label);
+        let vector = match &self.quantizer {
+            Some(quantizer) => ListVector::Quantized(quantizer.quantize(&vector)),
+            None => ListVector::Full(vector),
+        };
+        self.lists.entry(list).or_default().insert(label, vector);
+        self.assignments.insert(label, list);
+    }
+
+    fn remove(&mut self, label: usize) {
+        if let Some(list) = self.assignments.remove(&label) {
+            if let Some(vectors) = self.lists.get_mut(&list) {
+                vectors.remove(&label);
+            }
+        }
+    }
+}
+
+/// An inverted file index.
+/// # Description
+/// Vectors are partitioned into lists by their closest centroid, with centroids trained
+/// with k-means. A query only compares the query against the vectors in the nprobe
+/// lists whose centroids are closest to it, with exact distances.
+/// # Training
+/// Until the index holds enough vectors to train centroids from, all vectors are in a
+/// single list, list 0, and queries are exact. Centroids are not retrained once trained,
+/// vectors added later go to the list of their closest centroid.
+/// # Quantization
+/// A quantized index trains a `ScalarQuantizer` along with its centroids, and only
+/// holds the int8 codes of the vectors in its lists, so queries return the quantized
+/// distances. Callers should query for rerank_factor * k candidates and rerank them
+/// with the full vectors, as for a product quantized index.
+/// # Loading
+/// The index only holds the lists that are loaded, see `IvfIndexReader`, which loads the
+/// lists a query probes from a blockfile. Lists are written to blockfiles by the
+/// `IvfIndexWriter`.
+pub(crate) struct IvfIndex {
+    state: RwLock<IvfState>,
+    dimensionality: usize,
+    distance_function: DistanceFunction,
+    config: IvfIndexConfig,
+    pub(crate) id: Uuid,
+}
+
+impl IvfIndex {
+    fn preprocess(&self, vector: &[f32]) -> Vec<f32> {
+        match self.distance_function {
+            DistanceFunction::Cosine => normalize(vector),
+            _ => vector.to_vec(),
+        }
+    }
+
+    pub(crate) fn is_trained(&self) -> bool {
+        self.state.read().is_trained()
+    }
+
+    /// The number of vectors in the loaded lists.
+    pub(crate) fn len(&self) -> usize {
+        self.state.read().assignments.len()
+    }
+
+    pub(crate) fn centroids(&self) -> Vec<f32> {
+        self.state.read().centroids.clone()
+    }
+
+    /// Set the centroids of an index that has no vectors loaded, e.g. centroids read
+    /// from a blockfile.
+    pub(crate) fn set_centroids(&self, centroids: Vec<f32>) -> Result<(), IvfIndexError> {
+        if centroids.len() % self.dimensionality != 0 {
+            return Err(IvfIndexError::InvalidCentroids(
+                centroids.len(),
+                self.dimensionality,
+            ));
+        }
+        self.state.write().centroids = centroids;
+        Ok(())
+    }
+
+    pub(crate) fn quantizer(&self) -> Option<ScalarQuantizer> {
+        self.state.read().quantizer.clone()
+    }
+
+    /// Set the quantizer of an index that has no vectors loaded, e.g. a quantizer read
+    /// from a blockfile.
+    pub(crate) fn set_quantizer(&self, quantizer: ScalarQuantizer) -> Result<(), IvfIndexError> {
+        if quantizer.dimensionality() != self.dimensionality {
+            return Err(IvfIndexError::QuantizerError(
+                ScalarQuantizerError::InvalidDimensionality(
+                    quantizer.dimensionality(),
+                    self.dimensionality,
+                ),
+            ));
+        }
+        self.state.write().quantizer = Some(quantizer);
+        Ok(())
+    }
+
+    /// The list a vector that was preprocessed for this index belongs in.
+    fn assign(state: &IvfState, vector: &[f32]) -> usize {
+        match state.is_trained() {
+            true => closest_centroid(&state.centroids, vector),
+            false => 0,
+        }
+    }
+
+    /// The nprobe lists whose centroids are closest to the query, closest first.
+    pub(crate) fn probe(&self, query: &[f32], nprobe: usize) -> Vec<usize> {
+        let query = self.preprocess(query);
+        let state = self.state.read();
+        if !state.is_trained() {
+            return vec![0];
+        }
+        let mut lists: Vec<(f32, usize)> = state
+            .centroids
+            .chunks_exact(self.dimensionality)
+            .map(|centroid| squared_l2(centroid, &query))
+            .enumerate()
+            .map(|(list, distance)| (distance, list))
+            .collect();
+        lists.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        lists.truncate(nprobe.max(1));
+        lists.into_iter().map(|(_, list)| list).collect()
+    }
+
+    pub(crate) fn is_loaded(&self, list: usize) -> bool {
+        self.state.read().lists.contains_key(&list)
+    }
+
+    /// Load the vectors of a list, as they were written by an `IvfIndexWriter`.
+    pub(crate) fn load_list(&self, list: usize, vectors: Vec<(usize, Vec<f32>)>) {
+        let mut state = self.state.write();
+        state.lists.entry(list).or_default();
+        for (label, vector) in vectors {
+            state.insert(list, label, vector);
+        }
+    }
+
+    /// Train the centroids from the loaded vectors if there are enough of them, and
+    /// reassign the vectors to the lists of their closest centroids. Returns whether the
+    /// index was trained, it is never retrained.
+    pub(crate) fn train(&self) -> bool {
+        let mut state = self.state.write();
+        if state.is_trained() {
+            return false;
+        }
+        let num_vectors = state.assignments.len();
+        let num_lists = match self.config.num_lists {
+            Some(num_lists) => num_lists,
+            None => ((num_vectors as f64).sqrt() as usize).max(1),
+        };
+        if num_vectors < num_lists * MIN_TRAINING_VECTORS_PER_LIST {
+            return false;
+        }
+
+        // Train on the vectors in label order, so that training is deterministic. An
+        // untrained index has no quantizer, so its vectors are full.
+        let mut vectors: Vec<(usize, Vec<f32>)> = state
+            .lists
+            .drain()
+            .flat_map(|(_, vectors)| vectors.into_iter())
+            .filter_map(|(label, vector)| match vector {
+                ListVector::Full(vector) => Some((label, vector)),
+                ListVector::Quantized(_) => None,
+            })
+            .collect();
+        vectors.sort_by_key(|(label, _)| *label);
+        state.assignments.clear();
+        let mut rng = StdRng::seed_from_u64(self.config.random_seed as u64);
+        let num_samples = num_vectors.min(num_lists * MAX_TRAINING_VECTORS_PER_LIST);
+        let mut points = Vec::with_capacity(num_samples * self.dimensionality);
+        for i in sample(&mut rng, num_vectors, num_samples).iter() {
+            points.extend_from_slice(&vectors[i].1);
+        }
+        state.centroids = kmeans(
+            &points,
+            self.dimensionality,
+            num_lists,
+            self.config.training_iterations,
+            &mut rng,
+        );
+        if self.config.quantization.is_some() {
+            state.quantizer = Some(ScalarQuantizer::train(
+                &points,
+                self.dimensionality,
+                &self.distance_function,
+            ));
+        }
+
+        for (label, vector) in vectors {
+            let list = IvfIndex::assign(&state, &vector);
+            state.insert(list, label, vector);
+        }
+        true
+    }
+
+    /// The k nearest neighbors among the vectors in the nprobe lists closest to the
+    /// vector, by their quantized distance if the index is quantized. Lists that are
+    /// not loaded are skipped.
+    pub(crate) fn query_with_nprobe(
+        &self,
+        vector: &[f32],
+        k: usize,
+        nprobe: usize,
+        filter: &IndexFilter,
+    ) -> (Vec<usize>, Vec<f32>) {
+        let lists = self.probe(vector, nprobe);
+        let query = self.preprocess(vector);
+
+        let state = self.state.read();
+        let quantized_query = state
+            .quantizer
+            .as_ref()
+            .map(|quantizer| quantizer.quantize(&query));
+        let mut results: Vec<(f32, usize)> = Vec::new();
+        for list in lists {
+            let vectors = match state.lists.get(&list) {
+                Some(vectors) => vectors,
+                None => continue,
+            };
+            for (label, vector) in vectors.iter() {
+                if !filter.allows(*label) {
+                    continue;
+                }
+                let distance = match (vector, &quantized_query) {
+                    (ListVector::Full(vector), _) => {
+                        self.distance_function.distance(&query, vector)
+                    }
+                    (ListVector::Quantized(code), Some(quantized_query)) => {
+                        self.distance_function.distance_int8(quantized_query, code)
+                    }
+                    // Codes are only inserted once the index has a quantizer
+                    (ListVector::Quantized(_), None) => continue,
+                };
+                results.push((distance, *label));
+            }
+        }
+
+        let by_distance =
+            |a: &(f32, usize), b: &(f32, usize)| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1));
+        if results.len() > k && k > 0 {
+            results.select_nth_unstable_by(k - 1, by_distance);
+        }
+        results.truncate(k);
+        results.sort_by(by_distance);
+        results
+            .into_iter()
+            .map(|(distance, label)| (label, distance))
+            .unzip()
+    }
+}
+
+impl Index<IvfIndexConfig> for IvfIndex {
+    fn init(
+        index_config: &IndexConfig,
+        ivf_config: Option<&IvfIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        let config = match ivf_config {
+            Some(config) => config,
+            None => return Err(Box::new(IvfIndexError::NoConfigProvided)),
+        };
+        Ok(IvfIndex {
+            state: RwLock::new(IvfState {
+                centroids: Vec::new(),
+                lists: HashMap::new(),
+                assignments: HashMap::new(),
+                quantizer: None,
+            }),
+            dimensionality: index_config.dimensionality as usize,
+            distance_function: index_config.distance_function.clone(),
+            config: config.clone(),
+            id,
+        })
+    }
+
+    fn add(&self, id: usize, vector: &[f32]) {
+        let vector = self.preprocess(vector);
+        let mut state = self.state.write();
+        let list = IvfIndex::assign(&state, &vector);
+        state.insert(list, id, vector);
+    }
+
+    fn delete(&self, id: usize) {
+        self.state.write().remove(id);
+    }
+
+    fn query(&self, vector: &[f32], k: usize, filter: &IndexFilter) -> (Vec<usize>, Vec<f32>) {
+        self.query_with_nprobe(vector, k, self.config.nprobe, filter)
+    }
+
+    /// The vector of a label, None once the index is quantized, as it only holds the
+    /// codes of its vectors.
+    fn get(&self, id: usize) -> Option<Vec<f32>> {
+        let state = self.state.read();
+        let list = state.assignments.get(&id)?;
+        match state.lists.get(list)?.get(&id)? {
+            ListVector::Full(vector) => Some(vector.clone()),
+            ListVector::Quantized(_) => None,
+        }
+    }
+}
+
+// The quantizer is stored in the centroids blockfile, with its minimums and maximums
+// under this prefix, apart from the centroids which have the empty prefix.
+const QUANTIZER_PREFIX: &str = "quantizer";
+const QUANTIZER_MIN_KEY: u32 = 0;
+const QUANTIZER_MAX_KEY: u32 = 1;
+
+// Lists are stored under their list id as the prefix
+fn list_prefix(list: usize) -> String {
+    list.to_string()
+}
+
+fn vector_record(vector: &[f32]) -> DataRecord<'_> {
+    DataRecord {
+        id: "",
+        embedding: vector,
+        sparse_embedding: None,
+        multi_embedding: None,
+        metadata: None,
+        document: None,
+    }
+}
+
+// The labels and vectors of a list in a lists blockfile. A list without vectors
+// has no entries.
+async fn read_list<'me>(
+    lists_blockfile_reader: &'me BlockfileReader<'me, u32, DataRecord<'me>>,
+    list: usize,
+) -> Result<Vec<(usize, Vec<f32>)>, Box<dyn ChromaError>> {
+    let vectors = lists_blockfile_reader
+        .get_by_prefix(&list_prefix(list))
+        .await?;
+    Ok(vectors
+        .into_iter()
+        .map(|(_, label, record)| (label as usize, record.embedding.to_vec()))
+        .collect())
+}
+
+impl IvfIndex {
+    /// Load the centroids, and the quantizer of a quantized index, from a centroids
+    /// blockfile written by an `IvfIndexWriter`.
+    pub(crate) async fn load_centroids<'me>(
+        &self,
+        centroids_blockfile_reader: &'me BlockfileReader<'me, u32, DataRecord<'me>>,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        // An untrained index has no centroids
+        let mut centroids = centroids_blockfile_reader.get_by_prefix("").await?;
+        centroids.sort_by_key(|(_, list, _)| *list);
+        let centroids: Vec<f32> = centroids
+            .into_iter()
+            .flat_map(|(_, _, record)| record.embedding.iter().copied())
+            .collect();
+        match self.set_centroids(centroids) {
+            Ok(_) => {}
+            Err(e) => return Err(Box::new(e)),
+        }
+
+        // An index that is not quantized, or not trained, has no quantizer
+        let mut bounds = centroids_blockfile_reader
+            .get_by_prefix(QUANTIZER_PREFIX)
+            .await?;
+        if bounds.len() != 2 {
+            return Ok(());
+        }
+        bounds.sort_by_key(|(_, key, _)| *key);
+        let quantizer = match ScalarQuantizer::new(
+            bounds[QUANTIZER_MIN_KEY as usize].2.embedding.to_vec(),
+            bounds[QUANTIZER_MAX_KEY as usize].2.embedding.to_vec(),
+        ) {
+            Ok(quantizer) => quantizer,
+            Err(e) => return Err(Box::new(IvfIndexError::QuantizerError(e))),
+        };
+        match self.set_quantizer(quantizer) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+/// Writes the changes to an inverted file index to blockfiles.
+/// # Description
+/// The index is stored in three blockfiles: the centroids, keyed by list; the lists,
+/// which hold the vectors of each list under the list as the prefix; and the
+/// assignments, the list of each vector. Writers are forked from the blockfiles of the
+/// previous version of the index, and only the entries of vectors that changed are
+/// written, so a compaction only rewrites the blocks of the lists it changed.
+/// The whole index is rewritten once, when its centroids are trained.
+#[derive(Clone)]
+pub(crate) struct IvfIndexWriter {
+    // Holds the centroids to assign vectors with
+    index: Arc<IvfIndex>,
+    centroids_blockfile_writer: BlockfileWriter,
+    lists_blockfile_writer: BlockfileWriter,
+    assignments_blockfile_writer: BlockfileWriter,
+    // The blockfiles the writers were forked from, if any
+    lists_blockfile_reader: Option<Arc<BlockfileReader<'static, u32, DataRecord<'static>>>>,
+    assignments_blockfile_reader: Option<Arc<BlockfileReader<'static, u32, u32>>>,
+
+    // label -> vector added or updated since the index was forked
+    uncommitted_adds: Arc<Mutex<HashMap<u32, Vec<f32>>>>,
+    // labels deleted since the index was forked
+    uncommitted_deletes: Arc<Mutex<HashSet<u32>>>,
+}
+
+impl IvfIndexWriter {
+    pub(crate) fn new(
+        index: IvfIndex,
+        centroids_blockfile_writer: BlockfileWriter,
+        lists_blockfile_writer: BlockfileWriter,
+        assignments_blockfile_writer: BlockfileWriter,
+        lists_blockfile_reader: Option<BlockfileReader<'static, u32, DataRecord<'static>>>,
+        assignments_blockfile_reader: Option<BlockfileReader<'static, u32, u32>>,
+    ) -> Self {
+        IvfIndexWriter {
+            index: Arc::new(index),
+            centroids_blockfile_writer,
+            lists_blockfile_writer,
+            assignments_blockfile_writer,
+            lists_blockfile_reader: lists_blockfile_reader.map(Arc::new),
+            assignments_blockfile_reader: assignments_blockfile_reader.map(Arc::new),
+            uncommitted_adds: Arc::new(Mutex::new(HashMap::new())),
+            uncommitted_deletes: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Add a vector, or replace the vector of the label.
+    pub(crate) fn add(&self, label: u32, vector: &[f32]) {
+        self.uncommitted_adds.lock().insert(label, vector.to_vec());
+    }
+
+    pub(crate) fn delete(&self, label: u32) {
+        self.uncommitted_adds.lock().remove(&label);
+        self.uncommitted_deletes.lock().insert(label);
+    }
+
+    // The list a label is in, in the index the writers were forked from
+    async fn get_forked_list(&self, label: u32) -> Result<Option<u32>, Box<dyn ChromaError>> {
+        match &self.assignments_blockfile_reader {
+            Some(reader) => match reader.get("", label).await {
+                Ok(list) => Ok(Some(list)),
+                Err(e) if BlockfileError::is_not_found(&*e) => Ok(None),
+                Err(e) => Err(e),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn write_to_blockfiles(&mut self) -> Result<(), Box<dyn ChromaError>> {
+        let uncommitted_adds: Vec<(u32, Vec<f32>)> = self.uncommitted_adds.lock().drain().collect();
+        let uncommitted_deletes: Vec<u32> = self.uncommitted_deletes.lock().drain().collect();
+
+        if !self.index.is_trained() {
+            match self
+                .train_and_write(&uncommitted_adds, &uncommitted_deletes)
+                .await
+            {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Remove the forked entries of deleted and replaced vectors
+        let changed_labels = uncommitted_deletes
+            .iter()
+            .chain(uncommitted_adds.iter().map(|(label, _)| label));
+        for label in changed_labels {
+            let list = match self.get_forked_list(*label).await? {
+                Some(list) => list,
+                None => continue,
+            };
+            self.lists_blockfile_writer
+                .delete::<u32, &DataRecord>(&list_prefix(list as usize), *label)
+                .await?;
+            self.assignments_blockfile_writer
+                .delete::<u32, u32>("", *label)
+                .await?;
+        }
+
+        for (label, vector) in uncommitted_adds {
+            let vector = self.index.preprocess(&vector);
+            let list = IvfIndex::assign(&self.index.state.read(), &vector);
+            self.write_vector(list, label, &vector).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_vector(
+        &self,
+        list: usize,
+        label: u32,
+        vector: &[f32],
+    ) -> Result<(), Box<dyn ChromaError>> {
+        self.lists_blockfile_writer
+            .set(&list_prefix(list), label, &vector_record(vector))
+            .await?;
+        self.assignments_blockfile_writer
+            .set("", label, list as u32)
+            .await
+    }
+
+    // An untrained index has all its vectors in list 0. Train the centroids from them
+    // and the uncommitted vectors if there are enough, and rewrite the index. Returns
+    // whether the index was trained.
+    async fn train_and_write(
+        &self,
+        uncommitted_adds: &[(u32, Vec<f32>)],
+        uncommitted_deletes: &[u32],
+    ) -> Result<bool, Box<dyn ChromaError>> {
+        let forked = match &self.lists_blockfile_reader {
+            Some(reader) => read_list(reader, 0).await?,
+            None => Vec::new(),
+        };
+        let forked_labels: Vec<usize> = forked.iter().map(|(label, _)| *label).collect();
+        // The vectors of the index, preprocessed as they are written
+        let mut vectors: HashMap<usize, Vec<f32>> = forked.into_iter().collect();
+        for label in uncommitted_deletes {
+            vectors.remove(&(*label as usize));
+        }
+        for (label, vector) in uncommitted_adds {
+            vectors.insert(*label as usize, self.index.preprocess(vector));
+        }
+        let mut vectors: Vec<(usize, Vec<f32>)> = vectors.into_iter().collect();
+        vectors.sort_by_key(|(label, _)| *label);
+
+        let index_config = IndexConfig {
+            dimensionality: self.index.dimensionality as i32,
+            distance_function: self.index.distance_function.clone(),
+        };
+        let index = IvfIndex::init(&index_config, Some(&self.index.config), self.index.id)?;
+        index.load_list(0, vectors.clone());
+        if !index.train() {
+            return Ok(false);
+        }
+
+        for label in forked_labels {
+            self.lists_blockfile_writer
+                .delete::<u32, &DataRecord>(&list_prefix(0), label as u32)
+                .await?;
+            self.assignments_blockfile_writer
+                .delete::<u32, u32>("", label as u32)
+                .await?;
+        }
+        let centroids = index.centroids();
+        let quantizer = index.quantizer();
+        if let Some(quantizer) = &quantizer {
+            self.centroids_blockfile_writer
+                .set(
+                    QUANTIZER_PREFIX,
+                    QUANTIZER_MIN_KEY,
+                    &vector_record(quantizer.min()),
+                )
+                .await?;
+            self.centroids_blockfile_writer
+                .set(
+                    QUANTIZER_PREFIX,
+                    QUANTIZER_MAX_KEY,
+                    &vector_record(quantizer.max()),
+                )
+                .await?;
+        }
+        for (list, centroid) in centroids.chunks_exact(index.dimensionality).enumerate() {
+            self.centroids_blockfile_writer
+                .set("", list as u32, &vector_record(centroid))
+                .await?;
+        }
+        // The index only holds the codes of the vectors once quantized, so the full
+        // vectors are assigned to their lists here
+        let lists: Vec<usize> = {
+            let state = index.state.read();
+            vectors
+                .iter()
+                .map(|(_, vector)| IvfIndex::assign(&state, vector))
+                .collect()
+        };
+        for ((label, vector), list) in vectors.iter().zip(lists) {
+            self.write_vector(list, *label as u32, vector).await?;
+        }
+        match self.index.set_centroids(centroids) {
+            Ok(_) => {}
+            Err(e) => return Err(Box::new(e)),
+        }
+        match quantizer {
+            Some(quantizer) => match self.index.set_quantizer(quantizer) {
+                Ok(_) => Ok(true),
+                Err(e) => Err(Box::new(e)),
+            },
+            None => Ok(true),
+        }
+    }
+
+    pub(crate) fn commit(self) -> Result<IvfIndexFlusher, Box<dyn ChromaError>> {
+        let centroids_blockfile_flusher = self
+            .centroids_blockfile_writer
+            .commit::<u32, &DataRecord>()?;
+        let lists_blockfile_flusher = self.lists_blockfile_writer.commit::<u32, &DataRecord>()?;
+        let assignments_blockfile_flusher =
+            self.assignments_blockfile_writer.commit::<u32, u32>()?;
+        Ok(IvfIndexFlusher {
+            centroids_blockfile_flusher,
+            lists_blockfile_flusher,
+            assignments_blockfile_flusher,
+        })
+    }
+}
+
+pub(crate) struct IvfIndexFlusher {
+    centroids_blockfile_flusher: BlockfileFlusher,
+    lists_blockfile_flusher: BlockfileFlusher,
+    assignments_blockfile_flusher: BlockfileFlusher,
+}
+
+impl IvfIndexFlusher {
+    pub(crate) async fn flush(self) -> Result<(), Box<dyn ChromaError>> {
+        self.centroids_blockfile_flusher
+            .flush::<u32, &DataRecord>()
+            .await?;
+        self.lists_blockfile_flusher
+            .flush::<u32, &DataRecord>()
+            .await?;
+        self.assignments_blockfile_flusher.flush::<u32, u32>().await
+    }
+
+    pub(crate) fn centroids_id(&self) -> Uuid {
+        self.centroids_blockfile_flusher.id()
+    }
+
+    pub(crate) fn lists_id(&self) -> Uuid {
+        self.lists_blockfile_flusher.id()
+    }
+
+    pub(crate) fn assignments_id(&self) -> Uuid {
+        self.assignments_blockfile_flusher.id()
+    }
+}
+
+/// Queries an inverted file index stored in blockfiles, loading only the lists a
+/// query probes.
+pub(crate) struct IvfIndexReader<'me> {
+    index: IvfIndex,
+    lists_blockfile_reader: BlockfileReader<'me, u32, DataRecord<'me>>,
+}
+
+impl<'me> IvfIndexReader<'me> {
+    /// The index must have its centroids loaded, see `IvfIndex::load_centroids`.
+    pub(crate) fn new(
+        index: IvfIndex,
+        lists_blockfile_reader: BlockfileReader<'me, u32, DataRecord<'me>>,
+    ) -> Self {
+        IvfIndexReader {
+            index,
+            lists_blockfile_reader,
+        }
+    }
+
+    pub(crate) async fn query(
+        &'me self,
+        vector: &[f32],
+        k: usize,
+        nprobe: usize,
+        filter: &IndexFilter<'_>,
+    ) -> Result<(Vec<usize>, Vec<f32>), Box<dyn ChromaError>> {
+        for list in self.index.probe(vector, nprobe) {
+            if self.index.is_loaded(list) {
+                continue;
+            }
+            let vectors = read_list(&self.lists_blockfile_reader, list).await?;
+            self.index.load_list(list, vectors);
+        }
+        Ok(self.index.query_with_nprobe(vector, k, nprobe, filter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockstore::provider::BlockfileProvider;
+    use crate::index::DEFAULT_RERANK_FACTOR;
+    use crate::storage::local::LocalStorage;
+    use crate::storage::Storage;
+    use crate::types::{Metadata, MetadataValue};
+    use rand::Rng;
+    use roaring::RoaringBitmap;
+
+    fn config(num_lists: usize) -> IvfIndexConfig {
+        IvfIndexConfig {
+            num_lists: Some(num_lists),
+            nprobe: 1,
+            training_iterations: DEFAULT_TRAINING_ITERATIONS,
+            quantization: None,
+            random_seed: 0,
+        }
+    }
+
+    fn index_config(dimensionality: i32) -> IndexConfig {
+        IndexConfig {
+            dimensionality,
+            distance_function: DistanceFunction::Euclidean,
+        }
+    }
+
+    // Vectors around a few far apart cluster centers
+    fn clustered_vectors(n: usize, d: usize, num_clusters: usize) -> Vec<Vec<f32>> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let centers: Vec<Vec<f32>> = (0..num_clusters)
+            .map(|_| (0..d).map(|_| rng.gen::<f32>() * 100.0).collect())
+            .collect();
+        (0..n)
+            .map(|i| {
+                centers[i % num_clusters]
+                    .iter()
+                    .map(|x| x + rng.gen::<f32>())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn exact_neighbors(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<usize> {
+        let mut distances: Vec<(f32, usize)> = vectors
+            .iter()
+            .enumerate()
+            .map(|(label, vector)| (squared_l2(vector, query), label))
+            .collect();
+        distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+        distances.into_iter().take(k).map(|(_, l)| l).collect()
+    }
+
+    #[test]
+    fn it_probes_the_closest_lists() {
+        let d = 8;
+        let vectors = clustered_vectors(1024, d, 4);
+        let index =
+            IvfIndex::init(&index_config(d as i32), Some(&config(4)), Uuid::new_v4()).unwrap();
+        for (label, vector) in vectors.iter().enumerate() {
+            index.add(label, vector);
+        }
+        // Untrained indices search exactly
+        assert!(!index.is_trained());
+        let (labels, _) = index.query(&vectors[5], 10, &IndexFilter::default());
+        assert_eq!(labels, exact_neighbors(&vectors, &vectors[5], 10));
+
+        assert!(index.train());
+        assert!(!index.train());
+        assert_eq!(index.len(), vectors.len());
+        assert_eq!(index.centroids().len(), 4 * d);
+
+        // The clusters are far apart, so probing the closest list finds the neighbors
+        for query in vectors.iter().take(20) {
+            let (labels, distances) = index.query(query, 10, &IndexFilter::default());
+            assert_eq!(labels, exact_neighbors(&vectors, query, 10));
+            assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+        }
+        // Vectors added after training go to the list of their closest centroid
+        index.add(5000, &vectors[7]);
+        let disallowed = RoaringBitmap::from_iter([7]);
+        let filter = IndexFilter::new(None, Some(&disallowed));
+        let (labels, _) = index.query(&vectors[7], 2, &filter);
+        assert_eq!(labels[0], 5000);
+
+        index.delete(5000);
+        assert_eq!(index.get(5000), None);
+        assert_eq!(index.get(7), Some(vectors[7].clone()));
+        let allowed = RoaringBitmap::from_iter([1, 2, 3]);
+        let filter = IndexFilter::new(Some(&allowed), None);
+        let (labels, _) = index.query_with_nprobe(&vectors[7], 3, 4, &filter);
+        assert_eq!(labels.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_writes_and_reads_lists_from_blockfiles() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let d = 8;
+        let vectors = clustered_vectors(1024, d, 4);
+        let index_config = index_config(d as i32);
+        let config = config(4);
+
+        let new_index = || IvfIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let mut writer = IvfIndexWriter::new(
+            new_index(),
+            blockfile_provider.create::<u32, &DataRecord>().unwrap(),
+            blockfile_provider.create::<u32, &DataRecord>().unwrap(),
+            blockfile_provider.create::<u32, u32>().unwrap(),
+            None,
+            None,
+        );
+        for (label, vector) in vectors.iter().enumerate() {
+            writer.add(label as u32, vector);
+        }
+        writer.write_to_blockfiles().await.unwrap();
+        let flusher = writer.commit().unwrap();
+        let (centroids_id, lists_id, assignments_id) = (
+            flusher.centroids_id(),
+            flusher.lists_id(),
+            flusher.assignments_id(),
+        );
+        flusher.flush().await.unwrap();
+
+        let centroids_reader = blockfile_provider
+            .open::<u32, DataRecord>(&centroids_id)
+            .await
+            .unwrap();
+        let index = new_index();
+        index.load_centroids(&centroids_reader).await.unwrap();
+        assert!(index.is_trained());
+        let lists_reader = blockfile_provider
+            .open::<u32, DataRecord>(&lists_id)
+            .await
+            .unwrap();
+        let reader = IvfIndexReader::new(index, lists_reader);
+        let (labels, _) = reader
+            .query(&vectors[3], 10, 1, &IndexFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(labels, exact_neighbors(&vectors, &vectors[3], 10));
+        // Only the probed list is loaded
+        assert_eq!(reader.index.len(), vectors.len() / 4);
+
+        // A forked writer only writes the changes
+        let index = new_index();
+        index.load_centroids(&centroids_reader).await.unwrap();
+        let mut writer = IvfIndexWriter::new(
+            index,
+            blockfile_provider
+                .fork::<u32, &DataRecord>(&centroids_id)
+                .await
+                .unwrap(),
+            blockfile_provider
+                .fork::<u32, &DataRecord>(&lists_id)
+                .await
+                .unwrap(),
+            blockfile_provider
+                .fork::<u32, u32>(&assignments_id)
+                .await
+                .unwrap(),
+            Some(
+                blockfile_provider
+                    .open::<u32, DataRecord>(&lists_id)
+                    .await
+                    .unwrap(),
+            ),
+            Some(
+                blockfile_provider
+                    .open::<u32, u32>(&assignments_id)
+                    .await
+                    .unwrap(),
+            ),
+        );
+        writer.delete(3);
+        // Moves vector 4 into the cluster of vector 5
+        writer.add(4, &vectors[5]);
+        writer.write_to_blockfiles().await.unwrap();
+        let flusher = writer.commit().unwrap();
+        let (forked_centroids_id, forked_lists_id) = (flusher.centroids_id(), flusher.lists_id());
+        flusher.flush().await.unwrap();
+
+        let centroids_reader = blockfile_provider
+            .open::<u32, DataRecord>(&forked_centroids_id)
+            .await
+            .unwrap();
+        let index = new_index();
+        index.load_centroids(&centroids_reader).await.unwrap();
+        let lists_reader = blockfile_provider
+            .open::<u32, DataRecord>(&forked_lists_id)
+            .await
+            .unwrap();
+        let reader = IvfIndexReader::new(index, lists_reader);
+        let (labels, _) = reader
+            .query(&vectors[3], 10, 4, &IndexFilter::default())
+            .await
+            .unwrap();
+        assert!(!labels.contains(&3));
+        assert!(!labels.contains(&4));
+        let (labels, distances) = reader
+            .query(&vectors[5], 2, 4, &IndexFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(labels, vec![4, 5]);
+        assert_eq!(distances, vec![0.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn it_scans_quantized_lists() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let d = 16;
+        let vectors = clustered_vectors(1024, d, 4);
+        let index_config = index_config(d as i32);
+        let mut config = config(4);
+        config.quantization = Some(ScalarQuantizationConfig { rerank_factor: 4 });
+
+        let new_index = || IvfIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let mut writer = IvfIndexWriter::new(
+            new_index(),
+            blockfile_provider.create::<u32, &DataRecord>().unwrap(),
+            blockfile_provider.create::<u32, &DataRecord>().unwrap(),
+            blockfile_provider.create::<u32, u32>().unwrap(),
+            None,
+            None,
+        );
+        for (label, vector) in vectors.iter().enumerate() {
+            writer.add(label as u32, vector);
+        }
+        writer.write_to_blockfiles().await.unwrap();
+        let quantizer = writer.index.quantizer().unwrap();
+        let flusher = writer.commit().unwrap();
+        let (centroids_id, lists_id) = (flusher.centroids_id(), flusher.lists_id());
+        flusher.flush().await.unwrap();
+
+        // The quantizer is stored with the centroids
+        let centroids_reader = blockfile_provider
+            .open::<u32, DataRecord>(&centroids_id)
+            .await
+            .unwrap();
+        let index = new_index();
+        index.load_centroids(&centroids_reader).await.unwrap();
+        assert_eq!(index.centroids().len(), 4 * d);
+        assert_eq!(index.quantizer(), Some(quantizer));
+
+        // The lists hold the full vectors to rerank with
+        let lists_reader = blockfile_provider
+            .open::<u32, DataRecord>(&lists_id)
+            .await
+            .unwrap();
+        let mut num_written = 0;
+        for list in 0..4 {
+            let written = lists_reader
+                .get_by_prefix(&list_prefix(list))
+                .await
+                .unwrap();
+            num_written += written.len();
+            for (_, label, record) in written {
+                assert_eq!(record.embedding, vectors[label as usize].as_slice());
+            }
+        }
+        assert_eq!(num_written, vectors.len());
+
+        // The index only holds the codes, so candidates are reranked by the caller
+        let reader = IvfIndexReader::new(index, lists_reader);
+        assert_eq!(reader.index.get(0), None);
+        let mut num_found = 0;
+        for query in vectors.iter().take(20) {
+            let (candidates, _) = reader
+                .query(query, 5 * 4, 1, &IndexFilter::default())
+                .await
+                .unwrap();
+            let mut labels: Vec<(f32, usize)> = candidates
+                .into_iter()
+                .map(|label| (squared_l2(&vectors[label], query), label))
+                .collect();
+            labels.sort_by(|a, b| a.0.total_cmp(&b.0));
+            labels.truncate(5);
+            let neighbors = exact_neighbors(&vectors, query, 5);
+            num_found += labels.iter().filter(|(_, l)| neighbors.contains(l)).count();
+            assert_eq!(labels[0].1, neighbors[0]);
+        }
+        // The vectors of a cluster are within a quantization step or two of each other
+        assert!(num_found as f32 / 100.0 >= 0.8);
+    }
+
+    #[test]
+    fn it_reads_the_config_from_segment_metadata() {
+        let mut metadata = Metadata::new();
+        metadata.insert("ivf:num_lists".to_string(), MetadataValue::Int(16));
+        metadata.insert("ivf:nprobe".to_string(), MetadataValue::Int(4));
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::IvfDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(metadata),
+            file_path: HashMap::new(),
+        };
+        let config = IvfIndexConfig::from_segment(&segment).unwrap();
+        assert_eq!(config.num_lists, Some(16));
+        assert_eq!(config.nprobe, 4);
+        assert_eq!(config.training_iterations, DEFAULT_TRAINING_ITERATIONS);
+
+        segment
+            .metadata
+            .as_mut()
+            .unwrap()
+            .insert("ivf:nprobe".to_string(), MetadataValue::Int(0));
+        let result = IvfIndexConfig::from_segment(&segment);
+        assert_eq!(result.err().unwrap().code(), ErrorCodes::InvalidArgument);
+        segment
+            .metadata
+            .as_mut()
+            .unwrap()
+            .insert("ivf:nprobe".to_string(), MetadataValue::Int(4));
+        let config = IvfIndexConfig::from_segment(&segment).unwrap();
+        assert_eq!(config.quantization, None);
+        segment.metadata.as_mut().unwrap().insert(
+            "ivf:quantization".to_string(),
+            MetadataValue::Str("int8".to_string()),
+        );
+        let config = IvfIndexConfig::from_segment(&segment).unwrap();
+        assert_eq!(
+            config.quantization,
+            Some(ScalarQuantizationConfig {
+                rerank_factor: DEFAULT_RERANK_FACTOR
+            })
+        );
+        segment.metadata.as_mut().unwrap().insert(
+            "ivf:quantization".to_string(),
+            MetadataValue::Str("int4".to_string()),
+        );
+        let result = IvfIndexConfig::from_segment(&segment);
+        assert_eq!(result.err().unwrap().code(), ErrorCodes::InvalidArgument);
+    }
+}
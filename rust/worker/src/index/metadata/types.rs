@@ -1,5 +1,8 @@
-use crate::blockstore::{key::KeyWrapper, BlockfileFlusher, BlockfileReader, BlockfileWriter};
+use crate::blockstore::arrow::types::ArrowReadableKey;
+use crate::blockstore::{key::KeyWrapper, BlockfileFlusher, BlockfileReader, BlockfileWriter, Key};
 use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::fulltext::query::DocumentQueryParseError;
+use crate::index::fulltext::regex_prefilter::RegexPrefilterError;
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -14,11 +17,24 @@ use std::sync::Arc;
 pub(crate) enum MetadataIndexError {
     #[error("Invalid key type")]
     InvalidKeyType,
+    #[error("Operator {0} is not supported")]
+    UnsupportedOperator(String),
+    #[error("Invalid document query: {0}")]
+    InvalidDocumentQuery(#[from] DocumentQueryParseError),
+    #[error("{0}")]
+    InvalidRegex(#[from] RegexPrefilterError),
+    #[error("Regex requires no literal text and cannot be looked up in the index")]
+    UnboundedRegex,
+    #[error("Error querying the full text index: {0}")]
+    FullTextIndexError(Box<dyn ChromaError>),
 }
 
 impl ChromaError for MetadataIndexError {
     fn code(&self) -> crate::errors::ErrorCodes {
-        ErrorCodes::InvalidArgument
+        match self {
+            MetadataIndexError::FullTextIndexError(e) => e.code(),
+            _ => ErrorCodes::InvalidArgument,
+        }
     }
 }
 
@@ -33,14 +49,19 @@ impl ChromaError for MetadataIndexError {
 // - We could do the Arrow pattern of having keys know how to write themselves
 //  into MetadataIndexWriter store and long term we probably want to. But for now
 //  this gets the job done.
+// The uncommitted rbms only hold the changes to each key, they are applied to
+// the rbm of the forked blockfile, read through the optional reader, on write.
+#[derive(Clone)]
 pub(crate) enum MetadataIndexWriter {
     StringMetadataIndexWriter(
         BlockfileWriter,
-        Arc<Mutex<HashMap<String, HashMap<String, RoaringBitmap>>>>,
+        Option<Arc<BlockfileReader<'static, &'static str, RoaringBitmap>>>,
+        Arc<Mutex<HashMap<String, HashMap<String, UncommittedRbm>>>>,
     ),
     U32MetadataIndexWriter(
         BlockfileWriter,
-        Arc<Mutex<HashMap<String, HashMap<u32, RoaringBitmap>>>>,
+        Option<Arc<BlockfileReader<'static, u32, RoaringBitmap>>>,
+        Arc<Mutex<HashMap<String, HashMap<u32, UncommittedRbm>>>>,
     ),
     // We use a Vec<(KeyWrapper, RoaringBitmap)> instead of a HashMap because
     // f32 doesn't implement Eq or Hash. Eq is trivial since we disallow
@@ -49,97 +70,149 @@ pub(crate) enum MetadataIndexWriter {
     // and the expected case is much less than that.
     F32MetadataIndexWriter(
         BlockfileWriter,
-        Arc<Mutex<HashMap<String, Vec<(f32, RoaringBitmap)>>>>,
+        Option<Arc<BlockfileReader<'static, f32, RoaringBitmap>>>,
+        Arc<Mutex<HashMap<String, Vec<(f32, UncommittedRbm)>>>>,
     ),
     BoolMetadataIndexWriter(
         BlockfileWriter,
-        Arc<Mutex<HashMap<String, HashMap<bool, RoaringBitmap>>>>,
+        Option<Arc<BlockfileReader<'static, bool, RoaringBitmap>>>,
+        Arc<Mutex<HashMap<String, HashMap<bool, UncommittedRbm>>>>,
     ),
 }
 
+/// The offset ids added to and deleted from a metadata value since the
+/// blockfile was forked.
+/// # Notes
+/// The two sets are kept disjoint, so the last of a set and a delete of the
+/// same offset id wins.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UncommittedRbm {
+    added: RoaringBitmap,
+    deleted: RoaringBitmap,
+}
+
+impl UncommittedRbm {
+    fn insert(&mut self, offset_id: u32) {
+        self.deleted.remove(offset_id);
+        self.added.insert(offset_id);
+    }
+
+    fn remove(&mut self, offset_id: u32) {
+        self.added.remove(offset_id);
+        self.deleted.insert(offset_id);
+    }
+
+    fn apply(&self, forked_rbm: RoaringBitmap) -> RoaringBitmap {
+        (forked_rbm | &self.added) - &self.deleted
+    }
+}
+
+// Reads the rbm of a key from the forked blockfile, None if the key is not in it.
+async fn get_forked_rbm<'me, K>(
+    blockfile_reader: &'me Option<Arc<BlockfileReader<'me, K, RoaringBitmap>>>,
+    prefix: &str,
+    key: K,
+) -> Result<Option<RoaringBitmap>, Box<dyn ChromaError>>
+where
+    K: Key + Into<KeyWrapper> + From<&'me KeyWrapper> + ArrowReadableKey<'me>,
+{
+    let blockfile_reader = match blockfile_reader {
+        Some(reader) => reader,
+        None => return Ok(None),
+    };
+    if !blockfile_reader.contains(prefix, key.clone()).await {
+        return Ok(None);
+    }
+    Ok(Some(blockfile_reader.get(prefix, key).await?))
+}
+
 impl MetadataIndexWriter {
-    pub fn new_string(init_blockfile_writer: BlockfileWriter) -> Self {
+    pub fn new_string(
+        init_blockfile_writer: BlockfileWriter,
+        forked_blockfile_reader: Option<BlockfileReader<'static, &'static str, RoaringBitmap>>,
+    ) -> Self {
         MetadataIndexWriter::StringMetadataIndexWriter(
             init_blockfile_writer,
+            forked_blockfile_reader.map(Arc::new),
             Arc::new(Mutex::new(HashMap::new())),
         )
     }
 
-    pub fn new_u32(init_blockfile_writer: BlockfileWriter) -> Self {
+    pub fn new_u32(
+        init_blockfile_writer: BlockfileWriter,
+        forked_blockfile_reader: Option<BlockfileReader<'static, u32, RoaringBitmap>>,
+    ) -> Self {
         MetadataIndexWriter::U32MetadataIndexWriter(
             init_blockfile_writer,
+            forked_blockfile_reader.map(Arc::new),
             Arc::new(Mutex::new(HashMap::new())),
         )
     }
 
-    pub fn new_f32(init_blockfile_writer: BlockfileWriter) -> Self {
+    pub fn new_f32(
+        init_blockfile_writer: BlockfileWriter,
+        forked_blockfile_reader: Option<BlockfileReader<'static, f32, RoaringBitmap>>,
+    ) -> Self {
         MetadataIndexWriter::F32MetadataIndexWriter(
             init_blockfile_writer,
+            forked_blockfile_reader.map(Arc::new),
             Arc::new(Mutex::new(HashMap::new())),
         )
     }
 
-    pub fn new_bool(init_blockfile_writer: BlockfileWriter) -> Self {
+    pub fn new_bool(
+        init_blockfile_writer: BlockfileWriter,
+        forked_blockfile_reader: Option<BlockfileReader<'static, bool, RoaringBitmap>>,
+    ) -> Self {
         MetadataIndexWriter::BoolMetadataIndexWriter(
             init_blockfile_writer,
+            forked_blockfile_reader.map(Arc::new),
             Arc::new(Mutex::new(HashMap::new())),
         )
     }
 
-    fn look_up_key_and_populate_uncommitted_rbms(
+    fn update_uncommitted_rbm(
         &self,
         prefix: &str,
-        key: &KeyWrapper,
+        key: KeyWrapper,
+        update: impl FnOnce(&mut UncommittedRbm),
     ) -> Result<(), Box<dyn ChromaError>> {
         match self {
-            MetadataIndexWriter::StringMetadataIndexWriter(_, uncommitted_rbms) => match key {
+            MetadataIndexWriter::StringMetadataIndexWriter(_, _, uncommitted_rbms) => match key {
                 KeyWrapper::String(k) => {
                     let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    if !uncommitted_rbms.contains_key(prefix) {
-                        uncommitted_rbms.insert(prefix.to_string(), HashMap::new());
-                    }
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    if !rbms.contains_key(k) {
-                        rbms.insert(k.to_string(), RoaringBitmap::new());
-                    }
+                    let rbms = uncommitted_rbms.entry(prefix.to_string()).or_default();
+                    update(rbms.entry(k).or_default());
                 }
                 _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
             },
-            MetadataIndexWriter::U32MetadataIndexWriter(_, uncommitted_rbms) => match key {
-                KeyWrapper::Uint32(k) => {
+            MetadataIndexWriter::BoolMetadataIndexWriter(_, _, uncommitted_rbms) => match key {
+                KeyWrapper::Bool(k) => {
                     let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    if !uncommitted_rbms.contains_key(prefix) {
-                        uncommitted_rbms.insert(prefix.to_string(), HashMap::new());
-                    }
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    if !rbms.contains_key(k) {
-                        rbms.insert(*k, RoaringBitmap::new());
-                    }
+                    let rbms = uncommitted_rbms.entry(prefix.to_string()).or_default();
+                    update(rbms.entry(k).or_default());
                 }
                 _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
             },
-            MetadataIndexWriter::F32MetadataIndexWriter(_, uncommitted_rbms) => match key {
-                KeyWrapper::Float32(k) => {
+            MetadataIndexWriter::U32MetadataIndexWriter(_, _, uncommitted_rbms) => match key {
+                KeyWrapper::Uint32(k) => {
                     let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    if !uncommitted_rbms.contains_key(prefix) {
-                        uncommitted_rbms.insert(prefix.to_string(), vec![]);
-                    }
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    if !rbms.iter().any(|(rbm_k, _)| rbm_k == k) {
-                        rbms.push((*k, RoaringBitmap::new()));
-                    }
+                    let rbms = uncommitted_rbms.entry(prefix.to_string()).or_default();
+                    update(rbms.entry(k).or_default());
                 }
                 _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
             },
-            MetadataIndexWriter::BoolMetadataIndexWriter(_, uncommitted_rbms) => match key {
-                KeyWrapper::Bool(k) => {
+            MetadataIndexWriter::F32MetadataIndexWriter(_, _, uncommitted_rbms) => match key {
+                KeyWrapper::Float32(k) => {
                     let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    if !uncommitted_rbms.contains_key(prefix) {
-                        uncommitted_rbms.insert(prefix.to_string(), HashMap::new());
-                    }
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    if !rbms.contains_key(k) {
-                        rbms.insert(*k, RoaringBitmap::new());
+                    let rbms = uncommitted_rbms.entry(prefix.to_string()).or_default();
+                    match rbms.iter_mut().find(|(rbm_k, _)| *rbm_k == k) {
+                        Some((_, rbm)) => update(rbm),
+                        None => {
+                            let mut rbm = UncommittedRbm::default();
+                            update(&mut rbm);
+                            rbms.push((k, rbm));
+                        }
                     }
                 }
                 _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
@@ -154,82 +227,108 @@ impl MetadataIndexWriter {
         key: K,
         offset_id: u32,
     ) -> Result<(), Box<dyn ChromaError>> {
-        let key = key.into();
-        self.look_up_key_and_populate_uncommitted_rbms(prefix, &key)?;
-        match self {
-            MetadataIndexWriter::StringMetadataIndexWriter(_, uncommitted_rbms) => match key {
-                KeyWrapper::String(k) => {
-                    let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    let rbm = rbms.get_mut(&k).unwrap();
-                    rbm.insert(offset_id);
-                }
-                _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
-            },
-            MetadataIndexWriter::BoolMetadataIndexWriter(_, uncommitted_rbms) => match key {
-                KeyWrapper::Bool(k) => {
-                    let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    let rbm = rbms.get_mut(&k).unwrap();
-                    rbm.insert(offset_id);
-                }
-                _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
-            },
-            MetadataIndexWriter::U32MetadataIndexWriter(_, uncommitted_rbms) => match key {
-                KeyWrapper::Uint32(k) => {
-                    let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    let rbm = rbms.get_mut(&k).unwrap();
-                    rbm.insert(offset_id);
-                }
-                _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
-            },
-            MetadataIndexWriter::F32MetadataIndexWriter(_, uncommitted_rbms) => match key {
-                KeyWrapper::Float32(k) => {
-                    let mut uncommitted_rbms = uncommitted_rbms.lock();
-                    let rbms = uncommitted_rbms.get_mut(prefix).unwrap();
-                    let rbm = rbms.iter_mut().find(|(rbm_k, _)| *rbm_k == k).unwrap();
-                    rbm.1.insert(offset_id);
-                }
-                _ => return Err(Box::new(MetadataIndexError::InvalidKeyType)),
-            },
-        }
-        Ok(())
+        self.update_uncommitted_rbm(prefix, key.into(), |rbm| rbm.insert(offset_id))
+    }
+
+    pub fn delete<K: Into<KeyWrapper>>(
+        &self,
+        prefix: &str,
+        key: K,
+        offset_id: u32,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        self.update_uncommitted_rbm(prefix, key.into(), |rbm| rbm.remove(offset_id))
     }
 
     pub async fn write_to_blockfile(&mut self) -> Result<(), Box<dyn ChromaError>> {
         match self {
-            MetadataIndexWriter::StringMetadataIndexWriter(blockfile_writer, uncommitted_rbms) => {
-                let mut uncommitted_rbms = uncommitted_rbms.lock();
-                for (prefix, rbms) in uncommitted_rbms.drain() {
+            MetadataIndexWriter::StringMetadataIndexWriter(
+                blockfile_writer,
+                blockfile_reader,
+                uncommitted_rbms,
+            ) => {
+                // Drain before writing, the lock must not be held across an await.
+                let uncommitted_rbms: Vec<_> = uncommitted_rbms.lock().drain().collect();
+                for (prefix, rbms) in uncommitted_rbms {
                     for (key, rbm) in rbms.iter() {
-                        blockfile_writer
-                            .set(prefix.as_str(), key.as_str(), rbm)
-                            .await?
+                        let forked_rbm =
+                            get_forked_rbm(blockfile_reader, prefix.as_str(), key.as_str()).await?;
+                        let exists = forked_rbm.is_some();
+                        let rbm = rbm.apply(forked_rbm.unwrap_or_default());
+                        if !rbm.is_empty() {
+                            blockfile_writer
+                                .set(prefix.as_str(), key.as_str(), &rbm)
+                                .await?
+                        } else if exists {
+                            blockfile_writer
+                                .delete::<&str, &RoaringBitmap>(prefix.as_str(), key.as_str())
+                                .await?
+                        }
                     }
                 }
             }
-            MetadataIndexWriter::U32MetadataIndexWriter(blockfile_writer, uncommitted_rbms) => {
-                let mut uncommitted_rbms = uncommitted_rbms.lock();
-                for (prefix, rbms) in uncommitted_rbms.drain() {
+            MetadataIndexWriter::U32MetadataIndexWriter(
+                blockfile_writer,
+                blockfile_reader,
+                uncommitted_rbms,
+            ) => {
+                let uncommitted_rbms: Vec<_> = uncommitted_rbms.lock().drain().collect();
+                for (prefix, rbms) in uncommitted_rbms {
                     for (key, rbm) in rbms.iter() {
-                        blockfile_writer.set(prefix.as_str(), *key, rbm).await?
+                        let forked_rbm =
+                            get_forked_rbm(blockfile_reader, prefix.as_str(), *key).await?;
+                        let exists = forked_rbm.is_some();
+                        let rbm = rbm.apply(forked_rbm.unwrap_or_default());
+                        if !rbm.is_empty() {
+                            blockfile_writer.set(prefix.as_str(), *key, &rbm).await?
+                        } else if exists {
+                            blockfile_writer
+                                .delete::<u32, &RoaringBitmap>(prefix.as_str(), *key)
+                                .await?
+                        }
                     }
                 }
             }
-            MetadataIndexWriter::F32MetadataIndexWriter(blockfile_writer, uncommitted_rbms) => {
-                let mut uncommitted_rbms = uncommitted_rbms.lock();
-                for (prefix, rbms) in uncommitted_rbms.drain() {
+            MetadataIndexWriter::F32MetadataIndexWriter(
+                blockfile_writer,
+                blockfile_reader,
+                uncommitted_rbms,
+            ) => {
+                let uncommitted_rbms: Vec<_> = uncommitted_rbms.lock().drain().collect();
+                for (prefix, rbms) in uncommitted_rbms {
                     for (key, rbm) in rbms.iter() {
-                        blockfile_writer.set(prefix.as_str(), *key, rbm).await?
+                        let forked_rbm =
+                            get_forked_rbm(blockfile_reader, prefix.as_str(), *key).await?;
+                        let exists = forked_rbm.is_some();
+                        let rbm = rbm.apply(forked_rbm.unwrap_or_default());
+                        if !rbm.is_empty() {
+                            blockfile_writer.set(prefix.as_str(), *key, &rbm).await?
+                        } else if exists {
+                            blockfile_writer
+                                .delete::<f32, &RoaringBitmap>(prefix.as_str(), *key)
+                                .await?
+                        }
                     }
                 }
             }
-            MetadataIndexWriter::BoolMetadataIndexWriter(blockfile_writer, uncommitted_rbms) => {
-                let mut uncommitted_rbms = uncommitted_rbms.lock();
-                for (prefix, rbms) in uncommitted_rbms.drain() {
+            MetadataIndexWriter::BoolMetadataIndexWriter(
+                blockfile_writer,
+                blockfile_reader,
+                uncommitted_rbms,
+            ) => {
+                let uncommitted_rbms: Vec<_> = uncommitted_rbms.lock().drain().collect();
+                for (prefix, rbms) in uncommitted_rbms {
                     for (key, rbm) in rbms.iter() {
-                        blockfile_writer.set(prefix.as_str(), *key, rbm).await?
+                        let forked_rbm =
+                            get_forked_rbm(blockfile_reader, prefix.as_str(), *key).await?;
+                        let exists = forked_rbm.is_some();
+                        let rbm = rbm.apply(forked_rbm.unwrap_or_default());
+                        if !rbm.is_empty() {
+                            blockfile_writer.set(prefix.as_str(), *key, &rbm).await?
+                        } else if exists {
+                            blockfile_writer
+                                .delete::<bool, &RoaringBitmap>(prefix.as_str(), *key)
+                                .await?
+                        }
                     }
                 }
             }
@@ -239,22 +338,22 @@ impl MetadataIndexWriter {
 
     pub fn commit(self) -> Result<MetadataIndexFlusher, Box<dyn ChromaError>> {
         match self {
-            MetadataIndexWriter::StringMetadataIndexWriter(blockfile_writer, _) => {
+            MetadataIndexWriter::StringMetadataIndexWriter(blockfile_writer, _, _) => {
                 Ok(MetadataIndexFlusher::StringMetadataIndexFlusher(
                     blockfile_writer.commit::<&str, &RoaringBitmap>()?,
                 ))
             }
-            MetadataIndexWriter::U32MetadataIndexWriter(blockfile_writer, _) => {
+            MetadataIndexWriter::U32MetadataIndexWriter(blockfile_writer, _, _) => {
                 Ok(MetadataIndexFlusher::U32MetadataIndexFlusher(
                     blockfile_writer.commit::<u32, &RoaringBitmap>()?,
                 ))
             }
-            MetadataIndexWriter::F32MetadataIndexWriter(blockfile_writer, _) => {
+            MetadataIndexWriter::F32MetadataIndexWriter(blockfile_writer, _, _) => {
                 Ok(MetadataIndexFlusher::F32MetadataIndexFlusher(
                     blockfile_writer.commit::<f32, &RoaringBitmap>()?,
                 ))
             }
-            MetadataIndexWriter::BoolMetadataIndexWriter(blockfile_writer, _) => {
+            MetadataIndexWriter::BoolMetadataIndexWriter(blockfile_writer, _, _) => {
                 Ok(MetadataIndexFlusher::BoolMetadataIndexFlusher(
                     blockfile_writer.commit::<bool, &RoaringBitmap>()?,
                 ))
@@ -555,28 +654,28 @@ mod test {
     fn test_new_string_writer() {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<&str, &RoaringBitmap>().unwrap();
-        let _writer = MetadataIndexWriter::new_string(blockfile_writer);
+        let _writer = MetadataIndexWriter::new_string(blockfile_writer, None);
     }
 
     #[test]
     fn test_new_u32_writer() {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
-        let _writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let _writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
     }
 
     #[test]
     fn test_new_f32_writer() {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
-        let _writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let _writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
     }
 
     #[test]
     fn test_new_bool_writer() {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<bool, &RoaringBitmap>().unwrap();
-        let _writer = MetadataIndexWriter::new_bool(blockfile_writer);
+        let _writer = MetadataIndexWriter::new_bool(blockfile_writer, None);
     }
 
     #[tokio::test]
@@ -584,7 +683,7 @@ mod test {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<&str, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
-        let mut md_writer = MetadataIndexWriter::new_string(blockfile_writer);
+        let mut md_writer = MetadataIndexWriter::new_string(blockfile_writer, None);
         md_writer.write_to_blockfile().await.unwrap();
         let flusher = md_writer.commit().unwrap();
         flusher.flush().await.unwrap();
@@ -601,7 +700,7 @@ mod test {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
-        let mut md_writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let mut md_writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
         md_writer.write_to_blockfile().await.unwrap();
         let flusher = md_writer.commit().unwrap();
         flusher.flush().await.unwrap();
@@ -618,7 +717,7 @@ mod test {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
-        let mut md_writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let mut md_writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
         md_writer.write_to_blockfile().await.unwrap();
         let flusher = md_writer.commit().unwrap();
         flusher.flush().await.unwrap();
@@ -635,7 +734,7 @@ mod test {
         let provider = BlockfileProvider::new_memory();
         let blockfile_writer = provider.create::<bool, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
-        let mut md_writer = MetadataIndexWriter::new_bool(blockfile_writer);
+        let mut md_writer = MetadataIndexWriter::new_bool(blockfile_writer, None);
         md_writer.write_to_blockfile().await.unwrap();
         let flusher = md_writer.commit().unwrap();
         flusher.flush().await.unwrap();
@@ -653,7 +752,7 @@ mod test {
         let blockfile_writer = provider.create::<&str, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_string(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_string(blockfile_writer, None);
         writer.set("key", "value", 1).unwrap();
         writer.write_to_blockfile().await.unwrap();
         let flusher = writer.commit().unwrap();
@@ -675,7 +774,7 @@ mod test {
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
         writer.set("key", 1, 1).unwrap();
         writer.write_to_blockfile().await.unwrap();
         let flusher = writer.commit().unwrap();
@@ -697,7 +796,7 @@ mod test {
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
         writer.set("key", 1.0, 1).unwrap();
         writer.write_to_blockfile().await.unwrap();
         let flusher = writer.commit().unwrap();
@@ -719,7 +818,7 @@ mod test {
         let blockfile_writer = provider.create::<bool, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_bool(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_bool(blockfile_writer, None);
         writer.set("key", true, 1).unwrap();
         writer.write_to_blockfile().await.unwrap();
         let flusher = writer.commit().unwrap();
@@ -741,7 +840,7 @@ mod test {
         let blockfile_writer = provider.create::<&str, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_string(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_string(blockfile_writer, None);
         writer.set("key1", "value", 1).unwrap();
         writer.set("key1", "value", 2).unwrap();
         writer.set("key2", "value", 3).unwrap();
@@ -771,7 +870,7 @@ mod test {
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
         writer.set("key1", 1, 1).unwrap();
         writer.set("key1", 1, 2).unwrap();
         writer.set("key2", 1, 3).unwrap();
@@ -801,7 +900,7 @@ mod test {
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
         writer.set("key1", 1.0, 1).unwrap();
         writer.set("key1", 1.0, 2).unwrap();
         writer.set("key2", 1.0, 3).unwrap();
@@ -831,7 +930,7 @@ mod test {
         let blockfile_writer = provider.create::<bool, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_bool(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_bool(blockfile_writer, None);
         writer.set("key1", true, 1).unwrap();
         writer.set("key1", true, 2).unwrap();
         writer.set("key2", true, 3).unwrap();
@@ -861,7 +960,7 @@ mod test {
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
         writer.set("key1", 1, 1).unwrap();
         writer.set("key1", 2, 2).unwrap();
         writer.set("key1", 3, 3).unwrap();
@@ -895,7 +994,7 @@ mod test {
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
         writer.set("key1", 1, 1).unwrap();
         writer.set("key1", 2, 2).unwrap();
         writer.set("key1", 3, 3).unwrap();
@@ -930,7 +1029,7 @@ mod test {
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
         writer.set("key1", 1, 1).unwrap();
         writer.set("key1", 2, 2).unwrap();
         writer.set("key1", 3, 3).unwrap();
@@ -964,7 +1063,7 @@ mod test {
         let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
         writer.set("key1", 1, 1).unwrap();
         writer.set("key1", 2, 2).unwrap();
         writer.set("key1", 3, 3).unwrap();
@@ -999,7 +1098,7 @@ mod test {
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
         writer.set("key1", 1.0, 1).unwrap();
         writer.set("key1", 2.0, 2).unwrap();
         writer.set("key1", 3.0, 3).unwrap();
@@ -1034,7 +1133,7 @@ mod test {
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
         writer.set("key1", 1.0, 1).unwrap();
         writer.set("key1", 2.0, 2).unwrap();
         writer.set("key1", 3.0, 3).unwrap();
@@ -1070,7 +1169,7 @@ mod test {
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
         writer.set("key1", 1.0, 1).unwrap();
         writer.set("key1", 2.0, 2).unwrap();
         writer.set("key1", 3.0, 3).unwrap();
@@ -1104,7 +1203,7 @@ mod test {
         let blockfile_writer = provider.create::<f32, &RoaringBitmap>().unwrap();
         let writer_id = blockfile_writer.id();
 
-        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer);
+        let mut writer = MetadataIndexWriter::new_f32(blockfile_writer, None);
         writer.set("key1", 1.0, 1).unwrap();
         writer.set("key1", 2.0, 2).unwrap();
         writer.set("key1", 3.0, 3).unwrap();
@@ -1,11 +1,22 @@
+pub(crate) mod config;
 pub(crate) mod fulltext;
 mod hnsw;
+mod hnsw_cache;
+mod hnsw_native;
 pub(crate) mod hnsw_provider;
+mod ivf;
 pub(crate) mod metadata;
+mod pq;
+pub(crate) mod pq_provider;
+mod scalar_quantizer;
 mod types;
 mod utils;
 
 // Re-export types
 
 pub(crate) use hnsw::*;
+pub(crate) use hnsw_native::*;
+pub(crate) use ivf::*;
+pub(crate) use pq::*;
+pub(crate) use scalar_quantizer::*;
 pub(crate) use types::*;
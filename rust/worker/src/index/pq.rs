@@ -0,0 +1,1010 @@
+use super::utils::{closest_centroid, kmeans, normalize, squared_l2};
+use super::{Index, IndexConfig, IndexFilter, PersistentIndex};
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::segment::schema::{PqSegmentConfig, SegmentConfigError};
+use crate::types::Segment;
+use parking_lot::RwLock;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub(crate) const PQ_INDEX_FILE: &str = "pq_index.bin";
+const PQ_INDEX_MAGIC: &[u8; 4] = b"CHPQ";
+const PQ_INDEX_VERSION: u32 = 1;
+
+// Codes are a byte per subquantizer, so there are at most 256 centroids.
+pub(crate) const MAX_NUM_CENTROIDS: usize = 256;
+const DEFAULT_SUBVECTOR_DIMENSIONALITY: usize = 4;
+pub(crate) const DEFAULT_TRAINING_ITERATIONS: usize = 25;
+// Codebooks are not trained until there are this many vectors per centroid, and are
+// trained on a sample of at most MAX_TRAINING_SAMPLES_PER_CENTROID vectors per centroid.
+const MIN_TRAINING_SAMPLES_PER_CENTROID: usize = 4;
+const MAX_TRAINING_SAMPLES_PER_CENTROID: usize = 256;
+pub(crate) const DEFAULT_RERANK_FACTOR: usize = 4;
+
+/// The configuration of a product quantized index.
+/// # Fields
+/// - `num_subquantizers` - The number of subvectors a vector is split into, and the
+///   number of bytes its code takes. Defaults to a subvector per 4 dimensions.
+/// - `num_centroids` - The number of centroids of each subquantizer, at most 256.
+/// - `training_iterations` - The number of k-means iterations to train codebooks with.
+/// - `rerank_factor` - Queries take rerank_factor * k candidates by approximate distance
+///   and rerank them with the full vectors. 0 disables reranking.
+#[derive(Clone, Debug)]
+pub(crate) struct PqIndexConfig {
+    pub(crate) num_subquantizers: Option<usize>,
+    pub(crate) num_centroids: usize,
+    pub(crate) training_iterations: usize,
+    pub(crate) rerank_factor: usize,
+    pub(crate) random_seed: usize,
+    pub(crate) persist_path: String,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum PqIndexFromSegmentError {
+    #[error("Missing config `{0}`")]
+    MissingConfig(String),
+    #[error(transparent)]
+    InvalidConfig(#[from] SegmentConfigError),
+}
+
+impl ChromaError for PqIndexFromSegmentError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            PqIndexFromSegmentError::MissingConfig(_) => ErrorCodes::InvalidArgument,
+            PqIndexFromSegmentError::InvalidConfig(e) => e.code(),
+        }
+    }
+}
+
+impl PqIndexConfig {
+    pub(crate) fn from_segment(
+        segment: &Segment,
+        persist_path: &Path,
+    ) -> Result<PqIndexConfig, Box<PqIndexFromSegmentError>> {
+        let persist_path = match persist_path.to_str() {
+            Some(persist_path) => persist_path,
+            None => {
+                return Err(Box::new(PqIndexFromSegmentError::MissingConfig(
+                    "persist_path".to_string(),
+                )))
+            }
+        };
+        let config = match PqSegmentConfig::from_segment(segment) {
+            Ok(config) => config,
+            Err(e) => return Err(Box::new(PqIndexFromSegmentError::InvalidConfig(e))),
+        };
+        Ok(PqIndexConfig {
+            num_subquantizers: config.num_subquantizers,
+            num_centroids: config.num_centroids,
+            training_iterations: config.training_iterations,
+            rerank_factor: config.rerank_factor,
+            random_seed: 0,
+            persist_path: persist_path.to_string(),
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum PqIndexError {
+    #[error("No config provided")]
+    NoConfigProvided,
+    #[error("Invalid number of subquantizers {0} for dimensionality {1}")]
+    InvalidNumSubquantizers(usize, i32),
+    #[error("Index was built with {0} {1}, but is configured with {2}")]
+    ConfigMismatch(String, String, String),
+    #[error("IO error")]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid pq index file: {0}")]
+    InvalidFormat(String),
+    #[error("Product quantization does not support the {0} space")]
+    UnsupportedDistanceFunction(String),
+}
+
+impl ChromaError for PqIndexError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            PqIndexError::NoConfigProvided => ErrorCodes::InvalidArgument,
+            PqIndexError::InvalidNumSubquantizers(_, _) => ErrorCodes::InvalidArgument,
+            PqIndexError::ConfigMismatch(_, _, _) => ErrorCodes::FailedPrecondition,
+            PqIndexError::IOError(_) => ErrorCodes::Internal,
+            PqIndexError::InvalidFormat(_) => ErrorCodes::Internal,
+            PqIndexError::UnsupportedDistanceFunction(_) => ErrorCodes::InvalidArgument,
+        }
+    }
+}
+
+struct PqState {
+    // centroids[s] are the flattened centroids of subquantizer s, empty until trained
+    centroids: Vec<Vec<f32>>,
+    labels: Vec<usize>,
+    label_to_slot: HashMap<usize, usize>,
+    // Codes of all slots, flattened, a byte per subquantizer
+    codes: Vec<u8>,
+    // Deleted slots stay in place and are reused by the next labels that are added,
+    // so the slots only grow with the number of labels the index holds at once
+    deleted: Vec<bool>,
+    free_slots: Vec<usize>,
+    // Vectors added before the codebooks are trained, kept in full
+    pending: HashMap<usize, Vec<f32>>,
+}
+
+impl PqState {
+    fn is_trained(&self) -> bool {
+        !self.centroids.is_empty()
+    }
+}
+
+/// A product quantized vector index.
+/// # Description
+/// Vectors are split into subvectors, and each subvector is replaced by the byte id of
+/// its closest centroid in a codebook trained with k-means. A 4 dimensional subvector
+/// takes a byte instead of 16. Queries compare the query against every code with
+/// asymmetric distance computation, the query is not quantized.
+/// # Training
+/// Vectors are kept in full and searched exactly until the index holds enough vectors
+/// to train codebooks from. Codebooks are trained on save, typically at the end of a
+/// compaction, and every vector added after is encoded with them.
+/// # Notes
+/// The distances are approximate. Callers that have the full vectors should take more
+/// candidates than they need and rerank them, see `rerank_factor`.
+pub(crate) struct PqIndex {
+    state: RwLock<PqState>,
+    dimensionality: usize,
+    distance_function: DistanceFunction,
+    // The first dimension of every subvector, and the dimensionality at the end
+    subvector_offsets: Vec<usize>,
+    config: PqIndexConfig,
+    pub(crate) id: Uuid,
+}
+
+impl PqIndex {
+    fn new(
+        index_config: &IndexConfig,
+        config: &PqIndexConfig,
+        num_subquantizers: usize,
+        id: Uuid,
+    ) -> Result<Self, PqIndexError> {
+        let dimensionality = index_config.dimensionality as usize;
+        // Hamming and Jaccard distances do not decompose over the centroids of the
+        // subvectors
+        if matches!(
+            index_config.distance_function,
+            DistanceFunction::Hamming | DistanceFunction::Jaccard
+        ) {
+            return Err(PqIndexError::UnsupportedDistanceFunction(
+                index_config.distance_function.clone().into(),
+            ));
+        }
+        if num_subquantizers == 0 || num_subquantizers > dimensionality {
+            return Err(PqIndexError::InvalidNumSubquantizers(
+                num_subquantizers,
+                index_config.dimensionality,
+            ));
+        }
+        // Spread the dimensions as evenly as possible over the subquantizers
+        let subvector_offsets = (0..=num_subquantizers)
+            .map(|s| s * dimensionality / num_subquantizers)
+            .collect();
+        Ok(PqIndex {
+            state: RwLock::new(PqState {
+                centroids: Vec::new(),
+                labels: Vec::new(),
+                label_to_slot: HashMap::new(),
+                codes: Vec::new(),
+                deleted: Vec::new(),
+                free_slots: Vec::new(),
+                pending: HashMap::new(),
+            }),
+            dimensionality,
+            distance_function: index_config.distance_function.clone(),
+            subvector_offsets,
+            config: config.clone(),
+            id,
+        })
+    }
+
+    fn num_subquantizers(&self) -> usize {
+        self.subvector_offsets.len() - 1
+    }
+
+    fn subvector<'vector>(&self, vector: &'vector [f32], subquantizer: usize) -> &'vector [f32] {
+        &vector[self.subvector_offsets[subquantizer]..self.subvector_offsets[subquantizer + 1]]
+    }
+
+    fn preprocess(&self, vector: &[f32]) -> Vec<f32> {
+        match self.distance_function {
+            DistanceFunction::Cosine => normalize(vector),
+            _ => vector.to_vec(),
+        }
+    }
+
+    /// The number of candidates to rerank per result, 0 if results should not be reranked.
+    pub(crate) fn rerank_factor(&self) -> usize {
+        self.config.rerank_factor
+    }
+
+    pub(crate) fn distance_function(&self) -> &DistanceFunction {
+        &self.distance_function
+    }
+
+    pub(crate) fn is_trained(&self) -> bool {
+        self.state.read().is_trained()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let state = self.state.read();
+        state.label_to_slot.len() + state.pending.len()
+    }
+
+    fn encode(&self, centroids: &[Vec<f32>], vector: &[f32]) -> Vec<u8> {
+        (0..self.num_subquantizers())
+            .map(|s| {
+                let subvector = self.subvector(vector, s);
+                closest_centroid(&centroids[s], subvector) as u8
+            })
+            .collect()
+    }
+
+    fn decode(&self, centroids: &[Vec<f32>], code: &[u8]) -> Vec<f32> {
+        let mut vector = Vec::with_capacity(self.dimensionality);
+        for (s, centroid) in code.iter().enumerate() {
+            let subvector_dimensionality =
+                self.subvector_offsets[s + 1] - self.subvector_offsets[s];
+            let start = *centroid as usize * subvector_dimensionality;
+            vector.extend_from_slice(&centroids[s][start..start + subvector_dimensionality]);
+        }
+        vector
+    }
+
+    fn insert_code(state: &mut PqState, label: usize, code: Vec<u8>) {
+        match state.label_to_slot.get(&label) {
+            Some(slot) => {
+                let num_subquantizers = code.len();
+                state.codes[slot * num_subquantizers..(slot + 1) * num_subquantizers]
+                    .copy_from_slice(&code);
+            }
+            None => match state.free_slots.pop() {
+                Some(slot) => {
+                    let num_subquantizers = code.len();
+                    state.labels[slot] = label;
+                    state.deleted[slot] = false;
+                    state.codes[slot * num_subquantizers..(slot + 1) * num_subquantizers]
+                        .copy_from_slice(&code);
+                    state.label_to_slot.insert(label, slot);
+                }
+                None => {
+                    let slot = state.labels.len();
+                    state.labels.push(label);
+                    state.deleted.push(false);
+                    state.codes.extend_from_slice(&code);
+                    state.label_to_slot.insert(label, slot);
+                }
+            },
+        }
+    }
+
+    /// Train the codebooks from the pending vectors if there are enough of them, and
+    /// encode the pending vectors. Does nothing once the codebooks are trained.
+    pub(crate) fn train(&self) {
+        let mut state = self.state.write();
+        if state.is_trained() {
+            return;
+        }
+        let num_centroids = self.config.num_centroids;
+        if state.pending.len() < num_centroids * MIN_TRAINING_SAMPLES_PER_CENTROID {
+            return;
+        }
+
+        // Train on the pending vectors in label order, so that training is deterministic
+        let mut pending: Vec<(usize, Vec<f32>)> = state.pending.drain().collect();
+        pending.sort_by_key(|(label, _)| *label);
+        let mut rng = StdRng::seed_from_u64(self.config.random_seed as u64);
+        let num_samples = pending
+            .len()
+            .min(num_centroids * MAX_TRAINING_SAMPLES_PER_CENTROID);
+        let samples = sample(&mut rng, pending.len(), num_samples).into_vec();
+        let mut centroids = Vec::with_capacity(self.num_subquantizers());
+        for s in 0..self.num_subquantizers() {
+            let subvector_dimensionality =
+                self.subvector_offsets[s + 1] - self.subvector_offsets[s];
+            let mut points = Vec::with_capacity(num_samples * subvector_dimensionality);
+            for i in samples.iter() {
+                points.extend_from_slice(self.subvector(&pending[*i].1, s));
+            }
+            centroids.push(kmeans(
+                &points,
+                subvector_dimensionality,
+                num_centroids,
+                self.config.training_iterations,
+                &mut rng,
+            ));
+        }
+
+        for (label, vector) in pending {
+            let code = self.encode(&centroids, &vector);
+            PqIndex::insert_code(&mut state, label, code);
+        }
+        state.centroids = centroids;
+    }
+
+    // The distance between the query and each centroid of each subquantizer
+    fn distance_table(&self, centroids: &[Vec<f32>], query: &[f32]) -> Vec<Vec<f32>> {
+        (0..self.num_subquantizers())
+            .map(|s| {
+                let subquery = self.subvector(query, s);
+                centroids[s]
+                    .chunks_exact(subquery.len())
+                    .map(|centroid| match self.distance_function {
+                        DistanceFunction::Euclidean => squared_l2(subquery, centroid),
+                        DistanceFunction::Manhattan => subquery
+                            .iter()
+                            .zip(centroid)
+                            .map(|(a, b)| (a - b).abs())
+                            .sum::<f32>(),
+                        // The distance is 1 - the sum of the dot products
+                        DistanceFunction::Cosine | DistanceFunction::InnerProduct => -subquery
+                            .iter()
+                            .zip(centroid)
+                            .map(|(a, b)| a * b)
+                            .sum::<f32>(),
+                        // Rejected by new
+                        DistanceFunction::Hamming | DistanceFunction::Jaccard => unreachable!(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn table_offset(&self) -> f32 {
+        match self.distance_function {
+            DistanceFunction::Euclidean
+            | DistanceFunction::Manhattan
+            | DistanceFunction::Hamming
+            | DistanceFunction::Jaccard => 0.0,
+            DistanceFunction::Cosine | DistanceFunction::InnerProduct => 1.0,
+        }
+    }
+
+    fn write_file(&self) -> Result<(), PqIndexError> {
+        let state = self.state.read();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(PQ_INDEX_MAGIC);
+        bytes.extend_from_slice(&PQ_INDEX_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.dimensionality as u32).to_le_bytes());
+        let space: String = self.distance_function.clone().into();
+        bytes.extend_from_slice(&(space.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(space.as_bytes());
+        bytes.extend_from_slice(&(self.num_subquantizers() as u32).to_le_bytes());
+        let num_centroids = match state.centroids.first() {
+            Some(centroids) => centroids.len() / (self.subvector_offsets[1]),
+            None => 0,
+        };
+        bytes.extend_from_slice(&(num_centroids as u32).to_le_bytes());
+        for centroids in state.centroids.iter() {
+            for value in centroids {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&(state.labels.len() as u64).to_le_bytes());
+        for (slot, label) in state.labels.iter().enumerate() {
+            bytes.extend_from_slice(&(*label as u64).to_le_bytes());
+            bytes.push(state.deleted[slot] as u8);
+        }
+        bytes.extend_from_slice(&state.codes);
+        let mut pending: Vec<(&usize, &Vec<f32>)> = state.pending.iter().collect();
+        pending.sort_by_key(|(label, _)| **label);
+        bytes.extend_from_slice(&(pending.len() as u64).to_le_bytes());
+        for (label, vector) in pending {
+            bytes.extend_from_slice(&(*label as u64).to_le_bytes());
+            for value in vector {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        let mut file =
+            std::fs::File::create(Path::new(&self.config.persist_path).join(PQ_INDEX_FILE))?;
+        file.write_all(&bytes)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    fn read_file(
+        path: &str,
+        index_config: &IndexConfig,
+        pq_config: Option<&PqIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, PqIndexError> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(Path::new(path).join(PQ_INDEX_FILE))?.read_to_end(&mut bytes)?;
+
+        // A cursor over the file that errors instead of reading past its end
+        struct Reader<'bytes> {
+            bytes: &'bytes [u8],
+            offset: usize,
+        }
+        impl<'bytes> Reader<'bytes> {
+            fn take(&mut self, len: usize) -> Result<&'bytes [u8], PqIndexError> {
+                let end = match self.offset.checked_add(len) {
+                    Some(end) if end <= self.bytes.len() => end,
+                    _ => return Err(PqIndexError::InvalidFormat("file is too short".to_string())),
+                };
+                let slice = &self.bytes[self.offset..end];
+                self.offset = end;
+                Ok(slice)
+            }
+            fn u32(&mut self) -> Result<usize, PqIndexError> {
+                Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+            }
+            fn u64(&mut self) -> Result<usize, PqIndexError> {
+                Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()) as usize)
+            }
+            fn f32s(&mut self, len: usize) -> Result<Vec<f32>, PqIndexError> {
+                let byte_len = len
+                    .checked_mul(4)
+                    .ok_or_else(|| PqIndexError::InvalidFormat("file is too short".to_string()))?;
+                Ok(self
+                    .take(byte_len)?
+                    .chunks_exact(4)
+                    .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                    .collect())
+            }
+        }
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+
+        if reader.take(4)? != PQ_INDEX_MAGIC {
+            return Err(PqIndexError::InvalidFormat("not a pq index".to_string()));
+        }
+        let version = reader.u32()?;
+        if version > PQ_INDEX_VERSION as usize {
+            return Err(PqIndexError::InvalidFormat(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let dimensionality = reader.u32()?;
+        let space_len = reader.u32()?;
+        let space = String::from_utf8_lossy(reader.take(space_len)?).to_string();
+        let num_subquantizers = reader.u32()?;
+
+        fn check<T: PartialEq + ToString>(
+            name: &str,
+            built: T,
+            configured: T,
+        ) -> Result<(), PqIndexError> {
+            match built == configured {
+                true => Ok(()),
+                false => Err(PqIndexError::ConfigMismatch(
+                    name.to_string(),
+                    built.to_string(),
+                    configured.to_string(),
+                )),
+            }
+        }
+        check(
+            "dimensionality",
+            dimensionality,
+            index_config.dimensionality as usize,
+        )?;
+        let configured_space: String = index_config.distance_function.clone().into();
+        check("space", space, configured_space)?;
+        let config = match pq_config {
+            Some(pq_config) => {
+                if let Some(configured) = pq_config.num_subquantizers {
+                    check("num_subquantizers", num_subquantizers, configured)?;
+                }
+                PqIndexConfig {
+                    persist_path: path.to_string(),
+                    ..pq_config.clone()
+                }
+            }
+            None => PqIndexConfig {
+                num_subquantizers: Some(num_subquantizers),
+                num_centroids: MAX_NUM_CENTROIDS,
+                training_iterations: DEFAULT_TRAINING_ITERATIONS,
+                rerank_factor: DEFAULT_RERANK_FACTOR,
+                random_seed: 0,
+                persist_path: path.to_string(),
+            },
+        };
+
+        let index = PqIndex::new(index_config, &config, num_subquantizers, id)?;
+        {
+            let mut state = index.state.write();
+            let num_centroids = reader.u32()?;
+            if num_centroids > MAX_NUM_CENTROIDS {
+                return Err(PqIndexError::InvalidFormat(
+                    "too many centroids".to_string(),
+                ));
+            }
+            if num_centroids > 0 {
+                for s in 0..num_subquantizers {
+                    let subvector_dimensionality =
+                        index.subvector_offsets[s + 1] - index.subvector_offsets[s];
+                    state
+                        .centroids
+                        .push(reader.f32s(num_centroids * subvector_dimensionality)?);
+                }
+            }
+            let count = reader.u64()?;
+            for slot in 0..count {
+                let label = reader.u64()?;
+                let deleted = reader.take(1)?[0] != 0;
+                state.labels.push(label);
+                state.deleted.push(deleted);
+                match deleted {
+                    true => state.free_slots.push(slot),
+                    false => {
+                        state.label_to_slot.insert(label, slot);
+                    }
+                }
+            }
+            let codes_len = count
+                .checked_mul(num_subquantizers)
+                .ok_or_else(|| PqIndexError::InvalidFormat("too many codes".to_string()))?;
+            state.codes = reader.take(codes_len)?.to_vec();
+            if state
+                .codes
+                .iter()
+                .any(|code| *code as usize >= num_centroids)
+            {
+                return Err(PqIndexError::InvalidFormat("code out of range".to_string()));
+            }
+            let num_pending = reader.u64()?;
+            for _ in 0..num_pending {
+                let label = reader.u64()?;
+                let vector = reader.f32s(dimensionality)?;
+                state.pending.insert(label, vector);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Load the index saved at path with the given configuration. The dimensionality,
+    /// the space and the number of subquantizers must match the saved index.
+    pub(crate) fn load_with_config(
+        path: &str,
+        index_config: &IndexConfig,
+        pq_config: Option<&PqIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        match PqIndex::read_file(path, index_config, pq_config, id) {
+            Ok(index) => Ok(index),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+impl Index<PqIndexConfig> for PqIndex {
+    fn init(
+        index_config: &IndexConfig,
+        pq_config: Option<&PqIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        let config = match pq_config {
+            Some(config) => config,
+            None => return Err(Box::new(PqIndexError::NoConfigProvided)),
+        };
+        let dimensionality = index_config.dimensionality as usize;
+        let num_subquantizers = match config.num_subquantizers {
+            Some(num_subquantizers) => num_subquantizers,
+            None => (dimensionality / DEFAULT_SUBVECTOR_DIMENSIONALITY).max(1),
+        };
+        match PqIndex::new(index_config, config, num_subquantizers, id) {
+            Ok(index) => Ok(index),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn add(&self, id: usize, vector: &[f32]) {
+        let vector = self.preprocess(vector);
+        let mut state = self.state.write();
+        if !state.is_trained() {
+            state.pending.insert(id, vector);
+            return;
+        }
+        let code = self.encode(&state.centroids, &vector);
+        PqIndex::insert_code(&mut state, id, code);
+    }
+
+    fn delete(&self, id: usize) {
+        let mut state = self.state.write();
+        if state.pending.remove(&id).is_some() {
+            return;
+        }
+        if let Some(slot) = state.label_to_slot.remove(&id) {
+            state.deleted[slot] = true;
+            state.free_slots.push(slot);
+        }
+    }
+
+    fn query(&self, vector: &[f32], k: usize, filter: &IndexFilter) -> (Vec<usize>, Vec<f32>) {
+        let query = self.preprocess(vector);
+
+        let state = self.state.read();
+        let mut results: Vec<(f32, usize)> = Vec::new();
+        if state.is_trained() {
+            let table = self.distance_table(&state.centroids, &query);
+            let offset = self.table_offset();
+            let num_subquantizers = self.num_subquantizers();
+            for (slot, code) in state.codes.chunks_exact(num_subquantizers).enumerate() {
+                let label = state.labels[slot];
+                if state.deleted[slot] || !filter.allows(label) {
+                    continue;
+                }
+                let distance: f32 = code
+                    .iter()
+                    .enumerate()
+                    .map(|(s, centroid)| table[s][*centroid as usize])
+                    .sum();
+                results.push((distance + offset, label));
+            }
+        }
+        for (label, pending) in state.pending.iter() {
+            if !filter.allows(*label) {
+                continue;
+            }
+            results.push((self.distance_function.distance(&query, pending), *label));
+        }
+
+        let by_distance =
+            |a: &(f32, usize), b: &(f32, usize)| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1));
+        if results.len() > k && k > 0 {
+            results.select_nth_unstable_by(k - 1, by_distance);
+        }
+        results.truncate(k);
+        results.sort_by(by_distance);
+        results
+            .into_iter()
+            .map(|(distance, label)| (label, distance))
+            .unzip()
+    }
+
+    /// Returns the reconstruction of the vector from its code once the index is trained,
+    /// which only approximates the vector that was added.
+    fn get(&self, id: usize) -> Option<Vec<f32>> {
+        let state = self.state.read();
+        if let Some(pending) = state.pending.get(&id) {
+            return Some(pending.clone());
+        }
+        let slot = state.label_to_slot.get(&id)?;
+        let num_subquantizers = self.num_subquantizers();
+        let code = &state.codes[slot * num_subquantizers..(slot + 1) * num_subquantizers];
+        Some(self.decode(&state.centroids, code))
+    }
+}
+
+impl PersistentIndex<PqIndexConfig> for PqIndex {
+    /// Trains the codebooks if there are enough vectors to, then saves the index.
+    fn save(&self) -> Result<(), Box<dyn ChromaError>> {
+        self.train();
+        match self.write_file() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn load(
+        path: &str,
+        index_config: &IndexConfig,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        PqIndex::load_with_config(path, index_config, None, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Metadata, MetadataValue};
+    use rand::Rng;
+    use roaring::RoaringBitmap;
+    use tempfile::tempdir;
+
+    fn config(persist_path: &str, num_subquantizers: usize) -> PqIndexConfig {
+        PqIndexConfig {
+            num_subquantizers: Some(num_subquantizers),
+            num_centroids: 16,
+            training_iterations: 10,
+            rerank_factor: DEFAULT_RERANK_FACTOR,
+            random_seed: 0,
+            persist_path: persist_path.to_string(),
+        }
+    }
+
+    fn random_vectors(n: usize, d: usize) -> Vec<Vec<f32>> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..n)
+            .map(|_| (0..d).map(|_| rng.gen::<f32>()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn it_searches_pending_vectors_exactly() {
+        let tmp_dir = tempdir().unwrap();
+        let index_config = IndexConfig {
+            dimensionality: 2,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let config = config(tmp_dir.path().to_str().unwrap(), 2);
+        let index = PqIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        index.add(1, &[0.0, 0.0]);
+        index.add(2, &[1.0, 1.0]);
+        index.add(3, &[3.0, 3.0]);
+        // Too few vectors to train codebooks from
+        index.save().unwrap();
+        assert!(!index.is_trained());
+
+        let (ids, distances) = index.query(&[1.0, 0.0], 2, &IndexFilter::default());
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(distances, vec![1.0, 1.0]);
+        let disallowed = RoaringBitmap::from_iter([1]);
+        let filter = IndexFilter::new(None, Some(&disallowed));
+        let (ids, _) = index.query(&[1.0, 0.0], 2, &filter);
+        assert_eq!(ids, vec![2, 3]);
+        index.delete(2);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(3), Some(vec![3.0, 3.0]));
+    }
+
+    #[test]
+    fn it_trains_and_finds_neighbors() {
+        let tmp_dir = tempdir().unwrap();
+        let (n, d) = (1000, 16);
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let config = config(tmp_dir.path().to_str().unwrap(), 4);
+        let index = PqIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let vectors = random_vectors(n, d);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.add(i, vector);
+        }
+        index.save().unwrap();
+        assert!(index.is_trained());
+        assert_eq!(index.len(), n);
+
+        // The codes are a good enough approximation to find the true nearest neighbors
+        // among a few candidates
+        let mut found = 0;
+        for query in vectors.iter().take(100) {
+            let exact = vectors
+                .iter()
+                .enumerate()
+                .map(|(i, vector)| (squared_l2(query, vector), i))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap()
+                .1;
+            let (ids, distances) = index.query(query, 10, &IndexFilter::default());
+            assert_eq!(ids.len(), 10);
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+            if ids.contains(&exact) {
+                found += 1;
+            }
+        }
+        assert!(found > 90, "found {} of 100", found);
+
+        // Vectors added after training are encoded
+        index.add(n, &vectors[0]);
+        let (ids, _) = index.query(&vectors[0], 2, &IndexFilter::default());
+        assert!(ids.contains(&n));
+        index.delete(0);
+        let (ids, _) = index.query(&vectors[0], 2, &IndexFilter::default());
+        assert!(!ids.contains(&0));
+        let allowed = RoaringBitmap::from_iter([1, 2, 3]);
+        let disallowed = RoaringBitmap::from_iter([2]);
+        let filter = IndexFilter::new(Some(&allowed), Some(&disallowed));
+        let (ids, _) = index.query(&vectors[0], 5, &filter);
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|id| *id == 1 || *id == 3));
+    }
+
+    #[test]
+    fn it_supports_distance_functions_that_decompose() {
+        let tmp_dir = tempdir().unwrap();
+        let (n, d) = (500, 8);
+        let config = config(tmp_dir.path().to_str().unwrap(), 4);
+        for distance_function in [DistanceFunction::Hamming, DistanceFunction::Jaccard] {
+            let index_config = IndexConfig {
+                dimensionality: d as i32,
+                distance_function,
+            };
+            let result = PqIndex::init(&index_config, Some(&config), Uuid::new_v4());
+            assert_eq!(result.err().unwrap().code(), ErrorCodes::InvalidArgument);
+        }
+
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Manhattan,
+        };
+        let index = PqIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let vectors = random_vectors(n, d);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.add(i, vector);
+        }
+        index.save().unwrap();
+        assert!(index.is_trained());
+        let mut found = 0;
+        for (i, query) in vectors.iter().enumerate().take(50) {
+            let (ids, _) = index.query(query, 10, &IndexFilter::default());
+            if ids.contains(&i) {
+                found += 1;
+            }
+        }
+        assert!(found > 45, "found {} of 50", found);
+    }
+
+    #[test]
+    fn it_can_persist_and_load() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let (n, d) = (200, 8);
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Cosine,
+        };
+        let config = config(persist_path, 2);
+        let index = PqIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let vectors = random_vectors(n, d);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.add(i, vector);
+        }
+        index.save().unwrap();
+        index.delete(3);
+        // Added after training
+        index.add(n, &vectors[0]);
+        index.save().unwrap();
+
+        let loaded =
+            PqIndex::load_with_config(persist_path, &index_config, Some(&config), index.id)
+                .unwrap();
+        assert_eq!(loaded.len(), n);
+        assert_eq!(loaded.get(3), None);
+        assert_eq!(loaded.get(7), index.get(7));
+        let query = &vectors[10];
+        assert_eq!(
+            loaded.query(query, 5, &IndexFilter::default()),
+            index.query(query, 5, &IndexFilter::default())
+        );
+
+        // A different dimensionality, space or number of subquantizers is detected
+        let mismatched = [
+            (
+                IndexConfig {
+                    dimensionality: 4,
+                    distance_function: DistanceFunction::Cosine,
+                },
+                config.clone(),
+            ),
+            (
+                IndexConfig {
+                    dimensionality: d as i32,
+                    distance_function: DistanceFunction::Euclidean,
+                },
+                config.clone(),
+            ),
+            (index_config.clone(), self::config(persist_path, 4)),
+        ];
+        for (index_config, config) in mismatched.iter() {
+            let result =
+                PqIndex::load_with_config(persist_path, index_config, Some(config), index.id);
+            assert_eq!(result.err().unwrap().code(), ErrorCodes::FailedPrecondition);
+        }
+    }
+
+    #[test]
+    fn it_reuses_deleted_slots() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let (n, d) = (200, 8);
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let config = config(persist_path, 2);
+        let index = PqIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        let vectors = random_vectors(n, d);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.add(i, vector);
+        }
+        index.save().unwrap();
+        assert!(index.is_trained());
+
+        // Deleting and adding a label over and over keeps the number of slots
+        for round in 0..10 {
+            index.delete(round);
+            index.add(n + round, &vectors[round]);
+        }
+        assert_eq!(index.state.read().labels.len(), n);
+        assert_eq!(index.len(), n);
+        let (ids, _) = index.query(&vectors[0], 2, &IndexFilter::default());
+        assert!(ids.contains(&n) && !ids.contains(&0));
+
+        // Slots deleted before saving are reused after loading
+        index.delete(n);
+        index.save().unwrap();
+        let loaded =
+            PqIndex::load_with_config(persist_path, &index_config, Some(&config), index.id)
+                .unwrap();
+        loaded.add(2 * n, &vectors[0]);
+        assert_eq!(loaded.state.read().labels.len(), n);
+        assert_eq!(loaded.len(), n);
+        assert!(loaded.get(2 * n).is_some());
+    }
+
+    #[test]
+    fn it_rejects_counts_past_the_end_of_the_file() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let (n, d) = (200, 8);
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let config = config(persist_path, 2);
+        let index = PqIndex::init(&index_config, Some(&config), Uuid::new_v4()).unwrap();
+        for (i, vector) in random_vectors(n, d).iter().enumerate() {
+            index.add(i, vector);
+        }
+        index.save().unwrap();
+
+        let file_path = tmp_dir.path().join(PQ_INDEX_FILE);
+        let bytes = std::fs::read(&file_path).unwrap();
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        // Magic, version, dimensionality, space, subquantizers and centroids
+        let space_len = u32_at(12);
+        let num_centroids = u32_at(20 + space_len);
+        let count_offset = 24 + space_len + num_centroids * d * 4;
+        assert_eq!(
+            u64::from_le_bytes(bytes[count_offset..count_offset + 8].try_into().unwrap()),
+            n as u64
+        );
+        for count in [u64::MAX, n as u64 + 1] {
+            let mut corrupted = bytes.clone();
+            corrupted[count_offset..count_offset + 8].copy_from_slice(&count.to_le_bytes());
+            std::fs::write(&file_path, &corrupted).unwrap();
+            let result =
+                PqIndex::load_with_config(persist_path, &index_config, Some(&config), index.id);
+            assert_eq!(result.err().unwrap().code(), ErrorCodes::Internal);
+        }
+    }
+
+    #[test]
+    fn it_reads_the_config_from_segment_metadata() {
+        let tmp_dir = tempdir().unwrap();
+        let mut metadata = Metadata::new();
+        metadata.insert("pq:num_subquantizers".to_string(), MetadataValue::Int(8));
+        metadata.insert("pq:rerank_factor".to_string(), MetadataValue::Int(0));
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::PqDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(metadata),
+            file_path: HashMap::new(),
+        };
+        let config = PqIndexConfig::from_segment(&segment, tmp_dir.path()).unwrap();
+        assert_eq!(config.num_subquantizers, Some(8));
+        assert_eq!(config.num_centroids, MAX_NUM_CENTROIDS);
+        assert_eq!(config.rerank_factor, 0);
+
+        segment
+            .metadata
+            .as_mut()
+            .unwrap()
+            .insert("pq:num_centroids".to_string(), MetadataValue::Int(1000));
+        assert!(PqIndexConfig::from_segment(&segment, tmp_dir.path()).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use super::{
+    Index, IndexConfig, IndexConfigFromSegmentError, PqIndex, PqIndexConfig,
+    PqIndexFromSegmentError, PQ_INDEX_FILE,
+};
+use crate::errors::ErrorCodes;
+use crate::index::types::PersistentIndex;
+use crate::{errors::ChromaError, storage::Storage, types::Segment};
+use parking_lot::RwLock;
+use std::fmt::Debug;
+use std::path::Path;
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Loads product quantized indices from storage and caches them, see HnswIndexProvider.
+#[derive(Clone)]
+pub(crate) struct PqIndexProvider {
+    cache: Arc<RwLock<HashMap<Uuid, Arc<RwLock<PqIndex>>>>>,
+    pub(crate) temporary_storage_path: PathBuf,
+    storage: Storage,
+}
+
+impl Debug for PqIndexProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PqIndexProvider {{ temporary_storage_path: {:?}, cache: {} }}",
+            self.temporary_storage_path,
+            self.cache.read().len(),
+        )
+    }
+}
+
+impl PqIndexProvider {
+    pub(crate) fn new(storage: Storage, storage_path: PathBuf) -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            storage,
+            temporary_storage_path: storage_path,
+        }
+    }
+
+    pub(crate) fn get(&self, id: &Uuid) -> Option<Arc<RwLock<PqIndex>>> {
+        let cache = self.cache.read();
+        cache.get(id).cloned()
+    }
+
+    fn format_key(&self, id: &Uuid, file: &str) -> String {
+        format!("pq/{}/{}", id, file)
+    }
+
+    fn configs(
+        segment: &Segment,
+        dimensionality: i32,
+        index_storage_path: &Path,
+    ) -> Result<(IndexConfig, PqIndexConfig), PqIndexProviderConfigError> {
+        let index_config = match IndexConfig::from_segment(segment, dimensionality) {
+            Ok(index_config) => index_config,
+            Err(e) => return Err(PqIndexProviderConfigError::IndexConfigError(*e)),
+        };
+        let pq_config = match PqIndexConfig::from_segment(segment, index_storage_path) {
+            Ok(pq_config) => pq_config,
+            Err(e) => return Err(PqIndexProviderConfigError::PqConfigError(*e)),
+        };
+        Ok((index_config, pq_config))
+    }
+
+    /// Load the index with the given id from storage into a new index with a new id,
+    /// which can be written to without changing the source index.
+    pub(crate) async fn fork(
+        &self,
+        source_id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<Arc<RwLock<PqIndex>>, Box<PqIndexProviderError>> {
+        let new_id = Uuid::new_v4();
+        self.load(source_id, new_id, segment, dimensionality).await
+    }
+
+    pub(crate) async fn open(
+        &self,
+        id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<Arc<RwLock<PqIndex>>, Box<PqIndexProviderError>> {
+        self.load(id, *id, segment, dimensionality).await
+    }
+
+    async fn load(
+        &self,
+        source_id: &Uuid,
+        id: Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<Arc<RwLock<PqIndex>>, Box<PqIndexProviderError>> {
+        let index_storage_path = self.temporary_storage_path.join(id.to_string());
+        match std::fs::create_dir_all(&index_storage_path) {
+            Ok(_) => {}
+            Err(e) => return Err(Box::new(PqIndexProviderError::IOError(e))),
+        }
+        match self
+            .load_pq_file_into_directory(source_id, &index_storage_path)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let (index_config, pq_config) =
+            match PqIndexProvider::configs(segment, dimensionality, &index_storage_path) {
+                Ok(configs) => configs,
+                Err(e) => return Err(Box::new(PqIndexProviderError::ConfigError(e))),
+            };
+        match PqIndex::load_with_config(
+            &pq_config.persist_path,
+            &index_config,
+            Some(&pq_config),
+            id,
+        ) {
+            Ok(index) => {
+                let index = Arc::new(RwLock::new(index));
+                let mut cache = self.cache.write();
+                cache.insert(id, index.clone());
+                Ok(index)
+            }
+            Err(e) => Err(Box::new(PqIndexProviderError::IndexLoadError(e))),
+        }
+    }
+
+    async fn load_pq_file_into_directory(
+        &self,
+        source_id: &Uuid,
+        index_storage_path: &Path,
+    ) -> Result<(), Box<PqIndexProviderError>> {
+        let key = self.format_key(source_id, PQ_INDEX_FILE);
+        println!("Loading pq index file: {}", key);
+        let mut reader = match self.storage.get(&key).await {
+            Ok(reader) => reader,
+            Err(e) => {
+                println!("Failed to load pq index file from storage: {}", e);
+                return Err(Box::new(PqIndexProviderError::StorageGetError(e)));
+            }
+        };
+        let file_path = index_storage_path.join(PQ_INDEX_FILE);
+        let mut file_handle = match tokio::fs::File::create(&file_path).await {
+            Ok(file) => file,
+            Err(e) => return Err(Box::new(PqIndexProviderError::IOError(e))),
+        };
+        match tokio::io::copy(&mut reader, &mut file_handle).await {
+            Ok(_) => {
+                println!("Loaded pq index file: {}", key);
+                Ok(())
+            }
+            Err(e) => Err(Box::new(PqIndexProviderError::IOError(e))),
+        }
+    }
+
+    pub(crate) fn create(
+        &self,
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<Arc<RwLock<PqIndex>>, Box<PqIndexProviderError>> {
+        let id = Uuid::new_v4();
+        let index_storage_path = self.temporary_storage_path.join(id.to_string());
+        match std::fs::create_dir_all(&index_storage_path) {
+            Ok(_) => {}
+            Err(e) => return Err(Box::new(PqIndexProviderError::IOError(e))),
+        }
+
+        let (index_config, pq_config) =
+            match PqIndexProvider::configs(segment, dimensionality, &index_storage_path) {
+                Ok(configs) => configs,
+                Err(e) => return Err(Box::new(PqIndexProviderError::ConfigError(e))),
+            };
+        let index = match PqIndex::init(&index_config, Some(&pq_config), id) {
+            Ok(index) => index,
+            Err(e) => return Err(Box::new(PqIndexProviderError::IndexInitError(e))),
+        };
+        let index = Arc::new(RwLock::new(index));
+        let mut cache = self.cache.write();
+        cache.insert(id, index.clone());
+        Ok(index)
+    }
+
+    /// Save the index, training its codebooks if it has enough vectors to.
+    pub(crate) fn commit(&self, id: &Uuid) -> Result<(), Box<PqIndexProviderError>> {
+        let index = match self.get(id) {
+            Some(index) => index,
+            None => return Err(Box::new(PqIndexProviderError::NoIndexFound(*id))),
+        };
+        let res = index.write().save();
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(PqIndexProviderError::PqSaveError(e))),
+        }
+    }
+
+    pub(crate) async fn flush(&self, id: &Uuid) -> Result<(), Box<PqIndexProviderError>> {
+        if self.get(id).is_none() {
+            return Err(Box::new(PqIndexProviderError::NoIndexFound(*id)));
+        }
+        let file_path = self
+            .temporary_storage_path
+            .join(id.to_string())
+            .join(PQ_INDEX_FILE);
+        let file_path = match file_path.to_str() {
+            Some(file_path) => file_path.to_string(),
+            None => return Err(Box::new(PqIndexProviderError::PathToStringError(file_path))),
+        };
+        let key = self.format_key(id, PQ_INDEX_FILE);
+        match self.storage.put_file(&key, &file_path).await {
+            Ok(_) => {
+                println!("Flushed pq index file: {}", key);
+                Ok(())
+            }
+            Err(e) => Err(Box::new(PqIndexProviderError::StoragePutError(e))),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum PqIndexProviderConfigError {
+    #[error("Index configuration error")]
+    IndexConfigError(#[from] IndexConfigFromSegmentError),
+    #[error("PQ config error")]
+    PqConfigError(#[from] PqIndexFromSegmentError),
+}
+
+impl ChromaError for PqIndexProviderConfigError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            PqIndexProviderConfigError::IndexConfigError(e) => e.code(),
+            PqIndexProviderConfigError::PqConfigError(e) => e.code(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum PqIndexProviderError {
+    #[error("No index found for id: {0}")]
+    NoIndexFound(Uuid),
+    #[error("Configuration error")]
+    ConfigError(#[from] PqIndexProviderConfigError),
+    #[error("IO Error")]
+    IOError(#[from] std::io::Error),
+    #[error("Path: {0} could not be converted to string")]
+    PathToStringError(PathBuf),
+    #[error("Storage Get Error")]
+    StorageGetError(#[from] crate::storage::GetError),
+    #[error("Storage Put Error")]
+    StoragePutError(#[from] crate::storage::PutError),
+    #[error("Index init error")]
+    IndexInitError(Box<dyn ChromaError>),
+    #[error("Index load error")]
+    IndexLoadError(Box<dyn ChromaError>),
+    #[error("PQ Save Error")]
+    PqSaveError(Box<dyn ChromaError>),
+}
+
+impl ChromaError for PqIndexProviderError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            PqIndexProviderError::NoIndexFound(_) => ErrorCodes::NotFound,
+            PqIndexProviderError::ConfigError(e) => e.code(),
+            PqIndexProviderError::IOError(_) => ErrorCodes::Internal,
+            PqIndexProviderError::PathToStringError(_) => ErrorCodes::InvalidArgument,
+            PqIndexProviderError::StorageGetError(e) => e.code(),
+            PqIndexProviderError::StoragePutError(e) => e.code(),
+            PqIndexProviderError::IndexInitError(e) => e.code(),
+            PqIndexProviderError::IndexLoadError(e) => e.code(),
+            PqIndexProviderError::PqSaveError(e) => e.code(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local::LocalStorage;
+    use crate::types::SegmentType;
+
+    #[tokio::test]
+    async fn test_commit_flush_and_fork() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let pq_tmp_path = storage_dir.path().join("pq_tmp");
+        std::fs::create_dir_all(&pq_tmp_path).unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let provider = PqIndexProvider::new(storage, pq_tmp_path);
+        let segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::PqDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+
+        let created_index = provider.create(&segment, 4).unwrap();
+        let created_index_id = created_index.read().id;
+        created_index.read().add(1, &[1.0, 2.0, 3.0, 4.0]);
+        provider.commit(&created_index_id).unwrap();
+        provider.flush(&created_index_id).await.unwrap();
+
+        let forked_index = provider.fork(&created_index_id, &segment, 4).await.unwrap();
+        let forked_index_id = forked_index.read().id;
+        assert_ne!(created_index_id, forked_index_id);
+        assert_eq!(forked_index.read().get(1), Some(vec![1.0, 2.0, 3.0, 4.0]));
+
+        // Writing to the fork leaves the source alone
+        forked_index.read().add(2, &[4.0, 3.0, 2.0, 1.0]);
+        provider.commit(&forked_index_id).unwrap();
+        provider.flush(&forked_index_id).await.unwrap();
+        let opened_index = provider.open(&created_index_id, &segment, 4).await.unwrap();
+        assert_eq!(opened_index.read().len(), 1);
+
+        // An index with a different dimensionality can not be opened
+        let result = provider.open(&forked_index_id, &segment, 8).await;
+        assert_eq!(result.err().unwrap().code(), ErrorCodes::FailedPrecondition);
+    }
+}
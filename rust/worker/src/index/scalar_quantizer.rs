@@ -0,0 +1,176 @@
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use thiserror::Error;
+
+/// The configuration of int8 scalar quantization, see `ScalarQuantizer`.
+/// # Fields
+/// - `rerank_factor` - Queries take rerank_factor * k candidates by their quantized
+///   distance and rerank them with the full vectors.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ScalarQuantizationConfig {
+    pub(crate) rerank_factor: usize,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum ScalarQuantizerError {
+    #[error("Quantizer has {0} minimums but {1} maximums")]
+    MismatchedBounds(usize, usize),
+    #[error("Quantizer has dimensionality {0}, expected {1}")]
+    InvalidDimensionality(usize, usize),
+}
+
+impl ChromaError for ScalarQuantizerError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::FailedPrecondition
+    }
+}
+
+/// Quantizes vectors to int8, with a range per dimension.
+/// # Description
+/// Each dimension is mapped linearly from [min, max] to [-127, 127], values outside
+/// the range are clamped. Distances between quantized vectors are computed with the
+/// int8 kernels of `DistanceFunction::distance_int8`, and are only approximate, so
+/// callers should rerank the closest candidates with their full vectors.
+/// The ranges are trained once from a sample of the vectors, and stored with the
+/// segment so that the vectors written later are quantized the same way.
+/// # Notes
+/// Shifting a dimension preserves differences, but not inner products. For inner
+/// product and cosine distances every dimension has the same range, centred on zero,
+/// so that the inner product of quantized vectors is the inner product of the vectors
+/// scaled by a constant.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ScalarQuantizer {
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+impl ScalarQuantizer {
+    pub(crate) fn new(min: Vec<f32>, max: Vec<f32>) -> Result<Self, ScalarQuantizerError> {
+        if min.len() != max.len() {
+            return Err(ScalarQuantizerError::MismatchedBounds(min.len(), max.len()));
+        }
+        Ok(ScalarQuantizer { min, max })
+    }
+
+    /// Train the range of each dimension from the flattened points, for computing the
+    /// distance function between quantized vectors.
+    pub(crate) fn train(
+        points: &[f32],
+        dimensionality: usize,
+        distance_function: &DistanceFunction,
+    ) -> Self {
+        // Without points every dimension quantizes to 0
+        if points.is_empty() {
+            return ScalarQuantizer {
+                min: vec![0.0; dimensionality],
+                max: vec![0.0; dimensionality],
+            };
+        }
+        match distance_function {
+            DistanceFunction::InnerProduct | DistanceFunction::Cosine => {
+                let bound = points
+                    .iter()
+                    .fold(0.0_f32, |bound, value| bound.max(value.abs()));
+                ScalarQuantizer {
+                    min: vec![-bound; dimensionality],
+                    max: vec![bound; dimensionality],
+                }
+            }
+            _ => {
+                let mut min = vec![f32::INFINITY; dimensionality];
+                let mut max = vec![f32::NEG_INFINITY; dimensionality];
+                for point in points.chunks_exact(dimensionality) {
+                    for (i, value) in point.iter().enumerate() {
+                        min[i] = min[i].min(*value);
+                        max[i] = max[i].max(*value);
+                    }
+                }
+                ScalarQuantizer { min, max }
+            }
+        }
+    }
+
+    pub(crate) fn dimensionality(&self) -> usize {
+        self.min.len()
+    }
+
+    pub(crate) fn min(&self) -> &[f32] {
+        &self.min
+    }
+
+    pub(crate) fn max(&self) -> &[f32] {
+        &self.max
+    }
+
+    pub(crate) fn quantize(&self, vector: &[f32]) -> Vec<i8> {
+        vector
+            .iter()
+            .zip(self.min.iter().zip(self.max.iter()))
+            .map(|(value, (min, max))| {
+                let half_range = (max - min) / 2.0;
+                if half_range <= 0.0 {
+                    return 0;
+                }
+                let scaled = (value - (min + half_range)) / half_range * 127.0;
+                scaled.round().clamp(-127.0, 127.0) as i8
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::DistanceFunction;
+
+    #[test]
+    fn it_quantizes_each_dimension_to_its_range() {
+        let points = vec![0.0, -1.0, 5.0, 1.0, 1.0, 5.0];
+        let quantizer = ScalarQuantizer::train(&points, 3, &DistanceFunction::Euclidean);
+        assert_eq!(quantizer.min(), &[0.0, -1.0, 5.0]);
+        assert_eq!(quantizer.max(), &[1.0, 1.0, 5.0]);
+
+        assert_eq!(quantizer.quantize(&[0.0, 1.0, 5.0]), vec![-127, 127, 0]);
+        assert_eq!(quantizer.quantize(&[0.5, 0.0, 7.0]), vec![0, 0, 0]);
+        // Values outside of the range are clamped
+        assert_eq!(quantizer.quantize(&[2.0, -3.0, 1.0]), vec![127, -127, 0]);
+
+        assert!(ScalarQuantizer::new(vec![0.0], vec![1.0, 2.0]).is_err());
+    }
+
+    #[test]
+    fn it_preserves_the_order_of_distances() {
+        for distance_function in [DistanceFunction::Euclidean, DistanceFunction::InnerProduct] {
+            let quantizer = ScalarQuantizer::train(&[-1.0, -1.0, 1.0, 1.0], 2, &distance_function);
+            let query = quantizer.quantize(&[0.5, 0.5]);
+            let near = quantizer.quantize(&[0.4, 0.6]);
+            let far = quantizer.quantize(&[-0.5, -0.5]);
+            assert!(
+                distance_function.distance_int8(&query, &near)
+                    < distance_function.distance_int8(&query, &far)
+            );
+        }
+    }
+
+    #[test]
+    fn it_centres_inner_products_on_zero() {
+        // The dimensions have different ranges, which an affine mapping would shift
+        // and scale apart
+        let points = vec![0.0, 2.0, 1.0, 4.0, 0.5, 3.0];
+        let quantizer = ScalarQuantizer::train(&points, 2, &DistanceFunction::InnerProduct);
+        assert_eq!(quantizer.min(), &[-4.0, -4.0]);
+        assert_eq!(quantizer.max(), &[4.0, 4.0]);
+        assert_eq!(quantizer.quantize(&[0.0, -4.0]), vec![0, -127]);
+
+        // The dot product of [1, 4] with the query is larger than that of [0, 2], so
+        // [1, 4] is closer by inner product distance
+        let query = quantizer.quantize(&[1.0, 1.0]);
+        let near = quantizer.quantize(&[1.0, 4.0]);
+        let far = quantizer.quantize(&[0.0, 2.0]);
+        let distance_function = DistanceFunction::InnerProduct;
+        assert!(
+            distance_function.distance_int8(&query, &near)
+                < distance_function.distance_int8(&query, &far)
+        );
+    }
+}
@@ -1,6 +1,8 @@
-use crate::distance::{DistanceFunction, DistanceFunctionError};
+use crate::distance::DistanceFunction;
 use crate::errors::{ChromaError, ErrorCodes};
-use crate::types::{MetadataValue, Segment};
+use crate::segment::schema::{distance_function_from_segment, SegmentConfigError};
+use crate::types::Segment;
+use roaring::RoaringBitmap;
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -12,14 +14,14 @@ pub(crate) struct IndexConfig {
 
 #[derive(Error, Debug)]
 pub(crate) enum IndexConfigFromSegmentError {
-    #[error("Invalid distance function")]
-    InvalidDistanceFunction(#[from] DistanceFunctionError),
+    #[error(transparent)]
+    InvalidConfig(#[from] SegmentConfigError),
 }
 
 impl ChromaError for IndexConfigFromSegmentError {
     fn code(&self) -> ErrorCodes {
         match self {
-            IndexConfigFromSegmentError::InvalidDistanceFunction(_) => ErrorCodes::InvalidArgument,
+            IndexConfigFromSegmentError::InvalidConfig(e) => e.code(),
         }
     }
 }
@@ -29,25 +31,55 @@ impl IndexConfig {
         segment: &Segment,
         dimensionality: i32,
     ) -> Result<Self, Box<IndexConfigFromSegmentError>> {
-        let space = match segment.metadata {
-            Some(ref metadata) => match metadata.get("hnsw:space") {
-                Some(MetadataValue::Str(space)) => space,
-                _ => "l2",
-            },
-            None => "l2",
-        };
-        match DistanceFunction::try_from(space) {
+        match distance_function_from_segment(segment) {
             Ok(distance_function) => Ok(IndexConfig {
                 dimensionality,
                 distance_function,
             }),
-            Err(e) => Err(Box::new(
-                IndexConfigFromSegmentError::InvalidDistanceFunction(e),
-            )),
+            Err(e) => Err(Box::new(IndexConfigFromSegmentError::InvalidConfig(e))),
         }
     }
 }
 
+/// The filter of a query over the ids of an index.
+/// # Fields
+/// - `allowed` - If set, only these ids may be returned.
+/// - `disallowed` - If set, these ids may not be returned.
+/// # Notes
+/// Ids are matched as u32, the offset ids of the record segment. The bitmaps are
+/// borrowed so that filters matching many records are not copied per query.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct IndexFilter<'a> {
+    pub(crate) allowed: Option<&'a RoaringBitmap>,
+    pub(crate) disallowed: Option<&'a RoaringBitmap>,
+}
+
+impl<'a> IndexFilter<'a> {
+    pub(crate) fn new(
+        allowed: Option<&'a RoaringBitmap>,
+        disallowed: Option<&'a RoaringBitmap>,
+    ) -> Self {
+        IndexFilter {
+            allowed,
+            disallowed,
+        }
+    }
+
+    /// Whether the filter lets every id through.
+    pub(crate) fn is_unfiltered(&self) -> bool {
+        self.allowed.is_none() && self.disallowed.map_or(true, |ids| ids.is_empty())
+    }
+
+    pub(crate) fn allows(&self, id: usize) -> bool {
+        let id = match u32::try_from(id) {
+            Ok(id) => id,
+            Err(_) => return self.allowed.is_none(),
+        };
+        self.allowed.map_or(true, |ids| ids.contains(id))
+            && !self.disallowed.map_or(false, |ids| ids.contains(id))
+    }
+}
+
 /// The index trait.
 /// # Description
 /// This trait defines the interface for a KNN index.
@@ -55,7 +87,8 @@ impl IndexConfig {
 /// - `init` - Initialize the index with a given dimension and distance function.
 /// - `add` - Add a vector to the index.
 /// - `delete` - Delete a vector from the index.
-/// - `query` - Query the index for the K nearest neighbors of a given vector.
+/// - `query` - Query the index for the K nearest neighbors of a given vector, among the
+///   ids the filter allows.
 pub(crate) trait Index<C> {
     fn init(
         index_config: &IndexConfig,
@@ -66,13 +99,7 @@ pub(crate) trait Index<C> {
         Self: Sized;
     fn add(&self, id: usize, vector: &[f32]);
     fn delete(&self, id: usize);
-    fn query(
-        &self,
-        vector: &[f32],
-        k: usize,
-        allowed_ids: &[usize],
-        disallow_ids: &[usize],
-    ) -> (Vec<usize>, Vec<f32>);
+    fn query(&self, vector: &[f32], k: usize, filter: &IndexFilter) -> (Vec<usize>, Vec<f32>);
     fn get(&self, id: usize) -> Option<Vec<f32>>;
 }
 
@@ -84,7 +111,8 @@ pub(crate) trait Index<C> {
 /// - `load` - Load the index from a given path.
 /// # Notes
 /// This defines a rudimentary interface for saving and loading indices.
-/// TODO: Right now load() takes IndexConfig because we don't implement save/load of the config.
+/// save() persists the config the index was built with alongside the index, and load()
+/// validates the given IndexConfig against it.
 pub(crate) trait PersistentIndex<C>: Index<C> {
     fn save(&self) -> Result<(), Box<dyn ChromaError>>;
     fn load(path: &str, index_config: &IndexConfig, id: Uuid) -> Result<Self, Box<dyn ChromaError>>
@@ -1,3 +1,5 @@
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
 use rand::Rng;
 
 pub(super) fn generate_random_data(n: usize, d: usize) -> Vec<f32> {
@@ -11,3 +13,77 @@ pub(super) fn generate_random_data(n: usize, d: usize) -> Vec<f32> {
     }
     return data;
 }
+
+pub(super) fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm = 1.0 / (norm + 1e-30);
+    vector.iter().map(|x| x * norm).collect()
+}
+
+pub(super) fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+// The index of the closest of the flattened centroids
+pub(super) fn closest_centroid(centroids: &[f32], vector: &[f32]) -> usize {
+    let mut closest = 0;
+    let mut closest_distance = f32::INFINITY;
+    for (i, centroid) in centroids.chunks_exact(vector.len()).enumerate() {
+        let distance = squared_l2(centroid, vector);
+        if distance < closest_distance {
+            closest = i;
+            closest_distance = distance;
+        }
+    }
+    closest
+}
+
+// Lloyd's k-means over the flattened points. Centroids start at a random sample of the
+// points, and a centroid that loses all its points stays where it is.
+pub(super) fn kmeans(
+    points: &[f32],
+    dimensionality: usize,
+    k: usize,
+    iterations: usize,
+    rng: &mut StdRng,
+) -> Vec<f32> {
+    let num_points = points.len() / dimensionality;
+    let mut centroids = Vec::with_capacity(k * dimensionality);
+    for i in sample(rng, num_points, k).iter() {
+        centroids.extend_from_slice(&points[i * dimensionality..(i + 1) * dimensionality]);
+    }
+    let mut assignments = vec![usize::MAX; num_points];
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (i, point) in points.chunks_exact(dimensionality).enumerate() {
+            let closest = closest_centroid(&centroids, point);
+            if assignments[i] != closest {
+                assignments[i] = closest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+        let mut sums = vec![0.0; k * dimensionality];
+        let mut counts = vec![0; k];
+        for (point, cluster) in points.chunks_exact(dimensionality).zip(&assignments) {
+            counts[*cluster] += 1;
+            let sum = &mut sums[cluster * dimensionality..(cluster + 1) * dimensionality];
+            for (sum, value) in sum.iter_mut().zip(point) {
+                *sum += value;
+            }
+        }
+        for cluster in 0..k {
+            if counts[cluster] == 0 {
+                continue;
+            }
+            let centroid = &mut centroids[cluster * dimensionality..(cluster + 1) * dimensionality];
+            let sum = &sums[cluster * dimensionality..(cluster + 1) * dimensionality];
+            for (centroid, sum) in centroid.iter_mut().zip(sum) {
+                *centroid = sum / counts[cluster] as f32;
+            }
+        }
+    }
+    centroids
+}
@@ -171,3 +171,72 @@ pub async fn compaction_service_entrypoint() {
     };
     println!("Server stopped");
 }
+
+pub async fn recall_eval_entrypoint() {
+    let args = match execution::recall_eval::RecallEvalArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+
+    // Check if the config path is set in the env var
+    let config = match std::env::var(CONFIG_PATH_ENV_VAR) {
+        Ok(config_path) => config::RootConfig::load_from_path(&config_path),
+        Err(_) => config::RootConfig::load(),
+    };
+
+    let config = config.query_service;
+
+    let sysdb = match sysdb::from_config(&config.sysdb).await {
+        Ok(sysdb) => sysdb,
+        Err(err) => {
+            println!("Failed to create sysdb component: {:?}", err);
+            return;
+        }
+    };
+    let storage = match storage::from_config(&config.storage).await {
+        Ok(storage) => storage,
+        Err(err) => {
+            println!("Failed to create storage component: {:?}", err);
+            return;
+        }
+    };
+    // TODO: real path
+    let hnsw_index_provider = index::hnsw_provider::HnswIndexProvider::new(
+        storage.clone(),
+        std::path::PathBuf::from("~/tmp"),
+        config.hnsw_cache.capacity_bytes,
+    );
+    let blockfile_provider = blockstore::provider::BlockfileProvider::new_arrow(storage);
+
+    let reports =
+        match execution::recall_eval::run(&args, sysdb, hnsw_index_provider, &blockfile_provider)
+            .await
+        {
+            Ok(reports) => reports,
+            Err(err) => {
+                println!("Failed to evaluate recall: {}", err);
+                return;
+            }
+        };
+    println!(
+        "{:>8} {:>10} {:>12} {:>12} {:>12}",
+        "ef",
+        format!("recall@{}", args.k),
+        "p50 (us)",
+        "p95 (us)",
+        "p99 (us)"
+    );
+    for report in reports {
+        println!(
+            "{:>8} {:>10.4} {:>12} {:>12} {:>12}",
+            report.ef,
+            report.recall,
+            report.p50.as_micros(),
+            report.p95.as_micros(),
+            report.p99.as_micros()
+        );
+    }
+}
@@ -1,17 +1,20 @@
+use super::record_segment::{RecordSegmentReader, RecordSegmentReaderCreationError};
+use super::schema::{HnswSegmentConfig, SegmentConfigError};
 use super::{SegmentFlusher, SegmentWriter};
+use crate::blockstore::provider::BlockfileProvider;
+use crate::distance::DistanceFunction;
 use crate::errors::{ChromaError, ErrorCodes};
 use crate::index::hnsw_provider::{
     HnswIndexProvider, HnswIndexProviderCommitError, HnswIndexProviderCreateError,
     HnswIndexProviderFlushError, HnswIndexProviderForkError, HnswIndexProviderOpenError,
 };
 use crate::index::{
-    HnswIndex, HnswIndexConfig, HnswIndexFromSegmentError, Index, IndexConfig,
-    IndexConfigFromSegmentError,
+    HnswIndex, HnswIndexConfig, HnswIndexFromSegmentError, HnswSearchParams, Index, IndexConfig,
+    IndexConfigFromSegmentError, IndexFilter, ScalarQuantizationConfig,
 };
-use crate::types::{LogRecord, Operation, Segment};
+use crate::types::{LogRecord, Operation, Segment, MAX_MULTI_VECTOR_LEN};
 use async_trait::async_trait;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::sync::Arc;
 use thiserror::Error;
@@ -19,11 +22,27 @@ use uuid::Uuid;
 
 const HNSW_INDEX: &str = "hnsw_index";
 
+// The vectors of a multi-vector embedding are labelled with the offset id of their
+// record in the high bits and their position in the low bits
+const MULTI_VECTOR_POSITION_BITS: u32 = MAX_MULTI_VECTOR_LEN.trailing_zeros();
+
+pub(crate) fn multi_vector_label(offset_id: u32, position: usize) -> usize {
+    ((offset_id as usize) << MULTI_VECTOR_POSITION_BITS) | position
+}
+
+pub(crate) fn multi_vector_offset_id(label: usize) -> u32 {
+    (label >> MULTI_VECTOR_POSITION_BITS) as u32
+}
+
 #[derive(Clone)]
 pub(crate) struct DistributedHNSWSegmentWriter {
-    index: Arc<RwLock<HnswIndex>>,
+    index: Arc<HnswIndex>,
     hnsw_index_provider: HnswIndexProvider,
     pub(crate) id: Uuid,
+    // Whether the index holds the vectors of multi-vector embeddings
+    multi_vector: bool,
+    // Whether the index is int8 quantized, its quantizer is trained on the first chunk
+    quantization: Option<ScalarQuantizationConfig>,
 }
 
 impl Debug for DistributedHNSWSegmentWriter {
@@ -44,12 +63,20 @@ pub enum DistributedHNSWSegmentFromSegmentError {
     IndexConfigError(#[from] IndexConfigFromSegmentError),
     #[error("HNSW index configuration error")]
     HnswIndexConfigError(#[from] HnswIndexFromSegmentError),
+    #[error("HNSW segment configuration error")]
+    SegmentConfigError(#[from] SegmentConfigError),
     #[error("HNSW index provider open error")]
     HnswIndexProviderOpenError(#[from] HnswIndexProviderOpenError),
     #[error("HNSW index provider fork error")]
     HnswIndexProviderForkError(#[from] HnswIndexProviderForkError),
     #[error("HNSW index provider create error")]
     HnswIndexProviderCreateError(#[from] HnswIndexProviderCreateError),
+    #[error("Record segment reader error")]
+    RecordSegmentReaderError(#[from] RecordSegmentReaderCreationError),
+    #[error("Error rebuilding the index from the record segment")]
+    RebuildError(#[from] Box<dyn ChromaError>),
+    #[error("Index rebuild task failed")]
+    RebuildTaskError(#[from] tokio::task::JoinError),
 }
 
 impl ChromaError for DistributedHNSWSegmentFromSegmentError {
@@ -60,30 +87,61 @@ impl ChromaError for DistributedHNSWSegmentFromSegmentError {
             DistributedHNSWSegmentFromSegmentError::Uninitialized => ErrorCodes::InvalidArgument,
             DistributedHNSWSegmentFromSegmentError::IndexConfigError(e) => e.code(),
             DistributedHNSWSegmentFromSegmentError::HnswIndexConfigError(e) => e.code(),
+            DistributedHNSWSegmentFromSegmentError::SegmentConfigError(e) => e.code(),
             DistributedHNSWSegmentFromSegmentError::HnswIndexProviderOpenError(e) => e.code(),
             DistributedHNSWSegmentFromSegmentError::HnswIndexProviderForkError(e) => e.code(),
             DistributedHNSWSegmentFromSegmentError::HnswIndexProviderCreateError(e) => e.code(),
+            DistributedHNSWSegmentFromSegmentError::RecordSegmentReaderError(e) => e.code(),
+            DistributedHNSWSegmentFromSegmentError::RebuildError(e) => e.code(),
+            DistributedHNSWSegmentFromSegmentError::RebuildTaskError(_) => ErrorCodes::Internal,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum DistributedHNSWSegmentWriteError {
+    #[error("Multi-vector of record {0} has dimension {1}, the index has dimension {2}")]
+    MultiVectorDimensionMismatch(String, usize, usize),
+}
+
+impl ChromaError for DistributedHNSWSegmentWriteError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            DistributedHNSWSegmentWriteError::MultiVectorDimensionMismatch(_, _, _) => {
+                ErrorCodes::InvalidArgument
+            }
         }
     }
 }
 
 impl DistributedHNSWSegmentWriter {
     pub(crate) fn new(
-        index: Arc<RwLock<HnswIndex>>,
+        index: Arc<HnswIndex>,
         hnsw_index_provider: HnswIndexProvider,
         id: Uuid,
+        multi_vector: bool,
+        quantization: Option<ScalarQuantizationConfig>,
     ) -> Self {
         return DistributedHNSWSegmentWriter {
             index,
             hnsw_index_provider,
             id,
+            multi_vector,
+            quantization,
         };
     }
 
+    /// Fork the index of the segment, or create one if the segment has none.
+    /// # Notes
+    /// When the deleted ratio of the forked index reaches the vacuum threshold of the
+    /// segment, a new index is built from the live vectors in the record segment instead,
+    /// which purges the deleted elements. The fork is removed with its temporary files.
     pub(crate) async fn from_segment(
         segment: &Segment,
         dimensionality: usize,
         hnsw_index_provider: HnswIndexProvider,
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
     ) -> Result<Box<DistributedHNSWSegmentWriter>, Box<DistributedHNSWSegmentFromSegmentError>>
     {
         let index_config = match IndexConfig::from_segment(&segment, dimensionality as i32) {
@@ -104,6 +162,15 @@ impl DistributedHNSWSegmentWriter {
                 ));
             }
         };
+        let segment_config = match HnswSegmentConfig::from_segment(segment) {
+            Ok(segment_config) => segment_config,
+            Err(e) => {
+                return Err(Box::new(
+                    DistributedHNSWSegmentFromSegmentError::SegmentConfigError(e),
+                ));
+            }
+        };
+        let multi_vector = segment_config.multi_vector;
 
         // TODO: this is hacky, we use the presence of files to determine if we need to load or create the index
         // ideally, an explicit state would be better. When we implement distributed HNSW segments,
@@ -137,7 +204,7 @@ impl DistributedHNSWSegmentWriter {
                 }
             };
 
-            let index = match hnsw_index_provider
+            let mut index = match hnsw_index_provider
                 .fork(&index_uuid, segment, dimensionality as i32)
                 .await
             {
@@ -149,10 +216,33 @@ impl DistributedHNSWSegmentWriter {
                 }
             };
 
+            let deleted_ratio = index.deleted_ratio();
+            if deleted_ratio >= segment_config.vacuum_threshold {
+                tracing::info!(
+                    "Rebuilding hnsw index {} with {:.2} of its elements deleted",
+                    index_uuid,
+                    deleted_ratio
+                );
+                let fork_id = index.id;
+                drop(index);
+                hnsw_index_provider.remove(&fork_id);
+                index = DistributedHNSWSegmentWriter::rebuild(
+                    segment,
+                    dimensionality,
+                    &hnsw_index_provider,
+                    record_segment,
+                    blockfile_provider,
+                    multi_vector,
+                )
+                .await?;
+            }
+
             Ok(Box::new(DistributedHNSWSegmentWriter::new(
                 index,
                 hnsw_index_provider,
                 segment.id,
+                multi_vector,
+                segment_config.quantization,
             )))
         } else {
             let index = match hnsw_index_provider.create(segment, dimensionality as i32) {
@@ -167,33 +257,242 @@ impl DistributedHNSWSegmentWriter {
                 index,
                 hnsw_index_provider,
                 segment.id,
+                multi_vector,
+                segment_config.quantization,
             )))
         }
     }
 }
 
+impl DistributedHNSWSegmentWriter {
+    // Create an index holding only the live vectors in the record segment, with the
+    // labels the forked index had for them
+    async fn rebuild(
+        segment: &Segment,
+        dimensionality: usize,
+        hnsw_index_provider: &HnswIndexProvider,
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
+        multi_vector: bool,
+    ) -> Result<Arc<HnswIndex>, Box<DistributedHNSWSegmentFromSegmentError>> {
+        let record_segment_reader =
+            match RecordSegmentReader::from_segment(record_segment, blockfile_provider).await {
+                Ok(reader) => reader,
+                Err(e) => {
+                    return Err(Box::new(
+                        DistributedHNSWSegmentFromSegmentError::RecordSegmentReaderError(*e),
+                    ));
+                }
+            };
+        let offset_ids = match record_segment_reader.get_all_offset_ids().await {
+            Ok(offset_ids) => offset_ids,
+            Err(e) => {
+                return Err(Box::new(
+                    DistributedHNSWSegmentFromSegmentError::RebuildError(e),
+                ));
+            }
+        };
+        let mut vectors = Vec::with_capacity(offset_ids.len());
+        for offset_id in offset_ids {
+            match record_segment_reader
+                .get_data_for_offset_id(offset_id)
+                .await
+            {
+                Ok(data) if multi_vector => {
+                    if let Some(multi_embedding) = data.multi_embedding.as_ref() {
+                        for (position, vector) in multi_embedding.iter().enumerate() {
+                            vectors
+                                .push((multi_vector_label(offset_id, position), vector.to_vec()));
+                        }
+                    }
+                }
+                Ok(data) => vectors.push((offset_id as usize, data.embedding.to_vec())),
+                Err(e) => {
+                    return Err(Box::new(
+                        DistributedHNSWSegmentFromSegmentError::RebuildError(e),
+                    ));
+                }
+            }
+        }
+
+        let index = match hnsw_index_provider.create(segment, dimensionality as i32) {
+            Ok(index) => index,
+            Err(e) => {
+                return Err(Box::new(
+                    DistributedHNSWSegmentFromSegmentError::HnswIndexProviderCreateError(*e),
+                ))
+            }
+        };
+        // Building the graph is cpu bound, keep it off the async runtime
+        let building_index = index.clone();
+        let build_res = tokio::task::spawn_blocking(move || {
+            building_index.reserve(vectors.len())?;
+            for (offset_id, vector) in vectors.iter() {
+                building_index.add(*offset_id, vector);
+            }
+            Ok::<(), Box<dyn ChromaError>>(())
+        })
+        .await;
+        match build_res {
+            Ok(Ok(_)) => Ok(index),
+            Ok(Err(e)) => Err(Box::new(
+                DistributedHNSWSegmentFromSegmentError::RebuildError(e),
+            )),
+            Err(e) => Err(Box::new(
+                DistributedHNSWSegmentFromSegmentError::RebuildTaskError(e),
+            )),
+        }
+    }
+}
+
+impl DistributedHNSWSegmentWriter {
+    // Grow the index up front if the adds would not fit, hnswlib can not add
+    // past its capacity.
+    fn reserve(&self, num_adds: usize) -> Result<(), Box<dyn ChromaError>> {
+        if num_adds == 0 {
+            return Ok(());
+        }
+        let index = &self.index;
+        let capacity = index.capacity();
+        let new_capacity = index.reserve(num_adds)?;
+        if new_capacity > capacity {
+            tracing::info!(
+                "Resized hnsw index {} from {} to {} elements",
+                index.id,
+                capacity,
+                new_capacity
+            );
+        }
+        Ok(())
+    }
+
+    // Quantize an index that is configured to be quantized but has no quantizer yet,
+    // with a quantizer trained on the embeddings of the chunk. The quantizer is saved
+    // with the index, so every later chunk is quantized the same way.
+    fn train_quantizer(
+        &self,
+        records: &crate::execution::data::data_chunk::Chunk<super::MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        if self.quantization.is_none() || self.index.quantizer().is_some() {
+            return Ok(());
+        }
+        let vectors: Vec<&[f32]> = records
+            .iter()
+            .filter(|record| record.0.log_record.record.operation != Operation::Delete)
+            .filter_map(|record| record.0.log_record.record.embedding.as_deref())
+            .collect();
+        if vectors.is_empty() {
+            return Ok(());
+        }
+        match self.index.train_quantizer(&vectors) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Index every vector of the multi-vector embeddings. A record whose multi-vector
+    // shrinks or is deleted has the vectors past its new length removed. The chunk is
+    // rejected before anything is indexed if a multi-vector does not match the
+    // dimensionality of the index.
+    fn apply_multi_vector_log_chunk(
+        &self,
+        records: crate::execution::data::data_chunk::Chunk<super::MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        let index = &self.index;
+        for record in records.iter() {
+            if record.0.log_record.record.operation == Operation::Delete {
+                continue;
+            }
+            if let Some(multi_embedding) = record.0.materialized_record.multi_embedding.as_ref() {
+                if multi_embedding.dimension != index.dimensionality() {
+                    return Err(Box::new(
+                        DistributedHNSWSegmentWriteError::MultiVectorDimensionMismatch(
+                            record.0.log_record.record.id.clone(),
+                            multi_embedding.dimension,
+                            index.dimensionality(),
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let num_adds = records
+            .iter()
+            .filter_map(|record| match record.0.log_record.record.operation {
+                Operation::Delete => None,
+                _ => record
+                    .0
+                    .materialized_record
+                    .multi_embedding
+                    .as_ref()
+                    .map(|multi_embedding| multi_embedding.len()),
+            })
+            .sum();
+        self.reserve(num_adds)?;
+
+        for record in records.iter() {
+            let segment_offset_id = record.0.segment_offset_id;
+            let previous_len = record
+                .0
+                .previous_record
+                .as_ref()
+                .and_then(|previous| previous.multi_embedding.as_ref())
+                .map_or(0, |multi_embedding| multi_embedding.len());
+            let len = match record.0.log_record.record.operation {
+                Operation::Delete => 0,
+                _ => match record.0.materialized_record.multi_embedding.as_ref() {
+                    Some(multi_embedding) => {
+                        for (position, vector) in multi_embedding.iter().enumerate() {
+                            index.add(multi_vector_label(segment_offset_id, position), vector);
+                        }
+                        multi_embedding.len()
+                    }
+                    // The record has no multi-vector, or an update left it unchanged
+                    None => continue,
+                },
+            };
+            for position in len..previous_len {
+                index.delete(multi_vector_label(segment_offset_id, position));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl SegmentWriter for DistributedHNSWSegmentWriter {
     fn apply_materialized_log_chunk(
         &self,
         records: crate::execution::data::data_chunk::Chunk<super::MaterializedLogRecord>,
-    ) {
+    ) -> Result<(), Box<dyn ChromaError>> {
+        if self.multi_vector {
+            return self.apply_multi_vector_log_chunk(records);
+        }
+
+        // Upserts may replace existing elements, so this may reserve more room
+        // than needed.
+        let num_adds = records
+            .iter()
+            .filter(|record| match record.0.log_record.record.operation {
+                Operation::Add | Operation::Upsert => true,
+                _ => false,
+            })
+            .count();
+        self.reserve(num_adds)?;
+        self.train_quantizer(&records)?;
+
         for record in records.iter() {
             match record.0.log_record.record.operation {
                 Operation::Add => {
                     let segment_offset_id = record.0.segment_offset_id;
                     let embedding = record.0.log_record.record.embedding.as_ref().unwrap();
-                    self.index
-                        .read()
-                        .add(segment_offset_id as usize, &embedding);
+                    self.index.add(segment_offset_id as usize, &embedding);
                 }
                 Operation::Upsert => {
                     // hnsw index behavior is to treat add() as upsert
                     let segment_offset_id = record.0.segment_offset_id;
                     // Assumption: Upserts must have embedding set
                     let embedding = record.0.log_record.record.embedding.as_ref().unwrap();
-                    self.index
-                        .read()
-                        .add(segment_offset_id as usize, &embedding);
+                    self.index.add(segment_offset_id as usize, &embedding);
                 }
                 Operation::Update => {
                     // hnsw index behvaior is to treat add() as upsert so this
@@ -203,7 +502,7 @@ impl SegmentWriter for DistributedHNSWSegmentWriter {
                     let segment_offset_id = record.0.segment_offset_id;
                     match record.0.log_record.record.embedding.as_ref() {
                         Some(e) => {
-                            self.index.read().add(segment_offset_id as usize, &e);
+                            self.index.add(segment_offset_id as usize, &e);
                         }
                         None => {
                             // An update may not necessarily update the embedding
@@ -215,18 +514,22 @@ impl SegmentWriter for DistributedHNSWSegmentWriter {
                     // The assumption is that materialized log records only contain
                     // valid deletes
                     let segment_offset_id = record.0.segment_offset_id;
-                    self.index.read().delete(segment_offset_id as usize);
+                    self.index.delete(segment_offset_id as usize);
                 }
             }
         }
+        Ok(())
     }
 
-    fn apply_log_chunk(&self, records: crate::execution::data::data_chunk::Chunk<LogRecord>) {
+    fn apply_log_chunk(
+        &self,
+        records: crate::execution::data::data_chunk::Chunk<LogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
         todo!()
     }
 
     fn commit(self) -> Result<impl SegmentFlusher, Box<dyn ChromaError>> {
-        let hnsw_index_id = self.index.read().id;
+        let hnsw_index_id = self.index.id;
         let res = self.hnsw_index_provider.commit(&hnsw_index_id);
         match res {
             Ok(_) => Ok(self),
@@ -238,7 +541,7 @@ impl SegmentWriter for DistributedHNSWSegmentWriter {
 #[async_trait]
 impl SegmentFlusher for DistributedHNSWSegmentWriter {
     async fn flush(self) -> Result<HashMap<String, Vec<String>>, Box<dyn ChromaError>> {
-        let hnsw_index_id = self.index.read().id;
+        let hnsw_index_id = self.index.id;
         match self.hnsw_index_provider.flush(&hnsw_index_id).await {
             Ok(_) => {}
             Err(e) => return Err(e),
@@ -251,9 +554,11 @@ impl SegmentFlusher for DistributedHNSWSegmentWriter {
 
 #[derive(Clone)]
 pub(crate) struct DistributedHNSWSegmentReader {
-    index: Arc<RwLock<HnswIndex>>,
+    index: Arc<HnswIndex>,
     hnsw_index_provider: HnswIndexProvider,
     pub(crate) id: Uuid,
+    pub(crate) multi_vector: bool,
+    quantization: Option<ScalarQuantizationConfig>,
 }
 
 impl Debug for DistributedHNSWSegmentReader {
@@ -264,14 +569,18 @@ impl Debug for DistributedHNSWSegmentReader {
 
 impl DistributedHNSWSegmentReader {
     fn new(
-        index: Arc<RwLock<HnswIndex>>,
+        index: Arc<HnswIndex>,
         hnsw_index_provider: HnswIndexProvider,
         id: Uuid,
+        multi_vector: bool,
+        quantization: Option<ScalarQuantizationConfig>,
     ) -> Self {
         return DistributedHNSWSegmentReader {
             index,
             hnsw_index_provider,
             id,
+            multi_vector,
+            quantization,
         };
     }
 
@@ -291,6 +600,15 @@ impl DistributedHNSWSegmentReader {
             }
         };
         let persist_path = &hnsw_index_provider.temporary_storage_path;
+        let segment_config = match HnswSegmentConfig::from_segment(segment) {
+            Ok(segment_config) => segment_config,
+            Err(e) => {
+                return Err(Box::new(
+                    DistributedHNSWSegmentFromSegmentError::SegmentConfigError(e),
+                ));
+            }
+        };
+        let multi_vector = segment_config.multi_vector;
 
         // TODO: this is hacky, we use the presence of files to determine if we need to load or create the index
         // ideally, an explicit state would be better. When we implement distributed HNSW segments,
@@ -324,22 +642,27 @@ impl DistributedHNSWSegmentReader {
                 }
             };
 
-            let index = match hnsw_index_provider
-                .open(&index_uuid, segment, dimensionality as i32)
-                .await
-            {
-                Ok(index) => index,
-                Err(e) => {
-                    return Err(Box::new(
-                        DistributedHNSWSegmentFromSegmentError::HnswIndexProviderOpenError(*e),
-                    ))
-                }
+            let index = match hnsw_index_provider.get(&index_uuid) {
+                Some(index) => index,
+                None => match hnsw_index_provider
+                    .open(&index_uuid, segment, dimensionality as i32)
+                    .await
+                {
+                    Ok(index) => index,
+                    Err(e) => {
+                        return Err(Box::new(
+                            DistributedHNSWSegmentFromSegmentError::HnswIndexProviderOpenError(*e),
+                        ))
+                    }
+                },
             };
 
             Ok(Box::new(DistributedHNSWSegmentReader::new(
                 index,
                 hnsw_index_provider,
                 segment.id,
+                multi_vector,
+                segment_config.quantization,
             )))
         } else {
             return Err(Box::new(
@@ -348,14 +671,287 @@ impl DistributedHNSWSegmentReader {
         }
     }
 
+    /// The k nearest neighbors of the vector. A quantized index returns
+    /// rerank_factor() * k candidates by their quantized distance.
     pub(crate) fn query(
         &self,
         vector: &[f32],
         k: usize,
-        allowed_ids: &[usize],
-        disallowd_ids: &[usize],
+        filter: &IndexFilter,
+        params: &HnswSearchParams,
     ) -> (Vec<usize>, Vec<f32>) {
-        let index = self.index.read();
-        index.query(vector, k, allowed_ids, disallowd_ids)
+        let num_candidates = k * self.rerank_factor().max(1);
+        self.index
+            .query_with_params(vector, num_candidates, filter, params)
+    }
+
+    /// The number of candidates per result a query returns to rerank with the full
+    /// vectors, 0 if the index is not quantized.
+    pub(crate) fn rerank_factor(&self) -> usize {
+        match &self.quantization {
+            Some(quantization) if self.index.quantizer().is_some() => quantization.rerank_factor,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn distance_function(&self) -> DistanceFunction {
+        self.index.distance_function().clone()
+    }
+
+    /// Find the records with the k nearest vectors of a multi-vector index to the query
+    /// vector. Each record is returned once, with the distance of its nearest vector.
+    /// # Notes
+    /// The filter of the index can not express the labels of the vectors, so hits are
+    /// filtered by record and the search widened until k records are found or every
+    /// vector has been searched.
+    pub(crate) fn query_multi_vector(
+        &self,
+        vector: &[f32],
+        k: usize,
+        filter: &IndexFilter,
+        params: &HnswSearchParams,
+    ) -> (Vec<usize>, Vec<f32>) {
+        let index = &self.index;
+        let len = index.len();
+        let mut search_k = k;
+        loop {
+            let (labels, distances) =
+                index.query_with_params(vector, search_k, &IndexFilter::default(), params);
+            let mut seen = HashSet::new();
+            let mut offset_ids = Vec::with_capacity(k);
+            let mut offset_id_distances = Vec::with_capacity(k);
+            // Hits are sorted by distance, so the first hit of a record is its nearest
+            for (label, distance) in labels.iter().zip(distances.iter()) {
+                let offset_id = multi_vector_offset_id(*label);
+                if !filter.allows(offset_id as usize) || !seen.insert(offset_id) {
+                    continue;
+                }
+                offset_ids.push(offset_id as usize);
+                offset_id_distances.push(*distance);
+                if offset_ids.len() == k {
+                    break;
+                }
+            }
+            if offset_ids.len() == k || search_k >= len {
+                return (offset_ids, offset_id_distances);
+            }
+            search_k = (search_k * 2).min(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::data::data_chunk::Chunk;
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::LogMaterializer;
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{MetadataValue, MultiVector, OperationRecord, SegmentScope, SegmentType};
+
+    fn log_record(log_offset: i64, id: usize, operation: Operation) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: format!("embedding_id_{}", id),
+                embedding: match operation {
+                    Operation::Add => Some(vec![id as f32, 1.0]),
+                    _ => None,
+                },
+                encoding: None,
+                sparse_embedding: None,
+                multi_embedding: None,
+                metadata: None,
+                document: None,
+                operation,
+            },
+        }
+    }
+
+    // Compact the log into the segments, returning the index that was written
+    async fn compact(
+        log_records: Vec<LogRecord>,
+        record_segment: &mut Segment,
+        hnsw_segment: &mut Segment,
+        blockfile_provider: &BlockfileProvider,
+        hnsw_index_provider: &HnswIndexProvider,
+    ) -> Arc<HnswIndex> {
+        let log_records: Chunk<LogRecord> = Chunk::new(log_records.into());
+        let record_segment_writer =
+            RecordSegmentWriter::from_segment(record_segment, blockfile_provider)
+                .await
+                .unwrap();
+        let hnsw_segment_writer = DistributedHNSWSegmentWriter::from_segment(
+            hnsw_segment,
+            2,
+            hnsw_index_provider.clone(),
+            record_segment,
+            blockfile_provider,
+        )
+        .await
+        .unwrap();
+        let materialized = record_segment_writer
+            .materialize(&log_records)
+            .await
+            .unwrap();
+        hnsw_segment_writer
+            .apply_materialized_log_chunk(materialized)
+            .unwrap();
+        let index = hnsw_segment_writer.index.clone();
+        record_segment.file_path = record_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+        hnsw_segment.file_path = hnsw_segment_writer.commit().unwrap().flush().await.unwrap();
+        index
+    }
+
+    #[tokio::test]
+    async fn it_rebuilds_the_index_past_the_vacuum_threshold() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let hnsw_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider =
+            HnswIndexProvider::new(storage, hnsw_dir.path().to_path_buf(), 1 << 30);
+        let mut record_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::Record,
+            scope: SegmentScope::RECORD,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut hnsw_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::HnswDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(HashMap::from([
+                (
+                    "hnsw:implementation".to_string(),
+                    MetadataValue::Str("native".to_string()),
+                ),
+                (
+                    "hnsw:vacuum_threshold".to_string(),
+                    MetadataValue::Float(0.5),
+                ),
+            ])),
+            file_path: HashMap::new(),
+        };
+
+        let adds = (0..10)
+            .map(|id| log_record(id as i64, id, Operation::Add))
+            .collect();
+        compact(
+            adds,
+            &mut record_segment,
+            &mut hnsw_segment,
+            &blockfile_provider,
+            &hnsw_index_provider,
+        )
+        .await;
+        let deletes = (0..6)
+            .map(|id| log_record(10 + id as i64, id, Operation::Delete))
+            .collect();
+        let index = compact(
+            deletes,
+            &mut record_segment,
+            &mut hnsw_segment,
+            &blockfile_provider,
+            &hnsw_index_provider,
+        )
+        .await;
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.len_with_deleted(), 10);
+
+        // The next compaction rebuilds the index from the live vectors, and discards
+        // the fork along with its files
+        let index = compact(
+            vec![log_record(16, 10, Operation::Add)],
+            &mut record_segment,
+            &mut hnsw_segment,
+            &blockfile_provider,
+            &hnsw_index_provider,
+        )
+        .await;
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.len_with_deleted(), 5);
+        // The directories of the first two indices and the rebuilt one
+        assert_eq!(std::fs::read_dir(hnsw_dir.path()).unwrap().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_multi_vectors_of_another_dimension() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let hnsw_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider =
+            HnswIndexProvider::new(storage, hnsw_dir.path().to_path_buf(), 1 << 30);
+        let record_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::Record,
+            scope: SegmentScope::RECORD,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let hnsw_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::HnswDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(HashMap::from([
+                (
+                    "hnsw:implementation".to_string(),
+                    MetadataValue::Str("native".to_string()),
+                ),
+                (
+                    "hnsw:vectors".to_string(),
+                    MetadataValue::Str("multi_vector".to_string()),
+                ),
+            ])),
+            file_path: HashMap::new(),
+        };
+
+        let log_records: Chunk<LogRecord> = Chunk::new(
+            [(0, 2), (1, 3)]
+                .into_iter()
+                .map(|(id, dimension)| LogRecord {
+                    log_offset: id as i64,
+                    record: OperationRecord {
+                        multi_embedding: Some(
+                            MultiVector::new(dimension, vec![1.0; 2 * dimension]).unwrap(),
+                        ),
+                        ..log_record(id as i64, id, Operation::Add).record
+                    },
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        let record_segment_writer =
+            RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let hnsw_segment_writer = DistributedHNSWSegmentWriter::from_segment(
+            &hnsw_segment,
+            2,
+            hnsw_index_provider,
+            &record_segment,
+            &blockfile_provider,
+        )
+        .await
+        .unwrap();
+        let materialized = record_segment_writer
+            .materialize(&log_records)
+            .await
+            .unwrap();
+        let res = hnsw_segment_writer.apply_materialized_log_chunk(materialized);
+        assert_eq!(res.unwrap_err().code(), ErrorCodes::InvalidArgument);
+        // Nothing in the chunk is indexed
+        assert_eq!(hnsw_segment_writer.index.len(), 0);
     }
 }
@@ -0,0 +1,388 @@
+use super::{DataRecord, MaterializedLogRecord, SegmentFlusher, SegmentWriter};
+use crate::blockstore::provider::{BlockfileProvider, CreateError, OpenError};
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::execution::data::data_chunk::Chunk;
+use crate::index::{
+    Index, IndexConfig, IndexConfigFromSegmentError, IndexFilter, IvfIndex, IvfIndexConfig,
+    IvfIndexFlusher, IvfIndexFromSegmentError, IvfIndexReader, IvfIndexWriter,
+};
+use crate::types::{Operation, Segment};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use thiserror::Error;
+use uuid::Uuid;
+
+const IVF_CENTROIDS: &str = "ivf_centroids";
+const IVF_LISTS: &str = "ivf_lists";
+const IVF_ASSIGNMENTS: &str = "ivf_assignments";
+
+#[derive(Error, Debug)]
+pub enum DistributedIvfSegmentFromSegmentError {
+    #[error("IVF segment uninitialized")]
+    Uninitialized,
+    #[error("Missing file: {0}")]
+    MissingFile(String),
+    #[error("Invalid Uuid for file: {0}")]
+    InvalidUuid(String),
+    #[error("Blockfile Creation Error")]
+    BlockfileCreateError(#[from] Box<CreateError>),
+    #[error("Blockfile Open Error")]
+    BlockfileOpenError(#[from] Box<OpenError>),
+    #[error("Index configuration error")]
+    IndexConfigError(#[from] IndexConfigFromSegmentError),
+    #[error("IVF config error")]
+    IvfConfigError(#[from] IvfIndexFromSegmentError),
+    #[error("Index load error")]
+    IndexLoadError(Box<dyn ChromaError>),
+}
+
+impl ChromaError for DistributedIvfSegmentFromSegmentError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            DistributedIvfSegmentFromSegmentError::Uninitialized => ErrorCodes::InvalidArgument,
+            DistributedIvfSegmentFromSegmentError::MissingFile(_) => ErrorCodes::NotFound,
+            DistributedIvfSegmentFromSegmentError::InvalidUuid(_) => ErrorCodes::InvalidArgument,
+            DistributedIvfSegmentFromSegmentError::BlockfileCreateError(e) => e.code(),
+            DistributedIvfSegmentFromSegmentError::BlockfileOpenError(e) => e.code(),
+            DistributedIvfSegmentFromSegmentError::IndexConfigError(e) => e.code(),
+            DistributedIvfSegmentFromSegmentError::IvfConfigError(e) => e.code(),
+            DistributedIvfSegmentFromSegmentError::IndexLoadError(e) => e.code(),
+        }
+    }
+}
+
+// The ids of the centroids, lists and assignments blockfiles, None if the segment
+// has no files yet
+fn blockfile_ids_from_segment(
+    segment: &Segment,
+) -> Result<Option<(Uuid, Uuid, Uuid)>, Box<DistributedIvfSegmentFromSegmentError>> {
+    if segment.file_path.is_empty() {
+        return Ok(None);
+    }
+    let blockfile_id = |file: &str| {
+        let id = match segment.file_path.get(file) {
+            Some(ids) if !ids.is_empty() => &ids[0],
+            _ => {
+                return Err(Box::new(
+                    DistributedIvfSegmentFromSegmentError::MissingFile(file.to_string()),
+                ))
+            }
+        };
+        match Uuid::parse_str(id) {
+            Ok(id) => Ok(id),
+            Err(_) => Err(Box::new(
+                DistributedIvfSegmentFromSegmentError::InvalidUuid(file.to_string()),
+            )),
+        }
+    };
+    Ok(Some((
+        blockfile_id(IVF_CENTROIDS)?,
+        blockfile_id(IVF_LISTS)?,
+        blockfile_id(IVF_ASSIGNMENTS)?,
+    )))
+}
+
+fn configs_from_segment(
+    segment: &Segment,
+    dimensionality: usize,
+) -> Result<(IndexConfig, IvfIndexConfig), Box<DistributedIvfSegmentFromSegmentError>> {
+    let index_config = match IndexConfig::from_segment(segment, dimensionality as i32) {
+        Ok(index_config) => index_config,
+        Err(e) => {
+            return Err(Box::new(
+                DistributedIvfSegmentFromSegmentError::IndexConfigError(*e),
+            ))
+        }
+    };
+    match IvfIndexConfig::from_segment(segment) {
+        Ok(ivf_config) => Ok((index_config, ivf_config)),
+        Err(e) => Err(Box::new(
+            DistributedIvfSegmentFromSegmentError::IvfConfigError(*e),
+        )),
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct DistributedIvfSegmentWriter {
+    index_writer: IvfIndexWriter,
+    pub(crate) id: Uuid,
+}
+
+impl Debug for DistributedIvfSegmentWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DistributedIvfSegmentWriter")
+    }
+}
+
+impl DistributedIvfSegmentWriter {
+    pub(crate) async fn from_segment(
+        segment: &Segment,
+        dimensionality: usize,
+        blockfile_provider: &BlockfileProvider,
+    ) -> Result<Box<DistributedIvfSegmentWriter>, Box<DistributedIvfSegmentFromSegmentError>> {
+        let (index_config, ivf_config) = configs_from_segment(segment, dimensionality)?;
+        let index = match IvfIndex::init(&index_config, Some(&ivf_config), Uuid::new_v4()) {
+            Ok(index) => index,
+            Err(e) => {
+                return Err(Box::new(
+                    DistributedIvfSegmentFromSegmentError::IndexLoadError(e),
+                ))
+            }
+        };
+
+        let index_writer = match blockfile_ids_from_segment(segment)? {
+            None => {
+                println!("No files found, creating new blockfiles for IVF segment");
+                let centroids_writer = blockfile_provider.create::<u32, &DataRecord>();
+                let lists_writer = blockfile_provider.create::<u32, &DataRecord>();
+                let assignments_writer = blockfile_provider.create::<u32, u32>();
+                match (centroids_writer, lists_writer, assignments_writer) {
+                    (Ok(centroids_writer), Ok(lists_writer), Ok(assignments_writer)) => {
+                        IvfIndexWriter::new(
+                            index,
+                            centroids_writer,
+                            lists_writer,
+                            assignments_writer,
+                            None,
+                            None,
+                        )
+                    }
+                    (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                        return Err(Box::new(
+                            DistributedIvfSegmentFromSegmentError::BlockfileCreateError(e),
+                        ))
+                    }
+                }
+            }
+            Some((centroids_id, lists_id, assignments_id)) => {
+                println!("Found files, forking blockfiles for IVF segment");
+                let centroids_reader = match blockfile_provider
+                    .open::<u32, DataRecord>(&centroids_id)
+                    .await
+                {
+                    Ok(reader) => reader,
+                    Err(e) => {
+                        return Err(Box::new(
+                            DistributedIvfSegmentFromSegmentError::BlockfileOpenError(e),
+                        ))
+                    }
+                };
+                match index.load_centroids(&centroids_reader).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Err(Box::new(
+                            DistributedIvfSegmentFromSegmentError::IndexLoadError(e),
+                        ))
+                    }
+                }
+
+                let centroids_writer = blockfile_provider
+                    .fork::<u32, &DataRecord>(&centroids_id)
+                    .await;
+                let lists_writer = blockfile_provider.fork::<u32, &DataRecord>(&lists_id).await;
+                let assignments_writer = blockfile_provider.fork::<u32, u32>(&assignments_id).await;
+                let (centroids_writer, lists_writer, assignments_writer) =
+                    match (centroids_writer, lists_writer, assignments_writer) {
+                        (Ok(centroids_writer), Ok(lists_writer), Ok(assignments_writer)) => {
+                            (centroids_writer, lists_writer, assignments_writer)
+                        }
+                        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                            return Err(Box::new(
+                                DistributedIvfSegmentFromSegmentError::BlockfileCreateError(e),
+                            ))
+                        }
+                    };
+
+                let lists_reader = blockfile_provider.open::<u32, DataRecord>(&lists_id).await;
+                let assignments_reader = blockfile_provider.open::<u32, u32>(&assignments_id).await;
+                let (lists_reader, assignments_reader) = match (lists_reader, assignments_reader) {
+                    (Ok(lists_reader), Ok(assignments_reader)) => {
+                        (lists_reader, assignments_reader)
+                    }
+                    (Err(e), _) | (_, Err(e)) => {
+                        return Err(Box::new(
+                            DistributedIvfSegmentFromSegmentError::BlockfileOpenError(e),
+                        ))
+                    }
+                };
+                IvfIndexWriter::new(
+                    index,
+                    centroids_writer,
+                    lists_writer,
+                    assignments_writer,
+                    Some(lists_reader),
+                    Some(assignments_reader),
+                )
+            }
+        };
+
+        Ok(Box::new(DistributedIvfSegmentWriter {
+            index_writer,
+            id: segment.id,
+        }))
+    }
+
+    /// Write the buffered changes to the blockfiles, must be called before commit.
+    pub(crate) async fn write_to_blockfiles(&mut self) -> Result<(), Box<dyn ChromaError>> {
+        self.index_writer.write_to_blockfiles().await
+    }
+
+    pub(crate) fn commit_index(self) -> Result<DistributedIvfSegmentFlusher, Box<dyn ChromaError>> {
+        match self.index_writer.commit() {
+            Ok(index_flusher) => Ok(DistributedIvfSegmentFlusher { index_flusher }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl SegmentWriter for DistributedIvfSegmentWriter {
+    fn apply_materialized_log_chunk(
+        &self,
+        records: Chunk<MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        for record in records.iter() {
+            let segment_offset_id = record.0.segment_offset_id;
+            match record.0.log_record.record.operation {
+                // Adds replace, and updates without an embedding leave the vector alone
+                Operation::Add | Operation::Upsert | Operation::Update => {
+                    match record.0.log_record.record.embedding.as_ref() {
+                        Some(embedding) => self.index_writer.add(segment_offset_id, embedding),
+                        None => continue,
+                    }
+                }
+                Operation::Delete => {
+                    self.index_writer.delete(segment_offset_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(self) -> Result<impl SegmentFlusher, Box<dyn ChromaError>> {
+        self.commit_index()
+    }
+}
+
+pub(crate) struct DistributedIvfSegmentFlusher {
+    index_flusher: IvfIndexFlusher,
+}
+
+impl Debug for DistributedIvfSegmentFlusher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DistributedIvfSegmentFlusher")
+    }
+}
+
+#[async_trait]
+impl SegmentFlusher for DistributedIvfSegmentFlusher {
+    async fn flush(self) -> Result<HashMap<String, Vec<String>>, Box<dyn ChromaError>> {
+        let centroids_id = self.index_flusher.centroids_id();
+        let lists_id = self.index_flusher.lists_id();
+        let assignments_id = self.index_flusher.assignments_id();
+        match self.index_flusher.flush().await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        let mut flushed_files = HashMap::new();
+        flushed_files.insert(IVF_CENTROIDS.to_string(), vec![centroids_id.to_string()]);
+        flushed_files.insert(IVF_LISTS.to_string(), vec![lists_id.to_string()]);
+        flushed_files.insert(
+            IVF_ASSIGNMENTS.to_string(),
+            vec![assignments_id.to_string()],
+        );
+        Ok(flushed_files)
+    }
+}
+
+/// Reads an IVF segment. The blockfiles are opened per query, and a query only loads
+/// the lists it probes.
+#[derive(Clone)]
+pub(crate) struct DistributedIvfSegmentReader {
+    centroids_id: Uuid,
+    lists_id: Uuid,
+    index_config: IndexConfig,
+    ivf_config: IvfIndexConfig,
+    blockfile_provider: BlockfileProvider,
+    pub(crate) id: Uuid,
+}
+
+impl Debug for DistributedIvfSegmentReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DistributedIvfSegmentReader")
+    }
+}
+
+impl DistributedIvfSegmentReader {
+    pub(crate) async fn from_segment(
+        segment: &Segment,
+        dimensionality: usize,
+        blockfile_provider: &BlockfileProvider,
+    ) -> Result<Box<DistributedIvfSegmentReader>, Box<DistributedIvfSegmentFromSegmentError>> {
+        let (centroids_id, lists_id, _) = match blockfile_ids_from_segment(segment)? {
+            Some(ids) => ids,
+            None => {
+                return Err(Box::new(
+                    DistributedIvfSegmentFromSegmentError::Uninitialized,
+                ))
+            }
+        };
+        let (index_config, ivf_config) = configs_from_segment(segment, dimensionality)?;
+        Ok(Box::new(DistributedIvfSegmentReader {
+            centroids_id,
+            lists_id,
+            index_config,
+            ivf_config,
+            blockfile_provider: blockfile_provider.clone(),
+            id: segment.id,
+        }))
+    }
+
+    /// The k nearest neighbors among the vectors in the nprobe lists closest to the
+    /// vector, nprobe defaults to the segment's configuration. A quantized segment
+    /// returns rerank_factor() * k candidates by their quantized distance.
+    pub(crate) async fn query(
+        &self,
+        vector: &[f32],
+        k: usize,
+        nprobe: Option<usize>,
+        filter: &IndexFilter<'_>,
+    ) -> Result<(Vec<usize>, Vec<f32>), Box<dyn ChromaError>> {
+        let index = IvfIndex::init(&self.index_config, Some(&self.ivf_config), self.lists_id)?;
+        let centroids_reader = match self
+            .blockfile_provider
+            .open::<u32, DataRecord>(&self.centroids_id)
+            .await
+        {
+            Ok(reader) => reader,
+            Err(e) => return Err(e),
+        };
+        index.load_centroids(&centroids_reader).await?;
+        let lists_reader = match self
+            .blockfile_provider
+            .open::<u32, DataRecord>(&self.lists_id)
+            .await
+        {
+            Ok(reader) => reader,
+            Err(e) => return Err(e),
+        };
+        let reader = IvfIndexReader::new(index, lists_reader);
+        let nprobe = nprobe.unwrap_or(self.ivf_config.nprobe);
+        let num_candidates = k * self.rerank_factor().max(1);
+        reader.query(vector, num_candidates, nprobe, filter).await
+    }
+
+    /// The number of candidates per result a query returns to rerank with the full
+    /// vectors, 0 if the segment is not quantized.
+    pub(crate) fn rerank_factor(&self) -> usize {
+        match &self.ivf_config.quantization {
+            Some(quantization) => quantization.rerank_factor,
+            None => 0,
+        }
+    }
+
+    pub(crate) fn distance_function(&self) -> DistanceFunction {
+        self.index_config.distance_function.clone()
+    }
+}
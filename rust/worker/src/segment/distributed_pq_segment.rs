@@ -0,0 +1,215 @@
+use super::{SegmentFlusher, SegmentWriter};
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::pq_provider::{PqIndexProvider, PqIndexProviderError};
+use crate::index::{Index, IndexFilter, PqIndex};
+use crate::types::{Operation, Segment};
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+const PQ_INDEX: &str = "pq_index";
+
+#[derive(Error, Debug)]
+pub enum DistributedPQSegmentFromSegmentError {
+    #[error("No PQ file found for segment")]
+    NoPqFileFound,
+    #[error("PQ file id not a valid uuid")]
+    InvalidUUID,
+    #[error("PQ segment uninitialized")]
+    Uninitialized,
+    #[error("PQ index provider error")]
+    PqIndexProviderError(#[from] PqIndexProviderError),
+}
+
+impl ChromaError for DistributedPQSegmentFromSegmentError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            DistributedPQSegmentFromSegmentError::NoPqFileFound => ErrorCodes::NotFound,
+            DistributedPQSegmentFromSegmentError::InvalidUUID => ErrorCodes::InvalidArgument,
+            DistributedPQSegmentFromSegmentError::Uninitialized => ErrorCodes::InvalidArgument,
+            DistributedPQSegmentFromSegmentError::PqIndexProviderError(e) => e.code(),
+        }
+    }
+}
+
+// The id of the index the segment files point to, None if the segment has no files yet
+fn index_id_from_segment(
+    segment: &Segment,
+) -> Result<Option<Uuid>, Box<DistributedPQSegmentFromSegmentError>> {
+    if segment.file_path.is_empty() {
+        return Ok(None);
+    }
+    let index_id = match segment.file_path.get(PQ_INDEX) {
+        Some(files) if !files.is_empty() => &files[0],
+        _ => {
+            return Err(Box::new(
+                DistributedPQSegmentFromSegmentError::NoPqFileFound,
+            ))
+        }
+    };
+    match Uuid::parse_str(index_id) {
+        Ok(uuid) => Ok(Some(uuid)),
+        Err(_) => Err(Box::new(DistributedPQSegmentFromSegmentError::InvalidUUID)),
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct DistributedPQSegmentWriter {
+    index: Arc<RwLock<PqIndex>>,
+    pq_index_provider: PqIndexProvider,
+    pub(crate) id: Uuid,
+}
+
+impl Debug for DistributedPQSegmentWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DistributedPQSegmentWriter")
+    }
+}
+
+impl DistributedPQSegmentWriter {
+    pub(crate) async fn from_segment(
+        segment: &Segment,
+        dimensionality: usize,
+        pq_index_provider: PqIndexProvider,
+    ) -> Result<Box<DistributedPQSegmentWriter>, Box<DistributedPQSegmentFromSegmentError>> {
+        let index = match index_id_from_segment(segment)? {
+            Some(index_id) => {
+                println!("Loading PQ index from files");
+                pq_index_provider
+                    .fork(&index_id, segment, dimensionality as i32)
+                    .await
+            }
+            None => pq_index_provider.create(segment, dimensionality as i32),
+        };
+        match index {
+            Ok(index) => Ok(Box::new(DistributedPQSegmentWriter {
+                index,
+                pq_index_provider,
+                id: segment.id,
+            })),
+            Err(e) => Err(Box::new(
+                DistributedPQSegmentFromSegmentError::PqIndexProviderError(*e),
+            )),
+        }
+    }
+}
+
+impl SegmentWriter for DistributedPQSegmentWriter {
+    fn apply_materialized_log_chunk(
+        &self,
+        records: crate::execution::data::data_chunk::Chunk<super::MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        for record in records.iter() {
+            let segment_offset_id = record.0.segment_offset_id as usize;
+            match record.0.log_record.record.operation {
+                // Adds replace, and updates without an embedding leave the vector alone
+                Operation::Add | Operation::Upsert | Operation::Update => {
+                    match record.0.log_record.record.embedding.as_ref() {
+                        Some(embedding) => self.index.read().add(segment_offset_id, embedding),
+                        None => continue,
+                    }
+                }
+                Operation::Delete => {
+                    self.index.read().delete(segment_offset_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(self) -> Result<impl SegmentFlusher, Box<dyn ChromaError>> {
+        let pq_index_id = self.index.read().id;
+        match self.pq_index_provider.commit(&pq_index_id) {
+            Ok(_) => Ok(self),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl SegmentFlusher for DistributedPQSegmentWriter {
+    async fn flush(self) -> Result<HashMap<String, Vec<String>>, Box<dyn ChromaError>> {
+        let pq_index_id = self.index.read().id;
+        match self.pq_index_provider.flush(&pq_index_id).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        let mut flushed_files = HashMap::new();
+        flushed_files.insert(PQ_INDEX.to_string(), vec![pq_index_id.to_string()]);
+        Ok(flushed_files)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct DistributedPQSegmentReader {
+    index: Arc<RwLock<PqIndex>>,
+    pub(crate) id: Uuid,
+}
+
+impl Debug for DistributedPQSegmentReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DistributedPQSegmentReader")
+    }
+}
+
+impl DistributedPQSegmentReader {
+    pub(crate) async fn from_segment(
+        segment: &Segment,
+        dimensionality: usize,
+        pq_index_provider: PqIndexProvider,
+    ) -> Result<Box<DistributedPQSegmentReader>, Box<DistributedPQSegmentFromSegmentError>> {
+        let index_id = match index_id_from_segment(segment)? {
+            Some(index_id) => index_id,
+            None => {
+                return Err(Box::new(
+                    DistributedPQSegmentFromSegmentError::Uninitialized,
+                ))
+            }
+        };
+        let index = match pq_index_provider.get(&index_id) {
+            Some(index) => index,
+            None => match pq_index_provider
+                .open(&index_id, segment, dimensionality as i32)
+                .await
+            {
+                Ok(index) => index,
+                Err(e) => {
+                    return Err(Box::new(
+                        DistributedPQSegmentFromSegmentError::PqIndexProviderError(*e),
+                    ))
+                }
+            },
+        };
+        Ok(Box::new(DistributedPQSegmentReader {
+            index,
+            id: segment.id,
+        }))
+    }
+
+    /// The k nearest neighbors by approximate distance. With reranking enabled,
+    /// rerank_factor * k candidates are returned for the caller to rerank by their
+    /// exact distance, computed from the full vectors in the record segment.
+    pub(crate) fn query(
+        &self,
+        vector: &[f32],
+        k: usize,
+        filter: &IndexFilter,
+    ) -> (Vec<usize>, Vec<f32>) {
+        let index = self.index.read();
+        let num_candidates = k * index.rerank_factor().max(1);
+        index.query(vector, num_candidates, filter)
+    }
+
+    pub(crate) fn rerank_factor(&self) -> usize {
+        self.index.read().rerank_factor()
+    }
+
+    pub(crate) fn distance_function(&self) -> DistanceFunction {
+        self.index.read().distance_function().clone()
+    }
+}
@@ -10,10 +10,14 @@ use tantivy::tokenizer::NgramTokenizer;
 use thiserror::Error;
 use uuid::Uuid;
 
+use super::schema::{MetadataSegmentConfig, SegmentConfigError};
 use super::types::{MaterializedLogRecord, SegmentWriter};
 use super::SegmentFlusher;
 use crate::blockstore::provider::{BlockfileProvider, CreateError, OpenError};
 use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::fulltext::normalization::{TextNormalization, TextNormalizationError};
+use crate::index::fulltext::query::DocumentQuery;
+use crate::index::fulltext::regex_prefilter::regex_prefilter;
 use crate::index::fulltext::tokenizer::TantivyChromaTokenizer;
 use crate::index::fulltext::types::{
     FullTextIndexError, FullTextIndexFlusher, FullTextIndexReader, FullTextIndexWriter,
@@ -23,27 +27,32 @@ use crate::index::metadata::types::{
 };
 use crate::types::SegmentType;
 use crate::types::{
-    BooleanOperator, DirectComparison, MetadataValue, Operation, Segment, Where, WhereChildren,
-    WhereClauseComparator, WhereClauseListOperator, WhereComparison, WhereDocument,
+    BooleanOperator, DirectComparison, Metadata, MetadataValue, Operation, Segment, Where,
+    WhereChildren, WhereClauseComparator, WhereClauseListOperator, WhereComparison, WhereDocument,
     WhereDocumentOperator,
 };
 use crate::utils::{merge_sorted_vecs_conjunction, merge_sorted_vecs_disjunction};
 
 const FULL_TEXT_PLS: &str = "full_text_pls";
 const FULL_TEXT_FREQS: &str = "full_text_freqs";
+// Not a file, records the text normalization the full text index was built with.
+const FULL_TEXT_NORMALIZATION: &str = "full_text_normalization";
 const STRING_METADATA: &str = "string_metadata";
 const BOOL_METADATA: &str = "bool_metadata";
 const F32_METADATA: &str = "f32_metadata";
 const U32_METADATA: &str = "u32_metadata";
 
+#[derive(Clone)]
 pub(crate) struct MetadataSegmentWriter {
     pub(crate) full_text_index_writer: Option<FullTextIndexWriter>,
+    pub(crate) full_text_normalization: TextNormalization,
     // TODO this needs a real lifetime. However doing it breaks the commit() method
     // for some reason? This works for now.
     pub(crate) string_metadata_index_writer: Option<MetadataIndexWriter>,
     pub(crate) bool_metadata_index_writer: Option<MetadataIndexWriter>,
     pub(crate) f32_metadata_index_writer: Option<MetadataIndexWriter>,
     pub(crate) u32_metadata_index_writer: Option<MetadataIndexWriter>,
+    pub(crate) id: Uuid,
 }
 
 impl Debug for MetadataSegmentWriter {
@@ -81,6 +90,10 @@ pub enum MetadataSegmentError {
     LimitOffsetNotSupported,
     #[error("Could not query metadata index {0}")]
     MetadataIndexQueryError(#[from] MetadataIndexError),
+    #[error("Invalid full text normalization {0}")]
+    FullTextNormalizationError(#[from] TextNormalizationError),
+    #[error("Invalid metadata segment configuration {0}")]
+    InvalidConfig(#[from] SegmentConfigError),
 }
 
 impl ChromaError for MetadataSegmentError {
@@ -90,6 +103,23 @@ impl ChromaError for MetadataSegmentError {
     }
 }
 
+/// The text normalization of the segment's full text index.
+pub(crate) fn full_text_normalization(
+    segment: &Segment,
+) -> Result<TextNormalization, MetadataSegmentError> {
+    let config = MetadataSegmentConfig::from_segment(segment)?;
+    // An existing index keeps the normalization it was built with, indexes
+    // written before normalization was recorded are not normalized.
+    let normalization = match segment.file_path.get(FULL_TEXT_PLS) {
+        Some(_) => match segment.file_path.get(FULL_TEXT_NORMALIZATION) {
+            Some(options) => TextNormalization::from_options(options)?,
+            None => TextNormalization::default(),
+        },
+        None => config.full_text_normalization,
+    };
+    Ok(normalization)
+}
+
 impl MetadataSegmentWriter {
     pub(crate) async fn from_segment(
         segment: &Segment,
@@ -136,7 +166,7 @@ impl MetadataSegmentWriter {
                 Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
             },
         };
-        let freqs_writer = match segment.file_path.get(FULL_TEXT_FREQS) {
+        let (freqs_writer, freqs_reader) = match segment.file_path.get(FULL_TEXT_FREQS) {
             Some(freqs_path) => match freqs_path.get(0) {
                 Some(freqs_uuid) => {
                     let freqs_uuid = match Uuid::parse_str(freqs_uuid) {
@@ -152,80 +182,110 @@ impl MetadataSegmentWriter {
                         Ok(writer) => writer,
                         Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
                     };
-                    freqs_writer
+                    // The writer reads the frequencies it updates from the forked blockfile.
+                    let freqs_reader = match blockfile_provider.open::<u32, u32>(&freqs_uuid).await
+                    {
+                        Ok(reader) => reader,
+                        Err(e) => return Err(MetadataSegmentError::BlockfileOpenError(*e)),
+                    };
+                    (freqs_writer, Some(freqs_reader))
                 }
                 None => return Err(MetadataSegmentError::EmptyPathVector),
             },
             None => match blockfile_provider.create::<u32, u32>() {
-                Ok(writer) => writer,
+                Ok(writer) => (writer, None),
                 Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
             },
         };
+        let full_text_normalization = full_text_normalization(segment)?;
         let full_text_tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 3, false).unwrap(),
         )));
-        let full_text_index_writer =
-            FullTextIndexWriter::new(pls_writer, freqs_writer, full_text_tokenizer);
+        let full_text_index_writer = FullTextIndexWriter::new(
+            pls_writer,
+            freqs_writer,
+            freqs_reader,
+            full_text_tokenizer,
+            full_text_normalization,
+        );
 
-        let string_metadata_writer = match segment.file_path.get(STRING_METADATA) {
-            Some(string_metadata_path) => match string_metadata_path.get(0) {
-                Some(string_metadata_uuid) => {
-                    let string_metadata_uuid = match Uuid::parse_str(string_metadata_uuid) {
-                        Ok(uuid) => uuid,
-                        Err(_) => {
-                            return Err(MetadataSegmentError::UuidParseError(
-                                string_metadata_uuid.to_string(),
-                            ))
-                        }
-                    };
-                    let string_metadata_writer = match blockfile_provider
-                        .fork::<&str, &RoaringBitmap>(&string_metadata_uuid)
-                        .await
-                    {
-                        Ok(writer) => writer,
-                        Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
-                    };
-                    string_metadata_writer
-                }
-                None => return Err(MetadataSegmentError::EmptyPathVector),
-            },
-            None => match blockfile_provider.create::<&str, &RoaringBitmap>() {
-                Ok(writer) => writer,
-                Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
-            },
-        };
-        let string_metadata_index_writer = MetadataIndexWriter::new_string(string_metadata_writer);
+        let (string_metadata_writer, string_metadata_reader) =
+            match segment.file_path.get(STRING_METADATA) {
+                Some(string_metadata_path) => match string_metadata_path.get(0) {
+                    Some(string_metadata_uuid) => {
+                        let string_metadata_uuid = match Uuid::parse_str(string_metadata_uuid) {
+                            Ok(uuid) => uuid,
+                            Err(_) => {
+                                return Err(MetadataSegmentError::UuidParseError(
+                                    string_metadata_uuid.to_string(),
+                                ))
+                            }
+                        };
+                        let string_metadata_writer = match blockfile_provider
+                            .fork::<&str, &RoaringBitmap>(&string_metadata_uuid)
+                            .await
+                        {
+                            Ok(writer) => writer,
+                            Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
+                        };
+                        let string_metadata_reader = match blockfile_provider
+                            .open::<&str, RoaringBitmap>(&string_metadata_uuid)
+                            .await
+                        {
+                            Ok(reader) => reader,
+                            Err(e) => return Err(MetadataSegmentError::BlockfileOpenError(*e)),
+                        };
+                        (string_metadata_writer, Some(string_metadata_reader))
+                    }
+                    None => return Err(MetadataSegmentError::EmptyPathVector),
+                },
+                None => match blockfile_provider.create::<&str, &RoaringBitmap>() {
+                    Ok(writer) => (writer, None),
+                    Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
+                },
+            };
+        let string_metadata_index_writer =
+            MetadataIndexWriter::new_string(string_metadata_writer, string_metadata_reader);
 
-        let bool_metadata_writer = match segment.file_path.get(BOOL_METADATA) {
-            Some(bool_metadata_path) => match bool_metadata_path.get(0) {
-                Some(bool_metadata_uuid) => {
-                    let bool_metadata_uuid = match Uuid::parse_str(bool_metadata_uuid) {
-                        Ok(uuid) => uuid,
-                        Err(_) => {
-                            return Err(MetadataSegmentError::UuidParseError(
-                                bool_metadata_uuid.to_string(),
-                            ))
-                        }
-                    };
-                    let bool_metadata_writer = match blockfile_provider
-                        .fork::<bool, &RoaringBitmap>(&bool_metadata_uuid)
-                        .await
-                    {
-                        Ok(writer) => writer,
-                        Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
-                    };
-                    bool_metadata_writer
-                }
-                None => return Err(MetadataSegmentError::EmptyPathVector),
-            },
-            None => match blockfile_provider.create::<bool, &RoaringBitmap>() {
-                Ok(writer) => writer,
-                Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
-            },
-        };
-        let bool_metadata_index_writer = MetadataIndexWriter::new_bool(bool_metadata_writer);
+        let (bool_metadata_writer, bool_metadata_reader) =
+            match segment.file_path.get(BOOL_METADATA) {
+                Some(bool_metadata_path) => match bool_metadata_path.get(0) {
+                    Some(bool_metadata_uuid) => {
+                        let bool_metadata_uuid = match Uuid::parse_str(bool_metadata_uuid) {
+                            Ok(uuid) => uuid,
+                            Err(_) => {
+                                return Err(MetadataSegmentError::UuidParseError(
+                                    bool_metadata_uuid.to_string(),
+                                ))
+                            }
+                        };
+                        let bool_metadata_writer = match blockfile_provider
+                            .fork::<bool, &RoaringBitmap>(&bool_metadata_uuid)
+                            .await
+                        {
+                            Ok(writer) => writer,
+                            Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
+                        };
+                        let bool_metadata_reader = match blockfile_provider
+                            .open::<bool, RoaringBitmap>(&bool_metadata_uuid)
+                            .await
+                        {
+                            Ok(reader) => reader,
+                            Err(e) => return Err(MetadataSegmentError::BlockfileOpenError(*e)),
+                        };
+                        (bool_metadata_writer, Some(bool_metadata_reader))
+                    }
+                    None => return Err(MetadataSegmentError::EmptyPathVector),
+                },
+                None => match blockfile_provider.create::<bool, &RoaringBitmap>() {
+                    Ok(writer) => (writer, None),
+                    Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
+                },
+            };
+        let bool_metadata_index_writer =
+            MetadataIndexWriter::new_bool(bool_metadata_writer, bool_metadata_reader);
 
-        let f32_metadata_writer = match segment.file_path.get(F32_METADATA) {
+        let (f32_metadata_writer, f32_metadata_reader) = match segment.file_path.get(F32_METADATA) {
             Some(f32_metadata_path) => match f32_metadata_path.get(0) {
                 Some(f32_metadata_uuid) => {
                     let f32_metadata_uuid = match Uuid::parse_str(f32_metadata_uuid) {
@@ -243,18 +303,26 @@ impl MetadataSegmentWriter {
                         Ok(writer) => writer,
                         Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
                     };
-                    f32_metadata_writer
+                    let f32_metadata_reader = match blockfile_provider
+                        .open::<f32, RoaringBitmap>(&f32_metadata_uuid)
+                        .await
+                    {
+                        Ok(reader) => reader,
+                        Err(e) => return Err(MetadataSegmentError::BlockfileOpenError(*e)),
+                    };
+                    (f32_metadata_writer, Some(f32_metadata_reader))
                 }
                 None => return Err(MetadataSegmentError::EmptyPathVector),
             },
             None => match blockfile_provider.create::<f32, &RoaringBitmap>() {
-                Ok(writer) => writer,
+                Ok(writer) => (writer, None),
                 Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
             },
         };
-        let f32_metadata_index_writer = MetadataIndexWriter::new_f32(f32_metadata_writer);
+        let f32_metadata_index_writer =
+            MetadataIndexWriter::new_f32(f32_metadata_writer, f32_metadata_reader);
 
-        let u32_metadata_writer = match segment.file_path.get(U32_METADATA) {
+        let (u32_metadata_writer, u32_metadata_reader) = match segment.file_path.get(U32_METADATA) {
             Some(u32_metadata_path) => match u32_metadata_path.get(0) {
                 Some(u32_metadata_uuid) => {
                     let u32_metadata_uuid = match Uuid::parse_str(u32_metadata_uuid) {
@@ -272,23 +340,33 @@ impl MetadataSegmentWriter {
                         Ok(writer) => writer,
                         Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
                     };
-                    u32_metadata_writer
+                    let u32_metadata_reader = match blockfile_provider
+                        .open::<u32, RoaringBitmap>(&u32_metadata_uuid)
+                        .await
+                    {
+                        Ok(reader) => reader,
+                        Err(e) => return Err(MetadataSegmentError::BlockfileOpenError(*e)),
+                    };
+                    (u32_metadata_writer, Some(u32_metadata_reader))
                 }
                 None => return Err(MetadataSegmentError::EmptyPathVector),
             },
             None => match blockfile_provider.create::<u32, &RoaringBitmap>() {
-                Ok(writer) => writer,
+                Ok(writer) => (writer, None),
                 Err(e) => return Err(MetadataSegmentError::BlockfileError(*e)),
             },
         };
-        let u32_metadata_index_writer = MetadataIndexWriter::new_u32(u32_metadata_writer);
+        let u32_metadata_index_writer =
+            MetadataIndexWriter::new_u32(u32_metadata_writer, u32_metadata_reader);
 
         Ok(MetadataSegmentWriter {
             full_text_index_writer: Some(full_text_index_writer),
+            full_text_normalization,
             string_metadata_index_writer: Some(string_metadata_index_writer),
             bool_metadata_index_writer: Some(bool_metadata_index_writer),
             f32_metadata_index_writer: Some(f32_metadata_index_writer),
             u32_metadata_index_writer: Some(u32_metadata_index_writer),
+            id: segment.id,
         })
     }
 
@@ -352,79 +430,140 @@ impl MetadataSegmentWriter {
     }
 }
 
+impl MetadataSegmentWriter {
+    fn set_metadata(
+        &self,
+        metadata: &Metadata,
+        offset_id: u32,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        for (key, value) in metadata.iter() {
+            match value {
+                MetadataValue::Str(value) => match &self.string_metadata_index_writer {
+                    Some(writer) => writer.set(key, value.as_str(), offset_id)?,
+                    None => {}
+                },
+                MetadataValue::Float(value) => match &self.f32_metadata_index_writer {
+                    Some(writer) => writer.set(key, *value as f32, offset_id)?,
+                    None => {}
+                },
+                MetadataValue::Int(value) => match &self.u32_metadata_index_writer {
+                    Some(writer) => writer.set(key, *value as u32, offset_id)?,
+                    None => {}
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_metadata(
+        &self,
+        metadata: &Metadata,
+        offset_id: u32,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        for (key, value) in metadata.iter() {
+            match value {
+                MetadataValue::Str(value) => match &self.string_metadata_index_writer {
+                    Some(writer) => writer.delete(key, value.as_str(), offset_id)?,
+                    None => {}
+                },
+                MetadataValue::Float(value) => match &self.f32_metadata_index_writer {
+                    Some(writer) => writer.delete(key, *value as f32, offset_id)?,
+                    None => {}
+                },
+                MetadataValue::Int(value) => match &self.u32_metadata_index_writer {
+                    Some(writer) => writer.delete(key, *value as u32, offset_id)?,
+                    None => {}
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
 impl SegmentWriter for MetadataSegmentWriter {
     fn apply_materialized_log_chunk(
         &self,
         records: crate::execution::data::data_chunk::Chunk<MaterializedLogRecord>,
-    ) {
+    ) -> Result<(), Box<dyn ChromaError>> {
         for record in records.iter() {
             let segment_offset_id = record.0.segment_offset_id;
-            match record.0.log_record.record.operation {
+            let previous_record = record.0.previous_record.as_ref();
+            match record.0.operation() {
                 Operation::Add => {
                     match &record.0.materialized_record.metadata {
-                        Some(metadata) => {
-                            for (key, value) in metadata.iter() {
-                                match value {
-                                    MetadataValue::Str(value) => {
-                                        match &self.string_metadata_index_writer {
-                                            Some(writer) => {
-                                                let _ = writer.set(
-                                                    key,
-                                                    value.as_str(),
-                                                    segment_offset_id,
-                                                );
-                                            }
-                                            None => {}
-                                        }
-                                    }
-                                    MetadataValue::Float(value) => {
-                                        match &self.f32_metadata_index_writer {
-                                            Some(writer) => {
-                                                let _ = writer.set(
-                                                    key,
-                                                    *value as f32,
-                                                    segment_offset_id,
-                                                );
-                                            }
-                                            None => {}
-                                        }
-                                    }
-                                    MetadataValue::Int(value) => {
-                                        match &self.u32_metadata_index_writer {
-                                            Some(writer) => {
-                                                let _ = writer.set(
-                                                    key,
-                                                    *value as u32,
-                                                    segment_offset_id,
-                                                );
-                                            }
-                                            None => {}
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                        Some(metadata) => self.set_metadata(metadata, segment_offset_id)?,
                         None => {}
                     };
                     match &record.0.materialized_record.document {
                         Some(document) => match &self.full_text_index_writer {
                             Some(writer) => {
-                                let _ = writer.add_document(document, segment_offset_id as i32);
+                                writer.add_document(document, segment_offset_id as i32)?
                             }
                             None => {}
                         },
                         None => {}
                     }
                 }
-                _ => todo!(),
+                Operation::Update => {
+                    // The materialized metadata is the full metadata of the
+                    // record, so the previous metadata is replaced.
+                    match previous_record.and_then(|previous| previous.metadata.as_ref()) {
+                        Some(metadata) => self.delete_metadata(metadata, segment_offset_id)?,
+                        None => {}
+                    }
+                    match &record.0.materialized_record.metadata {
+                        Some(metadata) => self.set_metadata(metadata, segment_offset_id)?,
+                        None => {}
+                    }
+                    match (
+                        previous_record.and_then(|previous| previous.document.as_ref()),
+                        &record.0.materialized_record.document,
+                    ) {
+                        (Some(previous_document), Some(document)) => {
+                            match &self.full_text_index_writer {
+                                Some(writer) => writer.update_document(
+                                    previous_document,
+                                    document,
+                                    segment_offset_id as i32,
+                                )?,
+                                None => {}
+                            }
+                        }
+                        (None, Some(document)) => match &self.full_text_index_writer {
+                            Some(writer) => {
+                                writer.add_document(document, segment_offset_id as i32)?
+                            }
+                            None => {}
+                        },
+                        // The update leaves the document as is.
+                        (_, None) => {}
+                    }
+                }
+                Operation::Delete => {
+                    match previous_record.and_then(|previous| previous.metadata.as_ref()) {
+                        Some(metadata) => self.delete_metadata(metadata, segment_offset_id)?,
+                        None => {}
+                    }
+                    match previous_record.and_then(|previous| previous.document.as_ref()) {
+                        Some(previous_document) => match &self.full_text_index_writer {
+                            Some(writer) => writer
+                                .delete_document(previous_document, segment_offset_id as i32)?,
+                            None => {}
+                        },
+                        None => {}
+                    }
+                }
+                // Upserts are resolved to adds or updates by the materializer.
+                Operation::Upsert => unreachable!(),
             }
         }
+        Ok(())
     }
 
     fn apply_log_chunk(
         &self,
         _: crate::execution::data::data_chunk::Chunk<crate::types::LogRecord>,
-    ) {
+    ) -> Result<(), Box<dyn ChromaError>> {
         unreachable!();
     }
 
@@ -456,6 +595,7 @@ impl SegmentWriter for MetadataSegmentWriter {
 
         Ok(MetadataSegmentFlusher {
             full_text_index_flusher: full_text_flusher,
+            full_text_normalization: self.full_text_normalization,
             string_metadata_index_flusher: string_metadata_flusher,
             bool_metadata_index_flusher: bool_metadata_flusher,
             f32_metadata_index_flusher: f32_metadata_flusher,
@@ -466,6 +606,7 @@ impl SegmentWriter for MetadataSegmentWriter {
 
 pub(crate) struct MetadataSegmentFlusher {
     pub(crate) full_text_index_flusher: FullTextIndexFlusher,
+    pub(crate) full_text_normalization: TextNormalization,
     pub(crate) string_metadata_index_flusher: MetadataIndexFlusher,
     pub(crate) bool_metadata_index_flusher: MetadataIndexFlusher,
     pub(crate) f32_metadata_index_flusher: MetadataIndexFlusher,
@@ -493,6 +634,10 @@ impl SegmentFlusher for MetadataSegmentFlusher {
             FULL_TEXT_FREQS.to_string(),
             vec![full_text_freqs_id.to_string()],
         );
+        flushed.insert(
+            FULL_TEXT_NORMALIZATION.to_string(),
+            self.full_text_normalization.to_options(),
+        );
 
         self.bool_metadata_index_flusher
             .flush()
@@ -600,10 +745,12 @@ impl MetadataSegmentReader<'_> {
             },
             None => return Err(MetadataSegmentError::IncorrectNumberOfFiles),
         };
+        let normalization = full_text_normalization(segment)?;
         let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
             NgramTokenizer::new(1, 3, false).unwrap(),
         )));
-        let full_text_index_reader = FullTextIndexReader::new(pls_reader, freqs_reader, tokenizer);
+        let full_text_index_reader =
+            FullTextIndexReader::new(pls_reader, freqs_reader, tokenizer, normalization);
 
         let string_metadata_reader = match segment.file_path.get(STRING_METADATA) {
             Some(string_metadata_path) => match string_metadata_path.get(0) {
@@ -734,18 +881,17 @@ impl MetadataSegmentReader<'_> {
         let where_results = match where_clause {
             Some(where_clause) => {
                 match self.process_where_clause(where_clause).await.map_err(|e| e) {
-                    Ok(results) => results,
+                    Ok(results) => Some(results),
                     Err(e) => return Err(MetadataSegmentError::MetadataIndexQueryError(e)),
                 }
             }
-            None => {
-                vec![]
-            }
+            None => None,
         };
         // Where and WhereDocument are implicitly ANDed, so if we have nothing
         // for the Where query we can just return.
-        if where_results.is_empty() {
-            return Ok(where_results);
+        match &where_results {
+            Some(where_results) if where_results.is_empty() => return Ok(vec![]),
+            _ => {}
         }
 
         let where_document_results = match where_document_clause {
@@ -754,22 +900,57 @@ impl MetadataSegmentReader<'_> {
                     .process_where_document_clause(where_document_clause)
                     .await
                 {
-                    Ok(results) => results,
+                    Ok(results) => Some(results),
                     Err(e) => return Err(MetadataSegmentError::MetadataIndexQueryError(e)),
                 }
             }
-            None => {
-                vec![]
-            }
+            None => None,
         };
-        if where_document_results.is_empty() {
-            return Ok(where_document_results);
+
+        match (where_results, where_document_results) {
+            (Some(where_results), Some(where_document_results)) => Ok(
+                merge_sorted_vecs_conjunction(where_results, where_document_results),
+            ),
+            (Some(results), None) | (None, Some(results)) => Ok(results),
+            (None, None) => Ok(vec![]),
         }
+    }
 
-        Ok(merge_sorted_vecs_conjunction(
-            where_results,
-            where_document_results,
-        ))
+    /// Find where each of the phrases occurs in the given documents.
+    /// # Returns
+    /// Offset ID -> sorted (start, end) byte ranges of the occurrences. Ranges are
+    /// offsets into the normalized documents, see `full_text_normalization`.
+    pub async fn phrase_offsets(
+        &self,
+        phrases: &[&str],
+        offset_ids: &[u32],
+    ) -> HashMap<u32, Vec<(usize, usize)>> {
+        let mut results: HashMap<u32, Vec<(usize, usize)>> = HashMap::new();
+        for phrase in phrases {
+            // Phrases with tokens absent from the index occur nowhere.
+            let matches = match self
+                .full_text_index_reader
+                .search_with_offsets(phrase)
+                .await
+            {
+                Ok(matches) => matches,
+                Err(_) => continue,
+            };
+            for offset_id in offset_ids {
+                match matches.get(&(*offset_id as i32)) {
+                    Some(ranges) => results
+                        .entry(*offset_id)
+                        .or_insert_with(Vec::new)
+                        .extend(ranges.iter().cloned()),
+                    None => {}
+                }
+            }
+        }
+        for ranges in results.values_mut() {
+            ranges.sort();
+            ranges.dedup();
+        }
+        results
     }
 
     fn process_where_clause(
@@ -1123,7 +1304,62 @@ impl MetadataSegmentReader<'_> {
                         }
                     }
                     WhereDocumentOperator::NotContains => {
-                        todo!();
+                        // TODO: This needs the set of all documents in the segment.
+                        return Box::pin(async {
+                            Err(MetadataIndexError::UnsupportedOperator(
+                                "$not_contains".to_string(),
+                            ))
+                        });
+                    }
+                    WhereDocumentOperator::Matches => {
+                        let query = match DocumentQuery::parse(&direct_document_comparison.document)
+                        {
+                            Ok(query) => query,
+                            Err(e) => {
+                                return Box::pin(async {
+                                    Err(MetadataIndexError::InvalidDocumentQuery(e))
+                                });
+                            }
+                        };
+                        return Box::pin(async move {
+                            let mut results: Vec<usize> = self
+                                .full_text_index_reader
+                                .search_query(&query)
+                                .await
+                                .map_err(MetadataIndexError::FullTextIndexError)?
+                                .iter()
+                                .map(|x| *x as usize)
+                                .collect();
+                            results.sort();
+                            Ok(results)
+                        });
+                    }
+                    WhereDocumentOperator::Regex => {
+                        // The index only narrows down the candidates, callers must verify
+                        // them against the document text with the regex.
+                        let query = match regex_prefilter(&direct_document_comparison.document) {
+                            Ok(Some(query)) => query,
+                            Ok(None) => {
+                                return Box::pin(async { Err(MetadataIndexError::UnboundedRegex) });
+                            }
+                            Err(e) => {
+                                return Box::pin(async {
+                                    Err(MetadataIndexError::InvalidRegex(e))
+                                });
+                            }
+                        };
+                        return Box::pin(async move {
+                            let mut results: Vec<usize> = self
+                                .full_text_index_reader
+                                .search_query(&query)
+                                .await
+                                .map_err(MetadataIndexError::FullTextIndexError)?
+                                .iter()
+                                .map(|x| *x as usize)
+                                .collect();
+                            results.sort();
+                            Ok(results)
+                        });
                     }
                 }
             }
@@ -1156,3 +1392,265 @@ impl MetadataSegmentReader<'_> {
         return Box::pin(async { Ok(results) });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::data::data_chunk::Chunk;
+    use crate::segment::record_segment::{RecordSegmentReader, RecordSegmentWriter};
+    use crate::segment::LogMaterializer;
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{
+        DirectDocumentComparison, LogRecord, OperationRecord, SegmentScope, UpdateMetadataValue,
+    };
+
+    fn log_record(
+        log_offset: i64,
+        id: &str,
+        color: Option<&str>,
+        document: Option<&str>,
+        operation: Operation,
+    ) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: match operation {
+                    Operation::Add | Operation::Upsert => Some(vec![1.0, 2.0, 3.0]),
+                    _ => None,
+                },
+                encoding: None,
+                sparse_embedding: None,
+                multi_embedding: None,
+                metadata: color.map(|color| {
+                    HashMap::from([(
+                        "color".to_string(),
+                        UpdateMetadataValue::Str(color.to_string()),
+                    )])
+                }),
+                document: document.map(|document| document.to_string()),
+                operation,
+            },
+        }
+    }
+
+    async fn compact(
+        log_records: Vec<LogRecord>,
+        record_segment: &mut Segment,
+        metadata_segment: &mut Segment,
+        blockfile_provider: &BlockfileProvider,
+    ) {
+        let log_records: Chunk<LogRecord> = Chunk::new(log_records.into());
+        let record_segment_writer =
+            RecordSegmentWriter::from_segment(record_segment, blockfile_provider)
+                .await
+                .unwrap();
+        let mut metadata_segment_writer =
+            MetadataSegmentWriter::from_segment(metadata_segment, blockfile_provider)
+                .await
+                .unwrap();
+        let materialized = record_segment_writer
+            .materialize(&log_records)
+            .await
+            .unwrap();
+        metadata_segment_writer
+            .apply_materialized_log_chunk(materialized)
+            .unwrap();
+        metadata_segment_writer.write_to_blockfiles().await.unwrap();
+        record_segment.file_path = record_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+        metadata_segment.file_path = metadata_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+    }
+
+    async fn user_ids(
+        record_segment_reader: &RecordSegmentReader<'_>,
+        offset_ids: Vec<usize>,
+    ) -> Vec<String> {
+        let mut user_ids = Vec::new();
+        for offset_id in offset_ids {
+            user_ids.push(
+                record_segment_reader
+                    .get_user_id_for_offset_id(offset_id as u32)
+                    .await
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+        user_ids.sort();
+        user_ids
+    }
+
+    #[tokio::test]
+    async fn test_apply_updates_and_deletes_in_same_chunk() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let mut record_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::Record,
+            scope: SegmentScope::RECORD,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut metadata_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileMetadata,
+            scope: SegmentScope::METADATA,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        compact(
+            vec![
+                log_record(1, "a", Some("red"), Some("hello apple"), Operation::Add),
+                log_record(2, "b", Some("red"), Some("hello banana"), Operation::Add),
+            ],
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+        )
+        .await;
+        compact(
+            vec![
+                // Records in the segment
+                log_record(3, "a", Some("blue"), None, Operation::Update),
+                log_record(4, "b", None, None, Operation::Delete),
+                log_record(5, "a", None, Some("hello apricot"), Operation::Upsert),
+                // Records added earlier in the chunk
+                log_record(6, "c", Some("red"), Some("hello cherry"), Operation::Add),
+                log_record(
+                    7,
+                    "c",
+                    Some("green"),
+                    Some("hello coconut"),
+                    Operation::Update,
+                ),
+                log_record(8, "d", Some("red"), Some("hello date"), Operation::Add),
+                log_record(9, "d", None, None, Operation::Delete),
+                log_record(
+                    10,
+                    "e",
+                    Some("red"),
+                    Some("hello elderberry"),
+                    Operation::Upsert,
+                ),
+                // Adding a record that exists is a no-op
+                log_record(11, "e", Some("blue"), Some("hello fig"), Operation::Add),
+            ],
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+        )
+        .await;
+
+        let record_segment_reader =
+            RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        assert_eq!(record_segment_reader.count().await.unwrap(), 3);
+        let metadata_segment_reader =
+            MetadataSegmentReader::from_segment(&metadata_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        for (color, expected) in [
+            ("red", vec!["e"]),
+            ("blue", vec!["a"]),
+            ("green", vec!["c"]),
+        ] {
+            let where_clause = Where::DirectWhereComparison(DirectComparison {
+                key: "color".to_string(),
+                comparison: WhereComparison::SingleStringComparison(
+                    color.to_string(),
+                    WhereClauseComparator::Equal,
+                ),
+            });
+            let offset_ids = metadata_segment_reader
+                .query(Some(&where_clause), None, None, 0, 0)
+                .await
+                .unwrap();
+            assert_eq!(
+                user_ids(&record_segment_reader, offset_ids).await,
+                expected,
+                "color {}",
+                color
+            );
+        }
+        for (document, expected) in [
+            ("hello", vec!["a", "c", "e"]),
+            ("apricot", vec!["a"]),
+            ("apple", vec![]),
+            ("banana", vec![]),
+            ("cherry", vec![]),
+            ("date", vec![]),
+            ("fig", vec![]),
+        ] {
+            let where_document_clause =
+                WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+                    document: document.to_string(),
+                    operator: WhereDocumentOperator::Contains,
+                });
+            let offset_ids = metadata_segment_reader
+                .query(None, Some(&where_document_clause), None, 0, 0)
+                .await
+                .unwrap();
+            assert_eq!(
+                user_ids(&record_segment_reader, offset_ids).await,
+                expected,
+                "document {}",
+                document
+            );
+        }
+        for (document_query, expected) in [
+            ("hello NOT apricot", vec!["c", "e"]),
+            ("coconut OR elderberry", vec!["c", "e"]),
+            ("\"hello fig\"", vec![]),
+        ] {
+            let where_document_clause =
+                WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+                    document: document_query.to_string(),
+                    operator: WhereDocumentOperator::Matches,
+                });
+            let offset_ids = metadata_segment_reader
+                .query(None, Some(&where_document_clause), None, 0, 0)
+                .await
+                .unwrap();
+            assert_eq!(
+                user_ids(&record_segment_reader, offset_ids).await,
+                expected,
+                "document query {}",
+                document_query
+            );
+        }
+        // The index only returns the candidates of a regex.
+        for (regex, expected) in [
+            ("apricot|coconut", vec!["a", "c"]),
+            ("hello (fig|date)", vec![]),
+        ] {
+            let where_document_clause =
+                WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+                    document: regex.to_string(),
+                    operator: WhereDocumentOperator::Regex,
+                });
+            let offset_ids = metadata_segment_reader
+                .query(None, Some(&where_document_clause), None, 0, 0)
+                .await
+                .unwrap();
+            assert_eq!(
+                user_ids(&record_segment_reader, offset_ids).await,
+                expected,
+                "regex {}",
+                regex
+            );
+        }
+    }
+}
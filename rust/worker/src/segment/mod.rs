@@ -1,7 +1,12 @@
 pub(crate) mod config;
 pub(crate) mod distributed_hnsw_segment;
+pub(crate) mod distributed_ivf_segment;
+pub(crate) mod distributed_pq_segment;
 pub(crate) mod metadata_segment;
 pub(crate) mod record_segment;
+pub(crate) mod schema;
+pub(crate) mod sparse_vector_segment;
 pub(crate) mod types;
+pub(crate) mod vector_segment;
 
 pub(crate) use types::*;
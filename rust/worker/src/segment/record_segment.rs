@@ -1,10 +1,16 @@
-use super::types::{LogMaterializer, MaterializedLogRecord, SegmentWriter};
+use super::schema::{RecordSegmentConfig, SegmentConfigError};
+use super::types::{
+    LogMaterializer, LogMaterializerError, MaterializedLogRecord, PreviousRecord, SegmentWriter,
+};
 use super::{DataRecord, SegmentFlusher};
 use crate::blockstore::provider::{BlockfileProvider, CreateError, OpenError};
 use crate::blockstore::{BlockfileFlusher, BlockfileReader, BlockfileWriter};
 use crate::errors::{ChromaError, ErrorCodes};
 use crate::execution::data::data_chunk::Chunk;
-use crate::types::{update_metdata_to_metdata, LogRecord, Operation, Segment, SegmentType};
+use crate::types::{
+    update_metdata_to_metdata, LogRecord, MetadataValue, Operation, Segment, SegmentType,
+    UpdateMetadataValue,
+};
 use async_trait::async_trait;
 use futures::StreamExt;
 use std::collections::HashMap;
@@ -32,7 +38,7 @@ pub(crate) struct RecordSegmentWriter {
     pub(crate) id: Uuid,
     // If there is an old version of the data, we need to keep it around to be able to
     // materialize the log records
-    // old_id_to_data: Option<BlockfileReader<'a, u32, DataRecord<'a>>>,
+    old_record_segment_reader: Option<Arc<RecordSegmentReader<'static>>>,
 }
 
 impl Debug for RecordSegmentWriter {
@@ -57,6 +63,10 @@ pub enum RecordSegmentWriterCreationError {
     BlockfileOpenError(#[from] Box<OpenError>),
     #[error("No exisiting offset id found")]
     NoExistingOffsetId,
+    #[error("Record segment reader creation error")]
+    ReaderCreationError(#[from] Box<RecordSegmentReaderCreationError>),
+    #[error("Invalid record segment configuration")]
+    InvalidConfig(#[from] SegmentConfigError),
 }
 
 impl RecordSegmentWriter {
@@ -68,8 +78,10 @@ impl RecordSegmentWriter {
         if segment.r#type != SegmentType::Record {
             return Err(RecordSegmentWriterCreationError::InvalidSegmentType);
         }
+        RecordSegmentConfig::from_segment(segment)?;
 
         let mut exising_max_offset_id = 0;
+        let mut old_record_segment_reader = None;
 
         let (user_id_to_id, id_to_user_id, id_to_data, max_offset_id) = match segment
             .file_path
@@ -250,6 +262,9 @@ impl RecordSegmentWriter {
                         return Err(RecordSegmentWriterCreationError::NoExistingOffsetId);
                     }
                 };
+                old_record_segment_reader = Some(Arc::new(
+                    RecordSegmentReader::from_segment(segment, blockfile_provider).await?,
+                ));
 
                 (user_id_to_id, id_to_user_id, id_to_data, max_offset_id_bf)
             }
@@ -264,16 +279,20 @@ impl RecordSegmentWriter {
             max_offset_id: Some(max_offset_id),
             curr_max_offset_id: Arc::new(AtomicU32::new(exising_max_offset_id + 1)),
             id: segment.id,
+            old_record_segment_reader,
         })
     }
 }
 
 impl SegmentWriter for RecordSegmentWriter {
-    fn apply_materialized_log_chunk(&self, records: Chunk<MaterializedLogRecord>) {
+    fn apply_materialized_log_chunk(
+        &self,
+        records: Chunk<MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
         todo!()
     }
 
-    fn apply_log_chunk(&self, records: Chunk<LogRecord>) {
+    fn apply_log_chunk(&self, records: Chunk<LogRecord>) -> Result<(), Box<dyn ChromaError>> {
         todo!()
     }
 
@@ -401,85 +420,276 @@ impl SegmentFlusher for RecordSegmentFlusher {
     }
 }
 
+// The state of a record as of the log records materialized so far.
+#[derive(Clone)]
+struct MaterializedState {
+    offset_id: u32,
+    embedding: Vec<f32>,
+    record: PreviousRecord,
+}
+
+impl MaterializedState {
+    fn from_data_record(offset_id: u32, data_record: &DataRecord) -> Self {
+        MaterializedState {
+            offset_id,
+            embedding: data_record.embedding.to_vec(),
+            record: PreviousRecord {
+                document: data_record.document.map(|d| d.to_string()),
+                sparse_embedding: data_record.sparse_embedding.clone(),
+                multi_embedding: data_record.multi_embedding.clone(),
+                metadata: data_record.metadata.clone(),
+            },
+        }
+    }
+
+    fn data_record<'a>(&'a self, id: &'a str) -> DataRecord<'a> {
+        DataRecord {
+            id,
+            embedding: &self.embedding,
+            sparse_embedding: self.record.sparse_embedding.clone(),
+            multi_embedding: self.record.multi_embedding.clone(),
+            metadata: self.record.metadata.clone(),
+            document: self.record.document.as_deref(),
+        }
+    }
+}
+
+impl RecordSegmentWriter {
+    // Returns the record as of the log records materialized so far, None if
+    // it does not exist. Records not yet touched by the chunk are read from
+    // the old segment.
+    async fn get_materialized_state(
+        &self,
+        user_id: &str,
+        chunk_state: &HashMap<&str, Option<MaterializedState>>,
+    ) -> Result<Option<MaterializedState>, Box<dyn ChromaError>> {
+        if let Some(state) = chunk_state.get(user_id) {
+            return Ok(state.clone());
+        }
+        let old_record_segment_reader = match &self.old_record_segment_reader {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        if !old_record_segment_reader
+            .data_exists_for_user_id(user_id)
+            .await?
+        {
+            return Ok(None);
+        }
+        let offset_id = old_record_segment_reader
+            .get_offset_id_for_user_id(user_id)
+            .await?;
+        let data_record = old_record_segment_reader
+            .get_data_for_offset_id(offset_id)
+            .await?;
+        Ok(Some(MaterializedState::from_data_record(
+            offset_id,
+            &data_record,
+        )))
+    }
+}
+
 // TODO: remove log materializer, its needless abstraction and complexity
 #[async_trait]
 impl LogMaterializer for RecordSegmentWriter {
+    /// Materializes the log records against the segment and writes them to
+    /// the record segment.
+    /// # Notes
+    /// Log records are applied in order, so a record added earlier in the chunk
+    /// can be updated or deleted by a later one. An upsert is materialized as an
+    /// add or an update depending on whether the record exists. Adding a record
+    /// that exists, and updating or deleting one that does not, is a no-op.
     async fn materialize<'chunk>(
         &self,
         log_records: &'chunk Chunk<LogRecord>,
-    ) -> Chunk<MaterializedLogRecord<'chunk>> {
+    ) -> Result<Chunk<MaterializedLogRecord<'chunk>>, Box<dyn ChromaError>> {
         let mut materialized_records = Vec::new();
-        for (log_entry, index) in log_records.iter() {
-            match log_entry.record.operation {
-                Operation::Add => {
-                    let next_offset_id = self
-                        .curr_max_offset_id
-                        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-
+        // The state of each record touched by the chunk, None once deleted
+        let mut chunk_state: HashMap<&str, Option<MaterializedState>> = HashMap::new();
+        for (log_entry, _) in log_records.iter() {
+            let user_id = log_entry.record.id.as_str();
+            let current = self.get_materialized_state(user_id, &chunk_state).await?;
+            match (&log_entry.record.operation, current) {
+                (Operation::Add | Operation::Upsert, None) => {
+                    let embedding = match &log_entry.record.embedding {
+                        Some(embedding) => embedding.as_slice(),
+                        None => {
+                            return Err(Box::new(LogMaterializerError::EmbeddingMissing(
+                                user_id.to_string(),
+                            )));
+                        }
+                    };
                     let metadata = match &log_entry.record.metadata {
-                        Some(metadata) => match update_metdata_to_metdata(&metadata) {
+                        Some(metadata) => match update_metdata_to_metdata(metadata) {
                             Ok(metadata) => Some(metadata),
                             Err(e) => {
-                                // TODO: this should error out and return an error
-                                panic!("Error converting metadata: {}", e);
+                                return Err(Box::new(LogMaterializerError::MetadataConversion(e)));
                             }
                         },
                         None => None,
                     };
-
-                    let document = match &log_entry.record.document {
-                        Some(document) => Some(document.as_str()),
-                        None => None,
-                    };
-
+                    let next_offset_id = self
+                        .curr_max_offset_id
+                        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
                     let data_record = DataRecord {
-                        id: &log_entry.record.id,
-                        // TODO: don't unwrap here, it should never happen as Adds always have embeddings
-                        // but we should handle this gracefully
-                        embedding: log_entry.record.embedding.as_ref().unwrap(),
-                        document,
+                        id: user_id,
+                        embedding,
+                        sparse_embedding: log_entry.record.sparse_embedding.clone(),
+                        multi_embedding: log_entry.record.multi_embedding.clone(),
+                        document: log_entry.record.document.as_deref(),
                         metadata,
                     };
-                    let materialized =
-                        MaterializedLogRecord::new(next_offset_id, log_entry, data_record);
-                    println!("Writing to id_to_data");
-                    let res = self
-                        .id_to_data
+                    self.id_to_data
                         .as_ref()
                         .unwrap()
-                        .set("", next_offset_id, &materialized.materialized_record)
-                        .await;
-                    println!("Writing to user_id_to_id");
-                    let res = self
-                        .user_id_to_id
+                        .set("", next_offset_id, &data_record)
+                        .await?;
+                    self.user_id_to_id
                         .as_ref()
                         .unwrap()
-                        .set::<&str, u32>("", log_entry.record.id.as_str(), next_offset_id)
-                        .await;
-                    println!("Writing to id_to_user_id");
-                    let res = self
-                        .id_to_user_id
+                        .set::<&str, u32>("", user_id, next_offset_id)
+                        .await?;
+                    self.id_to_user_id
                         .as_ref()
                         .unwrap()
-                        .set("", next_offset_id, log_entry.record.id.as_str())
-                        .await;
-                    println!("Writing to max_offset_id: {}", next_offset_id);
-                    let res = self
-                        .max_offset_id
+                        .set("", next_offset_id, user_id)
+                        .await?;
+                    self.max_offset_id
                         .as_ref()
                         .unwrap()
                         .set("", MAX_OFFSET_ID, next_offset_id)
-                        .await;
-                    // TODO: use res
-                    materialized_records.push(materialized);
+                        .await?;
+                    chunk_state.insert(
+                        user_id,
+                        Some(MaterializedState::from_data_record(
+                            next_offset_id,
+                            &data_record,
+                        )),
+                    );
+                    materialized_records.push(MaterializedLogRecord::new(
+                        next_offset_id,
+                        log_entry,
+                        data_record,
+                        None,
+                    ));
+                }
+                // Adding a record that exists is a no-op
+                (Operation::Add, Some(_)) => {}
+                // Updating or deleting a record that does not exist is a no-op
+                (Operation::Update | Operation::Delete, None) => {}
+                (Operation::Delete, Some(previous)) => {
+                    self.user_id_to_id
+                        .as_ref()
+                        .unwrap()
+                        .delete::<&str, u32>("", user_id)
+                        .await?;
+                    self.id_to_user_id
+                        .as_ref()
+                        .unwrap()
+                        .delete::<u32, &str>("", previous.offset_id)
+                        .await?;
+                    self.id_to_data
+                        .as_ref()
+                        .unwrap()
+                        .delete::<u32, &DataRecord>("", previous.offset_id)
+                        .await?;
+                    chunk_state.insert(user_id, None);
+                    let data_record = DataRecord {
+                        id: user_id,
+                        embedding: &[],
+                        sparse_embedding: None,
+                        multi_embedding: None,
+                        metadata: None,
+                        document: None,
+                    };
+                    materialized_records.push(MaterializedLogRecord::new(
+                        previous.offset_id,
+                        log_entry,
+                        data_record,
+                        Some(previous.record),
+                    ));
+                }
+                (Operation::Update | Operation::Upsert, Some(previous)) => {
+                    let mut metadata = previous.record.metadata.clone().unwrap_or_default();
+                    if let Some(update_metadata) = &log_entry.record.metadata {
+                        for (key, value) in update_metadata.iter() {
+                            match value {
+                                UpdateMetadataValue::None => {
+                                    metadata.remove(key);
+                                }
+                                value => match MetadataValue::try_from(value) {
+                                    Ok(value) => {
+                                        metadata.insert(key.clone(), value);
+                                    }
+                                    Err(e) => {
+                                        return Err(Box::new(
+                                            LogMaterializerError::MetadataConversion(e),
+                                        ));
+                                    }
+                                },
+                            }
+                        }
+                    }
+                    let metadata = match metadata.is_empty() {
+                        true => None,
+                        false => Some(metadata),
+                    };
+                    let document = log_entry.record.document.as_deref();
+
+                    let updated = MaterializedState {
+                        offset_id: previous.offset_id,
+                        embedding: match &log_entry.record.embedding {
+                            Some(embedding) => embedding.clone(),
+                            None => previous.embedding.clone(),
+                        },
+                        record: PreviousRecord {
+                            document: document
+                                .map(|d| d.to_string())
+                                .or_else(|| previous.record.document.clone()),
+                            sparse_embedding: log_entry
+                                .record
+                                .sparse_embedding
+                                .clone()
+                                .or_else(|| previous.record.sparse_embedding.clone()),
+                            multi_embedding: log_entry
+                                .record
+                                .multi_embedding
+                                .clone()
+                                .or_else(|| previous.record.multi_embedding.clone()),
+                            metadata: metadata.clone(),
+                        },
+                    };
+                    self.id_to_data
+                        .as_ref()
+                        .unwrap()
+                        .set("", previous.offset_id, &updated.data_record(user_id))
+                        .await?;
+                    chunk_state.insert(user_id, Some(updated));
+
+                    // The embeddings and document are only materialized if the
+                    // update sets them, the full record is in the segment.
+                    let data_record = DataRecord {
+                        id: user_id,
+                        embedding: match &log_entry.record.embedding {
+                            Some(embedding) => embedding.as_slice(),
+                            None => &[],
+                        },
+                        sparse_embedding: log_entry.record.sparse_embedding.clone(),
+                        multi_embedding: log_entry.record.multi_embedding.clone(),
+                        metadata,
+                        document,
+                    };
+                    materialized_records.push(MaterializedLogRecord::new(
+                        previous.offset_id,
+                        log_entry,
+                        data_record,
+                        Some(previous.record),
+                    ));
                 }
-                Operation::Delete => {}
-                Operation::Update => {}
-                Operation::Upsert => {}
             }
         }
 
-        Chunk::new(materialized_records.into())
+        Ok(Chunk::new(materialized_records.into()))
     }
 }
 
@@ -497,6 +707,8 @@ pub enum RecordSegmentReaderCreationError {
     BlockfileOpenError(#[from] Box<OpenError>),
     #[error("Segment has invalid number of files")]
     InvalidNumberOfFiles,
+    #[error("Invalid record segment configuration")]
+    InvalidConfig(#[from] SegmentConfigError),
 }
 
 impl ChromaError for RecordSegmentReaderCreationError {
@@ -505,6 +717,7 @@ impl ChromaError for RecordSegmentReaderCreationError {
             RecordSegmentReaderCreationError::BlockfileOpenError(e) => e.code(),
             RecordSegmentReaderCreationError::InvalidNumberOfFiles => ErrorCodes::InvalidArgument,
             RecordSegmentReaderCreationError::UninitializedSegment => ErrorCodes::InvalidArgument,
+            RecordSegmentReaderCreationError::InvalidConfig(e) => e.code(),
         }
     }
 }
@@ -514,6 +727,9 @@ impl RecordSegmentReader<'_> {
         segment: &Segment,
         blockfile_provider: &BlockfileProvider,
     ) -> Result<Self, Box<RecordSegmentReaderCreationError>> {
+        if let Err(e) = RecordSegmentConfig::from_segment(segment) {
+            return Err(Box::new(RecordSegmentReaderCreationError::InvalidConfig(e)));
+        }
         let (user_id_to_id, id_to_user_id, id_to_data) = match segment.file_path.len() {
             4 => {
                 let user_id_to_id_bf_id = &segment.file_path.get(USER_ID_TO_OFFSET_ID).unwrap()[0];
@@ -634,7 +850,65 @@ impl RecordSegmentReader<'_> {
         Ok(data)
     }
 
+    /// Returns the offset ids of all data in the record segment, sorted by
+    /// embedding id
+    pub(crate) async fn get_all_offset_ids(&self) -> Result<Vec<u32>, Box<dyn ChromaError>> {
+        let mut offset_ids = Vec::new();
+        let max_size = self.user_id_to_id.count().await?;
+        for i in 0..max_size {
+            let (_, _, offset_id) = self.user_id_to_id.get_at_index(i).await?;
+            offset_ids.push(offset_id);
+        }
+        Ok(offset_ids)
+    }
+
     pub(crate) async fn count(&self) -> Result<usize, Box<dyn ChromaError>> {
         self.id_to_data.count().await
     }
 }
+
+/// A log operation as it applies to the record segment, see `resolve_log`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ResolvedOperation {
+    Add,
+    Update,
+    Delete,
+}
+
+/// Resolve the operations of the log against the records of the segment, as the log
+/// materializer applies them, for readers that merge the log with the segment.
+/// # Returns
+/// The log records that take effect, in log order, with their index in the chunk and
+/// their resolved operation.
+/// # Notes
+/// An upsert is an add or an update depending on whether the record exists. Adding a
+/// record that exists, and updating or deleting one that does not, is a no-op and left
+/// out. Without a reader the segment is empty.
+pub(crate) async fn resolve_log<'log>(
+    logs: &'log Chunk<LogRecord>,
+    reader: Option<&RecordSegmentReader<'_>>,
+) -> Result<Vec<(&'log LogRecord, usize, ResolvedOperation)>, Box<dyn ChromaError>> {
+    // Whether each record touched by the log so far exists
+    let mut exists: HashMap<&str, bool> = HashMap::new();
+    let mut resolved = Vec::new();
+    for (log_record, index) in logs.iter() {
+        let user_id = log_record.record.id.as_str();
+        let record_exists = match (exists.get(user_id), reader) {
+            (Some(record_exists), _) => *record_exists,
+            (None, Some(reader)) => reader.data_exists_for_user_id(user_id).await?,
+            (None, None) => false,
+        };
+        let operation = match (&log_record.record.operation, record_exists) {
+            (Operation::Add | Operation::Upsert, false) => ResolvedOperation::Add,
+            (Operation::Update | Operation::Upsert, true) => ResolvedOperation::Update,
+            (Operation::Delete, true) => ResolvedOperation::Delete,
+            (Operation::Add, true) | (Operation::Update | Operation::Delete, false) => {
+                exists.insert(user_id, record_exists);
+                continue;
+            }
+        };
+        exists.insert(user_id, operation != ResolvedOperation::Delete);
+        resolved.push((log_record, index, operation));
+    }
+    Ok(resolved)
+}
@@ -0,0 +1,1017 @@
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::index::fulltext::normalization::TextNormalization;
+use crate::index::{
+    HnswImplementation, HnswRerankConfig, ScalarQuantizationConfig, DEFAULT_NPROBE,
+    DEFAULT_RERANK_FACTOR, DEFAULT_RESIZE_FACTOR, DEFAULT_TRAINING_ITERATIONS,
+    DEFAULT_VACUUM_THRESHOLD, MAX_NUM_CENTROIDS,
+};
+use crate::types::{Metadata, MetadataValue, Segment, SegmentType};
+use std::fmt::Display;
+use std::ops::{Bound, RangeBounds};
+use thiserror::Error;
+
+// The configuration of a segment is read from its metadata. Every segment type has a
+// schema, which declares the keys it reads, their types, defaults and valid values.
+
+/// An integer config key.
+/// # Fields
+/// - `aliases` - Other names the key is read from, such as the names the python
+///   client writes.
+/// - `default` - The value of the key when the segment does not set it. Keys without
+///   a default are optional, or required if the segment config says so.
+struct IntKey {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    default: Option<i32>,
+    min: Bound<i32>,
+    max: Bound<i32>,
+}
+
+/// A float config key, which also accepts integers.
+struct FloatKey {
+    name: &'static str,
+    default: Option<f64>,
+    min: Bound<f64>,
+    max: Bound<f64>,
+}
+
+/// A string config key. A key without choices accepts any string.
+struct StrKey {
+    name: &'static str,
+    default: Option<&'static str>,
+    choices: &'static [&'static str],
+}
+
+enum ConfigKey {
+    Int(&'static IntKey),
+    Float(&'static FloatKey),
+    Str(&'static StrKey),
+    // A key that is valid but not read by this service, such as the keys of the
+    // local hnsw segment of the python client
+    Ignored(&'static str),
+}
+
+impl ConfigKey {
+    fn names(&self) -> impl Iterator<Item = &'static str> {
+        let (name, aliases): (&'static str, &'static [&'static str]) = match self {
+            ConfigKey::Int(key) => (key.name, key.aliases),
+            ConfigKey::Float(key) => (key.name, &[]),
+            ConfigKey::Str(key) => (key.name, &[]),
+            ConfigKey::Ignored(name) => (name, &[]),
+        };
+        std::iter::once(name).chain(aliases.iter().copied())
+    }
+}
+
+/// The config keys of a segment type.
+/// # Fields
+/// - `namespace` - The prefix of the keys owned by the segment type, such as `hnsw` for
+///   `hnsw:m`. Keys in the namespace that are not in the schema are rejected.
+struct SegmentSchema {
+    namespace: Option<&'static str>,
+    keys: &'static [ConfigKey],
+}
+
+const SPACE: StrKey = StrKey {
+    name: "hnsw:space",
+    default: Some("l2"),
+    choices: &["l2", "cosine", "ip", "hamming", "l1", "jaccard"],
+};
+
+const HNSW_MAX_ELEMENTS: IntKey = IntKey {
+    name: "hnsw:max_elements",
+    aliases: &[],
+    default: Some(1000),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const HNSW_M: IntKey = IntKey {
+    name: "hnsw:m",
+    aliases: &["hnsw:M"],
+    default: Some(16),
+    min: Bound::Included(2),
+    max: Bound::Unbounded,
+};
+const HNSW_EF_CONSTRUCTION: IntKey = IntKey {
+    name: "hnsw:ef_construction",
+    aliases: &["hnsw:construction_ef"],
+    default: Some(100),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const HNSW_EF_SEARCH: IntKey = IntKey {
+    name: "hnsw:ef_search",
+    aliases: &["hnsw:search_ef"],
+    default: Some(10),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const HNSW_RESIZE_FACTOR: FloatKey = FloatKey {
+    name: "hnsw:resize_factor",
+    default: Some(DEFAULT_RESIZE_FACTOR),
+    min: Bound::Excluded(1.0),
+    max: Bound::Unbounded,
+};
+// The native index unless the segment asks for hnswlib, queries proceed while it is
+// written
+const HNSW_IMPLEMENTATION: StrKey = StrKey {
+    name: "hnsw:implementation",
+    default: None,
+    choices: &["hnswlib", "native"],
+};
+const HNSW_VACUUM_THRESHOLD: FloatKey = FloatKey {
+    name: "hnsw:vacuum_threshold",
+    default: Some(DEFAULT_VACUUM_THRESHOLD),
+    min: Bound::Excluded(0.0),
+    max: Bound::Included(1.0),
+};
+const HNSW_VECTORS: StrKey = StrKey {
+    name: "hnsw:vectors",
+    default: Some("embedding"),
+    choices: &["embedding", "multi_vector"],
+};
+const HNSW_RERANK_SPACE: StrKey = StrKey {
+    name: "hnsw:rerank_space",
+    default: None,
+    choices: &["l2", "cosine", "ip", "l1", "jaccard"],
+};
+const HNSW_QUANTIZATION: StrKey = StrKey {
+    name: "hnsw:quantization",
+    default: None,
+    choices: &["int8"],
+};
+const HNSW_RERANK_FACTOR: IntKey = IntKey {
+    name: "hnsw:rerank_factor",
+    aliases: &[],
+    default: Some(DEFAULT_RERANK_FACTOR as i32),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+
+const PQ_NUM_SUBQUANTIZERS: IntKey = IntKey {
+    name: "pq:num_subquantizers",
+    aliases: &[],
+    default: None,
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const PQ_NUM_CENTROIDS: IntKey = IntKey {
+    name: "pq:num_centroids",
+    aliases: &[],
+    default: Some(MAX_NUM_CENTROIDS as i32),
+    min: Bound::Included(1),
+    max: Bound::Included(MAX_NUM_CENTROIDS as i32),
+};
+const PQ_TRAINING_ITERATIONS: IntKey = IntKey {
+    name: "pq:training_iterations",
+    aliases: &[],
+    default: Some(DEFAULT_TRAINING_ITERATIONS as i32),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const PQ_RERANK_FACTOR: IntKey = IntKey {
+    name: "pq:rerank_factor",
+    aliases: &[],
+    default: Some(DEFAULT_RERANK_FACTOR as i32),
+    min: Bound::Included(0),
+    max: Bound::Unbounded,
+};
+
+const IVF_NUM_LISTS: IntKey = IntKey {
+    name: "ivf:num_lists",
+    aliases: &[],
+    default: None,
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const IVF_NPROBE: IntKey = IntKey {
+    name: "ivf:nprobe",
+    aliases: &[],
+    default: Some(DEFAULT_NPROBE as i32),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const IVF_TRAINING_ITERATIONS: IntKey = IntKey {
+    name: "ivf:training_iterations",
+    aliases: &[],
+    default: Some(DEFAULT_TRAINING_ITERATIONS as i32),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+const IVF_QUANTIZATION: StrKey = StrKey {
+    name: "ivf:quantization",
+    default: None,
+    choices: &["int8"],
+};
+const IVF_RERANK_FACTOR: IntKey = IntKey {
+    name: "ivf:rerank_factor",
+    aliases: &[],
+    default: Some(DEFAULT_RERANK_FACTOR as i32),
+    min: Bound::Included(1),
+    max: Bound::Unbounded,
+};
+
+const FULLTEXT_NORMALIZATION: StrKey = StrKey {
+    name: "fulltext:normalization",
+    default: None,
+    choices: &[],
+};
+
+const HNSW_SCHEMA: SegmentSchema = SegmentSchema {
+    namespace: Some("hnsw"),
+    keys: &[
+        ConfigKey::Str(&SPACE),
+        ConfigKey::Int(&HNSW_MAX_ELEMENTS),
+        ConfigKey::Int(&HNSW_M),
+        ConfigKey::Int(&HNSW_EF_CONSTRUCTION),
+        ConfigKey::Int(&HNSW_EF_SEARCH),
+        ConfigKey::Float(&HNSW_RESIZE_FACTOR),
+        ConfigKey::Str(&HNSW_IMPLEMENTATION),
+        ConfigKey::Float(&HNSW_VACUUM_THRESHOLD),
+        ConfigKey::Str(&HNSW_VECTORS),
+        ConfigKey::Str(&HNSW_RERANK_SPACE),
+        ConfigKey::Str(&HNSW_QUANTIZATION),
+        ConfigKey::Int(&HNSW_RERANK_FACTOR),
+        ConfigKey::Ignored("hnsw:num_threads"),
+        ConfigKey::Ignored("hnsw:batch_size"),
+        ConfigKey::Ignored("hnsw:sync_threshold"),
+    ],
+};
+
+const PQ_SCHEMA: SegmentSchema = SegmentSchema {
+    namespace: Some("pq"),
+    keys: &[
+        ConfigKey::Str(&SPACE),
+        ConfigKey::Int(&PQ_NUM_SUBQUANTIZERS),
+        ConfigKey::Int(&PQ_NUM_CENTROIDS),
+        ConfigKey::Int(&PQ_TRAINING_ITERATIONS),
+        ConfigKey::Int(&PQ_RERANK_FACTOR),
+    ],
+};
+
+const IVF_SCHEMA: SegmentSchema = SegmentSchema {
+    namespace: Some("ivf"),
+    keys: &[
+        ConfigKey::Str(&SPACE),
+        ConfigKey::Int(&IVF_NUM_LISTS),
+        ConfigKey::Int(&IVF_NPROBE),
+        ConfigKey::Int(&IVF_TRAINING_ITERATIONS),
+        ConfigKey::Str(&IVF_QUANTIZATION),
+        ConfigKey::Int(&IVF_RERANK_FACTOR),
+    ],
+};
+
+const METADATA_SCHEMA: SegmentSchema = SegmentSchema {
+    namespace: Some("fulltext"),
+    keys: &[ConfigKey::Str(&FULLTEXT_NORMALIZATION)],
+};
+
+// Segment types without config keys
+const EMPTY_SCHEMA: SegmentSchema = SegmentSchema {
+    namespace: None,
+    keys: &[],
+};
+
+const SCHEMAS: &[&SegmentSchema] = &[&HNSW_SCHEMA, &PQ_SCHEMA, &IVF_SCHEMA, &METADATA_SCHEMA];
+
+fn schema(segment_type: &SegmentType) -> &'static SegmentSchema {
+    match segment_type {
+        SegmentType::HnswDistributed => &HNSW_SCHEMA,
+        SegmentType::PqDistributed => &PQ_SCHEMA,
+        SegmentType::IvfDistributed => &IVF_SCHEMA,
+        SegmentType::BlockfileMetadata => &METADATA_SCHEMA,
+        SegmentType::SparseVectorDistributed | SegmentType::Record | SegmentType::Sqlite => {
+            &EMPTY_SCHEMA
+        }
+    }
+}
+
+// Keys within this edit distance of a known key are taken to be misspelled
+const MAX_MISSPELLING_DISTANCE: usize = 2;
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum SegmentConfigError {
+    #[error("Unknown config `{0}`")]
+    UnknownKey(String),
+    #[error("Unknown config `{0}`, did you mean `{1}`?")]
+    MisspelledKey(String, &'static str),
+    #[error("Missing config `{0}`")]
+    MissingKey(&'static str),
+    #[error("Config `{0}` must be {1}")]
+    InvalidType(String, &'static str),
+    #[error("Config `{0}` must be {1}, got {2}")]
+    OutOfRange(String, String, String),
+    #[error("Config `{0}` must be one of {1}, got `{2}`")]
+    InvalidChoice(String, String, String),
+    #[error("Invalid config `{0}`: {1}")]
+    InvalidValue(String, String),
+}
+
+impl ChromaError for SegmentConfigError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+// The optimal string alignment distance, the number of insertions, deletions,
+// substitutions and transpositions of adjacent characters that turn a into b.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for j in 0..=b.len() {
+        distances[0][j] = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+// The name closest to the key, if it is close enough to be a misspelling
+fn closest_key(key: &str, names: impl Iterator<Item = &'static str>) -> Option<&'static str> {
+    names
+        .map(|name| (edit_distance(key, name), name))
+        .filter(|(distance, _)| *distance <= MAX_MISSPELLING_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+// A description of the values within the bounds, such as "at least 1"
+fn range_description<T: Display>(min: &Bound<T>, max: &Bound<T>) -> String {
+    let lower = match min {
+        Bound::Included(min) => Some(format!("at least {}", min)),
+        Bound::Excluded(min) => Some(format!("greater than {}", min)),
+        Bound::Unbounded => None,
+    };
+    let upper = match max {
+        Bound::Included(max) => Some(format!("at most {}", max)),
+        Bound::Excluded(max) => Some(format!("less than {}", max)),
+        Bound::Unbounded => None,
+    };
+    match (lower, upper) {
+        (Some(lower), Some(upper)) => format!("{} and {}", lower, upper),
+        (Some(bound), None) | (None, Some(bound)) => bound,
+        (None, None) => "any value".to_string(),
+    }
+}
+
+impl SegmentSchema {
+    fn contains(&self, key: &str) -> bool {
+        self.keys
+            .iter()
+            .any(|config_key| config_key.names().any(|name| name == key))
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.keys.iter().flat_map(|config_key| config_key.names())
+    }
+
+    /// Checks that the metadata sets no unknown keys.
+    /// # Notes
+    /// Segment metadata may carry the keys of other segment types and keys that are
+    /// not config at all, which are ignored. A key in the namespace of the segment type
+    /// is rejected if the schema does not declare it, and a key in an unknown namespace
+    /// is rejected if it is a misspelling of a key of any schema, such as `hsnw:m`.
+    fn validate(&self, metadata: &Metadata) -> Result<(), SegmentConfigError> {
+        for key in metadata.keys() {
+            if self.contains(key) {
+                continue;
+            }
+            let namespace = key.split_once(':').map(|(namespace, _)| namespace);
+            if namespace.is_some() && namespace == self.namespace {
+                return Err(match closest_key(key, self.names()) {
+                    Some(closest_key) => {
+                        SegmentConfigError::MisspelledKey(key.clone(), closest_key)
+                    }
+                    None => SegmentConfigError::UnknownKey(key.clone()),
+                });
+            }
+            if SCHEMAS
+                .iter()
+                .any(|schema| namespace.is_some() && schema.namespace == namespace)
+            {
+                continue;
+            }
+            let names = SCHEMAS.iter().flat_map(|schema| schema.names());
+            if let Some(closest_key) = closest_key(key, names) {
+                return Err(SegmentConfigError::MisspelledKey(key.clone(), closest_key));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads the config keys of a segment from its validated metadata
+struct ConfigReader<'a> {
+    metadata: Option<&'a Metadata>,
+}
+
+impl<'a> ConfigReader<'a> {
+    fn new(segment: &'a Segment) -> Result<Self, SegmentConfigError> {
+        if let Some(metadata) = &segment.metadata {
+            schema(&segment.r#type).validate(metadata)?;
+        }
+        Ok(ConfigReader {
+            metadata: segment.metadata.as_ref(),
+        })
+    }
+
+    // The key the segment sets, by its name or an alias, and its value
+    fn get(
+        &self,
+        name: &'static str,
+        aliases: &'static [&'static str],
+    ) -> Option<(&'static str, &'a MetadataValue)> {
+        let metadata = self.metadata?;
+        std::iter::once(name)
+            .chain(aliases.iter().copied())
+            .find_map(|key| metadata.get(key).map(|value| (key, value)))
+    }
+
+    fn int(&self, key: &IntKey) -> Result<Option<usize>, SegmentConfigError> {
+        let (name, value) = match self.get(key.name, key.aliases) {
+            Some((name, MetadataValue::Int(value))) => (name, *value),
+            Some((name, _)) => {
+                return Err(SegmentConfigError::InvalidType(
+                    name.to_string(),
+                    "an integer",
+                ))
+            }
+            None => return Ok(key.default.map(|default| default as usize)),
+        };
+        if !(key.min, key.max).contains(&value) {
+            return Err(SegmentConfigError::OutOfRange(
+                name.to_string(),
+                range_description(&key.min, &key.max),
+                value.to_string(),
+            ));
+        }
+        Ok(Some(value as usize))
+    }
+
+    fn float(&self, key: &FloatKey) -> Result<Option<f64>, SegmentConfigError> {
+        let value = match self.get(key.name, &[]) {
+            Some((_, MetadataValue::Float(value))) => *value,
+            Some((_, MetadataValue::Int(value))) => *value as f64,
+            Some((name, _)) => {
+                return Err(SegmentConfigError::InvalidType(
+                    name.to_string(),
+                    "a number",
+                ))
+            }
+            None => return Ok(key.default),
+        };
+        if !(key.min, key.max).contains(&value) {
+            return Err(SegmentConfigError::OutOfRange(
+                key.name.to_string(),
+                range_description(&key.min, &key.max),
+                value.to_string(),
+            ));
+        }
+        Ok(Some(value))
+    }
+
+    fn str(&self, key: &StrKey) -> Result<Option<&'a str>, SegmentConfigError> {
+        let value = match self.get(key.name, &[]) {
+            Some((_, MetadataValue::Str(value))) => value.as_str(),
+            Some((name, _)) => {
+                return Err(SegmentConfigError::InvalidType(
+                    name.to_string(),
+                    "a string",
+                ))
+            }
+            None => return Ok(key.default),
+        };
+        if !key.choices.is_empty() && !key.choices.contains(&value) {
+            return Err(SegmentConfigError::InvalidChoice(
+                key.name.to_string(),
+                key.choices.join(", "),
+                value.to_string(),
+            ));
+        }
+        Ok(Some(value))
+    }
+}
+
+// The value of a key the schema declares a default for
+fn required<T>(value: Option<T>, name: &'static str) -> Result<T, SegmentConfigError> {
+    value.ok_or(SegmentConfigError::MissingKey(name))
+}
+
+fn distance_function(
+    reader: &ConfigReader,
+    key: &StrKey,
+) -> Result<Option<DistanceFunction>, SegmentConfigError> {
+    match reader.str(key)? {
+        Some(space) => match DistanceFunction::try_from(space) {
+            Ok(distance_function) => Ok(Some(distance_function)),
+            Err(e) => Err(SegmentConfigError::InvalidValue(
+                key.name.to_string(),
+                e.to_string(),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The distance function of a vector segment, set with the `hnsw:space` key for all
+/// vector segment types. The default is `l2`.
+pub(crate) fn distance_function_from_segment(
+    segment: &Segment,
+) -> Result<DistanceFunction, SegmentConfigError> {
+    let reader = ConfigReader::new(segment)?;
+    required(distance_function(&reader, &SPACE)?, SPACE.name)
+}
+
+/// The configuration of an hnsw segment.
+/// # Fields
+/// - `max_elements` - The initial capacity of a new index, `hnsw:max_elements`.
+/// - `m` - The number of neighbors of an element, `hnsw:m`.
+/// - `ef_construction` - The number of candidates when adding, `hnsw:ef_construction`.
+/// - `ef_search` - The number of candidates when querying, `hnsw:ef_search`.
+/// - `resize_factor` - The factor the capacity grows by, `hnsw:resize_factor`.
+/// - `implementation` - The implementation of the index, `hnsw:implementation`.
+/// - `vacuum_threshold` - The deleted ratio at which compaction rebuilds the index,
+///   `hnsw:vacuum_threshold`.
+/// - `multi_vector` - Whether the index holds the vectors of the records'
+///   multi-vector embeddings, `hnsw:vectors` set to `multi_vector`.
+/// - `rerank` - The rerank stage of a hamming index, `hnsw:rerank_space` and
+///   `hnsw:rerank_factor`. None if the segment sets no rerank space.
+/// - `quantization` - Whether the index searches int8 quantized vectors,
+///   `hnsw:quantization` set to `int8`, and the `hnsw:rerank_factor` of its results.
+/// # Notes
+/// The python client names `hnsw:m`, `hnsw:ef_construction` and `hnsw:ef_search`
+/// `hnsw:M`, `hnsw:construction_ef` and `hnsw:search_ef`, which are read as well.
+/// The native implementation is the default, since queries proceed while it is written.
+/// Only the native implementation quantizes, so quantization is rejected with hnswlib,
+/// multi-vectors or hamming.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HnswSegmentConfig {
+    pub(crate) max_elements: usize,
+    pub(crate) m: usize,
+    pub(crate) ef_construction: usize,
+    pub(crate) ef_search: usize,
+    pub(crate) resize_factor: f64,
+    pub(crate) implementation: HnswImplementation,
+    pub(crate) vacuum_threshold: f64,
+    pub(crate) multi_vector: bool,
+    pub(crate) rerank: Option<HnswRerankConfig>,
+    pub(crate) quantization: Option<ScalarQuantizationConfig>,
+}
+
+impl HnswSegmentConfig {
+    pub(crate) fn from_segment(segment: &Segment) -> Result<Self, SegmentConfigError> {
+        let reader = ConfigReader::new(segment)?;
+        let multi_vector = reader.str(&HNSW_VECTORS)? == Some("multi_vector");
+        let quantization = match reader.str(&HNSW_QUANTIZATION)? {
+            Some(_) => {
+                let unsupported = if multi_vector {
+                    Some("multi-vector indices")
+                } else if required(distance_function(&reader, &SPACE)?, SPACE.name)?
+                    == DistanceFunction::Hamming
+                {
+                    Some("hamming indices")
+                } else if reader.str(&HNSW_IMPLEMENTATION)? == Some("hnswlib") {
+                    Some("the hnswlib implementation")
+                } else {
+                    None
+                };
+                if let Some(unsupported) = unsupported {
+                    return Err(SegmentConfigError::InvalidValue(
+                        HNSW_QUANTIZATION.name.to_string(),
+                        format!("{} are not quantized", unsupported),
+                    ));
+                }
+                Some(ScalarQuantizationConfig {
+                    rerank_factor: required(
+                        reader.int(&HNSW_RERANK_FACTOR)?,
+                        HNSW_RERANK_FACTOR.name,
+                    )?,
+                })
+            }
+            None => None,
+        };
+        let implementation = match reader.str(&HNSW_IMPLEMENTATION)? {
+            Some("hnswlib") => HnswImplementation::Hnswlib,
+            _ => HnswImplementation::Native,
+        };
+        let rerank = match distance_function(&reader, &HNSW_RERANK_SPACE)? {
+            Some(distance_function) => Some(HnswRerankConfig {
+                rerank_factor: required(reader.int(&HNSW_RERANK_FACTOR)?, HNSW_RERANK_FACTOR.name)?,
+                distance_function,
+            }),
+            None => None,
+        };
+        Ok(HnswSegmentConfig {
+            max_elements: required(reader.int(&HNSW_MAX_ELEMENTS)?, HNSW_MAX_ELEMENTS.name)?,
+            m: required(reader.int(&HNSW_M)?, HNSW_M.name)?,
+            ef_construction: required(
+                reader.int(&HNSW_EF_CONSTRUCTION)?,
+                HNSW_EF_CONSTRUCTION.name,
+            )?,
+            ef_search: required(reader.int(&HNSW_EF_SEARCH)?, HNSW_EF_SEARCH.name)?,
+            resize_factor: required(reader.float(&HNSW_RESIZE_FACTOR)?, HNSW_RESIZE_FACTOR.name)?,
+            implementation,
+            vacuum_threshold: required(
+                reader.float(&HNSW_VACUUM_THRESHOLD)?,
+                HNSW_VACUUM_THRESHOLD.name,
+            )?,
+            multi_vector,
+            rerank,
+            quantization,
+        })
+    }
+}
+
+/// The configuration of a product quantized segment, see `PqIndexConfig`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PqSegmentConfig {
+    pub(crate) num_subquantizers: Option<usize>,
+    pub(crate) num_centroids: usize,
+    pub(crate) training_iterations: usize,
+    pub(crate) rerank_factor: usize,
+}
+
+impl PqSegmentConfig {
+    pub(crate) fn from_segment(segment: &Segment) -> Result<Self, SegmentConfigError> {
+        let reader = ConfigReader::new(segment)?;
+        Ok(PqSegmentConfig {
+            num_subquantizers: reader.int(&PQ_NUM_SUBQUANTIZERS)?,
+            num_centroids: required(reader.int(&PQ_NUM_CENTROIDS)?, PQ_NUM_CENTROIDS.name)?,
+            training_iterations: required(
+                reader.int(&PQ_TRAINING_ITERATIONS)?,
+                PQ_TRAINING_ITERATIONS.name,
+            )?,
+            rerank_factor: required(reader.int(&PQ_RERANK_FACTOR)?, PQ_RERANK_FACTOR.name)?,
+        })
+    }
+}
+
+/// The configuration of an inverted file segment, see `IvfIndexConfig`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct IvfSegmentConfig {
+    pub(crate) num_lists: Option<usize>,
+    pub(crate) nprobe: usize,
+    pub(crate) training_iterations: usize,
+    pub(crate) quantization: Option<ScalarQuantizationConfig>,
+}
+
+impl IvfSegmentConfig {
+    pub(crate) fn from_segment(segment: &Segment) -> Result<Self, SegmentConfigError> {
+        let reader = ConfigReader::new(segment)?;
+        let quantization = match reader.str(&IVF_QUANTIZATION)? {
+            Some(_) => Some(ScalarQuantizationConfig {
+                rerank_factor: required(reader.int(&IVF_RERANK_FACTOR)?, IVF_RERANK_FACTOR.name)?,
+            }),
+            None => None,
+        };
+        Ok(IvfSegmentConfig {
+            num_lists: reader.int(&IVF_NUM_LISTS)?,
+            nprobe: required(reader.int(&IVF_NPROBE)?, IVF_NPROBE.name)?,
+            training_iterations: required(
+                reader.int(&IVF_TRAINING_ITERATIONS)?,
+                IVF_TRAINING_ITERATIONS.name,
+            )?,
+            quantization,
+        })
+    }
+}
+
+/// The configuration of a metadata segment.
+/// # Fields
+/// - `full_text_normalization` - The normalization of a new full text index, the
+///   comma separated options of `fulltext:normalization`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MetadataSegmentConfig {
+    pub(crate) full_text_normalization: TextNormalization,
+}
+
+impl MetadataSegmentConfig {
+    pub(crate) fn from_segment(segment: &Segment) -> Result<Self, SegmentConfigError> {
+        let reader = ConfigReader::new(segment)?;
+        let full_text_normalization = match reader.str(&FULLTEXT_NORMALIZATION)? {
+            Some(value) => {
+                let options: Vec<String> =
+                    value.split(',').map(|option| option.to_string()).collect();
+                match TextNormalization::from_options(&options) {
+                    Ok(normalization) => normalization,
+                    Err(e) => {
+                        return Err(SegmentConfigError::InvalidValue(
+                            FULLTEXT_NORMALIZATION.name.to_string(),
+                            e.to_string(),
+                        ))
+                    }
+                }
+            }
+            None => TextNormalization::default(),
+        };
+        Ok(MetadataSegmentConfig {
+            full_text_normalization,
+        })
+    }
+}
+
+/// The configuration of a record segment, which has no keys. Reading it checks that
+/// the segment sets no misspelled keys.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RecordSegmentConfig {}
+
+impl RecordSegmentConfig {
+    pub(crate) fn from_segment(segment: &Segment) -> Result<Self, SegmentConfigError> {
+        ConfigReader::new(segment)?;
+        Ok(RecordSegmentConfig {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::SegmentScope;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn segment(r#type: SegmentType, metadata: &[(&str, MetadataValue)]) -> Segment {
+        Segment {
+            id: Uuid::new_v4(),
+            r#type,
+            scope: SegmentScope::VECTOR,
+            collection: None,
+            metadata: Some(
+                metadata
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.clone()))
+                    .collect(),
+            ),
+            file_path: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_hnsw_defaults_and_aliases() {
+        let mut hnsw_segment = segment(SegmentType::HnswDistributed, &[]);
+        hnsw_segment.metadata = None;
+        let config = HnswSegmentConfig::from_segment(&hnsw_segment).unwrap();
+        assert_eq!(
+            config,
+            HnswSegmentConfig {
+                max_elements: 1000,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 10,
+                resize_factor: DEFAULT_RESIZE_FACTOR,
+                implementation: HnswImplementation::Native,
+                vacuum_threshold: DEFAULT_VACUUM_THRESHOLD,
+                multi_vector: false,
+                rerank: None,
+                quantization: None,
+            }
+        );
+        // Empty metadata has the same defaults as no metadata
+        let empty = segment(SegmentType::HnswDistributed, &[]);
+        assert_eq!(HnswSegmentConfig::from_segment(&empty).unwrap(), config);
+
+        // The keys of the python client
+        let python = segment(
+            SegmentType::HnswDistributed,
+            &[
+                ("hnsw:M", MetadataValue::Int(8)),
+                ("hnsw:construction_ef", MetadataValue::Int(20)),
+                ("hnsw:search_ef", MetadataValue::Int(30)),
+                ("hnsw:resize_factor", MetadataValue::Int(2)),
+                ("hnsw:batch_size", MetadataValue::Int(100)),
+            ],
+        );
+        let config = HnswSegmentConfig::from_segment(&python).unwrap();
+        assert_eq!(config.m, 8);
+        assert_eq!(config.ef_construction, 20);
+        assert_eq!(config.ef_search, 30);
+        assert_eq!(config.resize_factor, 2.0);
+    }
+
+    #[test]
+    fn test_rejects_unknown_and_misspelled_keys() {
+        let misspelled = segment(
+            SegmentType::HnswDistributed,
+            &[("hsnw:max_elements", MetadataValue::Int(10))],
+        );
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&misspelled),
+            Err(SegmentConfigError::MisspelledKey(
+                "hsnw:max_elements".to_string(),
+                "hnsw:max_elements"
+            ))
+        );
+        let misspelled = segment(
+            SegmentType::HnswDistributed,
+            &[("hnsw:ef_serch", MetadataValue::Int(10))],
+        );
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&misspelled),
+            Err(SegmentConfigError::MisspelledKey(
+                "hnsw:ef_serch".to_string(),
+                "hnsw:ef_search"
+            ))
+        );
+        let unknown = segment(
+            SegmentType::HnswDistributed,
+            &[("hnsw:foobar", MetadataValue::Str("blarg".to_string()))],
+        );
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&unknown),
+            Err(SegmentConfigError::UnknownKey("hnsw:foobar".to_string()))
+        );
+
+        // Keys of other segment types and keys that are not config are ignored
+        let other = segment(
+            SegmentType::HnswDistributed,
+            &[
+                ("ivf:nprobe", MetadataValue::Int(4)),
+                ("fulltext:normalization", MetadataValue::Int(1)),
+                ("owner", MetadataValue::Str("me".to_string())),
+            ],
+        );
+        assert!(HnswSegmentConfig::from_segment(&other).is_ok());
+        let record = segment(SegmentType::Record, &[("hnsw:m", MetadataValue::Int(8))]);
+        assert!(RecordSegmentConfig::from_segment(&record).is_ok());
+        let record = segment(SegmentType::Record, &[("hsnw:m", MetadataValue::Int(8))]);
+        assert_eq!(
+            RecordSegmentConfig::from_segment(&record),
+            Err(SegmentConfigError::MisspelledKey(
+                "hsnw:m".to_string(),
+                "hnsw:m"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_validates_values() {
+        let invalid = segment(
+            SegmentType::HnswDistributed,
+            &[("hnsw:resize_factor", MetadataValue::Float(0.5))],
+        );
+        let error = HnswSegmentConfig::from_segment(&invalid).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Config `hnsw:resize_factor` must be greater than 1, got 0.5"
+        );
+        let invalid = segment(
+            SegmentType::HnswDistributed,
+            &[("hnsw:vacuum_threshold", MetadataValue::Float(1.5))],
+        );
+        assert!(matches!(
+            HnswSegmentConfig::from_segment(&invalid),
+            Err(SegmentConfigError::OutOfRange(_, _, _))
+        ));
+        let invalid = segment(
+            SegmentType::HnswDistributed,
+            &[("hnsw:m", MetadataValue::Str("16".to_string()))],
+        );
+        assert_eq!(
+            HnswSegmentConfig::from_segment(&invalid),
+            Err(SegmentConfigError::InvalidType(
+                "hnsw:m".to_string(),
+                "an integer"
+            ))
+        );
+        let invalid = segment(
+            SegmentType::HnswDistributed,
+            &[("hnsw:vectors", MetadataValue::Str("tokens".to_string()))],
+        );
+        let error = HnswSegmentConfig::from_segment(&invalid).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Config `hnsw:vectors` must be one of embedding, multi_vector, got `tokens`"
+        );
+        let invalid = segment(
+            SegmentType::PqDistributed,
+            &[("pq:num_centroids", MetadataValue::Int(1000))],
+        );
+        assert_eq!(
+            PqSegmentConfig::from_segment(&invalid)
+                .unwrap_err()
+                .to_string(),
+            "Config `pq:num_centroids` must be at least 1 and at most 256, got 1000"
+        );
+
+        for (space, distance_function) in [
+            ("l1", DistanceFunction::Manhattan),
+            ("jaccard", DistanceFunction::Jaccard),
+        ] {
+            let valid = segment(
+                SegmentType::HnswDistributed,
+                &[("hnsw:space", MetadataValue::Str(space.to_string()))],
+            );
+            assert_eq!(
+                distance_function_from_segment(&valid),
+                Ok(distance_function)
+            );
+        }
+        let invalid = segment(
+            SegmentType::HnswDistributed,
+            &[("hnsw:space", MetadataValue::Str("l3".to_string()))],
+        );
+        assert!(matches!(
+            distance_function_from_segment(&invalid),
+            Err(SegmentConfigError::InvalidChoice(_, _, _))
+        ));
+    }
+
+    #[test]
+    fn test_hnsw_quantization() {
+        let quantized = segment(
+            SegmentType::HnswDistributed,
+            &[
+                ("hnsw:quantization", MetadataValue::Str("int8".to_string())),
+                ("hnsw:rerank_factor", MetadataValue::Int(3)),
+            ],
+        );
+        let config = HnswSegmentConfig::from_segment(&quantized).unwrap();
+        assert_eq!(
+            config.quantization,
+            Some(ScalarQuantizationConfig { rerank_factor: 3 })
+        );
+        // Only the native implementation quantizes
+        assert_eq!(config.implementation, HnswImplementation::Native);
+
+        for (key, value) in [
+            ("hnsw:implementation", "hnswlib"),
+            ("hnsw:vectors", "multi_vector"),
+            ("hnsw:space", "hamming"),
+        ] {
+            let invalid = segment(
+                SegmentType::HnswDistributed,
+                &[
+                    ("hnsw:quantization", MetadataValue::Str("int8".to_string())),
+                    (key, MetadataValue::Str(value.to_string())),
+                ],
+            );
+            assert!(matches!(
+                HnswSegmentConfig::from_segment(&invalid),
+                Err(SegmentConfigError::InvalidValue(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_metadata_segment_config() {
+        let metadata_segment = segment(SegmentType::BlockfileMetadata, &[]);
+        assert_eq!(
+            MetadataSegmentConfig::from_segment(&metadata_segment)
+                .unwrap()
+                .full_text_normalization,
+            TextNormalization::default()
+        );
+        let metadata_segment = segment(
+            SegmentType::BlockfileMetadata,
+            &[(
+                "fulltext:normalization",
+                MetadataValue::Str("case_fold, strip_accents".to_string()),
+            )],
+        );
+        assert_eq!(
+            MetadataSegmentConfig::from_segment(&metadata_segment)
+                .unwrap()
+                .full_text_normalization,
+            TextNormalization {
+                case_fold: true,
+                strip_accents: true,
+            }
+        );
+        let metadata_segment = segment(
+            SegmentType::BlockfileMetadata,
+            &[("fulltext:normalization", MetadataValue::Int(1))],
+        );
+        assert_eq!(
+            MetadataSegmentConfig::from_segment(&metadata_segment),
+            Err(SegmentConfigError::InvalidType(
+                "fulltext:normalization".to_string(),
+                "a string"
+            ))
+        );
+        let metadata_segment = segment(
+            SegmentType::BlockfileMetadata,
+            &[(
+                "fulltext:tokenizer",
+                MetadataValue::Str("ngram".to_string()),
+            )],
+        );
+        assert!(matches!(
+            MetadataSegmentConfig::from_segment(&metadata_segment),
+            Err(SegmentConfigError::UnknownKey(_))
+        ));
+    }
+}
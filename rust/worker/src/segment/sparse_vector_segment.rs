@@ -0,0 +1,789 @@
+use super::{MaterializedLogRecord, SegmentFlusher, SegmentWriter};
+use crate::blockstore::provider::{BlockfileProvider, CreateError, OpenError};
+use crate::blockstore::{BlockfileFlusher, BlockfileWriter};
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::execution::data::data_chunk::Chunk;
+use crate::index::IndexFilter;
+use crate::types::{Operation, Segment, SparseVector};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fmt::Debug;
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+const SPARSE_POSTINGS: &str = "sparse_postings";
+
+#[derive(Error, Debug)]
+pub enum SparseVectorSegmentFromSegmentError {
+    #[error("Sparse vector segment uninitialized")]
+    Uninitialized,
+    #[error("Missing file: {0}")]
+    MissingFile(String),
+    #[error("Invalid Uuid for file: {0}")]
+    InvalidUuid(String),
+    #[error("Blockfile Creation Error")]
+    BlockfileCreateError(#[from] Box<CreateError>),
+    #[error("Blockfile Open Error")]
+    BlockfileOpenError(#[from] Box<OpenError>),
+}
+
+impl ChromaError for SparseVectorSegmentFromSegmentError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            SparseVectorSegmentFromSegmentError::Uninitialized => ErrorCodes::InvalidArgument,
+            SparseVectorSegmentFromSegmentError::MissingFile(_) => ErrorCodes::NotFound,
+            SparseVectorSegmentFromSegmentError::InvalidUuid(_) => ErrorCodes::InvalidArgument,
+            SparseVectorSegmentFromSegmentError::BlockfileCreateError(e) => e.code(),
+            SparseVectorSegmentFromSegmentError::BlockfileOpenError(e) => e.code(),
+        }
+    }
+}
+
+// The id of the postings blockfile, None if the segment has no files yet
+fn postings_id_from_segment(
+    segment: &Segment,
+) -> Result<Option<Uuid>, Box<SparseVectorSegmentFromSegmentError>> {
+    if segment.file_path.is_empty() {
+        return Ok(None);
+    }
+    let id = match segment.file_path.get(SPARSE_POSTINGS) {
+        Some(ids) if !ids.is_empty() => &ids[0],
+        _ => {
+            return Err(Box::new(SparseVectorSegmentFromSegmentError::MissingFile(
+                SPARSE_POSTINGS.to_string(),
+            )))
+        }
+    };
+    match Uuid::parse_str(id) {
+        Ok(id) => Ok(Some(id)),
+        Err(_) => Err(Box::new(SparseVectorSegmentFromSegmentError::InvalidUuid(
+            SPARSE_POSTINGS.to_string(),
+        ))),
+    }
+}
+
+// The postings of a token are stored under the token as the prefix, keyed by offset id
+// with the weight of the token in the record as the value.
+fn token_prefix(token: u32) -> String {
+    token.to_string()
+}
+
+/// Writes an inverted index of the sparse embeddings of a collection. Postings are
+/// buffered as the log is applied and written out by `write_to_blockfiles`.
+#[derive(Clone)]
+pub(crate) struct SparseVectorSegmentWriter {
+    postings_blockfile_writer: BlockfileWriter,
+    // (token, offset id) -> the new weight, or None if the posting is removed
+    uncommitted_postings: Arc<Mutex<BTreeMap<(u32, u32), Option<f32>>>>,
+    pub(crate) id: Uuid,
+}
+
+impl Debug for SparseVectorSegmentWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SparseVectorSegmentWriter")
+    }
+}
+
+impl SparseVectorSegmentWriter {
+    pub(crate) async fn from_segment(
+        segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
+    ) -> Result<SparseVectorSegmentWriter, Box<SparseVectorSegmentFromSegmentError>> {
+        let postings_blockfile_writer = match postings_id_from_segment(segment)? {
+            None => {
+                println!("No files found, creating new blockfile for sparse vector segment");
+                blockfile_provider.create::<u32, f32>()
+            }
+            Some(postings_id) => {
+                println!("Found files, forking blockfile for sparse vector segment");
+                blockfile_provider.fork::<u32, f32>(&postings_id).await
+            }
+        };
+        match postings_blockfile_writer {
+            Ok(postings_blockfile_writer) => Ok(SparseVectorSegmentWriter {
+                postings_blockfile_writer,
+                uncommitted_postings: Arc::new(Mutex::new(BTreeMap::new())),
+                id: segment.id,
+            }),
+            Err(e) => Err(Box::new(
+                SparseVectorSegmentFromSegmentError::BlockfileCreateError(e),
+            )),
+        }
+    }
+
+    fn add_postings(&self, offset_id: u32, vector: &SparseVector) {
+        let mut uncommitted_postings = self.uncommitted_postings.lock();
+        for (token, weight) in vector.iter() {
+            uncommitted_postings.insert((token, offset_id), Some(weight));
+        }
+    }
+
+    fn delete_postings(&self, offset_id: u32, vector: &SparseVector) {
+        let mut uncommitted_postings = self.uncommitted_postings.lock();
+        for (token, _) in vector.iter() {
+            uncommitted_postings.insert((token, offset_id), None);
+        }
+    }
+
+    /// Write the buffered changes to the blockfile, must be called before commit.
+    pub(crate) async fn write_to_blockfiles(&mut self) -> Result<(), Box<dyn ChromaError>> {
+        let uncommitted_postings = std::mem::take(&mut *self.uncommitted_postings.lock());
+        for ((token, offset_id), weight) in uncommitted_postings {
+            match weight {
+                Some(weight) => {
+                    self.postings_blockfile_writer
+                        .set(&token_prefix(token), offset_id, weight)
+                        .await?
+                }
+                None => {
+                    self.postings_blockfile_writer
+                        .delete::<u32, f32>(&token_prefix(token), offset_id)
+                        .await?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn commit_index(self) -> Result<SparseVectorSegmentFlusher, Box<dyn ChromaError>> {
+        match self.postings_blockfile_writer.commit::<u32, f32>() {
+            Ok(postings_blockfile_flusher) => Ok(SparseVectorSegmentFlusher {
+                postings_blockfile_flusher,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl SegmentWriter for SparseVectorSegmentWriter {
+    fn apply_materialized_log_chunk(
+        &self,
+        records: Chunk<MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        for record in records.iter() {
+            let segment_offset_id = record.0.segment_offset_id;
+            let previous_sparse_embedding = record
+                .0
+                .previous_record
+                .as_ref()
+                .and_then(|previous| previous.sparse_embedding.as_ref());
+            match record.0.log_record.record.operation {
+                // Updates without a sparse embedding leave the postings alone
+                Operation::Add | Operation::Upsert | Operation::Update => {
+                    let sparse_embedding = match &record.0.materialized_record.sparse_embedding {
+                        Some(sparse_embedding) => sparse_embedding,
+                        None => continue,
+                    };
+                    if let Some(previous) = previous_sparse_embedding {
+                        self.delete_postings(segment_offset_id, previous);
+                    }
+                    self.add_postings(segment_offset_id, sparse_embedding);
+                }
+                Operation::Delete => {
+                    if let Some(previous) = previous_sparse_embedding {
+                        self.delete_postings(segment_offset_id, previous);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(self) -> Result<impl SegmentFlusher, Box<dyn ChromaError>> {
+        self.commit_index()
+    }
+}
+
+pub(crate) struct SparseVectorSegmentFlusher {
+    postings_blockfile_flusher: BlockfileFlusher,
+}
+
+impl Debug for SparseVectorSegmentFlusher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SparseVectorSegmentFlusher")
+    }
+}
+
+#[async_trait]
+impl SegmentFlusher for SparseVectorSegmentFlusher {
+    async fn flush(self) -> Result<HashMap<String, Vec<String>>, Box<dyn ChromaError>> {
+        let postings_id = self.postings_blockfile_flusher.id();
+        match self.postings_blockfile_flusher.flush::<u32, f32>().await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        let mut flushed_files = HashMap::new();
+        flushed_files.insert(SPARSE_POSTINGS.to_string(), vec![postings_id.to_string()]);
+        Ok(flushed_files)
+    }
+}
+
+/// Reads a sparse vector segment. The postings blockfile is opened per query, and a
+/// query only loads the postings of its own tokens.
+#[derive(Clone)]
+pub(crate) struct SparseVectorSegmentReader {
+    postings_id: Uuid,
+    blockfile_provider: BlockfileProvider,
+    pub(crate) id: Uuid,
+}
+
+impl Debug for SparseVectorSegmentReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SparseVectorSegmentReader")
+    }
+}
+
+impl SparseVectorSegmentReader {
+    pub(crate) fn from_segment(
+        segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
+    ) -> Result<SparseVectorSegmentReader, Box<SparseVectorSegmentFromSegmentError>> {
+        let postings_id = match postings_id_from_segment(segment)? {
+            Some(postings_id) => postings_id,
+            None => return Err(Box::new(SparseVectorSegmentFromSegmentError::Uninitialized)),
+        };
+        Ok(SparseVectorSegmentReader {
+            postings_id,
+            blockfile_provider: blockfile_provider.clone(),
+            id: segment.id,
+        })
+    }
+
+    /// The offset ids of the k records with the largest dot product with the vector
+    /// among the ids the filter allows, and their dot products, largest first.
+    pub(crate) async fn query(
+        &self,
+        vector: &SparseVector,
+        k: usize,
+        filter: &IndexFilter<'_>,
+    ) -> Result<(Vec<u32>, Vec<f32>), Box<dyn ChromaError>> {
+        let postings_reader = match self
+            .blockfile_provider
+            .open::<u32, f32>(&self.postings_id)
+            .await
+        {
+            Ok(reader) => reader,
+            Err(e) => return Err(e),
+        };
+        let mut terms = Vec::with_capacity(vector.len());
+        for (token, weight) in vector.iter() {
+            // A token without postings has no entries under its prefix
+            let postings: Vec<(u32, f32)> = postings_reader
+                .get_by_prefix(&token_prefix(token))
+                .await?
+                .into_iter()
+                .map(|(_, offset_id, posting_weight)| (offset_id, posting_weight))
+                .collect();
+            if !postings.is_empty() {
+                terms.push(Term::new(weight, postings));
+            }
+        }
+        Ok(max_score(terms, k, filter))
+    }
+}
+
+// The postings of one query token, sorted by offset id.
+struct Term {
+    query_weight: f32,
+    postings: Vec<(u32, f32)>,
+    // The most a record can gain from this token
+    upper_bound: f32,
+    position: usize,
+}
+
+impl Term {
+    fn new(query_weight: f32, postings: Vec<(u32, f32)>) -> Self {
+        let upper_bound = postings
+            .iter()
+            .map(|(_, weight)| query_weight * weight)
+            .fold(0.0, f32::max);
+        Term {
+            query_weight,
+            postings,
+            upper_bound,
+            position: 0,
+        }
+    }
+
+    fn current(&self) -> Option<u32> {
+        self.postings
+            .get(self.position)
+            .map(|(offset_id, _)| *offset_id)
+    }
+
+    // Moves to the first posting at or past the offset id, and returns the score of
+    // the offset id if the token has a posting for it.
+    fn advance_to(&mut self, offset_id: u32) -> Option<f32> {
+        self.position += self.postings[self.position..].partition_point(|(id, _)| *id < offset_id);
+        match self.postings.get(self.position) {
+            Some((id, weight)) if *id == offset_id => {
+                self.position += 1;
+                Some(self.query_weight * weight)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct Scored {
+    score: f32,
+    offset_id: u32,
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.offset_id.cmp(&self.offset_id))
+    }
+}
+
+// MaxScore over the postings of the query tokens. The tokens are ordered by their
+// upper bounds, and the smallest ones whose bounds add up to at most the k-th best
+// score so far are non-essential: a record with none of the other tokens can not make
+// the top k. Candidates come from the essential tokens only, and the non-essential
+// tokens are probed for a candidate until its score can no longer make the top k.
+fn max_score(mut terms: Vec<Term>, k: usize, filter: &IndexFilter) -> (Vec<u32>, Vec<f32>) {
+    if k == 0 {
+        return (vec![], vec![]);
+    }
+    terms.sort_by(|a, b| a.upper_bound.total_cmp(&b.upper_bound));
+    // cumulative_bounds[i] is the sum of the upper bounds of terms 0..=i
+    let cumulative_bounds: Vec<f32> = terms
+        .iter()
+        .scan(0.0, |sum, term| {
+            *sum += term.upper_bound;
+            Some(*sum)
+        })
+        .collect();
+
+    let mut top_k: BinaryHeap<Reverse<Scored>> = BinaryHeap::with_capacity(k + 1);
+    let mut first_essential = 0;
+    loop {
+        let candidate = match terms[first_essential..]
+            .iter()
+            .filter_map(|term| term.current())
+            .min()
+        {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        let mut score = 0.0;
+        for term in terms[first_essential..].iter_mut() {
+            if let Some(contribution) = term.advance_to(candidate) {
+                score += contribution;
+            }
+        }
+        if !filter.allows(candidate as usize) {
+            continue;
+        }
+
+        let threshold = match top_k.len() == k {
+            true => top_k.peek().map(|Reverse(scored)| scored.score),
+            false => None,
+        };
+        let mut pruned = false;
+        for i in (0..first_essential).rev() {
+            if let Some(threshold) = threshold {
+                if score + cumulative_bounds[i] <= threshold {
+                    pruned = true;
+                    break;
+                }
+            }
+            if let Some(contribution) = terms[i].advance_to(candidate) {
+                score += contribution;
+            }
+        }
+        if pruned || threshold.map_or(false, |threshold| score <= threshold) {
+            continue;
+        }
+
+        top_k.push(Reverse(Scored {
+            score,
+            offset_id: candidate,
+        }));
+        if top_k.len() > k {
+            top_k.pop();
+        }
+        if top_k.len() == k {
+            let threshold = top_k
+                .peek()
+                .map_or(f32::MIN, |Reverse(scored)| scored.score);
+            while first_essential < terms.len() && cumulative_bounds[first_essential] <= threshold {
+                first_essential += 1;
+            }
+        }
+    }
+
+    let mut offset_ids = Vec::with_capacity(top_k.len());
+    let mut scores = Vec::with_capacity(top_k.len());
+    for Reverse(scored) in top_k.into_sorted_vec() {
+        offset_ids.push(scored.offset_id);
+        scores.push(scored.score);
+    }
+    (offset_ids, scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::{DataRecord, LogMaterializer, PreviousRecord};
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{LogRecord, OperationRecord, SegmentScope, SegmentType};
+    use rand::{Rng, SeedableRng};
+    use roaring::RoaringBitmap;
+
+    fn random_sparse_vector(rng: &mut impl Rng, vocabulary: u32, nnz: usize) -> SparseVector {
+        let mut indices: Vec<u32> = (0..nnz).map(|_| rng.gen_range(0..vocabulary)).collect();
+        indices.sort();
+        indices.dedup();
+        let values = indices.iter().map(|_| rng.gen_range(0.0..1.0)).collect();
+        SparseVector::new(indices, values).unwrap()
+    }
+
+    fn brute_force(
+        vectors: &[(u32, SparseVector)],
+        query: &SparseVector,
+        k: usize,
+        filter: &IndexFilter,
+    ) -> Vec<(u32, f32)> {
+        let mut scored: Vec<(u32, f32)> = vectors
+            .iter()
+            .filter(|(offset_id, _)| filter.allows(*offset_id as usize))
+            .map(|(offset_id, vector)| (*offset_id, query.dot(vector)))
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(k);
+        scored
+    }
+
+    fn terms_for(vectors: &[(u32, SparseVector)], query: &SparseVector) -> Vec<Term> {
+        query
+            .iter()
+            .filter_map(|(token, weight)| {
+                let postings: Vec<(u32, f32)> = vectors
+                    .iter()
+                    .filter_map(|(offset_id, vector)| {
+                        vector
+                            .iter()
+                            .find(|(index, _)| *index == token)
+                            .map(|(_, value)| (*offset_id, value))
+                    })
+                    .collect();
+                match postings.is_empty() {
+                    true => None,
+                    false => Some(Term::new(weight, postings)),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_max_score_matches_brute_force() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let vectors: Vec<(u32, SparseVector)> = (0..500)
+            .map(|offset_id| (offset_id, random_sparse_vector(&mut rng, 100, 10)))
+            .collect();
+        let mut allowed = RoaringBitmap::new();
+        allowed.insert_range(0..250);
+        let filters = [
+            IndexFilter::new(None, None),
+            IndexFilter::new(Some(&allowed), None),
+            IndexFilter::new(None, Some(&allowed)),
+        ];
+        for _ in 0..20 {
+            let query = random_sparse_vector(&mut rng, 100, 8);
+            for filter in filters.iter() {
+                for k in [1, 10, 1000] {
+                    let expected = brute_force(&vectors, &query, k, filter);
+                    let (offset_ids, scores) = max_score(terms_for(&vectors, &query), k, filter);
+                    assert_eq!(offset_ids.len(), expected.len());
+                    for (i, (offset_id, score)) in expected.iter().enumerate() {
+                        assert!((scores[i] - score).abs() < 1e-5);
+                        // Ties may come out in either order
+                        if i + 1 < expected.len() && expected[i + 1].1 != *score {
+                            assert_eq!(offset_ids[i], *offset_id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sparse_vector_segment_write_then_query() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::SparseVectorDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let vectors = [
+            SparseVector::new(vec![1, 2], vec![1.0, 0.5]).unwrap(),
+            SparseVector::new(vec![2, 3], vec![2.0, 1.0]).unwrap(),
+            SparseVector::new(vec![1, 3], vec![0.5, 3.0]).unwrap(),
+        ];
+        let log_records: Vec<LogRecord> = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, vector)| LogRecord {
+                log_offset: i as i64,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i),
+                    embedding: None,
+                    encoding: None,
+                    sparse_embedding: Some(vector.clone()),
+                    multi_embedding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect();
+        let materialized: Vec<MaterializedLogRecord> = log_records
+            .iter()
+            .enumerate()
+            .map(|(i, log_record)| {
+                let data_record = DataRecord {
+                    id: &log_record.record.id,
+                    embedding: &[],
+                    sparse_embedding: log_record.record.sparse_embedding.clone(),
+                    multi_embedding: None,
+                    metadata: None,
+                    document: None,
+                };
+                MaterializedLogRecord::new(i as u32 + 1, log_record, data_record, None)
+            })
+            .collect();
+
+        let mut writer = SparseVectorSegmentWriter::from_segment(&segment, &blockfile_provider)
+            .await
+            .unwrap();
+        writer
+            .apply_materialized_log_chunk(Chunk::new(materialized.into()))
+            .unwrap();
+        writer.write_to_blockfiles().await.unwrap();
+        let flusher = writer.commit().unwrap();
+        segment.file_path = flusher.flush().await.unwrap();
+
+        let reader =
+            SparseVectorSegmentReader::from_segment(&segment, &blockfile_provider).unwrap();
+        let query = SparseVector::new(vec![1, 3], vec![1.0, 1.0]).unwrap();
+        let (offset_ids, scores) = reader
+            .query(&query, 2, &IndexFilter::new(None, None))
+            .await
+            .unwrap();
+        // Ties go to the smaller offset id
+        assert_eq!(offset_ids, vec![3, 1]);
+        assert_eq!(scores, vec![3.5, 1.0]);
+
+        // Deleting a record removes its postings
+        let delete_record = LogRecord {
+            log_offset: 3,
+            record: OperationRecord {
+                id: "embedding_id_2".to_string(),
+                embedding: None,
+                encoding: None,
+                sparse_embedding: None,
+                multi_embedding: None,
+                metadata: None,
+                document: None,
+                operation: Operation::Delete,
+            },
+        };
+        let deleted = MaterializedLogRecord::new(
+            3,
+            &delete_record,
+            DataRecord {
+                id: &delete_record.record.id,
+                embedding: &[],
+                sparse_embedding: None,
+                multi_embedding: None,
+                metadata: None,
+                document: None,
+            },
+            Some(PreviousRecord {
+                sparse_embedding: Some(vectors[2].clone()),
+                ..Default::default()
+            }),
+        );
+        let mut writer = SparseVectorSegmentWriter::from_segment(&segment, &blockfile_provider)
+            .await
+            .unwrap();
+        writer
+            .apply_materialized_log_chunk(Chunk::new(vec![deleted].into()))
+            .unwrap();
+        writer.write_to_blockfiles().await.unwrap();
+        let flusher = writer.commit().unwrap();
+        segment.file_path = flusher.flush().await.unwrap();
+
+        let reader =
+            SparseVectorSegmentReader::from_segment(&segment, &blockfile_provider).unwrap();
+        let (offset_ids, scores) = reader
+            .query(&query, 2, &IndexFilter::new(None, None))
+            .await
+            .unwrap();
+        assert_eq!(offset_ids, vec![1, 2]);
+        assert_eq!(scores, vec![1.0, 1.0]);
+    }
+
+    fn sparse_log_record(
+        log_offset: i64,
+        id: &str,
+        sparse_embedding: Option<SparseVector>,
+        operation: Operation,
+    ) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: match operation {
+                    Operation::Add | Operation::Upsert => Some(vec![1.0, 2.0, 3.0]),
+                    _ => None,
+                },
+                encoding: None,
+                sparse_embedding,
+                multi_embedding: None,
+                metadata: None,
+                document: None,
+                operation,
+            },
+        }
+    }
+
+    async fn compact(
+        log_records: Vec<LogRecord>,
+        record_segment: &mut Segment,
+        sparse_vector_segment: &mut Segment,
+        blockfile_provider: &BlockfileProvider,
+    ) {
+        let log_records: Chunk<LogRecord> = Chunk::new(log_records.into());
+        let record_segment_writer =
+            RecordSegmentWriter::from_segment(record_segment, blockfile_provider)
+                .await
+                .unwrap();
+        let mut sparse_vector_segment_writer =
+            SparseVectorSegmentWriter::from_segment(sparse_vector_segment, blockfile_provider)
+                .await
+                .unwrap();
+        // Offset ids are only known once the log is materialized
+        assert!(sparse_vector_segment_writer
+            .apply_log_chunk(log_records.clone())
+            .is_err());
+        let materialized = record_segment_writer
+            .materialize(&log_records)
+            .await
+            .unwrap();
+        sparse_vector_segment_writer
+            .apply_materialized_log_chunk(materialized)
+            .unwrap();
+        sparse_vector_segment_writer
+            .write_to_blockfiles()
+            .await
+            .unwrap();
+        record_segment.file_path = record_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+        sparse_vector_segment.file_path = sparse_vector_segment_writer
+            .commit()
+            .unwrap()
+            .flush()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_updates_replace_postings() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let mut record_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::Record,
+            scope: SegmentScope::RECORD,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut sparse_vector_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::SparseVectorDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let token = |token: u32| SparseVector::new(vec![token], vec![1.0]).unwrap();
+        compact(
+            vec![sparse_log_record(1, "a", Some(token(1)), Operation::Add)],
+            &mut record_segment,
+            &mut sparse_vector_segment,
+            &blockfile_provider,
+        )
+        .await;
+        compact(
+            vec![
+                // A record in the segment updated twice
+                sparse_log_record(2, "a", Some(token(2)), Operation::Update),
+                sparse_log_record(3, "a", Some(token(3)), Operation::Upsert),
+                // A record added and updated in the same chunk
+                sparse_log_record(4, "b", Some(token(4)), Operation::Add),
+                sparse_log_record(5, "b", Some(token(5)), Operation::Update),
+                // An update without a sparse embedding keeps the postings
+                sparse_log_record(6, "b", None, Operation::Update),
+            ],
+            &mut record_segment,
+            &mut sparse_vector_segment,
+            &blockfile_provider,
+        )
+        .await;
+
+        let reader =
+            SparseVectorSegmentReader::from_segment(&sparse_vector_segment, &blockfile_provider)
+                .unwrap();
+        let filter = IndexFilter::new(None, None);
+        for (query_token, expected) in [(1, 0), (2, 0), (3, 1), (4, 0), (5, 1)] {
+            let (offset_ids, _) = reader
+                .query(&token(query_token), 10, &filter)
+                .await
+                .unwrap();
+            assert_eq!(offset_ids.len(), expected, "token {}", query_token);
+        }
+
+        // Deleting the record in a later compaction removes its current postings
+        compact(
+            vec![sparse_log_record(7, "a", None, Operation::Delete)],
+            &mut record_segment,
+            &mut sparse_vector_segment,
+            &blockfile_provider,
+        )
+        .await;
+        let reader =
+            SparseVectorSegmentReader::from_segment(&sparse_vector_segment, &blockfile_provider)
+                .unwrap();
+        let (offset_ids, _) = reader.query(&token(3), 10, &filter).await.unwrap();
+        assert!(offset_ids.is_empty());
+    }
+}
@@ -1,15 +1,22 @@
 use std::collections::HashMap;
 
-use crate::errors::ChromaError;
+use crate::errors::{ChromaError, ErrorCodes};
 use crate::execution::data::data_chunk::Chunk;
-use crate::types::{LogRecord, Metadata};
+use crate::types::{
+    LogRecord, Metadata, MetadataValueConversionError, MultiVector, Operation, SparseVector,
+};
 use async_trait::async_trait;
+use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct MaterializedLogRecord<'a> {
     pub(super) segment_offset_id: u32,
     pub(super) log_record: &'a LogRecord,
     pub(super) materialized_record: DataRecord<'a>,
+    // The record before an update or delete, so that segments indexing it can
+    // remove it. None if the log record adds the record, which is how an
+    // upsert of a new record is told apart from an upsert of an existing one.
+    pub(super) previous_record: Option<PreviousRecord>,
 }
 
 impl<'a> MaterializedLogRecord<'a> {
@@ -17,19 +24,43 @@ impl<'a> MaterializedLogRecord<'a> {
         segment_offset_id: u32,
         log_record: &'a LogRecord,
         materialized_record: DataRecord<'a>,
+        previous_record: Option<PreviousRecord>,
     ) -> Self {
         Self {
             segment_offset_id,
             log_record,
             materialized_record,
+            previous_record,
         }
     }
+
+    /// The operation the record applies to the segment, with an upsert
+    /// resolved to an add or an update of the record.
+    pub(crate) fn operation(&self) -> Operation {
+        match (&self.log_record.record.operation, &self.previous_record) {
+            (Operation::Upsert, None) => Operation::Add,
+            (Operation::Upsert, Some(_)) => Operation::Update,
+            (operation, _) => operation.clone(),
+        }
+    }
+}
+
+/// The indexed fields of a record before an update or delete.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PreviousRecord {
+    pub(crate) document: Option<String>,
+    pub(crate) sparse_embedding: Option<SparseVector>,
+    // The vectors of a multi-vector embedding are indexed separately.
+    pub(crate) multi_embedding: Option<MultiVector>,
+    pub(crate) metadata: Option<Metadata>,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct DataRecord<'a> {
     pub(crate) id: &'a str,
     pub(crate) embedding: &'a [f32],
+    pub(crate) sparse_embedding: Option<SparseVector>,
+    pub(crate) multi_embedding: Option<MultiVector>,
     pub(crate) metadata: Option<Metadata>,
     pub(crate) document: Option<&'a str>,
 }
@@ -38,22 +69,59 @@ impl DataRecord<'_> {
     pub(crate) fn get_size(&self) -> usize {
         let id_size = self.id.len();
         let embedding_size = self.embedding.len() * std::mem::size_of::<f32>();
+        let sparse_embedding_size = match &self.sparse_embedding {
+            Some(sparse_embedding) => {
+                sparse_embedding.len() * (std::mem::size_of::<u32>() + std::mem::size_of::<f32>())
+            }
+            None => 0,
+        };
+        let multi_embedding_size = match &self.multi_embedding {
+            Some(multi_embedding) => multi_embedding.values.len() * std::mem::size_of::<f32>(),
+            None => 0,
+        };
         // TODO: use serialized_metadata size to calculate the size
         let metadata_size = 0;
         let document_size = match self.document {
             Some(document) => document.len(),
             None => 0,
         };
-        id_size + embedding_size + metadata_size + document_size
+        id_size
+            + embedding_size
+            + sparse_embedding_size
+            + multi_embedding_size
+            + metadata_size
+            + document_size
     }
 }
 
 pub(crate) trait SegmentWriter {
-    fn apply_materialized_log_chunk(&self, records: Chunk<MaterializedLogRecord>);
-    fn apply_log_chunk(&self, records: Chunk<LogRecord>);
+    fn apply_materialized_log_chunk(
+        &self,
+        records: Chunk<MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>>;
+    // Segments index records by their offset in the record segment, which only the
+    // materializer knows, so by default writers reject log chunks that are not
+    // materialized.
+    fn apply_log_chunk(&self, _records: Chunk<LogRecord>) -> Result<(), Box<dyn ChromaError>> {
+        Err(Box::new(SegmentWriterError::UnmaterializedLogChunk))
+    }
     fn commit(self) -> Result<impl SegmentFlusher, Box<dyn ChromaError>>;
 }
 
+#[derive(Error, Debug)]
+pub(crate) enum SegmentWriterError {
+    #[error("Log chunk must be materialized before it is applied to the segment")]
+    UnmaterializedLogChunk,
+}
+
+impl ChromaError for SegmentWriterError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            SegmentWriterError::UnmaterializedLogChunk => ErrorCodes::Unimplemented,
+        }
+    }
+}
+
 #[async_trait]
 pub(crate) trait SegmentFlusher {
     async fn flush(self) -> Result<HashMap<String, Vec<String>>, Box<dyn ChromaError>>;
@@ -64,7 +132,24 @@ pub(crate) trait LogMaterializer: SegmentWriter {
     async fn materialize<'chunk>(
         &self,
         records: &'chunk Chunk<LogRecord>,
-    ) -> Chunk<MaterializedLogRecord<'chunk>>;
+    ) -> Result<Chunk<MaterializedLogRecord<'chunk>>, Box<dyn ChromaError>>;
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum LogMaterializerError {
+    #[error("Error converting metadata")]
+    MetadataConversion(#[from] MetadataValueConversionError),
+    #[error("Record {0} is added without an embedding")]
+    EmbeddingMissing(String),
+}
+
+impl ChromaError for LogMaterializerError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            LogMaterializerError::MetadataConversion(e) => e.code(),
+            LogMaterializerError::EmbeddingMissing(_) => ErrorCodes::InvalidArgument,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -88,6 +173,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -99,6 +186,8 @@ mod tests {
                     id: "embedding_id_2".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -110,6 +199,8 @@ mod tests {
                     id: "embedding_id_1".to_string(),
                     embedding: None,
                     encoding: None,
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: None,
                     document: None,
                     operation: Operation::Add,
@@ -126,9 +217,12 @@ mod tests {
                 materialized_record: DataRecord {
                     id: &record.0.record.id,
                     embedding: &[],
+                    sparse_embedding: None,
+                    multi_embedding: None,
                     metadata: metadata_1.clone(),
                     document: None,
                 },
+                previous_record: None,
             })
             .collect::<Vec<_>>();
 
@@ -0,0 +1,328 @@
+use super::distributed_hnsw_segment::{
+    DistributedHNSWSegmentFromSegmentError, DistributedHNSWSegmentReader,
+    DistributedHNSWSegmentWriter,
+};
+use super::distributed_ivf_segment::{
+    DistributedIvfSegmentFlusher, DistributedIvfSegmentFromSegmentError,
+    DistributedIvfSegmentReader, DistributedIvfSegmentWriter,
+};
+use super::distributed_pq_segment::{
+    DistributedPQSegmentFromSegmentError, DistributedPQSegmentReader, DistributedPQSegmentWriter,
+};
+use super::{MaterializedLogRecord, SegmentFlusher, SegmentWriter};
+use crate::blockstore::provider::BlockfileProvider;
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::execution::data::data_chunk::Chunk;
+use crate::index::hnsw_provider::HnswIndexProvider;
+use crate::index::pq_provider::PqIndexProvider;
+use crate::index::{HnswSearchParams, IndexFilter};
+use crate::types::{LogRecord, Segment, SegmentType};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// The vector segment types, a collection has one of them.
+pub(crate) fn is_vector_segment_type(segment_type: &SegmentType) -> bool {
+    match segment_type {
+        SegmentType::HnswDistributed | SegmentType::PqDistributed | SegmentType::IvfDistributed => {
+            true
+        }
+        _ => false,
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum VectorSegmentFromSegmentError {
+    #[error("Not a vector segment type `{0}`")]
+    InvalidSegmentType(String),
+    #[error(transparent)]
+    HnswSegmentError(#[from] DistributedHNSWSegmentFromSegmentError),
+    #[error(transparent)]
+    PqSegmentError(#[from] DistributedPQSegmentFromSegmentError),
+    #[error(transparent)]
+    IvfSegmentError(#[from] DistributedIvfSegmentFromSegmentError),
+}
+
+impl VectorSegmentFromSegmentError {
+    /// Whether the segment has not been compacted yet, and has no files to read.
+    pub(crate) fn is_uninitialized(&self) -> bool {
+        match self {
+            VectorSegmentFromSegmentError::HnswSegmentError(
+                DistributedHNSWSegmentFromSegmentError::Uninitialized,
+            ) => true,
+            VectorSegmentFromSegmentError::PqSegmentError(
+                DistributedPQSegmentFromSegmentError::Uninitialized,
+            ) => true,
+            VectorSegmentFromSegmentError::IvfSegmentError(
+                DistributedIvfSegmentFromSegmentError::Uninitialized,
+            ) => true,
+            _ => false,
+        }
+    }
+}
+
+impl ChromaError for VectorSegmentFromSegmentError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            VectorSegmentFromSegmentError::InvalidSegmentType(_) => ErrorCodes::InvalidArgument,
+            VectorSegmentFromSegmentError::HnswSegmentError(e) => e.code(),
+            VectorSegmentFromSegmentError::PqSegmentError(e) => e.code(),
+            VectorSegmentFromSegmentError::IvfSegmentError(e) => e.code(),
+        }
+    }
+}
+
+/// A writer for the vector segment of a collection, of any vector segment type.
+#[derive(Clone, Debug)]
+pub(crate) enum VectorSegmentWriter {
+    Hnsw(Box<DistributedHNSWSegmentWriter>),
+    Pq(Box<DistributedPQSegmentWriter>),
+    Ivf(Box<DistributedIvfSegmentWriter>),
+}
+
+impl VectorSegmentWriter {
+    /// The record segment of the collection is read by hnsw segments that rebuild
+    /// their index from its live vectors.
+    pub(crate) async fn from_segment(
+        segment: &Segment,
+        dimensionality: usize,
+        hnsw_index_provider: HnswIndexProvider,
+        pq_index_provider: PqIndexProvider,
+        blockfile_provider: &BlockfileProvider,
+        record_segment: &Segment,
+    ) -> Result<VectorSegmentWriter, Box<VectorSegmentFromSegmentError>> {
+        match segment.r#type {
+            SegmentType::HnswDistributed => {
+                match DistributedHNSWSegmentWriter::from_segment(
+                    segment,
+                    dimensionality,
+                    hnsw_index_provider,
+                    record_segment,
+                    blockfile_provider,
+                )
+                .await
+                {
+                    Ok(writer) => Ok(VectorSegmentWriter::Hnsw(writer)),
+                    Err(e) => Err(Box::new(VectorSegmentFromSegmentError::HnswSegmentError(
+                        *e,
+                    ))),
+                }
+            }
+            SegmentType::PqDistributed => {
+                match DistributedPQSegmentWriter::from_segment(
+                    segment,
+                    dimensionality,
+                    pq_index_provider,
+                )
+                .await
+                {
+                    Ok(writer) => Ok(VectorSegmentWriter::Pq(writer)),
+                    Err(e) => Err(Box::new(VectorSegmentFromSegmentError::PqSegmentError(*e))),
+                }
+            }
+            SegmentType::IvfDistributed => {
+                match DistributedIvfSegmentWriter::from_segment(
+                    segment,
+                    dimensionality,
+                    blockfile_provider,
+                )
+                .await
+                {
+                    Ok(writer) => Ok(VectorSegmentWriter::Ivf(writer)),
+                    Err(e) => Err(Box::new(VectorSegmentFromSegmentError::IvfSegmentError(*e))),
+                }
+            }
+            _ => Err(Box::new(VectorSegmentFromSegmentError::InvalidSegmentType(
+                segment.r#type.clone().into(),
+            ))),
+        }
+    }
+
+    pub(crate) fn id(&self) -> Uuid {
+        match self {
+            VectorSegmentWriter::Hnsw(writer) => writer.id,
+            VectorSegmentWriter::Pq(writer) => writer.id,
+            VectorSegmentWriter::Ivf(writer) => writer.id,
+        }
+    }
+
+    /// Write the changes buffered by segments that write to blockfiles, must be called
+    /// before commit.
+    pub(crate) async fn write_to_blockfiles(&mut self) -> Result<(), Box<dyn ChromaError>> {
+        match self {
+            VectorSegmentWriter::Hnsw(_) | VectorSegmentWriter::Pq(_) => Ok(()),
+            VectorSegmentWriter::Ivf(writer) => writer.write_to_blockfiles().await,
+        }
+    }
+}
+
+impl SegmentWriter for VectorSegmentWriter {
+    fn apply_materialized_log_chunk(
+        &self,
+        records: Chunk<MaterializedLogRecord>,
+    ) -> Result<(), Box<dyn ChromaError>> {
+        match self {
+            VectorSegmentWriter::Hnsw(writer) => writer.apply_materialized_log_chunk(records),
+            VectorSegmentWriter::Pq(writer) => writer.apply_materialized_log_chunk(records),
+            VectorSegmentWriter::Ivf(writer) => writer.apply_materialized_log_chunk(records),
+        }
+    }
+
+    fn apply_log_chunk(&self, records: Chunk<LogRecord>) -> Result<(), Box<dyn ChromaError>> {
+        match self {
+            VectorSegmentWriter::Hnsw(writer) => writer.apply_log_chunk(records),
+            VectorSegmentWriter::Pq(writer) => writer.apply_log_chunk(records),
+            VectorSegmentWriter::Ivf(writer) => writer.apply_log_chunk(records),
+        }
+    }
+
+    fn commit(self) -> Result<impl SegmentFlusher, Box<dyn ChromaError>> {
+        // The HNSW and PQ writers are their own flushers
+        match self {
+            VectorSegmentWriter::Hnsw(writer) => {
+                writer.as_ref().clone().commit()?;
+                Ok(VectorSegmentFlusher::Hnsw(writer))
+            }
+            VectorSegmentWriter::Pq(writer) => {
+                writer.as_ref().clone().commit()?;
+                Ok(VectorSegmentFlusher::Pq(writer))
+            }
+            VectorSegmentWriter::Ivf(writer) => match writer.commit_index() {
+                Ok(flusher) => Ok(VectorSegmentFlusher::Ivf(Box::new(flusher))),
+                Err(e) => Err(e),
+            },
+        }
+    }
+}
+
+pub(crate) enum VectorSegmentFlusher {
+    Hnsw(Box<DistributedHNSWSegmentWriter>),
+    Pq(Box<DistributedPQSegmentWriter>),
+    Ivf(Box<DistributedIvfSegmentFlusher>),
+}
+
+#[async_trait]
+impl SegmentFlusher for VectorSegmentFlusher {
+    async fn flush(self) -> Result<HashMap<String, Vec<String>>, Box<dyn ChromaError>> {
+        match self {
+            VectorSegmentFlusher::Hnsw(writer) => writer.flush().await,
+            VectorSegmentFlusher::Pq(writer) => writer.flush().await,
+            VectorSegmentFlusher::Ivf(flusher) => flusher.flush().await,
+        }
+    }
+}
+
+/// A reader for the vector segment of a collection, of any vector segment type.
+#[derive(Clone, Debug)]
+pub(crate) enum VectorSegmentReader {
+    Hnsw(Box<DistributedHNSWSegmentReader>),
+    Pq(Box<DistributedPQSegmentReader>),
+    Ivf(Box<DistributedIvfSegmentReader>),
+}
+
+impl VectorSegmentReader {
+    pub(crate) async fn from_segment(
+        segment: &Segment,
+        dimensionality: usize,
+        hnsw_index_provider: HnswIndexProvider,
+        pq_index_provider: PqIndexProvider,
+        blockfile_provider: &BlockfileProvider,
+    ) -> Result<VectorSegmentReader, Box<VectorSegmentFromSegmentError>> {
+        match segment.r#type {
+            SegmentType::HnswDistributed => {
+                match DistributedHNSWSegmentReader::from_segment(
+                    segment,
+                    dimensionality,
+                    hnsw_index_provider,
+                )
+                .await
+                {
+                    Ok(reader) => Ok(VectorSegmentReader::Hnsw(reader)),
+                    Err(e) => Err(Box::new(VectorSegmentFromSegmentError::HnswSegmentError(
+                        *e,
+                    ))),
+                }
+            }
+            SegmentType::PqDistributed => {
+                match DistributedPQSegmentReader::from_segment(
+                    segment,
+                    dimensionality,
+                    pq_index_provider,
+                )
+                .await
+                {
+                    Ok(reader) => Ok(VectorSegmentReader::Pq(reader)),
+                    Err(e) => Err(Box::new(VectorSegmentFromSegmentError::PqSegmentError(*e))),
+                }
+            }
+            SegmentType::IvfDistributed => {
+                match DistributedIvfSegmentReader::from_segment(
+                    segment,
+                    dimensionality,
+                    blockfile_provider,
+                )
+                .await
+                {
+                    Ok(reader) => Ok(VectorSegmentReader::Ivf(reader)),
+                    Err(e) => Err(Box::new(VectorSegmentFromSegmentError::IvfSegmentError(*e))),
+                }
+            }
+            _ => Err(Box::new(VectorSegmentFromSegmentError::InvalidSegmentType(
+                segment.r#type.clone().into(),
+            ))),
+        }
+    }
+
+    /// The nearest neighbors of the vector. Approximate segments return
+    /// rerank_factor() * k candidates, see rerank_factor(). Hnsw segments take the
+    /// ef and exact search params, ivf segments the nprobe. Hnsw segments indexing multi-vectors return the records with
+    /// the nearest vectors.
+    pub(crate) async fn query(
+        &self,
+        vector: &[f32],
+        k: usize,
+        filter: &IndexFilter<'_>,
+        params: &HnswSearchParams,
+    ) -> Result<(Vec<usize>, Vec<f32>), Box<dyn ChromaError>> {
+        match self {
+            VectorSegmentReader::Hnsw(reader) if reader.multi_vector => {
+                Ok(reader.query_multi_vector(vector, k, filter, params))
+            }
+            VectorSegmentReader::Hnsw(reader) => Ok(reader.query(vector, k, filter, params)),
+            VectorSegmentReader::Pq(reader) => Ok(reader.query(vector, k, filter)),
+            VectorSegmentReader::Ivf(reader) => {
+                reader.query(vector, k, params.nprobe, filter).await
+            }
+        }
+    }
+
+    /// The number of candidates query() returns per result that should be reranked with
+    /// the full vectors, and the distance function to rerank with. None if the results
+    /// should not be reranked.
+    pub(crate) fn rerank(&self) -> Option<(usize, DistanceFunction)> {
+        match self {
+            VectorSegmentReader::Hnsw(reader) => match reader.rerank_factor() {
+                0 => None,
+                rerank_factor => Some((rerank_factor, reader.distance_function())),
+            },
+            VectorSegmentReader::Pq(reader) => match reader.rerank_factor() {
+                0 => None,
+                rerank_factor => Some((rerank_factor, reader.distance_function())),
+            },
+            VectorSegmentReader::Ivf(reader) => match reader.rerank_factor() {
+                0 => None,
+                rerank_factor => Some((rerank_factor, reader.distance_function())),
+            },
+        }
+    }
+
+    /// Whether the segment indexes the vectors of multi-vector embeddings.
+    pub(crate) fn multi_vector(&self) -> bool {
+        match self {
+            VectorSegmentReader::Hnsw(reader) => reader.multi_vector,
+            VectorSegmentReader::Pq(_) | VectorSegmentReader::Ivf(_) => false,
+        }
+    }
+}
@@ -6,21 +6,26 @@ use crate::chroma_proto::{
     self, CountRecordsRequest, CountRecordsResponse, QueryMetadataRequest, QueryMetadataResponse,
 };
 use crate::chroma_proto::{
-    GetVectorsRequest, GetVectorsResponse, QueryVectorsRequest, QueryVectorsResponse,
+    GetVectorsRequest, GetVectorsResponse, HybridQueryRequest, HybridQueryResponse,
+    QueryVectorsRequest, QueryVectorsResponse,
 };
 use crate::config::{Configurable, QueryServiceConfig};
 use crate::errors::ChromaError;
 use crate::execution::operator::TaskMessage;
 use crate::execution::orchestration::{
-    CountQueryOrchestrator, HnswQueryOrchestrator, MetadataQueryOrchestrator,
+    CountQueryOrchestrator, FusionMethod, HnswQueryOrchestrator, MetadataQueryOrchestrator,
+    DEFAULT_RANK_CONSTANT,
 };
 use crate::index::hnsw_provider::HnswIndexProvider;
+use crate::index::pq_provider::PqIndexProvider;
+use crate::index::HnswSearchParams;
 use crate::log::log::Log;
 use crate::sysdb::sysdb::SysDb;
 use crate::system::{Receiver, System};
 use crate::tracing::util::wrap_span_with_parent_context;
 use crate::types::MetadataValue;
 use crate::types::ScalarEncoding;
+use crate::types::SparseVector;
 use async_trait::async_trait;
 use tonic::{transport::Server, Request, Response, Status};
 use tracing::{trace, trace_span, Instrument};
@@ -36,6 +41,7 @@ pub struct WorkerServer {
     log: Box<dyn Log>,
     sysdb: Box<dyn SysDb>,
     hnsw_index_provider: HnswIndexProvider,
+    pq_index_provider: PqIndexProvider,
     blockfile_provider: BlockfileProvider,
     port: u16,
 }
@@ -75,7 +81,12 @@ impl Configurable<QueryServiceConfig> for WorkerServer {
             system: None,
             sysdb,
             log,
-            hnsw_index_provider: HnswIndexProvider::new(storage.clone(), path),
+            hnsw_index_provider: HnswIndexProvider::new(
+                storage.clone(),
+                path.clone(),
+                config.hnsw_cache.capacity_bytes,
+            ),
+            pq_index_provider: PqIndexProvider::new(storage.clone(), path),
             blockfile_provider: BlockfileProvider::new_arrow(storage),
             port: config.my_port,
         })
@@ -117,6 +128,28 @@ impl WorkerServer {
                 return Err(Status::invalid_argument("Invalid Segment UUID"));
             }
         };
+        let search_params = HnswSearchParams {
+            ef: match request.ef {
+                Some(ef) if ef <= 0 => {
+                    return Err(Status::invalid_argument("ef must be positive"));
+                }
+                Some(ef) => Some(ef as usize),
+                None => None,
+            },
+            exact: request.exact,
+            nprobe: match request.nprobe {
+                Some(nprobe) if nprobe <= 0 => {
+                    return Err(Status::invalid_argument("nprobe must be positive"));
+                }
+                Some(nprobe) => Some(nprobe as usize),
+                None => None,
+            },
+        };
+        if request.max_sim && request.vectors.is_empty() {
+            return Err(Status::invalid_argument(
+                "MaxSim queries need at least one query vector",
+            ));
+        }
 
         let mut proto_results_for_all = Vec::new();
 
@@ -145,7 +178,7 @@ impl WorkerServer {
 
         let result = match self.system {
             Some(ref system) => {
-                let orchestrator = HnswQueryOrchestrator::new(
+                let mut orchestrator = HnswQueryOrchestrator::new(
                     // TODO: Should not have to clone query vectors here
                     system.clone(),
                     query_vectors.clone(),
@@ -156,9 +189,14 @@ impl WorkerServer {
                     self.log.clone(),
                     self.sysdb.clone(),
                     self.hnsw_index_provider.clone(),
+                    self.pq_index_provider.clone(),
                     self.blockfile_provider.clone(),
                     dispatcher.clone(),
                 );
+                orchestrator.set_search_params(search_params);
+                if request.max_sim {
+                    orchestrator.set_max_sim();
+                }
                 orchestrator.run().await
             }
             None => {
@@ -213,6 +251,165 @@ impl WorkerServer {
         return Ok(Response::new(resp));
     }
 
+    pub(crate) async fn hybrid_query_instrumented(
+        &self,
+        request: Request<HybridQueryRequest>,
+    ) -> Result<Response<HybridQueryResponse>, Status> {
+        let request = request.into_inner();
+        let segment_uuid = match Uuid::parse_str(&request.segment_id) {
+            Ok(uuid) => uuid,
+            Err(_) => {
+                return Err(Status::invalid_argument("Invalid Segment UUID"));
+            }
+        };
+        if request.k <= 0 {
+            return Err(Status::invalid_argument("k must be positive"));
+        }
+
+        let fusion_method = match chroma_proto::FusionMethod::try_from(request.fusion_method) {
+            Ok(chroma_proto::FusionMethod::ReciprocalRank) => FusionMethod::ReciprocalRank {
+                rank_constant: match request.rrf_k > 0.0 {
+                    true => request.rrf_k,
+                    false => DEFAULT_RANK_CONSTANT,
+                },
+            },
+            Ok(chroma_proto::FusionMethod::WeightedScore) => FusionMethod::WeightedScore {
+                vector_weight: request.vector_weight,
+                text_weight: request.text_weight,
+            },
+            Err(_) => {
+                return Err(Status::invalid_argument("Invalid fusion method"));
+            }
+        };
+        // Each search retrieves at least k candidates for fusion
+        let candidate_k = request.k.max(request.candidate_k);
+
+        let mut query_vectors = Vec::new();
+        for proto_query_vector in request.vectors {
+            let (query_vector, _encoding) = match proto_query_vector.try_into() {
+                Ok((vector, encoding)) => (vector, encoding),
+                Err(e) => {
+                    return Err(Status::internal(format!("Error converting vector: {}", e)));
+                }
+            };
+            query_vectors.push(query_vector);
+        }
+        trace!("Parsed vectors {:?}", query_vectors);
+
+        let sparse_query = match request.sparse_query {
+            Some(_) if !request.document_query.is_empty() => {
+                return Err(Status::invalid_argument(
+                    "Only one of document query and sparse query may be set",
+                ));
+            }
+            Some(proto_sparse_query) => match SparseVector::try_from(proto_sparse_query) {
+                Ok(sparse_query) => Some(sparse_query),
+                Err(e) => {
+                    return Err(Status::invalid_argument(format!(
+                        "Error converting sparse query: {}",
+                        e
+                    )));
+                }
+            },
+            None => None,
+        };
+
+        let dispatcher = match self.dispatcher {
+            Some(ref dispatcher) => dispatcher,
+            None => {
+                return Err(Status::internal("No dispatcher found"));
+            }
+        };
+
+        let result = match self.system {
+            Some(ref system) => {
+                let mut orchestrator = HnswQueryOrchestrator::new(
+                    system.clone(),
+                    query_vectors.clone(),
+                    candidate_k,
+                    request.allowed_ids,
+                    request.include_embeddings,
+                    segment_uuid,
+                    self.log.clone(),
+                    self.sysdb.clone(),
+                    self.hnsw_index_provider.clone(),
+                    self.pq_index_provider.clone(),
+                    self.blockfile_provider.clone(),
+                    dispatcher.clone(),
+                );
+                match sparse_query {
+                    Some(sparse_query) => {
+                        orchestrator.set_sparse_query(
+                            sparse_query,
+                            fusion_method,
+                            request.k as usize,
+                        );
+                    }
+                    None => {
+                        orchestrator.set_full_text_query(
+                            request.document_query,
+                            fusion_method,
+                            request.k as usize,
+                        );
+                    }
+                }
+                orchestrator.run_hybrid().await
+            }
+            None => {
+                return Err(Status::internal("No system found"));
+            }
+        };
+
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                return Err(Status::internal(format!(
+                    "Error running orchestrator: {}",
+                    e
+                )));
+            }
+        };
+
+        let mut proto_results_for_all = Vec::new();
+        for result_set in result {
+            let mut proto_results = Vec::new();
+            for query_result in result_set {
+                let proto_result = chroma_proto::HybridQueryResult {
+                    id: query_result.id,
+                    score: query_result.score,
+                    distance: query_result.distance,
+                    text_score: query_result.text_score,
+                    vector: match query_result.vector {
+                        Some(vector) => {
+                            match (vector, ScalarEncoding::FLOAT32, query_vectors[0].len())
+                                .try_into()
+                            {
+                                Ok(proto_vector) => Some(proto_vector),
+                                Err(e) => {
+                                    return Err(Status::internal(format!(
+                                        "Error converting vector: {}",
+                                        e
+                                    )));
+                                }
+                            }
+                        }
+                        None => None,
+                    },
+                };
+                proto_results.push(proto_result);
+            }
+            proto_results_for_all.push(chroma_proto::HybridQueryResults {
+                results: proto_results,
+            });
+        }
+
+        let resp = chroma_proto::HybridQueryResponse {
+            results: proto_results_for_all,
+        };
+
+        return Ok(Response::new(resp));
+    }
+
     async fn query_metadata_instrumented(
         &self,
         request: Request<QueryMetadataRequest>,
@@ -239,16 +436,33 @@ impl WorkerServer {
             }
         };
 
-        // For now we don't support limit/offset/where/where document
+        // For now we don't support limit/offset/where
         if request.limit.is_some() || request.offset.is_some() {
             return Err(Status::unimplemented("Limit and offset not supported"));
         }
-        if request.where_document.is_some() {
-            return Err(Status::unimplemented("Where document not supported"));
-        }
         if request.r#where.is_some() {
             return Err(Status::unimplemented("Where not supported"));
         }
+        if request.where_document.is_some() && !request.ids.is_empty() {
+            return Err(Status::unimplemented(
+                "Where document combined with ids not supported",
+            ));
+        }
+        if request.include_match_offsets && request.where_document.is_none() {
+            return Err(Status::invalid_argument(
+                "Match offsets require a where document clause",
+            ));
+        }
+
+        let where_document_clause = match request.where_document {
+            Some(where_document) => match where_document.try_into() {
+                Ok(where_document) => Some(where_document),
+                Err(_) => {
+                    return Err(Status::invalid_argument("Invalid where document clause"));
+                }
+            },
+            None => None,
+        };
 
         // If no ids are provided, pass None to the orchestrator
         let query_ids = match request.ids.len() {
@@ -260,6 +474,8 @@ impl WorkerServer {
             system.clone(),
             &segment_uuid,
             query_ids,
+            where_document_clause,
+            request.include_match_offsets,
             self.log.clone(),
             self.sysdb.clone(),
             dispatcher.clone(),
@@ -278,11 +494,12 @@ impl WorkerServer {
         };
 
         let mut output = Vec::new();
-        let (ids, metadatas, documents) = result;
-        for ((id, metadata), document) in ids
+        let (ids, metadatas, documents, match_offsets) = result;
+        for (((id, metadata), document), match_offsets) in ids
             .into_iter()
             .zip(metadatas.into_iter())
             .zip(documents.into_iter())
+            .zip(match_offsets.into_iter())
         {
             // The transport layer assumes the document exists in the metadata
             // with the special key "chroma:document"
@@ -300,6 +517,13 @@ impl WorkerServer {
             let record = chroma_proto::MetadataEmbeddingRecord {
                 id,
                 metadata: Some(chroma_proto::UpdateMetadata::from(output_metadata)),
+                match_offsets: match_offsets
+                    .into_iter()
+                    .map(|(start, end)| chroma_proto::MatchOffset {
+                        start: start as u32,
+                        end: end as u32,
+                    })
+                    .collect(),
             };
             output.push(record);
         }
@@ -345,6 +569,25 @@ impl chroma_proto::vector_reader_server::VectorReader for WorkerServer {
             .instrument(instrumented_span)
             .await
     }
+
+    async fn hybrid_query(
+        &self,
+        request: Request<HybridQueryRequest>,
+    ) -> Result<Response<HybridQueryResponse>, Status> {
+        let query_span = trace_span!(
+            "Hybrid query",
+            k = request.get_ref().k,
+            candidate_k = request.get_ref().candidate_k,
+            segment_id = request.get_ref().segment_id,
+            document_query = request.get_ref().document_query,
+            include_embeddings = request.get_ref().include_embeddings,
+            allowed_ids = ?request.get_ref().allowed_ids
+        );
+        let instrumented_span = wrap_span_with_parent_context(query_span, request.metadata());
+        self.hybrid_query_instrumented(request)
+            .instrument(instrumented_span)
+            .await
+    }
 }
 
 #[tonic::async_trait]
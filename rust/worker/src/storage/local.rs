@@ -17,10 +17,14 @@ impl LocalStorage {
         };
     }
 
+    pub(crate) fn path(&self, key: &str) -> std::path::PathBuf {
+        std::path::PathBuf::from(format!("{}/{}", self.root, key))
+    }
+
     pub(crate) async fn get(
         &self,
         key: &str,
-    ) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, String> {
+    ) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, std::io::Error> {
         let file_path = format!("{}/{}", self.root, key);
         let file = tokio::fs::File::open(file_path).await;
         match file {
@@ -28,7 +32,7 @@ impl LocalStorage {
                 return Ok(Box::new(tokio::io::BufReader::new(file)));
             }
             Err(e) => {
-                return Err::<_, String>(e.to_string());
+                return Err(e);
             }
         }
     }
@@ -71,13 +71,24 @@ impl Storage {
                 let res = local.get(key).await;
                 match res {
                     Ok(res) => Ok(res),
-                    // TODO: Special case no such key if possible
-                    Err(e) => Err(GetError::LocalError(e)),
+                    Err(e) => match e.kind() {
+                        std::io::ErrorKind::NotFound => Err(GetError::NoSuchKey(key.to_string())),
+                        _ => Err(GetError::LocalError(e.to_string())),
+                    },
                 }
             }
         }
     }
 
+    /// The path of the file of the key, when the storage is on local disk and the
+    /// file can be read in place.
+    pub(crate) fn local_path(&self, key: &str) -> Option<std::path::PathBuf> {
+        match self {
+            Storage::S3(_) => None,
+            Storage::Local(local) => Some(local.path(key)),
+        }
+    }
+
     pub(crate) async fn put_file(&self, key: &str, path: &str) -> Result<(), PutError> {
         match self {
             Storage::S3(s3) => s3
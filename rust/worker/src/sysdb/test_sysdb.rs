@@ -7,7 +7,6 @@ use crate::types::FlushCompactionResponse;
 use crate::types::Segment;
 use crate::types::SegmentFlushInfo;
 use crate::types::SegmentScope;
-use crate::types::SegmentType;
 use crate::types::Tenant;
 use async_trait::async_trait;
 use parking_lot::Mutex;
@@ -93,15 +92,9 @@ impl TestSysDb {
         if id.is_some() && id.unwrap() != segment.id {
             return false;
         }
-        if r#type.is_some() {
-            match r#type.unwrap().as_str() {
-                "hnsw" => {
-                    if segment.r#type != SegmentType::HnswDistributed {
-                        return false;
-                    }
-                }
-                _ => return false,
-            }
+        // Segment types are filtered by their urn, as in the sysdb
+        if r#type.is_some() && r#type.unwrap() != String::from(segment.r#type.clone()) {
+            return false;
         }
         if scope.is_some() && scope.unwrap() != segment.scope {
             return false;
@@ -352,6 +352,10 @@ pub(crate) struct DirectDocumentComparison {
 pub(crate) enum WhereDocumentOperator {
     Contains,
     NotContains,
+    // The document is a query in the syntax parsed by DocumentQuery.
+    Matches,
+    // The document is a regular expression.
+    Regex,
 }
 
 #[derive(Debug, PartialEq)]
@@ -638,6 +642,8 @@ impl TryFrom<chroma_proto::WhereDocumentOperator> for WhereDocumentOperator {
             chroma_proto::WhereDocumentOperator::NotContains => {
                 Ok(WhereDocumentOperator::NotContains)
             }
+            chroma_proto::WhereDocumentOperator::Matches => Ok(WhereDocumentOperator::Matches),
+            chroma_proto::WhereDocumentOperator::Regex => Ok(WhereDocumentOperator::Regex),
         }
     }
 }
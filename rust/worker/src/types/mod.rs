@@ -3,21 +3,25 @@ mod types;
 mod collection;
 mod flush;
 mod metadata;
+mod multi_vector;
 mod operation;
 mod record;
 mod scalar_encoding;
 mod segment;
 mod segment_scope;
+mod sparse_vector;
 mod tenant;
 
 // Re-export the types module, so that we can use it as a single import in other modules.
 pub(crate) use collection::*;
 pub(crate) use flush::*;
 pub(crate) use metadata::*;
+pub(crate) use multi_vector::*;
 pub(crate) use operation::*;
 pub(crate) use record::*;
 pub(crate) use scalar_encoding::*;
 pub(crate) use segment::*;
 pub(crate) use segment_scope::*;
+pub(crate) use sparse_vector::*;
 pub(crate) use tenant::*;
 pub(crate) use types::*;
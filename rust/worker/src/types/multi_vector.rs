@@ -0,0 +1,127 @@
+use super::ConversionError;
+use crate::{
+    chroma_proto,
+    errors::{ChromaError, ErrorCodes},
+};
+use thiserror::Error;
+
+/// The most vectors a multi-vector embedding can have. Vector segments label each
+/// vector of a record by its position.
+pub(crate) const MAX_MULTI_VECTOR_LEN: usize = 1 << 16;
+
+/// A multi-vector embedding, such as the token embeddings of a late-interaction
+/// model. The vectors are concatenated and all have the same dimension.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MultiVector {
+    pub(crate) dimension: usize,
+    pub(crate) values: Vec<f32>,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum MultiVectorConversionError {
+    #[error("Multi-vector dimension must be positive")]
+    InvalidDimension,
+    #[error("Multi-vector has {0} values, which is not a multiple of its dimension {1}")]
+    LengthMismatch(usize, usize),
+    #[error("Multi-vector has {0} vectors, more than the maximum {MAX_MULTI_VECTOR_LEN}")]
+    TooManyVectors(usize),
+    #[error(transparent)]
+    DecodeError(#[from] ConversionError),
+}
+
+impl_base_convert_error!(MultiVectorConversionError, {
+    MultiVectorConversionError::InvalidDimension => ErrorCodes::InvalidArgument,
+    MultiVectorConversionError::LengthMismatch(_, _) => ErrorCodes::InvalidArgument,
+    MultiVectorConversionError::TooManyVectors(_) => ErrorCodes::InvalidArgument,
+});
+
+impl MultiVector {
+    pub(crate) fn new(
+        dimension: usize,
+        values: Vec<f32>,
+    ) -> Result<Self, MultiVectorConversionError> {
+        if dimension == 0 {
+            return Err(MultiVectorConversionError::InvalidDimension);
+        }
+        if values.len() % dimension != 0 {
+            return Err(MultiVectorConversionError::LengthMismatch(
+                values.len(),
+                dimension,
+            ));
+        }
+        let len = values.len() / dimension;
+        if len > MAX_MULTI_VECTOR_LEN {
+            return Err(MultiVectorConversionError::TooManyVectors(len));
+        }
+        Ok(MultiVector { dimension, values })
+    }
+
+    /// The number of vectors.
+    pub(crate) fn len(&self) -> usize {
+        self.values.len() / self.dimension
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &[f32]> + '_ {
+        self.values.chunks_exact(self.dimension)
+    }
+}
+
+impl TryFrom<chroma_proto::MultiVector> for MultiVector {
+    type Error = MultiVectorConversionError;
+
+    fn try_from(proto_vector: chroma_proto::MultiVector) -> Result<Self, Self::Error> {
+        match usize::try_from(proto_vector.dimension) {
+            Ok(dimension) => MultiVector::new(dimension, proto_vector.values),
+            Err(_) => Err(MultiVectorConversionError::InvalidDimension),
+        }
+    }
+}
+
+impl From<MultiVector> for chroma_proto::MultiVector {
+    fn from(vector: MultiVector) -> Self {
+        chroma_proto::MultiVector {
+            dimension: vector.dimension as i32,
+            values: vector.values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_vector_try_from() {
+        let proto_vector = chroma_proto::MultiVector {
+            dimension: 2,
+            values: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        };
+        let converted: MultiVector = proto_vector.try_into().unwrap();
+        assert_eq!(converted.len(), 3);
+        assert_eq!(
+            converted.iter().collect::<Vec<_>>(),
+            vec![&[1.0, 2.0][..], &[3.0, 4.0][..], &[5.0, 6.0][..]]
+        );
+
+        let mismatched = chroma_proto::MultiVector {
+            dimension: 4,
+            values: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        };
+        assert!(matches!(
+            MultiVector::try_from(mismatched),
+            Err(MultiVectorConversionError::LengthMismatch(6, 4))
+        ));
+        let negative = chroma_proto::MultiVector {
+            dimension: -1,
+            values: vec![],
+        };
+        assert!(matches!(
+            MultiVector::try_from(negative),
+            Err(MultiVectorConversionError::InvalidDimension)
+        ));
+    }
+}
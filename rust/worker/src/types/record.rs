@@ -1,7 +1,7 @@
 use super::{
-    ConversionError, Operation, OperationConversionError, ScalarEncoding,
-    ScalarEncodingConversionError, UpdateMetadata, UpdateMetadataValue,
-    UpdateMetadataValueConversionError,
+    ConversionError, MultiVector, MultiVectorConversionError, Operation, OperationConversionError,
+    ScalarEncoding, ScalarEncodingConversionError, SparseVector, SparseVectorConversionError,
+    UpdateMetadata, UpdateMetadataValue, UpdateMetadataValueConversionError,
 };
 use crate::{
     chroma_proto,
@@ -14,6 +14,8 @@ pub(crate) struct OperationRecord {
     pub(crate) id: String,
     pub(crate) embedding: Option<Vec<f32>>, // NOTE: we only support float32 embeddings for now so this ignores the encoding
     pub(crate) encoding: Option<ScalarEncoding>,
+    pub(crate) sparse_embedding: Option<SparseVector>,
+    pub(crate) multi_embedding: Option<MultiVector>,
     pub(crate) metadata: Option<UpdateMetadata>,
     // Document is implemented in the python code as a special key "chroma:document" in the metadata
     // This is ugly and clunky. In the rust code we choose to make it a separate field and
@@ -42,6 +44,10 @@ pub(crate) enum RecordConversionError {
     UpdateMetadataValueConversionError(#[from] UpdateMetadataValueConversionError),
     #[error(transparent)]
     VectorConversionError(#[from] VectorConversionError),
+    #[error(transparent)]
+    SparseVectorConversionError(#[from] SparseVectorConversionError),
+    #[error(transparent)]
+    MultiVectorConversionError(#[from] MultiVectorConversionError),
 }
 
 impl_base_convert_error!(RecordConversionError, {
@@ -50,6 +56,8 @@ impl_base_convert_error!(RecordConversionError, {
     RecordConversionError::ScalarEncodingConversionError(inner) => inner.code(),
     RecordConversionError::UpdateMetadataValueConversionError(inner) => inner.code(),
     RecordConversionError::VectorConversionError(inner) => inner.code(),
+    RecordConversionError::SparseVectorConversionError(inner) => inner.code(),
+    RecordConversionError::MultiVectorConversionError(inner) => inner.code(),
 });
 
 impl TryFrom<chroma_proto::OperationRecord> for OperationRecord {
@@ -72,6 +80,22 @@ impl TryFrom<chroma_proto::OperationRecord> for OperationRecord {
             None => (None, None),
         };
 
+        let sparse_embedding = match operation_record_proto.sparse_vector {
+            Some(proto_vector) => match SparseVector::try_from(proto_vector) {
+                Ok(sparse_embedding) => Some(sparse_embedding),
+                Err(e) => return Err(RecordConversionError::SparseVectorConversionError(e)),
+            },
+            None => None,
+        };
+
+        let multi_embedding = match operation_record_proto.multi_vector {
+            Some(proto_vector) => match MultiVector::try_from(proto_vector) {
+                Ok(multi_embedding) => Some(multi_embedding),
+                Err(e) => return Err(RecordConversionError::MultiVectorConversionError(e)),
+            },
+            None => None,
+        };
+
         let (metadata, document) = match operation_record_proto.metadata {
             Some(proto_metadata) => match UpdateMetadata::try_from(proto_metadata) {
                 Ok(mut metadata) => {
@@ -92,6 +116,8 @@ impl TryFrom<chroma_proto::OperationRecord> for OperationRecord {
             id: operation_record_proto.id,
             embedding,
             encoding,
+            sparse_embedding,
+            multi_embedding,
             metadata,
             document,
             operation,
@@ -237,6 +263,17 @@ pub(crate) struct VectorQueryResult {
     pub(crate) vector: Option<Vec<f32>>,
 }
 
+/// A result of a hybrid query. The distance and the vector are set if the
+/// record was a nearest neighbor, the text score if its document matched.
+#[derive(Debug)]
+pub(crate) struct HybridQueryResult {
+    pub(crate) id: String,
+    pub(crate) score: f32,
+    pub(crate) distance: Option<f32>,
+    pub(crate) text_score: Option<f32>,
+    pub(crate) vector: Option<Vec<f32>>,
+}
+
 /*
 ===========================================
 Metadata Embedding Record
@@ -254,6 +291,7 @@ impl From<MetadataEmbeddingRecord> for chroma_proto::MetadataEmbeddingRecord {
         chroma_proto::MetadataEmbeddingRecord {
             id: record.id,
             metadata: Some(record.metadata.into()),
+            match_offsets: vec![],
         }
     }
 }
@@ -307,6 +345,8 @@ mod tests {
             vector: Some(proto_vector),
             metadata: Some(metadata),
             operation: chroma_proto::Operation::Add as i32,
+            sparse_vector: None,
+            multi_vector: None,
         };
         let converted_operation_record = OperationRecord::try_from(proto_submit).unwrap();
         assert_eq!(converted_operation_record.id, Uuid::nil().to_string());
@@ -352,6 +392,8 @@ mod tests {
             vector: Some(proto_vector),
             metadata: Some(metadata),
             operation: chroma_proto::Operation::Add as i32,
+            sparse_vector: None,
+            multi_vector: None,
         };
         let record_log = chroma_proto::LogRecord {
             log_offset: 42,
@@ -10,6 +10,9 @@ use uuid::Uuid;
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum SegmentType {
     HnswDistributed,
+    PqDistributed,
+    IvfDistributed,
+    SparseVectorDistributed,
     BlockfileMetadata,
     Record,
     Sqlite,
@@ -21,6 +24,11 @@ impl From<SegmentType> for String {
             SegmentType::HnswDistributed => {
                 "urn:chroma:segment/vector/hnsw-distributed".to_string()
             }
+            SegmentType::PqDistributed => "urn:chroma:segment/vector/pq-distributed".to_string(),
+            SegmentType::IvfDistributed => "urn:chroma:segment/vector/ivf-distributed".to_string(),
+            SegmentType::SparseVectorDistributed => {
+                "urn:chroma:segment/vector/sparse-distributed".to_string()
+            }
             SegmentType::Record => "urn:chroma:segment/record".to_string(),
             SegmentType::Sqlite => "urn:chroma:segment/metadata/sqlite".to_string(),
             SegmentType::BlockfileMetadata => "urn:chroma:segment/metadata/blockfile".to_string(),
@@ -93,6 +101,9 @@ impl TryFrom<chroma_proto::Segment> for Segment {
 
         let segment_type = match proto_segment.r#type.as_str() {
             "urn:chroma:segment/vector/hnsw-distributed" => SegmentType::HnswDistributed,
+            "urn:chroma:segment/vector/pq-distributed" => SegmentType::PqDistributed,
+            "urn:chroma:segment/vector/ivf-distributed" => SegmentType::IvfDistributed,
+            "urn:chroma:segment/vector/sparse-distributed" => SegmentType::SparseVectorDistributed,
             "urn:chroma:segment/record" => SegmentType::Record,
             "urn:chroma:segment/metadata/sqlite" => SegmentType::Sqlite,
             "urn:chroma:segment/metadata/blockfile" => SegmentType::BlockfileMetadata,
@@ -0,0 +1,139 @@
+use super::ConversionError;
+use crate::{
+    chroma_proto,
+    errors::{ChromaError, ErrorCodes},
+};
+use thiserror::Error;
+
+/// A sparse embedding, such as a learned SPLADE embedding. Only the non-zero
+/// dimensions are stored, the indices are sorted and unique.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SparseVector {
+    pub(crate) indices: Vec<u32>,
+    pub(crate) values: Vec<f32>,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum SparseVectorConversionError {
+    #[error("Sparse vector has {0} indices and {1} values")]
+    LengthMismatch(usize, usize),
+    #[error("Sparse vector indices must be sorted and unique")]
+    UnsortedIndices,
+    #[error(transparent)]
+    DecodeError(#[from] ConversionError),
+}
+
+impl_base_convert_error!(SparseVectorConversionError, {
+    SparseVectorConversionError::LengthMismatch(_, _) => ErrorCodes::InvalidArgument,
+    SparseVectorConversionError::UnsortedIndices => ErrorCodes::InvalidArgument,
+});
+
+impl SparseVector {
+    pub(crate) fn new(
+        indices: Vec<u32>,
+        values: Vec<f32>,
+    ) -> Result<Self, SparseVectorConversionError> {
+        if indices.len() != values.len() {
+            return Err(SparseVectorConversionError::LengthMismatch(
+                indices.len(),
+                values.len(),
+            ));
+        }
+        if indices.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(SparseVectorConversionError::UnsortedIndices);
+        }
+        Ok(SparseVector { indices, values })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// The (index, value) pairs of the non-zero dimensions in index order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        self.indices
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+    }
+
+    pub(crate) fn dot(&self, other: &SparseVector) -> f32 {
+        let mut dot = 0.0;
+        let (mut i, mut j) = (0, 0);
+        while i < self.indices.len() && j < other.indices.len() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    dot += self.values[i] * other.values[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        dot
+    }
+}
+
+impl TryFrom<chroma_proto::SparseVector> for SparseVector {
+    type Error = SparseVectorConversionError;
+
+    fn try_from(proto_vector: chroma_proto::SparseVector) -> Result<Self, Self::Error> {
+        SparseVector::new(proto_vector.indices, proto_vector.values)
+    }
+}
+
+impl From<SparseVector> for chroma_proto::SparseVector {
+    fn from(vector: SparseVector) -> Self {
+        chroma_proto::SparseVector {
+            indices: vector.indices,
+            values: vector.values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_vector_try_from() {
+        let proto_vector = chroma_proto::SparseVector {
+            indices: vec![1, 5, 9],
+            values: vec![0.5, 1.0, 2.0],
+        };
+        let converted: SparseVector = proto_vector.try_into().unwrap();
+        assert_eq!(converted.indices, vec![1, 5, 9]);
+        assert_eq!(converted.values, vec![0.5, 1.0, 2.0]);
+
+        let mismatched = chroma_proto::SparseVector {
+            indices: vec![1, 5],
+            values: vec![0.5],
+        };
+        assert!(matches!(
+            SparseVector::try_from(mismatched),
+            Err(SparseVectorConversionError::LengthMismatch(2, 1))
+        ));
+        let unsorted = chroma_proto::SparseVector {
+            indices: vec![5, 1],
+            values: vec![0.5, 1.0],
+        };
+        assert!(matches!(
+            SparseVector::try_from(unsorted),
+            Err(SparseVectorConversionError::UnsortedIndices)
+        ));
+    }
+
+    #[test]
+    fn test_sparse_vector_dot() {
+        let a = SparseVector::new(vec![0, 3, 7], vec![1.0, 2.0, 3.0]).unwrap();
+        let b = SparseVector::new(vec![3, 4, 7, 9], vec![0.5, 1.0, 2.0, 4.0]).unwrap();
+        assert_eq!(a.dot(&b), 2.0 * 0.5 + 3.0 * 2.0);
+        assert_eq!(b.dot(&a), a.dot(&b));
+        assert_eq!(a.dot(&SparseVector::new(vec![], vec![]).unwrap()), 0.0);
+    }
+}